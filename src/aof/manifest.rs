@@ -0,0 +1,173 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::rdb::sync_dir;
+
+// What an AOF file holds: the dataset as of a rewrite, writes made after it, or either of those
+// left behind by a later rewrite and waiting to be deleted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileType {
+    Base,
+    Incr,
+    History,
+}
+
+impl FileType {
+    fn code(self) -> &'static str {
+        match self {
+            FileType::Base => "b",
+            FileType::Incr => "i",
+            FileType::History => "h",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AofFile {
+    pub name: String,
+    pub seq: u64,
+    pub file_type: FileType,
+}
+
+// The files the AOF is made of, laid out as in Redis 7. The base is loaded first, then the
+// incr files in order. Sequence numbers only go up, so a file's name is never reused.
+#[derive(Debug, Default)]
+pub struct Manifest {
+    pub base: Option<AofFile>,
+    pub incrs: Vec<AofFile>,
+    pub history: Vec<AofFile>,
+    base_seq: u64,
+    incr_seq: u64,
+}
+
+fn bad_manifest(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid AOF manifest file format: {}", msg))
+}
+
+impl Manifest {
+    // Each line describes a file as pairs of `file <name> seq <n> type <b|i|h>`, in any order
+    pub fn parse(text: &str) -> io::Result<Manifest> {
+        let mut manifest = Manifest::default();
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if !words.chunks_exact(2).remainder().is_empty() {
+                return Err(bad_manifest(line));
+            }
+            let (mut name, mut seq, mut file_type) = (None, None, None);
+            for pair in words.chunks_exact(2) {
+                match pair[0] {
+                    "file" => name = Some(pair[1].to_string()),
+                    "seq" => seq = pair[1].parse::<u64>().ok(),
+                    "type" => {
+                        file_type = match pair[1] {
+                            "b" => Some(FileType::Base),
+                            "i" => Some(FileType::Incr),
+                            "h" => Some(FileType::History),
+                            _ => None,
+                        }
+                    }
+                    // Left for whatever wrote them
+                    _ => {}
+                }
+            }
+            let (Some(name), Some(seq), Some(file_type)) = (name, seq, file_type) else {
+                return Err(bad_manifest(line));
+            };
+            let file = AofFile { name, seq, file_type };
+            match file_type {
+                FileType::Base if manifest.base.is_some() => return Err(bad_manifest("more than one base file")),
+                FileType::Base => {
+                    manifest.base_seq = seq;
+                    manifest.base = Some(file);
+                }
+                FileType::Incr if seq <= manifest.incr_seq => return Err(bad_manifest("incr files out of order")),
+                FileType::Incr => {
+                    manifest.incr_seq = seq;
+                    manifest.incrs.push(file);
+                }
+                FileType::History => manifest.history.push(file),
+            }
+        }
+        Ok(manifest)
+    }
+
+    // None when there is no manifest yet
+    pub fn load(path: &Path) -> io::Result<Option<Manifest>> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Manifest::parse(&text).map(Some),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn serialize(&self) -> String {
+        let files = self.base.iter().chain(&self.history).chain(&self.incrs);
+        files.map(|file| format!("file {} seq {} type {}\n", file.name, file.seq, file.file_type.code())).collect()
+    }
+
+    // Write the manifest, then delete the history files it lists and write it again without
+    // them, so no file is deleted while a manifest on disk still needs it
+    pub fn save(&mut self, path: &Path) -> io::Result<()> {
+        self.write(path)?;
+        if self.history.is_empty() {
+            return Ok(());
+        }
+        self.remove_history(path.parent().unwrap_or(Path::new(".")));
+        self.write(path)
+    }
+
+    // Write the manifest beside the one it replaces and rename it over, so a crash leaves
+    // either the old list of files or the new one
+    fn write(&self, path: &Path) -> io::Result<()> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp = path.with_file_name(format!("temp-{}", name));
+        let result = File::create(&temp).and_then(|mut file| {
+            file.write_all(self.serialize().as_bytes())?;
+            file.sync_all()
+        });
+        if let Err(e) = result.and_then(|_| std::fs::rename(&temp, path)) {
+            let _ = std::fs::remove_file(&temp);
+            return Err(e);
+        }
+        sync_dir(path.parent().unwrap_or(Path::new(".")))
+    }
+
+    // Name the next base, which holds an RDB image or commands
+    pub fn next_base(&mut self, prefix: &str, rdb: bool) -> AofFile {
+        self.base_seq += 1;
+        let extension = if rdb { "rdb" } else { "aof" };
+        AofFile { name: format!("{}.{}.base.{}", prefix, self.base_seq, extension), seq: self.base_seq, file_type: FileType::Base }
+    }
+
+    // Add an incr file after the others, for writes to go to from now on
+    pub fn add_incr(&mut self, prefix: &str) -> &AofFile {
+        self.incr_seq += 1;
+        let seq = self.incr_seq;
+        self.incrs.push(AofFile { name: format!("{}.{}.incr.aof", prefix, seq), seq, file_type: FileType::Incr });
+        self.incrs.last().unwrap()
+    }
+
+    // A base from a rewrite replaces the previous one and the incr files before the one the
+    // rewrite started at, which become history
+    pub fn set_base(&mut self, base: AofFile, first_incr: u64) {
+        self.base_seq = self.base_seq.max(base.seq);
+        let replaced = self.base.replace(base).into_iter();
+        let (kept, covered) = std::mem::take(&mut self.incrs).into_iter().partition(|file| file.seq >= first_incr);
+        self.incrs = kept;
+        self.history.extend(replaced.chain(covered).map(|file| AofFile { file_type: FileType::History, ..file }));
+    }
+
+    // Delete the files no longer needed and forget them
+    fn remove_history(&mut self, dir: &Path) {
+        for file in self.history.drain(..) {
+            if let Err(e) = std::fs::remove_file(dir.join(&file.name)) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    println!("Error removing AOF history file {}: {}", file.name, e);
+                }
+            }
+        }
+    }
+}
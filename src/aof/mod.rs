@@ -0,0 +1,586 @@
+use std::{
+    fs::OpenOptions,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Error, Result};
+use tokio::{
+    fs::File,
+    io::AsyncWriteExt,
+    net::tcp::OwnedReadHalf,
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot, watch, RwLock,
+    },
+    task,
+    time::{self, Duration, Instant, MissedTickBehavior},
+};
+
+use crate::{
+    blocking::wait_for_disconnect,
+    command::Command,
+    commands::{functions::Libraries, stream::unix_time_ms},
+    config::{AofOptions, AppendFsync},
+    persistent_map::PersistentMap,
+    rdb,
+    propagate::Chunk,
+    resp::DataType,
+    state::{DataStoreValue, State},
+};
+
+mod manifest;
+mod rewrite;
+
+use manifest::{AofFile, FileType, Manifest};
+use rewrite::write_rewrite;
+
+pub use rewrite::RewriteOptions;
+
+// How often the everysec policy fsyncs
+const FSYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+// How the AOF writer is doing, for INFO persistence. The writer task updates it from outside
+// the state lock, and takes the fsync policy from it so CONFIG SET reaches a running writer.
+#[derive(Debug)]
+pub struct AofStatus {
+    pub fsync: AppendFsync,
+    pub fsync_before_reply: bool,
+    pub last_write_ok: bool,
+    // Unix time in seconds of the last fsync, zero before the first
+    pub last_fsync: u64,
+    // Whether there are writes that haven't been fsynced yet
+    pub pending_fsync: bool,
+    pub rewrite_started: Option<Instant>,
+    pub last_rewrite_ok: bool,
+    pub last_rewrite_duration: Option<Duration>,
+    // Offset in the write stream up to which the AOF is on disk, for WAITAOF. With appendfsync
+    // no that is as soon as it is written, as the server never fsyncs it.
+    pub fsynced_offset: watch::Sender<u64>,
+}
+
+impl AofStatus {
+    pub fn new() -> Arc<Mutex<AofStatus>> {
+        Arc::new(Mutex::new(AofStatus {
+            fsync: AppendFsync::EverySec,
+            fsync_before_reply: false,
+            last_write_ok: true,
+            last_fsync: 0,
+            pending_fsync: false,
+            rewrite_started: None,
+            last_rewrite_ok: true,
+            last_rewrite_duration: None,
+            fsynced_offset: watch::channel(0).0,
+        }))
+    }
+
+    pub fn set_policy(&mut self, options: &AofOptions) {
+        self.fsync = options.fsync;
+        self.fsync_before_reply = options.fsync_before_reply;
+    }
+
+    pub fn finish_rewrite(&mut self, ok: bool) {
+        self.last_rewrite_ok = ok;
+        self.last_rewrite_duration = self.rewrite_started.take().map(|started| started.elapsed());
+    }
+}
+
+// Changes to the files the writer appends to, for a rewrite
+pub enum Request {
+    // Finish appending to the current incr file what was written up to the offset, and go on
+    // to a new incr file with the writes from then on
+    Rotate { start: u64, tail: UnboundedReceiver<Chunk>, done: oneshot::Sender<io::Result<()>> },
+    // Put a rewritten base in place of the files from before the last rotation
+    Install { temp: PathBuf, rdb: bool, done: oneshot::Sender<io::Result<()>> },
+}
+
+async fn request(writer: &UnboundedSender<Request>, request: impl FnOnce(oneshot::Sender<io::Result<()>>) -> Request) -> io::Result<()> {
+    let (done, replied) = oneshot::channel();
+    if writer.send(request(done)).is_err() {
+        return Err(io::Error::other("AOF writer stopped"));
+    }
+    replied.await.unwrap_or_else(|_| Err(io::Error::other("AOF writer stopped")))
+}
+
+// Why a command couldn't be read: the file ends part way through it, as it does after a crash
+// mid-append, or there is something other than a command there
+enum ReadError {
+    Truncated,
+    BadFormat,
+}
+
+// Reads the commands out of an AOF, each an array of bulk strings
+struct AofReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> AofReader<'a> {
+    // The number after a type byte, up to the end of the line
+    fn header(&mut self, kind: u8) -> Result<usize, ReadError> {
+        let rest = &self.data[self.pos..];
+        let end = rest.windows(2).position(|window| window == b"\r\n").ok_or(ReadError::Truncated)?;
+        if rest[0] != kind {
+            return Err(ReadError::BadFormat);
+        }
+        let number = std::str::from_utf8(&rest[1..end]).ok().and_then(|number| number.parse().ok()).ok_or(ReadError::BadFormat)?;
+        self.pos += end + 2;
+        Ok(number)
+    }
+
+    fn bulk_string(&mut self) -> Result<Vec<u8>, ReadError> {
+        let len = self.header(b'$')?;
+        let end = self.pos.checked_add(len).ok_or(ReadError::BadFormat)?;
+        match self.data.get(end..end + 2) {
+            Some(b"\r\n") => {}
+            Some(_) => return Err(ReadError::BadFormat),
+            None => return Err(ReadError::Truncated),
+        }
+        let string = self.data[self.pos..end].to_vec();
+        self.pos = end + 2;
+        Ok(string)
+    }
+
+    fn command(&mut self) -> Result<Option<Vec<Vec<u8>>>, ReadError> {
+        if self.pos == self.data.len() {
+            return Ok(None);
+        }
+        let argc = self.header(b'*')?;
+        if argc == 0 {
+            return Err(ReadError::BadFormat);
+        }
+        (0..argc).map(|_| self.bulk_string()).collect::<Result<_, _>>().map(Some)
+    }
+}
+
+impl State {
+    pub fn aof_dir(&self) -> AofDir {
+        AofDir { dir: self.dump_path().with_file_name(&self.config.aof.dirname), prefix: self.config.aof.filename.clone() }
+    }
+
+    // Load the files the manifest lists, the base and then the incr files in order. An AOF
+    // from before there were several files, which sits next to the dump, is moved into the
+    // directory as the base first. Returns false when there is no AOF yet. Replayed writes
+    // were made before the last shutdown, so they don't count as unsaved changes.
+    pub fn load_aof(&mut self) -> Result<bool> {
+        let aof = self.aof_dir();
+        let mut manifest = aof.load_manifest()?;
+        let legacy = self.dump_path().with_file_name(&aof.prefix);
+        if legacy.exists() {
+            // The manifest goes first, so a crash part way leaves it naming the file to move
+            if manifest.is_none() {
+                std::fs::create_dir_all(&aof.dir)?;
+                let mut upgraded = Manifest::default();
+                upgraded.set_base(AofFile { name: aof.prefix.clone(), seq: 1, file_type: FileType::Base }, 0);
+                upgraded.save(&aof.manifest_path())?;
+                manifest = Some(upgraded);
+            }
+            let moved = aof.dir.join(&aof.prefix);
+            if manifest.as_ref().and_then(|manifest| manifest.base.as_ref()).is_some_and(|base| base.name == aof.prefix) && !moved.exists() {
+                std::fs::rename(&legacy, &moved)?;
+                rdb::sync_dir(&aof.dir)?;
+                println!("Moved {} into {} as the base of the AOF", legacy.display(), aof.dir.display());
+            }
+        }
+        let Some(manifest) = manifest else {
+            return Ok(false);
+        };
+        let files: Vec<&AofFile> = manifest.base.iter().chain(&manifest.incrs).collect();
+        for (i, file) in files.iter().enumerate() {
+            self.load_aof_file(&aof.path(file), i + 1 == files.len()).map_err(|e| Error::msg(format!("{}: {}", file.name, e)))?;
+        }
+        self.dirty = 0;
+        Ok(true)
+    }
+
+    // Replay an AOF file through the same dispatch clients go through. A rewrite may have
+    // started it with an RDB image, which is loaded first. MULTI/EXEC only mark the commands
+    // of a transaction, which are applied once the EXEC is seen.
+    //
+    // A file that ends part way through a command, or through a transaction, was cut short
+    // while being appended to. That can only happen to the last file. With aof-load-truncated
+    // it is cut back to the last complete command and loaded, otherwise it is refused like a
+    // file with garbage in it. Errors give the offset of the problem so the file can be
+    // repaired by hand.
+    fn load_aof_file(&mut self, path: &Path, last: bool) -> Result<()> {
+        let data = std::fs::read(path)?;
+        let mut pos = 0;
+        if data.starts_with(b"REDIS") {
+            pos = self.load_rdb_data(&data)?;
+            self.verify_rdb_checksum(&data[..pos])?;
+        }
+        let mut reader = AofReader { data: &data, pos };
+        let bad_file = |msg: &str, offset: usize| Error::msg(format!("Bad AOF file at offset {}: {}", offset, msg));
+        // Where the file can be cut back to, after the last command outside a transaction
+        let mut valid = pos;
+        let mut transaction: Option<Vec<Command>> = None;
+        let truncated = loop {
+            let start = reader.pos;
+            let args = match reader.command() {
+                Ok(Some(args)) => args,
+                Ok(None) => break transaction.is_some(),
+                Err(ReadError::Truncated) => break true,
+                Err(ReadError::BadFormat) => return Err(bad_file("bad file format", start)),
+            };
+            match Command::from(&DataType::bulk_array(args)) {
+                Command::MULTI if transaction.is_none() => transaction = Some(Vec::new()),
+                Command::EXEC => {
+                    let commands = transaction.take().ok_or_else(|| bad_file("EXEC without MULTI", start))?;
+                    for cmd in commands {
+                        self.execute(cmd);
+                    }
+                }
+                Command::INVALID(msg) => return Err(bad_file(&msg, start)),
+                cmd => match transaction.as_mut() {
+                    Some(commands) => commands.push(cmd),
+                    None => {
+                        self.execute(cmd);
+                    }
+                },
+            }
+            if transaction.is_none() {
+                valid = reader.pos;
+            }
+        };
+        if truncated {
+            if !last {
+                return Err(bad_file("unexpected end of file", valid));
+            }
+            if !self.config.aof.load_truncated {
+                return Err(bad_file("unexpected end of file, set aof-load-truncated to yes to load what precedes it", valid));
+            }
+            println!("AOF {} is truncated at offset {}, cutting it back to there and loading it anyway", path.display(), valid);
+            OpenOptions::new().write(true).open(path)?.set_len(valid as u64)?;
+        }
+        Ok(())
+    }
+
+    // Open the AOF for appending and hand it the stream of writes, which go to the last incr
+    // file. An AOF without a base gets one first, holding whatever was loaded without it.
+    // Appending happens on its own task, so replies never wait on the disk, not even with
+    // appendfsync always.
+    pub fn start_aof(&mut self) -> io::Result<()> {
+        let aof = self.aof_dir();
+        std::fs::create_dir_all(&aof.dir)?;
+        let mut manifest = aof.load_manifest()?.unwrap_or_default();
+        if manifest.base.is_none() {
+            let options = RewriteOptions { rdb_preamble: self.config.aof.use_rdb_preamble, checksum: self.config.rdb.checksum };
+            let base = manifest.next_base(&aof.prefix, options.rdb_preamble);
+            write_rewrite(&aof.path(&base), &self.datastore, &self.libraries, &options)?;
+            manifest.set_base(base, u64::MAX);
+        }
+        if manifest.incrs.is_empty() {
+            manifest.add_incr(&aof.prefix);
+        }
+        let incr = manifest.incrs.last().unwrap();
+        let file = OpenOptions::new().create(true).append(true).open(aof.path(incr))?;
+        let (len, rewrite_from) = (file.metadata()?.len(), incr.seq);
+        manifest.save(&aof.manifest_path())?;
+
+        let writes = self.propagation.subscribe();
+        let (writer, requests) = mpsc::unbounded_channel();
+        self.aof_writer = Some(writer);
+        self.aof_status.lock().unwrap().set_policy(&self.config.aof);
+        let written = self.propagation.offset();
+        let writer = Writer {
+            aof,
+            manifest,
+            file: File::from_std(file),
+            len,
+            unwritten: Vec::new(),
+            unwritten_end: written,
+            written,
+            rewrite_from,
+            status: self.aof_status.clone(),
+        };
+        tokio::spawn(writer.run(writes, requests));
+        Ok(())
+    }
+
+    // WAITAOF's reply: whether the AOF is on disk up to the offset, and how many replicas have
+    // acknowledged it, of which there are none
+    pub fn waitaof_counts(&self, numlocal: u64, offset: u64) -> DataType {
+        if numlocal > 0 && self.aof_writer.is_none() {
+            return DataType::SimpleError("ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled.".to_string());
+        }
+        let local = self.aof_writer.is_some() && *self.aof_status.lock().unwrap().fsynced_offset.borrow() >= offset;
+        DataType::Array(vec![DataType::Integer(local as i64), DataType::Integer(0)])
+    }
+}
+
+// Park the connection until the AOF is on disk up to the offset of its last write and enough
+// replicas have it, the timeout elapses, or the client goes away
+pub async fn wait_aof(stream: &mut OwnedReadHalf, offset: u64, numlocal: u64, numreplicas: u64, timeout: Option<Duration>, state: &RwLock<State>) -> Option<DataType> {
+    let mut fsynced = {
+        let state = state.read().await;
+        if numlocal > 0 && state.aof_writer.is_none() {
+            return Some(state.waitaof_counts(numlocal, offset));
+        }
+        let fsynced = state.aof_status.lock().unwrap().fsynced_offset.subscribe();
+        fsynced
+    };
+    let acknowledged = async {
+        if numlocal > 0 {
+            fsynced_past(&mut fsynced, offset).await;
+        }
+        if numreplicas > 0 {
+            std::future::pending::<()>().await;
+        }
+    };
+    let sleep = async {
+        match timeout {
+            Some(timeout) => time::sleep(timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        _ = acknowledged => {}
+        _ = sleep => {}
+        _ = wait_for_disconnect(stream) => return None,
+    }
+    Some(state.read().await.waitaof_counts(numlocal, offset))
+}
+
+// With aof-fsync-before-reply, hold the reply to a write until the AOF has it on disk
+pub async fn wait_fsynced(offset: u64, state: &RwLock<State>) {
+    let mut fsynced = {
+        let state = state.read().await;
+        if !state.config.aof.fsync_before_reply || state.aof_writer.is_none() {
+            return;
+        }
+        let fsynced = state.aof_status.lock().unwrap().fsynced_offset.subscribe();
+        fsynced
+    };
+    fsynced_past(&mut fsynced, offset).await;
+}
+
+async fn fsynced_past(fsynced: &mut watch::Receiver<u64>, offset: u64) {
+    while *fsynced.borrow() < offset {
+        if fsynced.changed().await.is_err() {
+            break;
+        }
+    }
+}
+
+// Where the AOF's files go, and the name they are named after
+#[derive(Clone)]
+pub struct AofDir {
+    pub dir: PathBuf,
+    pub prefix: String,
+}
+
+impl AofDir {
+    fn manifest_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.manifest", self.prefix))
+    }
+
+    fn path(&self, file: &AofFile) -> PathBuf {
+        self.dir.join(&file.name)
+    }
+
+    fn load_manifest(&self) -> io::Result<Option<Manifest>> {
+        Manifest::load(&self.manifest_path())
+    }
+
+    // Move a rewritten base into the directory in place of the base and the incr files before
+    // the given one, which are deleted once the manifest no longer lists them
+    fn install_base(&self, manifest: &mut Manifest, temp: &Path, rdb: bool, first_incr: u64) -> io::Result<()> {
+        let base = manifest.next_base(&self.prefix, rdb);
+        std::fs::rename(temp, self.path(&base))?;
+        manifest.set_base(base, first_incr);
+        manifest.save(&self.manifest_path())
+    }
+}
+
+// Rewrite the AOF from a snapshot of the dataset, writing it out on a blocking task. A running
+// writer first moves on to a new incr file for the writes made from the snapshot on, which is
+// then all the rewritten base needs after it. Without one the base replaces every file.
+pub async fn rewrite(
+    aof: AofDir,
+    rotate: Option<(UnboundedSender<Request>, u64, UnboundedReceiver<Chunk>)>,
+    datastore: PersistentMap<Vec<u8>, DataStoreValue>,
+    libraries: Libraries,
+    options: RewriteOptions,
+) -> io::Result<()> {
+    let writer = match rotate {
+        Some((writer, start, tail)) => {
+            request(&writer, |done| Request::Rotate { start, tail, done }).await?;
+            Some(writer)
+        }
+        None => None,
+    };
+    let temp = aof.dir.join(format!("temp-rewriteaof-bg-{}.aof", std::process::id()));
+    let rdb = options.rdb_preamble;
+    let written = {
+        let (dir, temp) = (aof.dir.clone(), temp.clone());
+        task::spawn_blocking(move || {
+            std::fs::create_dir_all(&dir)?;
+            write_rewrite(&temp, &datastore, &libraries, &options)
+        })
+        .await
+        .unwrap_or_else(|e| Err(io::Error::other(e)))
+    };
+    let result = match (written, writer) {
+        (Ok(()), Some(writer)) => request(&writer, |done| Request::Install { temp: temp.clone(), rdb, done }).await,
+        (Ok(()), None) => aof.load_manifest().and_then(|manifest| aof.install_base(&mut manifest.unwrap_or_default(), &temp, rdb, u64::MAX)),
+        (Err(e), _) => Err(e),
+    };
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result
+}
+
+// The task appending to the AOF, which owns its files and the manifest listing them
+struct Writer {
+    aof: AofDir,
+    manifest: Manifest,
+    // The last incr file, and its length up to the last complete write
+    file: File,
+    len: u64,
+    // Writes not in the file yet, kept after a failed write to try again, and the offset in
+    // the write stream just past them
+    unwritten: Vec<u8>,
+    unwritten_end: u64,
+    // Offset in the write stream just past what has been written to the file
+    written: u64,
+    // The first incr file the base of a rewrite in progress is followed by
+    rewrite_from: u64,
+    status: Arc<Mutex<AofStatus>>,
+}
+
+impl Writer {
+    // Writes that queued up while the previous append was in progress go out together. Once a
+    // rewrite has rotated, the writes collected for it carry on as the stream to append.
+    async fn run(mut self, mut writes: UnboundedReceiver<Chunk>, mut requests: UnboundedReceiver<Request>) {
+        let mut timer = time::interval(FSYNC_INTERVAL);
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                chunk = writes.recv() => {
+                    let Some(chunk) = chunk else {
+                        break;
+                    };
+                    self.queue(chunk);
+                    while let Ok(chunk) = writes.try_recv() {
+                        self.queue(chunk);
+                    }
+                    self.append().await;
+                }
+                Some(request) = requests.recv() => match request {
+                    Request::Rotate { start, tail, done } => {
+                        let result = self.rotate(&mut writes, start).await;
+                        if result.is_ok() {
+                            writes = tail;
+                        }
+                        let _ = done.send(result);
+                    }
+                    Request::Install { temp, rdb, done } => {
+                        let result = self.aof.install_base(&mut self.manifest, &temp, rdb, self.rewrite_from);
+                        let _ = done.send(result);
+                    }
+                },
+                _ = timer.tick() => {
+                    if !self.unwritten.is_empty() {
+                        self.append().await;
+                    }
+                    let due = {
+                        let status = self.status.lock().unwrap();
+                        status.fsync == AppendFsync::EverySec && status.pending_fsync
+                    };
+                    if due {
+                        self.fsync_or_log().await;
+                    }
+                }
+            }
+        }
+    }
+
+    fn queue(&mut self, chunk: Chunk) {
+        self.unwritten.extend_from_slice(&chunk.payload);
+        self.unwritten_end = chunk.end;
+    }
+
+    async fn append(&mut self) {
+        let result = self.write_unwritten().await;
+        let (fsync, before_reply) = {
+            let mut status = self.status.lock().unwrap();
+            status.last_write_ok = result.is_ok();
+            status.pending_fsync |= result.is_ok();
+            (status.fsync, status.fsync_before_reply)
+        };
+        if let Err(e) = result {
+            println!("Error writing to the AOF, will try again: {}", e);
+            return;
+        }
+        match fsync {
+            // Replies are waiting on it
+            _ if before_reply => self.fsync_or_log().await,
+            AppendFsync::Always => self.fsync_or_log().await,
+            // The server never fsyncs, so writes are as durable as they get once written
+            AppendFsync::No => {
+                self.status.lock().unwrap().fsynced_offset.send_replace(self.written);
+            }
+            AppendFsync::EverySec => {}
+        }
+    }
+
+    // A write that fails part way is cut back off the file, so trying again doesn't leave a
+    // partial command in the middle of it
+    async fn write_unwritten(&mut self) -> io::Result<()> {
+        if let Err(e) = write_all(&mut self.file, &self.unwritten).await {
+            if let Err(e) = self.file.set_len(self.len).await {
+                println!("Error cutting a failed write off the AOF: {}", e);
+            }
+            return Err(e);
+        }
+        self.len += self.unwritten.len() as u64;
+        self.written = self.unwritten_end;
+        self.unwritten.clear();
+        Ok(())
+    }
+
+    // Finish the current incr file with the writes up to the offset, which are already queued,
+    // and start a new one. Writes queued after them are on the tail as well.
+    async fn rotate(&mut self, writes: &mut UnboundedReceiver<Chunk>, start: u64) -> io::Result<()> {
+        while self.unwritten_end < start {
+            let Ok(chunk) = writes.try_recv() else {
+                break;
+            };
+            self.queue(chunk);
+        }
+        self.write_unwritten().await?;
+        self.fsync().await?;
+
+        let incr = self.manifest.add_incr(&self.aof.prefix).clone();
+        let file = tokio::fs::OpenOptions::new().create(true).append(true).open(self.aof.path(&incr)).await?;
+        if let Err(e) = self.manifest.save(&self.aof.manifest_path()) {
+            self.manifest.incrs.pop();
+            return Err(e);
+        }
+        let len = file.metadata().await?.len();
+        (self.file, self.len, self.rewrite_from) = (file, len, incr.seq);
+        Ok(())
+    }
+
+    async fn fsync(&self) -> io::Result<()> {
+        self.file.sync_data().await?;
+        let mut status = self.status.lock().unwrap();
+        status.pending_fsync = false;
+        status.last_fsync = unix_time_ms() / 1000;
+        status.fsynced_offset.send_replace(self.written);
+        Ok(())
+    }
+
+    async fn fsync_or_log(&self) {
+        if let Err(e) = self.fsync().await {
+            println!("Error fsyncing the AOF: {}", e);
+        }
+    }
+}
+
+async fn write_all(file: &mut File, buf: &[u8]) -> io::Result<()> {
+    file.write_all(buf).await?;
+    file.flush().await
+}
@@ -0,0 +1,113 @@
+use std::{collections::BTreeMap, fs::File, io::Write, path::Path};
+
+use crate::{
+    commands::{functions::Libraries, zset::format_score},
+    persistent_map::PersistentMap,
+    rdb,
+    resp::DataType,
+    state::{DataStoreValue, Value},
+    types::json::JsonFormat,
+};
+
+// How a rewrite writes out the dataset
+pub struct RewriteOptions {
+    pub rdb_preamble: bool,
+    // Whether an RDB preamble carries a checksum
+    pub checksum: bool,
+}
+
+// Items added per command when rebuilding a collection, as in Redis
+const ITEMS_PER_COMMAND: usize = 64;
+
+fn arg(s: &str) -> Vec<u8> {
+    s.as_bytes().to_vec()
+}
+
+fn emit(buf: &mut Vec<u8>, args: impl IntoIterator<Item = Vec<u8>>) {
+    DataType::bulk_array(args).serialize_into(buf, false);
+}
+
+// Add a collection's items a batch at a time, each item being one or more arguments
+fn emit_batched(buf: &mut Vec<u8>, command: &str, key: &[u8], items: impl Iterator<Item = Vec<Vec<u8>>>) {
+    let mut batch = Vec::new();
+    let mut count = 0;
+    for item in items {
+        if count == 0 {
+            batch = vec![arg(command), key.to_vec()];
+        }
+        batch.extend(item);
+        count += 1;
+        if count == ITEMS_PER_COMMAND {
+            emit(buf, std::mem::take(&mut batch));
+            count = 0;
+        }
+    }
+    if count > 0 {
+        emit(buf, batch);
+    }
+}
+
+// Values no command rebuilds exactly are restored from their serialized form
+fn emit_restore(buf: &mut Vec<u8>, key: &[u8], dsv: &DataStoreValue) {
+    let ttl = dsv.expiry.unwrap_or(0).to_string().into_bytes();
+    let mut args = vec![arg("RESTORE"), key.to_vec(), ttl, rdb::dump_value(&dsv.value)];
+    if dsv.expiry.is_some() {
+        args.push(arg("ABSTTL"));
+    }
+    emit(buf, args);
+}
+
+fn emit_key(buf: &mut Vec<u8>, key: &[u8], dsv: &DataStoreValue) {
+    match &dsv.value {
+        Value::String(s) => match dsv.expiry {
+            Some(at) => emit(buf, [arg("SET"), key.to_vec(), s.clone(), arg("PXAT"), at.to_string().into_bytes()]),
+            None => emit(buf, [arg("SET"), key.to_vec(), s.clone()]),
+        },
+        // Only strings can be given an expiry by a command
+        _ if dsv.expiry.is_some() => emit_restore(buf, key, dsv),
+        Value::List(list) => emit_batched(buf, "RPUSH", key, list.iter().map(|item| vec![item.to_vec()])),
+        Value::Set(set) => emit_batched(buf, "SADD", key, set.members().into_iter().map(|member| vec![member])),
+        Value::SortedSet(zset) => {
+            emit_batched(buf, "ZADD", key, zset.iter().map(|(member, score)| vec![format_score(score), member.to_vec()]))
+        }
+        Value::Hash(hash) => {
+            emit_batched(buf, "HSET", key, hash.iter().map(|(field, value)| vec![field.to_vec(), value.to_vec()]));
+            let mut expiring: BTreeMap<u64, Vec<Vec<u8>>> = BTreeMap::new();
+            for (field, _) in hash.iter() {
+                if let Some(at) = hash.expiry(field) {
+                    expiring.entry(at).or_default().push(field.to_vec());
+                }
+            }
+            for (at, fields) in expiring {
+                let header = [arg("HPEXPIREAT"), key.to_vec(), at.to_string().into_bytes(), arg("FIELDS"), fields.len().to_string().into_bytes()];
+                emit(buf, header.into_iter().chain(fields));
+            }
+        }
+        Value::Json(json) => emit(buf, [arg("JSON.SET"), key.to_vec(), arg("$"), json.serialize(&JsonFormat::default()).into_bytes()]),
+        Value::Stream(_) | Value::Bloom(_) | Value::CountMinSketch(_) | Value::TopK(_) | Value::TimeSeries(_) => emit_restore(buf, key, dsv),
+    }
+}
+
+// The shortest run of commands that rebuilds the function libraries and the keyspace
+pub fn rewrite_commands(datastore: &PersistentMap<Vec<u8>, DataStoreValue>, libraries: &Libraries) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for library in libraries.values() {
+        emit(&mut buf, [arg("FUNCTION"), arg("LOAD"), library.code.clone()]);
+    }
+    for (key, dsv) in datastore.iter().filter(|(_, dsv)| !dsv.is_expired()) {
+        emit_key(&mut buf, key, dsv);
+    }
+    buf
+}
+
+// A rewritten AOF either rebuilds the dataset with commands, or loads it from an RDB image,
+// which is quicker to write and much quicker to load
+pub fn write_rewrite(path: &Path, datastore: &PersistentMap<Vec<u8>, DataStoreValue>, libraries: &Libraries, options: &RewriteOptions) -> std::io::Result<()> {
+    let contents = match options.rdb_preamble {
+        true => rdb::serialize(datastore, libraries, options.checksum),
+        false => rewrite_commands(datastore, libraries),
+    };
+    let mut file = File::create(path)?;
+    file.write_all(&contents)?;
+    file.sync_all()
+}
@@ -0,0 +1,190 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use tokio::{
+    net::tcp::OwnedReadHalf,
+    sync::{oneshot, RwLock},
+    time::{self, Duration},
+};
+
+use crate::{
+    command::Command,
+    resp::DataType,
+    state::State,
+};
+
+struct BlockedClient {
+    keys: Vec<Vec<u8>>,
+    command: Command,
+    // The arguments it was sent with, for propagating it once served
+    args: Option<Vec<Vec<u8>>>,
+    reply: oneshot::Sender<DataType>,
+}
+
+// Registry of connections parked on blocking commands, queued per key in arrival order
+#[derive(Default)]
+pub struct BlockingState {
+    next_id: u64,
+    clients: HashMap<u64, BlockedClient>,
+    waiters: HashMap<Vec<u8>, VecDeque<u64>>,
+    ready_keys: Vec<Vec<u8>>,
+    ready_set: HashSet<Vec<u8>>,
+}
+
+impl BlockingState {
+    fn block(&mut self, keys: Vec<Vec<u8>>, command: Command, args: Option<Vec<Vec<u8>>>) -> (u64, oneshot::Receiver<DataType>) {
+        let (tx, rx) = oneshot::channel();
+        let id = self.next_id;
+        self.next_id += 1;
+        for key in keys.iter() {
+            self.waiters.entry(key.clone()).or_default().push_back(id);
+        }
+        self.clients.insert(id, BlockedClient { keys, command, args, reply: tx });
+        (id, rx)
+    }
+
+    // Remove a client from every key queue it is waiting on
+    fn unblock(&mut self, id: u64) -> Option<BlockedClient> {
+        let client = self.clients.remove(&id)?;
+        for key in client.keys.iter() {
+            if let Some(queue) = self.waiters.get_mut(key) {
+                queue.retain(|waiter| *waiter != id);
+                if queue.is_empty() {
+                    self.waiters.remove(key);
+                }
+            }
+        }
+        Some(client)
+    }
+
+    // Mark a key as having new data so blocked clients get a chance to be served
+    pub fn signal_key_ready(&mut self, key: &[u8]) {
+        if self.waiters.contains_key(key) && !self.ready_set.contains(key) {
+            self.ready_set.insert(key.to_vec());
+            self.ready_keys.push(key.to_vec());
+        }
+    }
+}
+
+impl Command {
+    // Keys and timeout of commands that park the connection when they find no data
+    pub fn blocking_keys(&self) -> Option<(&[Vec<u8>], Option<Duration>)> {
+        match self {
+            Command::BLPOP(keys, timeout) | Command::BRPOP(keys, timeout) => Some((keys, *timeout)),
+            Command::BLMPOP(keys, _, _, timeout) | Command::BZMPOP(keys, _, _, timeout) => Some((keys, *timeout)),
+            Command::XREADBLOCK(keys, _, _, timeout) => Some((keys, *timeout)),
+            Command::XREADGROUPBLOCK(args, timeout) => Some((&args.keys, *timeout)),
+            // Only the source list is waited on
+            Command::BLMOVE(keys, _, _, timeout) => Some((&keys[..1], *timeout)),
+            _ => None,
+        }
+    }
+
+    fn timeout_reply(&self) -> DataType {
+        match self {
+            Command::BLMOVE(..) => DataType::NullBulkString,
+            _ => DataType::NullArray,
+        }
+    }
+}
+
+impl State {
+    // Fix up arguments that are relative to the data present when the command was issued, so
+    // re-running it later only picks up data that arrived afterwards
+    fn resolve_blocking_command(&mut self, cmd: Command) -> Command {
+        match cmd {
+            Command::XREADBLOCK(keys, mut ids, count, timeout) => {
+                self.resolve_xread_ids(&keys, &mut ids);
+                Command::XREADBLOCK(keys, ids, count, timeout)
+            }
+            cmd => cmd,
+        }
+    }
+}
+
+fn is_null_reply(reply: &DataType) -> bool {
+    matches!(reply, DataType::NullArray | DataType::NullBulkString)
+}
+
+impl State {
+    // Hand data on ready keys to the clients that have been waiting longest. Serving a client
+    // can make further keys ready (e.g. moves between lists) so keep going until none remain.
+    pub fn serve_blocked_clients(&mut self) {
+        while !self.blocking.ready_keys.is_empty() {
+            let keys = std::mem::take(&mut self.blocking.ready_keys);
+            self.blocking.ready_set.clear();
+            for key in keys {
+                while let Some(&id) = self.blocking.waiters.get(&key).and_then(|queue| queue.front()) {
+                    let client = &self.blocking.clients[&id];
+                    if client.reply.is_closed() {
+                        self.blocking.unblock(id);
+                        continue;
+                    }
+                    let reply = self.execute(client.command.clone());
+                    if is_null_reply(&reply) {
+                        break;
+                    }
+                    if let Some(client) = self.blocking.unblock(id) {
+                        if let Some(args) = client.args {
+                            self.propagate_command(args, &reply);
+                            self.flush_propagation();
+                        }
+                        let _ = client.reply.send(reply);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Completes if the peer closes the connection while we are parked. Pipelined data that is
+// already waiting can't be told apart from a live client, so stop watching in that case.
+pub async fn wait_for_disconnect(stream: &mut OwnedReadHalf) {
+    let mut buf = [0u8; 1];
+    if stream.readable().await.is_ok() {
+        if let Ok(0) = stream.peek(&mut buf).await {
+            return;
+        }
+    }
+    std::future::pending::<()>().await
+}
+
+// Execute a command that may block. If it finds no data the connection is parked until a
+// writer serves it, the timeout elapses, or the client goes away.
+pub async fn execute_blocking(stream: &mut OwnedReadHalf, cmd: Command, args: Option<Vec<Vec<u8>>>, state: &RwLock<State>) -> Option<DataType> {
+    let (id, mut rx, timeout) = {
+        let mut state = state.write().await;
+        let reply = state.execute(cmd.clone());
+        if let Some(args) = args.clone() {
+            state.propagate_command(args, &reply);
+        }
+        state.flush_propagation();
+        state.serve_blocked_clients();
+        if !is_null_reply(&reply) {
+            return Some(reply);
+        }
+        let (keys, timeout) = cmd.blocking_keys().unwrap();
+        let parked = state.resolve_blocking_command(cmd.clone());
+        let (id, rx) = state.blocking.block(keys.to_vec(), parked, args);
+        (id, rx, timeout)
+    };
+
+    let sleep = async {
+        match timeout {
+            Some(timeout) => time::sleep(timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+    let disconnected = tokio::select! {
+        reply = &mut rx => return reply.ok(),
+        _ = sleep => false,
+        _ = wait_for_disconnect(stream) => true,
+    };
+
+    // We may have been served after giving up but before reacquiring the lock
+    let mut state = state.write().await;
+    if state.blocking.unblock(id).is_some() {
+        return if disconnected { None } else { Some(cmd.timeout_reply()) };
+    }
+    drop(state);
+    rx.try_recv().ok()
+}
@@ -0,0 +1,335 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+use bytes::Bytes;
+use tokio::{
+    io::AsyncWriteExt,
+    net::tcp::OwnedWriteHalf,
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        Notify,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    command::{wrong_number_of_args, Command},
+    commands::server::REDIS_VERSION,
+    config::OutputBufferLimit,
+    resp::DataType,
+    state::State,
+    transaction::Transaction,
+};
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+// Connections dropped for going over their client-output-buffer-limit, reported by INFO
+static OUTPUT_BUFFER_DISCONNECTIONS: AtomicU64 = AtomicU64::new(0);
+
+pub fn output_buffer_disconnections() -> u64 {
+    OUTPUT_BUFFER_DISCONNECTIONS.load(Ordering::Relaxed)
+}
+
+// What goes out on a connection: replies and messages, encoded for its protocol when written,
+// or bytes that are already encoded, like the dataset and write stream a replica is sent
+enum Frame {
+    Data(DataType),
+    Raw(Bytes),
+}
+
+impl Frame {
+    fn encoded_len(&self) -> usize {
+        match self {
+            Frame::Data(frame) => frame.encoded_len(),
+            Frame::Raw(bytes) => bytes.len(),
+        }
+    }
+
+    fn serialize_into(&self, buf: &mut Vec<u8>, resp3: bool) {
+        match self {
+            Frame::Data(frame) => frame.serialize_into(buf, resp3),
+            Frame::Raw(bytes) => buf.extend_from_slice(bytes),
+        }
+    }
+}
+
+// Connection state shared by its handles and its writer task
+struct Shared {
+    // RESP protocol version spoken on the connection, so that frames are encoded for whatever
+    // the client last negotiated
+    protocol: AtomicU8,
+    // Bytes queued but not yet written to the socket, counted in their RESP2 encoding
+    pending: AtomicUsize,
+    // When the queue went over the soft limit, cleared once it is back under
+    over_soft_limit: Mutex<Option<Instant>>,
+    // Set when the connection is dropped, for its output buffer or by the server, which stops
+    // the writer
+    closing: AtomicBool,
+    close: Notify,
+}
+
+// Per connection state for commands that act on the connection itself. Everything written to
+// the client goes through one queue, so replies and messages published by other connections
+// are delivered in order.
+pub struct Client {
+    pub id: u64,
+    handle: ClientHandle,
+    // Commands queued since MULTI
+    pub transaction: Option<Transaction>,
+    // Offset in the write stream just past this connection's last write, for WAITAOF
+    pub write_offset: u64,
+    // Set on the connection a replica keeps to its master, which sends writes to apply but
+    // doesn't read the replies
+    pub master: bool,
+    // The port a replica connecting here takes clients on and what it can handle, given
+    // before PSYNC
+    pub listening_port: Option<u16>,
+    pub capabilities: Vec<String>,
+    // What the master sent that hasn't been passed on to our own replicas yet
+    pub unrelayed: Vec<u8>,
+}
+
+impl Client {
+    // Start the writer task for a connection and return the handle used to queue frames on it
+    pub fn new(stream: OwnedWriteHalf) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let shared = Arc::new(Shared {
+            protocol: AtomicU8::new(2),
+            pending: AtomicUsize::new(0),
+            over_soft_limit: Mutex::new(None),
+            closing: AtomicBool::new(false),
+            close: Notify::new(),
+        });
+        tokio::spawn(write_frames(stream, receiver, shared.clone()));
+        let id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+        Client { id, handle: ClientHandle { sender, shared }, transaction: None, write_offset: 0, master: false, listening_port: None, capabilities: Vec::new(), unrelayed: Vec::new() }
+    }
+
+    // Handle other connections can use to queue frames for this one
+    pub fn handle(&self) -> ClientHandle {
+        self.handle.clone()
+    }
+
+    // Fails once the writer has stopped because the peer went away
+    pub fn send(&self, frame: DataType) -> Result<(), DataType> {
+        self.handle.send(frame)
+    }
+
+    // Resolves once the writer has stopped, after which nothing more can be sent
+    pub async fn closed(&self) {
+        self.handle.closed().await
+    }
+
+    pub fn enforce_limit(&self, limit: &OutputBufferLimit) -> bool {
+        self.handle.enforce_limit(limit)
+    }
+
+    pub fn protocol(&self) -> u8 {
+        self.handle.protocol()
+    }
+
+    pub fn set_protocol(&self, protocol: u8) {
+        self.handle.shared.protocol.store(protocol, Ordering::Relaxed);
+    }
+
+    // HELLO switches the protocol if asked to and describes the server and connection
+    pub fn hello(&self, protocol: Option<u8>, auth: Option<(Vec<u8>, Vec<u8>)>, role: &str) -> DataType {
+        if protocol.is_some_and(|protocol| !(2..=3).contains(&protocol)) {
+            return DataType::SimpleError("NOPROTO unsupported protocol version".to_string());
+        }
+        // Only the default user exists and it has no password, so any password is accepted for it
+        if auth.is_some_and(|(username, _)| username != b"default") {
+            return DataType::SimpleError("WRONGPASS invalid username-password pair or user is disabled.".to_string());
+        }
+        if let Some(protocol) = protocol {
+            self.set_protocol(protocol);
+        }
+        let text = |s: &str| DataType::BulkString(s.as_bytes().to_vec());
+        DataType::Map(vec![
+            (text("server"), text("redis")),
+            (text("version"), text(REDIS_VERSION)),
+            (text("proto"), DataType::Integer(self.protocol() as i64)),
+            (text("id"), DataType::Integer(self.id as i64)),
+            (text("mode"), text("standalone")),
+            (text("role"), text(role)),
+            (text("modules"), DataType::Array(Vec::new())),
+        ])
+    }
+}
+
+// What the server keeps about each open connection, for delivering frames to it from elsewhere
+#[derive(Clone)]
+pub struct ClientHandle {
+    sender: UnboundedSender<Frame>,
+    shared: Arc<Shared>,
+}
+
+impl ClientHandle {
+    fn queue(&self, frame: Frame) -> Result<(), Frame> {
+        // Counted before sending so the writer never takes away more than has been added
+        let len = frame.encoded_len();
+        self.shared.pending.fetch_add(len, Ordering::Relaxed);
+        self.sender.send(frame).map_err(|err| {
+            self.shared.pending.fetch_sub(len, Ordering::Relaxed);
+            err.0
+        })
+    }
+
+    pub fn send(&self, frame: DataType) -> Result<(), DataType> {
+        match self.queue(Frame::Data(frame)) {
+            Err(Frame::Data(frame)) => Err(frame),
+            _ => Ok(()),
+        }
+    }
+
+    // Queue bytes that are already encoded, returning whether the writer is still running
+    pub fn send_raw(&self, bytes: Bytes) -> bool {
+        self.queue(Frame::Raw(bytes)).is_ok()
+    }
+
+    pub async fn closed(&self) {
+        self.sender.closed().await
+    }
+
+    // Drop the connection, without writing what is still queued
+    pub fn close(&self) {
+        if !self.shared.closing.swap(true, Ordering::Relaxed) {
+            self.shared.close.notify_one();
+        }
+    }
+
+    pub fn protocol(&self) -> u8 {
+        self.shared.protocol.load(Ordering::Relaxed)
+    }
+
+    // Queue a frame, then drop the connection if that took it over its output buffer limits
+    pub fn send_limited(&self, frame: DataType, limit: &OutputBufferLimit) -> Result<(), DataType> {
+        self.send(frame)?;
+        self.enforce_limit(limit);
+        Ok(())
+    }
+
+    // Drop the connection if its queued output is over the hard limit, or has been over the
+    // soft limit for longer than allowed. Returns whether the connection is being dropped.
+    pub fn enforce_limit(&self, limit: &OutputBufferLimit) -> bool {
+        let pending = self.shared.pending.load(Ordering::Relaxed);
+        let over_hard = limit.hard > 0 && pending > limit.hard;
+        let mut over_soft_since = self.shared.over_soft_limit.lock().unwrap();
+        let over_soft = if limit.soft > 0 && pending > limit.soft {
+            over_soft_since.get_or_insert_with(Instant::now).elapsed() > Duration::from_secs(limit.soft_seconds)
+        } else {
+            *over_soft_since = None;
+            false
+        };
+        if (over_hard || over_soft) && !self.shared.closing.swap(true, Ordering::Relaxed) {
+            OUTPUT_BUFFER_DISCONNECTIONS.fetch_add(1, Ordering::Relaxed);
+            self.shared.close.notify_one();
+        }
+        self.shared.closing.load(Ordering::Relaxed)
+    }
+}
+
+impl Command {
+    // CLIENT ID | GETREDIR | TRACKING | CACHING
+    pub fn parse_client(args: &[Vec<u8>]) -> Command {
+        if args.len() < 2 {
+            return wrong_number_of_args("client");
+        }
+        let subcommand = String::from_utf8_lossy(&args[1]).to_lowercase();
+        match (subcommand.as_str(), args.len()) {
+            ("id", 2) => Command::CLIENTID,
+            ("getredir", 2) => Command::CLIENTGETREDIR,
+            ("tracking", 3..) => Command::parse_client_tracking(args),
+            ("caching", 3) => Command::parse_client_caching(args),
+            ("id" | "getredir" | "tracking" | "caching", _) => wrong_number_of_args(&format!("client|{}", subcommand)),
+            _ => Command::INVALID(format!("ERR unknown subcommand '{}'. Try CLIENT HELP.", subcommand)),
+        }
+    }
+
+    // Commands that act on the calling connection rather than the datastore
+    pub fn is_connection(&self) -> bool {
+        self.is_subscription()
+            || matches!(self, Command::RESET | Command::CLIENTID | Command::CLIENTGETREDIR | Command::CLIENTTRACKING(_) | Command::CLIENTCACHING(_) | Command::REPLCONFGETACK | Command::REPLCONFACK(_))
+    }
+}
+
+impl State {
+    pub fn add_client(&mut self, client: &Client) {
+        self.clients.insert(client.id, client.handle());
+    }
+
+    // Limits for a connection's class, replicas and subscribers being held to their own
+    pub fn output_buffer_limit(&self, id: u64) -> OutputBufferLimit {
+        if self.is_replica_client(id) {
+            return self.config.output_buffer_limits.replica;
+        }
+        match self.pubsub.is_subscriber(id) {
+            true => self.config.output_buffer_limits.pubsub,
+            false => self.config.output_buffer_limits.normal,
+        }
+    }
+
+    // Forget a connection that has closed
+    pub fn remove_client(&mut self, id: u64) {
+        self.pubsub.remove_client(id);
+        self.tracking.remove_client(id);
+        self.remove_replica(id);
+        self.clients.remove(&id);
+    }
+
+    pub fn execute_connection(&mut self, client: &Client, cmd: Command) -> Vec<DataType> {
+        match cmd {
+            Command::CLIENTID => vec![DataType::Integer(client.id as i64)],
+            Command::CLIENTGETREDIR => vec![self.client_getredir(client)],
+            Command::CLIENTTRACKING(options) => vec![self.client_tracking(client, options)],
+            Command::CLIENTCACHING(yes) => vec![self.client_caching(client, yes)],
+            Command::REPLCONFGETACK => self.replconf_getack(client),
+            // Replicas aren't answered
+            Command::REPLCONFACK(offset) => {
+                self.replica_ack(client.id, offset);
+                Vec::new()
+            }
+            // Leaves subscribe mode without the usual unsubscribe replies, stops tracking and
+            // returns to RESP2
+            Command::RESET => {
+                self.pubsub.remove_client(client.id);
+                self.tracking.remove_client(client.id);
+                client.set_protocol(2);
+                vec![DataType::SimpleString("RESET".to_string())]
+            }
+            cmd => self.execute_subscription(client, cmd),
+        }
+    }
+}
+
+// Drain the frames queued for a connection, batching whatever is already waiting into one write.
+// Stops when the peer goes away or the connection is dropped for its output buffer, even with a
+// write stuck on a peer that isn't reading.
+async fn write_frames(mut stream: OwnedWriteHalf, mut frames: UnboundedReceiver<Frame>, shared: Arc<Shared>) {
+    loop {
+        let frame = tokio::select! {
+            frame = frames.recv() => match frame {
+                Some(frame) => frame,
+                None => break,
+            },
+            _ = shared.close.notified() => break,
+        };
+        let resp3 = shared.protocol.load(Ordering::Relaxed) == 3;
+        let mut len = frame.encoded_len();
+        let mut buf = Vec::with_capacity(len);
+        frame.serialize_into(&mut buf, resp3);
+        while let Ok(frame) = frames.try_recv() {
+            len += frame.encoded_len();
+            frame.serialize_into(&mut buf, resp3);
+        }
+        tokio::select! {
+            result = stream.write_all(&buf) => if result.is_err() {
+                break;
+            },
+            _ = shared.close.notified() => break,
+        }
+        shared.pending.fetch_sub(len, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,16 @@
+use std::sync::OnceLock;
+
+use tokio::time::Instant;
+
+use crate::commands::stream::unix_time_ms;
+
+// Expiries are absolute unix times in milliseconds, so they mean the same thing in a dump file
+// or on a replica. Checking them against the wall clock read once and advanced by the
+// monotonic clock keeps the checks cheap and unaffected by the system clock being stepped
+// while the server runs.
+static START: OnceLock<(u64, Instant)> = OnceLock::new();
+
+pub fn now_ms() -> u64 {
+    let (unix_ms, instant) = START.get_or_init(|| (unix_time_ms(), Instant::now()));
+    unix_ms + instant.elapsed().as_millis() as u64
+}
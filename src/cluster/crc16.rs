@@ -0,0 +1,36 @@
+// CRC-16/XMODEM, not reflected and starting from zero, as Redis Cluster hashes keys to slots with
+
+const POLY: u16 = 0x1021;
+
+const fn make_table() -> [u16; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ POLY } else { crc << 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static TABLE: [u16; 256] = make_table();
+
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, byte| (crc << 8) ^ TABLE[((crc >> 8) as u8 ^ byte) as usize])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::crc16;
+
+    #[test]
+    fn check_value() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(crc16(b""), 0);
+    }
+}
@@ -0,0 +1,239 @@
+use std::{io, path::PathBuf};
+
+use crate::{
+    command::{wrong_number_of_args, Command},
+    random::random_hex_id,
+    resp::DataType,
+    state::State,
+};
+
+mod crc16;
+mod nodes;
+mod slots;
+
+use crc16::crc16;
+pub use slots::SlotRefusal;
+
+// The keyspace is split into this many slots, each served by one master
+pub const CLUSTER_SLOTS: usize = 16384;
+
+// Nodes talk to each other over a bus on the client port plus this
+const BUS_PORT_OFFSET: u16 = 10000;
+
+// A node of the cluster as this one knows it
+#[derive(Debug, Clone)]
+pub struct ClusterNode {
+    pub id: String,
+    // Empty until the node's address is known, as this node's own is at first
+    pub ip: String,
+    pub port: u16,
+    pub bus_port: u16,
+    // myself, master, slave and the like, kept as they are written in the config file
+    pub flags: Vec<String>,
+    // The master a replica node replicates
+    pub master: Option<String>,
+    pub ping_sent: u64,
+    pub pong_received: u64,
+    pub config_epoch: u64,
+    pub connected: bool,
+    // Inclusive ranges of the slots the node serves
+    pub slots: Vec<(u16, u16)>,
+}
+
+impl ClusterNode {
+    fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|f| f == flag)
+    }
+
+    fn is_myself(&self) -> bool {
+        self.has_flag("myself")
+    }
+
+    fn slot_count(&self) -> usize {
+        self.slots.iter().map(|&(start, end)| (end - start) as usize + 1).sum()
+    }
+}
+
+// This node's view of the cluster, kept in cluster-config-file so it survives a restart
+pub struct Cluster {
+    // Id of the node that is this one
+    pub myself: String,
+    // The highest epoch seen in the cluster, and the last one this node voted in
+    pub current_epoch: u64,
+    pub last_vote_epoch: u64,
+    pub nodes: Vec<ClusterNode>,
+    // Index into nodes of the node serving each slot, kept from the nodes' ranges
+    slot_owners: Vec<Option<usize>>,
+    // How many slots some node serves, which is all of them when the cluster is ok
+    assigned_slots: usize,
+}
+
+impl Cluster {
+    // A cluster of just this node, as a master serving no slots until some are assigned to it
+    fn new(port: u16) -> Cluster {
+        let myself = ClusterNode {
+            id: random_hex_id(),
+            ip: String::new(),
+            port,
+            bus_port: port.wrapping_add(BUS_PORT_OFFSET),
+            flags: vec!["myself".to_string(), "master".to_string()],
+            master: None,
+            ping_sent: 0,
+            pong_received: 0,
+            config_epoch: 0,
+            connected: true,
+            slots: Vec::new(),
+        };
+        let (id, nodes) = (myself.id.clone(), vec![myself]);
+        let mut cluster = Cluster { myself: id, current_epoch: 0, last_vote_epoch: 0, nodes, slot_owners: Vec::new(), assigned_slots: 0 };
+        cluster.index_slots();
+        cluster
+    }
+
+    fn node(&self, id: &str) -> Option<&ClusterNode> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+
+    // Failures aren't detected, so the cluster is ok as long as every slot is served
+    fn is_ok(&self) -> bool {
+        self.assigned_slots == CLUSTER_SLOTS
+    }
+}
+
+// The slot a key hashes to. A hash tag, the part between the first { and the } after it, is all
+// that is hashed when it isn't empty, so keys with the same tag go to the same slot.
+pub fn key_slot(key: &[u8]) -> u16 {
+    let tag = key.iter().position(|&b| b == b'{').and_then(|open| {
+        let rest = &key[open + 1..];
+        rest.iter().position(|&b| b == b'}').filter(|&close| close > 0).map(|close| &rest[..close])
+    });
+    crc16(tag.unwrap_or(key)) & (CLUSTER_SLOTS as u16 - 1)
+}
+
+impl Command {
+    pub fn parse_cluster(args: &[Vec<u8>]) -> Command {
+        if args.len() < 2 {
+            return wrong_number_of_args("cluster");
+        }
+        let subcommand = String::from_utf8_lossy(&args[1]).to_lowercase();
+        match subcommand.as_str() {
+            "info" if args.len() == 2 => Command::CLUSTERINFO,
+            "myid" if args.len() == 2 => Command::CLUSTERMYID,
+            "keyslot" if args.len() == 3 => Command::CLUSTERKEYSLOT(args[2].clone()),
+            "slots" if args.len() == 2 => Command::CLUSTERSLOTS,
+            "nodes" if args.len() == 2 => Command::CLUSTERNODES,
+            "addslots" | "addslotsrange" => {
+                Command::parse_cluster_slots(&subcommand, &args[2..], subcommand == "addslotsrange").map_or_else(|e| e, Command::CLUSTERADDSLOTS)
+            }
+            "delslots" | "delslotsrange" => {
+                Command::parse_cluster_slots(&subcommand, &args[2..], subcommand == "delslotsrange").map_or_else(|e| e, Command::CLUSTERDELSLOTS)
+            }
+            "info" | "myid" | "keyslot" | "slots" | "nodes" => wrong_number_of_args(&format!("cluster|{}", subcommand)),
+            _ => Command::INVALID(format!("ERR unknown subcommand '{}'. Try CLUSTER HELP.", subcommand)),
+        }
+    }
+}
+
+impl State {
+    // cluster-config-file sits next to the dump, like the AOF
+    pub fn nodes_config_path(&self) -> PathBuf {
+        self.dump_path().with_file_name(&self.config.cluster.config_file)
+    }
+
+    // Take this node's place in the cluster from cluster-config-file, or start out as a cluster
+    // of its own with a new id, writing the file so the id stays the same from then on. The
+    // port may have changed since the file was written.
+    pub fn load_cluster(&mut self) -> io::Result<()> {
+        let (path, port) = (self.nodes_config_path(), self.config.server.port);
+        let mut cluster = Cluster::load(&path)?.unwrap_or_else(|| Cluster::new(port));
+        let myself = cluster.myself.clone();
+        if let Some(node) = cluster.nodes.iter_mut().find(|node| node.id == myself) {
+            (node.port, node.bus_port) = (port, port.wrapping_add(BUS_PORT_OFFSET));
+        }
+        cluster.save(&path)?;
+        self.cluster = Some(cluster);
+        Ok(())
+    }
+
+    // Keep a change to the cluster table across a restart. Failing to only leaves the file
+    // behind, as for the AOF manifest.
+    pub(super) fn save_cluster(&self) {
+        if let Some(Err(e)) = self.cluster.as_ref().map(|cluster| cluster.save(&self.nodes_config_path())) {
+            println!("Could not save the cluster config file: {}", e);
+        }
+    }
+
+    pub(super) fn cluster(&self) -> Result<&Cluster, DataType> {
+        self.cluster.as_ref().ok_or_else(|| DataType::SimpleError("ERR This instance has cluster support disabled".to_string()))
+    }
+
+    pub fn cluster_myid(&self) -> Result<DataType, DataType> {
+        Ok(DataType::BulkString(self.cluster()?.myself.clone().into_bytes()))
+    }
+
+    pub fn cluster_keyslot(&self, key: &[u8]) -> Result<DataType, DataType> {
+        self.cluster()?;
+        Ok(DataType::Integer(key_slot(key) as i64))
+    }
+
+    // The nodes as cluster-config-file has them, without the epochs
+    pub fn cluster_nodes(&self) -> Result<DataType, DataType> {
+        let nodes: String = self.cluster()?.nodes.iter().map(ClusterNode::serialize).collect();
+        Ok(DataType::BulkString(nodes.into_bytes()))
+    }
+
+    // Every assigned slot counts as ok, as failures aren't detected. A replica's epoch is its
+    // master's.
+    pub fn cluster_info(&self) -> Result<DataType, DataType> {
+        let cluster = self.cluster()?;
+        let assigned = cluster.assigned_slots;
+        let size = cluster.nodes.iter().filter(|node| node.master.is_none() && node.slot_count() > 0).count();
+        let myself = cluster.node(&cluster.myself);
+        let my_epoch = myself
+            .and_then(|node| node.master.as_deref().and_then(|master| cluster.node(master)).or(Some(node)))
+            .map_or(0, |node| node.config_epoch);
+        let fields = [
+            ("cluster_state", if cluster.is_ok() { "ok" } else { "fail" }.to_string()),
+            ("cluster_slots_assigned", assigned.to_string()),
+            ("cluster_slots_ok", assigned.to_string()),
+            ("cluster_slots_pfail", "0".to_string()),
+            ("cluster_slots_fail", "0".to_string()),
+            ("cluster_known_nodes", cluster.nodes.len().to_string()),
+            ("cluster_size", size.to_string()),
+            ("cluster_current_epoch", cluster.current_epoch.to_string()),
+            ("cluster_my_epoch", my_epoch.to_string()),
+            ("cluster_stats_messages_sent", "0".to_string()),
+            ("cluster_stats_messages_received", "0".to_string()),
+        ];
+        let info: String = fields.iter().map(|(field, value)| format!("{}:{}\r\n", field, value)).collect();
+        Ok(DataType::BulkString(info.into_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::key_slot;
+
+    #[test]
+    fn slots_of_plain_keys() {
+        assert_eq!(key_slot(b"123456789"), 12739);
+        assert_eq!(key_slot(b"foo"), 12182);
+        assert_eq!(key_slot(b"somekey"), 11058);
+    }
+
+    #[test]
+    fn only_the_hash_tag_is_hashed() {
+        assert_eq!(key_slot(b"{user1000}.following"), 3443);
+        assert_eq!(key_slot(b"{user1000}.followers"), key_slot(b"user1000"));
+        assert_eq!(key_slot(b"foo{bar}{zap}"), key_slot(b"bar"));
+        assert_eq!(key_slot(b"foo{{bar}}zap"), key_slot(b"{bar"));
+        // The tag ends at the first } after the first {, and must not be empty
+        assert_eq!(key_slot(b"{{}}"), key_slot(b"{"));
+        assert_eq!(key_slot(b"{}"), 15257);
+        assert_ne!(key_slot(b"{}"), key_slot(b""));
+        assert_eq!(key_slot(b"foo{}{bar}"), 8363);
+        assert_ne!(key_slot(b"foo{}{bar}"), key_slot(b"bar"));
+        // Without a closing } there is no tag
+        assert_eq!(key_slot(b"foo{bar"), 15278);
+    }
+}
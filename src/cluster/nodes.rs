@@ -0,0 +1,132 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::rdb::sync_dir;
+
+use super::{Cluster, ClusterNode, CLUSTER_SLOTS};
+
+fn bad_config(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid cluster config file format: {}", msg))
+}
+
+// A node's line: id ip:port@bus-port flags master ping-sent pong-received config-epoch
+// link-state, then the slots it serves as single slots or start-end ranges. The master is - for
+// a master.
+fn parse_node(line: &str) -> Option<ClusterNode> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    if words.len() < 8 {
+        return None;
+    }
+    // A hostname may follow the address after a comma
+    let address = words[1].split(',').next()?;
+    let (host, bus_port) = address.split_once('@')?;
+    let (ip, port) = host.rsplit_once(':')?;
+    let slots = words[8..].iter().map(|range| {
+        let (start, end): (u16, u16) = match range.split_once('-') {
+            Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+            None => range.parse().ok().map(|slot| (slot, slot))?,
+        };
+        (start <= end && (end as usize) < CLUSTER_SLOTS).then_some((start, end))
+    });
+    Some(ClusterNode {
+        id: words[0].to_string(),
+        ip: ip.to_string(),
+        port: port.parse().ok()?,
+        bus_port: bus_port.parse().ok()?,
+        flags: words[2].split(',').map(str::to_string).collect(),
+        master: Some(words[3]).filter(|master| *master != "-").map(str::to_string),
+        ping_sent: words[4].parse().ok()?,
+        pong_received: words[5].parse().ok()?,
+        config_epoch: words[6].parse().ok()?,
+        connected: words[7] == "connected",
+        slots: slots.collect::<Option<_>>()?,
+    })
+}
+
+impl ClusterNode {
+    pub fn serialize(&self) -> String {
+        let slots = self.slots.iter().map(|&(start, end)| match start == end {
+            true => format!(" {}", start),
+            false => format!(" {}-{}", start, end),
+        });
+        format!(
+            "{} {}:{}@{} {} {} {} {} {} {}{}\n",
+            self.id,
+            self.ip,
+            self.port,
+            self.bus_port,
+            self.flags.join(","),
+            self.master.as_deref().unwrap_or("-"),
+            self.ping_sent,
+            self.pong_received,
+            self.config_epoch,
+            if self.connected { "connected" } else { "disconnected" },
+            slots.collect::<String>(),
+        )
+    }
+}
+
+impl Cluster {
+    // The nodes, one to a line, then a vars line with the epochs. The node flagged myself is
+    // this one.
+    pub fn parse(text: &str) -> io::Result<Cluster> {
+        let (mut nodes, mut current_epoch, mut last_vote_epoch) = (Vec::new(), 0, 0);
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            if let Some(vars) = line.strip_prefix("vars ") {
+                let words: Vec<&str> = vars.split_whitespace().collect();
+                for pair in words.chunks_exact(2) {
+                    let value = pair[1].parse::<u64>().map_err(|_| bad_config(line))?;
+                    match pair[0] {
+                        "currentEpoch" => current_epoch = value,
+                        "lastVoteEpoch" => last_vote_epoch = value,
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+            nodes.push(parse_node(line).ok_or_else(|| bad_config(line))?);
+        }
+        let mut myself = nodes.iter().filter(|node| node.is_myself());
+        let (Some(me), None) = (myself.next(), myself.next()) else {
+            return Err(bad_config("there must be exactly one node flagged myself"));
+        };
+        let myself = me.id.clone();
+        let mut cluster = Cluster { myself, current_epoch, last_vote_epoch, nodes, slot_owners: Vec::new(), assigned_slots: 0 };
+        cluster.index_slots();
+        Ok(cluster)
+    }
+
+    // None when there is no config file yet
+    pub fn load(path: &Path) -> io::Result<Option<Cluster>> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Cluster::parse(&text).map(Some),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut text: String = self.nodes.iter().map(ClusterNode::serialize).collect();
+        text.push_str(&format!("vars currentEpoch {} lastVoteEpoch {}\n", self.current_epoch, self.last_vote_epoch));
+        text
+    }
+
+    // Write the file beside the one it replaces and rename it over, so a crash leaves either
+    // the old table or the new one
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp = path.with_file_name(format!("temp-{}", name));
+        let result = File::create(&temp).and_then(|mut file| {
+            file.write_all(self.serialize().as_bytes())?;
+            file.sync_all()
+        });
+        if let Err(e) = result.and_then(|_| std::fs::rename(&temp, path)) {
+            let _ = std::fs::remove_file(&temp);
+            return Err(e);
+        }
+        sync_dir(path.parent().unwrap_or(Path::new(".")))
+    }
+}
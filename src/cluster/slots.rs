@@ -0,0 +1,326 @@
+use std::iter;
+
+use crate::{
+    command::{parse_integer_arg, wrong_number_of_args, Command},
+    resp::DataType,
+    state::State,
+};
+
+use super::{key_slot, Cluster, ClusterNode, CLUSTER_SLOTS};
+
+const CROSSSLOT_ERROR: &str = "CROSSSLOT Keys in request don't hash to the same slot";
+const CLUSTERDOWN_ERROR: &str = "CLUSTERDOWN The cluster is down";
+
+// Why this node won't serve a set of keys
+pub enum SlotRefusal {
+    // Keys in different slots can't be served together by any node
+    CrossSlot,
+    // Not every slot is served, so the cluster serves none
+    Down,
+    // The slot is served by the node at this address
+    Moved(u16, String, u16),
+}
+
+impl Command {
+    // The keys a command acts on, all of which a cluster node has to serve to run it. Sharded
+    // channels go to slots like keys.
+    pub fn keys(&self) -> Vec<&Vec<u8>> {
+        match self {
+            Command::GET(key)
+            | Command::SET(key, ..)
+            | Command::SETPX(key, ..)
+            | Command::SETBIT(key, ..)
+            | Command::GETBIT(key, ..)
+            | Command::BITCOUNT(key, ..)
+            | Command::BITPOS(key, ..)
+            | Command::BITFIELD(key, ..)
+            | Command::OBJECTIDLETIME(key)
+            | Command::OBJECTFREQ(key)
+            | Command::OBJECTENCODING(key)
+            | Command::DUMP(key)
+            | Command::RESTORE(key, ..)
+            | Command::LPUSH(key, ..)
+            | Command::RPUSH(key, ..)
+            | Command::LPOP(key, ..)
+            | Command::RPOP(key, ..)
+            | Command::LLEN(key)
+            | Command::LRANGE(key, ..)
+            | Command::LINSERT(key, ..)
+            | Command::LSET(key, ..)
+            | Command::LINDEX(key, ..)
+            | Command::LREM(key, ..)
+            | Command::LTRIM(key, ..)
+            | Command::LPOS(key, ..)
+            | Command::HSET(key, ..)
+            | Command::HMSET(key, ..)
+            | Command::HGET(key, ..)
+            | Command::HMGET(key, ..)
+            | Command::HDEL(key, ..)
+            | Command::HGETALL(key)
+            | Command::HEXISTS(key, ..)
+            | Command::HLEN(key)
+            | Command::HKEYS(key)
+            | Command::HVALS(key)
+            | Command::HSCAN(key, ..)
+            | Command::HRANDFIELD(key, ..)
+            | Command::HEXPIRE(key, ..)
+            | Command::HTTL(key, ..)
+            | Command::HPERSIST(key, ..)
+            | Command::SADD(key, ..)
+            | Command::SREM(key, ..)
+            | Command::SMEMBERS(key)
+            | Command::SISMEMBER(key, ..)
+            | Command::SCARD(key)
+            | Command::SPOP(key, ..)
+            | Command::SRANDMEMBER(key, ..)
+            | Command::SSCAN(key, ..)
+            | Command::SMISMEMBER(key, ..)
+            | Command::ZADD(key, ..)
+            | Command::ZSCORE(key, ..)
+            | Command::ZCARD(key)
+            | Command::ZRANGE(key, ..)
+            | Command::ZRANK(key, ..)
+            | Command::ZREVRANK(key, ..)
+            | Command::ZINCRBY(key, ..)
+            | Command::ZREM(key, ..)
+            | Command::ZREMRANGEBYRANK(key, ..)
+            | Command::ZREMRANGEBYSCORE(key, ..)
+            | Command::ZREMRANGEBYLEX(key, ..)
+            | Command::ZRANDMEMBER(key, ..)
+            | Command::ZMSCORE(key, ..)
+            | Command::ZCOUNT(key, ..)
+            | Command::ZLEXCOUNT(key, ..)
+            | Command::ZSCAN(key, ..)
+            | Command::GEOPOS(key, ..)
+            | Command::GEODIST(key, ..)
+            | Command::GEOHASH(key, ..)
+            | Command::BFRESERVE(key, ..)
+            | Command::BFADD(key, ..)
+            | Command::BFMADD(key, ..)
+            | Command::BFEXISTS(key, ..)
+            | Command::BFMEXISTS(key, ..)
+            | Command::BFINFO(key, ..)
+            | Command::CMSINIT(key, ..)
+            | Command::CMSINCRBY(key, ..)
+            | Command::CMSQUERY(key, ..)
+            | Command::CMSINFO(key)
+            | Command::TOPKRESERVE(key, ..)
+            | Command::TOPKADD(key, ..)
+            | Command::TOPKQUERY(key, ..)
+            | Command::TOPKCOUNT(key, ..)
+            | Command::TOPKLIST(key, ..)
+            | Command::TOPKINFO(key)
+            | Command::JSONSET(key, ..)
+            | Command::JSONGET(key, ..)
+            | Command::JSONDEL(key, ..)
+            | Command::JSONTYPE(key, ..)
+            | Command::TSCREATE(key, ..)
+            | Command::TSADD(key, ..)
+            | Command::TSGET(key)
+            | Command::TSRANGE(key, ..)
+            | Command::TSINFO(key)
+            | Command::XADD(key, ..)
+            | Command::XRANGE(key, ..)
+            | Command::XLEN(key)
+            | Command::XDEL(key, ..)
+            | Command::XTRIM(key, ..)
+            | Command::XSETID(key, ..)
+            | Command::XGROUPCREATE(key, ..)
+            | Command::XGROUPDESTROY(key, ..)
+            | Command::XACK(key, ..)
+            | Command::XPENDING(key, ..)
+            | Command::XCLAIM(key, ..)
+            | Command::XAUTOCLAIM(key, ..)
+            | Command::XINFOSTREAM(key, ..)
+            | Command::XINFOGROUPS(key)
+            | Command::XINFOCONSUMERS(key, ..)
+            | Command::SPUBLISH(key, _) => vec![key],
+            Command::LCS(first, second, _)
+            | Command::LMOVE(first, second, ..)
+            | Command::SMOVE(first, second, _)
+            | Command::ZRANGESTORE(first, second, _) => vec![first, second],
+            Command::BITOP(_, destination, keys)
+            | Command::SINTERSTORE(destination, keys)
+            | Command::SUNIONSTORE(destination, keys)
+            | Command::SDIFFSTORE(destination, keys)
+            | Command::ZDIFFSTORE(destination, keys) => iter::once(destination).chain(keys).collect(),
+            Command::ZUNIONSTORE(destination, inputs) | Command::ZINTERSTORE(destination, inputs) => {
+                iter::once(destination).chain(&inputs.keys).collect()
+            }
+            Command::CMSMERGE(destination, sources) => iter::once(destination).chain(sources.iter().map(|(key, _)| key)).collect(),
+            Command::DEL(keys)
+            | Command::TOUCH(keys)
+            | Command::BLPOP(keys, _)
+            | Command::BRPOP(keys, _)
+            | Command::BLMOVE(keys, ..)
+            | Command::LMPOP(keys, ..)
+            | Command::BLMPOP(keys, ..)
+            | Command::SINTER(keys)
+            | Command::SUNION(keys)
+            | Command::SDIFF(keys)
+            | Command::SINTERCARD(keys, _)
+            | Command::ZDIFF(keys, _)
+            | Command::ZMPOP(keys, ..)
+            | Command::BZMPOP(keys, ..)
+            | Command::XREAD(keys, ..)
+            | Command::XREADBLOCK(keys, ..)
+            | Command::EVAL(_, keys, _)
+            | Command::EVALRO(_, keys, _)
+            | Command::EVALSHA(_, keys, _)
+            | Command::EVALSHARO(_, keys, _)
+            | Command::FCALL(_, keys, _)
+            | Command::FCALLRO(_, keys, _)
+            | Command::SSUBSCRIBE(keys)
+            | Command::SUNSUBSCRIBE(keys) => keys.iter().collect(),
+            Command::XREADGROUP(args) | Command::XREADGROUPBLOCK(args, _) => args.keys.iter().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // The slots CLUSTER ADDSLOTS and DELSLOTS are given, one by one or, for the RANGE forms, as
+    // start and end pairs
+    pub fn parse_cluster_slots(subcommand: &str, args: &[Vec<u8>], ranges: bool) -> Result<Vec<u16>, Command> {
+        let per_slot = if ranges { 2 } else { 1 };
+        if args.is_empty() || !args.len().is_multiple_of(per_slot) {
+            return Err(wrong_number_of_args(&format!("cluster|{}", subcommand)));
+        }
+        let parse = |arg: &Vec<u8>| match parse_integer_arg::<u16>(arg) {
+            Some(slot) if (slot as usize) < CLUSTER_SLOTS => Ok(slot),
+            _ => Err(Command::INVALID("ERR Invalid or out of range slot".to_string())),
+        };
+        let mut slots = Vec::new();
+        for group in args.chunks_exact(per_slot) {
+            let (start, end) = (parse(&group[0])?, parse(&group[per_slot - 1])?);
+            if start > end {
+                return Err(Command::INVALID(format!("ERR start slot number {} is greater than end slot number {}", start, end)));
+            }
+            slots.extend(start..=end);
+        }
+        Ok(slots)
+    }
+}
+
+impl Cluster {
+    // Work out which node serves each slot from the ranges the nodes have
+    pub fn index_slots(&mut self) {
+        self.slot_owners = vec![None; CLUSTER_SLOTS];
+        for (i, node) in self.nodes.iter().enumerate() {
+            for &(start, end) in &node.slots {
+                self.slot_owners[start as usize..=end as usize].fill(Some(i));
+            }
+        }
+        self.assigned_slots = self.slot_owners.iter().filter(|owner| owner.is_some()).count();
+    }
+
+    // Set the nodes' ranges from the slots each serves, after the owners have changed
+    fn collect_slot_ranges(&mut self) {
+        for node in &mut self.nodes {
+            node.slots.clear();
+        }
+        let mut start = 0;
+        while start < CLUSTER_SLOTS {
+            let owner = self.slot_owners[start];
+            let end = (start..CLUSTER_SLOTS).find(|&slot| self.slot_owners[slot] != owner).unwrap_or(CLUSTER_SLOTS);
+            if let Some(i) = owner {
+                self.nodes[i].slots.push((start as u16, end as u16 - 1));
+            }
+            start = end;
+        }
+        self.assigned_slots = self.slot_owners.iter().filter(|owner| owner.is_some()).count();
+    }
+
+    fn myself_index(&self) -> usize {
+        self.nodes.iter().position(|node| node.id == self.myself).unwrap()
+    }
+}
+
+// A node as CLUSTER SLOTS lists it: address, id and any extra networking metadata
+fn node_reply(node: &ClusterNode) -> DataType {
+    DataType::Array(vec![
+        DataType::BulkString(node.ip.clone().into_bytes()),
+        DataType::Integer(node.port as i64),
+        DataType::BulkString(node.id.clone().into_bytes()),
+        DataType::Array(Vec::new()),
+    ])
+}
+
+impl State {
+    // The slot keys hash to when this node serves it, None without keys or outside a cluster
+    pub fn local_slot(&self, keys: &[&Vec<u8>]) -> Result<Option<u16>, SlotRefusal> {
+        let (Some(cluster), Some(first)) = (&self.cluster, keys.first()) else {
+            return Ok(None);
+        };
+        let slot = key_slot(first);
+        if keys[1..].iter().any(|key| key_slot(key) != slot) {
+            return Err(SlotRefusal::CrossSlot);
+        }
+        let owner = match cluster.slot_owners[slot as usize] {
+            Some(i) if cluster.is_ok() => &cluster.nodes[i],
+            _ => return Err(SlotRefusal::Down),
+        };
+        match owner.id == cluster.myself {
+            true => Ok(Some(slot)),
+            false => Err(SlotRefusal::Moved(slot, owner.ip.clone(), owner.port)),
+        }
+    }
+
+    // The error a client's command is refused with when this node can't serve its keys,
+    // telling a cluster client where to go instead
+    pub fn cluster_redirect(&self, cmd: &Command) -> Option<String> {
+        self.local_slot(&cmd.keys()).err().map(|refusal| match refusal {
+            SlotRefusal::CrossSlot => CROSSSLOT_ERROR.to_string(),
+            SlotRefusal::Down => CLUSTERDOWN_ERROR.to_string(),
+            SlotRefusal::Moved(slot, ip, port) => format!("MOVED {} {}:{}", slot, ip, port),
+        })
+    }
+
+    // Each contiguous range of slots a master serves, with the master first and then its
+    // replicas
+    pub fn cluster_slots(&self) -> Result<DataType, DataType> {
+        let cluster = self.cluster()?;
+        let mut ranges = Vec::new();
+        for node in &cluster.nodes {
+            let replicas = cluster.nodes.iter().filter(|replica| replica.master.as_ref() == Some(&node.id));
+            for &(start, end) in &node.slots {
+                let mut range = vec![DataType::Integer(start as i64), DataType::Integer(end as i64), node_reply(node)];
+                range.extend(replicas.clone().map(node_reply));
+                ranges.push(DataType::Array(range));
+            }
+        }
+        Ok(DataType::Array(ranges))
+    }
+
+    // Give this node slots no node serves, or take slots back from it, all of them or none
+    fn assign_slots(&mut self, slots: &[u16], add: bool) -> Result<DataType, DataType> {
+        self.cluster()?;
+        let cluster = self.cluster.as_mut().unwrap();
+        let me = cluster.myself_index();
+        let mut seen = vec![false; CLUSTER_SLOTS];
+        for &slot in slots {
+            let owner = cluster.slot_owners[slot as usize];
+            if add && owner.is_some() {
+                return Err(DataType::SimpleError(format!("ERR Slot {} is already busy", slot)));
+            }
+            if !add && owner.is_none() {
+                return Err(DataType::SimpleError(format!("ERR Slot {} is already unassigned", slot)));
+            }
+            if std::mem::replace(&mut seen[slot as usize], true) {
+                return Err(DataType::SimpleError(format!("ERR Slot {} specified multiple times", slot)));
+            }
+        }
+        for &slot in slots {
+            cluster.slot_owners[slot as usize] = add.then_some(me);
+        }
+        cluster.collect_slot_ranges();
+        self.save_cluster();
+        Ok(DataType::ok())
+    }
+
+    pub fn cluster_addslots(&mut self, slots: &[u16]) -> Result<DataType, DataType> {
+        self.assign_slots(slots, true)
+    }
+
+    pub fn cluster_delslots(&mut self, slots: &[u16]) -> Result<DataType, DataType> {
+        self.assign_slots(slots, false)
+    }
+}
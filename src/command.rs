@@ -0,0 +1,560 @@
+use std::convert::From;
+
+use tokio::time::Duration;
+
+use crate::{
+    clock::now_ms,
+    commands::{
+        bitmap::{BitOperation, BitRange, BitfieldOp},
+        bloom::BloomInfoField,
+        functions::RestorePolicy,
+        keys::RestoreOptions,
+        list::LposOptions,
+        stream::{XaddId, XaddOptions, XpendingRange, XreadId, XreadgroupArgs},
+        string::LcsOptions,
+        timeseries::{TsOptions, TsRange},
+        zset::{ZaddOptions, ZrangeSpec, ZsetAlgebraInputs},
+        ExpireCondition, ScanOptions,
+    },
+    resp::DataType,
+    tracking::TrackingOptions,
+    types::{
+        json::{Json, JsonFormat},
+        jsonpath::JsonPath,
+        stream::{ClaimOptions, StreamFields, StreamId, TrimOptions},
+        zset::{LexRange, ScoreRange},
+    },
+};
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    INVALID(String),
+    PING,
+    ECHO(Vec<u8>),
+    QUIT,
+    RESET,
+    MULTI,
+    EXEC,
+    DISCARD,
+    HELLO(Option<u8>, Option<(Vec<u8>, Vec<u8>)>),
+    CLIENTID,
+    CLIENTGETREDIR,
+    CLIENTTRACKING(Option<Box<TrackingOptions>>),
+    CLIENTCACHING(bool),
+    CONFIGGET(Vec<Vec<u8>>),
+    CONFIGSET(Vec<(Vec<u8>, Vec<u8>)>),
+    INFO(Vec<String>),
+    SAVE,
+    BGSAVE,
+    BGREWRITEAOF,
+    LASTSAVE,
+    WAITAOF(u64, u64, Option<Duration>),
+    REPLCONF(Option<u16>, Vec<String>),
+    REPLCONFGETACK,
+    REPLCONFACK(u64),
+    PSYNC(String, i64, bool),
+    REPLICAOF(Option<(String, u16)>),
+    FAILOVER(Option<(String, u16)>, bool, Option<Duration>),
+    FAILOVERABORT,
+    CLUSTERINFO,
+    CLUSTERMYID,
+    CLUSTERKEYSLOT(Vec<u8>),
+    CLUSTERSLOTS,
+    CLUSTERNODES,
+    CLUSTERADDSLOTS(Vec<u16>),
+    CLUSTERDELSLOTS(Vec<u16>),
+
+    // Strings
+    GET(Vec<u8>),
+    SET(Vec<u8>, Vec<u8>),
+    SETPX(Vec<u8>, Vec<u8>, Duration),
+    LCS(Vec<u8>, Vec<u8>, LcsOptions),
+
+    // Bitmaps
+    SETBIT(Vec<u8>, u64, bool),
+    GETBIT(Vec<u8>, u64),
+    BITCOUNT(Vec<u8>, Option<BitRange>),
+    BITOP(BitOperation, Vec<u8>, Vec<Vec<u8>>),
+    BITPOS(Vec<u8>, bool, BitRange, bool),
+    BITFIELD(Vec<u8>, Vec<BitfieldOp>),
+
+    // Keyspace
+    DEL(Vec<Vec<u8>>),
+    TOUCH(Vec<Vec<u8>>),
+    OBJECTIDLETIME(Vec<u8>),
+    OBJECTFREQ(Vec<u8>),
+    OBJECTENCODING(Vec<u8>),
+    DUMP(Vec<u8>),
+    RESTORE(Vec<u8>, u64, Vec<u8>, RestoreOptions),
+
+    // Lists
+    LPUSH(Vec<u8>, Vec<Vec<u8>>),
+    RPUSH(Vec<u8>, Vec<Vec<u8>>),
+    LPOP(Vec<u8>, Option<usize>),
+    RPOP(Vec<u8>, Option<usize>),
+    LLEN(Vec<u8>),
+    LRANGE(Vec<u8>, i64, i64),
+    LINSERT(Vec<u8>, bool, Vec<u8>, Vec<u8>),
+    LSET(Vec<u8>, i64, Vec<u8>),
+    LINDEX(Vec<u8>, i64),
+    LREM(Vec<u8>, i64, Vec<u8>),
+    LTRIM(Vec<u8>, i64, i64),
+    BLPOP(Vec<Vec<u8>>, Option<Duration>),
+    BRPOP(Vec<Vec<u8>>, Option<Duration>),
+    LMOVE(Vec<u8>, Vec<u8>, bool, bool),
+    BLMOVE(Vec<Vec<u8>>, bool, bool, Option<Duration>),
+    LPOS(Vec<u8>, Vec<u8>, LposOptions),
+    LMPOP(Vec<Vec<u8>>, bool, usize),
+    BLMPOP(Vec<Vec<u8>>, bool, usize, Option<Duration>),
+
+    // Hashes
+    HSET(Vec<u8>, Vec<(Vec<u8>, Vec<u8>)>),
+    HMSET(Vec<u8>, Vec<(Vec<u8>, Vec<u8>)>),
+    HGET(Vec<u8>, Vec<u8>),
+    HMGET(Vec<u8>, Vec<Vec<u8>>),
+    HDEL(Vec<u8>, Vec<Vec<u8>>),
+    HGETALL(Vec<u8>),
+    HEXISTS(Vec<u8>, Vec<u8>),
+    HLEN(Vec<u8>),
+    HKEYS(Vec<u8>),
+    HVALS(Vec<u8>),
+    HSCAN(Vec<u8>, u64, ScanOptions),
+    HRANDFIELD(Vec<u8>, Option<i64>, bool),
+    HEXPIRE(Vec<u8>, Duration, ExpireCondition, Vec<Vec<u8>>),
+    HTTL(Vec<u8>, Vec<Vec<u8>>, bool),
+    HPERSIST(Vec<u8>, Vec<Vec<u8>>),
+
+    // Sets
+    SADD(Vec<u8>, Vec<Vec<u8>>),
+    SREM(Vec<u8>, Vec<Vec<u8>>),
+    SMEMBERS(Vec<u8>),
+    SISMEMBER(Vec<u8>, Vec<u8>),
+    SCARD(Vec<u8>),
+    SINTER(Vec<Vec<u8>>),
+    SUNION(Vec<Vec<u8>>),
+    SDIFF(Vec<Vec<u8>>),
+    SINTERSTORE(Vec<u8>, Vec<Vec<u8>>),
+    SUNIONSTORE(Vec<u8>, Vec<Vec<u8>>),
+    SDIFFSTORE(Vec<u8>, Vec<Vec<u8>>),
+    SINTERCARD(Vec<Vec<u8>>, usize),
+    SPOP(Vec<u8>, Option<usize>),
+    SRANDMEMBER(Vec<u8>, Option<i64>),
+    SMOVE(Vec<u8>, Vec<u8>, Vec<u8>),
+    SSCAN(Vec<u8>, u64, ScanOptions),
+    SMISMEMBER(Vec<u8>, Vec<Vec<u8>>),
+
+    // Sorted sets
+    ZADD(Vec<u8>, ZaddOptions, Vec<(f64, Vec<u8>)>),
+    ZSCORE(Vec<u8>, Vec<u8>),
+    ZCARD(Vec<u8>),
+    ZRANGE(Vec<u8>, Box<ZrangeSpec>),
+    ZRANGESTORE(Vec<u8>, Vec<u8>, Box<ZrangeSpec>),
+    ZRANK(Vec<u8>, Vec<u8>, bool),
+    ZREVRANK(Vec<u8>, Vec<u8>, bool),
+    ZINCRBY(Vec<u8>, f64, Vec<u8>),
+    ZREM(Vec<u8>, Vec<Vec<u8>>),
+    ZREMRANGEBYRANK(Vec<u8>, i64, i64),
+    ZREMRANGEBYSCORE(Vec<u8>, ScoreRange),
+    ZREMRANGEBYLEX(Vec<u8>, LexRange),
+    ZUNIONSTORE(Vec<u8>, ZsetAlgebraInputs),
+    ZINTERSTORE(Vec<u8>, ZsetAlgebraInputs),
+    ZDIFF(Vec<Vec<u8>>, bool),
+    ZDIFFSTORE(Vec<u8>, Vec<Vec<u8>>),
+    ZRANDMEMBER(Vec<u8>, Option<i64>, bool),
+    ZMSCORE(Vec<u8>, Vec<Vec<u8>>),
+    ZCOUNT(Vec<u8>, ScoreRange),
+    ZLEXCOUNT(Vec<u8>, LexRange),
+    ZSCAN(Vec<u8>, u64, ScanOptions),
+    ZMPOP(Vec<Vec<u8>>, bool, usize),
+    BZMPOP(Vec<Vec<u8>>, bool, usize, Option<Duration>),
+
+    // Geospatial, stored as sorted sets scored by geohash
+    GEOPOS(Vec<u8>, Vec<Vec<u8>>),
+    GEODIST(Vec<u8>, Vec<u8>, Vec<u8>, f64),
+    GEOHASH(Vec<u8>, Vec<Vec<u8>>),
+
+    // Bloom filters
+    BFRESERVE(Vec<u8>, f64, u64, Option<u32>, bool),
+    BFADD(Vec<u8>, Vec<u8>),
+    BFMADD(Vec<u8>, Vec<Vec<u8>>),
+    BFEXISTS(Vec<u8>, Vec<u8>),
+    BFMEXISTS(Vec<u8>, Vec<Vec<u8>>),
+    BFINFO(Vec<u8>, Option<BloomInfoField>),
+
+    // Count-min sketches and top-k trackers
+    CMSINIT(Vec<u8>, usize, usize),
+    CMSINCRBY(Vec<u8>, Vec<(Vec<u8>, u64)>),
+    CMSQUERY(Vec<u8>, Vec<Vec<u8>>),
+    CMSMERGE(Vec<u8>, Vec<(Vec<u8>, i64)>),
+    CMSINFO(Vec<u8>),
+    TOPKRESERVE(Vec<u8>, usize, usize, usize, f64),
+    TOPKADD(Vec<u8>, Vec<(Vec<u8>, u64)>),
+    TOPKQUERY(Vec<u8>, Vec<Vec<u8>>),
+    TOPKCOUNT(Vec<u8>, Vec<Vec<u8>>),
+    TOPKLIST(Vec<u8>, bool),
+    TOPKINFO(Vec<u8>),
+
+    // JSON documents
+    JSONSET(Vec<u8>, Box<JsonPath>, Json, bool, bool),
+    JSONGET(Vec<u8>, JsonFormat, Vec<JsonPath>),
+    JSONDEL(Vec<u8>, JsonPath),
+    JSONTYPE(Vec<u8>, JsonPath),
+
+    // Pub/sub
+    SUBSCRIBE(Vec<Vec<u8>>),
+    UNSUBSCRIBE(Vec<Vec<u8>>),
+    PSUBSCRIBE(Vec<Vec<u8>>),
+    PUNSUBSCRIBE(Vec<Vec<u8>>),
+    PUBLISH(Vec<u8>, Vec<u8>),
+    SSUBSCRIBE(Vec<Vec<u8>>),
+    SUNSUBSCRIBE(Vec<Vec<u8>>),
+    SPUBLISH(Vec<u8>, Vec<u8>),
+    PUBSUBCHANNELS(Option<Vec<u8>>, bool),
+    PUBSUBNUMSUB(Vec<Vec<u8>>, bool),
+    PUBSUBNUMPAT,
+
+    // Time series
+    TSCREATE(Vec<u8>, Box<TsOptions>),
+    TSADD(Vec<u8>, Option<u64>, f64, Box<TsOptions>),
+    TSGET(Vec<u8>),
+    TSRANGE(Vec<u8>, Box<TsRange>),
+    TSINFO(Vec<u8>),
+
+    // Streams
+    XADD(Vec<u8>, XaddOptions, XaddId, StreamFields),
+    XRANGE(Vec<u8>, StreamId, StreamId, bool, usize),
+    XREAD(Vec<Vec<u8>>, Vec<XreadId>, usize),
+    XREADBLOCK(Vec<Vec<u8>>, Vec<XreadId>, usize, Option<Duration>),
+    XLEN(Vec<u8>),
+    XDEL(Vec<u8>, Vec<StreamId>),
+    XTRIM(Vec<u8>, TrimOptions),
+    XSETID(Vec<u8>, StreamId, Option<u64>, Option<StreamId>),
+    XGROUPCREATE(Vec<u8>, Vec<u8>, XreadId, bool),
+    XGROUPDESTROY(Vec<u8>, Vec<u8>),
+    XREADGROUP(Box<XreadgroupArgs>),
+    XREADGROUPBLOCK(Box<XreadgroupArgs>, Option<Duration>),
+    XACK(Vec<u8>, Vec<u8>, Vec<StreamId>),
+    XPENDING(Vec<u8>, Vec<u8>, Option<XpendingRange>),
+    XCLAIM(Vec<u8>, Vec<u8>, Vec<u8>, Vec<StreamId>, Box<ClaimOptions>),
+    XAUTOCLAIM(Vec<u8>, Vec<u8>, Vec<u8>, u64, StreamId, usize, bool),
+    XINFOSTREAM(Vec<u8>, Option<usize>),
+    XINFOGROUPS(Vec<u8>),
+    XINFOCONSUMERS(Vec<u8>, Vec<u8>),
+
+    // Scripting
+    EVAL(Vec<u8>, Vec<Vec<u8>>, Vec<Vec<u8>>),
+    EVALRO(Vec<u8>, Vec<Vec<u8>>, Vec<Vec<u8>>),
+    EVALSHA(String, Vec<Vec<u8>>, Vec<Vec<u8>>),
+    EVALSHARO(String, Vec<Vec<u8>>, Vec<Vec<u8>>),
+    SCRIPTLOAD(Vec<u8>),
+    SCRIPTEXISTS(Vec<String>),
+    SCRIPTFLUSH,
+    FCALL(String, Vec<Vec<u8>>, Vec<Vec<u8>>),
+    FCALLRO(String, Vec<Vec<u8>>, Vec<Vec<u8>>),
+    FUNCTIONLOAD(Vec<u8>, bool),
+    FUNCTIONDELETE(String),
+    FUNCTIONFLUSH,
+    FUNCTIONLIST(Option<Vec<u8>>, bool),
+    FUNCTIONDUMP,
+    FUNCTIONRESTORE(Vec<u8>, RestorePolicy),
+}
+
+pub fn wrong_number_of_args(name: &str) -> Command {
+    Command::INVALID(format!("ERR wrong number of arguments for '{}' command", name))
+}
+
+pub fn syntax_error() -> Command {
+    Command::INVALID("ERR syntax error".to_string())
+}
+
+pub fn not_an_integer() -> Command {
+    Command::INVALID("ERR value is not an integer or out of range".to_string())
+}
+
+// Counts of random members or fields, where a negative count allows repeats. As in Redis,
+// negative counts can't go below -(i64::MAX / 2), so twice their size still fits.
+pub fn parse_random_count(arg: &[u8]) -> Result<i64, Command> {
+    match parse_integer_arg::<i64>(arg) {
+        Some(count) if count < -(i64::MAX / 2) => Err(Command::INVALID("ERR value is out of range".to_string())),
+        Some(count) => Ok(count),
+        None => Err(not_an_integer()),
+    }
+}
+
+// Blocking timeouts are given in (possibly fractional) seconds, with zero meaning forever
+pub fn parse_timeout_arg(arg: &[u8]) -> Result<Option<Duration>, Command> {
+    let timeout = match parse_integer_arg::<f64>(arg) {
+        Some(timeout) if timeout.is_finite() => timeout,
+        _ => return Err(Command::INVALID("ERR timeout is not a float or out of range".to_string())),
+    };
+    if timeout < 0.0 {
+        return Err(Command::INVALID("ERR timeout is negative".to_string()));
+    }
+    Ok((timeout > 0.0).then(|| Duration::from_secs_f64(timeout)))
+}
+
+// Parse a `numkeys key [key ...]` argument block starting at index `start`, returning the keys
+// and the index of the first argument following them
+pub fn parse_numkeys(args: &[Vec<u8>], start: usize) -> Result<(Vec<Vec<u8>>, usize), Command> {
+    let numkeys = match args.get(start).and_then(|arg| parse_integer_arg::<i64>(arg)) {
+        Some(numkeys) if numkeys > 0 => numkeys as usize,
+        Some(_) => return Err(Command::INVALID("ERR numkeys should be greater than 0".to_string())),
+        None => return Err(not_an_integer()),
+    };
+    if args.len() < start + 1 + numkeys {
+        return Err(syntax_error());
+    }
+    Ok((args[start + 1..start + 1 + numkeys].to_vec(), start + 1 + numkeys))
+}
+
+pub fn parse_integer_arg<T: std::str::FromStr>(arg: &[u8]) -> Option<T> {
+    std::str::from_utf8(arg).ok()?.parse::<T>().ok()
+}
+
+// The bulk string arguments of a request, command name included, taken out of it
+pub fn request_args(data: DataType) -> Vec<Vec<u8>> {
+    match data {
+        DataType::Array(args) => args.into_iter().filter_map(|arg| match arg {
+            DataType::BulkString(arg) => Some(arg),
+            _ => None,
+        }).collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Lowercased name of the command in a request, empty if it isn't a well formed command
+pub fn command_name(data: &DataType) -> String {
+    match data {
+        DataType::Array(args) => match args.first() {
+            Some(DataType::BulkString(name)) => String::from_utf8_lossy(name).to_lowercase(),
+            _ => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+impl From<DataType> for Command {
+    fn from(data: DataType) -> Self {
+        Command::from(&data)
+    }
+}
+
+impl From<&DataType> for Command {
+    fn from(data: &DataType) -> Self {
+        match data {
+            DataType::Array(args) => {
+                if args.is_empty() {
+                    return Command::INVALID("Invalid data type for command. must be a non-empty array".to_string());
+                }
+                let name = String::from_utf8_lossy(match args[0] {
+                    DataType::BulkString(ref cmd) => cmd,
+                    _ => { return Command::INVALID("Invalid data type for command. must be a bulk string".to_string()); }
+                });
+                match name.to_lowercase().as_str() {
+                    "ping" => Command::PING,
+                    "echo" => {
+                        if args.len() != 2 {
+                            return Command::INVALID("Invalid data type for command. must be an array of length 2".to_string());
+                        }
+                        let msg = match args[1] {
+                            DataType::BulkString(ref msg) => msg,
+                            _ => { return Command::INVALID("Invalid data type for command. must be a bulk string".to_string()); }
+                        };
+                        Command::ECHO(msg.clone())
+                    }
+                    "get" => {
+                        if args.len() != 2 {
+                            return Command::INVALID("Invalid data type for command. must be an array of length 2".to_string());
+                        }
+                        let key = match args[1] {
+                            DataType::BulkString(ref key) => key,
+                            _ => { return Command::INVALID("Invalid data type for command. must be a bulk string".to_string()); }
+                        };
+                        Command::GET(key.clone())
+                    }
+                    "set" => {
+                        if args.len() != 3 && args.len() != 5 {
+                            return Command::INVALID("Invalid data type for command. must be an array of length 3 or 5".to_string());
+                        }
+                        let key = match args[1] {
+                            DataType::BulkString(ref key) => key,
+                            _ => { return Command::INVALID("Invalid data type for command. must be a bulk string".to_string()); }
+                        };
+                        let value = match args[2] {
+                            DataType::BulkString(ref value) => value,
+                            _ => { return Command::INVALID("Invalid data type for command. must be a bulk string".to_string()); }
+                        };
+                        match args.len() {
+                            3 => { Command::SET(key.clone(), value.clone()) }
+                            5 => {
+                                let arg = match args[3] {
+                                    DataType::BulkString(ref arg) => arg,
+                                    _ => { return Command::INVALID("Invalid data type for command. must be a bulk string".to_string()); }
+                                };
+                                // PXAT is what a PX expiry is propagated as
+                                let absolute = match arg.to_ascii_lowercase().as_slice() {
+                                    b"px" => false,
+                                    b"pxat" => true,
+                                    _ => { return Command::INVALID("Invalid argument for command. PX and PXAT are the only accepted argument names".to_string()); }
+                                };
+                                let expiry = match args[4] {
+                                    DataType::BulkString(ref expiry) => match parse_integer_arg::<i64>(expiry) {
+                                        Some(expiry) if expiry <= 0 => return Command::INVALID("ERR invalid expire time in 'set' command".to_string()),
+                                        Some(expiry) => expiry as u64,
+                                        None => return not_an_integer(),
+                                    },
+                                    _ => { return Command::INVALID("Invalid data type for command. PX argument must be a bulk string".to_string()); }
+                                };
+                                let expiry = Duration::from_millis(if absolute { expiry.saturating_sub(now_ms()) } else { expiry });
+                                Command::SETPX(key.clone(), value.clone(), expiry)
+                            }
+                            _ => { todo!(); }
+                        }
+                    }
+                    name => {
+                        // Remaining commands take bulk string arguments only
+                        let mut bulk_args = Vec::with_capacity(args.len());
+                        for arg in args.iter() {
+                            match arg {
+                                DataType::BulkString(arg) => bulk_args.push(arg.clone()),
+                                _ => { return Command::INVALID("Invalid data type for command. must be a bulk string".to_string()); }
+                            }
+                        }
+                        match name {
+                            "config" => Command::parse_config(&bulk_args),
+                            "info" => Command::parse_info(&bulk_args),
+                            "save" => Command::parse_save(&bulk_args),
+                            "bgsave" => Command::parse_bgsave(&bulk_args),
+                            "bgrewriteaof" => Command::parse_bgrewriteaof(&bulk_args),
+                            "lastsave" => Command::parse_lastsave(&bulk_args),
+                            "waitaof" => Command::parse_waitaof(&bulk_args),
+                            "replconf" => Command::parse_replconf(&bulk_args),
+                            "psync" => Command::parse_psync(&bulk_args),
+                            "replicaof" | "slaveof" => Command::parse_replicaof(name, &bulk_args),
+                            "failover" => Command::parse_failover(&bulk_args),
+                            "cluster" => Command::parse_cluster(&bulk_args),
+                            "lcs" => Command::parse_lcs(&bulk_args),
+                            "setbit" => Command::parse_setbit(&bulk_args),
+                            "getbit" => Command::parse_getbit(&bulk_args),
+                            "bitcount" => Command::parse_bitcount(&bulk_args),
+                            "bitop" => Command::parse_bitop(&bulk_args),
+                            "bitpos" => Command::parse_bitpos(&bulk_args),
+                            "bitfield" | "bitfield_ro" => Command::parse_bitfield(name, &bulk_args),
+                            "del" | "unlink" => Command::parse_del(name, &bulk_args),
+                            "touch" => Command::parse_touch(&bulk_args),
+                            "object" => Command::parse_object(&bulk_args),
+                            "dump" => Command::parse_dump(&bulk_args),
+                            "restore" => Command::parse_restore(&bulk_args),
+                            "lpush" | "rpush" => Command::parse_push(name, &bulk_args),
+                            "lpop" | "rpop" => Command::parse_pop(name, &bulk_args),
+                            "llen" => Command::parse_llen(&bulk_args),
+                            "lrange" => Command::parse_lrange(&bulk_args),
+                            "linsert" => Command::parse_linsert(&bulk_args),
+                            "lset" => Command::parse_lset(&bulk_args),
+                            "lindex" => Command::parse_lindex(&bulk_args),
+                            "lrem" => Command::parse_lrem(&bulk_args),
+                            "ltrim" => Command::parse_ltrim(&bulk_args),
+                            "blpop" | "brpop" => Command::parse_bpop(name, &bulk_args),
+                            "lmove" | "blmove" | "rpoplpush" | "brpoplpush" => Command::parse_lmove(name, &bulk_args),
+                            "lpos" => Command::parse_lpos(&bulk_args),
+                            "lmpop" | "blmpop" => Command::parse_lmpop(name, &bulk_args),
+                            "hset" | "hmset" => Command::parse_hset(name, &bulk_args),
+                            "hget" | "hexists" => Command::parse_hash_field(name, &bulk_args),
+                            "hmget" | "hdel" => Command::parse_hash_fields(name, &bulk_args),
+                            "hgetall" | "hlen" | "hkeys" | "hvals" => Command::parse_hash_key(name, &bulk_args),
+                            "hscan" => Command::parse_hscan(&bulk_args),
+                            "hrandfield" => Command::parse_hrandfield(&bulk_args),
+                            "hexpire" | "hpexpire" | "hexpireat" | "hpexpireat" => Command::parse_hexpire(name, &bulk_args),
+                            "httl" | "hpttl" | "hpersist" => Command::parse_hash_field_ttl(name, &bulk_args),
+                            "sadd" | "srem" => Command::parse_set_members(name, &bulk_args),
+                            "smembers" | "scard" => Command::parse_set_key(name, &bulk_args),
+                            "sismember" => Command::parse_sismember(&bulk_args),
+                            "sinter" | "sunion" | "sdiff" => Command::parse_set_algebra(name, &bulk_args),
+                            "sinterstore" | "sunionstore" | "sdiffstore" => Command::parse_set_algebra_store(name, &bulk_args),
+                            "sintercard" => Command::parse_sintercard(&bulk_args),
+                            "spop" | "srandmember" => Command::parse_set_random(name, &bulk_args),
+                            "smove" => Command::parse_smove(&bulk_args),
+                            "sscan" => Command::parse_sscan(&bulk_args),
+                            "smismember" => Command::parse_smismember(&bulk_args),
+                            "zadd" => Command::parse_zadd(&bulk_args),
+                            "zscore" => Command::parse_zscore(&bulk_args),
+                            "zcard" => Command::parse_zcard(&bulk_args),
+                            "zrange" | "zrangestore" | "zrevrange" | "zrangebyscore" | "zrevrangebyscore" | "zrangebylex"
+                            | "zrevrangebylex" => Command::parse_zrange(name, &bulk_args),
+                            "zrank" | "zrevrank" => Command::parse_zrank(name, &bulk_args),
+                            "zincrby" => Command::parse_zincrby(&bulk_args),
+                            "zrem" => Command::parse_zrem(&bulk_args),
+                            "zremrangebyrank" | "zremrangebyscore" | "zremrangebylex" => Command::parse_zremrange(name, &bulk_args),
+                            "zunionstore" | "zinterstore" => Command::parse_zset_algebra_store(name, &bulk_args),
+                            "zdiff" | "zdiffstore" => Command::parse_zdiff(name, &bulk_args),
+                            "zrandmember" => Command::parse_zrandmember(&bulk_args),
+                            "zmscore" => Command::parse_zmscore(&bulk_args),
+                            "zcount" | "zlexcount" => Command::parse_zcount(name, &bulk_args),
+                            "zscan" => Command::parse_zscan(&bulk_args),
+                            "zmpop" | "bzmpop" => Command::parse_zmpop(name, &bulk_args),
+                            "geoadd" => Command::parse_geoadd(&bulk_args),
+                            "geopos" => Command::parse_geopos(&bulk_args),
+                            "geodist" => Command::parse_geodist(&bulk_args),
+                            "geohash" => Command::parse_geohash(&bulk_args),
+                            "bf.reserve" => Command::parse_bf_reserve(&bulk_args),
+                            "bf.add" | "bf.exists" => Command::parse_bf_item(name, &bulk_args),
+                            "bf.madd" | "bf.mexists" => Command::parse_bf_items(name, &bulk_args),
+                            "bf.info" => Command::parse_bf_info(&bulk_args),
+                            "cms.initbydim" | "cms.initbyprob" => Command::parse_cms_init(name, &bulk_args),
+                            "cms.incrby" => Command::parse_cms_incrby(&bulk_args),
+                            "cms.query" => Command::parse_cms_query(&bulk_args),
+                            "cms.merge" => Command::parse_cms_merge(&bulk_args),
+                            "cms.info" => Command::parse_cms_info(&bulk_args),
+                            "topk.reserve" => Command::parse_topk_reserve(&bulk_args),
+                            "topk.add" | "topk.incrby" => Command::parse_topk_add(name, &bulk_args),
+                            "topk.query" | "topk.count" => Command::parse_topk_items(name, &bulk_args),
+                            "topk.list" => Command::parse_topk_list(&bulk_args),
+                            "topk.info" => Command::parse_topk_info(&bulk_args),
+                            "json.set" => Command::parse_json_set(&bulk_args),
+                            "json.get" => Command::parse_json_get(&bulk_args),
+                            "json.del" | "json.forget" | "json.type" => Command::parse_json_key_path(name, &bulk_args),
+                            "subscribe" | "unsubscribe" | "psubscribe" | "punsubscribe" | "ssubscribe" | "sunsubscribe" => {
+                                Command::parse_subscription(name, &bulk_args)
+                            }
+                            "publish" | "spublish" => Command::parse_publish(name, &bulk_args),
+                            "pubsub" => Command::parse_pubsub(&bulk_args),
+                            "quit" => Command::QUIT,
+                            "hello" => Command::parse_hello(&bulk_args),
+                            "client" => Command::parse_client(&bulk_args),
+                            "reset" if bulk_args.len() == 1 => Command::RESET,
+                            "reset" => wrong_number_of_args("reset"),
+                            "multi" if bulk_args.len() == 1 => Command::MULTI,
+                            "exec" if bulk_args.len() == 1 => Command::EXEC,
+                            "discard" if bulk_args.len() == 1 => Command::DISCARD,
+                            "multi" | "exec" | "discard" => wrong_number_of_args(name),
+                            "ts.create" => Command::parse_ts_create(&bulk_args),
+                            "ts.add" => Command::parse_ts_add(&bulk_args),
+                            "ts.get" => Command::parse_ts_get(&bulk_args),
+                            "ts.range" | "ts.revrange" => Command::parse_ts_range(name, &bulk_args),
+                            "ts.info" => Command::parse_ts_info(&bulk_args),
+                            "xadd" => Command::parse_xadd(&bulk_args),
+                            "xrange" | "xrevrange" => Command::parse_xrange(name, &bulk_args),
+                            "xread" => Command::parse_xread(&bulk_args),
+                            "xlen" => Command::parse_xlen(&bulk_args),
+                            "xdel" => Command::parse_xdel(&bulk_args),
+                            "xtrim" => Command::parse_xtrim(&bulk_args),
+                            "xsetid" => Command::parse_xsetid(&bulk_args),
+                            "xgroup" => Command::parse_xgroup(&bulk_args),
+                            "xreadgroup" => Command::parse_xreadgroup(&bulk_args),
+                            "xack" => Command::parse_xack(&bulk_args),
+                            "xpending" => Command::parse_xpending(&bulk_args),
+                            "xclaim" => Command::parse_xclaim(&bulk_args),
+                            "xautoclaim" => Command::parse_xautoclaim(&bulk_args),
+                            "xinfo" => Command::parse_xinfo(&bulk_args),
+                            "eval" | "eval_ro" | "evalsha" | "evalsha_ro" | "fcall" | "fcall_ro" => Command::parse_eval(name, &bulk_args),
+                            "script" => Command::parse_script(&bulk_args),
+                            "function" => Command::parse_function(&bulk_args),
+                            _ => Command::INVALID(format!("ERR unknown command '{}'", name)),
+                        }
+                    }
+                }
+            }
+            _ => Command::INVALID("Invalid data type for command. must be an array".to_string()),
+        }
+    }
+}
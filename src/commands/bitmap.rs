@@ -0,0 +1,462 @@
+use crate::{
+    command::{not_an_integer, parse_integer_arg, syntax_error, wrong_number_of_args, Command},
+    notify::{NOTIFY_GENERIC, NOTIFY_STRING},
+    resp::DataType,
+    state::{CommandResult, State},
+};
+
+// Strings are capped at 512MB, so bit offsets must fit in 2^32 bits
+const MAX_BIT_OFFSET: u64 = (512 << 23) - 1;
+
+// Optional [start end [BYTE|BIT]] range of BITCOUNT and BITPOS
+#[derive(Debug, Clone, Copy)]
+pub struct BitRange {
+    start: i64,
+    end: i64,
+    bit: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOperation {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+// Integer type of a BITFIELD operation: i1..i64 or u1..u63
+#[derive(Debug, Clone, Copy)]
+pub struct BitfieldType {
+    signed: bool,
+    bits: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Overflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+// A BITFIELD subcommand, with writes capturing the OVERFLOW mode in effect when they were given
+#[derive(Debug, Clone, Copy)]
+pub enum BitfieldOp {
+    Get(BitfieldType, u64),
+    Set(BitfieldType, u64, i64, Overflow),
+    IncrBy(BitfieldType, u64, i64, Overflow),
+}
+
+impl BitfieldType {
+    fn parse(arg: &[u8]) -> Option<BitfieldType> {
+        let signed = match arg.first()?.to_ascii_lowercase() {
+            b'i' => true,
+            b'u' => false,
+            _ => return None,
+        };
+        let bits = parse_integer_arg::<u32>(&arg[1..])?;
+        let max = if signed { 64 } else { 63 };
+        (1..=max).contains(&bits).then_some(BitfieldType { signed, bits })
+    }
+
+    fn range(&self) -> (i128, i128) {
+        if self.signed {
+            (-(1i128 << (self.bits - 1)), (1i128 << (self.bits - 1)) - 1)
+        } else {
+            (0, (1i128 << self.bits) - 1)
+        }
+    }
+
+    // Fit a value into the type according to the overflow mode, or None when FAIL rejects it
+    fn fit(&self, value: i128, overflow: Overflow) -> Option<i64> {
+        let (min, max) = self.range();
+        if (min..=max).contains(&value) {
+            return Some(value as i64);
+        }
+        match overflow {
+            Overflow::Wrap => Some(((value - min).rem_euclid(1i128 << self.bits) + min) as i64),
+            Overflow::Sat => Some(value.clamp(min, max) as i64),
+            Overflow::Fail => None,
+        }
+    }
+}
+
+// Read `bits` bits starting at the given offset as a big endian integer, sign extending signed
+// types. Bits past the end of the string read as zero.
+fn read_field(bytes: &[u8], offset: u64, kind: BitfieldType) -> i64 {
+    let mut value = 0u64;
+    for i in 0..kind.bits as u64 {
+        value = (value << 1) | get_bit(bytes, (offset + i) as usize) as u64;
+    }
+    if kind.signed && kind.bits < 64 && value >> (kind.bits - 1) & 1 == 1 {
+        value |= u64::MAX << kind.bits;
+    }
+    value as i64
+}
+
+fn write_field(bytes: &mut Vec<u8>, offset: u64, kind: BitfieldType, value: i64) {
+    let end = ((offset + kind.bits as u64 - 1) >> 3) as usize;
+    if bytes.len() <= end {
+        bytes.resize(end + 1, 0);
+    }
+    for i in 0..kind.bits as u64 {
+        let bit = (value as u64) >> (kind.bits as u64 - 1 - i) & 1;
+        let (index, mask) = (((offset + i) >> 3) as usize, 0x80u8 >> ((offset + i) & 7));
+        if bit == 1 {
+            bytes[index] |= mask;
+        } else {
+            bytes[index] &= !mask;
+        }
+    }
+}
+
+fn parse_bit_offset(arg: &[u8]) -> Result<u64, Command> {
+    match parse_integer_arg::<i64>(arg) {
+        Some(offset) if (0..=MAX_BIT_OFFSET as i64).contains(&offset) => Ok(offset as u64),
+        _ => Err(Command::INVALID("ERR bit offset is not an integer or out of range".to_string())),
+    }
+}
+
+// Parse `start end [BYTE|BIT]`, which must be all of args
+fn parse_bit_range(args: &[Vec<u8>]) -> Result<BitRange, Command> {
+    let (start, end) = match (parse_integer_arg::<i64>(&args[0]), parse_integer_arg::<i64>(&args[1])) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return Err(not_an_integer()),
+    };
+    let bit = match args.get(2) {
+        Some(unit) if unit.eq_ignore_ascii_case(b"bit") => true,
+        Some(unit) if unit.eq_ignore_ascii_case(b"byte") => false,
+        Some(_) => return Err(syntax_error()),
+        None => false,
+    };
+    Ok(BitRange { start, end, bit })
+}
+
+// Resolve an inclusive range over `len` units where negative indexes count from the end. Unlike
+// list ranges, out of range indexes are clamped to the ends rather than emptying the range.
+fn clamp_bit_range(start: i64, end: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 { (start + len).max(0) } else { start };
+    let end = if end < 0 { (end + len).max(0) } else { end.min(len - 1) };
+    if start > end || len == 0 {
+        return None;
+    }
+    Some((start as usize, end as usize))
+}
+
+fn get_bit(bytes: &[u8], offset: usize) -> bool {
+    bytes.get(offset >> 3).is_some_and(|byte| byte & (0x80 >> (offset & 7)) != 0)
+}
+
+// Number of set bits among bit positions start..=end, counted from the most significant bit
+fn count_bits(bytes: &[u8], start: usize, end: usize) -> usize {
+    let (first, last) = (start >> 3, end >> 3);
+    let mut count: usize = bytes[first..=last].iter().map(|byte| byte.count_ones() as usize).sum();
+    // Discount the bits of the edge bytes that fall outside the range
+    count -= (bytes[first] & !(0xffu8 >> (start & 7))).count_ones() as usize;
+    count -= (bytes[last] & (0x7fu8 >> (end & 7))).count_ones() as usize;
+    count
+}
+
+// First bit position in start..=end holding `bit`, skipping whole bytes that can't contain it
+fn find_bit(bytes: &[u8], bit: bool, start: usize, end: usize) -> Option<usize> {
+    let skip = if bit { 0x00 } else { 0xff };
+    let mut offset = start;
+    while offset <= end {
+        if offset & 7 == 0 && offset + 7 <= end && bytes[offset >> 3] == skip {
+            offset += 8;
+            continue;
+        }
+        if get_bit(bytes, offset) == bit {
+            return Some(offset);
+        }
+        offset += 1;
+    }
+    None
+}
+
+impl Command {
+    pub fn parse_setbit(args: &[Vec<u8>]) -> Command {
+        if args.len() != 4 {
+            return wrong_number_of_args("setbit");
+        }
+        let offset = match parse_bit_offset(&args[2]) {
+            Ok(offset) => offset,
+            Err(err) => return err,
+        };
+        let value = match args[3].as_slice() {
+            b"0" => false,
+            b"1" => true,
+            _ => return Command::INVALID("ERR bit is not an integer or out of range".to_string()),
+        };
+        Command::SETBIT(args[1].clone(), offset, value)
+    }
+
+    pub fn parse_getbit(args: &[Vec<u8>]) -> Command {
+        if args.len() != 3 {
+            return wrong_number_of_args("getbit");
+        }
+        match parse_bit_offset(&args[2]) {
+            Ok(offset) => Command::GETBIT(args[1].clone(), offset),
+            Err(err) => err,
+        }
+    }
+
+    // BITCOUNT key [start end [BYTE|BIT]]
+    pub fn parse_bitcount(args: &[Vec<u8>]) -> Command {
+        match args.len() {
+            2 => Command::BITCOUNT(args[1].clone(), None),
+            4 | 5 => match parse_bit_range(&args[2..]) {
+                Ok(range) => Command::BITCOUNT(args[1].clone(), Some(range)),
+                Err(err) => err,
+            },
+            3 => syntax_error(),
+            _ => wrong_number_of_args("bitcount"),
+        }
+    }
+
+    // BITFIELD key [GET type offset] [SET type offset value] [INCRBY type offset increment]
+    // [OVERFLOW WRAP|SAT|FAIL] ..., with BITFIELD_RO only accepting GET
+    pub fn parse_bitfield(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() < 2 {
+            return wrong_number_of_args(name);
+        }
+        let readonly = name == "bitfield_ro";
+        let mut ops = vec![];
+        let mut overflow = Overflow::Wrap;
+        let mut i = 2;
+        while i < args.len() {
+            let subcommand = args[i].to_ascii_lowercase();
+            if subcommand == b"overflow" {
+                overflow = match args.get(i + 1).map(|mode| mode.to_ascii_lowercase()).as_deref() {
+                    Some(b"wrap") => Overflow::Wrap,
+                    Some(b"sat") => Overflow::Sat,
+                    Some(b"fail") => Overflow::Fail,
+                    Some(_) => return Command::INVALID("ERR Invalid OVERFLOW type specified".to_string()),
+                    None => return syntax_error(),
+                };
+                i += 2;
+                continue;
+            }
+            let argc = match subcommand.as_slice() {
+                b"get" => 3,
+                b"set" | b"incrby" => 4,
+                _ => return syntax_error(),
+            };
+            if i + argc > args.len() {
+                return syntax_error();
+            }
+            if readonly && argc == 4 {
+                return Command::INVALID("ERR BITFIELD_RO only supports the GET subcommand".to_string());
+            }
+            let kind = match BitfieldType::parse(&args[i + 1]) {
+                Some(kind) => kind,
+                None => {
+                    return Command::INVALID(
+                        "ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is."
+                            .to_string(),
+                    );
+                }
+            };
+            // A `#` prefix counts the offset in multiples of the type width
+            let offset = match args[i + 2].strip_prefix(b"#") {
+                Some(index) => match parse_integer_arg::<u64>(index) {
+                    Some(index) if index.checked_mul(kind.bits as u64).is_some_and(|offset| offset <= MAX_BIT_OFFSET) => {
+                        Ok(index * kind.bits as u64)
+                    }
+                    _ => Err(Command::INVALID("ERR bit offset is not an integer or out of range".to_string())),
+                },
+                None => parse_bit_offset(&args[i + 2]),
+            };
+            let offset = match offset {
+                Ok(offset) => offset,
+                Err(err) => return err,
+            };
+            if argc == 3 {
+                ops.push(BitfieldOp::Get(kind, offset));
+            } else {
+                let value = match parse_integer_arg::<i64>(&args[i + 3]) {
+                    Some(value) => value,
+                    None => return not_an_integer(),
+                };
+                ops.push(if subcommand == b"set" {
+                    BitfieldOp::Set(kind, offset, value, overflow)
+                } else {
+                    BitfieldOp::IncrBy(kind, offset, value, overflow)
+                });
+            }
+            i += argc;
+        }
+        Command::BITFIELD(args[1].clone(), ops)
+    }
+
+    // BITOP AND|OR|XOR|NOT destkey key [key ...]
+    pub fn parse_bitop(args: &[Vec<u8>]) -> Command {
+        if args.len() < 4 {
+            return wrong_number_of_args("bitop");
+        }
+        let operation = match args[1].to_ascii_lowercase().as_slice() {
+            b"and" => BitOperation::And,
+            b"or" => BitOperation::Or,
+            b"xor" => BitOperation::Xor,
+            b"not" if args.len() == 4 => BitOperation::Not,
+            b"not" => return Command::INVALID("ERR BITOP NOT must be called with a single source key.".to_string()),
+            _ => return syntax_error(),
+        };
+        Command::BITOP(operation, args[2].clone(), args[3..].to_vec())
+    }
+
+    // BITPOS key bit [start [end [BYTE|BIT]]]
+    pub fn parse_bitpos(args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 || args.len() > 6 {
+            return wrong_number_of_args("bitpos");
+        }
+        let bit = match parse_integer_arg::<i64>(&args[2]) {
+            Some(0) => false,
+            Some(1) => true,
+            Some(_) => return Command::INVALID("ERR The bit argument must be 1 or 0.".to_string()),
+            None => return not_an_integer(),
+        };
+        let range = match args.len() {
+            3 => BitRange { start: 0, end: -1, bit: false },
+            4 => match parse_integer_arg::<i64>(&args[3]) {
+                Some(start) => BitRange { start, end: -1, bit: false },
+                None => return not_an_integer(),
+            },
+            _ => match parse_bit_range(&args[3..]) {
+                Ok(range) => range,
+                Err(err) => return err,
+            },
+        };
+        Command::BITPOS(args[1].clone(), bit, range, args.len() > 4)
+    }
+}
+
+impl State {
+    pub fn setbit(&mut self, key: &[u8], offset: u64, value: bool) -> CommandResult {
+        let bytes = self.get_or_create_string(key)?;
+        let (index, mask) = ((offset >> 3) as usize, 0x80u8 >> (offset & 7));
+        if bytes.len() <= index {
+            bytes.resize(index + 1, 0);
+        }
+        let old = bytes[index] & mask != 0;
+        if value {
+            bytes[index] |= mask;
+        } else {
+            bytes[index] &= !mask;
+        }
+        self.notify_keyspace_event(NOTIFY_STRING, "setbit", key);
+        Ok(DataType::Integer(old as i64))
+    }
+
+    pub fn getbit(&mut self, key: &[u8], offset: u64) -> CommandResult {
+        let bit = self.get_string(key)?.is_some_and(|bytes| get_bit(bytes, offset as usize));
+        Ok(DataType::Integer(bit as i64))
+    }
+
+    pub fn bitcount(&mut self, key: &[u8], range: Option<BitRange>) -> CommandResult {
+        let bytes = match self.get_string(key)? {
+            Some(bytes) => bytes,
+            None => return Ok(DataType::Integer(0)),
+        };
+        let bits = match range {
+            None => (!bytes.is_empty()).then(|| (0, bytes.len() * 8 - 1)),
+            Some(range) if range.bit => clamp_bit_range(range.start, range.end, bytes.len() * 8),
+            Some(range) => clamp_bit_range(range.start, range.end, bytes.len()).map(|(start, end)| (start * 8, end * 8 + 7)),
+        };
+        let count = bits.map_or(0, |(start, end)| count_bits(bytes, start, end));
+        Ok(DataType::Integer(count as i64))
+    }
+
+    pub fn bitfield(&mut self, key: &[u8], ops: &[BitfieldOp]) -> CommandResult {
+        let writes = ops.iter().any(|op| !matches!(op, BitfieldOp::Get(..)));
+        let mut empty = Vec::new();
+        let bytes = if writes {
+            self.get_or_create_string(key)?
+        } else {
+            self.get_string(key)?.unwrap_or(&mut empty)
+        };
+        let replies = ops.iter().map(|op| match *op {
+            BitfieldOp::Get(kind, offset) => DataType::Integer(read_field(bytes, offset, kind)),
+            BitfieldOp::Set(kind, offset, value, overflow) => match kind.fit(value as i128, overflow) {
+                Some(value) => {
+                    let old = read_field(bytes, offset, kind);
+                    write_field(bytes, offset, kind, value);
+                    DataType::Integer(old)
+                }
+                None => DataType::NullBulkString,
+            },
+            BitfieldOp::IncrBy(kind, offset, increment, overflow) => {
+                let old = read_field(bytes, offset, kind);
+                match kind.fit(old as i128 + increment as i128, overflow) {
+                    Some(value) => {
+                        write_field(bytes, offset, kind, value);
+                        DataType::Integer(value)
+                    }
+                    None => DataType::NullBulkString,
+                }
+            }
+        }).collect();
+        if writes {
+            self.notify_keyspace_event(NOTIFY_STRING, "setbit", key);
+        }
+        Ok(DataType::Array(replies))
+    }
+
+    // Missing keys count as empty strings and shorter inputs are zero padded to the longest one.
+    // An empty result deletes the destination.
+    pub fn bitop(&mut self, operation: BitOperation, destination: &[u8], keys: &[Vec<u8>]) -> CommandResult {
+        let mut sources = Vec::with_capacity(keys.len());
+        for key in keys {
+            sources.push(self.get_string(key)?.cloned().unwrap_or_default());
+        }
+        let len = sources.iter().map(Vec::len).max().unwrap_or(0);
+        let mut result = sources[0].clone();
+        result.resize(len, 0);
+        for source in &sources[1..] {
+            for (i, byte) in result.iter_mut().enumerate() {
+                let other = source.get(i).copied().unwrap_or(0);
+                match operation {
+                    BitOperation::And => *byte &= other,
+                    BitOperation::Or => *byte |= other,
+                    BitOperation::Xor => *byte ^= other,
+                    BitOperation::Not => (),
+                }
+            }
+        }
+        if operation == BitOperation::Not {
+            result.iter_mut().for_each(|byte| *byte = !*byte);
+        }
+        if result.is_empty() {
+            if self.datastore.remove(destination).is_some() {
+                self.notify_keyspace_event(NOTIFY_GENERIC, "del", destination);
+            }
+        } else {
+            self.set(destination.to_vec(), result, None)?;
+        }
+        Ok(DataType::Integer(len as i64))
+    }
+
+    // Position of the first bit with the given value in the range. Searching for a clear bit in
+    // a string that is all ones reports the position just past its end, unless an explicit end
+    // bounded the search.
+    pub fn bitpos(&mut self, key: &[u8], bit: bool, range: BitRange, explicit_end: bool) -> CommandResult {
+        let bytes = match self.get_string(key)? {
+            Some(bytes) => bytes,
+            None => return Ok(DataType::Integer(if bit { -1 } else { 0 })),
+        };
+        let bits = if range.bit {
+            clamp_bit_range(range.start, range.end, bytes.len() * 8)
+        } else {
+            clamp_bit_range(range.start, range.end, bytes.len()).map(|(start, end)| (start * 8, end * 8 + 7))
+        };
+        let (start, end) = match bits {
+            Some(bits) => bits,
+            None => return Ok(DataType::Integer(-1)),
+        };
+        Ok(DataType::Integer(match find_bit(bytes, bit, start, end) {
+            Some(offset) => offset as i64,
+            None if !bit && !explicit_end => (end + 1) as i64,
+            None => -1,
+        }))
+    }
+}
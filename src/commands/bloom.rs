@@ -0,0 +1,159 @@
+use crate::{
+    command::{parse_integer_arg, syntax_error, wrong_number_of_args, Command},
+    notify::NOTIFY_MODULE,
+    resp::DataType,
+    state::{CommandResult, DataStoreValue, State, Value},
+    types::bloom::BloomFilter,
+};
+
+// Single property requested with BF.INFO
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BloomInfoField {
+    Capacity,
+    Size,
+    Filters,
+    Items,
+    Expansion,
+}
+
+fn not_found() -> DataType {
+    DataType::SimpleError("ERR not found".to_string())
+}
+
+impl Command {
+    pub fn parse_bf_reserve(args: &[Vec<u8>]) -> Command {
+        if args.len() < 4 {
+            return wrong_number_of_args("bf.reserve");
+        }
+        let error_rate = match parse_integer_arg::<f64>(&args[2]) {
+            Some(rate) if rate > 0.0 && rate < 1.0 => rate,
+            Some(_) => return Command::INVALID("ERR (0 < error rate range < 1)".to_string()),
+            None => return Command::INVALID("ERR bad error rate".to_string()),
+        };
+        let capacity = match parse_integer_arg::<i64>(&args[3]) {
+            Some(capacity) if capacity > 0 => capacity as u64,
+            Some(_) => return Command::INVALID("ERR (capacity should be larger than 0)".to_string()),
+            None => return Command::INVALID("ERR bad capacity".to_string()),
+        };
+        let (mut expansion, mut nonscaling) = (None, false);
+        let mut i = 4;
+        while let Some(arg) = args.get(i) {
+            match arg.to_ascii_lowercase().as_slice() {
+                b"nonscaling" => nonscaling = true,
+                b"expansion" => {
+                    i += 1;
+                    expansion = match args.get(i).and_then(|arg| parse_integer_arg::<i64>(arg)) {
+                        Some(factor) if factor >= 1 && factor <= u32::MAX as i64 => Some(factor as u32),
+                        Some(_) => return Command::INVALID("ERR expansion should be greater or equal to 1".to_string()),
+                        None => return Command::INVALID("ERR bad expansion".to_string()),
+                    };
+                }
+                _ => return syntax_error(),
+            }
+            i += 1;
+        }
+        if nonscaling && expansion.is_some() {
+            return Command::INVALID("ERR Nonscaling filters cannot expand".to_string());
+        }
+        Command::BFRESERVE(args[1].clone(), error_rate, capacity, expansion, nonscaling)
+    }
+
+    // BF.ADD and BF.EXISTS take a single item
+    pub fn parse_bf_item(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() != 3 {
+            return wrong_number_of_args(name);
+        }
+        let (key, item) = (args[1].clone(), args[2].clone());
+        match name {
+            "bf.add" => Command::BFADD(key, item),
+            _ => Command::BFEXISTS(key, item),
+        }
+    }
+
+    // BF.MADD and BF.MEXISTS take one or more items
+    pub fn parse_bf_items(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 {
+            return wrong_number_of_args(name);
+        }
+        let (key, items) = (args[1].clone(), args[2..].to_vec());
+        match name {
+            "bf.madd" => Command::BFMADD(key, items),
+            _ => Command::BFMEXISTS(key, items),
+        }
+    }
+
+    pub fn parse_bf_info(args: &[Vec<u8>]) -> Command {
+        let field = match args.len() {
+            2 => None,
+            3 => Some(match args[2].to_ascii_lowercase().as_slice() {
+                b"capacity" => BloomInfoField::Capacity,
+                b"size" => BloomInfoField::Size,
+                b"filters" => BloomInfoField::Filters,
+                b"items" => BloomInfoField::Items,
+                b"expansion" => BloomInfoField::Expansion,
+                _ => return Command::INVALID("ERR Invalid information value".to_string()),
+            }),
+            _ => return wrong_number_of_args("bf.info"),
+        };
+        Command::BFINFO(args[1].clone(), field)
+    }
+}
+
+impl State {
+    pub fn bf_reserve(&mut self, key: &[u8], error_rate: f64, capacity: u64, expansion: Option<u32>, nonscaling: bool) -> CommandResult {
+        if self.get_value(key).is_some() {
+            return Err(DataType::SimpleError("ERR item exists".to_string()));
+        }
+        let expansion = if nonscaling { None } else { Some(expansion.unwrap_or(self.config.bloom.expansion_factor)) };
+        let bloom = BloomFilter::new(error_rate, capacity, expansion).map_err(|msg| DataType::SimpleError(msg.to_string()))?;
+        self.datastore.insert(key.to_vec(), DataStoreValue::new(Value::Bloom(bloom), None));
+        self.notify_keyspace_event(NOTIFY_MODULE, "bf.reserve", key);
+        Ok(DataType::ok())
+    }
+
+    pub fn bf_add(&mut self, key: &[u8], item: &[u8]) -> CommandResult {
+        let added = self.get_or_create_bloom(key)?.insert(item).map_err(|msg| DataType::SimpleError(msg.to_string()))?;
+        self.notify_keyspace_event(NOTIFY_MODULE, "bf.add", key);
+        Ok(DataType::Integer(added as i64))
+    }
+
+    // Unlike BF.ADD, a full non scaling filter fails only the items that did not fit
+    pub fn bf_madd(&mut self, key: &[u8], items: &[Vec<u8>]) -> CommandResult {
+        let bloom = self.get_or_create_bloom(key)?;
+        let replies = items.iter().map(|item| match bloom.insert(item) {
+            Ok(added) => DataType::Integer(added as i64),
+            Err(msg) => DataType::SimpleError(msg.to_string()),
+        });
+        let replies = replies.collect();
+        self.notify_keyspace_event(NOTIFY_MODULE, "bf.madd", key);
+        Ok(DataType::Array(replies))
+    }
+
+    pub fn bf_exists(&mut self, key: &[u8], item: &[u8]) -> CommandResult {
+        let found = self.get_bloom(key)?.is_some_and(|bloom| bloom.contains(item));
+        Ok(DataType::Integer(found as i64))
+    }
+
+    pub fn bf_mexists(&mut self, key: &[u8], items: &[Vec<u8>]) -> CommandResult {
+        let bloom = self.get_bloom(key)?;
+        let found = items.iter().map(|item| DataType::Integer(bloom.as_ref().is_some_and(|bloom| bloom.contains(item)) as i64));
+        Ok(DataType::Array(found.collect()))
+    }
+
+    pub fn bf_info(&mut self, key: &[u8], field: Option<BloomInfoField>) -> CommandResult {
+        let bloom = self.get_bloom(key)?.ok_or_else(not_found)?;
+        let expansion = bloom.expansion().map_or(DataType::NullBulkString, |factor| DataType::Integer(factor as i64));
+        let fields = [
+            (BloomInfoField::Capacity, "Capacity", DataType::Integer(bloom.capacity() as i64)),
+            (BloomInfoField::Size, "Size", DataType::Integer(bloom.size() as i64)),
+            (BloomInfoField::Filters, "Number of filters", DataType::Integer(bloom.filters() as i64)),
+            (BloomInfoField::Items, "Number of items inserted", DataType::Integer(bloom.items() as i64)),
+            (BloomInfoField::Expansion, "Expansion rate", expansion),
+        ];
+        let reply = match field {
+            Some(field) => fields.into_iter().filter(|(f, _, _)| *f == field).map(|(_, _, value)| value).collect(),
+            None => fields.into_iter().flat_map(|(_, name, value)| [DataType::SimpleString(name.to_string()), value]).collect(),
+        };
+        Ok(DataType::Array(reply))
+    }
+}
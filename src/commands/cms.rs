@@ -0,0 +1,161 @@
+use crate::{
+    command::{parse_integer_arg, wrong_number_of_args, Command},
+    notify::NOTIFY_MODULE,
+    resp::DataType,
+    state::{CommandResult, DataStoreValue, State, Value},
+    types::cms::CountMinSketch,
+};
+
+fn cms_error(msg: &str) -> Command {
+    Command::INVALID(format!("CMS: {}", msg))
+}
+
+fn key_does_not_exist() -> DataType {
+    DataType::SimpleError("CMS: key does not exist".to_string())
+}
+
+fn parse_dimension(arg: &[u8]) -> Option<usize> {
+    parse_integer_arg::<usize>(arg).filter(|&n| n > 0)
+}
+
+fn parse_probability(arg: &[u8]) -> Option<f64> {
+    parse_integer_arg::<f64>(arg).filter(|&p| p > 0.0 && p < 1.0)
+}
+
+impl Command {
+    // CMS.INITBYDIM takes the dimensions directly, CMS.INITBYPROB derives them from error bounds
+    pub fn parse_cms_init(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() != 4 {
+            return wrong_number_of_args(name);
+        }
+        let (width, depth) = if name == "cms.initbydim" {
+            let Some(width) = parse_dimension(&args[2]) else {
+                return cms_error("invalid width");
+            };
+            let Some(depth) = parse_dimension(&args[3]) else {
+                return cms_error("invalid depth");
+            };
+            (width, depth)
+        } else {
+            let Some(error) = parse_probability(&args[2]) else {
+                return cms_error("invalid overestimation value");
+            };
+            let Some(probability) = parse_probability(&args[3]) else {
+                return cms_error("invalid prob value");
+            };
+            CountMinSketch::dimensions_for(error, probability)
+        };
+        Command::CMSINIT(args[1].clone(), width, depth)
+    }
+
+    pub fn parse_cms_incrby(args: &[Vec<u8>]) -> Command {
+        if args.len() < 4 || !args[2..].chunks_exact(2).remainder().is_empty() {
+            return wrong_number_of_args("cms.incrby");
+        }
+        let mut increments = Vec::with_capacity((args.len() - 2) / 2);
+        for pair in args[2..].chunks_exact(2) {
+            match parse_integer_arg::<i64>(&pair[1]) {
+                Some(by) if by >= 0 => increments.push((pair[0].clone(), by as u64)),
+                Some(_) => return cms_error("Number cannot be negative"),
+                None => return cms_error("Cannot parse number"),
+            }
+        }
+        Command::CMSINCRBY(args[1].clone(), increments)
+    }
+
+    pub fn parse_cms_query(args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 {
+            return wrong_number_of_args("cms.query");
+        }
+        Command::CMSQUERY(args[1].clone(), args[2..].to_vec())
+    }
+
+    // CMS.MERGE destination numKeys source [source ...] [WEIGHTS weight [weight ...]]
+    pub fn parse_cms_merge(args: &[Vec<u8>]) -> Command {
+        if args.len() < 4 {
+            return wrong_number_of_args("cms.merge");
+        }
+        let numkeys = match parse_integer_arg::<usize>(&args[2]) {
+            Some(numkeys) if numkeys > 0 => numkeys,
+            _ => return cms_error("invalid numkeys"),
+        };
+        let Some(sources) = args.get(3..3 + numkeys) else {
+            return cms_error("wrong number of keys");
+        };
+        let weights = match &args[3 + numkeys..] {
+            [] => vec![1; numkeys],
+            [keyword, weights @ ..] if keyword.eq_ignore_ascii_case(b"weights") && weights.len() == numkeys => {
+                let weights: Option<Vec<i64>> = weights.iter().map(|weight| parse_integer_arg::<i64>(weight)).collect();
+                match weights {
+                    Some(weights) => weights,
+                    None => return cms_error("invalid weight value"),
+                }
+            }
+            _ => return cms_error("wrong number of keys/weights"),
+        };
+        Command::CMSMERGE(args[1].clone(), sources.iter().cloned().zip(weights).collect())
+    }
+
+    pub fn parse_cms_info(args: &[Vec<u8>]) -> Command {
+        if args.len() != 2 {
+            return wrong_number_of_args("cms.info");
+        }
+        Command::CMSINFO(args[1].clone())
+    }
+}
+
+impl State {
+    pub fn cms_init(&mut self, key: &[u8], width: usize, depth: usize) -> CommandResult {
+        if self.get_value(key).is_some() {
+            return Err(DataType::SimpleError("CMS: key already exists".to_string()));
+        }
+        let cms = CountMinSketch::new(width, depth);
+        self.datastore.insert(key.to_vec(), DataStoreValue::new(Value::CountMinSketch(cms), None));
+        self.notify_keyspace_event(NOTIFY_MODULE, "cms.init", key);
+        Ok(DataType::ok())
+    }
+
+    pub fn cms_incrby(&mut self, key: &[u8], increments: &[(Vec<u8>, u64)]) -> CommandResult {
+        let cms = self.get_cms(key)?.ok_or_else(key_does_not_exist)?;
+        let counts = increments.iter().map(|(item, by)| DataType::Integer(cms.increment(item, *by) as i64)).collect();
+        self.notify_keyspace_event(NOTIFY_MODULE, "cms.incrby", key);
+        Ok(DataType::Array(counts))
+    }
+
+    pub fn cms_query(&mut self, key: &[u8], items: &[Vec<u8>]) -> CommandResult {
+        let cms = self.get_cms(key)?.ok_or_else(key_does_not_exist)?;
+        let counts = items.iter().map(|item| DataType::Integer(cms.query(item) as i64));
+        Ok(DataType::Array(counts.collect()))
+    }
+
+    pub fn cms_merge(&mut self, destination: &[u8], sources: &[(Vec<u8>, i64)]) -> CommandResult {
+        let (width, depth) = {
+            let cms = self.get_cms(destination)?.ok_or_else(key_does_not_exist)?;
+            (cms.width(), cms.depth())
+        };
+        let mut sketches = Vec::with_capacity(sources.len());
+        for (key, weight) in sources {
+            let cms = self.get_cms(key)?.ok_or_else(key_does_not_exist)?;
+            if cms.width() != width || cms.depth() != depth {
+                return Err(DataType::SimpleError("CMS: width/depth is not equal".to_string()));
+            }
+            sketches.push((cms.clone(), *weight));
+        }
+        let sketches: Vec<(&CountMinSketch, i64)> = sketches.iter().map(|(cms, weight)| (cms, *weight)).collect();
+        self.get_cms(destination)?.unwrap().merge(&sketches);
+        self.notify_keyspace_event(NOTIFY_MODULE, "cms.merge", destination);
+        Ok(DataType::ok())
+    }
+
+    pub fn cms_info(&mut self, key: &[u8]) -> CommandResult {
+        let cms = self.get_cms(key)?.ok_or_else(key_does_not_exist)?;
+        Ok(DataType::Array(vec![
+            DataType::SimpleString("width".to_string()),
+            DataType::Integer(cms.width() as i64),
+            DataType::SimpleString("depth".to_string()),
+            DataType::Integer(cms.depth() as i64),
+            DataType::SimpleString("count".to_string()),
+            DataType::Integer(cms.count() as i64),
+        ]))
+    }
+}
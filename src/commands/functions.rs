@@ -0,0 +1,393 @@
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc, sync::Arc};
+
+use crate::{
+    command::{wrong_number_of_args, Command},
+    commands::scripting::{limit_time, lua_to_reply, redis_library, script_error, string_array, ScriptHost},
+    glob::glob_match,
+    lua::{interpreter::Interpreter, parser::{self, FunctionBody}, LuaError, Table, TableRef, Value},
+    rdb::{crc64, encode_length, Reader, RDB_OPCODE_FUNCTION2, RDB_VERSION},
+    resp::DataType,
+    state::{CommandResult, State},
+};
+
+// Name libraries are compiled under, which shows up in error positions
+const CHUNK_NAME: &str = "user_function";
+
+// Flags a function can be registered with
+const FUNCTION_FLAGS: [&str; 5] = ["no-writes", "allow-oom", "allow-stale", "no-cluster", "allow-cross-slot-keys"];
+
+// Libraries by name, kept sorted for FUNCTION LIST and DUMP
+pub type Libraries = BTreeMap<String, Library>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestorePolicy {
+    Flush,
+    Append,
+    Replace,
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionInfo {
+    pub name: String,
+    pub description: Option<String>,
+    pub flags: Vec<String>,
+}
+
+// A loaded library. Lua values can't outlive a script run, so the library's code is run again
+// on each FCALL to register its functions before the called one runs.
+#[derive(Clone)]
+pub struct Library {
+    pub code: Vec<u8>,
+    chunk: Arc<FunctionBody>,
+    pub functions: Vec<FunctionInfo>,
+}
+
+impl Library {
+    pub fn function(&self, name: &str) -> Option<&FunctionInfo> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+}
+
+impl Command {
+    // FUNCTION LOAD | DELETE | FLUSH | LIST | DUMP | RESTORE
+    pub fn parse_function(args: &[Vec<u8>]) -> Command {
+        if args.len() < 2 {
+            return wrong_number_of_args("function");
+        }
+        let subcommand = String::from_utf8_lossy(&args[1]).to_lowercase();
+        match (subcommand.as_str(), args.len()) {
+            ("load", 3) => Command::FUNCTIONLOAD(args[2].clone(), false),
+            ("load", 4) if args[2].eq_ignore_ascii_case(b"replace") => Command::FUNCTIONLOAD(args[3].clone(), true),
+            ("load", 4) => Command::INVALID(format!("ERR Unknown option given: {}", String::from_utf8_lossy(&args[2]))),
+            ("delete", 3) => Command::FUNCTIONDELETE(String::from_utf8_lossy(&args[2]).into_owned()),
+            ("flush", 2) => Command::FUNCTIONFLUSH,
+            ("flush", 3) => match args[2].to_ascii_lowercase().as_slice() {
+                b"async" | b"sync" => Command::FUNCTIONFLUSH,
+                _ => Command::INVALID("ERR FUNCTION FLUSH only supports SYNC|ASYNC option".to_string()),
+            },
+            ("list", _) => Command::parse_function_list(&args[2..]),
+            ("dump", 2) => Command::FUNCTIONDUMP,
+            ("restore", 3) => Command::FUNCTIONRESTORE(args[2].clone(), RestorePolicy::Append),
+            ("restore", 4) => match args[3].to_ascii_lowercase().as_slice() {
+                b"flush" => Command::FUNCTIONRESTORE(args[2].clone(), RestorePolicy::Flush),
+                b"append" => Command::FUNCTIONRESTORE(args[2].clone(), RestorePolicy::Append),
+                b"replace" => Command::FUNCTIONRESTORE(args[2].clone(), RestorePolicy::Replace),
+                _ => Command::INVALID("ERR Wrong restore policy given, value should be either FLUSH, APPEND or REPLACE.".to_string()),
+            },
+            ("load" | "delete" | "flush" | "dump" | "restore", _) => wrong_number_of_args(&format!("function|{}", subcommand)),
+            _ => Command::INVALID(format!("ERR unknown subcommand '{}'. Try FUNCTION HELP.", subcommand)),
+        }
+    }
+
+    // FUNCTION LIST [LIBRARYNAME pattern] [WITHCODE]
+    fn parse_function_list(args: &[Vec<u8>]) -> Command {
+        let mut pattern = None;
+        let mut with_code = false;
+        let mut i = 0;
+        while i < args.len() {
+            match args[i].to_ascii_lowercase().as_slice() {
+                b"withcode" => with_code = true,
+                b"libraryname" if i + 1 < args.len() => {
+                    if pattern.is_some() {
+                        return Command::INVALID("ERR library name argument was already given".to_string());
+                    }
+                    i += 1;
+                    pattern = Some(args[i].clone());
+                }
+                b"libraryname" => return Command::INVALID("ERR library name argument was not given".to_string()),
+                _ => return Command::INVALID(format!("ERR Unknown argument {}", String::from_utf8_lossy(&args[i]))),
+            }
+            i += 1;
+        }
+        Command::FUNCTIONLIST(pattern, with_code)
+    }
+}
+
+fn valid_name(name: &[u8]) -> bool {
+    !name.is_empty() && name.iter().all(|b| b.is_ascii_alphanumeric() || *b == b'_')
+}
+
+// The `#!lua name=<library>` line libraries start with, giving the library name and where the
+// Lua code starts
+fn parse_metadata(code: &[u8]) -> Result<(String, usize), DataType> {
+    let error = |msg: String| DataType::SimpleError(msg);
+    let Some(rest) = code.strip_prefix(b"#!") else {
+        return Err(error("ERR Missing library metadata".to_string()));
+    };
+    let line_end = rest.iter().position(|b| *b == b'\n').unwrap_or(rest.len());
+    let line = String::from_utf8_lossy(&rest[..line_end]);
+    let mut parts = line.split(' ').filter(|part| !part.is_empty());
+    let engine = parts.next().unwrap_or_default();
+    if !engine.eq_ignore_ascii_case("lua") {
+        return Err(error(format!("ERR Engine '{}' not found", engine)));
+    }
+    let mut name = None;
+    for part in parts {
+        match part.strip_prefix("name=") {
+            Some(value) => name = Some(value.to_string()),
+            None => return Err(error(format!("ERR Invalid metadata value given: {}", part))),
+        }
+    }
+    let Some(name) = name else {
+        return Err(error("ERR Library name was not given".to_string()));
+    };
+    if !valid_name(name.as_bytes()) {
+        return Err(error("ERR Library names can only contain letters, numbers, or underscores(_) and must be at least one character long".to_string()));
+    }
+    Ok((name, 2 + line_end))
+}
+
+// redis.register_function(name, callback) or redis.register_function{function_name=...,
+// callback=..., flags={...}, description=...}
+fn redis_register_function(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let (name, callback, flags, description) = match args.as_slice() {
+        [Value::Table(options)] => {
+            let options = options.borrow();
+            let mut key = Value::Nil;
+            while let Ok(Some((next, _))) = options.next(&key) {
+                if !matches!(&next, Value::Str(s) if [&b"function_name"[..], b"callback", b"flags", b"description"].contains(&&s[..])) {
+                    return Err(interp.error("unknown argument given to redis.register_function"));
+                }
+                key = next;
+            }
+            (options.get_str("function_name"), options.get_str("callback"), options.get_str("flags"), options.get_str("description"))
+        }
+        [name, callback] => (name.clone(), callback.clone(), Value::Nil, Value::Nil),
+        _ => return Err(interp.error("wrong number of arguments to redis.register_function")),
+    };
+    let Value::Str(name) = name else {
+        return Err(interp.error("function_name argument given to redis.register_function must be a string"));
+    };
+    if !valid_name(&name) {
+        return Err(interp.error("Function names can only contain letters, numbers, or underscores(_) and must be at least one character long"));
+    }
+    if !matches!(callback, Value::Function(_) | Value::Builtin(..)) {
+        return Err(interp.error("callback argument given to redis.register_function must be a function"));
+    }
+    let flags = match flags {
+        Value::Nil => Table::default(),
+        Value::Table(flags) => {
+            let flags = flags.borrow();
+            for flag in flags.array() {
+                if !matches!(flag, Value::Str(flag) if FUNCTION_FLAGS.iter().any(|known| known.as_bytes() == &flag[..])) {
+                    return Err(interp.error("unknown flag given"));
+                }
+            }
+            Table::from_array(flags.array().to_vec())
+        }
+        _ => return Err(interp.error("flags argument to redis.register_function must be a table representing function flags")),
+    };
+    if !matches!(description, Value::Nil | Value::Str(_)) {
+        return Err(interp.error("function description argument given to redis.register_function must a string"));
+    }
+    let registry = interp.registry.clone();
+    let mut registry = registry.borrow_mut();
+    if registry.array().iter().any(|entry| matches!(entry, Value::Table(entry) if entry.borrow().get_str("name").raw_equals(&Value::Str(name.clone())))) {
+        return Err(interp.error("Function already exists in the library"));
+    }
+    let mut entry = Table::default();
+    entry.set_str("name", Value::Str(name));
+    entry.set_str("callback", callback);
+    entry.set_str("flags", Value::table(flags));
+    entry.set_str("description", description);
+    registry.push(Value::table(entry));
+    Ok(Vec::new())
+}
+
+// The redis library as seen by a library's code, with register_function added
+fn library_api() -> TableRef {
+    let mut redis = redis_library();
+    redis.set_str("register_function", Value::Builtin("register_function", redis_register_function));
+    Rc::new(RefCell::new(redis))
+}
+
+// Run a library's code, returning the functions it registered with their callbacks
+fn register_functions(interp: &mut Interpreter, chunk: Arc<FunctionBody>) -> Result<Vec<(FunctionInfo, Value)>, LuaError> {
+    interp.run(chunk)?;
+    let registry = interp.registry.borrow();
+    let functions = registry.array().iter().filter_map(|entry| {
+        let Value::Table(entry) = entry else {
+            return None;
+        };
+        let entry = entry.borrow();
+        let text = |value: Value| value.to_bytes().map(|bytes| String::from_utf8_lossy(&bytes).into_owned());
+        let flags = match entry.get_str("flags") {
+            Value::Table(flags) => flags.borrow().array().iter().filter_map(|flag| text(flag.clone())).collect(),
+            _ => Vec::new(),
+        };
+        let info = FunctionInfo { name: text(entry.get_str("name"))?, description: text(entry.get_str("description")), flags };
+        Some((info, entry.get_str("callback")))
+    });
+    Ok(functions.collect())
+}
+
+fn function_info_reply(function: &FunctionInfo) -> DataType {
+    DataType::Map(vec![
+        (DataType::BulkString(b"name".to_vec()), DataType::BulkString(function.name.as_bytes().to_vec())),
+        (
+            DataType::BulkString(b"description".to_vec()),
+            function.description.as_ref().map_or(DataType::NullBulkString, |description| DataType::BulkString(description.as_bytes().to_vec())),
+        ),
+        (DataType::BulkString(b"flags".to_vec()), DataType::Array(function.flags.iter().map(|flag| DataType::SimpleString(flag.clone())).collect())),
+    ])
+}
+
+impl State {
+    // Compile a library and run its code to collect the functions it registers
+    fn compile_library(&mut self, code: &[u8]) -> Result<(String, Library), DataType> {
+        let (name, lua_start) = parse_metadata(code)?;
+        // Parsing from the end of the metadata line keeps line numbers in errors right
+        let chunk = parser::parse(&code[lua_start..]).map_err(|(msg, line)| {
+            DataType::SimpleError(format!("ERR Error compiling function: {}:{}: {}", CHUNK_NAME, line, msg))
+        })?;
+        let time_limit = self.config.server.busy_reply_threshold;
+        let mut host = ScriptHost::new(self, true);
+        let mut interp = Interpreter::new(&mut host, CHUNK_NAME);
+        limit_time(&mut interp, time_limit);
+        let redis = library_api();
+        // Libraries can't touch the keyspace while they are being loaded
+        redis.borrow_mut().set_str("call", Value::Nil);
+        redis.borrow_mut().set_str("pcall", Value::Nil);
+        interp.set_global("redis", Value::Table(redis));
+        interp.protect_globals();
+        let functions = register_functions(&mut interp, chunk.clone())
+            .map_err(|err| DataType::SimpleError(format!("ERR Error registering functions: {}", err.message())))?;
+        if functions.is_empty() {
+            return Err(DataType::SimpleError("ERR No functions registered".to_string()));
+        }
+        let functions = functions.into_iter().map(|(info, _)| info).collect();
+        Ok((name, Library { code: code.to_vec(), chunk, functions }))
+    }
+
+    // Add compiled libraries, all or none of them. Function names must stay unique across
+    // libraries, and existing libraries are only replaced when asked to.
+    fn add_libraries(&mut self, libraries: Vec<(String, Library)>, replace: bool) -> Result<(), DataType> {
+        let mut merged = self.libraries.clone();
+        for (name, library) in libraries {
+            if merged.remove(&name).is_some() && !replace {
+                return Err(DataType::SimpleError(format!("ERR Library '{}' already exists", name)));
+            }
+            for function in library.functions.iter() {
+                if merged.values().any(|other| other.function(&function.name).is_some()) {
+                    return Err(DataType::SimpleError(format!("ERR Function {} already exists", function.name)));
+                }
+            }
+            merged.insert(name, library);
+        }
+        self.libraries = merged;
+        Ok(())
+    }
+
+    // Add a library saved in the RDB file
+    pub fn load_library(&mut self, code: &[u8]) -> Result<(), DataType> {
+        let library = self.compile_library(code)?;
+        self.add_libraries(vec![library], false)
+    }
+
+    pub fn function_load(&mut self, code: &[u8], replace: bool) -> CommandResult {
+        let (name, library) = self.compile_library(code)?;
+        self.add_libraries(vec![(name.clone(), library)], replace)?;
+        Ok(DataType::BulkString(name.into_bytes()))
+    }
+
+    pub fn function_delete(&mut self, name: &str) -> CommandResult {
+        match self.libraries.remove(name) {
+            Some(_) => Ok(DataType::ok()),
+            None => Err(DataType::SimpleError("ERR Library not found".to_string())),
+        }
+    }
+
+    pub fn function_list(&self, pattern: Option<&[u8]>, with_code: bool) -> DataType {
+        let libraries = self.libraries.iter().filter(|(name, _)| pattern.is_none_or(|pattern| glob_match(pattern, name.as_bytes())));
+        DataType::Array(
+            libraries
+                .map(|(name, library)| {
+                    let mut fields = vec![
+                        (DataType::BulkString(b"library_name".to_vec()), DataType::BulkString(name.as_bytes().to_vec())),
+                        (DataType::BulkString(b"engine".to_vec()), DataType::BulkString(b"LUA".to_vec())),
+                        (DataType::BulkString(b"functions".to_vec()), DataType::Array(library.functions.iter().map(function_info_reply).collect())),
+                    ];
+                    if with_code {
+                        fields.push((DataType::BulkString(b"library_code".to_vec()), DataType::BulkString(library.code.clone())));
+                    }
+                    DataType::Map(fields)
+                })
+                .collect(),
+        )
+    }
+
+    // Serialize every library's code as RDB function records, followed by the RDB version and
+    // a checksum as in Redis, so FUNCTION RESTORE can load them elsewhere
+    pub fn function_dump(&self) -> DataType {
+        let mut payload = Vec::new();
+        for library in self.libraries.values() {
+            payload.push(RDB_OPCODE_FUNCTION2);
+            encode_length(&mut payload, library.code.len());
+            payload.extend_from_slice(&library.code);
+        }
+        payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+        let crc = crc64(0, &payload);
+        payload.extend_from_slice(&crc.to_le_bytes());
+        DataType::BulkString(payload)
+    }
+
+    pub fn function_restore(&mut self, payload: &[u8], policy: RestorePolicy) -> CommandResult {
+        let bad_payload = || DataType::SimpleError("ERR payload version or checksum are wrong".to_string());
+        let Some(body_len) = payload.len().checked_sub(10) else {
+            return Err(bad_payload());
+        };
+        let version = u16::from_le_bytes([payload[body_len], payload[body_len + 1]]);
+        let crc = u64::from_le_bytes(payload[body_len + 2..].try_into().unwrap());
+        if version > RDB_VERSION || crc != crc64(0, &payload[..body_len + 2]) {
+            return Err(bad_payload());
+        }
+        let mut body = Reader::new(&payload[..body_len]);
+        let mut libraries = Vec::new();
+        while !body.is_empty() {
+            if body.byte().ok() != Some(RDB_OPCODE_FUNCTION2) {
+                return Err(DataType::SimpleError("ERR given type is not a function".to_string()));
+            }
+            let code = body.string().map_err(|_| DataType::SimpleError("ERR function restore failed".to_string()))?;
+            libraries.push(self.compile_library(&code)?);
+        }
+        if policy == RestorePolicy::Flush {
+            self.libraries.clear();
+        }
+        self.add_libraries(libraries, policy == RestorePolicy::Replace)?;
+        Ok(DataType::ok())
+    }
+
+    // Run a library's code to register its functions, then call the named one with the keys
+    // and arguments
+    pub fn fcall(&mut self, name: &str, keys: Vec<Vec<u8>>, argv: Vec<Vec<u8>>, read_only: bool) -> CommandResult {
+        let Some(library) = self.libraries.values().find(|library| library.function(name).is_some()) else {
+            return Err(DataType::SimpleError("ERR Function not found".to_string()));
+        };
+        let no_writes = library.function(name).is_some_and(|function| function.flags.iter().any(|flag| flag == "no-writes"));
+        if read_only && !no_writes {
+            return Err(DataType::SimpleError("ERR Can not execute a script with write flag using *_ro command.".to_string()));
+        }
+        let chunk = library.chunk.clone();
+        let time_limit = self.config.server.busy_reply_threshold;
+        let mut host = ScriptHost::new(self, no_writes);
+        host.declare_keys(&keys);
+        let mut interp = Interpreter::new(&mut host, CHUNK_NAME);
+        limit_time(&mut interp, time_limit);
+        let redis = library_api();
+        interp.set_global("redis", Value::Table(redis.clone()));
+        interp.protect_globals();
+        let result = register_functions(&mut interp, chunk).and_then(|functions| {
+            // Registering is only allowed while the library loads
+            redis.borrow_mut().set_str("register_function", Value::Nil);
+            let callback = functions.into_iter().find(|(info, _)| info.name == name).map_or(Value::Nil, |(_, callback)| callback);
+            interp.call_function(&callback, vec![string_array(keys), string_array(argv)])
+        });
+        let result = match result {
+            Ok(values) => Ok(lua_to_reply(values.first().unwrap_or(&Value::Nil))),
+            Err(err) => Err(script_error(&err, name, CHUNK_NAME, interp.line())),
+        };
+        drop(interp);
+        host.propagate_effects();
+        result
+    }
+}
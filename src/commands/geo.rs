@@ -0,0 +1,137 @@
+use crate::{
+    command::{syntax_error, wrong_number_of_args, Command},
+    commands::zset::{not_a_float, parse_score, ZaddOptions},
+    geohash,
+    resp::DataType,
+    state::{CommandResult, State},
+};
+
+// Meters per unit accepted by GEODIST and the radius searches
+pub fn parse_unit(arg: &[u8]) -> Option<f64> {
+    match arg.to_ascii_lowercase().as_slice() {
+        b"m" => Some(1.0),
+        b"km" => Some(1000.0),
+        b"ft" => Some(0.3048),
+        b"mi" => Some(1609.34),
+        _ => None,
+    }
+}
+
+fn unsupported_unit() -> Command {
+    Command::INVALID("ERR unsupported unit provided. please use M, KM, FT, MI".to_string())
+}
+
+// Coordinates are reported with 17 significant digits, dropping any trailing zeros
+fn format_coordinate(value: f64) -> Vec<u8> {
+    let integer_digits = if value.abs() >= 1.0 { value.abs().log10() as usize + 1 } else { 1 };
+    let formatted = format!("{:.*}", 17usize.saturating_sub(integer_digits), value);
+    let trimmed = if formatted.contains('.') { formatted.trim_end_matches('0').trim_end_matches('.') } else { &formatted };
+    trimmed.as_bytes().to_vec()
+}
+
+pub fn format_distance(meters: f64, unit: f64) -> Vec<u8> {
+    format!("{:.4}", meters / unit).into_bytes()
+}
+
+impl Command {
+    // GEOADD is ZADD with each longitude,latitude pair turned into a geohash score
+    pub fn parse_geoadd(args: &[Vec<u8>]) -> Command {
+        let (mut nx, mut xx, mut ch) = (false, false, false);
+        let mut i = 2;
+        while let Some(arg) = args.get(i) {
+            match arg.to_ascii_lowercase().as_slice() {
+                b"nx" => nx = true,
+                b"xx" => xx = true,
+                b"ch" => ch = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        if args.len() < 5 || args.len() == i || !args[i..].chunks_exact(3).remainder().is_empty() {
+            return Command::INVALID("ERR syntax error. Try GEOADD key [x1] [y1] [name1] [x2] [y2] [name2] ... ".to_string());
+        }
+        if nx && xx {
+            return Command::INVALID("ERR XX and NX options at the same time are not compatible".to_string());
+        }
+        let mut pairs = Vec::with_capacity((args.len() - i) / 3);
+        for triple in args[i..].chunks_exact(3) {
+            let (Some(longitude), Some(latitude)) = (parse_score(&triple[0]), parse_score(&triple[1])) else {
+                return not_a_float();
+            };
+            if !geohash::valid_coordinates(longitude, latitude) {
+                return Command::INVALID(format!("ERR invalid longitude,latitude pair {:.6},{:.6}", longitude, latitude));
+            }
+            pairs.push((geohash::encode(longitude, latitude) as f64, triple[2].clone()));
+        }
+        Command::ZADD(args[1].clone(), ZaddOptions::geoadd(nx, xx, ch), pairs)
+    }
+
+    pub fn parse_geopos(args: &[Vec<u8>]) -> Command {
+        if args.len() < 2 {
+            return wrong_number_of_args("geopos");
+        }
+        Command::GEOPOS(args[1].clone(), args[2..].to_vec())
+    }
+
+    pub fn parse_geodist(args: &[Vec<u8>]) -> Command {
+        let unit = match args.len() {
+            4 => 1.0,
+            5 => match parse_unit(&args[4]) {
+                Some(unit) => unit,
+                None => return unsupported_unit(),
+            },
+            6.. => return syntax_error(),
+            _ => return wrong_number_of_args("geodist"),
+        };
+        Command::GEODIST(args[1].clone(), args[2].clone(), args[3].clone(), unit)
+    }
+
+    pub fn parse_geohash(args: &[Vec<u8>]) -> Command {
+        if args.len() < 2 {
+            return wrong_number_of_args("geohash");
+        }
+        Command::GEOHASH(args[1].clone(), args[2..].to_vec())
+    }
+}
+
+impl State {
+    // Decoded (longitude, latitude) of a member, the center of the cell its score names
+    fn geo_position(&mut self, key: &[u8], member: &[u8]) -> Result<Option<(f64, f64)>, DataType> {
+        let score = self.get_sorted_set(key)?.and_then(|zset| zset.score(member));
+        Ok(score.map(|score| geohash::decode(score as u64)))
+    }
+
+    pub fn geopos(&mut self, key: &[u8], members: &[Vec<u8>]) -> CommandResult {
+        let mut positions = Vec::with_capacity(members.len());
+        for member in members {
+            positions.push(match self.geo_position(key, member)? {
+                Some((longitude, latitude)) => DataType::Array(vec![
+                    DataType::BulkString(format_coordinate(longitude)),
+                    DataType::BulkString(format_coordinate(latitude)),
+                ]),
+                None => DataType::NullArray,
+            });
+        }
+        Ok(DataType::Array(positions))
+    }
+
+    pub fn geodist(&mut self, key: &[u8], member1: &[u8], member2: &[u8], unit: f64) -> CommandResult {
+        let from = self.geo_position(key, member1)?;
+        let to = self.geo_position(key, member2)?;
+        Ok(match from.zip(to) {
+            Some(((lon1, lat1), (lon2, lat2))) => DataType::BulkString(format_distance(geohash::distance(lon1, lat1, lon2, lat2), unit)),
+            None => DataType::NullBulkString,
+        })
+    }
+
+    pub fn geohash(&mut self, key: &[u8], members: &[Vec<u8>]) -> CommandResult {
+        let mut hashes = Vec::with_capacity(members.len());
+        for member in members {
+            hashes.push(match self.geo_position(key, member)? {
+                Some((longitude, latitude)) => DataType::BulkString(geohash::to_base32(longitude, latitude)),
+                None => DataType::NullBulkString,
+            });
+        }
+        Ok(DataType::Array(hashes))
+    }
+}
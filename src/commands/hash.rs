@@ -0,0 +1,336 @@
+use tokio::time::Duration;
+
+use crate::{
+    clock::now_ms,
+    command::{not_an_integer, parse_integer_arg, parse_random_count, syntax_error, wrong_number_of_args, Command},
+    commands::{parse_cursor, scan_items, scan_reply, ExpireCondition, ScanOptions},
+    notify::NOTIFY_HASH,
+    random::{random_index, sample_distinct, sample_with_repeats, REPLY_TOO_LARGE},
+    resp::DataType,
+    state::{CommandResult, State},
+};
+
+impl Command {
+    pub fn parse_hset(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() < 4 || !args[2..].chunks_exact(2).remainder().is_empty() {
+            return wrong_number_of_args(name);
+        }
+        let key = args[1].clone();
+        let pairs = args[2..].chunks_exact(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect();
+        match name {
+            "hset" => Command::HSET(key, pairs),
+            _ => Command::HMSET(key, pairs),
+        }
+    }
+
+    pub fn parse_hash_field(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() != 3 {
+            return wrong_number_of_args(name);
+        }
+        let (key, field) = (args[1].clone(), args[2].clone());
+        match name {
+            "hget" => Command::HGET(key, field),
+            _ => Command::HEXISTS(key, field),
+        }
+    }
+
+    pub fn parse_hash_fields(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 {
+            return wrong_number_of_args(name);
+        }
+        let (key, fields) = (args[1].clone(), args[2..].to_vec());
+        match name {
+            "hmget" => Command::HMGET(key, fields),
+            _ => Command::HDEL(key, fields),
+        }
+    }
+
+    pub fn parse_hash_key(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() != 2 {
+            return wrong_number_of_args(name);
+        }
+        let key = args[1].clone();
+        match name {
+            "hgetall" => Command::HGETALL(key),
+            "hlen" => Command::HLEN(key),
+            "hkeys" => Command::HKEYS(key),
+            _ => Command::HVALS(key),
+        }
+    }
+
+    pub fn parse_hscan(args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 {
+            return wrong_number_of_args("hscan");
+        }
+        let cursor = match parse_cursor(&args[2]) {
+            Ok(cursor) => cursor,
+            Err(err) => return err,
+        };
+        match ScanOptions::parse(&args[3..], true) {
+            Ok(options) => Command::HSCAN(args[1].clone(), cursor, options),
+            Err(err) => err,
+        }
+    }
+
+    pub fn parse_hrandfield(args: &[Vec<u8>]) -> Command {
+        if args.len() < 2 || args.len() > 4 {
+            return wrong_number_of_args("hrandfield");
+        }
+        let count = match args.get(2).map(|count| parse_random_count(count)).transpose() {
+            Ok(count) => count,
+            Err(err) => return err,
+        };
+        let with_values = match args.get(3) {
+            Some(arg) if arg.eq_ignore_ascii_case(b"withvalues") => true,
+            Some(_) => return syntax_error(),
+            None => false,
+        };
+        Command::HRANDFIELD(args[1].clone(), count, with_values)
+    }
+
+    // HEXPIRE/HPEXPIRE/HEXPIREAT/HPEXPIREAT key time [NX|XX|GT|LT] FIELDS numfields field
+    // [field ...]. Unix times are turned into the time left until then, zero once passed.
+    pub fn parse_hexpire(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() < 6 {
+            return wrong_number_of_args(name);
+        }
+        let millis = match parse_integer_arg::<i64>(&args[2]) {
+            Some(time) if name == "hexpire" || name == "hexpireat" => time.checked_mul(1000),
+            Some(time) => Some(time),
+            None => return not_an_integer(),
+        };
+        let millis = match millis {
+            Some(at) if name.ends_with("at") && at >= 0 => Some(at.saturating_sub(now_ms() as i64).max(0)),
+            millis => millis,
+        };
+        let millis = match millis {
+            Some(millis) if (0..=MAX_FIELD_TTL_MS).contains(&millis) => millis as u64,
+            _ => return Command::INVALID(format!("ERR invalid expire time in '{}' command", name)),
+        };
+        let (condition, fields_at) = match ExpireCondition::parse(&args[3]) {
+            Some(condition) => (condition, 4),
+            None => (ExpireCondition::Always, 3),
+        };
+        let fields = match parse_fields_block(args, fields_at) {
+            Ok(fields) => fields,
+            Err(err) => return err,
+        };
+        Command::HEXPIRE(args[1].clone(), Duration::from_millis(millis), condition, fields)
+    }
+
+    // HTTL/HPTTL/HPERSIST key FIELDS numfields field [field ...]
+    pub fn parse_hash_field_ttl(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() < 5 {
+            return wrong_number_of_args(name);
+        }
+        let fields = match parse_fields_block(args, 2) {
+            Ok(fields) => fields,
+            Err(err) => return err,
+        };
+        let key = args[1].clone();
+        match name {
+            "httl" => Command::HTTL(key, fields, false),
+            "hpttl" => Command::HTTL(key, fields, true),
+            _ => Command::HPERSIST(key, fields),
+        }
+    }
+}
+
+// Largest relative field TTL accepted, mirroring the Redis EB_EXPIRE_TIME_MAX limit
+const MAX_FIELD_TTL_MS: i64 = (1 << 48) - 1;
+
+fn parse_fields_block(args: &[Vec<u8>], start: usize) -> Result<Vec<Vec<u8>>, Command> {
+    if !args[start].eq_ignore_ascii_case(b"fields") {
+        return Err(Command::INVALID("ERR Mandatory argument FIELDS is missing or not at the right position".to_string()));
+    }
+    let numfields = match args.get(start + 1).and_then(|arg| parse_integer_arg::<i64>(arg)) {
+        Some(numfields) if numfields > 0 => numfields as usize,
+        Some(_) => return Err(Command::INVALID("ERR Parameter `numFields` should be greater than 0".to_string())),
+        None => return Err(not_an_integer()),
+    };
+    if args.len() - (start + 2) != numfields {
+        return Err(Command::INVALID("ERR The `numfields` parameter must match the number of arguments".to_string()));
+    }
+    Ok(args[start + 2..].to_vec())
+}
+
+impl State {
+    pub fn hset(&mut self, key: &[u8], pairs: Vec<(Vec<u8>, Vec<u8>)>) -> CommandResult {
+        let limits = self.config.limits;
+        let hash = self.get_or_create_hash(key)?;
+        let added = pairs.into_iter().filter(|(field, value)| hash.insert(field.clone(), value.clone(), &limits).is_none()).count();
+        self.notify_keyspace_event(NOTIFY_HASH, "hset", key);
+        Ok(DataType::Integer(added as i64))
+    }
+
+    pub fn hget(&mut self, key: &[u8], field: &[u8]) -> CommandResult {
+        let value = self.get_hash(key)?.and_then(|hash| hash.get(field).map(|value| value.to_vec()));
+        Ok(value.map_or(DataType::NullBulkString, DataType::BulkString))
+    }
+
+    pub fn hmget(&mut self, key: &[u8], fields: &[Vec<u8>]) -> CommandResult {
+        let hash = self.get_hash(key)?;
+        let values = fields.iter().map(|field| {
+            match hash.as_ref().and_then(|hash| hash.get(field)) {
+                Some(value) => DataType::BulkString(value.to_vec()),
+                None => DataType::NullBulkString,
+            }
+        });
+        Ok(DataType::Array(values.collect()))
+    }
+
+    pub fn hdel(&mut self, key: &[u8], fields: &[Vec<u8>]) -> CommandResult {
+        let hash = match self.get_hash(key)? {
+            Some(hash) => hash,
+            None => return Ok(DataType::Integer(0)),
+        };
+        let removed = fields.iter().filter(|field| hash.remove(field).is_some()).count();
+        if removed > 0 {
+            self.notify_keyspace_event(NOTIFY_HASH, "hdel", key);
+        }
+        self.remove_if_empty(key);
+        Ok(DataType::Integer(removed as i64))
+    }
+
+    pub fn hgetall(&mut self, key: &[u8]) -> CommandResult {
+        let hash = self.get_hash(key)?;
+        let items = hash.into_iter().flat_map(|hash| hash.iter()).flat_map(|(field, value)| [field.to_vec(), value.to_vec()]);
+        Ok(DataType::bulk_array(items))
+    }
+
+    pub fn hexists(&mut self, key: &[u8], field: &[u8]) -> CommandResult {
+        let exists = self.get_hash(key)?.is_some_and(|hash| hash.contains_key(field));
+        Ok(DataType::Integer(exists as i64))
+    }
+
+    pub fn hlen(&mut self, key: &[u8]) -> CommandResult {
+        let len = self.get_hash(key)?.map_or(0, |hash| hash.len());
+        Ok(DataType::Integer(len as i64))
+    }
+
+    pub fn hkeys(&mut self, key: &[u8]) -> CommandResult {
+        let hash = self.get_hash(key)?;
+        Ok(DataType::bulk_array(hash.into_iter().flat_map(|hash| hash.keys().map(|field| field.to_vec()))))
+    }
+
+    pub fn hvals(&mut self, key: &[u8]) -> CommandResult {
+        let hash = self.get_hash(key)?;
+        Ok(DataType::bulk_array(hash.into_iter().flat_map(|hash| hash.values().map(|value| value.to_vec()))))
+    }
+
+    pub fn hscan(&mut self, key: &[u8], cursor: u64, options: &ScanOptions) -> CommandResult {
+        let hash = match self.get_hash(key)? {
+            Some(hash) => hash,
+            None => return Ok(scan_reply(0, vec![])),
+        };
+        let (cursor, entries) = scan_items(hash.iter().map(|(field, value)| (field, (field, value))), cursor, options);
+        let mut items = Vec::with_capacity(entries.len() * 2);
+        for (field, value) in entries {
+            items.push(DataType::BulkString(field.to_vec()));
+            if !options.novalues {
+                items.push(DataType::BulkString(value.to_vec()));
+            }
+        }
+        Ok(scan_reply(cursor, items))
+    }
+
+    // Random fields: a positive count returns distinct fields, a negative one allows repeats
+    pub fn hrandfield(&mut self, key: &[u8], count: Option<i64>, with_values: bool) -> CommandResult {
+        let hash = match self.get_hash(key)? {
+            Some(hash) => hash,
+            None if count.is_some() => return Ok(DataType::Array(vec![])),
+            None => return Ok(DataType::NullBulkString),
+        };
+        let entries: Vec<(&[u8], &[u8])> = hash.iter().collect();
+        let count = match count {
+            Some(count) => count,
+            None => return Ok(DataType::BulkString(entries[random_index(entries.len())].0.to_vec())),
+        };
+        let picked = if count >= 0 {
+            sample_distinct(entries, count as usize)
+        } else {
+            match sample_with_repeats(&entries, count.unsigned_abs() as usize) {
+                Some(picked) => picked,
+                None => return Err(DataType::SimpleError(REPLY_TOO_LARGE.to_string())),
+            }
+        };
+        let mut items = Vec::with_capacity(picked.len() * 2);
+        for (field, value) in picked {
+            items.push(DataType::BulkString(field.to_vec()));
+            if with_values {
+                items.push(DataType::BulkString(value.to_vec()));
+            }
+        }
+        Ok(DataType::Array(items))
+    }
+
+    // Per field reply: -2 no such field, 0 condition not met, 1 expiry set, 2 field deleted
+    // because the deadline is already in the past
+    pub fn hexpire(&mut self, key: &[u8], ttl: Duration, condition: ExpireCondition, fields: &[Vec<u8>]) -> CommandResult {
+        let hash = match self.get_hash(key)? {
+            Some(hash) => hash,
+            None => return Ok(DataType::Array(fields.iter().map(|_| DataType::Integer(-2)).collect())),
+        };
+        let expiry = now_ms() + ttl.as_millis() as u64;
+        let mut replies = Vec::with_capacity(fields.len());
+        for field in fields {
+            if !hash.contains_key(field) {
+                replies.push(DataType::Integer(-2));
+            } else if !condition.allows(hash.expiry(field), expiry) {
+                replies.push(DataType::Integer(0));
+            } else if ttl.is_zero() {
+                hash.remove(field);
+                replies.push(DataType::Integer(2));
+            } else {
+                hash.set_expiry(field, expiry);
+                replies.push(DataType::Integer(1));
+            }
+        }
+        if hash.has_expiring_fields() {
+            self.hashes_with_field_ttl.insert(key.to_vec());
+        }
+        if replies.contains(&DataType::Integer(1)) {
+            self.notify_keyspace_event(NOTIFY_HASH, "hexpire", key);
+        }
+        if replies.contains(&DataType::Integer(2)) {
+            self.notify_keyspace_event(NOTIFY_HASH, "hdel", key);
+        }
+        self.remove_if_empty(key);
+        Ok(DataType::Array(replies))
+    }
+
+    // Per field reply: -2 no such field, -1 no expiry, otherwise the remaining time to live
+    pub fn httl(&mut self, key: &[u8], fields: &[Vec<u8>], millis: bool) -> CommandResult {
+        let hash = self.get_hash(key)?;
+        let replies = fields.iter().map(|field| {
+            let ttl = match hash.as_ref() {
+                Some(hash) if hash.contains_key(field) => match hash.expiry(field) {
+                    Some(expiry) => {
+                        let remaining = expiry.saturating_sub(now_ms()) as i64;
+                        if millis { remaining } else { (remaining + 500) / 1000 }
+                    }
+                    None => -1,
+                },
+                _ => -2,
+            };
+            DataType::Integer(ttl)
+        });
+        Ok(DataType::Array(replies.collect()))
+    }
+
+    // Per field reply: -2 no such field, -1 no expiry, 1 expiry removed
+    pub fn hpersist(&mut self, key: &[u8], fields: &[Vec<u8>]) -> CommandResult {
+        let mut hash = self.get_hash(key)?;
+        let replies = fields.iter().map(|field| {
+            let result = match hash.as_mut() {
+                Some(hash) if hash.contains_key(field) => if hash.persist(field) { 1 } else { -1 },
+                _ => -2,
+            };
+            DataType::Integer(result)
+        }).collect::<Vec<_>>();
+        if replies.contains(&DataType::Integer(1)) {
+            self.notify_keyspace_event(NOTIFY_HASH, "hpersist", key);
+        }
+        Ok(DataType::Array(replies))
+    }
+}
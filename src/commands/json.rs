@@ -0,0 +1,211 @@
+use crate::{
+    command::{syntax_error, wrong_number_of_args, Command},
+    notify::NOTIFY_MODULE,
+    resp::DataType,
+    state::{CommandResult, DataStoreValue, State, Value},
+    types::{
+        json::{Json, JsonFormat, PathStep},
+        jsonpath::JsonPath,
+    },
+};
+
+fn path_does_not_exist(path: &JsonPath) -> DataType {
+    DataType::SimpleError(format!("ERR Path '{}' does not exist", path.text()))
+}
+
+fn parse_path(arg: &[u8]) -> Result<JsonPath, Command> {
+    JsonPath::parse(arg).map_err(Command::INVALID)
+}
+
+// Serialized reply for one path: the first match for legacy paths, every match otherwise
+fn path_result(document: &Json, path: &JsonPath) -> Result<Json, DataType> {
+    let matches = path.select(document);
+    if !path.is_legacy() {
+        return Ok(Json::Array(matches.into_iter().map(|(_, value)| value.clone()).collect()));
+    }
+    match matches.first() {
+        Some((_, value)) => Ok((*value).clone()),
+        None => Err(path_does_not_exist(path)),
+    }
+}
+
+impl Command {
+    // JSON.SET key path value [NX | XX]
+    pub fn parse_json_set(args: &[Vec<u8>]) -> Command {
+        let (nx, xx) = match args.len() {
+            4 => (false, false),
+            5 if args[4].eq_ignore_ascii_case(b"nx") => (true, false),
+            5 if args[4].eq_ignore_ascii_case(b"xx") => (false, true),
+            5 => return syntax_error(),
+            _ => return wrong_number_of_args("json.set"),
+        };
+        let path = match parse_path(&args[2]) {
+            Ok(path) => path,
+            Err(err) => return err,
+        };
+        let value = match Json::parse(&args[3]) {
+            Ok(value) => value,
+            Err(msg) => return Command::INVALID(format!("ERR {}", msg)),
+        };
+        Command::JSONSET(args[1].clone(), Box::new(path), value, nx, xx)
+    }
+
+    // JSON.GET key [INDENT indent] [NEWLINE newline] [SPACE space] [path ...]
+    pub fn parse_json_get(args: &[Vec<u8>]) -> Command {
+        if args.len() < 2 {
+            return wrong_number_of_args("json.get");
+        }
+        let mut format = JsonFormat::default();
+        let mut i = 2;
+        while let Some(arg) = args.get(i) {
+            let option = match arg.to_ascii_lowercase().as_slice() {
+                b"indent" => &mut format.indent,
+                b"newline" => &mut format.newline,
+                b"space" => &mut format.space,
+                _ => break,
+            };
+            let Some(value) = args.get(i + 1) else {
+                return syntax_error();
+            };
+            *option = String::from_utf8_lossy(value).into_owned();
+            i += 2;
+        }
+        let paths: Result<Vec<JsonPath>, Command> = args[i..].iter().map(|arg| parse_path(arg)).collect();
+        match paths {
+            Ok(paths) => Command::JSONGET(args[1].clone(), format, paths),
+            Err(err) => err,
+        }
+    }
+
+    // JSON.DEL, JSON.FORGET and JSON.TYPE take a key and an optional path
+    pub fn parse_json_key_path(name: &str, args: &[Vec<u8>]) -> Command {
+        let path = match args.len() {
+            2 if name == "json.type" => JsonPath::legacy_root(),
+            2 => JsonPath::root(),
+            3 => match parse_path(&args[2]) {
+                Ok(path) => path,
+                Err(err) => return err,
+            },
+            _ => return wrong_number_of_args(name),
+        };
+        let key = args[1].clone();
+        match name {
+            "json.type" => Command::JSONTYPE(key, path),
+            _ => Command::JSONDEL(key, path),
+        }
+    }
+}
+
+impl State {
+    pub fn json_set(&mut self, key: &[u8], path: &JsonPath, value: Json, nx: bool, xx: bool) -> CommandResult {
+        let Some(document) = self.get_json(key)? else {
+            if !path.is_root() {
+                return Err(DataType::SimpleError("ERR new objects must be created at the root".to_string()));
+            }
+            if xx {
+                return Ok(DataType::NullBulkString);
+            }
+            self.datastore.insert(key.to_vec(), DataStoreValue::new(Value::Json(value), None));
+            self.notify_keyspace_event(NOTIFY_MODULE, "json.set", key);
+            return Ok(DataType::ok());
+        };
+        let matches: Vec<Vec<PathStep>> = path.select(document).into_iter().map(|(location, _)| location).collect();
+        if !matches.is_empty() {
+            if nx {
+                return Ok(DataType::NullBulkString);
+            }
+            for location in matches {
+                if let Some(target) = document.get_path_mut(&location) {
+                    *target = value.clone();
+                }
+            }
+            self.notify_keyspace_event(NOTIFY_MODULE, "json.set", key);
+            return Ok(DataType::ok());
+        }
+        // Nothing matched, but a new member can still be added to each matching parent object
+        let Some((parent, name)) = path.split_last_key().filter(|_| !xx) else {
+            return Ok(DataType::NullBulkString);
+        };
+        let parents: Vec<Vec<PathStep>> = parent.select(document).into_iter()
+            .filter(|(_, value)| matches!(value, Json::Object(_)))
+            .map(|(location, _)| location)
+            .collect();
+        if parents.is_empty() {
+            return Ok(DataType::NullBulkString);
+        }
+        for location in parents {
+            if let Some(target) = document.get_path_mut(&location) {
+                target.insert(name, value.clone());
+            }
+        }
+        self.notify_keyspace_event(NOTIFY_MODULE, "json.set", key);
+        Ok(DataType::ok())
+    }
+
+    pub fn json_get(&mut self, key: &[u8], format: &JsonFormat, paths: &[JsonPath]) -> CommandResult {
+        let Some(document) = self.get_json(key)? else {
+            return Ok(DataType::NullBulkString);
+        };
+        let result = match paths {
+            [] => document.clone(),
+            [path] => path_result(document, path)?,
+            _ => {
+                // With several paths, reply with an object keyed by path. Any JSONPath among
+                // them turns every entry into an array of matches.
+                let legacy = paths.iter().all(JsonPath::is_legacy);
+                let mut members = Vec::with_capacity(paths.len());
+                for path in paths {
+                    let value = if legacy {
+                        path_result(document, path)?
+                    } else {
+                        Json::Array(path.select(document).into_iter().map(|(_, value)| value.clone()).collect())
+                    };
+                    members.retain(|(name, _): &(String, Json)| name != path.text());
+                    members.push((path.text().to_string(), value));
+                }
+                Json::Object(members)
+            }
+        };
+        Ok(DataType::BulkString(result.serialize(format).into_bytes()))
+    }
+
+    pub fn json_del(&mut self, key: &[u8], path: &JsonPath) -> CommandResult {
+        let Some(document) = self.get_json(key)? else {
+            return Ok(DataType::Integer(0));
+        };
+        if path.is_root() {
+            self.datastore.remove(key);
+            self.notify_keyspace_event(NOTIFY_MODULE, "json.del", key);
+            return Ok(DataType::Integer(1));
+        }
+        let mut locations: Vec<Vec<PathStep>> = path.select(document).into_iter().map(|(location, _)| location).collect();
+        // Delete from the back so earlier array indexes stay valid, skipping values that sit
+        // inside something else being deleted
+        locations.sort();
+        locations.dedup();
+        let mut deleted = 0;
+        for (i, location) in locations.iter().enumerate().rev() {
+            if locations[..i].iter().any(|other| location.starts_with(other)) {
+                continue;
+            }
+            if document.remove_path(location) {
+                deleted += 1;
+            }
+        }
+        if deleted > 0 {
+            self.notify_keyspace_event(NOTIFY_MODULE, "json.del", key);
+        }
+        Ok(DataType::Integer(deleted))
+    }
+
+    pub fn json_type(&mut self, key: &[u8], path: &JsonPath) -> CommandResult {
+        let Some(document) = self.get_json(key)? else {
+            return Ok(DataType::NullBulkString);
+        };
+        let mut types = path.select(document).into_iter().map(|(_, value)| DataType::BulkString(value.type_name().as_bytes().to_vec()));
+        if path.is_legacy() {
+            return Ok(types.next().unwrap_or(DataType::NullBulkString));
+        }
+        Ok(DataType::Array(types.collect()))
+    }
+}
@@ -0,0 +1,150 @@
+use crate::{
+    clock::now_ms,
+    command::{not_an_integer, parse_integer_arg, syntax_error, wrong_number_of_args, Command},
+    notify::NOTIFY_GENERIC,
+    rdb,
+    resp::DataType,
+    state::{CommandResult, DataStoreValue, State, Value},
+};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RestoreOptions {
+    // Overwrite the key if it exists
+    pub replace: bool,
+    // The TTL is a unix time in milliseconds rather than a number of milliseconds from now
+    pub absttl: bool,
+}
+
+impl Command {
+    // UNLINK is DEL, as values are always freed straight away
+    pub fn parse_del(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() < 2 {
+            return wrong_number_of_args(name);
+        }
+        Command::DEL(args[1..].to_vec())
+    }
+
+    pub fn parse_touch(args: &[Vec<u8>]) -> Command {
+        if args.len() < 2 {
+            return wrong_number_of_args("touch");
+        }
+        Command::TOUCH(args[1..].to_vec())
+    }
+
+    pub fn parse_object(args: &[Vec<u8>]) -> Command {
+        if args.len() < 2 {
+            return wrong_number_of_args("object");
+        }
+        let subcommand = String::from_utf8_lossy(&args[1]).to_lowercase();
+        match (subcommand.as_str(), args.len()) {
+            ("idletime", 3) => Command::OBJECTIDLETIME(args[2].clone()),
+            ("freq", 3) => Command::OBJECTFREQ(args[2].clone()),
+            ("encoding", 3) => Command::OBJECTENCODING(args[2].clone()),
+            ("idletime", _) | ("freq", _) | ("encoding", _) => wrong_number_of_args(&format!("object|{}", subcommand)),
+            _ => Command::INVALID(format!("ERR unknown subcommand '{}'. Try OBJECT HELP.", subcommand)),
+        }
+    }
+
+    pub fn parse_dump(args: &[Vec<u8>]) -> Command {
+        if args.len() != 2 {
+            return wrong_number_of_args("dump");
+        }
+        Command::DUMP(args[1].clone())
+    }
+
+    // RESTORE key ttl serialized-value [REPLACE] [ABSTTL], where a ttl of 0 means no expiry
+    pub fn parse_restore(args: &[Vec<u8>]) -> Command {
+        if args.len() < 4 {
+            return wrong_number_of_args("restore");
+        }
+        let ttl = match parse_integer_arg::<i64>(&args[2]) {
+            Some(ttl) if ttl >= 0 => ttl as u64,
+            Some(_) => return Command::INVALID("ERR Invalid TTL value, must be >= 0".to_string()),
+            None => return not_an_integer(),
+        };
+        let mut options = RestoreOptions::default();
+        for arg in &args[4..] {
+            match arg.to_ascii_lowercase().as_slice() {
+                b"replace" => options.replace = true,
+                b"absttl" => options.absttl = true,
+                _ => return syntax_error(),
+            }
+        }
+        Command::RESTORE(args[1].clone(), ttl, args[3].clone(), options)
+    }
+}
+
+impl State {
+    // Keys that have expired count as missing, except to a replica applying its master's
+    // deletion of them
+    pub fn del(&mut self, keys: &[Vec<u8>]) -> CommandResult {
+        let mut deleted = 0;
+        for key in keys {
+            if self.peek_value(key).is_some() {
+                self.datastore.remove(key);
+                self.notify_keyspace_event(NOTIFY_GENERIC, "del", key);
+                deleted += 1;
+            }
+        }
+        Ok(DataType::Integer(deleted))
+    }
+
+    pub fn touch(&mut self, keys: &[Vec<u8>]) -> CommandResult {
+        let touched = keys.iter().filter(|key| self.get_value(key).is_some()).count();
+        Ok(DataType::Integer(touched as i64))
+    }
+
+    pub fn object_idletime(&mut self, key: &[u8]) -> CommandResult {
+        Ok(match self.peek_value(key) {
+            Some(dsv) => DataType::Integer(dsv.idle_time().as_secs() as i64),
+            None => DataType::NullBulkString,
+        })
+    }
+
+    pub fn object_freq(&mut self, key: &[u8]) -> CommandResult {
+        Ok(match self.peek_value(key) {
+            Some(dsv) => DataType::Integer(dsv.frequency() as i64),
+            None => DataType::NullBulkString,
+        })
+    }
+
+    pub fn dump(&mut self, key: &[u8]) -> CommandResult {
+        Ok(match self.get_value(key) {
+            Some(dsv) => DataType::BulkString(rdb::dump_value(&dsv.value)),
+            None => DataType::NullBulkString,
+        })
+    }
+
+    // A TTL that has already run out restores nothing, though REPLACE still removes the key
+    pub fn restore(&mut self, key: &[u8], ttl: u64, payload: &[u8], options: RestoreOptions) -> CommandResult {
+        if !options.replace && self.peek_value(key).is_some() {
+            return Err(DataType::SimpleError("BUSYKEY Target key name already exists.".to_string()));
+        }
+        let value = rdb::restore_value(payload, &self.config.limits).map_err(|e| DataType::SimpleError(e.to_string()))?;
+        let expiry = match (ttl, options.absttl) {
+            (0, _) => None,
+            (at, true) => Some(at),
+            (ttl, false) => Some(now_ms() + ttl),
+        };
+        if expiry.is_some_and(|at| at < now_ms()) {
+            if self.datastore.remove(key).is_some() {
+                self.notify_keyspace_event(NOTIFY_GENERIC, "del", key);
+            }
+            return Ok(DataType::ok());
+        }
+        if matches!(&value, Value::Hash(hash) if hash.has_expiring_fields()) {
+            self.hashes_with_field_ttl.insert(key.to_vec());
+        }
+        self.datastore.insert(key.to_vec(), DataStoreValue::new(value, expiry));
+        self.blocking.signal_key_ready(key);
+        self.notify_keyspace_event(NOTIFY_GENERIC, "restore", key);
+        Ok(DataType::ok())
+    }
+
+    pub fn object_encoding(&mut self, key: &[u8]) -> CommandResult {
+        Ok(match self.peek_value(key) {
+            Some(dsv) => DataType::BulkString(dsv.value.encoding().as_bytes().to_vec()),
+            None => DataType::NullBulkString,
+        })
+    }
+}
@@ -0,0 +1,463 @@
+use crate::{
+    command::{not_an_integer, parse_integer_arg, parse_numkeys, parse_timeout_arg, syntax_error, wrong_number_of_args, Command},
+    commands::normalize_range,
+    notify::NOTIFY_LIST,
+    resp::DataType,
+    state::{CommandResult, State},
+};
+
+#[derive(Debug, Clone)]
+pub struct LposOptions {
+    rank: i64,
+    count: Option<usize>,
+    maxlen: usize,
+}
+
+impl Command {
+    pub fn parse_push(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 {
+            return wrong_number_of_args(name);
+        }
+        let (key, values) = (args[1].clone(), args[2..].to_vec());
+        match name {
+            "lpush" => Command::LPUSH(key, values),
+            _ => Command::RPUSH(key, values),
+        }
+    }
+
+    pub fn parse_pop(name: &str, args: &[Vec<u8>]) -> Command {
+        let count = match args.len() {
+            2 => None,
+            3 => match parse_integer_arg::<i64>(&args[2]) {
+                Some(count) if count >= 0 => Some(count as usize),
+                Some(_) => return Command::INVALID("ERR value is out of range, must be positive".to_string()),
+                None => return not_an_integer(),
+            },
+            _ => return wrong_number_of_args(name),
+        };
+        let key = args[1].clone();
+        match name {
+            "lpop" => Command::LPOP(key, count),
+            _ => Command::RPOP(key, count),
+        }
+    }
+
+    pub fn parse_bpop(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 {
+            return wrong_number_of_args(name);
+        }
+        let timeout = match parse_timeout_arg(&args[args.len() - 1]) {
+            Ok(timeout) => timeout,
+            Err(err) => return err,
+        };
+        let keys = args[1..args.len() - 1].to_vec();
+        match name {
+            "blpop" => Command::BLPOP(keys, timeout),
+            _ => Command::BRPOP(keys, timeout),
+        }
+    }
+
+    pub fn parse_lmove(name: &str, args: &[Vec<u8>]) -> Command {
+        let (expected, blocking) = match name {
+            "lmove" => (5, false),
+            "blmove" => (6, true),
+            "rpoplpush" => (3, false),
+            _ => (4, true),
+        };
+        if args.len() != expected {
+            return wrong_number_of_args(name);
+        }
+        let (source, destination) = (args[1].clone(), args[2].clone());
+        let (from_left, to_left) = if args.len() >= 5 {
+            match (parse_direction(&args[3]), parse_direction(&args[4])) {
+                (Some(from_left), Some(to_left)) => (from_left, to_left),
+                _ => return syntax_error(),
+            }
+        } else {
+            (false, true)
+        };
+        if !blocking {
+            return Command::LMOVE(source, destination, from_left, to_left);
+        }
+        match parse_timeout_arg(&args[args.len() - 1]) {
+            Ok(timeout) => Command::BLMOVE(vec![source, destination], from_left, to_left, timeout),
+            Err(err) => err,
+        }
+    }
+
+    pub fn parse_lpos(args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 || !args[3..].chunks_exact(2).remainder().is_empty() {
+            return wrong_number_of_args("lpos");
+        }
+        let mut options = LposOptions { rank: 1, count: None, maxlen: 0 };
+        for pair in args[3..].chunks_exact(2) {
+            let value = match parse_integer_arg::<i64>(&pair[1]) {
+                Some(value) => value,
+                None => return not_an_integer(),
+            };
+            match pair[0].to_ascii_lowercase().as_slice() {
+                b"rank" if value == 0 => return Command::INVALID("ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list".to_string()),
+                b"rank" if value == i64::MIN => return Command::INVALID("ERR value is out of range".to_string()),
+                b"rank" => options.rank = value,
+                b"count" if value < 0 => return Command::INVALID("ERR COUNT can't be negative".to_string()),
+                b"count" => options.count = Some(value as usize),
+                b"maxlen" if value < 0 => return Command::INVALID("ERR MAXLEN can't be negative".to_string()),
+                b"maxlen" => options.maxlen = value as usize,
+                _ => return syntax_error(),
+            }
+        }
+        Command::LPOS(args[1].clone(), args[2].clone(), options)
+    }
+
+    pub fn parse_lmpop(name: &str, args: &[Vec<u8>]) -> Command {
+        let blocking = name == "blmpop";
+        let first = if blocking { 2 } else { 1 };
+        if args.len() < first + 3 {
+            return wrong_number_of_args(name);
+        }
+        let (keys, mut i) = match parse_numkeys(args, first) {
+            Ok(parsed) => parsed,
+            Err(err) => return err,
+        };
+        let left = match args.get(i).and_then(|arg| parse_direction(arg)) {
+            Some(left) => left,
+            None => return syntax_error(),
+        };
+        i += 1;
+        let mut count = 1;
+        if i < args.len() {
+            if args.len() != i + 2 || !args[i].eq_ignore_ascii_case(b"count") {
+                return syntax_error();
+            }
+            count = match parse_integer_arg::<i64>(&args[i + 1]) {
+                Some(count) if count > 0 => count as usize,
+                _ => return Command::INVALID("ERR count should be greater than 0".to_string()),
+            };
+        }
+        if !blocking {
+            return Command::LMPOP(keys, left, count);
+        }
+        match parse_timeout_arg(&args[1]) {
+            Ok(timeout) => Command::BLMPOP(keys, left, count, timeout),
+            Err(err) => err,
+        }
+    }
+
+    pub fn parse_llen(args: &[Vec<u8>]) -> Command {
+        if args.len() != 2 {
+            return wrong_number_of_args("llen");
+        }
+        Command::LLEN(args[1].clone())
+    }
+
+    pub fn parse_lrange(args: &[Vec<u8>]) -> Command {
+        if args.len() != 4 {
+            return wrong_number_of_args("lrange");
+        }
+        match (parse_integer_arg::<i64>(&args[2]), parse_integer_arg::<i64>(&args[3])) {
+            (Some(start), Some(stop)) => Command::LRANGE(args[1].clone(), start, stop),
+            _ => not_an_integer(),
+        }
+    }
+
+    pub fn parse_linsert(args: &[Vec<u8>]) -> Command {
+        if args.len() != 5 {
+            return wrong_number_of_args("linsert");
+        }
+        let before = match args[2].to_ascii_lowercase().as_slice() {
+            b"before" => true,
+            b"after" => false,
+            _ => return syntax_error(),
+        };
+        Command::LINSERT(args[1].clone(), before, args[3].clone(), args[4].clone())
+    }
+
+    pub fn parse_lset(args: &[Vec<u8>]) -> Command {
+        if args.len() != 4 {
+            return wrong_number_of_args("lset");
+        }
+        match parse_integer_arg::<i64>(&args[2]) {
+            Some(index) => Command::LSET(args[1].clone(), index, args[3].clone()),
+            None => not_an_integer(),
+        }
+    }
+
+    pub fn parse_lindex(args: &[Vec<u8>]) -> Command {
+        if args.len() != 3 {
+            return wrong_number_of_args("lindex");
+        }
+        match parse_integer_arg::<i64>(&args[2]) {
+            Some(index) => Command::LINDEX(args[1].clone(), index),
+            None => not_an_integer(),
+        }
+    }
+
+    pub fn parse_lrem(args: &[Vec<u8>]) -> Command {
+        if args.len() != 4 {
+            return wrong_number_of_args("lrem");
+        }
+        match parse_integer_arg::<i64>(&args[2]) {
+            Some(count) => Command::LREM(args[1].clone(), count, args[3].clone()),
+            None => not_an_integer(),
+        }
+    }
+
+    pub fn parse_ltrim(args: &[Vec<u8>]) -> Command {
+        if args.len() != 4 {
+            return wrong_number_of_args("ltrim");
+        }
+        match (parse_integer_arg::<i64>(&args[2]), parse_integer_arg::<i64>(&args[3])) {
+            (Some(start), Some(stop)) => Command::LTRIM(args[1].clone(), start, stop),
+            _ => not_an_integer(),
+        }
+    }
+}
+
+// LEFT/RIGHT arguments of the move commands, true for LEFT
+fn parse_direction(arg: &[u8]) -> Option<bool> {
+    match arg.to_ascii_lowercase().as_slice() {
+        b"left" => Some(true),
+        b"right" => Some(false),
+        _ => None,
+    }
+}
+
+// Map a possibly negative list index onto a position within a list of the given length
+fn list_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { index + len as i64 } else { index };
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
+
+// Keyspace event for a push or pop at one end of a list
+fn list_event(push: bool, left: bool) -> &'static str {
+    match (push, left) {
+        (true, true) => "lpush",
+        (true, false) => "rpush",
+        (false, true) => "lpop",
+        (false, false) => "rpop",
+    }
+}
+
+impl State {
+    pub fn push(&mut self, key: &[u8], values: Vec<Vec<u8>>, left: bool) -> CommandResult {
+        let limits = self.config.limits;
+        let list = self.get_or_create_list(key)?;
+        for value in values {
+            if left {
+                list.push_front(value, &limits);
+            } else {
+                list.push_back(value, &limits);
+            }
+        }
+        let len = list.len();
+        self.notify_keyspace_event(NOTIFY_LIST, list_event(true, left), key);
+        Ok(DataType::Integer(len as i64))
+    }
+
+    pub fn pop(&mut self, key: &[u8], count: Option<usize>, left: bool) -> CommandResult {
+        let list = match self.get_list(key)? {
+            Some(list) => list,
+            None if count.is_some() => return Ok(DataType::NullArray),
+            None => return Ok(DataType::NullBulkString),
+        };
+        let mut popped = Vec::new();
+        for _ in 0..count.unwrap_or(1) {
+            match if left { list.pop_front() } else { list.pop_back() } {
+                Some(value) => popped.push(value),
+                None => break,
+            }
+        }
+        if !popped.is_empty() {
+            self.notify_keyspace_event(NOTIFY_LIST, list_event(false, left), key);
+        }
+        self.remove_if_empty(key);
+        Ok(match count {
+            Some(_) => DataType::bulk_array(popped),
+            None => DataType::BulkString(popped.pop().unwrap()),
+        })
+    }
+
+    // Pop from the first non-empty list, replying with the key and element. Replies null when all
+    // lists are empty, which the connection layer takes as the signal to block.
+    pub fn bpop(&mut self, keys: &[Vec<u8>], left: bool) -> CommandResult {
+        for key in keys {
+            if let Some(list) = self.get_list(key)? {
+                let value = if left { list.pop_front() } else { list.pop_back() }.unwrap();
+                self.notify_keyspace_event(NOTIFY_LIST, list_event(false, left), key);
+                self.remove_if_empty(key);
+                return Ok(DataType::bulk_array([key.clone(), value]));
+            }
+        }
+        Ok(DataType::NullArray)
+    }
+
+    // Atomically pop from one list and push onto another, which may be the same list
+    pub fn lmove(&mut self, source: &[u8], destination: &[u8], from_left: bool, to_left: bool) -> CommandResult {
+        if self.get_list(source)?.is_none() {
+            return Ok(DataType::NullBulkString);
+        }
+        self.get_list(destination)?;
+
+        let limits = self.config.limits;
+        let list = self.get_list(source)?.unwrap();
+        let value = if from_left { list.pop_front() } else { list.pop_back() }.unwrap();
+        let list = self.get_or_create_list(destination)?;
+        if to_left {
+            list.push_front(value.clone(), &limits);
+        } else {
+            list.push_back(value.clone(), &limits);
+        }
+        self.notify_keyspace_event(NOTIFY_LIST, list_event(false, from_left), source);
+        self.notify_keyspace_event(NOTIFY_LIST, list_event(true, to_left), destination);
+        self.remove_if_empty(source);
+        Ok(DataType::BulkString(value))
+    }
+
+    pub fn lpos(&mut self, key: &[u8], element: &[u8], options: &LposOptions) -> CommandResult {
+        let list = match self.get_list(key)? {
+            Some(list) => list,
+            None if options.count.is_some() => return Ok(DataType::Array(vec![])),
+            None => return Ok(DataType::NullBulkString),
+        };
+        let len = list.len();
+        let limit = match options.count {
+            Some(0) => usize::MAX,
+            Some(count) => count,
+            None => 1,
+        };
+        let scan = if options.maxlen == 0 { len } else { options.maxlen.min(len) };
+        let mut skip = options.rank.unsigned_abs() - 1;
+        let mut matches = Vec::new();
+        for n in 0..scan {
+            let index = if options.rank > 0 { n } else { len - 1 - n };
+            if list.get(index) == Some(element) {
+                if skip > 0 {
+                    skip -= 1;
+                    continue;
+                }
+                matches.push(DataType::Integer(index as i64));
+                if matches.len() == limit {
+                    break;
+                }
+            }
+        }
+        Ok(match options.count {
+            Some(_) => DataType::Array(matches),
+            None => matches.pop().unwrap_or(DataType::NullBulkString),
+        })
+    }
+
+    // Pop up to count elements from the first non-empty list
+    pub fn lmpop(&mut self, keys: &[Vec<u8>], left: bool, count: usize) -> CommandResult {
+        for key in keys {
+            if let Some(list) = self.get_list(key)? {
+                let count = count.min(list.len());
+                let popped: Vec<Vec<u8>> = (0..count)
+                    .filter_map(|_| if left { list.pop_front() } else { list.pop_back() })
+                    .collect();
+                self.notify_keyspace_event(NOTIFY_LIST, list_event(false, left), key);
+                self.remove_if_empty(key);
+                return Ok(DataType::Array(vec![DataType::BulkString(key.clone()), DataType::bulk_array(popped)]));
+            }
+        }
+        Ok(DataType::NullArray)
+    }
+
+    pub fn llen(&mut self, key: &[u8]) -> CommandResult {
+        let len = self.get_list(key)?.map_or(0, |list| list.len());
+        Ok(DataType::Integer(len as i64))
+    }
+
+    pub fn lrange(&mut self, key: &[u8], start: i64, stop: i64) -> CommandResult {
+        let list = match self.get_list(key)? {
+            Some(list) => list,
+            None => return Ok(DataType::Array(vec![])),
+        };
+        Ok(match normalize_range(start, stop, list.len()) {
+            Some((start, end)) => DataType::bulk_array(list.range(start, end)),
+            None => DataType::Array(vec![]),
+        })
+    }
+
+    pub fn linsert(&mut self, key: &[u8], before: bool, pivot: &[u8], element: Vec<u8>) -> CommandResult {
+        let limits = self.config.limits;
+        let list = match self.get_list(key)? {
+            Some(list) => list,
+            None => return Ok(DataType::Integer(0)),
+        };
+        let Some(pos) = list.iter().position(|item| item == pivot) else {
+            return Ok(DataType::Integer(-1));
+        };
+        list.insert(if before { pos } else { pos + 1 }, element, &limits);
+        let len = list.len();
+        self.notify_keyspace_event(NOTIFY_LIST, "linsert", key);
+        Ok(DataType::Integer(len as i64))
+    }
+
+    pub fn lset(&mut self, key: &[u8], index: i64, element: Vec<u8>) -> CommandResult {
+        let limits = self.config.limits;
+        let list = match self.get_list(key)? {
+            Some(list) => list,
+            None => return Err(DataType::SimpleError("ERR no such key".to_string())),
+        };
+        let index = list_index(index, list.len()).ok_or_else(|| DataType::SimpleError("ERR index out of range".to_string()))?;
+        list.set(index, element, &limits);
+        self.notify_keyspace_event(NOTIFY_LIST, "lset", key);
+        Ok(DataType::ok())
+    }
+
+    pub fn lindex(&mut self, key: &[u8], index: i64) -> CommandResult {
+        let element = self.get_list(key)?.and_then(|list| {
+            list_index(index, list.len()).and_then(|index| list.get(index)).map(|value| value.to_vec())
+        });
+        Ok(element.map_or(DataType::NullBulkString, DataType::BulkString))
+    }
+
+    pub fn lrem(&mut self, key: &[u8], count: i64, element: &[u8]) -> CommandResult {
+        let list = match self.get_list(key)? {
+            Some(list) => list,
+            None => return Ok(DataType::Integer(0)),
+        };
+        let limit = if count == 0 { usize::MAX } else { count.unsigned_abs() as usize };
+        let mut removed = 0;
+        if count >= 0 {
+            let mut i = 0;
+            while i < list.len() && removed < limit {
+                if list.get(i) == Some(element) {
+                    list.remove(i);
+                    removed += 1;
+                } else {
+                    i += 1;
+                }
+            }
+        } else {
+            let mut i = list.len();
+            while i > 0 && removed < limit {
+                i -= 1;
+                if list.get(i) == Some(element) {
+                    list.remove(i);
+                    removed += 1;
+                }
+            }
+        }
+        if removed > 0 {
+            self.notify_keyspace_event(NOTIFY_LIST, "lrem", key);
+        }
+        self.remove_if_empty(key);
+        Ok(DataType::Integer(removed as i64))
+    }
+
+    pub fn ltrim(&mut self, key: &[u8], start: i64, stop: i64) -> CommandResult {
+        let list = match self.get_list(key)? {
+            Some(list) => list,
+            None => return Ok(DataType::ok()),
+        };
+        match normalize_range(start, stop, list.len()) {
+            Some((start, end)) => list.retain_range(start, end),
+            None => list.clear(),
+        }
+        self.notify_keyspace_event(NOTIFY_LIST, "ltrim", key);
+        self.remove_if_empty(key);
+        Ok(DataType::ok())
+    }
+}
@@ -0,0 +1,468 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::Hasher,
+};
+
+use crate::{
+    command::{parse_integer_arg, syntax_error, Command},
+    glob::glob_match,
+    resp::DataType,
+    state::State,
+};
+
+use bitmap::BitfieldOp;
+use set::{set_reply, SetOperation};
+use zset::ZrangeBy;
+
+pub mod bitmap;
+pub mod bloom;
+pub mod cms;
+pub mod functions;
+pub mod geo;
+pub mod hash;
+pub mod json;
+pub mod keys;
+pub mod list;
+pub mod scripting;
+pub mod server;
+pub mod set;
+pub mod stream;
+pub mod string;
+pub mod timeseries;
+pub mod topk;
+pub mod zset;
+
+impl State {
+    // Run a single command against the datastore and produce its reply
+    pub fn execute(&mut self, cmd: Command) -> DataType {
+        let dirties = cmd.is_write() && !matches!(cmd, Command::PUBLISH(..) | Command::SPUBLISH(..));
+        let result = match cmd {
+            Command::PING => Ok(DataType::SimpleString("PONG".to_string())),
+            Command::REPLCONF(..) => Ok(DataType::ok()),
+            Command::ECHO(msg) => Ok(DataType::BulkString(msg)),
+            Command::CONFIGGET(patterns) => self.config_get(&patterns),
+            Command::CONFIGSET(pairs) => self.config_set(&pairs),
+            Command::INFO(sections) => Ok(self.info(&sections)),
+            Command::SAVE => self.save(),
+            Command::BGSAVE => self.bgsave(),
+            Command::BGREWRITEAOF => self.bgrewriteaof(),
+            Command::CLUSTERINFO => self.cluster_info(),
+            Command::CLUSTERMYID => self.cluster_myid(),
+            Command::CLUSTERKEYSLOT(key) => self.cluster_keyslot(&key),
+            Command::CLUSTERSLOTS => self.cluster_slots(),
+            Command::CLUSTERNODES => self.cluster_nodes(),
+            Command::CLUSTERADDSLOTS(slots) => self.cluster_addslots(&slots),
+            Command::CLUSTERDELSLOTS(slots) => self.cluster_delslots(&slots),
+            Command::LASTSAVE => Ok(DataType::Integer(self.save_status.lock().unwrap().last_save_time as i64)),
+            Command::GET(key) => self.get(&key),
+            Command::SET(key, value) => self.set(key, value, None),
+            Command::SETPX(key, value, expiry) => self.set(key, value, Some(expiry)),
+            Command::LCS(key1, key2, options) => self.lcs(&key1, &key2, &options),
+            Command::SETBIT(key, offset, value) => self.setbit(&key, offset, value),
+            Command::GETBIT(key, offset) => self.getbit(&key, offset),
+            Command::BITCOUNT(key, range) => self.bitcount(&key, range),
+            Command::BITOP(operation, destination, keys) => self.bitop(operation, &destination, &keys),
+            Command::BITPOS(key, bit, range, explicit_end) => self.bitpos(&key, bit, range, explicit_end),
+            Command::BITFIELD(key, ops) => self.bitfield(&key, &ops),
+            Command::DEL(keys) => self.del(&keys),
+            Command::TOUCH(keys) => self.touch(&keys),
+            Command::OBJECTIDLETIME(key) => self.object_idletime(&key),
+            Command::OBJECTFREQ(key) => self.object_freq(&key),
+            Command::OBJECTENCODING(key) => self.object_encoding(&key),
+            Command::DUMP(key) => self.dump(&key),
+            Command::RESTORE(key, ttl, payload, options) => self.restore(&key, ttl, &payload, options),
+            Command::LPUSH(key, values) => self.push(&key, values, true),
+            Command::RPUSH(key, values) => self.push(&key, values, false),
+            Command::LPOP(key, count) => self.pop(&key, count, true),
+            Command::RPOP(key, count) => self.pop(&key, count, false),
+            Command::LLEN(key) => self.llen(&key),
+            Command::LRANGE(key, start, stop) => self.lrange(&key, start, stop),
+            Command::LINSERT(key, before, pivot, element) => self.linsert(&key, before, &pivot, element),
+            Command::LSET(key, index, element) => self.lset(&key, index, element),
+            Command::LINDEX(key, index) => self.lindex(&key, index),
+            Command::LREM(key, count, element) => self.lrem(&key, count, &element),
+            Command::LTRIM(key, start, stop) => self.ltrim(&key, start, stop),
+            Command::BLPOP(keys, _) => self.bpop(&keys, true),
+            Command::BRPOP(keys, _) => self.bpop(&keys, false),
+            Command::LMOVE(source, destination, from_left, to_left) => self.lmove(&source, &destination, from_left, to_left),
+            Command::BLMOVE(keys, from_left, to_left, _) => self.lmove(&keys[0], &keys[1], from_left, to_left),
+            Command::LPOS(key, element, options) => self.lpos(&key, &element, &options),
+            Command::LMPOP(keys, left, count) | Command::BLMPOP(keys, left, count, _) => self.lmpop(&keys, left, count),
+            Command::HSET(key, pairs) => self.hset(&key, pairs),
+            Command::HMSET(key, pairs) => self.hset(&key, pairs).map(|_| DataType::ok()),
+            Command::HGET(key, field) => self.hget(&key, &field),
+            Command::HMGET(key, fields) => self.hmget(&key, &fields),
+            Command::HDEL(key, fields) => self.hdel(&key, &fields),
+            Command::HGETALL(key) => self.hgetall(&key),
+            Command::HEXISTS(key, field) => self.hexists(&key, &field),
+            Command::HLEN(key) => self.hlen(&key),
+            Command::HKEYS(key) => self.hkeys(&key),
+            Command::HVALS(key) => self.hvals(&key),
+            Command::HSCAN(key, cursor, options) => self.hscan(&key, cursor, &options),
+            Command::HRANDFIELD(key, count, with_values) => self.hrandfield(&key, count, with_values),
+            Command::HEXPIRE(key, ttl, condition, fields) => self.hexpire(&key, ttl, condition, &fields),
+            Command::HTTL(key, fields, millis) => self.httl(&key, &fields, millis),
+            Command::HPERSIST(key, fields) => self.hpersist(&key, &fields),
+            Command::SADD(key, members) => self.sadd(&key, members),
+            Command::SREM(key, members) => self.srem(&key, &members),
+            Command::SMEMBERS(key) => self.smembers(&key),
+            Command::SISMEMBER(key, member) => self.sismember(&key, &member),
+            Command::SCARD(key) => self.scard(&key),
+            Command::SINTER(keys) => self.set_algebra(&keys, SetOperation::Inter).map(set_reply),
+            Command::SUNION(keys) => self.set_algebra(&keys, SetOperation::Union).map(set_reply),
+            Command::SDIFF(keys) => self.set_algebra(&keys, SetOperation::Diff).map(set_reply),
+            Command::SINTERSTORE(destination, keys) => self.set_algebra_store(destination, &keys, SetOperation::Inter),
+            Command::SUNIONSTORE(destination, keys) => self.set_algebra_store(destination, &keys, SetOperation::Union),
+            Command::SDIFFSTORE(destination, keys) => self.set_algebra_store(destination, &keys, SetOperation::Diff),
+            Command::SINTERCARD(keys, limit) => self.sintercard(&keys, limit),
+            Command::SPOP(key, count) => self.spop(&key, count),
+            Command::SRANDMEMBER(key, count) => self.srandmember(&key, count),
+            Command::SMOVE(source, destination, member) => self.smove(&source, &destination, member),
+            Command::SSCAN(key, cursor, options) => self.sscan(&key, cursor, &options),
+            Command::SMISMEMBER(key, members) => self.smismember(&key, &members),
+            Command::ZADD(key, options, pairs) => self.zadd(&key, &options, pairs),
+            Command::ZSCORE(key, member) => self.zscore(&key, &member),
+            Command::ZCARD(key) => self.zcard(&key),
+            Command::ZRANGE(key, spec) => self.zrange(&key, &spec),
+            Command::ZRANGESTORE(destination, source, spec) => self.zrangestore(destination, &source, &spec),
+            Command::ZRANK(key, member, with_score) => self.zrank(&key, &member, false, with_score),
+            Command::ZREVRANK(key, member, with_score) => self.zrank(&key, &member, true, with_score),
+            Command::ZINCRBY(key, increment, member) => self.zincrby(&key, increment, member),
+            Command::ZREM(key, members) => self.zrem(&key, &members),
+            Command::ZREMRANGEBYRANK(key, start, stop) => self.zremrange(&key, ZrangeBy::Rank(start, stop)),
+            Command::ZREMRANGEBYSCORE(key, range) => self.zremrange(&key, ZrangeBy::Score(range)),
+            Command::ZREMRANGEBYLEX(key, range) => self.zremrange(&key, ZrangeBy::Lex(range)),
+            Command::ZUNIONSTORE(destination, inputs) => self.zset_algebra_store(destination, &inputs, SetOperation::Union),
+            Command::ZINTERSTORE(destination, inputs) => self.zset_algebra_store(destination, &inputs, SetOperation::Inter),
+            Command::ZDIFF(keys, with_scores) => self.zdiff(keys, with_scores),
+            Command::ZDIFFSTORE(destination, keys) => self.zdiffstore(destination, keys),
+            Command::ZRANDMEMBER(key, count, with_scores) => self.zrandmember(&key, count, with_scores),
+            Command::ZMSCORE(key, members) => self.zmscore(&key, &members),
+            Command::ZCOUNT(key, range) => self.zcount(&key, &range),
+            Command::ZLEXCOUNT(key, range) => self.zlexcount(&key, &range),
+            Command::ZSCAN(key, cursor, options) => self.zscan(&key, cursor, &options),
+            Command::ZMPOP(keys, min, count) | Command::BZMPOP(keys, min, count, _) => self.zmpop(&keys, min, count),
+            Command::GEOPOS(key, members) => self.geopos(&key, &members),
+            Command::GEODIST(key, member1, member2, unit) => self.geodist(&key, &member1, &member2, unit),
+            Command::GEOHASH(key, members) => self.geohash(&key, &members),
+            Command::BFRESERVE(key, error_rate, capacity, expansion, nonscaling) => {
+                self.bf_reserve(&key, error_rate, capacity, expansion, nonscaling)
+            }
+            Command::BFADD(key, item) => self.bf_add(&key, &item),
+            Command::BFMADD(key, items) => self.bf_madd(&key, &items),
+            Command::BFEXISTS(key, item) => self.bf_exists(&key, &item),
+            Command::BFMEXISTS(key, items) => self.bf_mexists(&key, &items),
+            Command::BFINFO(key, field) => self.bf_info(&key, field),
+            Command::CMSINIT(key, width, depth) => self.cms_init(&key, width, depth),
+            Command::CMSINCRBY(key, increments) => self.cms_incrby(&key, &increments),
+            Command::CMSQUERY(key, items) => self.cms_query(&key, &items),
+            Command::CMSMERGE(destination, sources) => self.cms_merge(&destination, &sources),
+            Command::CMSINFO(key) => self.cms_info(&key),
+            Command::TOPKRESERVE(key, k, width, depth, decay) => self.topk_reserve(&key, k, width, depth, decay),
+            Command::TOPKADD(key, increments) => self.topk_add(&key, &increments),
+            Command::TOPKQUERY(key, items) => self.topk_query(&key, &items),
+            Command::TOPKCOUNT(key, items) => self.topk_count(&key, &items),
+            Command::TOPKLIST(key, with_count) => self.topk_list(&key, with_count),
+            Command::TOPKINFO(key) => self.topk_info(&key),
+            Command::JSONSET(key, path, value, nx, xx) => self.json_set(&key, &path, value, nx, xx),
+            Command::JSONGET(key, format, paths) => self.json_get(&key, &format, &paths),
+            Command::JSONDEL(key, path) => self.json_del(&key, &path),
+            Command::JSONTYPE(key, path) => self.json_type(&key, &path),
+            Command::TSCREATE(key, options) => self.ts_create(&key, &options),
+            Command::TSADD(key, timestamp, value, options) => self.ts_add(&key, timestamp, value, &options),
+            Command::TSGET(key) => self.ts_get(&key),
+            Command::TSRANGE(key, range) => self.ts_range(&key, &range),
+            Command::TSINFO(key) => self.ts_info(&key),
+            Command::XADD(key, options, id, fields) => self.xadd(&key, &options, id, fields),
+            Command::XRANGE(key, start, end, rev, count) => self.xrange(&key, start, end, rev, count),
+            Command::XREAD(keys, ids, count) | Command::XREADBLOCK(keys, ids, count, _) => self.xread(&keys, &ids, count),
+            Command::XLEN(key) => self.xlen(&key),
+            Command::XDEL(key, ids) => self.xdel(&key, &ids),
+            Command::XTRIM(key, options) => self.xtrim(&key, &options),
+            Command::XSETID(key, id, entries_added, max_deleted_id) => self.xsetid(&key, id, entries_added, max_deleted_id),
+            Command::XGROUPCREATE(key, group, id, mkstream) => self.xgroup_create(&key, &group, id, mkstream),
+            Command::XGROUPDESTROY(key, group) => self.xgroup_destroy(&key, &group),
+            Command::XREADGROUP(args) | Command::XREADGROUPBLOCK(args, _) => self.xreadgroup(&args),
+            Command::XACK(key, group, ids) => self.xack(&key, &group, &ids),
+            Command::XPENDING(key, group, range) => self.xpending(&key, &group, range.as_ref()),
+            Command::XCLAIM(key, group, consumer, ids, options) => self.xclaim(&key, &group, &consumer, &ids, &options),
+            Command::XAUTOCLAIM(key, group, consumer, min_idle, start, count, justid) => {
+                self.xautoclaim(&key, &group, &consumer, min_idle, start, count, justid)
+            }
+            Command::XINFOSTREAM(key, full) => self.xinfo_stream(&key, full),
+            Command::XINFOGROUPS(key) => self.xinfo_groups(&key),
+            Command::XINFOCONSUMERS(key, group) => self.xinfo_consumers(&key, &group),
+            Command::EVAL(script, keys, args) => self.eval(&script, keys, args, false),
+            Command::EVALRO(script, keys, args) => self.eval(&script, keys, args, true),
+            Command::EVALSHA(sha, keys, args) => self.evalsha(&sha, keys, args, false),
+            Command::EVALSHARO(sha, keys, args) => self.evalsha(&sha, keys, args, true),
+            Command::SCRIPTLOAD(source) => self.script_load(&source),
+            Command::SCRIPTEXISTS(shas) => Ok(self.script_exists(&shas)),
+            Command::SCRIPTFLUSH => {
+                self.scripts.clear();
+                Ok(DataType::ok())
+            }
+            Command::FCALL(function, keys, args) => self.fcall(&function, keys, args, false),
+            Command::FCALLRO(function, keys, args) => self.fcall(&function, keys, args, true),
+            Command::FUNCTIONLOAD(code, replace) => self.function_load(&code, replace),
+            Command::FUNCTIONDELETE(library) => self.function_delete(&library),
+            Command::FUNCTIONFLUSH => {
+                self.libraries.clear();
+                Ok(DataType::ok())
+            }
+            Command::FUNCTIONLIST(pattern, with_code) => Ok(self.function_list(pattern.as_deref(), with_code)),
+            Command::FUNCTIONDUMP => Ok(self.function_dump()),
+            Command::FUNCTIONRESTORE(payload, policy) => self.function_restore(&payload, policy),
+            Command::PUBLISH(channel, message) => Ok(self.publish(&channel, &message)),
+            Command::SPUBLISH(channel, message) => Ok(self.spublish(&channel, &message)),
+            Command::PUBSUBCHANNELS(pattern, shard) => Ok(self.pubsub_channels(pattern.as_deref(), shard)),
+            Command::PUBSUBNUMSUB(channels, shard) => Ok(self.pubsub_numsub(&channels, shard)),
+            Command::PUBSUBNUMPAT => Ok(self.pubsub_numpat()),
+            // Subscriptions belong to a connection, see execute_subscription
+            Command::SUBSCRIBE(_)
+            | Command::UNSUBSCRIBE(_)
+            | Command::PSUBSCRIBE(_)
+            | Command::PUNSUBSCRIBE(_)
+            | Command::SSUBSCRIBE(_)
+            | Command::SUNSUBSCRIBE(_) => {
+                Err(DataType::SimpleError("ERR subscriptions require a client connection".to_string()))
+            }
+            // Handled by the connection, see handle_command and execute_connection
+            Command::QUIT
+            | Command::RESET
+            | Command::MULTI
+            | Command::EXEC
+            | Command::DISCARD
+            | Command::HELLO(..)
+            | Command::CLIENTID
+            | Command::CLIENTGETREDIR
+            | Command::CLIENTTRACKING(_)
+            | Command::CLIENTCACHING(_)
+            | Command::REPLCONFGETACK
+            | Command::REPLCONFACK(_)
+            | Command::PSYNC(..)
+            | Command::REPLICAOF(_)
+            | Command::FAILOVER(..)
+            | Command::FAILOVERABORT
+            | Command::WAITAOF(..) => Err(DataType::SimpleError("ERR command requires a client connection".to_string())),
+            Command::INVALID(msg) => Err(DataType::SimpleError(msg)),
+        };
+        if dirties && result.is_ok() {
+            self.dirty += 1;
+        }
+        result.unwrap_or_else(|err| err)
+    }
+}
+
+impl Command {
+    // Commands that change the dataset or have effects replicas must see. Scripts and functions
+    // are judged by the commands they run instead.
+    pub fn is_write(&self) -> bool {
+        match self {
+            Command::BITFIELD(_, ops) => ops.iter().any(|op| !matches!(op, BitfieldOp::Get(..))),
+            Command::SET(..)
+            | Command::SETPX(..)
+            | Command::RESTORE(..)
+            | Command::DEL(..)
+            | Command::SETBIT(..)
+            | Command::BITOP(..)
+            | Command::LPUSH(..)
+            | Command::RPUSH(..)
+            | Command::LPOP(..)
+            | Command::RPOP(..)
+            | Command::LINSERT(..)
+            | Command::LSET(..)
+            | Command::LREM(..)
+            | Command::LTRIM(..)
+            | Command::BLPOP(..)
+            | Command::BRPOP(..)
+            | Command::LMOVE(..)
+            | Command::BLMOVE(..)
+            | Command::LMPOP(..)
+            | Command::BLMPOP(..)
+            | Command::HSET(..)
+            | Command::HMSET(..)
+            | Command::HDEL(..)
+            | Command::HEXPIRE(..)
+            | Command::HPERSIST(..)
+            | Command::SADD(..)
+            | Command::SREM(..)
+            | Command::SINTERSTORE(..)
+            | Command::SUNIONSTORE(..)
+            | Command::SDIFFSTORE(..)
+            | Command::SPOP(..)
+            | Command::SMOVE(..)
+            | Command::ZADD(..)
+            | Command::ZRANGESTORE(..)
+            | Command::ZINCRBY(..)
+            | Command::ZREM(..)
+            | Command::ZREMRANGEBYRANK(..)
+            | Command::ZREMRANGEBYSCORE(..)
+            | Command::ZREMRANGEBYLEX(..)
+            | Command::ZUNIONSTORE(..)
+            | Command::ZINTERSTORE(..)
+            | Command::ZDIFFSTORE(..)
+            | Command::ZMPOP(..)
+            | Command::BZMPOP(..)
+            | Command::BFRESERVE(..)
+            | Command::BFADD(..)
+            | Command::BFMADD(..)
+            | Command::CMSINIT(..)
+            | Command::CMSINCRBY(..)
+            | Command::CMSMERGE(..)
+            | Command::TOPKRESERVE(..)
+            | Command::TOPKADD(..)
+            | Command::JSONSET(..)
+            | Command::JSONDEL(..)
+            | Command::TSCREATE(..)
+            | Command::TSADD(..)
+            | Command::XADD(..)
+            | Command::XDEL(..)
+            | Command::XTRIM(..)
+            | Command::XSETID(..)
+            | Command::XGROUPCREATE(..)
+            | Command::XGROUPDESTROY(..)
+            | Command::XREADGROUP(..)
+            | Command::XREADGROUPBLOCK(..)
+            | Command::XACK(..)
+            | Command::XCLAIM(..)
+            | Command::XAUTOCLAIM(..)
+            | Command::FUNCTIONLOAD(..)
+            | Command::FUNCTIONDELETE(_)
+            | Command::FUNCTIONFLUSH
+            | Command::FUNCTIONRESTORE(..) => true,
+            // Messages are propagated to replicas
+            Command::PUBLISH(..) | Command::SPUBLISH(..) => true,
+            _ => false,
+        }
+    }
+}
+
+// Resolve a Redis style inclusive [start, stop] index range, where negative indexes count from
+// the end, into a half-open range clamped to the collection length. Returns None when empty.
+pub fn normalize_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 { (start + len).max(0) } else { start };
+    let stop = if stop < 0 { stop + len } else { stop.min(len - 1) };
+    if start > stop || start >= len {
+        return None;
+    }
+    Some((start as usize, stop as usize + 1))
+}
+
+// NX/XX/GT/LT conditions of the expire family. A missing expiry counts as an infinite TTL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpireCondition {
+    Always,
+    NX,
+    XX,
+    GT,
+    LT,
+}
+
+impl ExpireCondition {
+    pub fn parse(arg: &[u8]) -> Option<ExpireCondition> {
+        match arg.to_ascii_lowercase().as_slice() {
+            b"nx" => Some(ExpireCondition::NX),
+            b"xx" => Some(ExpireCondition::XX),
+            b"gt" => Some(ExpireCondition::GT),
+            b"lt" => Some(ExpireCondition::LT),
+            _ => None,
+        }
+    }
+
+    pub fn allows(&self, current: Option<u64>, new: u64) -> bool {
+        match (self, current) {
+            (ExpireCondition::Always, _) => true,
+            (ExpireCondition::NX, current) => current.is_none(),
+            (ExpireCondition::XX, current) => current.is_some(),
+            (ExpireCondition::GT, Some(current)) => new > current,
+            (ExpireCondition::GT, None) => false,
+            (ExpireCondition::LT, Some(current)) => new < current,
+            (ExpireCondition::LT, None) => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    pattern: Option<Vec<u8>>,
+    count: usize,
+    pub novalues: bool,
+}
+
+impl ScanOptions {
+    // Parse the MATCH/COUNT (and optionally NOVALUES) options trailing a SCAN family command
+    pub fn parse(args: &[Vec<u8>], allow_novalues: bool) -> Result<ScanOptions, Command> {
+        let mut options = ScanOptions { pattern: None, count: 10, novalues: false };
+        let mut i = 0;
+        while i < args.len() {
+            match args[i].to_ascii_lowercase().as_slice() {
+                b"match" if i + 1 < args.len() => {
+                    i += 1;
+                    options.pattern = Some(args[i].clone());
+                }
+                b"count" if i + 1 < args.len() => {
+                    i += 1;
+                    options.count = match parse_integer_arg::<i64>(&args[i]) {
+                        Some(count) if count >= 1 => count as usize,
+                        Some(_) => return Err(syntax_error()),
+                        None => return Err(Command::INVALID("ERR value is not an integer or out of range".to_string())),
+                    };
+                }
+                b"novalues" if allow_novalues => options.novalues = true,
+                _ => return Err(syntax_error()),
+            }
+            i += 1;
+        }
+        Ok(options)
+    }
+
+    pub fn matches(&self, item: &[u8]) -> bool {
+        match &self.pattern {
+            Some(pattern) => glob_match(pattern, item),
+            None => true,
+        }
+    }
+}
+
+pub fn parse_cursor(arg: &[u8]) -> Result<u64, Command> {
+    parse_integer_arg::<u64>(arg).ok_or_else(|| Command::INVALID("ERR invalid cursor".to_string()))
+}
+
+// Position of an element in scan order. This depends only on the element itself, so a scan
+// returns every element present for its whole duration regardless of concurrent changes.
+fn scan_position(item: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(item);
+    hasher.finish()
+}
+
+// Return roughly `count` items at or after `cursor` in scan order, plus the cursor to resume
+// from (0 once the scan is complete). Items sharing a position are always returned together.
+pub fn scan_items<'a, T>(items: impl Iterator<Item = (&'a [u8], T)>, cursor: u64, options: &ScanOptions) -> (u64, Vec<T>) {
+    let mut candidates: Vec<(u64, &[u8], T)> = items
+        .map(|(name, item)| (scan_position(name), name, item))
+        .filter(|(position, _, _)| *position >= cursor)
+        .collect();
+    candidates.sort_by_key(|(position, _, _)| *position);
+
+    let mut end = candidates.len().min(options.count);
+    while end > 0 && end < candidates.len() && candidates[end].0 == candidates[end - 1].0 {
+        end += 1;
+    }
+    let next_cursor = candidates.get(end).map_or(0, |(position, _, _)| *position);
+    let selected = candidates.into_iter()
+        .take(end)
+        .filter(|(_, name, _)| options.matches(name))
+        .map(|(_, _, item)| item)
+        .collect();
+    (next_cursor, selected)
+}
+
+pub fn scan_reply(cursor: u64, items: Vec<DataType>) -> DataType {
+    DataType::Array(vec![
+        DataType::BulkString(cursor.to_string().into_bytes()),
+        DataType::Array(items),
+    ])
+}
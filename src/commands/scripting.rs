@@ -0,0 +1,476 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    cluster::SlotRefusal,
+    command::{not_an_integer, parse_integer_arg, wrong_number_of_args, Command},
+    lua::{
+        interpreter::{Host, Interpreter},
+        parser::{self, FunctionBody},
+        stdlib::{arg, check_bytes, check_number, library},
+        Builtin, LuaError, Table, Value,
+    },
+    resp::DataType,
+    sha1::sha1_hex,
+    state::{CommandResult, State},
+};
+
+// Name scripts are compiled under, which shows up in error positions
+const CHUNK_NAME: &str = "user_script";
+
+impl Command {
+    // EVAL script numkeys [key ...] [arg ...], and likewise EVALSHA with a SHA1 and FCALL with
+    // a function name, each with a read-only _RO form
+    pub fn parse_eval(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 {
+            return wrong_number_of_args(name);
+        }
+        let Some(numkeys) = parse_integer_arg::<i64>(&args[2]) else {
+            return not_an_integer();
+        };
+        if numkeys < 0 {
+            return Command::INVALID("ERR Number of keys can't be negative".to_string());
+        }
+        if numkeys as usize > args.len() - 3 {
+            return Command::INVALID("ERR Number of keys can't be greater than number of args".to_string());
+        }
+        let (keys, argv) = args[3..].split_at(numkeys as usize);
+        match name {
+            "eval" => Command::EVAL(args[1].clone(), keys.to_vec(), argv.to_vec()),
+            "eval_ro" => Command::EVALRO(args[1].clone(), keys.to_vec(), argv.to_vec()),
+            "evalsha" => Command::EVALSHA(String::from_utf8_lossy(&args[1]).to_lowercase(), keys.to_vec(), argv.to_vec()),
+            "evalsha_ro" => Command::EVALSHARO(String::from_utf8_lossy(&args[1]).to_lowercase(), keys.to_vec(), argv.to_vec()),
+            "fcall" => Command::FCALL(String::from_utf8_lossy(&args[1]).into_owned(), keys.to_vec(), argv.to_vec()),
+            _ => Command::FCALLRO(String::from_utf8_lossy(&args[1]).into_owned(), keys.to_vec(), argv.to_vec()),
+        }
+    }
+
+    // SCRIPT LOAD script | EXISTS sha1 [sha1 ...] | FLUSH [ASYNC|SYNC]
+    pub fn parse_script(args: &[Vec<u8>]) -> Command {
+        if args.len() < 2 {
+            return wrong_number_of_args("script");
+        }
+        let subcommand = String::from_utf8_lossy(&args[1]).to_lowercase();
+        match (subcommand.as_str(), args.len()) {
+            ("load", 3) => Command::SCRIPTLOAD(args[2].clone()),
+            ("exists", 3..) => Command::SCRIPTEXISTS(args[2..].iter().map(|sha| String::from_utf8_lossy(sha).to_lowercase()).collect()),
+            // The cache is dropped in place either way
+            ("flush", 2) => Command::SCRIPTFLUSH,
+            ("flush", 3) => match args[2].to_ascii_lowercase().as_slice() {
+                b"async" | b"sync" => Command::SCRIPTFLUSH,
+                _ => Command::INVALID("ERR SCRIPT FLUSH only support SYNC|ASYNC option".to_string()),
+            },
+            ("load" | "exists" | "flush", _) => wrong_number_of_args(&format!("script|{}", subcommand)),
+            _ => Command::INVALID(format!("ERR unknown subcommand '{}'. Try SCRIPT HELP.", subcommand)),
+        }
+    }
+
+    // Commands that only make sense for a connection, or would nest scripts, can't be called
+    // from a script. Blocking commands are allowed and never block.
+    fn is_allowed_in_script(&self) -> bool {
+        !self.is_connection()
+            && !matches!(
+                self,
+                Command::QUIT
+                    | Command::SAVE
+                    | Command::BGSAVE
+                    | Command::WAITAOF(..)
+                    | Command::REPLCONF(..)
+                    | Command::PSYNC(..)
+                    | Command::REPLICAOF(_)
+                    | Command::FAILOVER(..)
+                    | Command::FAILOVERABORT
+                    | Command::MULTI
+                    | Command::EXEC
+                    | Command::DISCARD
+                    | Command::HELLO(..)
+                    | Command::EVAL(..)
+                    | Command::EVALRO(..)
+                    | Command::EVALSHA(..)
+                    | Command::EVALSHARO(..)
+                    | Command::SCRIPTLOAD(_)
+                    | Command::SCRIPTEXISTS(_)
+                    | Command::SCRIPTFLUSH
+                    | Command::FCALL(..)
+                    | Command::FCALLRO(..)
+                    | Command::FUNCTIONLOAD(..)
+                    | Command::FUNCTIONDELETE(_)
+                    | Command::FUNCTIONFLUSH
+                    | Command::FUNCTIONLIST(..)
+                    | Command::FUNCTIONDUMP
+                    | Command::FUNCTIONRESTORE(..)
+            )
+    }
+}
+
+// Gives scripts and functions access to the datastore they run against
+pub struct ScriptHost<'a> {
+    pub state: &'a mut State,
+    // Set for the _RO entry points and no-writes functions
+    read_only: bool,
+    // Write commands the script performed, which are propagated in place of the script
+    // itself so replicas and the AOF don't depend on the script behaving the same again
+    effects: Vec<Vec<Vec<u8>>>,
+    // In a cluster, the slot of the keys the script declared, or else of the first it used,
+    // which are all it may use
+    slot: Option<u16>,
+}
+
+impl<'a> ScriptHost<'a> {
+    pub fn new(state: &'a mut State, read_only: bool) -> Self {
+        ScriptHost { state, read_only, effects: Vec::new(), slot: None }
+    }
+
+    // The keys a script is run with have already been checked to be in a slot this node serves
+    pub fn declare_keys(&mut self, keys: &[Vec<u8>]) {
+        self.slot = self.state.local_slot(&keys.iter().collect::<Vec<_>>()).ok().flatten();
+    }
+
+    // A script's commands may only use keys of the one slot it runs against, which this node
+    // must serve
+    fn cluster_rejection(&mut self, cmd: &Command) -> Option<&'static str> {
+        match self.state.local_slot(&cmd.keys()) {
+            Ok(None) => None,
+            Ok(Some(slot)) if *self.slot.get_or_insert(slot) == slot => None,
+            Ok(Some(_)) | Err(SlotRefusal::CrossSlot) => Some("ERR Script attempted to access keys that do not hash to the same slot"),
+            Err(SlotRefusal::Down) => Some("ERR Script attempted to execute a command while the cluster is down"),
+            Err(SlotRefusal::Moved(..)) => Some("ERR Script attempted to access a non local key in a cluster node script"),
+        }
+    }
+
+    // Propagate the script's writes as one atomic group. This happens even when the script
+    // failed part way, as its earlier writes have been applied.
+    pub fn propagate_effects(self) {
+        self.state.propagate(self.effects);
+    }
+}
+
+impl Host for ScriptHost<'_> {
+    fn call(&mut self, args: Vec<Vec<u8>>) -> DataType {
+        let cmd = Command::from(DataType::bulk_array(args.iter().cloned()));
+        if !cmd.is_allowed_in_script() {
+            return DataType::SimpleError("ERR This Redis command is not allowed from script".to_string());
+        }
+        let write = cmd.is_write();
+        if self.read_only && write {
+            return DataType::SimpleError("ERR Write commands are not allowed from read-only scripts.".to_string());
+        }
+        if let Some(rejection) = self.state.write_rejection(&cmd).or_else(|| self.cluster_rejection(&cmd)) {
+            return DataType::SimpleError(rejection.to_string());
+        }
+        let reply = self.state.execute(cmd);
+        if write {
+            let effects = self.state.write_effects(args, &reply);
+            self.effects.extend(effects);
+        }
+        reply
+    }
+}
+
+pub fn compile(source: &[u8]) -> Result<Arc<FunctionBody>, DataType> {
+    parser::parse(source).map_err(|(msg, line)| {
+        DataType::SimpleError(format!("ERR Error compiling script (new function): {}:{}: {}", CHUNK_NAME, line, msg))
+    })
+}
+
+fn status_table(field: &str, msg: impl AsRef<[u8]>) -> Value {
+    let mut table = Table::default();
+    table.set_str(field, Value::str(msg));
+    Value::table(table)
+}
+
+// Redis replies as Lua values: status and error replies become tables with an ok or err
+// field, and nulls become false
+fn reply_to_lua(reply: DataType) -> Value {
+    match reply {
+        DataType::SimpleString(s) => status_table("ok", s),
+        DataType::SimpleError(s) => status_table("err", s),
+        DataType::Integer(n) => Value::Number(n as f64),
+        DataType::BulkString(s) => Value::str(s),
+        DataType::NullBulkString | DataType::NullArray => Value::Bool(false),
+        DataType::Array(items) | DataType::Push(items) => Value::table(Table::from_array(items.into_iter().map(reply_to_lua).collect())),
+        // Scripts talk RESP2, where maps are flat arrays
+        DataType::Map(pairs) => {
+            let items = pairs.into_iter().flat_map(|(key, value)| [reply_to_lua(key), reply_to_lua(value)]);
+            Value::table(Table::from_array(items.collect()))
+        }
+    }
+}
+
+// The value a script returns as a Redis reply. Numbers are truncated to integers and arrays
+// stop at the first nil.
+pub fn lua_to_reply(value: &Value) -> DataType {
+    match value {
+        Value::Bool(true) => DataType::Integer(1),
+        Value::Number(n) => DataType::Integer(*n as i64),
+        Value::Str(s) => DataType::BulkString(s.to_vec()),
+        Value::Table(table) => {
+            let table = table.borrow();
+            if let Value::Str(err) = table.get_str("err") {
+                return DataType::SimpleError(String::from_utf8_lossy(&err).into_owned());
+            }
+            if let Value::Str(ok) = table.get_str("ok") {
+                return DataType::SimpleString(String::from_utf8_lossy(&ok).into_owned());
+            }
+            let mut items = Vec::new();
+            loop {
+                match table.get(&Value::Number((items.len() + 1) as f64)) {
+                    Value::Nil => break,
+                    item => items.push(lua_to_reply(&item)),
+                }
+            }
+            DataType::Array(items)
+        }
+        Value::Nil | Value::Bool(false) | Value::Function(_) | Value::Builtin(..) => DataType::NullBulkString,
+    }
+}
+
+fn call_host(interp: &mut Interpreter, args: Vec<Value>) -> Result<DataType, LuaError> {
+    if args.is_empty() {
+        return Err(interp.error("Please specify at least one argument for this redis lib call"));
+    }
+    let mut command = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg {
+            Value::Str(_) | Value::Number(_) => command.push(arg.to_bytes().unwrap()),
+            _ => return Err(interp.error("Lua redis lib command arguments must be strings or integers")),
+        }
+    }
+    Ok(interp.host.call(command))
+}
+
+// redis.call raises error replies as errors the script can't ignore
+fn redis_call(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    match call_host(interp, args)? {
+        DataType::SimpleError(err) => Err(LuaError { value: status_table("err", err) }),
+        reply => Ok(vec![reply_to_lua(reply)]),
+    }
+}
+
+// redis.pcall hands error replies back as {err=...} tables
+fn redis_pcall(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    Ok(vec![reply_to_lua(call_host(interp, args)?)])
+}
+
+fn redis_error_reply(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    Ok(vec![status_table("err", check_bytes(interp, &args, 0, "error_reply")?)])
+}
+
+fn redis_status_reply(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    Ok(vec![status_table("ok", check_bytes(interp, &args, 0, "status_reply")?)])
+}
+
+fn redis_sha1hex(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    if args.len() != 1 {
+        return Err(interp.error("wrong number of arguments"));
+    }
+    Ok(vec![Value::str(sha1_hex(&check_bytes(interp, &args, 0, "sha1hex")?))])
+}
+
+fn redis_log(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    if args.len() < 2 {
+        return Err(interp.error("redis.log() requires two arguments or more."));
+    }
+    let level = check_number(interp, &args, 0, "log")?;
+    if !(0.0..=3.0).contains(&level) {
+        return Err(interp.error("Invalid debug level."));
+    }
+    let mut message = Vec::new();
+    for i in 1..args.len() {
+        if i > 1 {
+            message.push(b' ');
+        }
+        message.extend(arg(&args, i).to_display());
+    }
+    println!("{}", String::from_utf8_lossy(&message));
+    Ok(Vec::new())
+}
+
+pub fn redis_library() -> Table {
+    let functions: &[(&'static str, Builtin)] = &[
+        ("call", redis_call),
+        ("pcall", redis_pcall),
+        ("error_reply", redis_error_reply),
+        ("status_reply", redis_status_reply),
+        ("log", redis_log),
+        ("sha1hex", redis_sha1hex),
+    ];
+    let mut redis = library(functions);
+    for (level, name) in ["LOG_DEBUG", "LOG_VERBOSE", "LOG_NOTICE", "LOG_WARNING"].iter().enumerate() {
+        redis.set_str(name, Value::Number(level as f64));
+    }
+    redis
+}
+
+// KEYS and ARGV as Lua arrays
+pub fn string_array(items: Vec<Vec<u8>>) -> Value {
+    Value::table(Table::from_array(items.into_iter().map(Value::str).collect()))
+}
+
+// Scripts are stopped after busy-reply-threshold milliseconds, unless that is 0
+pub fn limit_time(interp: &mut Interpreter, millis: u64) {
+    if millis > 0 {
+        interp.set_time_limit(Duration::from_millis(millis));
+    }
+}
+
+// Errors raised by a script or function, positioned at the line that raised them
+pub fn script_error(err: &LuaError, name: &str, chunk_name: &str, line: usize) -> DataType {
+    let message = match err.value {
+        // Error replies from redis.call keep their own error code
+        Value::Table(_) => err.message(),
+        _ => format!("ERR {}", err.message()),
+    };
+    DataType::SimpleError(format!("{} script: {}, on @{}:{}.", message, name, chunk_name, line))
+}
+
+impl State {
+    // Compile a script into the cache unless it is already there, returning its SHA1
+    fn cache_script(&mut self, source: &[u8]) -> Result<(String, Arc<FunctionBody>), DataType> {
+        let sha = sha1_hex(source);
+        let chunk = match self.scripts.get(&sha) {
+            Some(chunk) => chunk.clone(),
+            None => {
+                let chunk = compile(source)?;
+                self.scripts.insert(sha.clone(), chunk.clone());
+                chunk
+            }
+        };
+        Ok((sha, chunk))
+    }
+
+    pub fn eval(&mut self, source: &[u8], keys: Vec<Vec<u8>>, argv: Vec<Vec<u8>>, read_only: bool) -> CommandResult {
+        let (sha, chunk) = self.cache_script(source)?;
+        self.run_script(&sha, chunk, keys, argv, read_only)
+    }
+
+    pub fn script_load(&mut self, source: &[u8]) -> CommandResult {
+        let (sha, _) = self.cache_script(source)?;
+        Ok(DataType::BulkString(sha.into_bytes()))
+    }
+
+    pub fn script_exists(&self, shas: &[String]) -> DataType {
+        DataType::Array(shas.iter().map(|sha| DataType::Integer(self.scripts.contains_key(sha) as i64)).collect())
+    }
+
+    pub fn evalsha(&mut self, sha: &str, keys: Vec<Vec<u8>>, argv: Vec<Vec<u8>>, read_only: bool) -> CommandResult {
+        let Some(chunk) = self.scripts.get(sha).cloned() else {
+            return Err(DataType::SimpleError("NOSCRIPT No matching script. Please use EVAL.".to_string()));
+        };
+        self.run_script(sha, chunk, keys, argv, read_only)
+    }
+
+    // Scripts run to completion while the caller holds the state, so they are atomic with
+    // respect to other connections
+    fn run_script(&mut self, sha: &str, chunk: Arc<FunctionBody>, keys: Vec<Vec<u8>>, argv: Vec<Vec<u8>>, read_only: bool) -> CommandResult {
+        let time_limit = self.config.server.busy_reply_threshold;
+        let mut host = ScriptHost::new(self, read_only);
+        host.declare_keys(&keys);
+        let mut interp = Interpreter::new(&mut host, CHUNK_NAME);
+        limit_time(&mut interp, time_limit);
+        interp.set_global("redis", Value::table(redis_library()));
+        interp.set_global("KEYS", string_array(keys));
+        interp.set_global("ARGV", string_array(argv));
+        interp.protect_globals();
+        let result = match interp.run(chunk) {
+            Ok(values) => Ok(lua_to_reply(values.first().unwrap_or(&Value::Nil))),
+            Err(err) => Err(script_error(&err, sha, CHUNK_NAME, interp.line())),
+        };
+        drop(interp);
+        host.propagate_effects();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Replies with whatever its first argument names
+    struct CannedHost;
+
+    impl Host for CannedHost {
+        fn call(&mut self, args: Vec<Vec<u8>>) -> DataType {
+            match &args[0][..] {
+                b"status" => DataType::SimpleString("OK".to_string()),
+                b"error" => DataType::SimpleError("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
+                b"integer" => DataType::Integer(42),
+                b"bulk" => DataType::BulkString(b"value".to_vec()),
+                b"null" => DataType::NullBulkString,
+                b"array" => DataType::Array(vec![DataType::BulkString(b"a".to_vec()), DataType::Integer(1), DataType::NullBulkString]),
+                b"map" => DataType::Map(vec![(DataType::BulkString(b"field".to_vec()), DataType::Integer(2))]),
+                // The arguments as the host got them
+                _ => DataType::bulk_array(args),
+            }
+        }
+    }
+
+    // The reply a script gives, as EVAL would send it
+    fn eval(source: &str) -> DataType {
+        let chunk = compile(source.as_bytes()).unwrap();
+        let mut host = CannedHost;
+        let mut interp = Interpreter::new(&mut host, CHUNK_NAME);
+        interp.set_global("redis", Value::table(redis_library()));
+        interp.protect_globals();
+        match interp.run(chunk) {
+            Ok(values) => lua_to_reply(values.first().unwrap_or(&Value::Nil)),
+            Err(err) => script_error(&err, "test", CHUNK_NAME, interp.line()),
+        }
+    }
+
+    fn bulk(s: &str) -> DataType {
+        DataType::BulkString(s.as_bytes().to_vec())
+    }
+
+    fn error(s: &str) -> DataType {
+        DataType::SimpleError(s.to_string())
+    }
+
+    #[test]
+    fn replies_convert_to_lua() {
+        assert_eq!(eval("return redis.call('status').ok"), bulk("OK"));
+        assert_eq!(eval("return redis.call('integer') + 1"), DataType::Integer(43));
+        assert_eq!(eval("return type(redis.call('integer'))"), bulk("number"));
+        assert_eq!(eval("return redis.call('bulk')"), bulk("value"));
+        assert_eq!(eval("return tostring(redis.call('null'))"), bulk("false"));
+        assert_eq!(eval("local items = redis.call('array') return {#items, items[1], items[2], tostring(items[3])}"),
+            DataType::Array(vec![DataType::Integer(3), bulk("a"), DataType::Integer(1), bulk("false")]));
+        assert_eq!(eval("return redis.call('map')"), DataType::Array(vec![bulk("field"), DataType::Integer(2)]));
+        assert_eq!(eval("return redis.call('echo', 'x', 7, 1.5)"), DataType::Array(vec![bulk("echo"), bulk("x"), bulk("7"), bulk("1.5")]));
+    }
+
+    #[test]
+    fn lua_values_convert_to_replies() {
+        assert_eq!(eval("return 3.99"), DataType::Integer(3));
+        assert_eq!(eval("return -2.5"), DataType::Integer(-2));
+        assert_eq!(eval("return true"), DataType::Integer(1));
+        assert_eq!(eval("return false"), DataType::NullBulkString);
+        assert_eq!(eval("return nil"), DataType::NullBulkString);
+        assert_eq!(eval("return 'text'"), bulk("text"));
+        assert_eq!(eval("return redis.status_reply('FINE')"), DataType::SimpleString("FINE".to_string()));
+        assert_eq!(eval("return {ok = 'FINE'}"), DataType::SimpleString("FINE".to_string()));
+        assert_eq!(eval("return redis.error_reply('MY failure')"), error("MY failure"));
+        // Arrays stop at the first nil, and keys that aren't positions are dropped
+        assert_eq!(eval("return {1, 'two', {3}, nil, 5, x = 6}"),
+            DataType::Array(vec![DataType::Integer(1), bulk("two"), DataType::Array(vec![DataType::Integer(3)])]));
+    }
+
+    #[test]
+    fn call_raises_error_replies() {
+        assert_eq!(eval("redis.call('error') return 'unreached'"),
+            error("WRONGTYPE Operation against a key holding the wrong kind of value script: test, on @user_script:1."));
+        // They can be caught as tables holding the error
+        assert_eq!(eval("local ok, err = pcall(redis.call, 'error') return {tostring(ok), err.err}"),
+            DataType::Array(vec![bulk("false"), bulk("WRONGTYPE Operation against a key holding the wrong kind of value")]));
+        assert_eq!(eval("redis.call()"),
+            error("ERR user_script:1: Please specify at least one argument for this redis lib call script: test, on @user_script:1."));
+        assert_eq!(eval("redis.call('echo', {})"),
+            error("ERR user_script:1: Lua redis lib command arguments must be strings or integers script: test, on @user_script:1."));
+    }
+
+    #[test]
+    fn pcall_returns_error_replies() {
+        assert_eq!(eval("local reply = redis.pcall('error') return reply.err"), bulk("WRONGTYPE Operation against a key holding the wrong kind of value"));
+        assert_eq!(eval("return redis.pcall('error')"), error("WRONGTYPE Operation against a key holding the wrong kind of value"));
+        assert_eq!(eval("return redis.pcall('integer')"), DataType::Integer(42));
+    }
+}
@@ -0,0 +1,363 @@
+use std::{os::unix::prelude::OsStrExt, path::PathBuf};
+
+use tokio::{
+    task,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    aof,
+    client,
+    commands::stream::unix_time_ms,
+    config::Config,
+    command::{not_an_integer, parse_integer_arg, syntax_error, wrong_number_of_args, Command},
+    glob::glob_match,
+    rdb,
+    resp::DataType,
+    state::{CommandResult, State},
+};
+
+// Version reported to clients, the Redis release whose behaviour the server follows
+pub const REDIS_VERSION: &str = "7.4.0";
+
+// Where SAVE writes when no --dir was given, relative to the working directory as in Redis
+const DEFAULT_DBFILENAME: &str = "dump.rdb";
+
+const BGSAVE_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+impl Command {
+    // HELLO [protover [AUTH username password] [SETNAME clientname]]
+    pub fn parse_hello(args: &[Vec<u8>]) -> Command {
+        let Some(protover) = args.get(1) else {
+            return Command::HELLO(None, None);
+        };
+        let Some(protocol) = parse_integer_arg::<u8>(protover) else {
+            return Command::INVALID("ERR Protocol version is not an integer or out of range".to_string());
+        };
+        let mut auth = None;
+        let mut i = 2;
+        while let Some(arg) = args.get(i) {
+            let option = String::from_utf8_lossy(arg).to_lowercase();
+            match option.as_str() {
+                "auth" if i + 2 < args.len() => {
+                    auth = Some((args[i + 1].clone(), args[i + 2].clone()));
+                    i += 3;
+                }
+                // Connections don't carry names yet, so the name is accepted and dropped
+                "setname" if i + 1 < args.len() => i += 2,
+                _ => return Command::INVALID(format!("ERR Syntax error in HELLO option '{}'", option)),
+            }
+        }
+        Command::HELLO(Some(protocol), auth)
+    }
+
+    // INFO [section ...], with no sections meaning the default set
+    pub fn parse_info(args: &[Vec<u8>]) -> Command {
+        Command::INFO(args[1..].iter().map(|section| String::from_utf8_lossy(section).to_lowercase()).collect())
+    }
+
+    pub fn parse_save(args: &[Vec<u8>]) -> Command {
+        if args.len() != 1 {
+            return wrong_number_of_args("save");
+        }
+        Command::SAVE
+    }
+
+    pub fn parse_lastsave(args: &[Vec<u8>]) -> Command {
+        if args.len() != 1 {
+            return wrong_number_of_args("lastsave");
+        }
+        Command::LASTSAVE
+    }
+
+    // BGSAVE [SCHEDULE]. Scheduling only matters while an AOF rewrite runs, which can run
+    // alongside a save here, so it is accepted and otherwise ignored.
+    pub fn parse_bgsave(args: &[Vec<u8>]) -> Command {
+        match args.len() {
+            1 => Command::BGSAVE,
+            2 if args[1].eq_ignore_ascii_case(b"schedule") => Command::BGSAVE,
+            _ => syntax_error(),
+        }
+    }
+
+    pub fn parse_bgrewriteaof(args: &[Vec<u8>]) -> Command {
+        if args.len() != 1 {
+            return wrong_number_of_args("bgrewriteaof");
+        }
+        Command::BGREWRITEAOF
+    }
+
+    // WAITAOF numlocal numreplicas timeout, the timeout in milliseconds with zero meaning forever
+    pub fn parse_waitaof(args: &[Vec<u8>]) -> Command {
+        if args.len() != 4 {
+            return wrong_number_of_args("waitaof");
+        }
+        let (Some(numlocal), Some(numreplicas)) = (parse_integer_arg::<u64>(&args[1]), parse_integer_arg::<u64>(&args[2])) else {
+            return not_an_integer();
+        };
+        let timeout = match parse_integer_arg::<i64>(&args[3]) {
+            Some(timeout) if timeout < 0 => return Command::INVALID("ERR timeout is negative".to_string()),
+            Some(timeout) => (timeout > 0).then(|| Duration::from_millis(timeout as u64)),
+            None => return Command::INVALID("ERR timeout is not an integer or out of range".to_string()),
+        };
+        Command::WAITAOF(numlocal, numreplicas, timeout)
+    }
+
+    pub fn parse_config(args: &[Vec<u8>]) -> Command {
+        if args.len() < 2 {
+            return wrong_number_of_args("config");
+        }
+        let subcommand = String::from_utf8_lossy(&args[1]).to_lowercase();
+        match subcommand.as_str() {
+            "get" if args.len() >= 3 => Command::CONFIGGET(args[2..].iter().map(|pattern| pattern.to_ascii_lowercase()).collect()),
+            "set" if args.len() >= 4 && args[2..].chunks_exact(2).remainder().is_empty() => {
+                let pairs = args[2..].chunks_exact(2).map(|pair| (pair[0].to_ascii_lowercase(), pair[1].clone()));
+                Command::CONFIGSET(pairs.collect())
+            }
+            "get" | "set" => wrong_number_of_args(&format!("config|{}", subcommand)),
+            _ => Command::INVALID(format!("ERR unknown subcommand '{}'. Try CONFIG HELP.", subcommand)),
+        }
+    }
+}
+
+impl State {
+    pub fn dump_path(&self) -> PathBuf {
+        self.rdb_path.clone().unwrap_or_else(|| PathBuf::from(DEFAULT_DBFILENAME))
+    }
+
+    fn bgsave_in_progress() -> DataType {
+        DataType::SimpleError("ERR Background save already in progress".to_string())
+    }
+
+    // Count a background save as running from now, unless one already is. Whoever starts it
+    // finishes it through the save status.
+    pub fn start_bgsave(&mut self) -> bool {
+        let mut status = self.save_status.lock().unwrap();
+        if status.bgsave_started.is_some() {
+            return false;
+        }
+        status.bgsave_started = Some(Instant::now());
+        status.last_bgsave_try = status.bgsave_started;
+        status.bgsave_dirty = self.dirty;
+        true
+    }
+
+    // Write the dataset to the dump file, replying once it is on disk
+    pub fn save(&mut self) -> CommandResult {
+        if self.save_status.lock().unwrap().bgsave_started.is_some() {
+            return Err(State::bgsave_in_progress());
+        }
+        let path = self.dump_path();
+        match rdb::write_file(&path, &rdb::serialize(&self.datastore, &self.libraries, self.config.rdb.checksum)) {
+            Ok(()) => {
+                self.save_status.lock().unwrap().saved(self.dirty);
+                Ok(DataType::ok())
+            }
+            Err(e) => {
+                println!("Failed saving {}: {}", path.display(), e);
+                Err(DataType::SimpleError("ERR".to_string()))
+            }
+        }
+    }
+
+    // Snapshot the dataset under the lock, which only shares the datastore's structure rather
+    // than copying it, then encode and write the snapshot on a blocking task so other clients
+    // carry on meanwhile
+    pub fn bgsave(&mut self) -> CommandResult {
+        if !self.start_bgsave() {
+            return Err(State::bgsave_in_progress());
+        }
+
+        let (datastore, libraries) = (self.datastore.clone(), self.libraries.clone());
+        let (path, status, checksum) = (self.dump_path(), self.save_status.clone(), self.config.rdb.checksum);
+        task::spawn_blocking(move || {
+            let result = rdb::write_file(&path, &rdb::serialize(&datastore, &libraries, checksum));
+            if let Err(e) = &result {
+                println!("Background save to {} failed: {}", path.display(), e);
+            }
+            status.lock().unwrap().finish_bgsave(result.is_ok());
+        });
+        Ok(DataType::SimpleString("Background saving started".to_string()))
+    }
+
+    // Rewrite the AOF from a snapshot of the dataset in the background. While the AOF is on,
+    // the writer moves on to a new incr file at the snapshot, which the rewritten base then
+    // replaces everything before.
+    pub fn bgrewriteaof(&mut self) -> CommandResult {
+        let mut status = self.aof_status.lock().unwrap();
+        if status.rewrite_started.is_some() {
+            return Err(DataType::SimpleError("ERR Background append only file rewriting already in progress".to_string()));
+        }
+        status.rewrite_started = Some(Instant::now());
+        drop(status);
+
+        let (datastore, libraries) = (self.datastore.clone(), self.libraries.clone());
+        let (aof, status) = (self.aof_dir(), self.aof_status.clone());
+        let options = aof::RewriteOptions { rdb_preamble: self.config.aof.use_rdb_preamble, checksum: self.config.rdb.checksum };
+        // Writes of a transaction this runs in are already in the snapshot, so they go out first
+        self.flush_propagation();
+        let rotate = self.aof_writer.clone().map(|writer| (writer, self.propagation.offset(), self.propagation.subscribe()));
+        tokio::spawn(async move {
+            let result = aof::rewrite(aof, rotate, datastore, libraries, options).await;
+            if let Err(e) = &result {
+                println!("Background AOF rewrite failed: {}", e);
+            }
+            status.lock().unwrap().finish_rewrite(result.is_ok());
+        });
+        Ok(DataType::SimpleString("Background append only file rewriting started".to_string()))
+    }
+
+    // Start a background save once any save rule is met. After a failed save, wait a while
+    // before trying again rather than failing over and over.
+    pub fn run_save_rules(&mut self) {
+        let status = self.save_status.lock().unwrap();
+        if status.bgsave_started.is_some() {
+            return;
+        }
+        if !status.last_bgsave_ok && status.last_bgsave_try.is_some_and(|tried| tried.elapsed() < BGSAVE_RETRY_DELAY) {
+            return;
+        }
+        let changes = self.dirty - status.dirty_at_last_save;
+        let elapsed = (unix_time_ms() / 1000).saturating_sub(status.last_save_time);
+        let due = self.config.save.0.iter().any(|(seconds, min_changes)| changes >= *min_changes && elapsed >= *seconds);
+        drop(status);
+        if due {
+            let _ = self.bgsave();
+        }
+    }
+
+    // Every parameter matching any of the patterns, as a flat name/value array
+    pub fn config_get(&mut self, patterns: &[Vec<u8>]) -> CommandResult {
+        let mut parameters: Vec<(String, Vec<u8>)> = vec![];
+        if let Some(rdbpath) = self.rdb_path.as_ref() {
+            let dir = rdbpath.parent().map_or(&[][..], |dir| dir.as_os_str().as_bytes());
+            let filename = rdbpath.file_name().map_or(&[][..], |filename| filename.as_bytes());
+            parameters.push(("dir".to_string(), dir.to_vec()));
+            parameters.push(("dbfilename".to_string(), filename.to_vec()));
+        }
+        let mut items = vec![];
+        for pattern in patterns {
+            let matches = parameters.iter()
+                .filter(|(name, _)| glob_match(pattern, name.as_bytes()))
+                .cloned()
+                .chain(self.config.matching(pattern).into_iter().map(|(name, value)| (name, value.into_bytes())));
+            for (name, value) in matches {
+                if !items.iter().any(|(seen, _)| *seen == name) {
+                    items.push((name, value));
+                }
+            }
+        }
+        Ok(DataType::bulk_array(items.into_iter().flat_map(|(name, value)| [name.into_bytes(), value])))
+    }
+
+    // Parameters are applied in order on a copy, so a rejected value leaves the config untouched
+    pub fn config_set(&mut self, pairs: &[(Vec<u8>, Vec<u8>)]) -> CommandResult {
+        let mut config = self.config.clone();
+        for (name, value) in pairs {
+            let name = String::from_utf8_lossy(name);
+            if self.config.get(&name).is_none() {
+                return Err(DataType::SimpleError(format!("ERR Unknown option or number of arguments for CONFIG SET - '{}'", name)));
+            }
+            if Config::IMMUTABLE.contains(&name.as_ref()) {
+                return Err(DataType::SimpleError(format!("ERR CONFIG SET failed (possibly related to argument '{}') - can't set immutable config", name)));
+            }
+            if let Err(msg) = config.set(&name, &String::from_utf8_lossy(value)) {
+                return Err(DataType::SimpleError(format!("ERR CONFIG SET failed (possibly related to argument '{}') - {}", name, msg)));
+            }
+        }
+        self.config = config;
+        // The AOF writer picks up a new fsync policy from its status
+        self.aof_status.lock().unwrap().set_policy(&self.config.aof);
+        self.propagation.resize_backlog(self.config.replication.backlog_size);
+        Ok(DataType::ok())
+    }
+
+    // Fields of one INFO section
+    fn info_section(&self, section: &str) -> Vec<(&'static str, String)> {
+        match section {
+            "server" => vec![
+                ("redis_version", REDIS_VERSION.to_string()),
+                ("redis_mode", if self.cluster.is_some() { "cluster" } else { "standalone" }.to_string()),
+                ("process_id", std::process::id().to_string()),
+            ],
+            "clients" => vec![
+                ("connected_clients", self.clients.len().to_string()),
+                ("pubsub_clients", self.pubsub.subscriber_count().to_string()),
+                ("tracking_clients", self.tracking.client_count().to_string()),
+            ],
+            "persistence" => {
+                let status = self.save_status.lock().unwrap();
+                let aof = self.aof_status.lock().unwrap();
+                let seconds = |duration: Option<Duration>| duration.map_or("-1".to_string(), |duration| duration.as_secs().to_string());
+                vec![
+                    ("loading", "0".to_string()),
+                    ("rdb_changes_since_last_save", (self.dirty - status.dirty_at_last_save).to_string()),
+                    ("rdb_bgsave_in_progress", (status.bgsave_started.is_some() as u8).to_string()),
+                    ("rdb_last_save_time", status.last_save_time.to_string()),
+                    ("rdb_last_bgsave_status", if status.last_bgsave_ok { "ok" } else { "err" }.to_string()),
+                    ("rdb_last_bgsave_time_sec", seconds(status.last_bgsave_duration)),
+                    ("rdb_current_bgsave_time_sec", seconds(status.bgsave_started.map(|started| started.elapsed()))),
+                    ("aof_enabled", (self.config.aof.enabled as u8).to_string()),
+                    ("aof_rewrite_in_progress", (aof.rewrite_started.is_some() as u8).to_string()),
+                    ("aof_last_rewrite_time_sec", seconds(aof.last_rewrite_duration)),
+                    ("aof_current_rewrite_time_sec", seconds(aof.rewrite_started.map(|started| started.elapsed()))),
+                    ("aof_last_bgrewrite_status", if aof.last_rewrite_ok { "ok" } else { "err" }.to_string()),
+                    ("aof_last_write_status", if aof.last_write_ok { "ok" } else { "err" }.to_string()),
+                    ("aof_last_fsync", aof.last_fsync.to_string()),
+                    ("aof_pending_fsync", (aof.pending_fsync as u8).to_string()),
+                ]
+            }
+            "stats" => {
+                let (channels, patterns, shard_channels) = self.pubsub.registry_sizes();
+                vec![
+                    ("pubsub_channels", channels.to_string()),
+                    ("pubsub_patterns", patterns.to_string()),
+                    ("pubsub_shardchannels", shard_channels.to_string()),
+                    ("client_output_buffer_limit_disconnections", client::output_buffer_disconnections().to_string()),
+                ]
+            }
+            "cluster" => vec![("cluster_enabled", (self.cluster.is_some() as u8).to_string())],
+            "keyspace" => {
+                let expires = self.datastore.values().filter(|value| value.expiry.is_some()).count();
+                match self.datastore.len() {
+                    0 => Vec::new(),
+                    keys => vec![("db0", format!("keys={},expires={},avg_ttl=0", keys, expires))],
+                }
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // Sections are always listed in the same order, whatever order they were asked for in
+    pub fn info(&self, sections: &[String]) -> DataType {
+        const ALL: &[&str] = &["server", "clients", "persistence", "stats", "replication", "cluster", "keyspace"];
+        let mut names: Vec<&str> = Vec::new();
+        for section in sections {
+            match section.as_str() {
+                "default" | "all" | "everything" => names.extend(ALL),
+                section => names.push(section),
+            }
+        }
+        if sections.is_empty() {
+            names.extend(ALL);
+        }
+        let mut info = String::new();
+        for name in ALL.iter().filter(|name| names.contains(name)) {
+            if !info.is_empty() {
+                info.push_str("\r\n");
+            }
+            let mut title = name.to_string();
+            title[..1].make_ascii_uppercase();
+            info.push_str(&format!("# {}\r\n", title));
+            // Replication lists each replica under a field of its own
+            let fields = match *name {
+                "replication" => self.replication_info(),
+                name => self.info_section(name).into_iter().map(|(field, value)| (field.to_string(), value)).collect(),
+            };
+            for (field, value) in fields {
+                info.push_str(&format!("{}:{}\r\n", field, value));
+            }
+        }
+        DataType::BulkString(info.into_bytes())
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::HashSet;
+
+use crate::{
+    command::{not_an_integer, parse_integer_arg, parse_numkeys, parse_random_count, syntax_error, wrong_number_of_args, Command},
+    commands::{parse_cursor, scan_items, scan_reply, ScanOptions},
+    notify::{NOTIFY_GENERIC, NOTIFY_SET},
+    random::{random_index, sample_distinct, sample_with_repeats, REPLY_TOO_LARGE},
+    resp::DataType,
+    state::{CommandResult, DataStoreValue, State, Value},
+    types::set::Set,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub enum SetOperation {
+    Inter,
+    Union,
+    Diff,
+}
+
+pub fn set_reply(set: HashSet<Vec<u8>>) -> DataType {
+    DataType::bulk_array(set)
+}
+
+impl Command {
+    pub fn parse_set_members(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 {
+            return wrong_number_of_args(name);
+        }
+        let (key, members) = (args[1].clone(), args[2..].to_vec());
+        match name {
+            "sadd" => Command::SADD(key, members),
+            _ => Command::SREM(key, members),
+        }
+    }
+
+    pub fn parse_set_key(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() != 2 {
+            return wrong_number_of_args(name);
+        }
+        let key = args[1].clone();
+        match name {
+            "smembers" => Command::SMEMBERS(key),
+            _ => Command::SCARD(key),
+        }
+    }
+
+    pub fn parse_sismember(args: &[Vec<u8>]) -> Command {
+        if args.len() != 3 {
+            return wrong_number_of_args("sismember");
+        }
+        Command::SISMEMBER(args[1].clone(), args[2].clone())
+    }
+
+    pub fn parse_set_algebra(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() < 2 {
+            return wrong_number_of_args(name);
+        }
+        let keys = args[1..].to_vec();
+        match name {
+            "sinter" => Command::SINTER(keys),
+            "sunion" => Command::SUNION(keys),
+            _ => Command::SDIFF(keys),
+        }
+    }
+
+    pub fn parse_set_algebra_store(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 {
+            return wrong_number_of_args(name);
+        }
+        let (destination, keys) = (args[1].clone(), args[2..].to_vec());
+        match name {
+            "sinterstore" => Command::SINTERSTORE(destination, keys),
+            "sunionstore" => Command::SUNIONSTORE(destination, keys),
+            _ => Command::SDIFFSTORE(destination, keys),
+        }
+    }
+
+    pub fn parse_sintercard(args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 {
+            return wrong_number_of_args("sintercard");
+        }
+        let (keys, i) = match parse_numkeys(args, 1) {
+            Ok(parsed) => parsed,
+            Err(err) => return err,
+        };
+        let limit = match &args[i..] {
+            [] => 0,
+            [option, limit] if option.eq_ignore_ascii_case(b"limit") => match parse_integer_arg::<i64>(limit) {
+                Some(limit) if limit >= 0 => limit as usize,
+                Some(_) => return Command::INVALID("ERR LIMIT can't be negative".to_string()),
+                None => return not_an_integer(),
+            },
+            _ => return syntax_error(),
+        };
+        Command::SINTERCARD(keys, limit)
+    }
+
+    pub fn parse_set_random(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() != 2 && args.len() != 3 {
+            return wrong_number_of_args(name);
+        }
+        let count = match args.get(2).map(|count| parse_random_count(count)).transpose() {
+            Ok(count) => count,
+            Err(err) => return err,
+        };
+        match (name, count) {
+            ("spop", Some(count)) if count < 0 => Command::INVALID("ERR value is out of range, must be positive".to_string()),
+            ("spop", count) => Command::SPOP(args[1].clone(), count.map(|count| count as usize)),
+            (_, count) => Command::SRANDMEMBER(args[1].clone(), count),
+        }
+    }
+
+    pub fn parse_smove(args: &[Vec<u8>]) -> Command {
+        if args.len() != 4 {
+            return wrong_number_of_args("smove");
+        }
+        Command::SMOVE(args[1].clone(), args[2].clone(), args[3].clone())
+    }
+
+    pub fn parse_sscan(args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 {
+            return wrong_number_of_args("sscan");
+        }
+        let cursor = match parse_cursor(&args[2]) {
+            Ok(cursor) => cursor,
+            Err(err) => return err,
+        };
+        match ScanOptions::parse(&args[3..], false) {
+            Ok(options) => Command::SSCAN(args[1].clone(), cursor, options),
+            Err(err) => err,
+        }
+    }
+
+    pub fn parse_smismember(args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 {
+            return wrong_number_of_args("smismember");
+        }
+        Command::SMISMEMBER(args[1].clone(), args[2..].to_vec())
+    }
+}
+
+impl State {
+    pub fn sadd(&mut self, key: &[u8], members: Vec<Vec<u8>>) -> CommandResult {
+        let limits = self.config.limits;
+        let set = self.get_or_create_set(key)?;
+        let added = members.into_iter().filter(|member| set.insert(member.clone(), &limits)).count();
+        if added > 0 {
+            self.notify_keyspace_event(NOTIFY_SET, "sadd", key);
+        }
+        Ok(DataType::Integer(added as i64))
+    }
+
+    pub fn srem(&mut self, key: &[u8], members: &[Vec<u8>]) -> CommandResult {
+        let set = match self.get_set(key)? {
+            Some(set) => set,
+            None => return Ok(DataType::Integer(0)),
+        };
+        let removed = members.iter().filter(|member| set.remove(member)).count();
+        if removed > 0 {
+            self.notify_keyspace_event(NOTIFY_SET, "srem", key);
+        }
+        self.remove_if_empty(key);
+        Ok(DataType::Integer(removed as i64))
+    }
+
+    pub fn smembers(&mut self, key: &[u8]) -> CommandResult {
+        let set = self.get_set(key)?;
+        Ok(DataType::bulk_array(set.map(|set| set.members()).unwrap_or_default()))
+    }
+
+    pub fn sismember(&mut self, key: &[u8], member: &[u8]) -> CommandResult {
+        let exists = self.get_set(key)?.is_some_and(|set| set.contains(member));
+        Ok(DataType::Integer(exists as i64))
+    }
+
+    pub fn scard(&mut self, key: &[u8]) -> CommandResult {
+        let len = self.get_set(key)?.map_or(0, |set| set.len());
+        Ok(DataType::Integer(len as i64))
+    }
+
+    // Compute an intersection, union or difference, treating missing keys as empty sets
+    pub fn set_algebra(&mut self, keys: &[Vec<u8>], operation: SetOperation) -> Result<HashSet<Vec<u8>>, DataType> {
+        let mut sets = Vec::with_capacity(keys.len());
+        for key in keys {
+            let members = self.get_set(key)?.map(|set| set.members()).unwrap_or_default();
+            sets.push(members.into_iter().collect::<HashSet<Vec<u8>>>());
+        }
+        let mut sets = sets.into_iter();
+        let mut result = sets.next().unwrap_or_default();
+        for set in sets {
+            match operation {
+                SetOperation::Inter => result.retain(|member| set.contains(member)),
+                SetOperation::Union => result.extend(set),
+                SetOperation::Diff => result.retain(|member| !set.contains(member)),
+            }
+        }
+        Ok(result)
+    }
+
+    // The destination is overwritten whatever its type, or deleted if the result is empty
+    pub fn set_algebra_store(&mut self, destination: Vec<u8>, keys: &[Vec<u8>], operation: SetOperation) -> CommandResult {
+        let result = self.set_algebra(keys, operation)?;
+        let len = result.len();
+        if result.is_empty() {
+            if self.datastore.remove(&destination).is_some() {
+                self.notify_keyspace_event(NOTIFY_GENERIC, "del", &destination);
+            }
+        } else {
+            self.datastore.insert(destination.clone(), DataStoreValue::new(Value::Set(Set::from_members(result, &self.config.limits)), None));
+            let event = match operation {
+                SetOperation::Inter => "sinterstore",
+                SetOperation::Union => "sunionstore",
+                SetOperation::Diff => "sdiffstore",
+            };
+            self.notify_keyspace_event(NOTIFY_SET, event, &destination);
+        }
+        Ok(DataType::Integer(len as i64))
+    }
+
+    // Cardinality of the intersection, stopping early once the limit is reached
+    pub fn sintercard(&mut self, keys: &[Vec<u8>], limit: usize) -> CommandResult {
+        let mut sets = Vec::with_capacity(keys.len());
+        for key in keys {
+            match self.get_set(key)? {
+                Some(set) => sets.push(set.clone()),
+                None => sets.push(Set::default()),
+            }
+        }
+        sets.sort_by_key(|set| set.len());
+        let (smallest, rest) = sets.split_first().unwrap();
+        let mut count = 0;
+        for member in smallest.members() {
+            if rest.iter().all(|set| set.contains(&member)) {
+                count += 1;
+                if count == limit {
+                    break;
+                }
+            }
+        }
+        Ok(DataType::Integer(count as i64))
+    }
+
+    pub fn spop(&mut self, key: &[u8], count: Option<usize>) -> CommandResult {
+        let set = match self.get_set(key)? {
+            Some(set) => set,
+            None if count.is_some() => return Ok(DataType::Array(vec![])),
+            None => return Ok(DataType::NullBulkString),
+        };
+        let picked = sample_distinct(set.members(), count.unwrap_or(1));
+        for member in picked.iter() {
+            set.remove(member);
+        }
+        if !picked.is_empty() {
+            self.notify_keyspace_event(NOTIFY_SET, "spop", key);
+        }
+        self.remove_if_empty(key);
+        Ok(match count {
+            Some(_) => DataType::bulk_array(picked),
+            None => DataType::BulkString(picked.into_iter().next().unwrap()),
+        })
+    }
+
+    // Random members: a positive count returns distinct members, a negative one allows repeats
+    pub fn srandmember(&mut self, key: &[u8], count: Option<i64>) -> CommandResult {
+        let set = match self.get_set(key)? {
+            Some(set) => set,
+            None if count.is_some() => return Ok(DataType::Array(vec![])),
+            None => return Ok(DataType::NullBulkString),
+        };
+        let members = set.members();
+        Ok(match count {
+            None => DataType::BulkString(members[random_index(members.len())].clone()),
+            Some(count) if count >= 0 => DataType::bulk_array(sample_distinct(members, count as usize)),
+            Some(count) => match sample_with_repeats(&members, count.unsigned_abs() as usize) {
+                Some(picked) => DataType::bulk_array(picked),
+                None => return Err(DataType::SimpleError(REPLY_TOO_LARGE.to_string())),
+            },
+        })
+    }
+
+    pub fn smove(&mut self, source: &[u8], destination: &[u8], member: Vec<u8>) -> CommandResult {
+        let exists = self.get_set(source)?.is_some_and(|set| set.contains(&member));
+        self.get_set(destination)?;
+        if !exists {
+            return Ok(DataType::Integer(0));
+        }
+        if source != destination {
+            let limits = self.config.limits;
+            self.get_set(source)?.unwrap().remove(&member);
+            self.notify_keyspace_event(NOTIFY_SET, "srem", source);
+            self.remove_if_empty(source);
+            if self.get_or_create_set(destination)?.insert(member, &limits) {
+                self.notify_keyspace_event(NOTIFY_SET, "sadd", destination);
+            }
+        }
+        Ok(DataType::Integer(1))
+    }
+
+    pub fn sscan(&mut self, key: &[u8], cursor: u64, options: &ScanOptions) -> CommandResult {
+        let set = match self.get_set(key)? {
+            Some(set) => set,
+            None => return Ok(scan_reply(0, vec![])),
+        };
+        let members = set.members();
+        let (cursor, members) = scan_items(members.iter().map(|member| (member.as_slice(), member)), cursor, options);
+        Ok(scan_reply(cursor, members.into_iter().map(|member| DataType::BulkString(member.clone())).collect()))
+    }
+
+    pub fn smismember(&mut self, key: &[u8], members: &[Vec<u8>]) -> CommandResult {
+        let set = self.get_set(key)?;
+        let replies = members.iter().map(|member| {
+            DataType::Integer(set.as_ref().is_some_and(|set| set.contains(member)) as i64)
+        });
+        Ok(DataType::Array(replies.collect()))
+    }
+}
@@ -0,0 +1,1015 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::time::Duration;
+
+use crate::{
+    command::{not_an_integer, parse_integer_arg, syntax_error, wrong_number_of_args, Command},
+    notify::NOTIFY_STREAM,
+    resp::DataType,
+    state::{CommandResult, State},
+    types::stream::{ClaimOptions, ConsumerGroup, Stream, StreamFields, StreamId, TrimOptions, TrimStrategy, STREAM_NODE_MAX_ENTRIES},
+};
+
+// ID argument of XADD: `*`, `ms-*` or a full explicit ID
+#[derive(Debug, Clone, Copy)]
+pub enum XaddId {
+    Auto,
+    Partial(u64),
+    Explicit(StreamId),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct XaddOptions {
+    nomkstream: bool,
+    trim: Option<TrimOptions>,
+}
+
+// Position XREAD reads after: an explicit ID, or `$` for whatever is the last entry when the
+// command is issued
+#[derive(Debug, Clone, Copy)]
+pub enum XreadId {
+    Last,
+    After(StreamId),
+}
+
+// Arguments of XREADGROUP. A `None` ID is `>`, asking for entries never delivered to the group,
+// while an explicit ID re-reads the consumer's own pending entries after it.
+#[derive(Debug, Clone)]
+pub struct XreadgroupArgs {
+    group: Vec<u8>,
+    consumer: Vec<u8>,
+    pub keys: Vec<Vec<u8>>,
+    ids: Vec<Option<StreamId>>,
+    count: usize,
+    noack: bool,
+}
+
+// Extended form of XPENDING, listing individual pending entries
+#[derive(Debug, Clone)]
+pub struct XpendingRange {
+    min_idle: u64,
+    start: StreamId,
+    end: StreamId,
+    count: usize,
+    consumer: Option<Vec<u8>>,
+}
+
+pub fn invalid_stream_id() -> Command {
+    Command::INVALID("ERR Invalid stream ID specified as stream command argument".to_string())
+}
+
+fn parse_xadd_id(arg: &[u8]) -> Option<XaddId> {
+    if arg == b"*" {
+        return Some(XaddId::Auto);
+    }
+    if let Some(ms) = arg.strip_suffix(b"-*") {
+        return Some(XaddId::Partial(std::str::from_utf8(ms).ok()?.parse().ok()?));
+    }
+    StreamId::parse(arg, 0).map(XaddId::Explicit)
+}
+
+// MAXLEN|MINID [=|~] threshold [LIMIT count] starting at args[i], returning the index of the
+// first argument after it
+fn parse_trim_options(args: &[Vec<u8>], mut i: usize) -> Result<(TrimOptions, usize), Command> {
+    let maxlen = args[i].eq_ignore_ascii_case(b"maxlen");
+    let approximate = args.get(i + 1).is_some_and(|arg| arg == b"~");
+    if approximate || args.get(i + 1).is_some_and(|arg| arg == b"=") {
+        i += 1;
+    }
+    i += 1;
+    let threshold = args.get(i).ok_or_else(syntax_error)?;
+    let strategy = if maxlen {
+        match parse_integer_arg::<i64>(threshold) {
+            Some(maxlen) if maxlen >= 0 => TrimStrategy::MaxLen(maxlen as usize),
+            Some(_) => return Err(Command::INVALID("ERR The MAXLEN argument must be >= 0.".to_string())),
+            None => return Err(not_an_integer()),
+        }
+    } else {
+        TrimStrategy::MinId(StreamId::parse(threshold, 0).ok_or_else(invalid_stream_id)?)
+    };
+    i += 1;
+    let mut limit = if approximate { 100 * STREAM_NODE_MAX_ENTRIES } else { 0 };
+    if args.get(i).is_some_and(|arg| arg.eq_ignore_ascii_case(b"limit")) {
+        limit = match args.get(i + 1).and_then(|arg| parse_integer_arg::<i64>(arg)) {
+            Some(limit) if limit >= 0 => limit as usize,
+            Some(_) => return Err(Command::INVALID("ERR The LIMIT argument must be >= 0.".to_string())),
+            None => return Err(not_an_integer()),
+        };
+        if !approximate {
+            return Err(Command::INVALID("ERR syntax error, LIMIT cannot be used without the special ~ option".to_string()));
+        }
+        i += 2;
+    }
+    Ok((TrimOptions { strategy, approximate, limit }, i))
+}
+
+pub fn unix_time_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+// Range bound of XRANGE: `-` and `+` are the extremes, a bare millisecond time covers every
+// sequence number in it and `(` makes the bound exclusive
+fn parse_range_bound(arg: &[u8], is_start: bool) -> Result<StreamId, Command> {
+    match arg {
+        b"-" => return Ok(StreamId::MIN),
+        b"+" => return Ok(StreamId::MAX),
+        _ => (),
+    }
+    let (arg, exclusive) = match arg.strip_prefix(b"(") {
+        Some(arg) => (arg, true),
+        None => (arg, false),
+    };
+    let id = StreamId::parse(arg, if is_start { 0 } else { u64::MAX }).ok_or_else(invalid_stream_id)?;
+    if !exclusive {
+        return Ok(id);
+    }
+    let bound = if is_start { id.next() } else { id.prev() };
+    bound.ok_or_else(|| {
+        let which = if is_start { "start" } else { "end" };
+        Command::INVALID(format!("ERR invalid {} ID for the interval", which))
+    })
+}
+
+fn no_group(key: &[u8], group: &[u8], suffix: &str) -> DataType {
+    DataType::SimpleError(format!(
+        "NOGROUP No such key '{}' or consumer group '{}'{}",
+        String::from_utf8_lossy(key),
+        String::from_utf8_lossy(group),
+        suffix,
+    ))
+}
+
+fn parse_idle_arg(arg: &[u8]) -> Result<u64, Command> {
+    parse_integer_arg::<i64>(arg).map(|ms| ms.max(0) as u64).ok_or_else(not_an_integer)
+}
+
+fn id_reply(id: StreamId) -> DataType {
+    DataType::BulkString(id.to_string().into_bytes())
+}
+
+// Flat [name, value, ...] reply used by the XINFO family
+fn info_reply(fields: Vec<(&str, DataType)>) -> DataType {
+    DataType::Array(fields.into_iter().flat_map(|(name, value)| [DataType::BulkString(name.as_bytes().to_vec()), value]).collect())
+}
+
+fn optional_integer(value: Option<u64>) -> DataType {
+    value.map_or(DataType::NullBulkString, |value| DataType::Integer(value as i64))
+}
+
+// Nested [id, [field, value, ...]] entry replies shared by the stream read commands
+pub fn entries_reply(entries: Vec<(StreamId, StreamFields)>) -> DataType {
+    DataType::Array(entries.iter().map(|(id, fields)| entry_reply(*id, Some(fields))).collect())
+}
+
+// Entries that have since been deleted are reported with nil fields
+fn entry_reply(id: StreamId, fields: Option<&StreamFields>) -> DataType {
+    let fields = match fields {
+        Some(fields) => DataType::bulk_array(fields.iter().flat_map(|(field, value)| [field.clone(), value.clone()])),
+        None => DataType::NullArray,
+    };
+    DataType::Array(vec![id_reply(id), fields])
+}
+
+impl Command {
+    // XGROUP CREATE key group id|$ [MKSTREAM] | XGROUP DESTROY key group
+    pub fn parse_xgroup(args: &[Vec<u8>]) -> Command {
+        let subcommand = match args.get(1) {
+            Some(subcommand) => String::from_utf8_lossy(subcommand).to_lowercase(),
+            None => return wrong_number_of_args("xgroup"),
+        };
+        match subcommand.as_str() {
+            "create" if args.len() == 5 || args.len() == 6 => {
+                let mkstream = match args.get(5) {
+                    Some(option) if option.eq_ignore_ascii_case(b"mkstream") => true,
+                    Some(_) => return syntax_error(),
+                    None => false,
+                };
+                let id = match args[4].as_slice() {
+                    b"$" => XreadId::Last,
+                    id => match StreamId::parse(id, 0) {
+                        Some(id) => XreadId::After(id),
+                        None => return invalid_stream_id(),
+                    },
+                };
+                Command::XGROUPCREATE(args[2].clone(), args[3].clone(), id, mkstream)
+            }
+            "destroy" if args.len() == 4 => Command::XGROUPDESTROY(args[2].clone(), args[3].clone()),
+            "create" | "destroy" => wrong_number_of_args(&format!("xgroup|{}", subcommand)),
+            _ => Command::INVALID(format!(
+                "ERR unknown subcommand '{}'. Try XGROUP HELP.",
+                String::from_utf8_lossy(&args[1])
+            )),
+        }
+    }
+
+    // XSETID key last-id [ENTRIESADDED entries-added] [MAXDELETEDID max-deleted-id]
+    pub fn parse_xsetid(args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 {
+            return wrong_number_of_args("xsetid");
+        }
+        let id = match StreamId::parse(&args[2], 0) {
+            Some(id) => id,
+            None => return invalid_stream_id(),
+        };
+        let (mut entries_added, mut max_deleted_id) = (None, None);
+        let mut i = 3;
+        while i < args.len() {
+            let value = match args.get(i + 1) {
+                Some(value) => value,
+                None => return syntax_error(),
+            };
+            if args[i].eq_ignore_ascii_case(b"entriesadded") {
+                entries_added = match parse_integer_arg::<i64>(value) {
+                    Some(added) if added >= 0 => Some(added as u64),
+                    Some(_) => return Command::INVALID("ERR entries_added must be positive".to_string()),
+                    None => return not_an_integer(),
+                };
+            } else if args[i].eq_ignore_ascii_case(b"maxdeletedid") {
+                match StreamId::parse(value, 0) {
+                    Some(max_deleted) if max_deleted > id => {
+                        return Command::INVALID(
+                            "ERR The ID specified in XSETID is smaller than the provided max_deleted_entry_id".to_string(),
+                        );
+                    }
+                    Some(max_deleted) => max_deleted_id = Some(max_deleted),
+                    None => return invalid_stream_id(),
+                }
+            } else {
+                return syntax_error();
+            }
+            i += 2;
+        }
+        Command::XSETID(args[1].clone(), id, entries_added, max_deleted_id)
+    }
+
+    // XINFO STREAM key [FULL [COUNT n]] | XINFO GROUPS key | XINFO CONSUMERS key group
+    pub fn parse_xinfo(args: &[Vec<u8>]) -> Command {
+        let subcommand = match args.get(1) {
+            Some(subcommand) => String::from_utf8_lossy(subcommand).to_lowercase(),
+            None => return wrong_number_of_args("xinfo"),
+        };
+        match (subcommand.as_str(), args.len()) {
+            ("stream", 3) => Command::XINFOSTREAM(args[2].clone(), None),
+            ("stream", 4..=6) => {
+                if !args[3].eq_ignore_ascii_case(b"full") {
+                    return syntax_error();
+                }
+                let count = match args.get(4..) {
+                    Some([option, count]) if option.eq_ignore_ascii_case(b"count") => match parse_integer_arg::<i64>(count) {
+                        Some(count) if count > 0 => count as usize,
+                        Some(_) => usize::MAX,
+                        None => return not_an_integer(),
+                    },
+                    Some([]) => 10,
+                    _ => return syntax_error(),
+                };
+                Command::XINFOSTREAM(args[2].clone(), Some(count))
+            }
+            ("groups", 3) => Command::XINFOGROUPS(args[2].clone()),
+            ("consumers", 4) => Command::XINFOCONSUMERS(args[2].clone(), args[3].clone()),
+            ("stream", _) | ("groups", _) | ("consumers", _) => wrong_number_of_args(&format!("xinfo|{}", subcommand)),
+            _ => Command::INVALID(format!(
+                "ERR unknown subcommand '{}'. Try XINFO HELP.",
+                String::from_utf8_lossy(&args[1])
+            )),
+        }
+    }
+
+    // XREADGROUP GROUP group consumer [COUNT n] [BLOCK ms] [NOACK] STREAMS key [key ...] id [id ...]
+    pub fn parse_xreadgroup(args: &[Vec<u8>]) -> Command {
+        if args.len() < 7 || !args[1].eq_ignore_ascii_case(b"group") {
+            return if args.len() < 7 { wrong_number_of_args("xreadgroup") } else { syntax_error() };
+        }
+        let (mut count, mut block, mut noack) = (usize::MAX, None, false);
+        let mut i = 4;
+        loop {
+            let option = match args.get(i) {
+                Some(option) => String::from_utf8_lossy(option).to_lowercase(),
+                None => return syntax_error(),
+            };
+            match option.as_str() {
+                "count" if i + 1 < args.len() => match parse_integer_arg::<i64>(&args[i + 1]) {
+                    Some(value) => count = if value > 0 { value as usize } else { usize::MAX },
+                    None => return not_an_integer(),
+                },
+                "block" if i + 1 < args.len() => match parse_integer_arg::<i64>(&args[i + 1]) {
+                    Some(ms) if ms < 0 => return Command::INVALID("ERR timeout is negative".to_string()),
+                    Some(ms) => block = Some((ms > 0).then(|| Duration::from_millis(ms as u64))),
+                    None => return Command::INVALID("ERR timeout is not an integer or out of range".to_string()),
+                },
+                "noack" => {
+                    noack = true;
+                    i += 1;
+                    continue;
+                }
+                "streams" => break,
+                _ => return syntax_error(),
+            }
+            i += 2;
+        }
+        let streams = &args[i + 1..];
+        if streams.is_empty() || !streams.chunks_exact(2).remainder().is_empty() {
+            return Command::INVALID(
+                "ERR Unbalanced 'xreadgroup' list of streams: for each stream key an ID or '>' must be specified.".to_string(),
+            );
+        }
+        let (keys, ids) = streams.split_at(streams.len() / 2);
+        let mut read_ids = Vec::with_capacity(ids.len());
+        for id in ids {
+            match id.as_slice() {
+                b">" => read_ids.push(None),
+                b"$" => return Command::INVALID("ERR The $ ID is meaningful only for XREAD command".to_string()),
+                id => match StreamId::parse(id, 0) {
+                    Some(id) => read_ids.push(Some(id)),
+                    None => return invalid_stream_id(),
+                },
+            }
+        }
+        let args = Box::new(XreadgroupArgs {
+            group: args[2].clone(),
+            consumer: args[3].clone(),
+            keys: keys.to_vec(),
+            ids: read_ids,
+            count,
+            noack,
+        });
+        match block {
+            Some(timeout) => Command::XREADGROUPBLOCK(args, timeout),
+            None => Command::XREADGROUP(args),
+        }
+    }
+
+    pub fn parse_xack(args: &[Vec<u8>]) -> Command {
+        if args.len() < 4 {
+            return wrong_number_of_args("xack");
+        }
+        let mut ids = Vec::with_capacity(args.len() - 3);
+        for arg in &args[3..] {
+            match StreamId::parse(arg, 0) {
+                Some(id) => ids.push(id),
+                None => return invalid_stream_id(),
+            }
+        }
+        Command::XACK(args[1].clone(), args[2].clone(), ids)
+    }
+
+    // XPENDING key group [[IDLE min-idle] start end count [consumer]]
+    pub fn parse_xpending(args: &[Vec<u8>]) -> Command {
+        if args.len() == 3 {
+            return Command::XPENDING(args[1].clone(), args[2].clone(), None);
+        }
+        let mut i = 3;
+        let mut min_idle = 0;
+        if args.get(i).is_some_and(|arg| arg.eq_ignore_ascii_case(b"idle")) {
+            match args.get(i + 1).map(|arg| parse_idle_arg(arg)) {
+                Some(Ok(idle)) => min_idle = idle,
+                Some(Err(err)) => return err,
+                None => return syntax_error(),
+            }
+            i += 2;
+        }
+        if args.len() < i + 3 || args.len() > i + 4 {
+            return if args.len() < 3 { wrong_number_of_args("xpending") } else { syntax_error() };
+        }
+        let (start, end) = match (parse_range_bound(&args[i], true), parse_range_bound(&args[i + 1], false)) {
+            (Ok(start), Ok(end)) => (start, end),
+            (Err(err), _) | (_, Err(err)) => return err,
+        };
+        let count = match parse_integer_arg::<i64>(&args[i + 2]) {
+            Some(count) => count.max(0) as usize,
+            None => return not_an_integer(),
+        };
+        let range = XpendingRange { min_idle, start, end, count, consumer: args.get(i + 3).cloned() };
+        Command::XPENDING(args[1].clone(), args[2].clone(), Some(range))
+    }
+
+    // XCLAIM key group consumer min-idle id [id ...] [IDLE ms] [TIME ms] [RETRYCOUNT n] [FORCE]
+    // [JUSTID] [LASTID id]
+    pub fn parse_xclaim(args: &[Vec<u8>]) -> Command {
+        if args.len() < 6 {
+            return wrong_number_of_args("xclaim");
+        }
+        let mut options = ClaimOptions::default();
+        match parse_idle_arg(&args[4]) {
+            Ok(min_idle) => options.min_idle = min_idle,
+            Err(_) => return Command::INVALID("ERR Invalid min-idle-time argument for XCLAIM".to_string()),
+        }
+        let mut i = 5;
+        let mut ids = vec![];
+        while let Some(id) = args.get(i).and_then(|arg| StreamId::parse(arg, 0)) {
+            ids.push(id);
+            i += 1;
+        }
+        while let Some(arg) = args.get(i) {
+            let option = String::from_utf8_lossy(arg).to_lowercase();
+            let value = args.get(i + 1);
+            match (option.as_str(), value) {
+                ("force", _) => options.force = true,
+                ("justid", _) => options.justid = true,
+                ("idle", Some(value)) => match parse_idle_arg(value) {
+                    Ok(idle) => options.delivery_time = Some(unix_time_ms().saturating_sub(idle)),
+                    Err(err) => return err,
+                },
+                ("time", Some(value)) => match parse_idle_arg(value) {
+                    Ok(time) => options.delivery_time = Some(time),
+                    Err(err) => return err,
+                },
+                ("retrycount", Some(value)) => match parse_idle_arg(value) {
+                    Ok(retry_count) => options.retry_count = Some(retry_count),
+                    Err(err) => return err,
+                },
+                ("lastid", Some(value)) => match StreamId::parse(value, 0) {
+                    Some(id) => options.last_id = Some(id),
+                    None => return invalid_stream_id(),
+                },
+                _ => {
+                    return Command::INVALID(format!("ERR Unrecognized XCLAIM option '{}'", String::from_utf8_lossy(arg)));
+                }
+            }
+            i += if matches!(option.as_str(), "force" | "justid") { 1 } else { 2 };
+        }
+        Command::XCLAIM(args[1].clone(), args[2].clone(), args[3].clone(), ids, Box::new(options))
+    }
+
+    // XAUTOCLAIM key group consumer min-idle start [COUNT n] [JUSTID]
+    pub fn parse_xautoclaim(args: &[Vec<u8>]) -> Command {
+        if args.len() < 6 {
+            return wrong_number_of_args("xautoclaim");
+        }
+        let min_idle = match parse_idle_arg(&args[4]) {
+            Ok(min_idle) => min_idle,
+            Err(_) => return Command::INVALID("ERR Invalid min-idle-time argument for XAUTOCLAIM".to_string()),
+        };
+        let start = match parse_range_bound(&args[5], true) {
+            Ok(start) => start,
+            Err(err) => return err,
+        };
+        let (mut count, mut justid) = (100, false);
+        let mut i = 6;
+        while let Some(arg) = args.get(i) {
+            if arg.eq_ignore_ascii_case(b"justid") {
+                justid = true;
+                i += 1;
+            } else if arg.eq_ignore_ascii_case(b"count") && i + 1 < args.len() {
+                count = match parse_integer_arg::<i64>(&args[i + 1]) {
+                    Some(value) if value > 0 && value <= i64::MAX / 10 => value as usize,
+                    Some(_) => return Command::INVALID("ERR COUNT must be > 0".to_string()),
+                    None => return not_an_integer(),
+                };
+                i += 2;
+            } else {
+                return syntax_error();
+            }
+        }
+        Command::XAUTOCLAIM(args[1].clone(), args[2].clone(), args[3].clone(), min_idle, start, count, justid)
+    }
+
+    // XADD key [NOMKSTREAM] [MAXLEN|MINID [=|~] threshold [LIMIT count]] <* | ms-* | id> field value ...
+    pub fn parse_xadd(args: &[Vec<u8>]) -> Command {
+        if args.len() < 5 {
+            return wrong_number_of_args("xadd");
+        }
+        let mut options = XaddOptions::default();
+        let mut i = 2;
+        while let Some(arg) = args.get(i) {
+            if arg.eq_ignore_ascii_case(b"nomkstream") {
+                options.nomkstream = true;
+                i += 1;
+            } else if arg.eq_ignore_ascii_case(b"maxlen") || arg.eq_ignore_ascii_case(b"minid") {
+                match parse_trim_options(args, i) {
+                    Ok((trim, next)) => (options.trim, i) = (Some(trim), next),
+                    Err(err) => return err,
+                }
+            } else {
+                break;
+            }
+        }
+        let id = match args.get(i).and_then(|arg| parse_xadd_id(arg)) {
+            Some(id) => id,
+            None if i < args.len() => return invalid_stream_id(),
+            None => return syntax_error(),
+        };
+        let fields = &args[i + 1..];
+        if fields.is_empty() || !fields.chunks_exact(2).remainder().is_empty() {
+            return wrong_number_of_args("xadd");
+        }
+        let fields = fields.chunks_exact(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect();
+        Command::XADD(args[1].clone(), options, id, fields)
+    }
+
+    pub fn parse_xlen(args: &[Vec<u8>]) -> Command {
+        if args.len() != 2 {
+            return wrong_number_of_args("xlen");
+        }
+        Command::XLEN(args[1].clone())
+    }
+
+    pub fn parse_xdel(args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 {
+            return wrong_number_of_args("xdel");
+        }
+        let mut ids = Vec::with_capacity(args.len() - 2);
+        for arg in &args[2..] {
+            match StreamId::parse(arg, 0) {
+                Some(id) => ids.push(id),
+                None => return invalid_stream_id(),
+            }
+        }
+        Command::XDEL(args[1].clone(), ids)
+    }
+
+    // XTRIM key MAXLEN|MINID [=|~] threshold [LIMIT count]
+    pub fn parse_xtrim(args: &[Vec<u8>]) -> Command {
+        if args.len() < 4 {
+            return wrong_number_of_args("xtrim");
+        }
+        if !args[2].eq_ignore_ascii_case(b"maxlen") && !args[2].eq_ignore_ascii_case(b"minid") {
+            return syntax_error();
+        }
+        match parse_trim_options(args, 2) {
+            Ok((options, next)) if next == args.len() => Command::XTRIM(args[1].clone(), options),
+            Ok(_) => syntax_error(),
+            Err(err) => err,
+        }
+    }
+
+    // XRANGE key start end [COUNT n], with XREVRANGE taking the end first
+    pub fn parse_xrange(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() != 4 && args.len() != 6 {
+            return wrong_number_of_args(name);
+        }
+        let rev = name == "xrevrange";
+        let (start, end) = if rev { (&args[3], &args[2]) } else { (&args[2], &args[3]) };
+        let (start, end) = match (parse_range_bound(start, true), parse_range_bound(end, false)) {
+            (Ok(start), Ok(end)) => (start, end),
+            (Err(err), _) | (_, Err(err)) => return err,
+        };
+        let count = match args.get(4..6) {
+            Some([option, count]) if option.eq_ignore_ascii_case(b"count") => match parse_integer_arg::<i64>(count) {
+                Some(count) => count.max(0) as usize,
+                None => return not_an_integer(),
+            },
+            Some(_) => return syntax_error(),
+            None => usize::MAX,
+        };
+        Command::XRANGE(args[1].clone(), start, end, rev, count)
+    }
+
+    // XREAD [COUNT n] [BLOCK ms] STREAMS key [key ...] id [id ...]
+    pub fn parse_xread(args: &[Vec<u8>]) -> Command {
+        let (mut count, mut block) = (usize::MAX, None);
+        let mut i = 1;
+        loop {
+            let option = match args.get(i) {
+                Some(option) => String::from_utf8_lossy(option).to_lowercase(),
+                None => return syntax_error(),
+            };
+            match option.as_str() {
+                "count" if i + 1 < args.len() => match parse_integer_arg::<i64>(&args[i + 1]) {
+                    Some(value) => count = if value > 0 { value as usize } else { usize::MAX },
+                    None => return not_an_integer(),
+                },
+                "block" if i + 1 < args.len() => match parse_integer_arg::<i64>(&args[i + 1]) {
+                    Some(ms) if ms < 0 => return Command::INVALID("ERR timeout is negative".to_string()),
+                    Some(ms) => block = Some((ms > 0).then(|| Duration::from_millis(ms as u64))),
+                    None => return Command::INVALID("ERR timeout is not an integer or out of range".to_string()),
+                },
+                "streams" => break,
+                _ => return syntax_error(),
+            }
+            i += 2;
+        }
+        let streams = &args[i + 1..];
+        if streams.is_empty() || !streams.chunks_exact(2).remainder().is_empty() {
+            return Command::INVALID(
+                "ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.".to_string(),
+            );
+        }
+        let (keys, ids) = streams.split_at(streams.len() / 2);
+        let mut read_ids = Vec::with_capacity(ids.len());
+        for id in ids {
+            match id.as_slice() {
+                b"$" => read_ids.push(XreadId::Last),
+                id => match StreamId::parse(id, 0) {
+                    Some(id) => read_ids.push(XreadId::After(id)),
+                    None => return invalid_stream_id(),
+                },
+            }
+        }
+        match block {
+            Some(timeout) => Command::XREADBLOCK(keys.to_vec(), read_ids, count, timeout),
+            None => Command::XREAD(keys.to_vec(), read_ids, count),
+        }
+    }
+}
+
+impl State {
+    pub fn xadd(&mut self, key: &[u8], options: &XaddOptions, id: XaddId, fields: StreamFields) -> CommandResult {
+        let last = match self.get_stream(key)? {
+            Some(stream) => stream.last_id(),
+            None if options.nomkstream => return Ok(DataType::NullBulkString),
+            None => StreamId::MIN,
+        };
+        let id = match id {
+            XaddId::Explicit(StreamId::MIN) => {
+                return Err(DataType::SimpleError("ERR The ID specified in XADD must be greater than 0-0".to_string()));
+            }
+            XaddId::Explicit(id) => Some(id),
+            XaddId::Partial(ms) if ms == last.ms => last.seq.checked_add(1).map(|seq| StreamId { ms, seq }),
+            // 0-0 is never a valid ID, so a fresh 0 millisecond starts at sequence 1
+            XaddId::Partial(ms) => Some(StreamId { ms, seq: (ms == 0) as u64 }),
+            XaddId::Auto => match unix_time_ms() {
+                ms if ms > last.ms => Some(StreamId { ms, seq: 0 }),
+                _ => last.next(),
+            },
+        };
+        let id = match id {
+            Some(id) if id > last => id,
+            Some(_) => {
+                return Err(DataType::SimpleError(
+                    "ERR The ID specified in XADD is equal or smaller than the target stream top item".to_string(),
+                ));
+            }
+            None => {
+                return Err(DataType::SimpleError(
+                    "ERR The stream has exhausted the last possible ID, unable to add more items".to_string(),
+                ));
+            }
+        };
+        let stream = self.get_or_create_stream(key)?;
+        stream.append(id, fields);
+        let trimmed = options.trim.as_ref().map_or(0, |trim| stream.trim(trim));
+        self.notify_keyspace_event(NOTIFY_STREAM, "xadd", key);
+        if trimmed > 0 {
+            self.notify_keyspace_event(NOTIFY_STREAM, "xtrim", key);
+        }
+        Ok(DataType::BulkString(id.to_string().into_bytes()))
+    }
+
+    pub fn xlen(&mut self, key: &[u8]) -> CommandResult {
+        let len = self.get_stream(key)?.map_or(0, |stream| stream.len());
+        Ok(DataType::Integer(len as i64))
+    }
+
+    pub fn xdel(&mut self, key: &[u8], ids: &[StreamId]) -> CommandResult {
+        let deleted = match self.get_stream(key)? {
+            Some(stream) => ids.iter().filter(|id| stream.remove(**id)).count(),
+            None => 0,
+        };
+        if deleted > 0 {
+            self.notify_keyspace_event(NOTIFY_STREAM, "xdel", key);
+        }
+        Ok(DataType::Integer(deleted as i64))
+    }
+
+    pub fn xtrim(&mut self, key: &[u8], options: &TrimOptions) -> CommandResult {
+        let trimmed = self.get_stream(key)?.map_or(0, |stream| stream.trim(options));
+        if trimmed > 0 {
+            self.notify_keyspace_event(NOTIFY_STREAM, "xtrim", key);
+        }
+        Ok(DataType::Integer(trimmed as i64))
+    }
+
+    pub fn xrange(&mut self, key: &[u8], start: StreamId, end: StreamId, rev: bool, count: usize) -> CommandResult {
+        Ok(match self.get_stream(key)? {
+            Some(stream) => entries_reply(stream.range(start, end, rev, count)),
+            None => DataType::Array(vec![]),
+        })
+    }
+
+    // Entries after the given IDs for every stream that has some, or nil when none do
+    pub fn xread(&mut self, keys: &[Vec<u8>], ids: &[XreadId], count: usize) -> CommandResult {
+        let mut streams = vec![];
+        for (key, id) in keys.iter().zip(ids) {
+            let stream = match self.get_stream(key)? {
+                Some(stream) => stream,
+                None => continue,
+            };
+            let start = match id {
+                XreadId::Last => continue,
+                XreadId::After(id) => match id.next() {
+                    Some(start) => start,
+                    None => continue,
+                },
+            };
+            let entries = stream.range(start, StreamId::MAX, false, count);
+            if !entries.is_empty() {
+                streams.push(DataType::Array(vec![DataType::BulkString(key.clone()), entries_reply(entries)]));
+            }
+        }
+        Ok(if streams.is_empty() { DataType::NullArray } else { DataType::Array(streams) })
+    }
+
+    pub fn xgroup_create(&mut self, key: &[u8], group: &[u8], id: XreadId, mkstream: bool) -> CommandResult {
+        let stream = match self.get_stream(key)? {
+            Some(stream) => stream,
+            None if mkstream => self.get_or_create_stream(key)?,
+            None => {
+                return Err(DataType::SimpleError(
+                    "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use \
+                     the MKSTREAM option to create an empty stream automatically."
+                        .to_string(),
+                ));
+            }
+        };
+        let last_delivered = match id {
+            XreadId::Last => stream.last_id(),
+            XreadId::After(id) => id,
+        };
+        if !stream.create_group(group, last_delivered) {
+            return Err(DataType::SimpleError("BUSYGROUP Consumer Group name already exists".to_string()));
+        }
+        self.notify_keyspace_event(NOTIFY_STREAM, "xgroup-create", key);
+        Ok(DataType::SimpleString("OK".to_string()))
+    }
+
+    pub fn xgroup_destroy(&mut self, key: &[u8], group: &[u8]) -> CommandResult {
+        match self.get_stream(key)? {
+            Some(stream) => Ok(DataType::Integer(stream.destroy_group(group) as i64)),
+            None => Err(DataType::SimpleError(
+                "ERR The XGROUP subcommand requires the key to exist".to_string(),
+            )),
+        }
+    }
+
+    // Deliver entries to a consumer of a group on each stream. Reading new entries replies nil
+    // when none of the streams had any, so a blocking read can wait for them.
+    pub fn xreadgroup(&mut self, args: &XreadgroupArgs) -> CommandResult {
+        for key in &args.keys {
+            if self.get_stream(key)?.and_then(|stream| stream.group(&args.group)).is_none() {
+                return Err(no_group(key, &args.group, " in XREADGROUP with GROUP option"));
+            }
+        }
+        let now = unix_time_ms();
+        let mut streams = vec![];
+        for (key, id) in args.keys.iter().zip(&args.ids) {
+            let stream = self.get_stream(key)?.unwrap();
+            let entries = match id {
+                None => {
+                    let entries = stream.read_new(&args.group, &args.consumer, args.count, args.noack, now);
+                    if entries.is_empty() {
+                        continue;
+                    }
+                    entries_reply(entries)
+                }
+                Some(after) => {
+                    let entries = stream.read_pending(&args.group, &args.consumer, *after, args.count, now);
+                    DataType::Array(entries.iter().map(|(id, fields)| entry_reply(*id, fields.as_ref())).collect())
+                }
+            };
+            streams.push(DataType::Array(vec![DataType::BulkString(key.clone()), entries]));
+        }
+        Ok(if streams.is_empty() { DataType::NullArray } else { DataType::Array(streams) })
+    }
+
+    pub fn xack(&mut self, key: &[u8], group: &[u8], ids: &[StreamId]) -> CommandResult {
+        let acked = match self.get_stream(key)?.and_then(|stream| stream.group_mut(group)) {
+            Some(group) => ids.iter().filter(|id| group.ack(**id)).count(),
+            None => 0,
+        };
+        Ok(DataType::Integer(acked as i64))
+    }
+
+    pub fn xpending(&mut self, key: &[u8], group_name: &[u8], range: Option<&XpendingRange>) -> CommandResult {
+        let group = match self.get_stream(key)?.and_then(|stream| stream.group(group_name)) {
+            Some(group) => group,
+            None => return Err(no_group(key, group_name, "")),
+        };
+        let range = match range {
+            Some(range) => range,
+            None => {
+                let (first, last) = match (group.pending.keys().next(), group.pending.keys().next_back()) {
+                    (Some(first), Some(last)) => (*first, *last),
+                    _ => {
+                        return Ok(DataType::Array(vec![
+                            DataType::Integer(0),
+                            DataType::NullBulkString,
+                            DataType::NullBulkString,
+                            DataType::NullArray,
+                        ]));
+                    }
+                };
+                let consumers = group.consumers.iter()
+                    .filter(|(_, consumer)| !consumer.pending.is_empty())
+                    .map(|(name, consumer)| {
+                        DataType::bulk_array([name.clone(), consumer.pending.len().to_string().into_bytes()])
+                    });
+                return Ok(DataType::Array(vec![
+                    DataType::Integer(group.pending.len() as i64),
+                    id_reply(first),
+                    id_reply(last),
+                    DataType::Array(consumers.collect()),
+                ]));
+            }
+        };
+        if range.start > range.end {
+            return Ok(DataType::Array(vec![]));
+        }
+        let now = unix_time_ms();
+        let entries = group.pending.range(range.start..=range.end)
+            .filter(|(_, entry)| range.consumer.as_ref().is_none_or(|consumer| *consumer == entry.consumer))
+            .filter(|(_, entry)| now.saturating_sub(entry.delivery_time) >= range.min_idle)
+            .take(range.count)
+            .map(|(id, entry)| {
+                DataType::Array(vec![
+                    id_reply(*id),
+                    DataType::BulkString(entry.consumer.clone()),
+                    DataType::Integer(now.saturating_sub(entry.delivery_time) as i64),
+                    DataType::Integer(entry.delivery_count as i64),
+                ])
+            });
+        Ok(DataType::Array(entries.collect()))
+    }
+
+    pub fn xclaim(&mut self, key: &[u8], group: &[u8], consumer: &[u8], ids: &[StreamId], options: &ClaimOptions) -> CommandResult {
+        let stream = match self.get_stream(key)? {
+            Some(stream) if stream.group(group).is_some() => stream,
+            _ => return Err(no_group(key, group, "")),
+        };
+        let claimed = stream.claim(group, consumer, ids, options, unix_time_ms());
+        if options.justid {
+            return Ok(DataType::Array(claimed.into_iter().map(id_reply).collect()));
+        }
+        Ok(entries_reply(claimed.into_iter().filter_map(|id| stream.get(id).map(|fields| (id, fields))).collect()))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn xautoclaim(&mut self, key: &[u8], group: &[u8], consumer: &[u8], min_idle: u64, start: StreamId, count: usize, justid: bool) -> CommandResult {
+        let stream = match self.get_stream(key)? {
+            Some(stream) if stream.group(group).is_some() => stream,
+            _ => return Err(no_group(key, group, "")),
+        };
+        let options = ClaimOptions { min_idle, justid, ..Default::default() };
+        let result = stream.autoclaim(group, consumer, start, count, &options, unix_time_ms());
+        let claimed = if justid {
+            DataType::Array(result.claimed.into_iter().map(id_reply).collect())
+        } else {
+            entries_reply(result.claimed.into_iter().filter_map(|id| stream.get(id).map(|fields| (id, fields))).collect())
+        };
+        Ok(DataType::Array(vec![
+            id_reply(result.next),
+            claimed,
+            DataType::Array(result.deleted.into_iter().map(id_reply).collect()),
+        ]))
+    }
+
+    pub fn xsetid(&mut self, key: &[u8], id: StreamId, entries_added: Option<u64>, max_deleted_id: Option<StreamId>) -> CommandResult {
+        let stream = match self.get_stream(key)? {
+            Some(stream) => stream,
+            None => return Err(DataType::SimpleError("ERR no such key".to_string())),
+        };
+        // Entries that are still present pin how far back the IDs may go
+        if let Some((top, _)) = stream.last_entry() {
+            if id < top {
+                return Err(DataType::SimpleError(
+                    "ERR The ID specified in XSETID is smaller than the target stream top item".to_string(),
+                ));
+            }
+            if entries_added.is_some_and(|added| added < stream.len() as u64) {
+                return Err(DataType::SimpleError(
+                    "ERR The entries_added specified in XSETID is smaller than the target stream length".to_string(),
+                ));
+            }
+        }
+        // Nor may they go back past an entry that was deleted, unless that is being reset too
+        if max_deleted_id.is_none() && id < stream.max_deleted_id() {
+            return Err(DataType::SimpleError(
+                "ERR The ID specified in XSETID is smaller than current max_deleted_entry_id".to_string(),
+            ));
+        }
+        stream.set_last_id(id, entries_added, max_deleted_id);
+        self.notify_keyspace_event(NOTIFY_STREAM, "xsetid", key);
+        Ok(DataType::SimpleString("OK".to_string()))
+    }
+
+    // XINFO STREAM, with `full` holding the number of entries to list in the FULL form
+    pub fn xinfo_stream(&mut self, key: &[u8], full: Option<usize>) -> CommandResult {
+        let stream = match self.get_stream(key)? {
+            Some(stream) => stream,
+            None => return Err(DataType::SimpleError("ERR no such key".to_string())),
+        };
+        let (keys, nodes) = stream.node_counts();
+        let mut fields = vec![
+            ("length", DataType::Integer(stream.len() as i64)),
+            ("radix-tree-keys", DataType::Integer(keys as i64)),
+            ("radix-tree-nodes", DataType::Integer(nodes as i64)),
+            ("last-generated-id", id_reply(stream.last_id())),
+            ("max-deleted-entry-id", id_reply(stream.max_deleted_id())),
+            ("entries-added", DataType::Integer(stream.entries_added() as i64)),
+            ("recorded-first-entry-id", id_reply(stream.first_id())),
+        ];
+        let count = match full {
+            Some(count) => count,
+            None => {
+                let entry = |entry: Option<(StreamId, StreamFields)>| match entry {
+                    Some((id, fields)) => entry_reply(id, Some(&fields)),
+                    None => DataType::NullBulkString,
+                };
+                fields.push(("groups", DataType::Integer(stream.groups().count() as i64)));
+                fields.push(("first-entry", entry(stream.first_entry())));
+                fields.push(("last-entry", entry(stream.last_entry())));
+                return Ok(info_reply(fields));
+            }
+        };
+        fields.push(("entries", entries_reply(stream.range(StreamId::MIN, StreamId::MAX, false, count))));
+        let groups = stream.groups().map(|(name, group)| {
+            let pending = group.pending.iter().take(count).map(|(id, entry)| {
+                DataType::Array(vec![
+                    id_reply(*id),
+                    DataType::BulkString(entry.consumer.clone()),
+                    DataType::Integer(entry.delivery_time as i64),
+                    DataType::Integer(entry.delivery_count as i64),
+                ])
+            });
+            let consumers = group.consumers.iter().map(|(name, consumer)| {
+                let pending = consumer.pending.iter().take(count).map(|id| {
+                    let entry = &group.pending[id];
+                    DataType::Array(vec![
+                        id_reply(*id),
+                        DataType::Integer(entry.delivery_time as i64),
+                        DataType::Integer(entry.delivery_count as i64),
+                    ])
+                });
+                info_reply(vec![
+                    ("name", DataType::BulkString(name.clone())),
+                    ("seen-time", DataType::Integer(consumer.seen_time as i64)),
+                    ("active-time", DataType::Integer(consumer.active_time.map_or(-1, |time| time as i64))),
+                    ("pel-count", DataType::Integer(consumer.pending.len() as i64)),
+                    ("pending", DataType::Array(pending.collect())),
+                ])
+            });
+            info_reply(vec![
+                ("name", DataType::BulkString(name.clone())),
+                ("last-delivered-id", id_reply(group.last_delivered)),
+                ("entries-read", optional_integer(group.entries_read)),
+                ("lag", optional_integer(stream.group_lag(group))),
+                ("pel-count", DataType::Integer(group.pending.len() as i64)),
+                ("pending", DataType::Array(pending.collect())),
+                ("consumers", DataType::Array(consumers.collect())),
+            ])
+        });
+        fields.push(("groups", DataType::Array(groups.collect())));
+        Ok(info_reply(fields))
+    }
+
+    pub fn xinfo_groups(&mut self, key: &[u8]) -> CommandResult {
+        let stream = match self.get_stream(key)? {
+            Some(stream) => stream,
+            None => return Err(DataType::SimpleError("ERR no such key".to_string())),
+        };
+        let groups = stream.groups().map(|(name, group)| group_info(stream, name, group));
+        Ok(DataType::Array(groups.collect()))
+    }
+
+    pub fn xinfo_consumers(&mut self, key: &[u8], group_name: &[u8]) -> CommandResult {
+        let group = match self.get_stream(key)? {
+            Some(stream) => stream.group(group_name),
+            None => return Err(DataType::SimpleError("ERR no such key".to_string())),
+        };
+        let group = match group {
+            Some(group) => group,
+            None => {
+                return Err(DataType::SimpleError(format!(
+                    "NOGROUP No such consumer group '{}' for key name '{}'",
+                    String::from_utf8_lossy(group_name),
+                    String::from_utf8_lossy(key),
+                )));
+            }
+        };
+        let now = unix_time_ms();
+        let consumers = group.consumers.iter().map(|(name, consumer)| {
+            let inactive = consumer.active_time.map_or(-1, |time| now.saturating_sub(time) as i64);
+            info_reply(vec![
+                ("name", DataType::BulkString(name.clone())),
+                ("pending", DataType::Integer(consumer.pending.len() as i64)),
+                ("idle", DataType::Integer(now.saturating_sub(consumer.seen_time) as i64)),
+                ("inactive", DataType::Integer(inactive)),
+            ])
+        });
+        Ok(DataType::Array(consumers.collect()))
+    }
+
+    // Pin `$` to the current last IDs, so a parked XREAD is served by entries added afterwards
+    pub fn resolve_xread_ids(&mut self, keys: &[Vec<u8>], ids: &mut [XreadId]) {
+        for (key, id) in keys.iter().zip(ids.iter_mut()) {
+            if let XreadId::Last = id {
+                let last = match self.get_stream(key) {
+                    Ok(Some(stream)) => stream.last_id(),
+                    _ => StreamId::MIN,
+                };
+                *id = XreadId::After(last);
+            }
+        }
+    }
+}
+
+fn group_info(stream: &Stream, name: &[u8], group: &ConsumerGroup) -> DataType {
+    info_reply(vec![
+        ("name", DataType::BulkString(name.to_vec())),
+        ("consumers", DataType::Integer(group.consumers.len() as i64)),
+        ("pending", DataType::Integer(group.pending.len() as i64)),
+        ("last-delivered-id", id_reply(group.last_delivered)),
+        ("entries-read", optional_integer(group.entries_read)),
+        ("lag", optional_integer(stream.group_lag(group))),
+    ])
+}
@@ -0,0 +1,159 @@
+use tokio::time::Duration;
+
+use crate::{
+    clock::now_ms,
+    command::{not_an_integer, parse_integer_arg, syntax_error, wrong_number_of_args, Command},
+    notify::{NOTIFY_GENERIC, NOTIFY_STRING},
+    resp::DataType,
+    state::{CommandResult, DataStoreValue, State, Value},
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct LcsOptions {
+    len: bool,
+    idx: bool,
+    min_match_len: usize,
+    with_match_len: bool,
+}
+
+impl Command {
+    pub fn parse_lcs(args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 {
+            return wrong_number_of_args("lcs");
+        }
+        let mut options = LcsOptions::default();
+        let mut i = 3;
+        while i < args.len() {
+            match args[i].to_ascii_lowercase().as_slice() {
+                b"len" => options.len = true,
+                b"idx" => options.idx = true,
+                b"withmatchlen" => options.with_match_len = true,
+                b"minmatchlen" if i + 1 < args.len() => {
+                    i += 1;
+                    options.min_match_len = match parse_integer_arg::<i64>(&args[i]) {
+                        Some(len) => len.max(0) as usize,
+                        None => return not_an_integer(),
+                    };
+                }
+                _ => return syntax_error(),
+            }
+            i += 1;
+        }
+        if options.len && options.idx {
+            return Command::INVALID("ERR If you want both the length and indexes, please just use IDX.".to_string());
+        }
+        Command::LCS(args[1].clone(), args[2].clone(), options)
+    }
+}
+
+impl State {
+    pub fn get(&mut self, key: &[u8]) -> CommandResult {
+        Ok(match self.get_string(key)? {
+            Some(value) => DataType::BulkString(value.clone()),
+            None => DataType::NullBulkString,
+        })
+    }
+
+    pub fn set(&mut self, key: Vec<u8>, value: Vec<u8>, expiry: Option<Duration>) -> CommandResult {
+        let dsv = DataStoreValue::new(Value::String(value), expiry.map(|expiry| now_ms() + expiry.as_millis() as u64));
+        self.datastore.insert(key.clone(), dsv);
+        self.notify_keyspace_event(NOTIFY_STRING, "set", &key);
+        if expiry.is_some() {
+            self.notify_keyspace_event(NOTIFY_GENERIC, "expire", &key);
+        }
+        Ok(DataType::ok())
+    }
+
+    pub fn lcs(&mut self, key1: &[u8], key2: &[u8], options: &LcsOptions) -> CommandResult {
+        let a = self.get_string(key1)?.cloned().unwrap_or_default();
+        let b = self.get_string(key2)?.cloned().unwrap_or_default();
+        Ok(lcs(&a, &b, options))
+    }
+}
+
+// Longest common subsequence of two strings, following the dynamic programming
+// table walk used by Redis so that IDX match ranges are reported identically.
+fn lcs(a: &[u8], b: &[u8], options: &LcsOptions) -> DataType {
+    let (alen, blen) = (a.len(), b.len());
+    let width = blen + 1;
+    let mut table = vec![0u32; (alen + 1) * width];
+    for i in 1..=alen {
+        for j in 1..=blen {
+            table[i * width + j] = if a[i - 1] == b[j - 1] {
+                table[(i - 1) * width + (j - 1)] + 1
+            } else {
+                table[(i - 1) * width + j].max(table[i * width + (j - 1)])
+            };
+        }
+    }
+
+    let mut idx = table[alen * width + blen] as usize;
+    if options.len {
+        return DataType::Integer(idx as i64);
+    }
+    let lcs_len = idx;
+    let mut result = vec![0u8; lcs_len];
+    let mut matches = Vec::new();
+
+    // Walk back from the bottom right corner; arange_start == alen means no range is in progress
+    let (mut i, mut j) = (alen, blen);
+    let (mut arange_start, mut arange_end, mut brange_start, mut brange_end) = (alen, 0, 0, 0);
+    while i > 0 && j > 0 {
+        let mut emit_range = false;
+        if a[i - 1] == b[j - 1] {
+            result[idx - 1] = a[i - 1];
+            if arange_start == alen {
+                arange_start = i - 1;
+                arange_end = i - 1;
+                brange_start = j - 1;
+                brange_end = j - 1;
+            } else if arange_start == i && brange_start == j {
+                arange_start -= 1;
+                brange_start -= 1;
+            } else {
+                emit_range = true;
+            }
+            if arange_start == 0 || brange_start == 0 {
+                emit_range = true;
+            }
+            idx -= 1;
+            i -= 1;
+            j -= 1;
+        } else {
+            if table[(i - 1) * width + j] > table[i * width + (j - 1)] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+            if arange_start != alen {
+                emit_range = true;
+            }
+        }
+
+        if emit_range {
+            let match_len = arange_end - arange_start + 1;
+            if options.min_match_len == 0 || match_len >= options.min_match_len {
+                let mut entry = vec![
+                    DataType::Array(vec![DataType::Integer(arange_start as i64), DataType::Integer(arange_end as i64)]),
+                    DataType::Array(vec![DataType::Integer(brange_start as i64), DataType::Integer(brange_end as i64)]),
+                ];
+                if options.with_match_len {
+                    entry.push(DataType::Integer(match_len as i64));
+                }
+                matches.push(DataType::Array(entry));
+            }
+            arange_start = alen;
+        }
+    }
+
+    if options.idx {
+        DataType::Array(vec![
+            DataType::BulkString(b"matches".to_vec()),
+            DataType::Array(matches),
+            DataType::BulkString(b"len".to_vec()),
+            DataType::Integer(lcs_len as i64),
+        ])
+    } else {
+        DataType::BulkString(result)
+    }
+}
@@ -0,0 +1,223 @@
+use crate::{
+    command::{parse_integer_arg, wrong_number_of_args, Command},
+    commands::{stream::unix_time_ms, zset::format_score},
+    notify::NOTIFY_MODULE,
+    resp::DataType,
+    state::{CommandResult, DataStoreValue, State, Value},
+    types::timeseries::{Aggregation, DuplicatePolicy, TimeSeries},
+};
+
+// Options shared by TS.CREATE and the implicit creation in TS.ADD
+#[derive(Debug, Clone, Default)]
+pub struct TsOptions {
+    retention: Option<u64>,
+    duplicate_policy: Option<DuplicatePolicy>,
+    // Per call override, TS.ADD only
+    on_duplicate: Option<DuplicatePolicy>,
+    labels: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+// TS.RANGE and TS.REVRANGE arguments after the key
+#[derive(Debug, Clone)]
+pub struct TsRange {
+    from: u64,
+    to: u64,
+    count: Option<usize>,
+    aggregation: Option<(Aggregation, u64)>,
+    rev: bool,
+}
+
+fn tsdb_error(msg: &str) -> Command {
+    Command::INVALID(format!("ERR TSDB: {}", msg))
+}
+
+fn key_does_not_exist() -> DataType {
+    DataType::SimpleError("ERR TSDB: the key does not exist".to_string())
+}
+
+fn sample_reply((timestamp, value): (u64, f64)) -> DataType {
+    DataType::Array(vec![DataType::Integer(timestamp as i64), DataType::BulkString(format_score(value))])
+}
+
+// Trailing [RETENTION ms] [DUPLICATE_POLICY policy] [ON_DUPLICATE policy] [LABELS label value ...]
+fn parse_ts_options(args: &[Vec<u8>], allow_on_duplicate: bool) -> Result<TsOptions, Command> {
+    let mut options = TsOptions::default();
+    let mut i = 0;
+    while let Some(arg) = args.get(i) {
+        match arg.to_ascii_lowercase().as_slice() {
+            b"retention" => {
+                let retention = args.get(i + 1).and_then(|arg| parse_integer_arg::<u64>(arg));
+                options.retention = Some(retention.ok_or_else(|| tsdb_error("Couldn't parse RETENTION"))?);
+            }
+            b"duplicate_policy" => {
+                let policy = args.get(i + 1).and_then(|arg| DuplicatePolicy::parse(arg));
+                options.duplicate_policy = Some(policy.ok_or_else(|| tsdb_error("Unknown DUPLICATE_POLICY"))?);
+            }
+            b"on_duplicate" if allow_on_duplicate => {
+                let policy = args.get(i + 1).and_then(|arg| DuplicatePolicy::parse(arg));
+                options.on_duplicate = Some(policy.ok_or_else(|| tsdb_error("Unknown ON_DUPLICATE policy"))?);
+            }
+            b"labels" => {
+                let labels = &args[i + 1..];
+                if labels.is_empty() || !labels.chunks_exact(2).remainder().is_empty() {
+                    return Err(tsdb_error("Invalid labels"));
+                }
+                options.labels = labels.chunks_exact(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect();
+                break;
+            }
+            _ => return Err(tsdb_error("wrong arguments")),
+        }
+        i += 2;
+    }
+    Ok(options)
+}
+
+fn parse_range_bound(arg: &[u8], open: &[u8], open_value: u64) -> Option<u64> {
+    if arg == open {
+        Some(open_value)
+    } else {
+        parse_integer_arg::<u64>(arg)
+    }
+}
+
+impl Command {
+    pub fn parse_ts_create(args: &[Vec<u8>]) -> Command {
+        if args.len() < 2 {
+            return wrong_number_of_args("ts.create");
+        }
+        match parse_ts_options(&args[2..], false) {
+            Ok(options) => Command::TSCREATE(args[1].clone(), Box::new(options)),
+            Err(err) => err,
+        }
+    }
+
+    // TS.ADD key timestamp|* value [options]
+    pub fn parse_ts_add(args: &[Vec<u8>]) -> Command {
+        if args.len() < 4 {
+            return wrong_number_of_args("ts.add");
+        }
+        let timestamp = match args[2].as_slice() {
+            b"*" => None,
+            arg => match parse_integer_arg::<u64>(arg) {
+                Some(timestamp) => Some(timestamp),
+                None => return tsdb_error("invalid timestamp, must be a nonnegative integer"),
+            },
+        };
+        let Some(value) = parse_integer_arg::<f64>(&args[3]).filter(|value| !value.is_nan()) else {
+            return tsdb_error("invalid value");
+        };
+        match parse_ts_options(&args[4..], true) {
+            Ok(options) => Command::TSADD(args[1].clone(), timestamp, value, Box::new(options)),
+            Err(err) => err,
+        }
+    }
+
+    pub fn parse_ts_get(args: &[Vec<u8>]) -> Command {
+        if args.len() != 2 {
+            return wrong_number_of_args("ts.get");
+        }
+        Command::TSGET(args[1].clone())
+    }
+
+    pub fn parse_ts_info(args: &[Vec<u8>]) -> Command {
+        if args.len() != 2 {
+            return wrong_number_of_args("ts.info");
+        }
+        Command::TSINFO(args[1].clone())
+    }
+
+    // TS.RANGE key from to [COUNT count] [AGGREGATION aggregator bucketDuration]
+    pub fn parse_ts_range(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() < 4 {
+            return wrong_number_of_args(name);
+        }
+        let Some(from) = parse_range_bound(&args[2], b"-", 0) else {
+            return tsdb_error("wrong fromTimestamp");
+        };
+        let Some(to) = parse_range_bound(&args[3], b"+", u64::MAX) else {
+            return tsdb_error("wrong toTimestamp");
+        };
+        let mut range = TsRange { from, to, count: None, aggregation: None, rev: name == "ts.revrange" };
+        let mut i = 4;
+        while let Some(arg) = args.get(i) {
+            match arg.to_ascii_lowercase().as_slice() {
+                b"count" => match args.get(i + 1).and_then(|arg| parse_integer_arg::<usize>(arg)) {
+                    Some(count) => range.count = Some(count),
+                    None => return tsdb_error("Couldn't parse COUNT"),
+                },
+                b"aggregation" => {
+                    let Some(aggregation) = args.get(i + 1).and_then(|arg| Aggregation::parse(arg)) else {
+                        return tsdb_error("Unknown aggregation type");
+                    };
+                    match args.get(i + 2).and_then(|arg| parse_integer_arg::<u64>(arg)) {
+                        Some(bucket) if bucket > 0 => range.aggregation = Some((aggregation, bucket)),
+                        _ => return tsdb_error("bucketDuration must be greater than zero"),
+                    }
+                    i += 1;
+                }
+                _ => return tsdb_error("wrong arguments"),
+            }
+            i += 2;
+        }
+        Command::TSRANGE(args[1].clone(), Box::new(range))
+    }
+}
+
+impl State {
+    fn create_time_series(&mut self, key: &[u8], options: &TsOptions) {
+        let policy = options.duplicate_policy.unwrap_or(DuplicatePolicy::Block);
+        let series = TimeSeries::new(options.retention.unwrap_or(0), policy, options.labels.clone());
+        self.datastore.insert(key.to_vec(), DataStoreValue::new(Value::TimeSeries(series), None));
+        self.notify_keyspace_event(NOTIFY_MODULE, "ts.create", key);
+    }
+
+    pub fn ts_create(&mut self, key: &[u8], options: &TsOptions) -> CommandResult {
+        if self.get_value(key).is_some() {
+            return Err(DataType::SimpleError("ERR TSDB: key already exists".to_string()));
+        }
+        self.create_time_series(key, options);
+        Ok(DataType::ok())
+    }
+
+    pub fn ts_add(&mut self, key: &[u8], timestamp: Option<u64>, value: f64, options: &TsOptions) -> CommandResult {
+        if self.get_time_series(key)?.is_none() {
+            self.create_time_series(key, options);
+        }
+        let series = self.get_time_series(key)?.unwrap();
+        let timestamp = timestamp.unwrap_or_else(unix_time_ms);
+        let timestamp = series.add(timestamp, value, options.on_duplicate).map_err(|msg| DataType::SimpleError(msg.to_string()))?;
+        self.notify_keyspace_event(NOTIFY_MODULE, "ts.add", key);
+        Ok(DataType::Integer(timestamp as i64))
+    }
+
+    pub fn ts_get(&mut self, key: &[u8]) -> CommandResult {
+        let series = self.get_time_series(key)?.ok_or_else(key_does_not_exist)?;
+        Ok(series.last().map_or(DataType::Array(Vec::new()), sample_reply))
+    }
+
+    pub fn ts_range(&mut self, key: &[u8], range: &TsRange) -> CommandResult {
+        let series = self.get_time_series(key)?.ok_or_else(key_does_not_exist)?;
+        let mut samples = series.range(range.from, range.to, range.aggregation);
+        if range.rev {
+            samples.reverse();
+        }
+        samples.truncate(range.count.unwrap_or(usize::MAX));
+        Ok(DataType::Array(samples.into_iter().map(sample_reply).collect()))
+    }
+
+    pub fn ts_info(&mut self, key: &[u8]) -> CommandResult {
+        let series = self.get_time_series(key)?.ok_or_else(key_does_not_exist)?;
+        let labels = series.labels().iter()
+            .map(|(label, value)| DataType::Array(vec![DataType::BulkString(label.clone()), DataType::BulkString(value.clone())]));
+        let info = vec![
+            ("totalSamples", DataType::Integer(series.len() as i64)),
+            ("firstTimestamp", DataType::Integer(series.first().map_or(0, |(timestamp, _)| timestamp as i64))),
+            ("lastTimestamp", DataType::Integer(series.last().map_or(0, |(timestamp, _)| timestamp as i64))),
+            ("retentionTime", DataType::Integer(series.retention() as i64)),
+            ("duplicatePolicy", DataType::BulkString(series.duplicate_policy().name().as_bytes().to_vec())),
+            ("labels", DataType::Array(labels.collect())),
+        ];
+        let reply = info.into_iter().flat_map(|(name, value)| [DataType::SimpleString(name.to_string()), value]);
+        Ok(DataType::Array(reply.collect()))
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{
+    command::{parse_integer_arg, syntax_error, wrong_number_of_args, Command},
+    commands::zset::format_score,
+    notify::NOTIFY_MODULE,
+    resp::DataType,
+    state::{CommandResult, DataStoreValue, State, Value},
+    types::topk::TopK,
+};
+
+// Bucket layout used when TOPK.RESERVE is only given k
+const DEFAULT_WIDTH: usize = 8;
+const DEFAULT_DEPTH: usize = 7;
+const DEFAULT_DECAY: f64 = 0.9;
+
+// Largest count TOPK.INCRBY accepts in one step, since every step may roll the decay dice
+const MAX_INCREMENT: u64 = 100000;
+
+fn topk_error(msg: &str) -> Command {
+    Command::INVALID(format!("TopK: {}", msg))
+}
+
+fn key_does_not_exist() -> DataType {
+    DataType::SimpleError("TopK: key does not exist".to_string())
+}
+
+fn parse_positive(arg: &[u8]) -> Option<usize> {
+    parse_integer_arg::<usize>(arg).filter(|&n| n > 0)
+}
+
+impl Command {
+    // TOPK.RESERVE key topk [width depth decay]
+    pub fn parse_topk_reserve(args: &[Vec<u8>]) -> Command {
+        if args.len() != 3 && args.len() != 6 {
+            return wrong_number_of_args("topk.reserve");
+        }
+        let Some(k) = parse_positive(&args[2]) else {
+            return topk_error("invalid k");
+        };
+        let (mut width, mut depth, mut decay) = (DEFAULT_WIDTH, DEFAULT_DEPTH, DEFAULT_DECAY);
+        if args.len() == 6 {
+            let Some(w) = parse_positive(&args[3]) else {
+                return topk_error("invalid width");
+            };
+            let Some(d) = parse_positive(&args[4]) else {
+                return topk_error("invalid depth");
+            };
+            match parse_integer_arg::<f64>(&args[5]) {
+                Some(value) if value > 0.0 && value <= 1.0 => decay = value,
+                _ => return topk_error("invalid decay value. must be '<= 1' & '> 0'"),
+            }
+            (width, depth) = (w, d);
+        }
+        Command::TOPKRESERVE(args[1].clone(), k, width, depth, decay)
+    }
+
+    // TOPK.ADD counts each item once, TOPK.INCRBY takes item/increment pairs
+    pub fn parse_topk_add(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 {
+            return wrong_number_of_args(name);
+        }
+        if name == "topk.add" {
+            return Command::TOPKADD(args[1].clone(), args[2..].iter().map(|item| (item.clone(), 1)).collect());
+        }
+        if !args[2..].chunks_exact(2).remainder().is_empty() {
+            return wrong_number_of_args(name);
+        }
+        let mut increments = Vec::with_capacity((args.len() - 2) / 2);
+        for pair in args[2..].chunks_exact(2) {
+            match parse_integer_arg::<u64>(&pair[1]) {
+                Some(by) if by <= MAX_INCREMENT => increments.push((pair[0].clone(), by)),
+                _ => return topk_error("increment must be an integer greater or equal to 0 and smaller or equal to 100000"),
+            }
+        }
+        Command::TOPKADD(args[1].clone(), increments)
+    }
+
+    // TOPK.QUERY and TOPK.COUNT look up one or more items
+    pub fn parse_topk_items(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 {
+            return wrong_number_of_args(name);
+        }
+        let (key, items) = (args[1].clone(), args[2..].to_vec());
+        match name {
+            "topk.query" => Command::TOPKQUERY(key, items),
+            _ => Command::TOPKCOUNT(key, items),
+        }
+    }
+
+    pub fn parse_topk_list(args: &[Vec<u8>]) -> Command {
+        let with_count = match args.len() {
+            2 => false,
+            3 if args[2].eq_ignore_ascii_case(b"withcount") => true,
+            3 => return syntax_error(),
+            _ => return wrong_number_of_args("topk.list"),
+        };
+        Command::TOPKLIST(args[1].clone(), with_count)
+    }
+
+    pub fn parse_topk_info(args: &[Vec<u8>]) -> Command {
+        if args.len() != 2 {
+            return wrong_number_of_args("topk.info");
+        }
+        Command::TOPKINFO(args[1].clone())
+    }
+}
+
+impl State {
+    pub fn topk_reserve(&mut self, key: &[u8], k: usize, width: usize, depth: usize, decay: f64) -> CommandResult {
+        if self.get_value(key).is_some() {
+            return Err(DataType::SimpleError("TopK: key already exists".to_string()));
+        }
+        let topk = TopK::new(k, width, depth, decay);
+        self.datastore.insert(key.to_vec(), DataStoreValue::new(Value::TopK(topk), None));
+        self.notify_keyspace_event(NOTIFY_MODULE, "topk.reserve", key);
+        Ok(DataType::ok())
+    }
+
+    // Reply with the item each addition expelled from the list, or nil
+    pub fn topk_add(&mut self, key: &[u8], increments: &[(Vec<u8>, u64)]) -> CommandResult {
+        let topk = self.get_topk(key)?.ok_or_else(key_does_not_exist)?;
+        let expelled = increments.iter().map(|(item, by)| match topk.add(item, *by) {
+            Some(expelled) => DataType::BulkString(expelled),
+            None => DataType::NullBulkString,
+        }).collect();
+        self.notify_keyspace_event(NOTIFY_MODULE, "topk.add", key);
+        Ok(DataType::Array(expelled))
+    }
+
+    pub fn topk_query(&mut self, key: &[u8], items: &[Vec<u8>]) -> CommandResult {
+        let topk = self.get_topk(key)?.ok_or_else(key_does_not_exist)?;
+        let found = items.iter().map(|item| DataType::Integer(topk.contains(item) as i64));
+        Ok(DataType::Array(found.collect()))
+    }
+
+    pub fn topk_count(&mut self, key: &[u8], items: &[Vec<u8>]) -> CommandResult {
+        let topk = self.get_topk(key)?.ok_or_else(key_does_not_exist)?;
+        let counts = items.iter().map(|item| DataType::Integer(topk.count(item) as i64));
+        Ok(DataType::Array(counts.collect()))
+    }
+
+    pub fn topk_list(&mut self, key: &[u8], with_count: bool) -> CommandResult {
+        let topk = self.get_topk(key)?.ok_or_else(key_does_not_exist)?;
+        let mut reply = Vec::new();
+        for (item, count) in topk.list() {
+            reply.push(DataType::BulkString(item));
+            if with_count {
+                reply.push(DataType::Integer(count as i64));
+            }
+        }
+        Ok(DataType::Array(reply))
+    }
+
+    pub fn topk_info(&mut self, key: &[u8]) -> CommandResult {
+        let topk = self.get_topk(key)?.ok_or_else(key_does_not_exist)?;
+        Ok(DataType::Array(vec![
+            DataType::SimpleString("k".to_string()),
+            DataType::Integer(topk.k() as i64),
+            DataType::SimpleString("width".to_string()),
+            DataType::Integer(topk.width() as i64),
+            DataType::SimpleString("depth".to_string()),
+            DataType::Integer(topk.depth() as i64),
+            DataType::SimpleString("decay".to_string()),
+            DataType::BulkString(format_score(topk.decay())),
+        ]))
+    }
+}
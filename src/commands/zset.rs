@@ -0,0 +1,779 @@
+use std::collections::HashMap;
+
+use crate::{
+    command::{not_an_integer, parse_integer_arg, parse_numkeys, parse_random_count, parse_timeout_arg, syntax_error, wrong_number_of_args, Command},
+    commands::{normalize_range, parse_cursor, scan_items, scan_reply, set::SetOperation, ScanOptions},
+    notify::{NOTIFY_GENERIC, NOTIFY_ZSET},
+    random::{random_index, sample_distinct, sample_with_repeats, REPLY_TOO_LARGE},
+    resp::DataType,
+    state::{wrong_type_error, CommandResult, DataStoreValue, State, Value},
+    types::zset::{LexBound, LexRange, ScoreRange, SortedSet},
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct ZaddOptions {
+    nx: bool,
+    xx: bool,
+    gt: bool,
+    lt: bool,
+    ch: bool,
+    incr: bool,
+}
+
+impl ZaddOptions {
+    // The subset of flags GEOADD accepts before handing its pairs to ZADD
+    pub fn geoadd(nx: bool, xx: bool, ch: bool) -> Self {
+        ZaddOptions { nx, xx, ch, ..Default::default() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RangeKind {
+    Rank,
+    Score,
+    Lex,
+}
+
+#[derive(Debug, Clone)]
+pub enum ZrangeBy {
+    Rank(i64, i64),
+    Score(ScoreRange),
+    Lex(LexRange),
+}
+
+#[derive(Debug, Clone)]
+pub struct ZrangeSpec {
+    by: ZrangeBy,
+    rev: bool,
+    limit: Option<(i64, i64)>,
+    with_scores: bool,
+}
+
+// How scores of a member present in several inputs are combined
+#[derive(Debug, Clone, Copy)]
+pub enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            // inf + -inf is taken to be zero rather than NaN
+            Aggregate::Sum => match a + b {
+                sum if sum.is_nan() => 0.0,
+                sum => sum,
+            },
+            Aggregate::Min => a.min(b),
+            Aggregate::Max => a.max(b),
+        }
+    }
+}
+
+// Inputs of ZUNIONSTORE and ZINTERSTORE, one weight per key
+#[derive(Debug, Clone)]
+pub struct ZsetAlgebraInputs {
+    pub keys: Vec<Vec<u8>>,
+    weights: Vec<f64>,
+    aggregate: Aggregate,
+}
+
+pub fn not_a_float() -> Command {
+    Command::INVALID("ERR value is not a valid float".to_string())
+}
+
+// Scores accept anything strtod would, including inf/-inf, but never NaN
+pub fn parse_score(arg: &[u8]) -> Option<f64> {
+    parse_integer_arg::<f64>(arg).filter(|score| !score.is_nan())
+}
+
+// Score bounds are inclusive unless prefixed with `(`
+fn parse_score_bound(arg: &[u8]) -> Option<(f64, bool)> {
+    match arg.strip_prefix(b"(") {
+        Some(score) => Some((parse_score(score)?, true)),
+        None => Some((parse_score(arg)?, false)),
+    }
+}
+
+pub fn parse_score_range(min: &[u8], max: &[u8]) -> Option<ScoreRange> {
+    let (min, min_exclusive) = parse_score_bound(min)?;
+    let (max, max_exclusive) = parse_score_bound(max)?;
+    Some(ScoreRange { min, min_exclusive, max, max_exclusive })
+}
+
+fn parse_lex_bound(arg: &[u8]) -> Option<LexBound> {
+    match arg.split_first() {
+        Some((b'-', [])) => Some(LexBound::Min),
+        Some((b'+', [])) => Some(LexBound::Max),
+        Some((b'[', value)) => Some(LexBound::Inclusive(value.to_vec())),
+        Some((b'(', value)) => Some(LexBound::Exclusive(value.to_vec())),
+        _ => None,
+    }
+}
+
+pub fn parse_lex_range(min: &[u8], max: &[u8]) -> Option<LexRange> {
+    Some(LexRange { min: parse_lex_bound(min)?, max: parse_lex_bound(max)? })
+}
+
+// Like parse_numkeys, but naming the command when no input key is given
+fn parse_zset_numkeys(name: &str, args: &[Vec<u8>], start: usize) -> Result<(Vec<Vec<u8>>, usize), Command> {
+    match parse_integer_arg::<i64>(&args[start]) {
+        Some(numkeys) if numkeys < 1 => Err(Command::INVALID(format!("ERR at least 1 input key is needed for '{}' command", name))),
+        _ => parse_numkeys(args, start),
+    }
+}
+
+// Scores are replied in their shortest round-tripping form, with exponents for very large or
+// small magnitudes the way Redis prints them
+pub fn format_score(score: f64) -> Vec<u8> {
+    let formatted = if score.is_infinite() {
+        if score > 0.0 { "inf".to_string() } else { "-inf".to_string() }
+    } else if score != 0.0 && (score.abs() >= 1e21 || score.abs() < 1e-6) {
+        let formatted = format!("{:e}", score);
+        match formatted.split_once('e') {
+            Some((mantissa, exponent)) if !exponent.starts_with('-') => format!("{}e+{}", mantissa, exponent),
+            _ => formatted,
+        }
+    } else {
+        score.to_string()
+    };
+    formatted.into_bytes()
+}
+
+// Flatten member/score pairs into a reply, leaving out the scores unless asked for
+pub fn scored_reply(elements: Vec<(Vec<u8>, f64)>, with_scores: bool) -> DataType {
+    DataType::Array(scored_items(elements, with_scores))
+}
+
+fn scored_items(elements: Vec<(Vec<u8>, f64)>, with_scores: bool) -> Vec<DataType> {
+    let mut items = Vec::with_capacity(elements.len() * if with_scores { 2 } else { 1 });
+    for (member, score) in elements {
+        items.push(DataType::BulkString(member));
+        if with_scores {
+            items.push(DataType::BulkString(format_score(score)));
+        }
+    }
+    items
+}
+
+impl Command {
+    pub fn parse_zadd(args: &[Vec<u8>]) -> Command {
+        if args.len() < 4 {
+            return wrong_number_of_args("zadd");
+        }
+        let mut options = ZaddOptions::default();
+        let mut i = 2;
+        while let Some(arg) = args.get(i) {
+            match String::from_utf8_lossy(arg).to_lowercase().as_str() {
+                "nx" => options.nx = true,
+                "xx" => options.xx = true,
+                "gt" => options.gt = true,
+                "lt" => options.lt = true,
+                "ch" => options.ch = true,
+                "incr" => options.incr = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        if args.len() == i || !args[i..].chunks_exact(2).remainder().is_empty() {
+            return syntax_error();
+        }
+        if options.nx && options.xx {
+            return Command::INVALID("ERR XX and NX options at the same time are not compatible".to_string());
+        }
+        if (options.gt && options.lt) || (options.nx && (options.gt || options.lt)) {
+            return Command::INVALID("ERR GT, LT, and/or NX options at the same time are not compatible".to_string());
+        }
+        if options.incr && args.len() - i > 2 {
+            return Command::INVALID("ERR INCR option supports a single increment-element pair".to_string());
+        }
+        let mut pairs = Vec::with_capacity((args.len() - i) / 2);
+        for pair in args[i..].chunks_exact(2) {
+            match parse_score(&pair[0]) {
+                Some(score) => pairs.push((score, pair[1].clone())),
+                None => return not_a_float(),
+            }
+        }
+        Command::ZADD(args[1].clone(), options, pairs)
+    }
+
+    pub fn parse_zscore(args: &[Vec<u8>]) -> Command {
+        if args.len() != 3 {
+            return wrong_number_of_args("zscore");
+        }
+        Command::ZSCORE(args[1].clone(), args[2].clone())
+    }
+
+    pub fn parse_zcard(args: &[Vec<u8>]) -> Command {
+        if args.len() != 2 {
+            return wrong_number_of_args("zcard");
+        }
+        Command::ZCARD(args[1].clone())
+    }
+
+    pub fn parse_zrank(name: &str, args: &[Vec<u8>]) -> Command {
+        let with_score = match args.len() {
+            3 => false,
+            4 if args[3].eq_ignore_ascii_case(b"withscore") => true,
+            4 => return syntax_error(),
+            _ => return wrong_number_of_args(name),
+        };
+        let (key, member) = (args[1].clone(), args[2].clone());
+        match name {
+            "zrank" => Command::ZRANK(key, member, with_score),
+            _ => Command::ZREVRANK(key, member, with_score),
+        }
+    }
+
+    pub fn parse_zincrby(args: &[Vec<u8>]) -> Command {
+        if args.len() != 4 {
+            return wrong_number_of_args("zincrby");
+        }
+        match parse_score(&args[2]) {
+            Some(increment) => Command::ZINCRBY(args[1].clone(), increment, args[3].clone()),
+            None => not_a_float(),
+        }
+    }
+
+    pub fn parse_zrem(args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 {
+            return wrong_number_of_args("zrem");
+        }
+        Command::ZREM(args[1].clone(), args[2..].to_vec())
+    }
+
+    pub fn parse_zremrange(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() != 4 {
+            return wrong_number_of_args(name);
+        }
+        let (key, min, max) = (args[1].clone(), &args[2], &args[3]);
+        match name {
+            "zremrangebyrank" => match (parse_integer_arg::<i64>(min), parse_integer_arg::<i64>(max)) {
+                (Some(start), Some(stop)) => Command::ZREMRANGEBYRANK(key, start, stop),
+                _ => not_an_integer(),
+            },
+            "zremrangebyscore" => match parse_score_range(min, max) {
+                Some(range) => Command::ZREMRANGEBYSCORE(key, range),
+                None => Command::INVALID("ERR min or max is not a float".to_string()),
+            },
+            _ => match parse_lex_range(min, max) {
+                Some(range) => Command::ZREMRANGEBYLEX(key, range),
+                None => Command::INVALID("ERR min or max not valid string range item".to_string()),
+            },
+        }
+    }
+
+    pub fn parse_zset_algebra_store(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() < 4 {
+            return wrong_number_of_args(name);
+        }
+        let (keys, mut i) = match parse_zset_numkeys(name, args, 2) {
+            Ok(parsed) => parsed,
+            Err(err) => return err,
+        };
+        let mut inputs = ZsetAlgebraInputs { weights: vec![1.0; keys.len()], keys, aggregate: Aggregate::Sum };
+        while i < args.len() {
+            match String::from_utf8_lossy(&args[i]).to_lowercase().as_str() {
+                "weights" if args.len() > i + inputs.keys.len() => {
+                    for (weight, arg) in inputs.weights.iter_mut().zip(&args[i + 1..]) {
+                        match parse_score(arg) {
+                            Some(value) => *weight = value,
+                            None => return Command::INVALID("ERR weight value is not a float".to_string()),
+                        }
+                    }
+                    i += inputs.keys.len();
+                }
+                "aggregate" if i + 1 < args.len() => {
+                    inputs.aggregate = match String::from_utf8_lossy(&args[i + 1]).to_lowercase().as_str() {
+                        "sum" => Aggregate::Sum,
+                        "min" => Aggregate::Min,
+                        "max" => Aggregate::Max,
+                        _ => return syntax_error(),
+                    };
+                    i += 1;
+                }
+                _ => return syntax_error(),
+            }
+            i += 1;
+        }
+        let destination = args[1].clone();
+        match name {
+            "zunionstore" => Command::ZUNIONSTORE(destination, inputs),
+            _ => Command::ZINTERSTORE(destination, inputs),
+        }
+    }
+
+    pub fn parse_zdiff(name: &str, args: &[Vec<u8>]) -> Command {
+        let store = name == "zdiffstore";
+        let start = if store { 2 } else { 1 };
+        if args.len() < start + 2 {
+            return wrong_number_of_args(name);
+        }
+        let (keys, i) = match parse_zset_numkeys(name, args, start) {
+            Ok(parsed) => parsed,
+            Err(err) => return err,
+        };
+        match &args[i..] {
+            [] if store => Command::ZDIFFSTORE(args[1].clone(), keys),
+            [] => Command::ZDIFF(keys, false),
+            [option] if !store && option.eq_ignore_ascii_case(b"withscores") => Command::ZDIFF(keys, true),
+            _ => syntax_error(),
+        }
+    }
+
+    pub fn parse_zrandmember(args: &[Vec<u8>]) -> Command {
+        if args.len() < 2 || args.len() > 4 {
+            return wrong_number_of_args("zrandmember");
+        }
+        let count = match args.get(2).map(|count| parse_random_count(count)).transpose() {
+            Ok(count) => count,
+            Err(err) => return err,
+        };
+        let with_scores = match args.get(3) {
+            Some(arg) if arg.eq_ignore_ascii_case(b"withscores") => true,
+            Some(_) => return syntax_error(),
+            None => false,
+        };
+        Command::ZRANDMEMBER(args[1].clone(), count, with_scores)
+    }
+
+    pub fn parse_zmscore(args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 {
+            return wrong_number_of_args("zmscore");
+        }
+        Command::ZMSCORE(args[1].clone(), args[2..].to_vec())
+    }
+
+    pub fn parse_zcount(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() != 4 {
+            return wrong_number_of_args(name);
+        }
+        let (key, min, max) = (args[1].clone(), &args[2], &args[3]);
+        match name {
+            "zcount" => match parse_score_range(min, max) {
+                Some(range) => Command::ZCOUNT(key, range),
+                None => Command::INVALID("ERR min or max is not a float".to_string()),
+            },
+            _ => match parse_lex_range(min, max) {
+                Some(range) => Command::ZLEXCOUNT(key, range),
+                None => Command::INVALID("ERR min or max not valid string range item".to_string()),
+            },
+        }
+    }
+
+    pub fn parse_zscan(args: &[Vec<u8>]) -> Command {
+        if args.len() < 3 {
+            return wrong_number_of_args("zscan");
+        }
+        let cursor = match parse_cursor(&args[2]) {
+            Ok(cursor) => cursor,
+            Err(err) => return err,
+        };
+        match ScanOptions::parse(&args[3..], false) {
+            Ok(options) => Command::ZSCAN(args[1].clone(), cursor, options),
+            Err(err) => err,
+        }
+    }
+
+    pub fn parse_zmpop(name: &str, args: &[Vec<u8>]) -> Command {
+        let blocking = name == "bzmpop";
+        let first = if blocking { 2 } else { 1 };
+        if args.len() < first + 3 {
+            return wrong_number_of_args(name);
+        }
+        let (keys, mut i) = match parse_numkeys(args, first) {
+            Ok(parsed) => parsed,
+            Err(err) => return err,
+        };
+        let min = match args.get(i).map(|arg| arg.to_ascii_lowercase()) {
+            Some(arg) if arg == b"min" => true,
+            Some(arg) if arg == b"max" => false,
+            _ => return syntax_error(),
+        };
+        i += 1;
+        let mut count = 1;
+        if i < args.len() {
+            if args.len() != i + 2 || !args[i].eq_ignore_ascii_case(b"count") {
+                return syntax_error();
+            }
+            count = match parse_integer_arg::<i64>(&args[i + 1]) {
+                Some(count) if count > 0 => count as usize,
+                _ => return Command::INVALID("ERR count should be greater than 0".to_string()),
+            };
+        }
+        if !blocking {
+            return Command::ZMPOP(keys, min, count);
+        }
+        match parse_timeout_arg(&args[1]) {
+            Ok(timeout) => Command::BZMPOP(keys, min, count, timeout),
+            Err(err) => err,
+        }
+    }
+
+    // Handles ZRANGE and ZRANGESTORE along with the older ZREVRANGE and *BYSCORE/*BYLEX forms
+    pub fn parse_zrange(name: &str, args: &[Vec<u8>]) -> Command {
+        let store = name == "zrangestore";
+        let first = if store { 3 } else { 2 };
+        if args.len() < first + 2 {
+            return wrong_number_of_args(name);
+        }
+        let unified = store || name == "zrange";
+        let (mut kind, mut rev) = match name {
+            "zrevrange" => (RangeKind::Rank, true),
+            "zrangebyscore" => (RangeKind::Score, false),
+            "zrevrangebyscore" => (RangeKind::Score, true),
+            "zrangebylex" => (RangeKind::Lex, false),
+            "zrevrangebylex" => (RangeKind::Lex, true),
+            _ => (RangeKind::Rank, false),
+        };
+        let (mut limit, mut with_scores) = (None, false);
+        let mut i = first + 2;
+        while i < args.len() {
+            match String::from_utf8_lossy(&args[i]).to_lowercase().as_str() {
+                "withscores" if !store => with_scores = true,
+                "byscore" if unified => kind = RangeKind::Score,
+                "bylex" if unified => kind = RangeKind::Lex,
+                "rev" if unified => rev = true,
+                "limit" if i + 2 < args.len() => {
+                    match (parse_integer_arg::<i64>(&args[i + 1]), parse_integer_arg::<i64>(&args[i + 2])) {
+                        (Some(offset), Some(count)) => limit = Some((offset, count)),
+                        _ => return not_an_integer(),
+                    }
+                    i += 2;
+                }
+                _ => return syntax_error(),
+            }
+            i += 1;
+        }
+        if limit.is_some() && kind == RangeKind::Rank {
+            return Command::INVALID("ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX".to_string());
+        }
+        if with_scores && kind == RangeKind::Lex {
+            return Command::INVALID("ERR syntax error, WITHSCORES not supported in combination with BYLEX".to_string());
+        }
+
+        // Reversed score and lex ranges name the upper bound first
+        let (min, max) = match kind {
+            RangeKind::Score | RangeKind::Lex if rev => (&args[first + 1], &args[first]),
+            _ => (&args[first], &args[first + 1]),
+        };
+        let by = match kind {
+            RangeKind::Rank => match (parse_integer_arg::<i64>(min), parse_integer_arg::<i64>(max)) {
+                (Some(start), Some(stop)) => ZrangeBy::Rank(start, stop),
+                _ => return not_an_integer(),
+            },
+            RangeKind::Score => match parse_score_range(min, max) {
+                Some(range) => ZrangeBy::Score(range),
+                None => return Command::INVALID("ERR min or max is not a float".to_string()),
+            },
+            RangeKind::Lex => match parse_lex_range(min, max) {
+                Some(range) => ZrangeBy::Lex(range),
+                None => return Command::INVALID("ERR min or max not valid string range item".to_string()),
+            },
+        };
+        let spec = Box::new(ZrangeSpec { by, rev, limit, with_scores });
+        if store {
+            Command::ZRANGESTORE(args[1].clone(), args[2].clone(), spec)
+        } else {
+            Command::ZRANGE(args[1].clone(), spec)
+        }
+    }
+}
+
+impl State {
+    // Replies with the number of added (or with CH, changed) members, or in INCR mode with the
+    // new score, nil when a condition prevented the update
+    pub fn zadd(&mut self, key: &[u8], options: &ZaddOptions, pairs: Vec<(f64, Vec<u8>)>) -> CommandResult {
+        let zset = match self.get_sorted_set(key)? {
+            None if options.xx => {
+                return Ok(if options.incr { DataType::NullBulkString } else { DataType::Integer(0) });
+            }
+            _ => self.get_or_create_sorted_set(key)?,
+        };
+        let (mut added, mut updated, mut result) = (0, 0, None);
+        for (score, member) in pairs {
+            let current = zset.score(&member);
+            let score = match current {
+                Some(current) if options.incr => current + score,
+                _ => score,
+            };
+            if score.is_nan() {
+                return Err(DataType::SimpleError("ERR resulting score is not a number (NaN)".to_string()));
+            }
+            match current {
+                None if options.xx => continue,
+                None => added += 1,
+                Some(_) if options.nx => continue,
+                Some(current) if (options.gt && score <= current) || (options.lt && score >= current) => continue,
+                Some(current) if score != current => updated += 1,
+                Some(_) => (),
+            }
+            zset.insert(member, score);
+            result = Some(score);
+        }
+        if options.incr && result.is_some() {
+            self.notify_keyspace_event(NOTIFY_ZSET, "zincr", key);
+        } else if added + updated > 0 {
+            self.notify_keyspace_event(NOTIFY_ZSET, "zadd", key);
+        }
+        Ok(match options {
+            ZaddOptions { incr: true, .. } => result.map_or(DataType::NullBulkString, |score| DataType::BulkString(format_score(score))),
+            ZaddOptions { ch: true, .. } => DataType::Integer(added + updated),
+            _ => DataType::Integer(added),
+        })
+    }
+
+    pub fn zscore(&mut self, key: &[u8], member: &[u8]) -> CommandResult {
+        let score = self.get_sorted_set(key)?.and_then(|zset| zset.score(member));
+        Ok(score.map_or(DataType::NullBulkString, |score| DataType::BulkString(format_score(score))))
+    }
+
+    pub fn zcard(&mut self, key: &[u8]) -> CommandResult {
+        let len = self.get_sorted_set(key)?.map_or(0, |zset| zset.len());
+        Ok(DataType::Integer(len as i64))
+    }
+
+    pub fn zrank(&mut self, key: &[u8], member: &[u8], rev: bool, with_score: bool) -> CommandResult {
+        let rank = self.get_sorted_set(key)?.and_then(|zset| zset.rank(member, rev));
+        Ok(match rank {
+            Some((rank, score)) if with_score => {
+                DataType::Array(vec![DataType::Integer(rank as i64), DataType::BulkString(format_score(score))])
+            }
+            Some((rank, _)) => DataType::Integer(rank as i64),
+            None if with_score => DataType::NullArray,
+            None => DataType::NullBulkString,
+        })
+    }
+
+    pub fn zincrby(&mut self, key: &[u8], increment: f64, member: Vec<u8>) -> CommandResult {
+        let options = ZaddOptions { incr: true, ..Default::default() };
+        self.zadd(key, &options, vec![(increment, member)])
+    }
+
+    pub fn zrem(&mut self, key: &[u8], members: &[Vec<u8>]) -> CommandResult {
+        let zset = match self.get_sorted_set(key)? {
+            Some(zset) => zset,
+            None => return Ok(DataType::Integer(0)),
+        };
+        let removed = members.iter().filter(|member| zset.remove(member)).count();
+        if removed > 0 {
+            self.notify_keyspace_event(NOTIFY_ZSET, "zrem", key);
+        }
+        self.remove_if_empty(key);
+        Ok(DataType::Integer(removed as i64))
+    }
+
+    pub fn zremrange(&mut self, key: &[u8], by: ZrangeBy) -> CommandResult {
+        let event = match by {
+            ZrangeBy::Rank(..) => "zremrangebyrank",
+            ZrangeBy::Score(_) => "zremrangebyscore",
+            ZrangeBy::Lex(_) => "zremrangebylex",
+        };
+        let spec = ZrangeSpec { by, rev: false, limit: None, with_scores: false };
+        let elements = self.zrange_elements(key, &spec)?;
+        if let Some(zset) = self.get_sorted_set(key)? {
+            for (member, _) in &elements {
+                zset.remove(member);
+            }
+        }
+        if !elements.is_empty() {
+            self.notify_keyspace_event(NOTIFY_ZSET, event, key);
+        }
+        self.remove_if_empty(key);
+        Ok(DataType::Integer(elements.len() as i64))
+    }
+
+    // Random members: a positive count returns distinct members, a negative one allows repeats
+    pub fn zrandmember(&mut self, key: &[u8], count: Option<i64>, with_scores: bool) -> CommandResult {
+        let zset = match self.get_sorted_set(key)? {
+            Some(zset) => zset,
+            None if count.is_some() => return Ok(DataType::Array(vec![])),
+            None => return Ok(DataType::NullBulkString),
+        };
+        let elements: Vec<(&[u8], f64)> = zset.iter().collect();
+        let count = match count {
+            Some(count) => count,
+            None => return Ok(DataType::BulkString(elements[random_index(elements.len())].0.to_vec())),
+        };
+        let picked = if count >= 0 {
+            sample_distinct(elements, count as usize)
+        } else {
+            match sample_with_repeats(&elements, count.unsigned_abs() as usize) {
+                Some(picked) => picked,
+                None => return Err(DataType::SimpleError(REPLY_TOO_LARGE.to_string())),
+            }
+        };
+        let picked = picked.into_iter().map(|(member, score)| (member.to_vec(), score)).collect();
+        Ok(scored_reply(picked, with_scores))
+    }
+
+    pub fn zmscore(&mut self, key: &[u8], members: &[Vec<u8>]) -> CommandResult {
+        let zset = self.get_sorted_set(key)?;
+        let scores = members.iter().map(|member| match zset.as_ref().and_then(|zset| zset.score(member)) {
+            Some(score) => DataType::BulkString(format_score(score)),
+            None => DataType::NullBulkString,
+        });
+        Ok(DataType::Array(scores.collect()))
+    }
+
+    pub fn zcount(&mut self, key: &[u8], range: &ScoreRange) -> CommandResult {
+        let count = self.get_sorted_set(key)?.map_or(0, |zset| zset.count_by_score(range));
+        Ok(DataType::Integer(count as i64))
+    }
+
+    pub fn zlexcount(&mut self, key: &[u8], range: &LexRange) -> CommandResult {
+        let count = self.get_sorted_set(key)?.map_or(0, |zset| zset.count_by_lex(range));
+        Ok(DataType::Integer(count as i64))
+    }
+
+    pub fn zscan(&mut self, key: &[u8], cursor: u64, options: &ScanOptions) -> CommandResult {
+        let zset = match self.get_sorted_set(key)? {
+            Some(zset) => zset,
+            None => return Ok(scan_reply(0, vec![])),
+        };
+        let (cursor, elements) = scan_items(zset.iter().map(|(member, score)| (member, (member, score))), cursor, options);
+        let elements = elements.into_iter().map(|(member, score)| (member.to_vec(), score)).collect();
+        Ok(scan_reply(cursor, scored_items(elements, true)))
+    }
+
+    // Pop up to count elements from the first non-empty sorted set, replying with the key and
+    // member/score pairs
+    pub fn zmpop(&mut self, keys: &[Vec<u8>], min: bool, count: usize) -> CommandResult {
+        for key in keys {
+            if let Some(zset) = self.get_sorted_set(key)? {
+                let popped: Vec<DataType> = (0..count)
+                    .map_while(|_| zset.pop(min))
+                    .map(|(member, score)| DataType::bulk_array([member, format_score(score)]))
+                    .collect();
+                self.notify_keyspace_event(NOTIFY_ZSET, if min { "zpopmin" } else { "zpopmax" }, key);
+                self.remove_if_empty(key);
+                return Ok(DataType::Array(vec![DataType::BulkString(key.clone()), DataType::Array(popped)]));
+            }
+        }
+        Ok(DataType::NullArray)
+    }
+
+    // Members and scores of a sorted set or plain set input, plain set members scoring 1
+    fn zset_input(&mut self, key: &[u8]) -> Result<HashMap<Vec<u8>, f64>, DataType> {
+        Ok(match self.get_value(key) {
+            Some(DataStoreValue { value: Value::SortedSet(zset), .. }) => {
+                zset.iter().map(|(member, score)| (member.to_vec(), score)).collect()
+            }
+            Some(DataStoreValue { value: Value::Set(set), .. }) => set.members().into_iter().map(|member| (member, 1.0)).collect(),
+            Some(_) => return Err(wrong_type_error()),
+            None => HashMap::new(),
+        })
+    }
+
+    // Combine the weighted inputs, returning the elements ordered by score
+    pub fn zset_algebra(&mut self, inputs: &ZsetAlgebraInputs, operation: SetOperation) -> Result<Vec<(Vec<u8>, f64)>, DataType> {
+        let mut zsets = Vec::with_capacity(inputs.keys.len());
+        for (key, &weight) in inputs.keys.iter().zip(&inputs.weights) {
+            let mut zset = self.zset_input(key)?;
+            for score in zset.values_mut() {
+                // 0 * inf is taken to be zero rather than NaN
+                *score = match *score * weight {
+                    weighted if weighted.is_nan() => 0.0,
+                    weighted => weighted,
+                };
+            }
+            zsets.push(zset);
+        }
+        let mut zsets = zsets.into_iter();
+        let mut result = zsets.next().unwrap_or_default();
+        for zset in zsets {
+            match operation {
+                SetOperation::Inter => {
+                    result.retain(|member, _| zset.contains_key(member));
+                    for (member, score) in result.iter_mut() {
+                        *score = inputs.aggregate.apply(*score, zset[member]);
+                    }
+                }
+                SetOperation::Union => {
+                    for (member, score) in zset {
+                        result.entry(member).and_modify(|current| *current = inputs.aggregate.apply(*current, score)).or_insert(score);
+                    }
+                }
+                SetOperation::Diff => result.retain(|member, _| !zset.contains_key(member)),
+            }
+        }
+        let mut elements: Vec<(Vec<u8>, f64)> = result.into_iter().collect();
+        elements.sort_by(|(a, a_score), (b, b_score)| a_score.total_cmp(b_score).then_with(|| a.cmp(b)));
+        Ok(elements)
+    }
+
+    // The destination is overwritten whatever its type, or deleted if the result is empty
+    pub fn zset_algebra_store(&mut self, destination: Vec<u8>, inputs: &ZsetAlgebraInputs, operation: SetOperation) -> CommandResult {
+        let elements = self.zset_algebra(inputs, operation)?;
+        let len = elements.len();
+        let event = match operation {
+            SetOperation::Inter => "zinterstore",
+            SetOperation::Union => "zunionstore",
+            SetOperation::Diff => "zdiffstore",
+        };
+        self.store_sorted_set(destination, elements, event);
+        Ok(DataType::Integer(len as i64))
+    }
+
+    pub fn zdiff(&mut self, keys: Vec<Vec<u8>>, with_scores: bool) -> CommandResult {
+        let inputs = ZsetAlgebraInputs { weights: vec![1.0; keys.len()], keys, aggregate: Aggregate::Sum };
+        let elements = self.zset_algebra(&inputs, SetOperation::Diff)?;
+        Ok(scored_reply(elements, with_scores))
+    }
+
+    pub fn zdiffstore(&mut self, destination: Vec<u8>, keys: Vec<Vec<u8>>) -> CommandResult {
+        let inputs = ZsetAlgebraInputs { weights: vec![1.0; keys.len()], keys, aggregate: Aggregate::Sum };
+        self.zset_algebra_store(destination, &inputs, SetOperation::Diff)
+    }
+
+    fn zrange_elements(&mut self, key: &[u8], spec: &ZrangeSpec) -> Result<Vec<(Vec<u8>, f64)>, DataType> {
+        let zset = match self.get_sorted_set(key)? {
+            Some(zset) => zset,
+            None => return Ok(vec![]),
+        };
+        // A negative offset selects nothing and a negative count everything
+        let (offset, limit) = match spec.limit {
+            Some((offset, _)) if offset < 0 => return Ok(vec![]),
+            Some((offset, count)) => (offset as usize, (count >= 0).then_some(count as usize)),
+            None => (0, None),
+        };
+        Ok(match &spec.by {
+            ZrangeBy::Rank(start, stop) => match normalize_range(*start, *stop, zset.len()) {
+                Some((start, end)) => zset.range_by_rank(start, end, spec.rev),
+                None => vec![],
+            },
+            ZrangeBy::Score(range) => zset.range_by_score(range, spec.rev, offset, limit),
+            ZrangeBy::Lex(range) => zset.range_by_lex(range, spec.rev, offset, limit),
+        })
+    }
+
+    pub fn zrange(&mut self, key: &[u8], spec: &ZrangeSpec) -> CommandResult {
+        let elements = self.zrange_elements(key, spec)?;
+        Ok(scored_reply(elements, spec.with_scores))
+    }
+
+    pub fn zrangestore(&mut self, destination: Vec<u8>, source: &[u8], spec: &ZrangeSpec) -> CommandResult {
+        let elements = self.zrange_elements(source, spec)?;
+        let len = elements.len();
+        self.store_sorted_set(destination, elements, "zrangestore");
+        Ok(DataType::Integer(len as i64))
+    }
+
+    // Replace the destination with a sorted set of the given elements, deleting it when empty
+    fn store_sorted_set(&mut self, destination: Vec<u8>, elements: Vec<(Vec<u8>, f64)>, event: &str) {
+        if elements.is_empty() {
+            if self.datastore.remove(&destination).is_some() {
+                self.notify_keyspace_event(NOTIFY_GENERIC, "del", &destination);
+            }
+            return;
+        }
+        let mut zset = SortedSet::default();
+        for (member, score) in elements {
+            zset.insert(member, score);
+        }
+        self.blocking.signal_key_ready(&destination);
+        self.datastore.insert(destination.clone(), DataStoreValue::new(Value::SortedSet(zset), None));
+        self.notify_keyspace_event(NOTIFY_ZSET, event, &destination);
+    }
+}
@@ -0,0 +1,500 @@
+use crate::{glob::glob_match, notify, replication::backlog::MIN_BACKLOG_SIZE};
+
+// Size thresholds below which aggregate values use their compact encodings
+#[derive(Debug, Clone, Copy)]
+pub struct EncodingLimits {
+    pub hash_max_listpack_entries: usize,
+    pub hash_max_listpack_value: usize,
+    // Positive values cap the entry count, negative values -1..-5 cap the encoded size at 4-64kb
+    pub list_max_listpack_size: i64,
+    pub set_max_intset_entries: usize,
+}
+
+impl Default for EncodingLimits {
+    fn default() -> Self {
+        EncodingLimits {
+            hash_max_listpack_entries: 128,
+            hash_max_listpack_value: 64,
+            list_max_listpack_size: -2,
+            set_max_intset_entries: 512,
+        }
+    }
+}
+
+impl EncodingLimits {
+    // Whether a listpack encoded list of the given shape is still within limits
+    pub fn list_fits_listpack(&self, entries: usize, bytes: usize) -> bool {
+        match self.list_max_listpack_size {
+            size if size > 0 => entries <= size as usize,
+            size => bytes <= 4096 << (size.unsigned_abs().clamp(1, 5) - 1),
+        }
+    }
+}
+
+// Parameters for bloom filters created implicitly by BF.ADD and BF.MADD
+#[derive(Debug, Clone, Copy)]
+pub struct BloomDefaults {
+    pub error_rate: f64,
+    pub initial_size: u64,
+    pub expansion_factor: u32,
+}
+
+impl Default for BloomDefaults {
+    fn default() -> Self {
+        BloomDefaults { error_rate: 0.01, initial_size: 100, expansion_factor: 2 }
+    }
+}
+
+// client-output-buffer-limit thresholds for one class of connection, zero disables a limit.
+// A connection is dropped as soon as its queued output passes the hard limit, or once it has
+// stayed over the soft limit for longer than soft_seconds.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OutputBufferLimit {
+    pub hard: usize,
+    pub soft: usize,
+    pub soft_seconds: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct OutputBufferLimits {
+    pub normal: OutputBufferLimit,
+    pub replica: OutputBufferLimit,
+    pub pubsub: OutputBufferLimit,
+}
+
+impl Default for OutputBufferLimits {
+    fn default() -> Self {
+        OutputBufferLimits {
+            normal: OutputBufferLimit::default(),
+            replica: OutputBufferLimit { hard: 256 << 20, soft: 64 << 20, soft_seconds: 60 },
+            pubsub: OutputBufferLimit { hard: 32 << 20, soft: 8 << 20, soft_seconds: 60 },
+        }
+    }
+}
+
+impl OutputBufferLimits {
+    // Replicas are reported under their old name, as Redis does
+    fn to_config_string(self) -> String {
+        [("normal", self.normal), ("slave", self.replica), ("pubsub", self.pubsub)]
+            .iter()
+            .map(|(class, limit)| format!("{} {} {} {}", class, limit.hard, limit.soft, limit.soft_seconds))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    // One or more "class hard soft soft_seconds" groups, leaving unnamed classes unchanged
+    fn parse(&self, value: &str) -> Result<OutputBufferLimits, String> {
+        let args: Vec<&str> = value.split_whitespace().collect();
+        if args.is_empty() || !args.chunks_exact(4).remainder().is_empty() {
+            return Err("Wrong number of arguments in buffer limit configuration.".to_string());
+        }
+        let mut limits = *self;
+        for group in args.chunks_exact(4) {
+            let limit = match group[0].to_lowercase().as_str() {
+                "normal" => &mut limits.normal,
+                "replica" | "slave" => &mut limits.replica,
+                "pubsub" => &mut limits.pubsub,
+                _ => return Err("Invalid client class specified in buffer limit configuration.".to_string()),
+            };
+            let (Some(hard), Some(soft), Ok(soft_seconds)) = (parse_memory(group[1]), parse_memory(group[2]), group[3].parse::<u64>()) else {
+                return Err("Error in hard, soft or soft_seconds setting in buffer limit configuration.".to_string());
+            };
+            *limit = OutputBufferLimit { hard, soft, soft_seconds };
+        }
+        Ok(limits)
+    }
+}
+
+// Snapshotting rules: save once at least `changes` writes happened within `seconds`
+#[derive(Debug, Clone)]
+pub struct SaveRules(pub Vec<(u64, u64)>);
+
+impl Default for SaveRules {
+    fn default() -> Self {
+        SaveRules(vec![(3600, 1), (300, 100), (60, 10000)])
+    }
+}
+
+impl SaveRules {
+    fn to_config_string(&self) -> String {
+        self.0.iter().map(|(seconds, changes)| format!("{} {}", seconds, changes)).collect::<Vec<_>>().join(" ")
+    }
+
+    // "seconds changes" pairs, or an empty string to turn snapshotting off
+    fn parse(value: &str) -> Result<SaveRules, String> {
+        let args: Vec<&str> = value.split_whitespace().collect();
+        if !args.chunks_exact(2).remainder().is_empty() {
+            return Err("Invalid save parameters".to_string());
+        }
+        let rules = args.chunks_exact(2).map(|pair| match (pair[0].parse::<u64>(), pair[1].parse::<u64>()) {
+            (Ok(seconds), Ok(changes)) => Ok((seconds, changes)),
+            _ => Err("Invalid save parameters".to_string()),
+        });
+        Ok(SaveRules(rules.collect::<Result<_, _>>()?))
+    }
+}
+
+// Whether dump files carry a checksum, and what a dump file failing its checksum at startup
+// does: refuse to load, or load anyway with a warning
+#[derive(Debug, Clone, Copy)]
+pub struct RdbOptions {
+    pub checksum: bool,
+    pub warn_on_bad_checksum: bool,
+}
+
+impl Default for RdbOptions {
+    fn default() -> Self {
+        RdbOptions { checksum: true, warn_on_bad_checksum: false }
+    }
+}
+
+// When the AOF writer asks the OS to put what it wrote on disk: after every write, about once
+// a second, or never, leaving it to the OS
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AppendFsync {
+    Always,
+    EverySec,
+    No,
+}
+
+// Whether writes are appended to an append-only file, and its name. The AOF is made of several
+// files named after it, kept in a directory that sits next to the dump.
+#[derive(Debug, Clone)]
+pub struct AofOptions {
+    pub enabled: bool,
+    pub filename: String,
+    pub dirname: String,
+    pub fsync: AppendFsync,
+    // Hold the replies to writes until the AOF has them on disk, fsyncing after every write
+    pub fsync_before_reply: bool,
+    // Rewrites start the file with an RDB image of the dataset rather than commands
+    pub use_rdb_preamble: bool,
+    // Load a file that ends part way through a command, dropping the incomplete command
+    pub load_truncated: bool,
+}
+
+impl Default for AofOptions {
+    fn default() -> Self {
+        AofOptions {
+            enabled: false,
+            filename: "appendonly.aof".to_string(),
+            dirname: "appendonlydir".to_string(),
+            fsync: AppendFsync::EverySec,
+            fsync_before_reply: false,
+            use_rdb_preamble: true,
+            load_truncated: true,
+        }
+    }
+}
+
+// The port clients connect on
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    pub port: u16,
+    // Milliseconds a script may run before it is stopped with an error, as no other client is
+    // served until it finishes. 0 means no limit.
+    pub busy_reply_threshold: u64,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        ServerOptions { port: 6379, busy_reply_threshold: 5000 }
+    }
+}
+
+// The master this server replicates, if it is a replica
+#[derive(Debug, Clone)]
+pub struct ReplicationOptions {
+    pub replicaof: Option<(String, u16)>,
+    // Refuse writes from clients other than the master while replicating
+    pub read_only: bool,
+    // Preference for promoting this replica, lower first, with 0 meaning never
+    pub priority: u64,
+    // Seconds without hearing from the master before the link is dropped, or from a replica
+    // before it is
+    pub timeout: u64,
+    // Seconds between the pings a master sends its replicas
+    pub ping_period: u64,
+    // Answer from the dataset while the link to the master is down or syncing
+    pub serve_stale_data: bool,
+    // Bytes of the replication stream kept for replicas that come back after losing their link
+    pub backlog_size: usize,
+    // Stream the dataset to replicas as it is encoded, rather than saving it to the dump file
+    // and sending that
+    pub diskless_sync: bool,
+    // Writes are refused unless this many replicas have acknowledged within the lag, in
+    // seconds. Either being 0 turns this off.
+    pub min_replicas_to_write: usize,
+    pub min_replicas_max_lag: u64,
+}
+
+impl Default for ReplicationOptions {
+    fn default() -> Self {
+        ReplicationOptions {
+            replicaof: None,
+            read_only: true,
+            priority: 100,
+            timeout: 60,
+            ping_period: 10,
+            serve_stale_data: true,
+            backlog_size: 1 << 20,
+            diskless_sync: true,
+            min_replicas_to_write: 0,
+            min_replicas_max_lag: 10,
+        }
+    }
+}
+
+// A byte count with an optional k, kb, m, mb, g or gb unit. The single letter units are powers
+// of ten and the two letter ones powers of two.
+pub fn parse_memory(value: &str) -> Option<usize> {
+    let value = value.to_lowercase();
+    let units = [("gb", 1 << 30), ("mb", 1 << 20), ("kb", 1 << 10), ("g", 1_000_000_000), ("m", 1_000_000), ("k", 1_000), ("b", 1)];
+    let (number, unit) = units.iter()
+        .find_map(|(suffix, unit)| value.strip_suffix(suffix).map(|number| (number, *unit)))
+        .unwrap_or((value.as_str(), 1));
+    number.parse::<usize>().ok()?.checked_mul(unit)
+}
+
+// Whether this server is a node of a cluster, and the file it keeps its view of the cluster in
+#[derive(Debug, Clone)]
+pub struct ClusterOptions {
+    pub enabled: bool,
+    pub config_file: String,
+}
+
+impl Default for ClusterOptions {
+    fn default() -> Self {
+        ClusterOptions { enabled: false, config_file: "nodes.conf".to_string() }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub server: ServerOptions,
+    pub limits: EncodingLimits,
+    pub bloom: BloomDefaults,
+    // Bitmask of notify::NOTIFY_* classes, zero when keyspace notifications are off
+    pub notify_keyspace_events: u16,
+    pub output_buffer_limits: OutputBufferLimits,
+    pub save: SaveRules,
+    pub rdb: RdbOptions,
+    pub aof: AofOptions,
+    pub replication: ReplicationOptions,
+    pub cluster: ClusterOptions,
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse::<T>().map_err(|_| "argument couldn't be parsed into an integer".to_string())
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'".to_string()),
+    }
+}
+
+fn bool_to_string(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+impl Config {
+    // Parameters exposed through CONFIG GET/SET and command line flags
+    pub const PARAMETERS: &'static [&'static str] = &[
+        "port",
+        "busy-reply-threshold",
+        "lua-time-limit",
+        "hash-max-listpack-entries",
+        "hash-max-listpack-value",
+        "list-max-listpack-size",
+        "set-max-intset-entries",
+        "bf-error-rate",
+        "bf-initial-size",
+        "bf-expansion-factor",
+        "notify-keyspace-events",
+        "client-output-buffer-limit",
+        "save",
+        "rdbchecksum",
+        "rdb-checksum-mismatch",
+        "appendonly",
+        "appendfilename",
+        "appenddirname",
+        "appendfsync",
+        "aof-fsync-before-reply",
+        "aof-use-rdb-preamble",
+        "aof-load-truncated",
+        "replicaof",
+        "replica-read-only",
+        "replica-priority",
+        "repl-timeout",
+        "replica-serve-stale-data",
+        "repl-backlog-size",
+        "repl-diskless-sync",
+        "repl-ping-replica-period",
+        "min-replicas-to-write",
+        "min-replicas-max-lag",
+        "cluster-enabled",
+        "cluster-config-file",
+    ];
+
+    // Parameters that can only be given at startup
+    pub const IMMUTABLE: &'static [&'static str] = &[
+        "port",
+        "appendonly",
+        "appendfilename",
+        "appenddirname",
+        "replicaof",
+        "cluster-enabled",
+        "cluster-config-file",
+    ];
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "port" => self.server.port.to_string(),
+            "busy-reply-threshold" | "lua-time-limit" => self.server.busy_reply_threshold.to_string(),
+            "hash-max-listpack-entries" => self.limits.hash_max_listpack_entries.to_string(),
+            "hash-max-listpack-value" => self.limits.hash_max_listpack_value.to_string(),
+            "list-max-listpack-size" => self.limits.list_max_listpack_size.to_string(),
+            "set-max-intset-entries" => self.limits.set_max_intset_entries.to_string(),
+            "bf-error-rate" => self.bloom.error_rate.to_string(),
+            "bf-initial-size" => self.bloom.initial_size.to_string(),
+            "bf-expansion-factor" => self.bloom.expansion_factor.to_string(),
+            "notify-keyspace-events" => notify::flags_to_string(self.notify_keyspace_events),
+            "client-output-buffer-limit" => self.output_buffer_limits.to_config_string(),
+            "save" => self.save.to_config_string(),
+            "rdbchecksum" => bool_to_string(self.rdb.checksum),
+            "rdb-checksum-mismatch" => if self.rdb.warn_on_bad_checksum { "warn" } else { "refuse" }.to_string(),
+            "appendonly" => bool_to_string(self.aof.enabled),
+            "appendfilename" => self.aof.filename.clone(),
+            "appenddirname" => self.aof.dirname.clone(),
+            "appendfsync" => match self.aof.fsync {
+                AppendFsync::Always => "always",
+                AppendFsync::EverySec => "everysec",
+                AppendFsync::No => "no",
+            }.to_string(),
+            "aof-fsync-before-reply" => bool_to_string(self.aof.fsync_before_reply),
+            "aof-use-rdb-preamble" => bool_to_string(self.aof.use_rdb_preamble),
+            "aof-load-truncated" => bool_to_string(self.aof.load_truncated),
+            "replicaof" => self.replication.replicaof.as_ref().map_or(String::new(), |(host, port)| format!("{} {}", host, port)),
+            "replica-read-only" => bool_to_string(self.replication.read_only),
+            "replica-priority" => self.replication.priority.to_string(),
+            "repl-timeout" => self.replication.timeout.to_string(),
+            "replica-serve-stale-data" => bool_to_string(self.replication.serve_stale_data),
+            "repl-backlog-size" => self.replication.backlog_size.to_string(),
+            "repl-diskless-sync" => bool_to_string(self.replication.diskless_sync),
+            "repl-ping-replica-period" => self.replication.ping_period.to_string(),
+            "min-replicas-to-write" => self.replication.min_replicas_to_write.to_string(),
+            "min-replicas-max-lag" => self.replication.min_replicas_max_lag.to_string(),
+            "cluster-enabled" => bool_to_string(self.cluster.enabled),
+            "cluster-config-file" => self.cluster.config_file.clone(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    // Returns an error message describing why the value was rejected
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "port" => self.server.port = parse_number(value)?,
+            // lua-time-limit is the old name
+            "busy-reply-threshold" | "lua-time-limit" => self.server.busy_reply_threshold = parse_number(value)?,
+            "hash-max-listpack-entries" => self.limits.hash_max_listpack_entries = parse_number(value)?,
+            "hash-max-listpack-value" => self.limits.hash_max_listpack_value = parse_number(value)?,
+            "list-max-listpack-size" => self.limits.list_max_listpack_size = parse_number(value)?,
+            "set-max-intset-entries" => self.limits.set_max_intset_entries = parse_number(value)?,
+            "bf-error-rate" => match value.parse::<f64>() {
+                Ok(rate) if rate > 0.0 && rate < 1.0 => self.bloom.error_rate = rate,
+                _ => return Err("argument must be a number between 0 and 1".to_string()),
+            },
+            "bf-initial-size" => match parse_number(value)? {
+                0 => return Err("argument must be larger than 0".to_string()),
+                size => self.bloom.initial_size = size,
+            },
+            "bf-expansion-factor" => match parse_number(value)? {
+                0 => return Err("argument must be larger than 0".to_string()),
+                factor => self.bloom.expansion_factor = factor,
+            },
+            "notify-keyspace-events" => match notify::parse_flags(value) {
+                Some(flags) => self.notify_keyspace_events = flags,
+                None => return Err("Invalid event class character. Use 'Ag$lshzxeKEtmnd'.".to_string()),
+            },
+            "client-output-buffer-limit" => self.output_buffer_limits = self.output_buffer_limits.parse(value)?,
+            "save" => self.save = SaveRules::parse(value)?,
+            "rdbchecksum" => self.rdb.checksum = parse_bool(value)?,
+            "rdb-checksum-mismatch" => match value.to_lowercase().as_str() {
+                "refuse" => self.rdb.warn_on_bad_checksum = false,
+                "warn" => self.rdb.warn_on_bad_checksum = true,
+                _ => return Err("argument must be 'refuse' or 'warn'".to_string()),
+            },
+            "appendonly" => self.aof.enabled = parse_bool(value)?,
+            // Bare names, as the directory always goes in the working directory. The manifest
+            // lists files by names separated by spaces, so they can't have any.
+            "appendfilename" => match value {
+                "" => return Err("appendfilename can't be empty".to_string()),
+                value if value.contains('/') => return Err("appendfilename can't be a path, just a filename".to_string()),
+                value if value.contains(char::is_whitespace) => return Err("appendfilename can't contain spaces".to_string()),
+                value => self.aof.filename = value.to_string(),
+            },
+            "appenddirname" => match value {
+                "" => return Err("appenddirname can't be empty".to_string()),
+                value if value.contains('/') => return Err("appenddirname can't be a path, just a dirname".to_string()),
+                value => self.aof.dirname = value.to_string(),
+            },
+            "appendfsync" => match value.to_lowercase().as_str() {
+                "always" => self.aof.fsync = AppendFsync::Always,
+                "everysec" => self.aof.fsync = AppendFsync::EverySec,
+                "no" => self.aof.fsync = AppendFsync::No,
+                _ => return Err("argument must be 'always', 'everysec' or 'no'".to_string()),
+            },
+            "aof-fsync-before-reply" => self.aof.fsync_before_reply = parse_bool(value)?,
+            "aof-use-rdb-preamble" => self.aof.use_rdb_preamble = parse_bool(value)?,
+            "aof-load-truncated" => self.aof.load_truncated = parse_bool(value)?,
+            // <host> <port>, or NO ONE for no master
+            "replicaof" => {
+                let words: Vec<&str> = value.split_whitespace().collect();
+                self.replication.replicaof = match words[..] {
+                    [no, one] if no.eq_ignore_ascii_case("no") && one.eq_ignore_ascii_case("one") => None,
+                    [host, port] => Some((host.to_string(), port.parse::<u16>().map_err(|_| "Invalid master port".to_string())?)),
+                    _ => return Err("wrong number of arguments".to_string()),
+                }
+            }
+            "replica-read-only" => self.replication.read_only = parse_bool(value)?,
+            "replica-priority" => self.replication.priority = parse_number(value)?,
+            "repl-timeout" => match parse_number(value)? {
+                0 => return Err("argument must be larger than 0".to_string()),
+                timeout => self.replication.timeout = timeout,
+            },
+            "replica-serve-stale-data" => self.replication.serve_stale_data = parse_bool(value)?,
+            "repl-backlog-size" => match parse_memory(value) {
+                Some(size) => self.replication.backlog_size = size.max(MIN_BACKLOG_SIZE),
+                None => return Err("argument must be a memory value".to_string()),
+            },
+            "repl-diskless-sync" => self.replication.diskless_sync = parse_bool(value)?,
+            "repl-ping-replica-period" => match parse_number(value)? {
+                0 => return Err("argument must be larger than 0".to_string()),
+                period => self.replication.ping_period = period,
+            },
+            "min-replicas-to-write" => self.replication.min_replicas_to_write = parse_number(value)?,
+            "min-replicas-max-lag" => self.replication.min_replicas_max_lag = parse_number(value)?,
+            "cluster-enabled" => self.cluster.enabled = parse_bool(value)?,
+            // A bare name, as the file goes in the working directory
+            "cluster-config-file" => match value {
+                "" => return Err("cluster-config-file can't be empty".to_string()),
+                value if value.contains('/') => return Err("cluster-config-file can't be a path, just a filename".to_string()),
+                value => self.cluster.config_file = value.to_string(),
+            },
+            _ => return Err("Unknown option".to_string()),
+        }
+        Ok(())
+    }
+
+    // Names and values of every parameter matching a CONFIG GET pattern
+    pub fn matching(&self, pattern: &[u8]) -> Vec<(String, String)> {
+        Config::PARAMETERS.iter()
+            .filter(|name| glob_match(pattern, name.as_bytes()))
+            .filter_map(|name| self.get(name).map(|value| (name.to_string(), value)))
+            .collect()
+    }
+}
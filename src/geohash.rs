@@ -0,0 +1,86 @@
+// 52-bit geohashes as used to store GEO members as sorted set scores. Latitude is limited to the
+// range a Web Mercator projection covers, and each axis gets 26 bits interleaved with latitude in
+// the even positions.
+const STEP: u32 = 26;
+
+pub const LONGITUDE_MIN: f64 = -180.0;
+pub const LONGITUDE_MAX: f64 = 180.0;
+pub const LATITUDE_MIN: f64 = -85.05112878;
+pub const LATITUDE_MAX: f64 = 85.05112878;
+
+// Same earth radius Redis uses, so distances match to the last reported digit
+const EARTH_RADIUS_IN_METERS: f64 = 6372797.560856;
+
+const BASE32_ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+pub fn valid_coordinates(longitude: f64, latitude: f64) -> bool {
+    (LONGITUDE_MIN..=LONGITUDE_MAX).contains(&longitude) && (LATITUDE_MIN..=LATITUDE_MAX).contains(&latitude)
+}
+
+// Spread the low 32 bits of `x` over the even bit positions
+fn spread(x: u32) -> u64 {
+    let mut x = x as u64;
+    x = (x | (x << 16)) & 0x0000FFFF0000FFFF;
+    x = (x | (x << 8)) & 0x00FF00FF00FF00FF;
+    x = (x | (x << 4)) & 0x0F0F0F0F0F0F0F0F;
+    x = (x | (x << 2)) & 0x3333333333333333;
+    (x | (x << 1)) & 0x5555555555555555
+}
+
+// Inverse of spread, gathering the even bit positions back together
+fn squash(x: u64) -> u32 {
+    let mut x = x & 0x5555555555555555;
+    x = (x | (x >> 1)) & 0x3333333333333333;
+    x = (x | (x >> 2)) & 0x0F0F0F0F0F0F0F0F;
+    x = (x | (x >> 4)) & 0x00FF00FF00FF00FF;
+    x = (x | (x >> 8)) & 0x0000FFFF0000FFFF;
+    ((x | (x >> 16)) & 0x00000000FFFFFFFF) as u32
+}
+
+fn encode_with_range(longitude: f64, latitude: f64, lat_min: f64, lat_max: f64) -> u64 {
+    let scale = (1u64 << STEP) as f64;
+    let lat_offset = ((latitude - lat_min) / (lat_max - lat_min) * scale) as u32;
+    let lon_offset = ((longitude - LONGITUDE_MIN) / (LONGITUDE_MAX - LONGITUDE_MIN) * scale) as u32;
+    spread(lat_offset) | (spread(lon_offset) << 1)
+}
+
+pub fn encode(longitude: f64, latitude: f64) -> u64 {
+    encode_with_range(longitude, latitude, LATITUDE_MIN, LATITUDE_MAX)
+}
+
+// Center of the cell a hash names, as (longitude, latitude)
+pub fn decode(hash: u64) -> (f64, f64) {
+    let scale = (1u64 << STEP) as f64;
+    let lat_cell = squash(hash) as f64;
+    let lon_cell = squash(hash >> 1) as f64;
+    let lat_width = LATITUDE_MAX - LATITUDE_MIN;
+    let lon_width = LONGITUDE_MAX - LONGITUDE_MIN;
+    let lat_min = LATITUDE_MIN + lat_cell / scale * lat_width;
+    let lat_max = LATITUDE_MIN + (lat_cell + 1.0) / scale * lat_width;
+    let lon_min = LONGITUDE_MIN + lon_cell / scale * lon_width;
+    let lon_max = LONGITUDE_MIN + (lon_cell + 1.0) / scale * lon_width;
+    let longitude = ((lon_min + lon_max) / 2.0).clamp(LONGITUDE_MIN, LONGITUDE_MAX);
+    let latitude = ((lat_min + lat_max) / 2.0).clamp(LATITUDE_MIN, LATITUDE_MAX);
+    (longitude, latitude)
+}
+
+// Great circle distance in meters using the haversine formula
+pub fn distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let v = ((lon2.to_radians() - lon1.to_radians()) / 2.0).sin();
+    let a = u * u + lat1.cos() * lat2.cos() * v * v;
+    2.0 * EARTH_RADIUS_IN_METERS * a.sqrt().asin()
+}
+
+// Standard 11 character geohash string. This re-encodes against the full [-90, 90] latitude range
+// the rest of the world uses, and pads the final character since 52 bits only fill ten of them.
+pub fn to_base32(longitude: f64, latitude: f64) -> Vec<u8> {
+    let hash = encode_with_range(longitude, latitude, -90.0, 90.0);
+    (0..11)
+        .map(|i| {
+            let index = if i == 10 { 0 } else { (hash >> (52 - (i + 1) * 5)) & 0x1f };
+            BASE32_ALPHABET[index as usize]
+        })
+        .collect()
+}
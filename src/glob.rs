@@ -0,0 +1,73 @@
+// Glob-style pattern matching with the same syntax as Redis' stringmatchlen: `*`, `?`,
+// `[...]` classes with `^` negation and `a-z` ranges, and `\` escapes.
+pub fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                while p + 1 < pattern.len() && pattern[p + 1] == b'*' {
+                    p += 1;
+                }
+                if p + 1 == pattern.len() {
+                    return true;
+                }
+                return (s..=string.len()).any(|start| glob_match(&pattern[p + 1..], &string[start..]));
+            }
+            b'?' => {
+                if s >= string.len() {
+                    return false;
+                }
+                s += 1;
+            }
+            b'[' => {
+                if s >= string.len() {
+                    return false;
+                }
+                p += 1;
+                let negate = p < pattern.len() && pattern[p] == b'^';
+                if negate {
+                    p += 1;
+                }
+                let mut matched = false;
+                while p < pattern.len() && pattern[p] != b']' {
+                    if pattern[p] == b'\\' && p + 1 < pattern.len() {
+                        p += 1;
+                        matched |= pattern[p] == string[s];
+                    } else if p + 2 < pattern.len() && pattern[p + 1] == b'-' {
+                        let (mut start, mut end) = (pattern[p], pattern[p + 2]);
+                        if start > end {
+                            std::mem::swap(&mut start, &mut end);
+                        }
+                        matched |= (start..=end).contains(&string[s]);
+                        p += 2;
+                    } else {
+                        matched |= pattern[p] == string[s];
+                    }
+                    p += 1;
+                }
+                if p == pattern.len() {
+                    // Unterminated class, treat the end of pattern as the closing bracket
+                    p -= 1;
+                }
+                if matched == negate {
+                    return false;
+                }
+                s += 1;
+            }
+            c => {
+                let c = if c == b'\\' && p + 1 < pattern.len() {
+                    p += 1;
+                    pattern[p]
+                } else {
+                    c
+                };
+                if s >= string.len() || string[s] != c {
+                    return false;
+                }
+                s += 1;
+            }
+        }
+        p += 1;
+    }
+    s == string.len()
+}
@@ -0,0 +1,700 @@
+use std::{cell::RefCell, rc::Rc, sync::Arc, time::{Duration, Instant}};
+
+use crate::resp::DataType;
+
+use super::{
+    parser::{BinOp, Expr, FunctionBody, Stmt, StmtKind, TableField, UnOp},
+    stdlib, Closure, LuaError, Table, TableRef, Value,
+};
+
+// Deepest nesting of Lua function calls before a script is stopped
+const MAX_CALL_DEPTH: usize = 200;
+
+// Statements run between looks at the clock for the time limit
+const STEPS_PER_CLOCK_CHECK: u32 = 1024;
+
+const RANDOM_SEED: u64 = 0x853C49E6748FEA9B;
+
+// What the interpreter is embedded in, reached by the redis library
+pub trait Host {
+    // Run a command on behalf of the script
+    fn call(&mut self, args: Vec<Vec<u8>>) -> DataType;
+}
+
+enum Flow {
+    Normal,
+    Break,
+    Return(Vec<Value>),
+}
+
+pub struct Interpreter<'h> {
+    pub host: &'h mut dyn Host,
+    pub globals: TableRef,
+    // Private to the embedding, for state its builtins keep between calls
+    pub registry: TableRef,
+    // Looked up for method calls on strings, as in s:upper()
+    string_lib: TableRef,
+    // Used in error positions, e.g. user_script:3:
+    chunk_name: &'static str,
+    // Once set, reading an undefined global or creating a new one is an error
+    protect_globals: bool,
+    locals: Vec<(Rc<str>, Rc<RefCell<Value>>)>,
+    varargs: Vec<Value>,
+    line: usize,
+    depth: usize,
+    // math.random starts from the same seed on every run so scripts stay deterministic
+    random_state: u64,
+    // When the script is stopped, and the limit for the error message
+    deadline: Option<(Instant, Duration)>,
+    steps: u32,
+    timed_out: bool,
+}
+
+impl<'h> Interpreter<'h> {
+    pub fn new(host: &'h mut dyn Host, chunk_name: &'static str) -> Self {
+        let globals = Rc::new(RefCell::new(Table::default()));
+        let string_lib = stdlib::install(&mut globals.borrow_mut());
+        Interpreter {
+            host,
+            globals,
+            registry: Rc::new(RefCell::new(Table::default())),
+            string_lib,
+            chunk_name,
+            protect_globals: false,
+            locals: Vec::new(),
+            varargs: Vec::new(),
+            line: 0,
+            depth: 0,
+            random_state: RANDOM_SEED,
+            deadline: None,
+            steps: 0,
+            timed_out: false,
+        }
+    }
+
+    // Stop the script with an error once it has run for longer than the limit
+    pub fn set_time_limit(&mut self, limit: Duration) {
+        self.deadline = Some((Instant::now() + limit, limit));
+    }
+
+    // Called as statements run and loops go round. Once the time is up every call fails, so
+    // pcall can't keep the script going.
+    fn tick(&mut self) -> Result<(), LuaError> {
+        let Some((deadline, limit)) = self.deadline else {
+            return Ok(());
+        };
+        if !self.timed_out {
+            self.steps += 1;
+            if self.steps < STEPS_PER_CLOCK_CHECK {
+                return Ok(());
+            }
+            self.steps = 0;
+            self.timed_out = Instant::now() >= deadline;
+            if !self.timed_out {
+                return Ok(());
+            }
+        }
+        Err(self.error(&format!("Script exceeded the time limit of {} milliseconds", limit.as_millis())))
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.borrow_mut().set_str(name, value);
+    }
+
+    // Stop scripts from reading undefined globals or defining their own
+    pub fn protect_globals(&mut self) {
+        self.protect_globals = true;
+    }
+
+    // A runtime error at the line being executed
+    pub fn error(&self, msg: &str) -> LuaError {
+        LuaError { value: Value::str(format!("{}:{}: {}", self.chunk_name, self.line, msg)) }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    // xorshift64* over the script's own state, in [0, 1)
+    pub fn random(&mut self) -> f64 {
+        let mut x = self.random_state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.random_state = x;
+        (x.wrapping_mul(0x2545F4914F6CDD1D) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn seed_random(&mut self, seed: u64) {
+        // Zero would get the generator stuck
+        self.random_state = (seed ^ RANDOM_SEED).max(1);
+    }
+
+    // Run a parsed chunk with no arguments
+    pub fn run(&mut self, chunk: Arc<FunctionBody>) -> Result<Vec<Value>, LuaError> {
+        let closure = Rc::new(Closure { body: chunk, captured: Vec::new() });
+        self.call_function(&Value::Function(closure), Vec::new())
+    }
+
+    pub fn call_function(&mut self, function: &Value, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+        match function {
+            Value::Builtin(_, builtin) => builtin(self, args),
+            Value::Function(closure) => {
+                if self.depth >= MAX_CALL_DEPTH {
+                    return Err(self.error("stack overflow"));
+                }
+                let body = closure.body.clone();
+                let mut locals = closure.captured.clone();
+                let mut args = args.into_iter();
+                for param in body.params.iter() {
+                    locals.push((Rc::from(param.as_str()), Rc::new(RefCell::new(args.next().unwrap_or(Value::Nil)))));
+                }
+                let varargs = if body.varargs { args.collect() } else { Vec::new() };
+                let saved_locals = std::mem::replace(&mut self.locals, locals);
+                let saved_varargs = std::mem::replace(&mut self.varargs, varargs);
+                let saved_line = self.line;
+                self.depth += 1;
+                let result = self.exec_block(&body.body);
+                self.depth -= 1;
+                self.locals = saved_locals;
+                self.varargs = saved_varargs;
+                match result {
+                    Ok(flow) => {
+                        self.line = saved_line;
+                        match flow {
+                            Flow::Return(values) => Ok(values),
+                            _ => Ok(Vec::new()),
+                        }
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            other => Err(self.error(&format!("attempt to call a {} value", other.type_name()))),
+        }
+    }
+
+    // Locals declared in the block go out of scope at its end. After an error they are left for
+    // call_function to discard along with the rest of the frame.
+    fn exec_block(&mut self, stmts: &[Stmt]) -> Result<Flow, LuaError> {
+        self.tick()?;
+        let scope = self.locals.len();
+        let mut flow = Ok(Flow::Normal);
+        for stmt in stmts {
+            flow = self.exec(stmt);
+            if !matches!(flow, Ok(Flow::Normal)) {
+                break;
+            }
+        }
+        self.locals.truncate(scope);
+        flow
+    }
+
+    fn declare(&mut self, name: &str, value: Value) {
+        self.locals.push((Rc::from(name), Rc::new(RefCell::new(value))));
+    }
+
+    fn exec(&mut self, stmt: &Stmt) -> Result<Flow, LuaError> {
+        self.line = stmt.line;
+        match &stmt.kind {
+            StmtKind::Local(names, exprs) => {
+                let mut values = self.eval_list(exprs)?.into_iter();
+                for name in names {
+                    self.declare(name, values.next().unwrap_or(Value::Nil));
+                }
+            }
+            StmtKind::LocalFunction(name, body) => {
+                // Declared first so the function can call itself
+                self.declare(name, Value::Nil);
+                let function = self.closure(body);
+                *self.locals.last().unwrap().1.borrow_mut() = function;
+            }
+            StmtKind::Assign(targets, exprs) => self.assign(targets, exprs)?,
+            StmtKind::Call(expr) => {
+                self.eval_multi(expr)?;
+            }
+            StmtKind::Do(body) => return self.exec_block(body),
+            StmtKind::While(condition, body) => {
+                while self.eval(condition)?.truthy() {
+                    match self.exec_block(body)? {
+                        Flow::Break => break,
+                        Flow::Return(values) => return Ok(Flow::Return(values)),
+                        Flow::Normal => {}
+                    }
+                }
+            }
+            StmtKind::Repeat(body, condition) => loop {
+                self.tick()?;
+                // The condition can see the body's locals
+                let scope = self.locals.len();
+                let mut flow = Flow::Normal;
+                for stmt in body {
+                    flow = self.exec(stmt)?;
+                    if !matches!(flow, Flow::Normal) {
+                        break;
+                    }
+                }
+                let done = match flow {
+                    Flow::Break => true,
+                    Flow::Return(values) => {
+                        self.locals.truncate(scope);
+                        return Ok(Flow::Return(values));
+                    }
+                    Flow::Normal => self.eval(condition)?.truthy(),
+                };
+                self.locals.truncate(scope);
+                if done {
+                    break;
+                }
+            },
+            StmtKind::If(branches, otherwise) => {
+                for (condition, body) in branches {
+                    if self.eval(condition)?.truthy() {
+                        return self.exec_block(body);
+                    }
+                }
+                if let Some(body) = otherwise {
+                    return self.exec_block(body);
+                }
+            }
+            StmtKind::NumericFor(name, start, limit, step, body) => {
+                let start = self.for_number(start, "initial")?;
+                let limit = self.for_number(limit, "limit")?;
+                let step = match step {
+                    Some(step) => self.for_number(step, "step")?,
+                    None => 1.0,
+                };
+                let mut i = start;
+                while (step > 0.0 && i <= limit) || (step <= 0.0 && i >= limit) {
+                    let scope = self.locals.len();
+                    self.declare(name, Value::Number(i));
+                    let flow = self.exec_block(body);
+                    self.locals.truncate(scope);
+                    match flow? {
+                        Flow::Break => break,
+                        Flow::Return(values) => return Ok(Flow::Return(values)),
+                        Flow::Normal => {}
+                    }
+                    i += step;
+                }
+            }
+            StmtKind::GenericFor(names, exprs, body) => {
+                let mut values = self.eval_list(exprs)?.into_iter();
+                let iterator = values.next().unwrap_or(Value::Nil);
+                let state = values.next().unwrap_or(Value::Nil);
+                let mut control = values.next().unwrap_or(Value::Nil);
+                loop {
+                    let line = self.line;
+                    let mut results = self.call_function(&iterator, vec![state.clone(), control.clone()])?.into_iter();
+                    self.line = line;
+                    let first = results.next().unwrap_or(Value::Nil);
+                    if matches!(first, Value::Nil) {
+                        break;
+                    }
+                    control = first.clone();
+                    let scope = self.locals.len();
+                    self.declare(&names[0], first);
+                    for name in &names[1..] {
+                        self.declare(name, results.next().unwrap_or(Value::Nil));
+                    }
+                    let flow = self.exec_block(body);
+                    self.locals.truncate(scope);
+                    match flow? {
+                        Flow::Break => break,
+                        Flow::Return(values) => return Ok(Flow::Return(values)),
+                        Flow::Normal => {}
+                    }
+                }
+            }
+            StmtKind::Return(exprs) => return Ok(Flow::Return(self.eval_list(exprs)?)),
+            StmtKind::Break => return Ok(Flow::Break),
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn for_number(&mut self, expr: &Expr, what: &str) -> Result<f64, LuaError> {
+        self.eval(expr)?.to_number().ok_or_else(|| self.error(&format!("'for' {} value must be a number", what)))
+    }
+
+    fn closure(&self, body: &Arc<FunctionBody>) -> Value {
+        Value::Function(Rc::new(Closure { body: body.clone(), captured: self.locals.clone() }))
+    }
+
+    fn local(&self, name: &str) -> Option<&Rc<RefCell<Value>>> {
+        self.locals.iter().rev().find(|(local, _)| &**local == name).map(|(_, cell)| cell)
+    }
+
+    fn assign(&mut self, targets: &[Expr], exprs: &[Expr]) -> Result<(), LuaError> {
+        // Table and key of each indexed target are evaluated before the values
+        let mut places = Vec::with_capacity(targets.len());
+        for target in targets {
+            places.push(match target {
+                Expr::Index(object, key) => Some((self.eval(object)?, self.eval(key)?)),
+                _ => None,
+            });
+        }
+        let mut values = self.eval_list(exprs)?.into_iter();
+        for (target, place) in targets.iter().zip(places) {
+            let value = values.next().unwrap_or(Value::Nil);
+            match (target, place) {
+                (_, Some((object, key))) => self.set_index(target, &object, key, value)?,
+                (Expr::Name(name), None) => self.set_variable(name, value)?,
+                _ => unreachable!("assignment targets are checked by the parser"),
+            }
+        }
+        Ok(())
+    }
+
+    fn set_variable(&mut self, name: &str, value: Value) -> Result<(), LuaError> {
+        if let Some(cell) = self.local(name) {
+            *cell.borrow_mut() = value;
+            return Ok(());
+        }
+        let mut globals = self.globals.borrow_mut();
+        if self.protect_globals && matches!(globals.get_str(name), Value::Nil) {
+            drop(globals);
+            return Err(self.error(&format!("Script attempted to create global variable '{}'", name)));
+        }
+        globals.set_str(name, value);
+        Ok(())
+    }
+
+    fn set_index(&self, target: &Expr, object: &Value, key: Value, value: Value) -> Result<(), LuaError> {
+        let Value::Table(table) = object else {
+            let Expr::Index(object_expr, _) = target else { unreachable!() };
+            return Err(self.type_error("index", object_expr, object));
+        };
+        if Rc::ptr_eq(table, &self.globals) && self.protect_globals {
+            let name = String::from_utf8_lossy(&key.to_display()).into_owned();
+            if matches!(table.borrow().get(&key), Value::Nil) {
+                return Err(self.error(&format!("Script attempted to create global variable '{}'", name)));
+            }
+        }
+        table.borrow_mut().set(key, value).map_err(|msg| self.error(msg))
+    }
+
+    // "attempt to call a nil value (global 'foo')" style errors, naming the variable if known
+    fn type_error(&self, action: &str, expr: &Expr, value: &Value) -> LuaError {
+        let name = match expr {
+            Expr::Name(name) if self.local(name).is_some() => format!(" (local '{}')", name),
+            Expr::Name(name) => format!(" (global '{}')", name),
+            Expr::Index(_, key) => match key.as_ref() {
+                Expr::Str(field) => format!(" (field '{}')", String::from_utf8_lossy(field)),
+                _ => String::new(),
+            },
+            Expr::Method(_, method, _) => format!(" (method '{}')", method),
+            _ => String::new(),
+        };
+        self.error(&format!("attempt to {} a {} value{}", action, value.type_name(), name))
+    }
+
+    fn lookup(&self, name: &str) -> Result<Value, LuaError> {
+        if let Some(cell) = self.local(name) {
+            return Ok(cell.borrow().clone());
+        }
+        let value = self.globals.borrow().get_str(name);
+        if self.protect_globals && matches!(value, Value::Nil) {
+            return Err(self.error(&format!("Script attempted to access nonexistent global variable '{}'", name)));
+        }
+        Ok(value)
+    }
+
+    pub fn index(&self, object: &Value, key: &Value) -> Option<Value> {
+        match object {
+            Value::Table(table) => Some(table.borrow().get(key)),
+            Value::Str(_) => Some(self.string_lib.borrow().get(key)),
+            _ => None,
+        }
+    }
+
+    // Evaluate to a single value, truncating calls and `...` to their first value
+    fn eval(&mut self, expr: &Expr) -> Result<Value, LuaError> {
+        let value = match expr {
+            Expr::Nil => Value::Nil,
+            Expr::True => Value::Bool(true),
+            Expr::False => Value::Bool(false),
+            Expr::Number(n) => Value::Number(*n),
+            Expr::Str(s) => Value::str(s),
+            Expr::VarArgs => self.varargs.first().cloned().unwrap_or(Value::Nil),
+            Expr::Function(body) => self.closure(body),
+            Expr::Table(fields) => self.table(fields)?,
+            Expr::Name(name) => self.lookup(name)?,
+            Expr::Index(object_expr, key) => {
+                let object = self.eval(object_expr)?;
+                let key = self.eval(key)?;
+                match self.index(&object, &key) {
+                    Some(value) => value,
+                    None => return Err(self.type_error("index", object_expr, &object)),
+                }
+            }
+            Expr::Call(..) | Expr::Method(..) => self.eval_multi(expr)?.into_iter().next().unwrap_or(Value::Nil),
+            Expr::Paren(inner) => self.eval(inner)?,
+            Expr::Unary(op, operand) => {
+                let value = self.eval(operand)?;
+                match op {
+                    UnOp::Not => Value::Bool(!value.truthy()),
+                    UnOp::Neg => match value.to_number() {
+                        Some(n) => Value::Number(-n),
+                        None => return Err(self.type_error("perform arithmetic on", operand, &value)),
+                    },
+                    UnOp::Len => match &value {
+                        Value::Str(s) => Value::Number(s.len() as f64),
+                        Value::Table(t) => Value::Number(t.borrow().len() as f64),
+                        _ => return Err(self.type_error("get length of", operand, &value)),
+                    },
+                }
+            }
+            Expr::Binary(BinOp::And, left, right) => {
+                let left = self.eval(left)?;
+                if !left.truthy() {
+                    return Ok(left);
+                }
+                self.eval(right)?
+            }
+            Expr::Binary(BinOp::Or, left, right) => {
+                let left = self.eval(left)?;
+                if left.truthy() {
+                    return Ok(left);
+                }
+                self.eval(right)?
+            }
+            Expr::Binary(op, left_expr, right_expr) => {
+                let left = self.eval(left_expr)?;
+                let right = self.eval(right_expr)?;
+                self.binary(*op, (left_expr, left), (right_expr, right))?
+            }
+        };
+        Ok(value)
+    }
+
+    fn binary(&self, op: BinOp, (left_expr, left): (&Expr, Value), (right_expr, right): (&Expr, Value)) -> Result<Value, LuaError> {
+        let value = match op {
+            BinOp::Eq => Value::Bool(left.raw_equals(&right)),
+            BinOp::Ne => Value::Bool(!left.raw_equals(&right)),
+            BinOp::Lt => Value::Bool(self.less_than(&left, &right, false)?),
+            BinOp::Le => Value::Bool(self.less_than(&left, &right, true)?),
+            BinOp::Gt => Value::Bool(self.less_than(&right, &left, false)?),
+            BinOp::Ge => Value::Bool(self.less_than(&right, &left, true)?),
+            BinOp::Concat => match (left.to_bytes(), right.to_bytes()) {
+                (Some(mut a), Some(b)) => {
+                    a.extend_from_slice(&b);
+                    Value::str(a)
+                }
+                (None, _) => return Err(self.type_error("concatenate", left_expr, &left)),
+                (_, None) => return Err(self.type_error("concatenate", right_expr, &right)),
+            },
+            _ => {
+                let (a, b) = match (left.to_number(), right.to_number()) {
+                    (Some(a), Some(b)) => (a, b),
+                    (None, _) => return Err(self.type_error("perform arithmetic on", left_expr, &left)),
+                    (_, None) => return Err(self.type_error("perform arithmetic on", right_expr, &right)),
+                };
+                Value::Number(match op {
+                    BinOp::Add => a + b,
+                    BinOp::Sub => a - b,
+                    BinOp::Mul => a * b,
+                    BinOp::Div => a / b,
+                    BinOp::Mod => a - (a / b).floor() * b,
+                    BinOp::Pow => a.powf(b),
+                    _ => unreachable!(),
+                })
+            }
+        };
+        Ok(value)
+    }
+
+    fn less_than(&self, a: &Value, b: &Value, or_equal: bool) -> Result<bool, LuaError> {
+        match (a, b) {
+            (Value::Number(a), Value::Number(b)) => Ok(if or_equal { a <= b } else { a < b }),
+            (Value::Str(a), Value::Str(b)) => Ok(if or_equal { a <= b } else { a < b }),
+            (a, b) if a.type_name() == b.type_name() => Err(self.error(&format!("attempt to compare two {} values", a.type_name()))),
+            (a, b) => Err(self.error(&format!("attempt to compare {} with {}", a.type_name(), b.type_name()))),
+        }
+    }
+
+    fn table(&mut self, fields: &[TableField]) -> Result<Value, LuaError> {
+        let mut table = Table::default();
+        let mut position = 1;
+        for (i, field) in fields.iter().enumerate() {
+            match field {
+                TableField::Keyed(key, value) => {
+                    let key = self.eval(key)?;
+                    let value = self.eval(value)?;
+                    table.set(key, value).map_err(|msg| self.error(msg))?;
+                }
+                // The last positional field expands if it's a call or `...`
+                TableField::Positional(expr) if i == fields.len() - 1 && expr.is_multi() => {
+                    for value in self.eval_multi(expr)? {
+                        let _ = table.set(Value::Number(position as f64), value);
+                        position += 1;
+                    }
+                }
+                TableField::Positional(expr) => {
+                    let value = self.eval(expr)?;
+                    let _ = table.set(Value::Number(position as f64), value);
+                    position += 1;
+                }
+            }
+        }
+        Ok(Value::table(table))
+    }
+
+    // Evaluate an expression list, with only the last expression expanding to several values
+    fn eval_list(&mut self, exprs: &[Expr]) -> Result<Vec<Value>, LuaError> {
+        let mut values = Vec::with_capacity(exprs.len());
+        for (i, expr) in exprs.iter().enumerate() {
+            if i == exprs.len() - 1 && expr.is_multi() {
+                values.extend(self.eval_multi(expr)?);
+            } else {
+                values.push(self.eval(expr)?);
+            }
+        }
+        Ok(values)
+    }
+
+    // Every value of a call or `...`
+    fn eval_multi(&mut self, expr: &Expr) -> Result<Vec<Value>, LuaError> {
+        let line = self.line;
+        let (function, args) = match expr {
+            Expr::VarArgs => return Ok(self.varargs.clone()),
+            Expr::Call(function_expr, args) => {
+                let function = self.eval(function_expr)?;
+                if !matches!(function, Value::Function(_) | Value::Builtin(..)) {
+                    return Err(self.type_error("call", function_expr, &function));
+                }
+                (function, self.eval_list(args)?)
+            }
+            Expr::Method(object_expr, method, args) => {
+                let object = self.eval(object_expr)?;
+                let Some(function) = self.index(&object, &Value::str(method)) else {
+                    return Err(self.type_error("index", object_expr, &object));
+                };
+                if !matches!(function, Value::Function(_) | Value::Builtin(..)) {
+                    return Err(self.type_error("call", expr, &function));
+                }
+                let mut values = vec![object];
+                values.extend(self.eval_list(args)?);
+                (function, values)
+            }
+            expr => return Ok(vec![self.eval(expr)?]),
+        };
+        let result = self.call_function(&function, args);
+        if result.is_ok() {
+            self.line = line;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::parser;
+
+    struct NoHost;
+
+    impl Host for NoHost {
+        fn call(&mut self, _: Vec<Vec<u8>>) -> DataType {
+            DataType::NullBulkString
+        }
+    }
+
+    // What a chunk returns, each value as tostring() would show it, or its error message. Run
+    // on a stack as big as the server's workers have, so deep recursion hits the depth limit.
+    fn run(source: &str) -> Result<Vec<String>, String> {
+        let source = source.to_string();
+        let run = move || {
+            let chunk = parser::parse(source.as_bytes()).map_err(|(msg, line)| format!("{}: {}", line, msg))?;
+            let mut host = NoHost;
+            let mut interp = Interpreter::new(&mut host, "test");
+            interp.set_time_limit(Duration::from_millis(100));
+            match interp.run(chunk) {
+                Ok(values) => Ok(values.iter().map(|value| String::from_utf8_lossy(&value.to_display()).into_owned()).collect()),
+                Err(err) => Err(err.message()),
+            }
+        };
+        std::thread::Builder::new().stack_size(16 << 20).spawn(run).unwrap().join().unwrap()
+    }
+
+    fn returns(source: &str) -> Vec<String> {
+        run(source).unwrap_or_else(|err| panic!("{}: {}", source, err))
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(returns("return 1 + 2 * 3, (1 + 2) * 3, 7 / 2, 7 % 3, -7 % 3, 2 ^ 10"), ["7", "9", "3.5", "1", "2", "1024"]);
+        assert_eq!(returns("return '10' + 5, 10 .. 5, 1e3, 0x10, -(-3)"), ["15", "105", "1000", "16", "3"]);
+        assert_eq!(returns("return 1 < 2, 'a' < 'b', 1 == 1.0, 2 ~= 2, not nil"), ["true", "true", "true", "false", "true"]);
+        assert_eq!(returns("return nil or 'x', false and 1, 1 and 2"), ["x", "false", "2"]);
+        assert_eq!(returns("return math.floor(3.7), math.max(1, 5, 3), math.abs(-2)"), ["3", "5", "2"]);
+        assert_eq!(run("return 1 + {}").unwrap_err(), "test:1: attempt to perform arithmetic on a table value");
+    }
+
+    #[test]
+    fn control_flow_and_closures() {
+        let source = "
+            local total = 0
+            for i = 1, 10 do
+                if i % 2 == 0 then total = total + i end
+            end
+            local n = 0
+            while true do n = n + 1; if n == 5 then break end end
+            local function counter()
+                local count = 0
+                return function() count = count + 1; return count end
+            end
+            local next_count = counter()
+            next_count()
+            return total, n, next_count()";
+        assert_eq!(returns(source), ["30", "5", "2"]);
+        assert_eq!(returns("local function fact(n) if n <= 1 then return 1 end return n * fact(n - 1) end return fact(10)"), ["3628800"]);
+        assert_eq!(returns("local function f(...) return select('#', ...), ... end return f(1, nil, 3)"), ["3", "1", "nil", "3"]);
+    }
+
+    #[test]
+    fn tables() {
+        let source = "
+            local t = {10, 20, 30, x = 'y'}
+            table.insert(t, 40)
+            table.insert(t, 1, 5)
+            local removed = table.remove(t)
+            local sum = 0
+            for _, v in ipairs(t) do sum = sum + v end
+            local keys = 0
+            for _ in pairs(t) do keys = keys + 1 end
+            return #t, t[1], t.x, removed, sum, keys";
+        assert_eq!(returns(source), ["4", "5", "y", "40", "65", "5"]);
+        assert_eq!(returns("local t = {3, 1, 2} table.sort(t) return table.concat(t, ',')"), ["1,2,3"]);
+        assert_eq!(returns("local t = {3, 1, 2} table.sort(t, function(a, b) return a > b end) return unpack(t)"), ["3", "2", "1"]);
+        assert_eq!(run("local t = nil return t.x").unwrap_err(), "test:1: attempt to index a nil value (local 't')");
+    }
+
+    #[test]
+    fn string_functions() {
+        assert_eq!(returns("return string.upper('abc'), ('abc'):len(), string.sub('hello', 2, -2), string.rep('ab', 3)"), ["ABC", "3", "ell", "ababab"]);
+        assert_eq!(returns("return string.format('%d-%s-%5.2f', 7, 'x', 3.14159), string.byte('A'), string.char(72, 105)"), ["7-x- 3.14", "65", "Hi"]);
+        assert_eq!(returns("return string.find('hello world', 'o w')"), ["5", "7"]);
+        assert_eq!(returns("return string.match('key:123', '(%a+):(%d+)')"), ["key", "123"]);
+        assert_eq!(returns("return string.gsub('hello world', 'o', '0')"), ["hell0 w0rld", "2"]);
+        assert_eq!(returns("local words = {} for w in string.gmatch('a bb ccc', '%a+') do table.insert(words, w) end return #words, words[3]"), ["3", "ccc"]);
+        assert_eq!(returns("return tostring(12), tonumber('0x1f'), tonumber('z'), type('s')"), ["12", "31", "nil", "string"]);
+    }
+
+    #[test]
+    fn errors_and_pcall() {
+        assert_eq!(returns("return pcall(error, 'boom', 0)"), ["false", "boom"]);
+        assert_eq!(returns("return pcall(function() error('boom') end)"), ["false", "test:1: boom"]);
+        assert_eq!(returns("return pcall(function(a) return a * 2 end, 21)"), ["true", "42"]);
+        assert_eq!(run("error('boom', 0)").unwrap_err(), "boom");
+        assert_eq!(run("local function f() return f() + 1 end return f()").unwrap_err(), "test:1: stack overflow");
+    }
+
+    #[test]
+    fn runaway_loops_are_stopped() {
+        let error = "test:1: Script exceeded the time limit of 100 milliseconds";
+        assert_eq!(run("while true do end").unwrap_err(), error);
+        assert_eq!(run("repeat until false").unwrap_err(), error);
+        assert_eq!(run("local i = 0 for i = 1, math.huge do i = i + 1 end").unwrap_err(), error);
+        // pcall can't catch the timeout and carry on
+        assert_eq!(run("while true do pcall(function() while true do end end) end").unwrap_err(), error);
+    }
+}
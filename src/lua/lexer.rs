@@ -0,0 +1,232 @@
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Name(String),
+    Number(f64),
+    Str(Vec<u8>),
+    // Keywords and punctuation
+    Sym(&'static str),
+    Eof,
+}
+
+const KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "in", "local", "nil", "not", "or", "repeat", "return",
+    "then", "true", "until", "while",
+];
+
+// Longest first, so that e.g. `...` isn't read as `..` followed by `.`
+const SYMBOLS: &[&str] = &[
+    "...", "..", "==", "~=", "<=", ">=", "+", "-", "*", "/", "%", "^", "#", "<", ">", "=", "(", ")", "{", "}", "[", "]", ";", ":", ",", ".",
+];
+
+struct Lexer<'a> {
+    src: &'a [u8],
+    pos: usize,
+    line: usize,
+}
+
+impl Lexer<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.src.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.src.get(self.pos + offset).copied()
+    }
+
+    fn error(&self, msg: &str) -> (String, usize) {
+        (msg.to_string(), self.line)
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), (String, usize)> {
+        loop {
+            match self.peek() {
+                Some(b'\n') => {
+                    self.line += 1;
+                    self.pos += 1;
+                }
+                Some(b' ' | b'\t' | b'\r') => self.pos += 1,
+                Some(b'-') if self.peek_at(1) == Some(b'-') => {
+                    self.pos += 2;
+                    if self.peek() == Some(b'[') {
+                        if let Some(level) = self.long_bracket_level() {
+                            self.long_string(level)?;
+                            continue;
+                        }
+                    }
+                    while self.peek().is_some_and(|b| b != b'\n') {
+                        self.pos += 1;
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    // Number of `=` in an opening long bracket such as `[==[` at the current position
+    fn long_bracket_level(&self) -> Option<usize> {
+        let mut level = 0;
+        while self.peek_at(1 + level) == Some(b'=') {
+            level += 1;
+        }
+        (self.peek_at(1 + level) == Some(b'[')).then_some(level)
+    }
+
+    fn long_string(&mut self, level: usize) -> Result<Vec<u8>, (String, usize)> {
+        self.pos += level + 2;
+        // A newline straight after the opening bracket is skipped
+        if self.peek() == Some(b'\r') {
+            self.pos += 1;
+        }
+        if self.peek() == Some(b'\n') {
+            self.line += 1;
+            self.pos += 1;
+        }
+        let close = [b"]".as_slice(), &vec![b'='; level], b"]"].concat();
+        let start = self.pos;
+        loop {
+            if self.src[self.pos..].starts_with(&close) {
+                let text = self.src[start..self.pos].to_vec();
+                self.pos += close.len();
+                return Ok(text);
+            }
+            match self.peek() {
+                None => return Err(self.error("unfinished long string")),
+                Some(b'\n') => self.line += 1,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn quoted_string(&mut self, quote: u8) -> Result<Vec<u8>, (String, usize)> {
+        self.pos += 1;
+        let mut text = Vec::new();
+        loop {
+            let Some(b) = self.peek() else {
+                return Err(self.error("unfinished string"));
+            };
+            self.pos += 1;
+            match b {
+                b'\n' => return Err(self.error("unfinished string")),
+                b if b == quote => return Ok(text),
+                b'\\' => {
+                    let Some(escape) = self.peek() else {
+                        return Err(self.error("unfinished string"));
+                    };
+                    self.pos += 1;
+                    match escape {
+                        b'n' => text.push(b'\n'),
+                        b't' => text.push(b'\t'),
+                        b'r' => text.push(b'\r'),
+                        b'a' => text.push(7),
+                        b'b' => text.push(8),
+                        b'f' => text.push(12),
+                        b'v' => text.push(11),
+                        b'\n' => {
+                            self.line += 1;
+                            text.push(b'\n');
+                        }
+                        b'x' => {
+                            let digits = self.src.get(self.pos..self.pos + 2).and_then(|hex| std::str::from_utf8(hex).ok());
+                            match digits.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                                Some(byte) => text.push(byte),
+                                None => return Err(self.error("hexadecimal digit expected")),
+                            }
+                            self.pos += 2;
+                        }
+                        b'0'..=b'9' => {
+                            let mut value = (escape - b'0') as u32;
+                            for _ in 0..2 {
+                                match self.peek() {
+                                    Some(digit @ b'0'..=b'9') => {
+                                        value = value * 10 + (digit - b'0') as u32;
+                                        self.pos += 1;
+                                    }
+                                    _ => break,
+                                }
+                            }
+                            if value > 255 {
+                                return Err(self.error("escape sequence too large"));
+                            }
+                            text.push(value as u8);
+                        }
+                        other => text.push(other),
+                    }
+                }
+                b => text.push(b),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<f64, (String, usize)> {
+        let start = self.pos;
+        if self.peek() == Some(b'0') && matches!(self.peek_at(1), Some(b'x' | b'X')) {
+            self.pos += 2;
+            while self.peek().is_some_and(|b| b.is_ascii_hexdigit()) {
+                self.pos += 1;
+            }
+        } else {
+            while let Some(b) = self.peek() {
+                let exponent_sign = matches!(b, b'+' | b'-') && matches!(self.src[self.pos - 1], b'e' | b'E');
+                if !(b.is_ascii_alphanumeric() || b == b'.' || exponent_sign) {
+                    break;
+                }
+                self.pos += 1;
+            }
+        }
+        let text = String::from_utf8_lossy(&self.src[start..self.pos]);
+        super::parse_number(&text).ok_or_else(|| self.error(&format!("malformed number near '{}'", text)))
+    }
+
+    fn next_token(&mut self) -> Result<Token, (String, usize)> {
+        self.skip_whitespace_and_comments()?;
+        let Some(b) = self.peek() else {
+            return Ok(Token::Eof);
+        };
+        if b.is_ascii_alphabetic() || b == b'_' {
+            let start = self.pos;
+            while self.peek().is_some_and(|b| b.is_ascii_alphanumeric() || b == b'_') {
+                self.pos += 1;
+            }
+            let name = std::str::from_utf8(&self.src[start..self.pos]).unwrap();
+            return Ok(match KEYWORDS.iter().find(|keyword| **keyword == name) {
+                Some(keyword) => Token::Sym(keyword),
+                None => Token::Name(name.to_string()),
+            });
+        }
+        if b.is_ascii_digit() || (b == b'.' && self.peek_at(1).is_some_and(|b| b.is_ascii_digit())) {
+            return self.number().map(Token::Number);
+        }
+        match b {
+            b'"' | b'\'' => return self.quoted_string(b).map(Token::Str),
+            b'[' => {
+                if let Some(level) = self.long_bracket_level() {
+                    return self.long_string(level).map(Token::Str);
+                }
+            }
+            _ => {}
+        }
+        match SYMBOLS.iter().find(|symbol| self.src[self.pos..].starts_with(symbol.as_bytes())) {
+            Some(symbol) => {
+                self.pos += symbol.len();
+                Ok(Token::Sym(symbol))
+            }
+            None => Err(self.error(&format!("unexpected symbol near '{}'", b as char))),
+        }
+    }
+}
+
+// Split a chunk into tokens, each with the line it starts on. Errors carry the line too.
+pub fn tokenize(src: &[u8]) -> Result<Vec<(Token, usize)>, (String, usize)> {
+    let mut lexer = Lexer { src, pos: 0, line: 1 };
+    let mut tokens = Vec::new();
+    loop {
+        let token = lexer.next_token()?;
+        let line = lexer.line;
+        let done = token == Token::Eof;
+        tokens.push((token, line));
+        if done {
+            return Ok(tokens);
+        }
+    }
+}
@@ -0,0 +1,363 @@
+// A small Lua 5.1 interpreter for scripts and functions: the core language with closures and
+// varargs, and the parts of the base, string, table and math libraries scripts tend to use.
+// Metatables and coroutines are not supported.
+pub mod interpreter;
+pub mod lexer;
+pub mod parser;
+pub mod pattern;
+pub mod stdlib;
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc, sync::Arc};
+
+use interpreter::Interpreter;
+use parser::FunctionBody;
+
+pub type Builtin = fn(&mut Interpreter<'_>, Vec<Value>) -> Result<Vec<Value>, LuaError>;
+
+pub type TableRef = Rc<RefCell<Table>>;
+
+// A local variable, shared by reference with the closures that capture it
+pub type Cell = Rc<RefCell<Value>>;
+
+pub struct Closure {
+    pub body: Arc<FunctionBody>,
+    pub captured: Vec<(Rc<str>, Cell)>,
+}
+
+#[derive(Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Str(Rc<[u8]>),
+    Table(TableRef),
+    Function(Rc<Closure>),
+    Builtin(&'static str, Builtin),
+}
+
+impl Value {
+    pub fn str(s: impl AsRef<[u8]>) -> Value {
+        Value::Str(Rc::from(s.as_ref()))
+    }
+
+    pub fn table(table: Table) -> Value {
+        Value::Table(Rc::new(RefCell::new(table)))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::Str(_) => "string",
+            Value::Table(_) => "table",
+            Value::Function(_) | Value::Builtin(..) => "function",
+        }
+    }
+
+    pub fn truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    // Numbers and strings that look like numbers, as arithmetic accepts them
+    pub fn to_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::Str(s) => parse_number(std::str::from_utf8(s).ok()?),
+            _ => None,
+        }
+    }
+
+    // Strings and numbers, as concatenation and the string library accept them
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            Value::Str(s) => Some(s.to_vec()),
+            Value::Number(n) => Some(format_number(*n).into_bytes()),
+            _ => None,
+        }
+    }
+
+    pub fn raw_equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Table(a), Value::Table(b)) => Rc::ptr_eq(a, b),
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Builtin(a, _), Value::Builtin(b, _)) => a == b,
+            _ => false,
+        }
+    }
+
+    // What tostring() produces
+    pub fn to_display(&self) -> Vec<u8> {
+        match self {
+            Value::Nil => b"nil".to_vec(),
+            Value::Bool(b) => b.to_string().into_bytes(),
+            Value::Number(n) => format_number(*n).into_bytes(),
+            Value::Str(s) => s.to_vec(),
+            Value::Table(t) => format!("table: {:p}", Rc::as_ptr(t)).into_bytes(),
+            Value::Function(f) => format!("function: {:p}", Rc::as_ptr(f)).into_bytes(),
+            Value::Builtin(name, _) => format!("function: builtin: {}", name).into_bytes(),
+        }
+    }
+}
+
+// Hashable identity of a table key. Tables and functions are keyed by address, which is stable
+// since the table keeps the key value alive.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Bool(bool),
+    Number(u64),
+    Str(Rc<[u8]>),
+    Address(usize),
+}
+
+impl Key {
+    fn new(value: &Value) -> Option<Key> {
+        let key = match value {
+            Value::Nil => return None,
+            Value::Number(n) if n.is_nan() => return None,
+            Value::Bool(b) => Key::Bool(*b),
+            // 0.0 and -0.0 are the same key
+            Value::Number(n) => Key::Number((n + 0.0).to_bits()),
+            Value::Str(s) => Key::Str(s.clone()),
+            Value::Table(t) => Key::Address(Rc::as_ptr(t) as *const () as usize),
+            Value::Function(f) => Key::Address(Rc::as_ptr(f) as *const () as usize),
+            Value::Builtin(_, f) => Key::Address(*f as usize),
+        };
+        Some(key)
+    }
+}
+
+// Tables keep consecutive integer keys from 1 in an array and everything else in insertion
+// order, which makes iteration order deterministic. Removed entries stay as nil slots so that
+// next() keeps working while a traversal clears fields.
+#[derive(Default)]
+pub struct Table {
+    array: Vec<Value>,
+    slots: Vec<(Value, Value)>,
+    index: HashMap<Key, usize>,
+}
+
+fn array_index(key: &Value) -> Option<usize> {
+    match key {
+        Value::Number(n) if n.fract() == 0.0 && *n >= 1.0 && *n <= usize::MAX as f64 => Some(*n as usize),
+        _ => None,
+    }
+}
+
+impl Table {
+    pub fn from_array(items: Vec<Value>) -> Table {
+        let mut table = Table::default();
+        for item in items {
+            table.push(item);
+        }
+        table
+    }
+
+    pub fn get(&self, key: &Value) -> Value {
+        if let Some(i) = array_index(key).filter(|i| *i <= self.array.len()) {
+            return self.array[i - 1].clone();
+        }
+        match Key::new(key).and_then(|key| self.index.get(&key)) {
+            Some(slot) => self.slots[*slot].1.clone(),
+            None => Value::Nil,
+        }
+    }
+
+    pub fn get_str(&self, key: &str) -> Value {
+        self.get(&Value::str(key))
+    }
+
+    // Fails for nil and NaN keys
+    pub fn set(&mut self, key: Value, value: Value) -> Result<(), &'static str> {
+        if let Some(i) = array_index(&key) {
+            if i <= self.array.len() {
+                self.array[i - 1] = value;
+                while matches!(self.array.last(), Some(Value::Nil)) {
+                    self.array.pop();
+                }
+                return Ok(());
+            }
+            if i == self.array.len() + 1 && !matches!(value, Value::Nil) {
+                self.remove_slot(&key);
+                self.array.push(value);
+                self.migrate_from_slots();
+                return Ok(());
+            }
+        }
+        let Some(hashed) = Key::new(&key) else {
+            return Err(if matches!(key, Value::Nil) { "table index is nil" } else { "table index is NaN" });
+        };
+        match self.index.get(&hashed) {
+            Some(slot) => self.slots[*slot].1 = value,
+            None if matches!(value, Value::Nil) => {}
+            None => {
+                self.index.insert(hashed, self.slots.len());
+                self.slots.push((key, value));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_str(&mut self, key: &str, value: Value) {
+        let _ = self.set(Value::str(key), value);
+    }
+
+    pub fn push(&mut self, value: Value) {
+        let key = Value::Number((self.len() + 1) as f64);
+        let _ = self.set(key, value);
+    }
+
+    fn remove_slot(&mut self, key: &Value) {
+        if let Some(slot) = Key::new(key).and_then(|key| self.index.get(&key)) {
+            self.slots[*slot].1 = Value::Nil;
+        }
+    }
+
+    // Move integer keys that now continue the array out of the hashed part
+    fn migrate_from_slots(&mut self) {
+        loop {
+            let next = Value::Number((self.array.len() + 1) as f64);
+            let Some(&slot) = Key::new(&next).and_then(|key| self.index.get(&key)) else {
+                return;
+            };
+            let value = std::mem::replace(&mut self.slots[slot].1, Value::Nil);
+            if matches!(value, Value::Nil) {
+                return;
+            }
+            self.array.push(value);
+        }
+    }
+
+    // The border the length operator reports
+    pub fn len(&self) -> usize {
+        self.array.len()
+    }
+
+    pub fn insert(&mut self, pos: usize, value: Value) {
+        let pos = pos.clamp(1, self.array.len() + 1);
+        self.array.insert(pos - 1, value);
+        self.migrate_from_slots();
+    }
+
+    pub fn remove(&mut self, pos: usize) -> Value {
+        if pos == 0 || pos > self.array.len() {
+            return Value::Nil;
+        }
+        self.array.remove(pos - 1)
+    }
+
+    pub fn array(&self) -> &[Value] {
+        &self.array
+    }
+
+    pub fn array_mut(&mut self) -> &mut Vec<Value> {
+        &mut self.array
+    }
+
+    // The entry after `key` in traversal order, array part first. None once the end is reached,
+    // Err when the key isn't in the table.
+    pub fn next(&self, key: &Value) -> Result<Option<(Value, Value)>, ()> {
+        let start = match key {
+            Value::Nil => 0,
+            key => match array_index(key).filter(|i| *i <= self.array.len()) {
+                Some(i) => i,
+                None => {
+                    let slot = Key::new(key).and_then(|key| self.index.get(&key)).ok_or(())?;
+                    self.array.len() + slot + 1
+                }
+            },
+        };
+        for i in start..self.array.len() {
+            if !matches!(self.array[i], Value::Nil) {
+                return Ok(Some((Value::Number((i + 1) as f64), self.array[i].clone())));
+            }
+        }
+        let first_slot = start.saturating_sub(self.array.len());
+        Ok(self.slots[first_slot.min(self.slots.len())..]
+            .iter()
+            .find(|(_, value)| !matches!(value, Value::Nil))
+            .cloned())
+    }
+}
+
+// An error raised by a script. Runtime errors are strings prefixed with the chunk name and
+// line, while error() can raise any value, and redis.call raises error reply tables.
+pub struct LuaError {
+    pub value: Value,
+}
+
+impl LuaError {
+    pub fn message(&self) -> String {
+        match &self.value {
+            Value::Table(t) => match t.borrow().get_str("err") {
+                Value::Str(err) => String::from_utf8_lossy(&err).into_owned(),
+                _ => "ERR unknown error".to_string(),
+            },
+            value => String::from_utf8_lossy(&value.to_display()).into_owned(),
+        }
+    }
+}
+
+// Number syntax accepted by the lexer and by tonumber(): decimal with optional fraction and
+// exponent, or hexadecimal integers, with surrounding whitespace allowed
+pub fn parse_number(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        let value = u64::from_str_radix(hex, 16).ok()? as f64;
+        return Some(if negative { -value } else { value });
+    }
+    let valid = !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit() || matches!(b, b'.' | b'e' | b'E' | b'+' | b'-'));
+    if !valid || !text.bytes().any(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    text.parse::<f64>().ok()
+}
+
+// Numbers print with up to 14 significant digits, the "%.14g" format of the reference
+// implementation
+pub fn format_number(n: f64) -> String {
+    format_g(n, 14, false)
+}
+
+// C style %g: the shorter of fixed and exponent notation for the given number of significant
+// digits, with trailing zeros removed unless `alternate` is set
+pub fn format_g(n: f64, precision: usize, alternate: bool) -> String {
+    if n.is_nan() {
+        return if n.is_sign_negative() { "-nan".to_string() } else { "nan".to_string() };
+    }
+    if n.is_infinite() {
+        return if n < 0.0 { "-inf".to_string() } else { "inf".to_string() };
+    }
+    let precision = precision.max(1);
+    if n == 0.0 {
+        let zero = if alternate { format!("{:.*}", precision - 1, 0.0) } else { "0".to_string() };
+        return if n.is_sign_negative() { format!("-{}", zero) } else { zero };
+    }
+    // The exponent after rounding to the requested precision
+    let scientific = format!("{:.*e}", precision - 1, n);
+    let exponent: i32 = scientific[scientific.find('e').unwrap() + 1..].parse().unwrap();
+    if exponent < -4 || exponent >= precision as i32 {
+        let (mantissa, _) = scientific.split_at(scientific.find('e').unwrap());
+        let mantissa = if alternate { mantissa.to_string() } else { trim_fraction(mantissa) };
+        format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+    } else {
+        let decimals = (precision as i32 - 1 - exponent).max(0) as usize;
+        let fixed = format!("{:.*}", decimals, n);
+        if alternate { fixed } else { trim_fraction(&fixed) }
+    }
+}
+
+fn trim_fraction(text: &str) -> String {
+    if !text.contains('.') {
+        return text.to_string();
+    }
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
@@ -0,0 +1,539 @@
+use std::sync::Arc;
+
+use super::lexer::{tokenize, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+impl BinOp {
+    fn from_symbol(symbol: &str) -> Option<BinOp> {
+        let op = match symbol {
+            "+" => BinOp::Add,
+            "-" => BinOp::Sub,
+            "*" => BinOp::Mul,
+            "/" => BinOp::Div,
+            "%" => BinOp::Mod,
+            "^" => BinOp::Pow,
+            ".." => BinOp::Concat,
+            "==" => BinOp::Eq,
+            "~=" => BinOp::Ne,
+            "<" => BinOp::Lt,
+            "<=" => BinOp::Le,
+            ">" => BinOp::Gt,
+            ">=" => BinOp::Ge,
+            "and" => BinOp::And,
+            "or" => BinOp::Or,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    // Left and right binding power, as in the reference implementation. Concatenation and
+    // exponentiation are right associative.
+    fn priority(self) -> (u8, u8) {
+        match self {
+            BinOp::Or => (1, 1),
+            BinOp::And => (2, 2),
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => (3, 3),
+            BinOp::Concat => (5, 4),
+            BinOp::Add | BinOp::Sub => (6, 6),
+            BinOp::Mul | BinOp::Div | BinOp::Mod => (7, 7),
+            BinOp::Pow => (10, 9),
+        }
+    }
+}
+
+const UNARY_PRIORITY: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnOp {
+    Neg,
+    Not,
+    Len,
+}
+
+#[derive(Debug)]
+pub enum Expr {
+    Nil,
+    True,
+    False,
+    Number(f64),
+    Str(Vec<u8>),
+    VarArgs,
+    Function(Arc<FunctionBody>),
+    Table(Vec<TableField>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Unary(UnOp, Box<Expr>),
+    Name(String),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    Method(Box<Expr>, String, Vec<Expr>),
+    // A parenthesized expression, which truncates calls and `...` to one value
+    Paren(Box<Expr>),
+}
+
+impl Expr {
+    // Calls and `...` produce every value they have when they end an expression list
+    pub fn is_multi(&self) -> bool {
+        matches!(self, Expr::Call(..) | Expr::Method(..) | Expr::VarArgs)
+    }
+}
+
+#[derive(Debug)]
+pub enum TableField {
+    Positional(Expr),
+    Keyed(Expr, Expr),
+}
+
+#[derive(Debug)]
+pub struct FunctionBody {
+    pub params: Vec<String>,
+    pub varargs: bool,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Debug)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub line: usize,
+}
+
+#[derive(Debug)]
+pub enum StmtKind {
+    Local(Vec<String>, Vec<Expr>),
+    Assign(Vec<Expr>, Vec<Expr>),
+    Call(Expr),
+    Do(Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Repeat(Vec<Stmt>, Expr),
+    If(Vec<(Expr, Vec<Stmt>)>, Option<Vec<Stmt>>),
+    NumericFor(String, Expr, Expr, Option<Expr>, Vec<Stmt>),
+    GenericFor(Vec<String>, Vec<Expr>, Vec<Stmt>),
+    LocalFunction(String, Arc<FunctionBody>),
+    Return(Vec<Expr>),
+    Break,
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+type ParseResult<T> = Result<T, (String, usize)>;
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos].1
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.pos].0.clone();
+        if self.pos + 1 < self.tokens.len() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn check(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Token::Sym(s) if *s == symbol)
+    }
+
+    fn accept(&mut self, symbol: &str) -> bool {
+        let found = self.check(symbol);
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    fn near(&self) -> String {
+        match self.peek() {
+            Token::Name(name) => name.clone(),
+            Token::Number(n) => super::format_number(*n),
+            Token::Str(s) => String::from_utf8_lossy(s).into_owned(),
+            Token::Sym(s) => s.to_string(),
+            Token::Eof => "<eof>".to_string(),
+        }
+    }
+
+    fn error<T>(&self, msg: &str) -> ParseResult<T> {
+        Err((format!("{} near '{}'", msg, self.near()), self.line()))
+    }
+
+    fn expect(&mut self, symbol: &str) -> ParseResult<()> {
+        if !self.accept(symbol) {
+            return self.error(&format!("'{}' expected", symbol));
+        }
+        Ok(())
+    }
+
+    fn name(&mut self) -> ParseResult<String> {
+        match self.peek() {
+            Token::Name(name) => {
+                let name = name.clone();
+                self.advance();
+                Ok(name)
+            }
+            _ => self.error("<name> expected"),
+        }
+    }
+
+    fn block_ends(&self) -> bool {
+        matches!(self.peek(), Token::Eof | Token::Sym("end" | "else" | "elseif" | "until"))
+    }
+
+    fn block(&mut self) -> ParseResult<Vec<Stmt>> {
+        let mut stmts = Vec::new();
+        while !self.block_ends() {
+            if self.accept(";") {
+                continue;
+            }
+            let stmt = self.statement()?;
+            let last = matches!(stmt.kind, StmtKind::Return(_) | StmtKind::Break);
+            stmts.push(stmt);
+            // return and break have to be the last statement of a block
+            if last {
+                self.accept(";");
+                if !self.block_ends() {
+                    return self.error("'end' expected");
+                }
+            }
+        }
+        Ok(stmts)
+    }
+
+    fn statement(&mut self) -> ParseResult<Stmt> {
+        let line = self.line();
+        let kind = match self.peek() {
+            Token::Sym("local") => {
+                self.advance();
+                if self.accept("function") {
+                    let name = self.name()?;
+                    StmtKind::LocalFunction(name, self.function_body(false)?)
+                } else {
+                    let mut names = vec![self.name()?];
+                    while self.accept(",") {
+                        names.push(self.name()?);
+                    }
+                    let values = if self.accept("=") { self.expr_list()? } else { Vec::new() };
+                    StmtKind::Local(names, values)
+                }
+            }
+            Token::Sym("function") => {
+                self.advance();
+                let mut target = Expr::Name(self.name()?);
+                while self.accept(".") {
+                    target = Expr::Index(Box::new(target), Box::new(Expr::Str(self.name()?.into_bytes())));
+                }
+                let method = self.accept(":");
+                if method {
+                    target = Expr::Index(Box::new(target), Box::new(Expr::Str(self.name()?.into_bytes())));
+                }
+                let function = Expr::Function(self.function_body(method)?);
+                StmtKind::Assign(vec![target], vec![function])
+            }
+            Token::Sym("do") => {
+                self.advance();
+                let body = self.block()?;
+                self.expect("end")?;
+                StmtKind::Do(body)
+            }
+            Token::Sym("while") => {
+                self.advance();
+                let condition = self.expr()?;
+                self.expect("do")?;
+                let body = self.block()?;
+                self.expect("end")?;
+                StmtKind::While(condition, body)
+            }
+            Token::Sym("repeat") => {
+                self.advance();
+                let body = self.block()?;
+                self.expect("until")?;
+                StmtKind::Repeat(body, self.expr()?)
+            }
+            Token::Sym("if") => {
+                self.advance();
+                let mut branches = Vec::new();
+                let mut otherwise = None;
+                loop {
+                    let condition = self.expr()?;
+                    self.expect("then")?;
+                    branches.push((condition, self.block()?));
+                    if self.accept("elseif") {
+                        continue;
+                    }
+                    if self.accept("else") {
+                        otherwise = Some(self.block()?);
+                    }
+                    self.expect("end")?;
+                    break;
+                }
+                StmtKind::If(branches, otherwise)
+            }
+            Token::Sym("for") => {
+                self.advance();
+                let first = self.name()?;
+                if self.accept("=") {
+                    let start = self.expr()?;
+                    self.expect(",")?;
+                    let limit = self.expr()?;
+                    let step = if self.accept(",") { Some(self.expr()?) } else { None };
+                    self.expect("do")?;
+                    let body = self.block()?;
+                    self.expect("end")?;
+                    StmtKind::NumericFor(first, start, limit, step, body)
+                } else {
+                    let mut names = vec![first];
+                    while self.accept(",") {
+                        names.push(self.name()?);
+                    }
+                    self.expect("in")?;
+                    let values = self.expr_list()?;
+                    self.expect("do")?;
+                    let body = self.block()?;
+                    self.expect("end")?;
+                    StmtKind::GenericFor(names, values, body)
+                }
+            }
+            Token::Sym("return") => {
+                self.advance();
+                let values = if self.block_ends() || self.check(";") { Vec::new() } else { self.expr_list()? };
+                StmtKind::Return(values)
+            }
+            Token::Sym("break") => {
+                self.advance();
+                StmtKind::Break
+            }
+            _ => {
+                let expr = self.suffixed_expr()?;
+                if self.check("=") || self.check(",") {
+                    let mut targets = vec![expr];
+                    while self.accept(",") {
+                        targets.push(self.suffixed_expr()?);
+                    }
+                    if targets.iter().any(|target| !matches!(target, Expr::Name(_) | Expr::Index(..))) {
+                        return self.error("syntax error");
+                    }
+                    self.expect("=")?;
+                    StmtKind::Assign(targets, self.expr_list()?)
+                } else if matches!(expr, Expr::Call(..) | Expr::Method(..)) {
+                    StmtKind::Call(expr)
+                } else {
+                    return self.error("syntax error");
+                }
+            }
+        };
+        Ok(Stmt { kind, line })
+    }
+
+    // Parameter list and body after `function name`, with methods taking an implicit self
+    fn function_body(&mut self, method: bool) -> ParseResult<Arc<FunctionBody>> {
+        let mut params = Vec::new();
+        if method {
+            params.push("self".to_string());
+        }
+        let mut varargs = false;
+        self.expect("(")?;
+        if !self.check(")") {
+            loop {
+                if self.accept("...") {
+                    varargs = true;
+                    break;
+                }
+                params.push(self.name()?);
+                if !self.accept(",") {
+                    break;
+                }
+            }
+        }
+        self.expect(")")?;
+        let body = self.block()?;
+        self.expect("end")?;
+        Ok(Arc::new(FunctionBody { params, varargs, body }))
+    }
+
+    fn expr_list(&mut self) -> ParseResult<Vec<Expr>> {
+        let mut exprs = vec![self.expr()?];
+        while self.accept(",") {
+            exprs.push(self.expr()?);
+        }
+        Ok(exprs)
+    }
+
+    fn expr(&mut self) -> ParseResult<Expr> {
+        self.subexpr(0)
+    }
+
+    // Precedence climbing over binary operators binding tighter than `limit`
+    fn subexpr(&mut self, limit: u8) -> ParseResult<Expr> {
+        let unary = match self.peek() {
+            Token::Sym("not") => Some(UnOp::Not),
+            Token::Sym("-") => Some(UnOp::Neg),
+            Token::Sym("#") => Some(UnOp::Len),
+            _ => None,
+        };
+        let mut left = match unary {
+            Some(op) => {
+                self.advance();
+                let operand = self.subexpr(UNARY_PRIORITY)?;
+                match (op, operand) {
+                    (UnOp::Neg, Expr::Number(n)) => Expr::Number(-n),
+                    (op, operand) => Expr::Unary(op, Box::new(operand)),
+                }
+            }
+            None => self.simple_expr()?,
+        };
+        while let Some(op) = match self.peek() {
+            Token::Sym(symbol) => BinOp::from_symbol(symbol),
+            _ => None,
+        } {
+            let (left_priority, right_priority) = op.priority();
+            if left_priority <= limit {
+                break;
+            }
+            self.advance();
+            let right = self.subexpr(right_priority)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn simple_expr(&mut self) -> ParseResult<Expr> {
+        let expr = match self.peek() {
+            Token::Number(n) => Expr::Number(*n),
+            Token::Str(s) => Expr::Str(s.clone()),
+            Token::Sym("nil") => Expr::Nil,
+            Token::Sym("true") => Expr::True,
+            Token::Sym("false") => Expr::False,
+            Token::Sym("...") => Expr::VarArgs,
+            Token::Sym("{") => return self.table(),
+            Token::Sym("function") => {
+                self.advance();
+                return Ok(Expr::Function(self.function_body(false)?));
+            }
+            _ => return self.suffixed_expr(),
+        };
+        self.advance();
+        Ok(expr)
+    }
+
+    fn primary_expr(&mut self) -> ParseResult<Expr> {
+        match self.peek() {
+            Token::Name(_) => Ok(Expr::Name(self.name()?)),
+            Token::Sym("(") => {
+                self.advance();
+                let expr = self.expr()?;
+                self.expect(")")?;
+                Ok(Expr::Paren(Box::new(expr)))
+            }
+            _ => self.error("unexpected symbol"),
+        }
+    }
+
+    // A name or parenthesized expression followed by any number of indexes and calls
+    fn suffixed_expr(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.primary_expr()?;
+        loop {
+            expr = match self.peek() {
+                Token::Sym(".") => {
+                    self.advance();
+                    Expr::Index(Box::new(expr), Box::new(Expr::Str(self.name()?.into_bytes())))
+                }
+                Token::Sym("[") => {
+                    self.advance();
+                    let key = self.expr()?;
+                    self.expect("]")?;
+                    Expr::Index(Box::new(expr), Box::new(key))
+                }
+                Token::Sym(":") => {
+                    self.advance();
+                    let name = self.name()?;
+                    Expr::Method(Box::new(expr), name, self.call_args()?)
+                }
+                Token::Sym("(" | "{") | Token::Str(_) => Expr::Call(Box::new(expr), self.call_args()?),
+                _ => return Ok(expr),
+            };
+        }
+    }
+
+    // `(args)`, or a lone table constructor or string literal
+    fn call_args(&mut self) -> ParseResult<Vec<Expr>> {
+        match self.peek() {
+            Token::Str(s) => {
+                let arg = Expr::Str(s.clone());
+                self.advance();
+                Ok(vec![arg])
+            }
+            Token::Sym("{") => Ok(vec![self.table()?]),
+            Token::Sym("(") => {
+                self.advance();
+                let args = if self.check(")") { Vec::new() } else { self.expr_list()? };
+                self.expect(")")?;
+                Ok(args)
+            }
+            _ => self.error("function arguments expected"),
+        }
+    }
+
+    fn table(&mut self) -> ParseResult<Expr> {
+        self.expect("{")?;
+        let mut fields = Vec::new();
+        while !self.check("}") {
+            let field = match self.peek() {
+                Token::Sym("[") => {
+                    self.advance();
+                    let key = self.expr()?;
+                    self.expect("]")?;
+                    self.expect("=")?;
+                    TableField::Keyed(key, self.expr()?)
+                }
+                Token::Name(name) if self.tokens.get(self.pos + 1).is_some_and(|(token, _)| *token == Token::Sym("=")) => {
+                    let key = Expr::Str(name.clone().into_bytes());
+                    self.advance();
+                    self.advance();
+                    TableField::Keyed(key, self.expr()?)
+                }
+                _ => TableField::Positional(self.expr()?),
+            };
+            fields.push(field);
+            if !self.accept(",") && !self.accept(";") {
+                break;
+            }
+        }
+        self.expect("}")?;
+        Ok(Expr::Table(fields))
+    }
+}
+
+// Parse a chunk into the body of a function taking any number of arguments. Errors are the
+// message and the line it happened on.
+pub fn parse(src: &[u8]) -> Result<Arc<FunctionBody>, (String, usize)> {
+    let tokens = tokenize(src)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let body = parser.block()?;
+    if *parser.peek() != Token::Eof {
+        return parser.error("'<eof>' expected");
+    }
+    Ok(Arc::new(FunctionBody { params: Vec::new(), varargs: true, body }))
+}
@@ -0,0 +1,338 @@
+// Lua pattern matching as used by string.find, match, gmatch and gsub. A port of the matcher in
+// the reference implementation: character classes, sets, the * + - ? quantifiers, anchors,
+// captures including position captures, back references, %b and %f.
+
+// Deepest recursion allowed while matching, which bounds backtracking on hostile patterns
+const MAX_DEPTH: usize = 200;
+
+const CAP_UNFINISHED: isize = -1;
+const CAP_POSITION: isize = -2;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Capture {
+    // Byte range of the subject
+    Range(usize, usize),
+    // Position capture `()`, reported 1-based
+    Position(usize),
+}
+
+pub struct Match {
+    pub start: usize,
+    pub end: usize,
+    pub captures: Vec<Capture>,
+}
+
+impl Match {
+    // The captures, or the whole match when the pattern has none
+    pub fn values(&self) -> Vec<Capture> {
+        if self.captures.is_empty() {
+            vec![Capture::Range(self.start, self.end)]
+        } else {
+            self.captures.clone()
+        }
+    }
+}
+
+// Whether a pattern uses anything beyond literal characters, so plain search can be used
+pub fn has_specials(pattern: &[u8]) -> bool {
+    pattern.iter().any(|b| b"^$*+?.([%-".contains(b))
+}
+
+struct MatchState<'a> {
+    src: &'a [u8],
+    pat: &'a [u8],
+    captures: Vec<(usize, isize)>,
+    depth: usize,
+}
+
+fn class_matches(c: u8, class: u8) -> bool {
+    let matches = match class.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => c.is_ascii_whitespace() || c == 0x0b,
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        b'z' => c == 0,
+        _ => return class == c,
+    };
+    if class.is_ascii_uppercase() {
+        !matches
+    } else {
+        matches
+    }
+}
+
+impl MatchState<'_> {
+    // Index just past the single character class starting at p
+    fn class_end(&self, mut p: usize) -> Result<usize, String> {
+        let c = self.pat[p];
+        p += 1;
+        match c {
+            b'%' => {
+                if p >= self.pat.len() {
+                    return Err("malformed pattern (ends with '%')".to_string());
+                }
+                Ok(p + 1)
+            }
+            b'[' => {
+                if self.pat.get(p) == Some(&b'^') {
+                    p += 1;
+                }
+                // The first character of a set is taken literally, even if it is `]`
+                loop {
+                    let Some(&c) = self.pat.get(p) else {
+                        return Err("malformed pattern (missing ']')".to_string());
+                    };
+                    p += 1;
+                    if c == b'%' {
+                        p += 1;
+                    }
+                    if self.pat.get(p) == Some(&b']') {
+                        return Ok(p + 1);
+                    }
+                }
+            }
+            _ => Ok(p),
+        }
+    }
+
+    // Set `[...]` between p (the `[`) and end (the `]`)
+    fn set_matches(&self, c: u8, mut p: usize, end: usize) -> bool {
+        let mut matches = true;
+        p += 1;
+        if self.pat[p] == b'^' {
+            matches = false;
+            p += 1;
+        }
+        while p < end {
+            if self.pat[p] == b'%' && p + 1 < end {
+                p += 1;
+                if class_matches(c, self.pat[p]) {
+                    return matches;
+                }
+                p += 1;
+            } else if self.pat.get(p + 1) == Some(&b'-') && p + 2 < end {
+                if self.pat[p] <= c && c <= self.pat[p + 2] {
+                    return matches;
+                }
+                p += 3;
+            } else {
+                if self.pat[p] == c {
+                    return matches;
+                }
+                p += 1;
+            }
+        }
+        !matches
+    }
+
+    fn single_matches(&self, s: usize, p: usize, ep: usize) -> bool {
+        let Some(&c) = self.src.get(s) else {
+            return false;
+        };
+        match self.pat[p] {
+            b'.' => true,
+            b'%' => class_matches(c, self.pat[p + 1]),
+            b'[' => self.set_matches(c, p, ep - 1),
+            literal => literal == c,
+        }
+    }
+
+    // Match the pattern from p against the subject from s, returning where the match ends
+    fn do_match(&mut self, mut s: usize, mut p: usize) -> Result<Option<usize>, String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err("pattern too complex".to_string());
+        }
+        let result = loop {
+            if p == self.pat.len() {
+                break Ok(Some(s));
+            }
+            match self.pat[p] {
+                b'(' => {
+                    break if self.pat.get(p + 1) == Some(&b')') {
+                        self.start_capture(s, p + 2, CAP_POSITION)
+                    } else {
+                        self.start_capture(s, p + 1, CAP_UNFINISHED)
+                    };
+                }
+                b')' => break self.end_capture(s, p + 1),
+                b'$' if p + 1 == self.pat.len() => break Ok((s == self.src.len()).then_some(s)),
+                b'%' if self.pat.get(p + 1) == Some(&b'b') => match self.match_balance(s, p + 2)? {
+                    Some(end) => {
+                        s = end;
+                        p += 4;
+                        continue;
+                    }
+                    None => break Ok(None),
+                },
+                b'%' if self.pat.get(p + 1) == Some(&b'f') => {
+                    p += 2;
+                    if self.pat.get(p) != Some(&b'[') {
+                        break Err("missing '[' after '%f' in pattern".to_string());
+                    }
+                    let ep = self.class_end(p)?;
+                    let previous = if s == 0 { 0 } else { self.src[s - 1] };
+                    let current = self.src.get(s).copied().unwrap_or(0);
+                    if !self.set_matches(previous, p, ep - 1) && self.set_matches(current, p, ep - 1) {
+                        p = ep;
+                        continue;
+                    }
+                    break Ok(None);
+                }
+                b'%' if self.pat.get(p + 1).is_some_and(u8::is_ascii_digit) => match self.match_back_reference(s, self.pat[p + 1])? {
+                    Some(end) => {
+                        s = end;
+                        p += 2;
+                        continue;
+                    }
+                    None => break Ok(None),
+                },
+                _ => {
+                    let ep = self.class_end(p)?;
+                    let matches = self.single_matches(s, p, ep);
+                    match self.pat.get(ep) {
+                        Some(b'?') => {
+                            if matches {
+                                if let Some(end) = self.do_match(s + 1, ep + 1)? {
+                                    break Ok(Some(end));
+                                }
+                            }
+                            p = ep + 1;
+                        }
+                        Some(b'*') => break self.max_expand(s, p, ep),
+                        Some(b'+') => break if matches { self.max_expand(s + 1, p, ep) } else { Ok(None) },
+                        Some(b'-') => break self.min_expand(s, p, ep),
+                        _ => {
+                            if !matches {
+                                break Ok(None);
+                            }
+                            s += 1;
+                            p = ep;
+                        }
+                    }
+                }
+            }
+        };
+        self.depth -= 1;
+        result
+    }
+
+    fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> Result<Option<usize>, String> {
+        let mut count = 0;
+        while self.single_matches(s + count, p, ep) {
+            count += 1;
+        }
+        // Try the longest run first, backing off one character at a time
+        loop {
+            if let Some(end) = self.do_match(s + count, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if count == 0 {
+                return Ok(None);
+            }
+            count -= 1;
+        }
+    }
+
+    fn min_expand(&mut self, mut s: usize, p: usize, ep: usize) -> Result<Option<usize>, String> {
+        loop {
+            if let Some(end) = self.do_match(s, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if !self.single_matches(s, p, ep) {
+                return Ok(None);
+            }
+            s += 1;
+        }
+    }
+
+    fn start_capture(&mut self, s: usize, p: usize, what: isize) -> Result<Option<usize>, String> {
+        self.captures.push((s, what));
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.captures.pop();
+        }
+        Ok(result)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        let Some(open) = self.captures.iter().rposition(|(_, len)| *len == CAP_UNFINISHED) else {
+            return Err("invalid pattern capture".to_string());
+        };
+        self.captures[open].1 = (s - self.captures[open].0) as isize;
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.captures[open].1 = CAP_UNFINISHED;
+        }
+        Ok(result)
+    }
+
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        let (Some(&open), Some(&close)) = (self.pat.get(p), self.pat.get(p + 1)) else {
+            return Err("missing arguments to '%b'".to_string());
+        };
+        if self.src.get(s) != Some(&open) {
+            return Ok(None);
+        }
+        let mut depth = 1;
+        for (i, &c) in self.src.iter().enumerate().skip(s + 1) {
+            if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if c == open {
+                depth += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    fn match_back_reference(&self, s: usize, digit: u8) -> Result<Option<usize>, String> {
+        let index = (digit - b'1') as usize;
+        let Some(&(start, len)) = self.captures.get(index).filter(|(_, len)| *len >= 0) else {
+            return Err(format!("invalid capture index %{}", digit as char));
+        };
+        let captured = &self.src[start..start + len as usize];
+        Ok(self.src[s..].starts_with(captured).then_some(s + captured.len()))
+    }
+}
+
+// Try to match an unanchored pattern (without a leading `^`) exactly at position s
+pub fn match_at(src: &[u8], pat: &[u8], s: usize) -> Result<Option<Match>, String> {
+    let mut state = MatchState { src, pat, captures: Vec::new(), depth: 0 };
+    let Some(end) = state.do_match(s, 0)? else {
+        return Ok(None);
+    };
+    let mut captures = Vec::with_capacity(state.captures.len());
+    for (start, len) in state.captures {
+        captures.push(match len {
+            CAP_POSITION => Capture::Position(start + 1),
+            CAP_UNFINISHED => return Err("unfinished capture".to_string()),
+            len => Capture::Range(start, start + len as usize),
+        });
+    }
+    Ok(Some(Match { start: s, end, captures }))
+}
+
+// First match at or after init, honouring a leading `^` anchor
+pub fn find(src: &[u8], pat: &[u8], init: usize) -> Result<Option<Match>, String> {
+    let (anchored, pat) = match pat.strip_prefix(b"^") {
+        Some(rest) => (true, rest),
+        None => (false, pat),
+    };
+    for s in init..=src.len() {
+        if let Some(found) = match_at(src, pat, s)? {
+            return Ok(Some(found));
+        }
+        if anchored {
+            break;
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,845 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::{
+    format_g, format_number,
+    interpreter::Interpreter,
+    pattern::{self, Capture},
+    Builtin, LuaError, Table, TableRef, Value,
+};
+
+pub fn arg(args: &[Value], i: usize) -> Value {
+    args.get(i).cloned().unwrap_or(Value::Nil)
+}
+
+fn type_of_arg(args: &[Value], i: usize) -> &'static str {
+    args.get(i).map_or("no value", Value::type_name)
+}
+
+pub fn bad_argument(interp: &Interpreter, i: usize, function: &str, msg: &str) -> LuaError {
+    interp.error(&format!("bad argument #{} to '{}' ({})", i + 1, function, msg))
+}
+
+pub fn check_number(interp: &Interpreter, args: &[Value], i: usize, function: &str) -> Result<f64, LuaError> {
+    arg(args, i)
+        .to_number()
+        .ok_or_else(|| bad_argument(interp, i, function, &format!("number expected, got {}", type_of_arg(args, i))))
+}
+
+fn opt_number(interp: &Interpreter, args: &[Value], i: usize, function: &str, default: f64) -> Result<f64, LuaError> {
+    match args.get(i) {
+        None | Some(Value::Nil) => Ok(default),
+        Some(_) => check_number(interp, args, i, function),
+    }
+}
+
+// Integer arguments are truncated towards zero
+fn check_integer(interp: &Interpreter, args: &[Value], i: usize, function: &str) -> Result<i64, LuaError> {
+    Ok(check_number(interp, args, i, function)? as i64)
+}
+
+fn opt_integer(interp: &Interpreter, args: &[Value], i: usize, function: &str, default: i64) -> Result<i64, LuaError> {
+    Ok(opt_number(interp, args, i, function, default as f64)? as i64)
+}
+
+pub fn check_bytes(interp: &Interpreter, args: &[Value], i: usize, function: &str) -> Result<Vec<u8>, LuaError> {
+    arg(args, i)
+        .to_bytes()
+        .ok_or_else(|| bad_argument(interp, i, function, &format!("string expected, got {}", type_of_arg(args, i))))
+}
+
+pub fn check_table(interp: &Interpreter, args: &[Value], i: usize, function: &str) -> Result<TableRef, LuaError> {
+    match args.get(i) {
+        Some(Value::Table(table)) => Ok(table.clone()),
+        _ => Err(bad_argument(interp, i, function, &format!("table expected, got {}", type_of_arg(args, i)))),
+    }
+}
+
+pub fn library(functions: &[(&'static str, Builtin)]) -> Table {
+    let mut table = Table::default();
+    for (name, function) in functions {
+        table.set_str(name, Value::Builtin(name, *function));
+    }
+    table
+}
+
+// Add the standard libraries to a global table, returning the string library for method calls
+// on string values
+pub fn install(globals: &mut Table) -> TableRef {
+    let base: &[(&'static str, Builtin)] = &[
+        ("assert", lua_assert),
+        ("error", lua_error),
+        ("ipairs", lua_ipairs),
+        ("next", lua_next),
+        ("pairs", lua_pairs),
+        ("pcall", lua_pcall),
+        ("rawequal", lua_rawequal),
+        ("rawget", lua_rawget),
+        ("rawset", lua_rawset),
+        ("select", lua_select),
+        ("tonumber", lua_tonumber),
+        ("tostring", lua_tostring),
+        ("type", lua_type),
+        ("unpack", lua_unpack),
+    ];
+    for (name, function) in base {
+        globals.set_str(name, Value::Builtin(name, *function));
+    }
+    globals.set_str("_VERSION", Value::str("Lua 5.1"));
+
+    let table: &[(&'static str, Builtin)] =
+        &[("concat", table_concat), ("getn", table_getn), ("insert", table_insert), ("remove", table_remove), ("sort", table_sort)];
+    globals.set_str("table", Value::table(library(table)));
+
+    let string: &[(&'static str, Builtin)] = &[
+        ("byte", string_byte),
+        ("char", string_char),
+        ("find", string_find),
+        ("format", string_format),
+        ("gmatch", string_gmatch),
+        ("gsub", string_gsub),
+        ("len", string_len),
+        ("lower", string_lower),
+        ("match", string_match),
+        ("rep", string_rep),
+        ("reverse", string_reverse),
+        ("sub", string_sub),
+        ("upper", string_upper),
+    ];
+    let string = Rc::new(RefCell::new(library(string)));
+    globals.set_str("string", Value::Table(string.clone()));
+
+    let math: &[(&'static str, Builtin)] = &[
+        ("abs", math_abs),
+        ("ceil", math_ceil),
+        ("exp", math_exp),
+        ("floor", math_floor),
+        ("fmod", math_fmod),
+        ("log", math_log),
+        ("log10", math_log10),
+        ("max", math_max),
+        ("min", math_min),
+        ("pow", math_pow),
+        ("random", math_random),
+        ("randomseed", math_randomseed),
+        ("sqrt", math_sqrt),
+    ];
+    let mut math = library(math);
+    math.set_str("huge", Value::Number(f64::INFINITY));
+    math.set_str("pi", Value::Number(std::f64::consts::PI));
+    globals.set_str("math", Value::table(math));
+
+    string
+}
+
+fn lua_assert(_: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    if arg(&args, 0).truthy() {
+        return Ok(args);
+    }
+    let value = match args.get(1) {
+        Some(msg) if !matches!(msg, Value::Nil) => msg.clone(),
+        _ => Value::str("assertion failed!"),
+    };
+    Err(LuaError { value })
+}
+
+// error(message [, level]), where level 0 leaves string messages without a position
+fn lua_error(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let level = opt_integer(interp, &args, 1, "error", 1)?;
+    match arg(&args, 0) {
+        Value::Str(msg) if level > 0 => Err(interp.error(&String::from_utf8_lossy(&msg))),
+        value => Err(LuaError { value }),
+    }
+}
+
+fn ipairs_next(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(interp, &args, 0, "ipairs")?;
+    let i = check_integer(interp, &args, 1, "ipairs")? + 1;
+    let value = table.borrow().get(&Value::Number(i as f64));
+    match value {
+        Value::Nil => Ok(vec![Value::Nil]),
+        value => Ok(vec![Value::Number(i as f64), value]),
+    }
+}
+
+fn lua_ipairs(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(interp, &args, 0, "ipairs")?;
+    Ok(vec![Value::Builtin("ipairs_next", ipairs_next), Value::Table(table), Value::Number(0.0)])
+}
+
+fn lua_next(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(interp, &args, 0, "next")?;
+    let entry = table.borrow().next(&arg(&args, 1));
+    match entry {
+        Ok(Some((key, value))) => Ok(vec![key, value]),
+        Ok(None) => Ok(vec![Value::Nil]),
+        Err(()) => Err(interp.error("invalid key to 'next'")),
+    }
+}
+
+fn lua_pairs(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(interp, &args, 0, "pairs")?;
+    Ok(vec![Value::Builtin("next", lua_next), Value::Table(table), Value::Nil])
+}
+
+fn lua_pcall(interp: &mut Interpreter, mut args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    if args.is_empty() {
+        return Err(bad_argument(interp, 0, "pcall", "value expected"));
+    }
+    let function = args.remove(0);
+    match interp.call_function(&function, args) {
+        Ok(mut values) => {
+            values.insert(0, Value::Bool(true));
+            Ok(values)
+        }
+        Err(err) => Ok(vec![Value::Bool(false), err.value]),
+    }
+}
+
+fn lua_rawequal(_: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    Ok(vec![Value::Bool(arg(&args, 0).raw_equals(&arg(&args, 1)))])
+}
+
+fn lua_rawget(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(interp, &args, 0, "rawget")?;
+    let value = table.borrow().get(&arg(&args, 1));
+    Ok(vec![value])
+}
+
+fn lua_rawset(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(interp, &args, 0, "rawset")?;
+    table.borrow_mut().set(arg(&args, 1), arg(&args, 2)).map_err(|msg| interp.error(msg))?;
+    Ok(vec![Value::Table(table)])
+}
+
+fn lua_select(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    if matches!(args.first(), Some(Value::Str(s)) if &**s == b"#") {
+        return Ok(vec![Value::Number((args.len() - 1) as f64)]);
+    }
+    let n = check_integer(interp, &args, 0, "select")?;
+    let count = args.len() as i64 - 1;
+    let start = match n {
+        n if n < 0 && -n <= count => count + n,
+        n if n > 0 => (n - 1).min(count),
+        _ => return Err(bad_argument(interp, 0, "select", "index out of range")),
+    };
+    Ok(args[1 + start as usize..].to_vec())
+}
+
+fn lua_tonumber(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let base = opt_integer(interp, &args, 1, "tonumber", 10)?;
+    if base == 10 {
+        return Ok(vec![arg(&args, 0).to_number().map_or(Value::Nil, Value::Number)]);
+    }
+    if !(2..=36).contains(&base) {
+        return Err(bad_argument(interp, 1, "tonumber", "base out of range"));
+    }
+    let text = check_bytes(interp, &args, 0, "tonumber")?;
+    let text = String::from_utf8_lossy(&text);
+    let text = text.trim();
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    Ok(vec![match u64::from_str_radix(digits, base as u32) {
+        Ok(n) if !digits.starts_with('+') => Value::Number(if negative { -(n as f64) } else { n as f64 }),
+        _ => Value::Nil,
+    }])
+}
+
+fn lua_tostring(_: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    Ok(vec![Value::str(arg(&args, 0).to_display())])
+}
+
+fn lua_type(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    if args.is_empty() {
+        return Err(bad_argument(interp, 0, "type", "value expected"));
+    }
+    Ok(vec![Value::str(args[0].type_name())])
+}
+
+fn lua_unpack(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(interp, &args, 0, "unpack")?;
+    let table = table.borrow();
+    let first = opt_integer(interp, &args, 1, "unpack", 1)?;
+    let last = opt_integer(interp, &args, 2, "unpack", table.len() as i64)?;
+    Ok((first..=last).map(|i| table.get(&Value::Number(i as f64))).collect())
+}
+
+fn table_concat(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(interp, &args, 0, "concat")?;
+    let table = table.borrow();
+    let separator = match args.get(1) {
+        None | Some(Value::Nil) => Vec::new(),
+        Some(_) => check_bytes(interp, &args, 1, "concat")?,
+    };
+    let first = opt_integer(interp, &args, 2, "concat", 1)?;
+    let last = opt_integer(interp, &args, 3, "concat", table.len() as i64)?;
+    let mut result = Vec::new();
+    for i in first..=last {
+        let Some(item) = table.get(&Value::Number(i as f64)).to_bytes() else {
+            return Err(interp.error(&format!("invalid value (at index {}) in table for 'concat'", i)));
+        };
+        result.extend_from_slice(&item);
+        if i < last {
+            result.extend_from_slice(&separator);
+        }
+    }
+    Ok(vec![Value::str(result)])
+}
+
+fn table_getn(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(interp, &args, 0, "getn")?;
+    let len = table.borrow().len();
+    Ok(vec![Value::Number(len as f64)])
+}
+
+// table.insert(t, value) appends, table.insert(t, pos, value) shifts later elements up
+fn table_insert(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(interp, &args, 0, "insert")?;
+    match args.len() {
+        2 => table.borrow_mut().push(args[1].clone()),
+        3 => {
+            let pos = check_integer(interp, &args, 1, "insert")?;
+            table.borrow_mut().insert(pos.max(1) as usize, args[2].clone());
+        }
+        _ => return Err(interp.error("wrong number of arguments to 'insert'")),
+    }
+    Ok(Vec::new())
+}
+
+fn table_remove(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(interp, &args, 0, "remove")?;
+    let len = table.borrow().len() as i64;
+    let pos = opt_integer(interp, &args, 1, "remove", len)?;
+    if pos < 1 {
+        return Ok(vec![Value::Nil]);
+    }
+    let value = table.borrow_mut().remove(pos as usize);
+    Ok(vec![value])
+}
+
+fn sort_less(interp: &mut Interpreter, comparator: &Value, a: &Value, b: &Value) -> Result<bool, LuaError> {
+    if matches!(comparator, Value::Nil) {
+        return match (a, b) {
+            (Value::Number(a), Value::Number(b)) => Ok(a < b),
+            (Value::Str(a), Value::Str(b)) => Ok(a < b),
+            (a, b) if a.type_name() == b.type_name() => Err(interp.error(&format!("attempt to compare two {} values", a.type_name()))),
+            (a, b) => Err(interp.error(&format!("attempt to compare {} with {}", a.type_name(), b.type_name()))),
+        };
+    }
+    let result = interp.call_function(comparator, vec![a.clone(), b.clone()])?;
+    Ok(result.first().is_some_and(Value::truthy))
+}
+
+// Merge sort, since the comparison can fail or be inconsistent
+fn merge_sort(interp: &mut Interpreter, comparator: &Value, items: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let mut left = items;
+    let right = left.split_off(left.len() / 2);
+    let left = merge_sort(interp, comparator, left)?;
+    let right = merge_sort(interp, comparator, right)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        if sort_less(interp, comparator, b, a)? {
+            merged.push(right.next().unwrap());
+        } else {
+            merged.push(left.next().unwrap());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+fn table_sort(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(interp, &args, 0, "sort")?;
+    let comparator = arg(&args, 1);
+    if !matches!(comparator, Value::Nil | Value::Function(_) | Value::Builtin(..)) {
+        return Err(bad_argument(interp, 1, "sort", &format!("function expected, got {}", comparator.type_name())));
+    }
+    let items = table.borrow().array().to_vec();
+    let sorted = merge_sort(interp, &comparator, items)?;
+    *table.borrow_mut().array_mut() = sorted;
+    Ok(Vec::new())
+}
+
+// 1-based, possibly negative string positions to a 0-based start and exclusive end
+fn string_range(len: usize, start: i64, end: i64) -> (usize, usize) {
+    let resolve = |i: i64| if i < 0 { (len as i64 + i + 1).max(0) } else { i };
+    let start = resolve(start).max(1);
+    let end = resolve(end).min(len as i64);
+    if start > end {
+        (0, 0)
+    } else {
+        (start as usize - 1, end as usize)
+    }
+}
+
+fn string_byte(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let s = check_bytes(interp, &args, 0, "byte")?;
+    let start = opt_integer(interp, &args, 1, "byte", 1)?;
+    let end = opt_integer(interp, &args, 2, "byte", start)?;
+    let (start, end) = string_range(s.len(), start, end);
+    Ok(s[start..end].iter().map(|b| Value::Number(*b as f64)).collect())
+}
+
+fn string_char(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let mut s = Vec::with_capacity(args.len());
+    for i in 0..args.len() {
+        let c = check_integer(interp, &args, i, "char")?;
+        let Ok(c) = u8::try_from(c) else {
+            return Err(bad_argument(interp, i, "char", "invalid value"));
+        };
+        s.push(c);
+    }
+    Ok(vec![Value::str(s)])
+}
+
+fn string_len(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    Ok(vec![Value::Number(check_bytes(interp, &args, 0, "len")?.len() as f64)])
+}
+
+fn string_lower(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    Ok(vec![Value::str(check_bytes(interp, &args, 0, "lower")?.to_ascii_lowercase())])
+}
+
+fn string_upper(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    Ok(vec![Value::str(check_bytes(interp, &args, 0, "upper")?.to_ascii_uppercase())])
+}
+
+fn string_rep(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let s = check_bytes(interp, &args, 0, "rep")?;
+    let n = check_integer(interp, &args, 1, "rep")?;
+    Ok(vec![Value::str(s.repeat(n.max(0) as usize))])
+}
+
+fn string_reverse(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let mut s = check_bytes(interp, &args, 0, "reverse")?;
+    s.reverse();
+    Ok(vec![Value::str(s)])
+}
+
+fn string_sub(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let s = check_bytes(interp, &args, 0, "sub")?;
+    let start = opt_integer(interp, &args, 1, "sub", 1)?;
+    let end = opt_integer(interp, &args, 2, "sub", -1)?;
+    let (start, end) = string_range(s.len(), start, end);
+    Ok(vec![Value::str(&s[start..end])])
+}
+
+fn capture_value(s: &[u8], capture: Capture) -> Value {
+    match capture {
+        Capture::Range(start, end) => Value::str(&s[start..end]),
+        Capture::Position(position) => Value::Number(position as f64),
+    }
+}
+
+// Start offset for find, match and gmatch from a 1-based, possibly negative init argument.
+// None when it is past the end of the subject.
+fn start_offset(len: usize, init: i64) -> Option<usize> {
+    let init = if init < 0 { (len as i64 + init + 1).max(1) } else { init.max(1) };
+    (init as usize <= len + 1).then_some(init as usize - 1)
+}
+
+fn string_find(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    find_or_match(interp, args, true)
+}
+
+fn string_match(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    find_or_match(interp, args, false)
+}
+
+fn find_or_match(interp: &mut Interpreter, args: Vec<Value>, find: bool) -> Result<Vec<Value>, LuaError> {
+    let name = if find { "find" } else { "match" };
+    let s = check_bytes(interp, &args, 0, name)?;
+    let pat = check_bytes(interp, &args, 1, name)?;
+    let Some(init) = start_offset(s.len(), opt_integer(interp, &args, 2, name, 1)?) else {
+        return Ok(vec![Value::Nil]);
+    };
+    if find && (arg(&args, 3).truthy() || !pattern::has_specials(&pat)) {
+        let found = if pat.is_empty() { Some(0) } else { s[init..].windows(pat.len()).position(|window| window == pat.as_slice()) };
+        return Ok(match found {
+            Some(offset) => vec![Value::Number((init + offset + 1) as f64), Value::Number((init + offset + pat.len()) as f64)],
+            None => vec![Value::Nil],
+        });
+    }
+    match pattern::find(&s, &pat, init).map_err(|msg| interp.error(&msg))? {
+        Some(found) if find => {
+            let mut values = vec![Value::Number((found.start + 1) as f64), Value::Number(found.end as f64)];
+            values.extend(found.captures.iter().map(|capture| capture_value(&s, *capture)));
+            Ok(values)
+        }
+        Some(found) => Ok(found.values().into_iter().map(|capture| capture_value(&s, capture)).collect()),
+        None => Ok(vec![Value::Nil]),
+    }
+}
+
+// The iterator state lives in a table passed back as the generic for's state argument
+fn gmatch_next(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let state = check_table(interp, &args, 0, "gmatch")?;
+    let (s, pat, mut position) = {
+        let state = state.borrow();
+        (
+            state.get_str("s").to_bytes().unwrap_or_default(),
+            state.get_str("pattern").to_bytes().unwrap_or_default(),
+            state.get_str("position").to_number().unwrap_or(0.0) as usize,
+        )
+    };
+    while position <= s.len() {
+        if let Some(found) = pattern::match_at(&s, &pat, position).map_err(|msg| interp.error(&msg))? {
+            // An empty match moves on by one character so the loop terminates
+            let next = if found.end == position { found.end + 1 } else { found.end };
+            state.borrow_mut().set_str("position", Value::Number(next as f64));
+            return Ok(found.values().into_iter().map(|capture| capture_value(&s, capture)).collect());
+        }
+        position += 1;
+    }
+    state.borrow_mut().set_str("position", Value::Number((s.len() + 1) as f64));
+    Ok(vec![Value::Nil])
+}
+
+// Works with the generic for, which passes the returned state to each call of the iterator
+fn string_gmatch(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let s = check_bytes(interp, &args, 0, "gmatch")?;
+    let pat = check_bytes(interp, &args, 1, "gmatch")?;
+    let mut state = Table::default();
+    state.set_str("s", Value::str(s));
+    state.set_str("pattern", Value::str(pat));
+    state.set_str("position", Value::Number(0.0));
+    Ok(vec![Value::Builtin("gmatch_next", gmatch_next), Value::table(state)])
+}
+
+// Replacement for one gsub match: a string with %0-%9 references, a table indexed by the first
+// capture, or a function called with the captures. nil and false keep the original text.
+fn gsub_replacement(interp: &mut Interpreter, s: &[u8], found: &pattern::Match, replacement: &Value) -> Result<Vec<u8>, LuaError> {
+    let whole = &s[found.start..found.end];
+    let captures = found.values();
+    let value = match replacement {
+        Value::Str(_) | Value::Number(_) => {
+            let template = replacement.to_bytes().unwrap();
+            let mut result = Vec::new();
+            let mut i = 0;
+            while i < template.len() {
+                if template[i] == b'%' && i + 1 < template.len() {
+                    i += 1;
+                    match template[i] {
+                        b'0' => result.extend_from_slice(whole),
+                        digit @ b'1'..=b'9' => match captures.get((digit - b'1') as usize) {
+                            Some(capture) => result.extend(capture_value(s, *capture).to_display()),
+                            None => return Err(interp.error("invalid capture index")),
+                        },
+                        other => result.push(other),
+                    }
+                } else {
+                    result.push(template[i]);
+                }
+                i += 1;
+            }
+            return Ok(result);
+        }
+        Value::Table(table) => table.borrow().get(&capture_value(s, captures[0])),
+        Value::Function(_) | Value::Builtin(..) => {
+            let args = captures.iter().map(|capture| capture_value(s, *capture)).collect();
+            interp.call_function(replacement, args)?.into_iter().next().unwrap_or(Value::Nil)
+        }
+        _ => unreachable!("checked by gsub"),
+    };
+    match value {
+        Value::Nil | Value::Bool(false) => Ok(whole.to_vec()),
+        value => value.to_bytes().ok_or_else(|| interp.error(&format!("invalid replacement value (a {})", value.type_name()))),
+    }
+}
+
+fn string_gsub(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let s = check_bytes(interp, &args, 0, "gsub")?;
+    let pat = check_bytes(interp, &args, 1, "gsub")?;
+    let replacement = arg(&args, 2);
+    if !matches!(replacement, Value::Str(_) | Value::Number(_) | Value::Table(_) | Value::Function(_) | Value::Builtin(..)) {
+        return Err(bad_argument(interp, 2, "gsub", "string/function/table expected"));
+    }
+    let max = opt_integer(interp, &args, 3, "gsub", s.len() as i64 + 1)?;
+    let (anchored, pat) = match pat.strip_prefix(b"^") {
+        Some(rest) => (true, rest.to_vec()),
+        None => (false, pat),
+    };
+    let mut result = Vec::new();
+    let mut position = 0;
+    let mut count = 0;
+    while count < max {
+        let found = pattern::match_at(&s, &pat, position).map_err(|msg| interp.error(&msg))?;
+        if let Some(found) = &found {
+            count += 1;
+            result.extend(gsub_replacement(interp, &s, found, &replacement)?);
+        }
+        match found {
+            Some(found) if found.end > position => position = found.end,
+            _ if position < s.len() => {
+                result.push(s[position]);
+                position += 1;
+            }
+            _ => break,
+        }
+        if anchored {
+            break;
+        }
+    }
+    result.extend_from_slice(&s[position.min(s.len())..]);
+    Ok(vec![Value::str(result), Value::Number(count as f64)])
+}
+
+// Pad a formatted conversion to the field width
+fn pad(body: String, width: usize, left: bool, zero: bool) -> String {
+    if body.len() >= width {
+        return body;
+    }
+    let fill = width - body.len();
+    if left {
+        format!("{}{}", body, " ".repeat(fill))
+    } else if zero {
+        // Zeros go between the sign and the digits
+        let sign_len = body.find(|c: char| c.is_ascii_digit() || c == 'i' || c == 'n').unwrap_or(0);
+        format!("{}{}{}", &body[..sign_len], "0".repeat(fill), &body[sign_len..])
+    } else {
+        format!("{}{}", " ".repeat(fill), body)
+    }
+}
+
+// C style %e: one digit before the point and an exponent of at least two digits
+fn format_exponent(n: f64, precision: usize, upper: bool) -> String {
+    if !n.is_finite() {
+        return format_g(n, precision, false);
+    }
+    let text = format!("{:.*e}", precision, n);
+    let (mantissa, exponent) = text.split_at(text.find('e').unwrap());
+    let exponent: i32 = exponent[1..].parse().unwrap();
+    let text = format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs());
+    if upper {
+        text.to_uppercase()
+    } else {
+        text
+    }
+}
+
+fn quote_string(s: &[u8]) -> Vec<u8> {
+    let mut quoted = vec![b'"'];
+    for &b in s {
+        match b {
+            b'"' | b'\\' | b'\n' => quoted.extend_from_slice(&[b'\\', b]),
+            b'\r' => quoted.extend_from_slice(b"\\r"),
+            0 => quoted.extend_from_slice(b"\\000"),
+            b => quoted.push(b),
+        }
+    }
+    quoted.push(b'"');
+    quoted
+}
+
+fn string_format(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let template = check_bytes(interp, &args, 0, "format")?;
+    let mut result = Vec::new();
+    let mut next_arg = 1;
+    let mut i = 0;
+    while i < template.len() {
+        if template[i] != b'%' {
+            result.push(template[i]);
+            i += 1;
+            continue;
+        }
+        i += 1;
+        if template.get(i) == Some(&b'%') {
+            result.push(b'%');
+            i += 1;
+            continue;
+        }
+        let mut flags = Vec::new();
+        while let Some(&flag @ (b'-' | b'+' | b' ' | b'#' | b'0')) = template.get(i) {
+            flags.push(flag);
+            i += 1;
+        }
+        let mut width = 0;
+        while let Some(digit @ b'0'..=b'9') = template.get(i) {
+            width = width * 10 + (digit - b'0') as usize;
+            i += 1;
+        }
+        let mut precision = None;
+        if template.get(i) == Some(&b'.') {
+            i += 1;
+            let mut digits = 0;
+            while let Some(digit @ b'0'..=b'9') = template.get(i) {
+                digits = digits * 10 + (digit - b'0') as usize;
+                i += 1;
+            }
+            precision = Some(digits);
+        }
+        let Some(&conversion) = template.get(i) else {
+            return Err(interp.error("invalid option '%' to 'format'"));
+        };
+        i += 1;
+        let (left, zero, plus, space, alternate) =
+            (flags.contains(&b'-'), flags.contains(&b'0'), flags.contains(&b'+'), flags.contains(&b' '), flags.contains(&b'#'));
+        let sign = |n: f64, body: String| {
+            if n.is_sign_negative() && !body.starts_with('-') && n != 0.0 {
+                format!("-{}", body)
+            } else if n >= 0.0 && plus {
+                format!("+{}", body)
+            } else if n >= 0.0 && space {
+                format!(" {}", body)
+            } else {
+                body
+            }
+        };
+        let index = next_arg;
+        next_arg += 1;
+        let text = match conversion {
+            b'd' | b'i' => {
+                let n = check_number(interp, &args, index, "format")? as i64;
+                let digits = n.unsigned_abs().to_string();
+                let digits = match precision {
+                    Some(precision) if digits.len() < precision => format!("{}{}", "0".repeat(precision - digits.len()), digits),
+                    _ => digits,
+                };
+                let body = if n < 0 { format!("-{}", digits) } else { sign(n as f64, digits) };
+                pad(body, width, left, zero && precision.is_none())
+            }
+            b'u' | b'x' | b'X' | b'o' => {
+                let n = check_number(interp, &args, index, "format")? as i64 as u64;
+                let body = match conversion {
+                    b'x' => format!("{}{:x}", if alternate && n != 0 { "0x" } else { "" }, n),
+                    b'X' => format!("{}{:X}", if alternate && n != 0 { "0X" } else { "" }, n),
+                    b'o' => format!("{}{:o}", if alternate { "0" } else { "" }, n),
+                    _ => n.to_string(),
+                };
+                pad(body, width, left, zero)
+            }
+            b'c' => {
+                let c = check_number(interp, &args, index, "format")? as i64 as u8;
+                result.extend(pad(String::new(), width.saturating_sub(1), left, false).into_bytes());
+                result.push(c);
+                continue;
+            }
+            b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => {
+                let n = check_number(interp, &args, index, "format")?;
+                let body = match conversion {
+                    b'e' | b'E' => format_exponent(n, precision.unwrap_or(6), conversion == b'E'),
+                    b'f' | b'F' if n.is_finite() => format!("{:.*}", precision.unwrap_or(6), n),
+                    b'g' | b'G' => {
+                        let text = format_g(n, precision.unwrap_or(6), alternate);
+                        if conversion == b'G' {
+                            text.to_uppercase()
+                        } else {
+                            text
+                        }
+                    }
+                    _ => format_number(n),
+                };
+                pad(sign(n, body), width, left, zero && n.is_finite())
+            }
+            b'q' => {
+                result.extend(quote_string(&check_bytes(interp, &args, index, "format")?));
+                continue;
+            }
+            b's' => {
+                if index >= args.len() {
+                    return Err(bad_argument(interp, index, "format", "string expected, got no value"));
+                }
+                let mut s = args[index].to_display();
+                if let Some(precision) = precision {
+                    s.truncate(precision);
+                }
+                if s.len() < width {
+                    let fill = vec![b' '; width - s.len()];
+                    s = if left { [s, fill].concat() } else { [fill, s].concat() };
+                }
+                result.extend(s);
+                continue;
+            }
+            other => return Err(interp.error(&format!("invalid option '%{}' to 'format'", other as char))),
+        };
+        result.extend(text.into_bytes());
+    }
+    Ok(vec![Value::str(result)])
+}
+
+fn math_unary(interp: &mut Interpreter, args: &[Value], name: &str, f: fn(f64) -> f64) -> Result<Vec<Value>, LuaError> {
+    Ok(vec![Value::Number(f(check_number(interp, args, 0, name)?))])
+}
+
+fn math_abs(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    math_unary(interp, &args, "abs", f64::abs)
+}
+
+fn math_ceil(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    math_unary(interp, &args, "ceil", f64::ceil)
+}
+
+fn math_exp(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    math_unary(interp, &args, "exp", f64::exp)
+}
+
+fn math_floor(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    math_unary(interp, &args, "floor", f64::floor)
+}
+
+fn math_log(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    math_unary(interp, &args, "log", f64::ln)
+}
+
+fn math_log10(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    math_unary(interp, &args, "log10", f64::log10)
+}
+
+fn math_sqrt(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    math_unary(interp, &args, "sqrt", f64::sqrt)
+}
+
+fn math_fmod(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let a = check_number(interp, &args, 0, "fmod")?;
+    let b = check_number(interp, &args, 1, "fmod")?;
+    Ok(vec![Value::Number(a % b)])
+}
+
+fn math_pow(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let a = check_number(interp, &args, 0, "pow")?;
+    let b = check_number(interp, &args, 1, "pow")?;
+    Ok(vec![Value::Number(a.powf(b))])
+}
+
+fn math_max(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let mut max = check_number(interp, &args, 0, "max")?;
+    for i in 1..args.len() {
+        max = max.max(check_number(interp, &args, i, "max")?);
+    }
+    Ok(vec![Value::Number(max)])
+}
+
+fn math_min(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let mut min = check_number(interp, &args, 0, "min")?;
+    for i in 1..args.len() {
+        min = min.min(check_number(interp, &args, i, "min")?);
+    }
+    Ok(vec![Value::Number(min)])
+}
+
+// math.random() in [0, 1), math.random(m) in [1, m] and math.random(m, n) in [m, n]
+fn math_random(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let r = interp.random();
+    let (low, high) = match args.len() {
+        0 => return Ok(vec![Value::Number(r)]),
+        1 => (1, check_integer(interp, &args, 0, "random")?),
+        2 => (check_integer(interp, &args, 0, "random")?, check_integer(interp, &args, 1, "random")?),
+        _ => return Err(interp.error("wrong number of arguments")),
+    };
+    if low > high {
+        return Err(bad_argument(interp, args.len() - 1, "random", "interval is empty"));
+    }
+    Ok(vec![Value::Number((low as f64 + (r * (high - low + 1) as f64).floor()).min(high as f64))])
+}
+
+fn math_randomseed(interp: &mut Interpreter, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let seed = check_integer(interp, &args, 0, "randomseed")?;
+    interp.seed_random(seed as u64);
+    Ok(Vec::new())
+}
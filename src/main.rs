@@ -1,315 +1,281 @@
+#![allow(clippy::upper_case_acronyms)]
+
 use anyhow::{Result, Error};
 
-use futures::future::{BoxFuture, FutureExt};
+use futures::FutureExt;
 
 use std::{
-    collections::HashMap,
-    convert::From,
-    sync::Arc, path::PathBuf, os::unix::prelude::OsStrExt,
+    sync::Arc, path::PathBuf, panic::AssertUnwindSafe,
 };
 
 use tokio::{
-    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
-    net::{TcpListener, TcpStream},
+    io::BufReader,
+    net::{tcp::OwnedReadHalf, TcpListener, TcpStream},
     sync::RwLock,
-    time::{Duration, Instant},
+    task,
+    time::{self, Duration},
 };
 
-#[derive(Debug, Clone)]
-struct DataStoreValue {
-    value: Vec<u8>,
-    expiry: Option<Instant>,
-}
+// How often the background task reclaims expired data nobody has touched
+const ACTIVE_EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
 
-struct State {
-    datastore: HashMap<Vec<u8>,DataStoreValue>,
-    rdb_path: Option<PathBuf>,
-}
+// How often the save rules are checked
+const SAVE_RULES_INTERVAL: Duration = Duration::from_secs(1);
 
-impl State {
-    fn new() -> Self {
-        State {
-            datastore: HashMap::new(),
-            rdb_path: None,
-        }
-    }
+// How often replicas are checked on and pinged when due
+const REPLICATION_CRON_INTERVAL: Duration = Duration::from_secs(1);
 
-    fn new_with_rdbpath(rdb_path: PathBuf) -> Self {
-        State {
-            datastore: HashMap::new(),
-            rdb_path: Some(rdb_path),
-        }
-    }
-}
+// Commands a connection runs back to back from its read buffer before letting other tasks in,
+// so a deep pipeline doesn't hold up interactive clients or its own replies
+const COMMAND_BUDGET: usize = 64;
 
-#[derive(Debug, Clone)]
-enum Command {
-    INVALID(String),
-    PING,
-    ECHO(Vec<u8>),
-    GET(Vec<u8>),
-    SET(Vec<u8>, Vec<u8>),
-    SETPX(Vec<u8>, Vec<u8>, Duration),
-    CONFIGGET(Vec<u8>),
-}
+mod aof;
+mod blocking;
+mod client;
+mod clock;
+mod cluster;
+mod command;
+mod commands;
+mod config;
+mod geohash;
+mod glob;
+mod lua;
+mod notify;
+mod persistent_map;
+mod propagate;
+mod pubsub;
+mod random;
+mod rdb;
+mod replication;
+mod resp;
+mod sha1;
+mod state;
+mod tracking;
+mod transaction;
+mod types;
 
-impl From<DataType> for Command {
-    fn from(data: DataType) -> Self {
-        match data {
-            DataType::Array(args) => {
-                if args.len() == 0 {
-                    return Command::INVALID("Invalid data type for command. must be a non-empty array".to_string());
-                }
-                let name = String::from_utf8_lossy(match args[0] {
-                    DataType::BulkString(ref cmd) => cmd,
-                    _ => { return Command::INVALID("Invalid data type for command. must be a bulk string".to_string()); }
-                });
-                match name.to_lowercase().as_str() {
-                    "ping" => Command::PING,
-                    "echo" => {
-                        if args.len() != 2 {
-                            return Command::INVALID("Invalid data type for command. must be an array of length 2".to_string());
-                        }
-                        let msg = match args[1] {
-                            DataType::BulkString(ref msg) => msg,
-                            _ => { return Command::INVALID("Invalid data type for command. must be a bulk string".to_string()); }
-                        };
-                        Command::ECHO(msg.clone())
-                    }
-                    "get" => {
-                        if args.len() != 2 {
-                            return Command::INVALID("Invalid data type for command. must be an array of length 2".to_string());
-                        }
-                        let key = match args[1] {
-                            DataType::BulkString(ref key) => key,
-                            _ => { return Command::INVALID("Invalid data type for command. must be a bulk string".to_string()); }
-                        };
-                        Command::GET(key.clone())
-                    }
-                    "set" => {
-                        if args.len() != 3 && args.len() != 5 {
-                            return Command::INVALID("Invalid data type for command. must be an array of length 3 or 5".to_string());
-                        }
-                        let key = match args[1] {
-                            DataType::BulkString(ref key) => key,
-                            _ => { return Command::INVALID("Invalid data type for command. must be a bulk string".to_string()); }
-                        };
-                        let value = match args[2] {
-                            DataType::BulkString(ref value) => value,
-                            _ => { return Command::INVALID("Invalid data type for command. must be a bulk string".to_string()); }
-                        };
-                        match args.len() {
-                            3 => { Command::SET(key.clone(), value.clone()) }
-                            5 => {
-                                let arg = match args[3] {
-                                    DataType::BulkString(ref arg) => arg,
-                                    _ => { return Command::INVALID("Invalid data type for command. must be a bulk string".to_string()); }
-                                };
-                                match arg.as_slice() {
-                                    b"px" => (),
-                                    _ => { return Command::INVALID("Invalid argument for command. PX is only accepted argument name".to_string()); }
-                                };
-                                let expiry = match args[4] {
-                                    DataType::BulkString(ref expiry) => {
-                                        let expiry = String::from_utf8_lossy(expiry).parse::<u64>().unwrap();
-                                        Duration::from_millis(expiry)
-                                    },
-                                    _ => { return Command::INVALID("Invalid data type for command. PX argument must be a bulk string".to_string()); }
-                                };
-                                Command::SETPX(key.clone(), value.clone(), expiry)
-                            }
-                            _ => { todo!(); }
-                        }
-                    }
-                    "config" => {
-                        if args.len() != 3 {
-                            return Command::INVALID("Invalid data type for command. must be an array of length 3".to_string());
-                        }
-                        let arg = match args[1] {
-                            DataType::BulkString(ref arg) => arg,
-                            _ => { return Command::INVALID("Invalid data type for command. must be a bulk string".to_string()); }
-                        };
-                        match arg.as_slice() {
-                            b"get" => (),
-                            _ => { return Command::INVALID("Invalid argument for command. GET is only accepted argument name".to_string()); }
-                        };
-                        let key = match args[2] {
-                            DataType::BulkString(ref key) => key,
-                            _ => { return Command::INVALID("Invalid data type for command. GET argument must be a bulk string".to_string()); }
-                        };
-                        Command::CONFIGGET(key.clone())
-                    }
-                    _ => { todo!(); }
-                }
-            }
-            _ => { return Command::INVALID("Invalid data type for command. must be an array".to_string()); }
-        }
-    }
-}
+use client::Client;
+use command::Command;
+use config::Config;
+use resp::DataType;
+use state::State;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum DataType {
-    SimpleString(String),
-    SimpleError(String),
-    Integer(u64),
-    BulkString(Vec<u8>),
-    Array(Vec<DataType>),
+// A request parsed into a command, with the command's name for error replies and, for writes,
+// the arguments as sent so they can be propagated. What a master sends is also kept as it came
+// on the wire, to be passed on to our own replicas. Masters send the canonical encoding, so
+// encoding it again gives the same bytes.
+struct Request {
+    name: String,
+    cmd: Command,
+    args: Option<Vec<Vec<u8>>>,
+    raw: Option<Vec<u8>>,
 }
 
-impl DataType {
-    fn deserialize_data<'a>(reader: &'a mut BufReader<TcpStream>) -> BoxFuture<'a, Result<DataType>> {
-        async move {
-            let mut buffer = String::with_capacity(1024);
-            let data;
-
-            // Read first line of data type and dispatch to handler for further processing
-            reader.read_line(&mut buffer).await?;
-            buffer = buffer.trim().to_string();
-            data = match buffer.chars().next() {
-                Some('+') => DataType::SimpleString(buffer[1..].to_string()),
-                Some('-') => DataType::SimpleError(buffer[1..].to_string()),
-                Some(':') => DataType::Integer(buffer[1..].parse::<u64>()?),
-                Some('$') => {
-                    let len = buffer[1..].parse::<usize>()? + 2;
-                    let mut data = vec![0; len];
-                    reader.read_exact(&mut data).await?;
-                    let foo = &data[0..(len - 2)];
-                    DataType::BulkString(foo.to_vec())
-                }
-                Some('*') => {
-                    let len = buffer[1..].parse::<usize>()?;
-                    let mut data: Vec<DataType> = Vec::with_capacity(len);
-                    for _ in 0..len {
-                        data.push(DataType::deserialize_data(reader).await?);
-                    }
-                    DataType::Array(data)
-                }
-                Some(_) => return Err(Error::msg("Command protocol error: unknown data type prefix")),
-                None => return Err(Error::msg("Client disconnected")),
-            };
-            Ok(data)
-        }.boxed()
-    }
-}
-
-async fn get_next_command(reader: &mut BufReader<TcpStream>) -> Result<Command> {
+async fn get_next_command(reader: &mut BufReader<OwnedReadHalf>, keep_raw: bool) -> Result<Request> {
     let data = DataType::deserialize_data(reader).await?;
-    Ok(Command::from(data))
+    let name = command::command_name(&data);
+    let raw = keep_raw.then(|| data.serialize(false));
+    let cmd = Command::from(&data);
+    let args = cmd.is_write().then(|| command::request_args(data));
+    Ok(Request { name, cmd, args, raw })
 }
 
-async fn handle_command(stream: &mut TcpStream, cmd: Command, state: &Arc<RwLock<State>>) -> Result<()> {
-    match cmd {
-        Command::PING => {
-            stream.write_all(b"+PONG\r\n").await?;
-        }
-        Command::ECHO(msg) => {
-            let len = msg.len();
-            stream.write_all(format!("${}\r\n", len).as_bytes()).await?;
-            stream.write_all(&msg).await?;
-            stream.write_all("\r\n".as_bytes()).await?;
-        }
-        Command::GET(key) => {
-            let state_ro = state.as_ref().read().await;
-            let ds = &state_ro.datastore;
-            match ds.get(&key) {
-                Some(dsv) => {
-                    match dsv.expiry {
-                        Some(expiry) => {
-                            if expiry < Instant::now() {
-                                drop(state_ro);
-                                let mut state_rw = state.as_ref().write().await;
-                                let ds = &mut state_rw.datastore;
-                                ds.remove(&key);
-                                stream.write_all(b"$-1\r\n").await?;
-                            } else {
-                                let len = dsv.value.len();
-                                stream.write_all(format!("${}\r\n", len).as_bytes()).await?;
-                                stream.write_all(&dsv.value).await?;
-                                stream.write_all("\r\n".as_bytes()).await?;
-                            }
-                        }
-                        None => {
-                            let len = dsv.value.len();
-                            stream.write_all(format!("${}\r\n", len).as_bytes()).await?;
-                            stream.write_all(&dsv.value).await?;
-                            stream.write_all("\r\n".as_bytes()).await?;
-                        }
-                    }
-                }
-                None => {
-                    stream.write_all(b"$-1\r\n").await?;
+async fn handle_command(reader: &mut BufReader<OwnedReadHalf>, client: &mut Client, request: Request, state: &Arc<RwLock<State>>) -> Result<()> {
+    let Request { name, cmd, args, raw } = request;
+    let name = name.as_str();
+    if let Some(raw) = raw {
+        client.unrelayed.extend_from_slice(&raw);
+    }
+    let acknowledgement = matches!(cmd, Command::REPLCONFGETACK);
+    let write_offset = client.write_offset;
+    // Writes wait out a failover, after which they may find this server a replica
+    let queued = client.transaction.is_some() && !matches!(cmd, Command::EXEC);
+    if !client.master && !queued && cmd.is_held_by_failover() {
+        replication::failover::failover_finished(state).await;
+    }
+    let (subscribe_mode, rejection) = {
+        let state = state.read().await;
+        let rejection = state
+            .cluster_redirect(&cmd)
+            .or_else(|| state.replication_rejection(&cmd).map(str::to_string))
+            .filter(|_| !client.master);
+        (client.protocol() == 2 && state.pubsub.is_subscriber(client.id), rejection)
+    };
+    // Failing like a bad command also aborts a transaction it was queued in
+    let cmd = match rejection {
+        Some(msg) => Command::INVALID(msg),
+        None => cmd,
+    };
+    if let Some(reply) = cmd.subscribe_mode_reply(name).filter(|_| subscribe_mode) {
+        return client.send(reply).map_err(|_| Error::msg("Client disconnected"));
+    }
+    // RESET also abandons a transaction in progress
+    if let Command::RESET = cmd {
+        client.transaction = None;
+    }
+    let replies = match cmd {
+        Command::MULTI => vec![client.multi()],
+        Command::DISCARD => vec![client.discard()],
+        Command::EXEC => match client.transaction.take() {
+            Some(transaction) => {
+                let mut state = state.write().await;
+                let reply = state.execute_transaction(client, transaction);
+                if let Some(offset) = state.flush_propagation() {
+                    client.write_offset = offset;
                 }
+                state.relay_master_stream(client);
+                state.serve_blocked_clients();
+                vec![reply]
             }
+            None => vec![DataType::SimpleError("ERR EXEC without MULTI".to_string())],
+        },
+        cmd if client.transaction.is_some() => vec![client.queue(cmd, args)],
+        Command::HELLO(protocol, auth) => {
+            let role = state.read().await.role();
+            vec![client.hello(protocol, auth, role)]
         }
-        Command::SET(key, value) => {
-            let mut state = state.as_ref().write().await;
-            let ds = &mut state.datastore;
-            let dsv = DataStoreValue {
-                value: value,
-                expiry: None,
-            };
-            ds.insert(key, dsv);
-            stream.write_all(b"+OK\r\n").await?;
+        // A GETACK is passed on after its ack, which only covers what came before it
+        cmd if cmd.is_connection() => {
+            let mut state = state.write().await;
+            let replies = state.execute_connection(client, cmd);
+            state.relay_master_stream(client);
+            replies
         }
-        Command::SETPX(key, value, expiry) => {
-            let mut state = state.as_ref().write().await;
-            let ds = &mut state.datastore;
-            let dsv = DataStoreValue {
-                value: value,
-                expiry: Some(Instant::now() + expiry),
+        cmd if cmd.blocking_keys().is_some() => {
+            let wrote = args.is_some();
+            let Some(reply) = blocking::execute_blocking(reader.get_mut(), cmd, args, state).await else {
+                return Err(Error::msg("Client disconnected"));
             };
-            ds.insert(key, dsv);
-            stream.write_all(b"+OK\r\n").await?;
+            // Writes of other clients may have gone out since, which only makes WAITAOF wait
+            // for a little more
+            if wrote {
+                client.write_offset = state.read().await.propagation.offset();
+            }
+            vec![reply]
         }
-        Command::CONFIGGET(key) => {
-            let state_ro = state.as_ref().read().await;
-            let rdbpath = state_ro.rdb_path.as_ref().unwrap();
-            match key.as_slice() {
-                b"dir" => {
-                    let dir = rdbpath.parent().unwrap().as_os_str();
-                    stream.write_all(b"*2\r\n").await?;
-                    stream.write_all(b"$3\r\ndir\r\n").await?;
-                    stream.write_all(format!("${}\r\n", dir.len()).as_bytes()).await?;
-                    stream.write_all(dir.as_bytes()).await?;
-                    stream.write_all(b"\r\n").await?;
-                }
-                b"dbfilename" => {
-                    let filename = rdbpath.file_name().unwrap();
-                    stream.write_all(b"*2\r\n").await?;
-                    stream.write_all(b"$10\r\ndbfilename\r\n").await?;
-                    stream.write_all(format!("${}\r\n", filename.len()).as_bytes()).await?;
-                    stream.write_all(filename.as_bytes()).await?;
-                    stream.write_all(b"\r\n").await?;
-                }
-                _ => {
-                    stream.write_all(b"$-1\r\n").await?;
-                }
+        Command::REPLICAOF(master) => vec![replication::replicaof(state, master).await],
+        Command::FAILOVER(target, force, timeout) => vec![replication::failover::failover(state, target, force, timeout).await],
+        Command::FAILOVERABORT => vec![state.write().await.failover_abort()],
+        Command::REPLCONF(listening_port, capabilities) => {
+            client.listening_port = listening_port.or(client.listening_port);
+            client.capabilities.extend(capabilities);
+            vec![DataType::ok()]
+        }
+        Command::PSYNC(replid, offset, failover) => {
+            let ip = reader.get_ref().peer_addr().ok().map(|addr| addr.ip());
+            replication::master::psync(client, ip, &replid, offset, failover, state).await.into_iter().collect()
+        }
+        Command::WAITAOF(numlocal, numreplicas, timeout) => {
+            match aof::wait_aof(reader.get_mut(), client.write_offset, numlocal, numreplicas, timeout, state).await {
+                Some(reply) => vec![reply],
+                None => return Err(Error::msg("Client disconnected")),
+            }
+        }
+        cmd => {
+            let mut state = state.as_ref().write().await;
+            let reply = state.execute_tracked(client.id, cmd);
+            if let Some(args) = args {
+                state.propagate_command(args, &reply);
+            }
+            if let Some(offset) = state.flush_propagation() {
+                client.write_offset = offset;
             }
+            state.relay_master_stream(client);
+            state.serve_blocked_clients();
+            vec![reply]
         }
-        Command::INVALID(msg) => {
-            stream.write_all(format!("-{}\r\n", msg).as_bytes()).await?;
+    };
+    // The master only reads the acknowledgements it asks for. An error means the datasets have
+    // drifted apart, which is worth a log line.
+    if client.master {
+        state.write().await.heard_from_master();
+        if !acknowledgement {
+            if let Some(DataType::SimpleError(e)) = replies.first() {
+                println!("Error applying '{}' from the master: {}", name, e);
+            }
+            return Ok(());
         }
     }
+    // Replies to writes can be held until the AOF has them on disk
+    if client.write_offset > write_offset {
+        aof::wait_fsynced(client.write_offset, state).await;
+    }
+    for reply in replies {
+        client.send(reply).map_err(|_| Error::msg("Client disconnected"))?;
+    }
+    // A client pipelining commands without reading the replies is held to its output limits too
+    let limit = state.read().await.output_buffer_limit(client.id);
+    if client.enforce_limit(&limit) {
+        return Err(Error::msg("Client disconnected: output buffer limit reached"));
+    }
     Ok(())
 }
 
 async fn handle_connection(stream: TcpStream, state: Arc<RwLock<State>>) -> Result<()> {
-    let mut reader = BufReader::new(stream);
+    let (read_half, write_half) = stream.into_split();
+    let mut client = Client::new(write_half);
+    state.write().await.add_client(&client);
+    let result = serve_client(&mut BufReader::new(read_half), &mut client, &state).await;
+    state.write().await.remove_client(client.id);
+    result
+}
+
+// Run a connection's commands until it closes. A replica's link to its master is served here
+// too once the master's dataset has been loaded. A command that panics ends the connection like
+// an error does, so the caller still removes the client.
+pub async fn serve_client(reader: &mut BufReader<OwnedReadHalf>, client: &mut Client, state: &Arc<RwLock<State>>) -> Result<()> {
+    AssertUnwindSafe(run_commands(reader, client, state))
+        .catch_unwind()
+        .await
+        .unwrap_or_else(|_| Err(Error::msg("Client disconnected: panicked running a command")))
+}
+
+async fn run_commands(reader: &mut BufReader<OwnedReadHalf>, client: &mut Client, state: &Arc<RwLock<State>>) -> Result<()> {
+    let mut budget = COMMAND_BUDGET;
     loop {
-        let command = get_next_command(&mut reader).await?;
-        handle_command(reader.get_mut(), command, &state).await?;
+        // The writer stops when the connection is dropped, for its output buffer or by the server
+        let request = tokio::select! {
+            next = get_next_command(reader, client.master) => next?,
+            _ = client.closed() => return Err(Error::msg("Client disconnected: connection dropped by the server")),
+        };
+        // The reply is flushed by the writer task before it sees the queue close
+        if let Command::QUIT = request.cmd {
+            let _ = client.send(DataType::ok());
+            return Ok(());
+        }
+        handle_command(reader, client, request, state).await?;
+        // Only pipelined commands count, as waiting on the socket yields anyway. Yielding also
+        // lets the writer task flush the replies queued so far.
+        if reader.buffer().is_empty() {
+            budget = COMMAND_BUDGET;
+        } else {
+            budget -= 1;
+            if budget == 0 {
+                task::yield_now().await;
+                budget = COMMAND_BUDGET;
+            }
+        }
     }
+}
 
-    #[allow(unreachable_code)]
-    Ok(())
+// Lua scripts run on the worker threads and the interpreter recurses on their stack, about
+// 17kb per Lua call in debug builds
+const WORKER_STACK_SIZE: usize = 16 << 20;
+
+fn main() -> Result<()> {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .thread_stack_size(WORKER_STACK_SIZE)
+        .build()?
+        .block_on(run())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+async fn run() -> Result<()> {
     eprintln!("Logs from your program will appear here!");
 
     let mut rdb_dir: Option<String> = None;
     let mut rdb_filename: Option<String> = None;
+    let mut config = Config::default();
 
     // Iterate over command line arguments
     let mut args = std::env::args().skip(1);
@@ -321,25 +287,110 @@ async fn main() -> Result<()> {
             "--dbfilename" => {
                 rdb_filename = args.next().clone();
             }
+            // The master can be given as one argument or as two
+            "--replicaof" | "--slaveof" => {
+                let mut master = args.next().unwrap_or_default();
+                if !master.contains(' ') {
+                    master = format!("{} {}", master, args.next().unwrap_or_default());
+                }
+                if let Err(msg) = config.set("replicaof", &master) {
+                    println!("Bad argument {}: {}", arg, msg);
+                    return Ok(());
+                }
+            }
             _ => {
-                println!("Unknown argument: {}", arg);
-                return Ok(());
+                // Any other --name value pair sets a configuration parameter
+                let name = arg.strip_prefix("--").unwrap_or(&arg);
+                let value = args.next().unwrap_or_default();
+                if let Err(msg) = config.set(name, &value) {
+                    println!("Bad argument {}: {}", arg, msg);
+                    return Ok(());
+                }
             }
         }
     }
 
-    let state;
-    if rdb_dir.is_some() {
+    let mut state = if let Some(rdb_dir) = rdb_dir {
         // Build rdb pathbuf
-        let mut rdb_file = PathBuf::from(rdb_dir.unwrap());
+        let mut rdb_file = PathBuf::from(rdb_dir);
         rdb_file.push(rdb_filename.unwrap_or("dump.rdb".to_string()));
 
-        state = Arc::new(RwLock::new(State::new_with_rdbpath(rdb_file)));
+        State::new_with_rdbpath(rdb_file)
     } else {
-        state = Arc::new(RwLock::new(State::new()));
+        State::new()
+    };
+    state.config = config;
+    if state.config.cluster.enabled {
+        if state.config.replication.replicaof.is_some() {
+            println!("replicaof directive not allowed in cluster mode");
+            return Ok(());
+        }
+        if let Err(e) = state.load_cluster() {
+            println!("Failed loading the cluster config {}: {}", state.nodes_config_path().display(), e);
+            return Ok(());
+        }
+    }
+    // The AOF is the more complete record once it is in use, the dump is only loaded without it
+    let aof_dir = state.aof_dir().dir;
+    let loaded_aof = match state.config.aof.enabled {
+        true => state.load_aof(),
+        false => Ok(false),
+    };
+    match loaded_aof {
+        Ok(true) => {}
+        Ok(false) => {
+            if let Some(rdb_path) = state.rdb_path.clone() {
+                if let Err(e) = state.load_rdb(&rdb_path) {
+                    println!("Failed loading {}: {}", rdb_path.display(), e);
+                    return Ok(());
+                }
+            }
+        }
+        Err(e) => {
+            println!("Failed loading the AOF in {}: {}", aof_dir.display(), e);
+            return Ok(());
+        }
     }
+    if state.config.aof.enabled {
+        if let Err(e) = state.start_aof() {
+            println!("Failed opening the AOF in {}: {}", aof_dir.display(), e);
+            return Ok(());
+        }
+    }
+    let (port, replicaof) = (state.config.server.port, state.config.replication.replicaof.clone());
+    let state = Arc::new(RwLock::new(state));
+    if let Some((host, master_port)) = replicaof {
+        replication::replicate(&state, host, master_port).await;
+    }
+
+    let expire_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = time::interval(ACTIVE_EXPIRE_INTERVAL);
+        loop {
+            interval.tick().await;
+            expire_state.write().await.active_expire_cycle();
+        }
+    });
+
+    let save_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = time::interval(SAVE_RULES_INTERVAL);
+        loop {
+            interval.tick().await;
+            save_state.write().await.run_save_rules();
+        }
+    });
+
+    let replication_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = time::interval(REPLICATION_CRON_INTERVAL);
+        loop {
+            interval.tick().await;
+            replication_state.write().await.replication_cron();
+        }
+    });
 
-    let listener = TcpListener::bind("127.0.0.1:6379").await?;
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
     loop {
         // Clone the datastore to be captured by the closure
         let state = state.clone();
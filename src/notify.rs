@@ -0,0 +1,99 @@
+use crate::state::State;
+
+// Event classes and delivery targets selected by the notify-keyspace-events flags
+pub const NOTIFY_KEYSPACE: u16 = 1 << 0;
+pub const NOTIFY_KEYEVENT: u16 = 1 << 1;
+pub const NOTIFY_GENERIC: u16 = 1 << 2;
+pub const NOTIFY_STRING: u16 = 1 << 3;
+pub const NOTIFY_LIST: u16 = 1 << 4;
+pub const NOTIFY_SET: u16 = 1 << 5;
+pub const NOTIFY_HASH: u16 = 1 << 6;
+pub const NOTIFY_ZSET: u16 = 1 << 7;
+pub const NOTIFY_EXPIRED: u16 = 1 << 8;
+pub const NOTIFY_EVICTED: u16 = 1 << 9;
+pub const NOTIFY_STREAM: u16 = 1 << 10;
+pub const NOTIFY_KEY_MISS: u16 = 1 << 11;
+pub const NOTIFY_NEW: u16 = 1 << 12;
+// Events from the module style types: bloom filters, sketches, JSON and time series
+pub const NOTIFY_MODULE: u16 = 1 << 13;
+// The classes selected by 'A', which leaves out key misses and new keys
+pub const NOTIFY_ALL: u16 = NOTIFY_GENERIC
+    | NOTIFY_STRING
+    | NOTIFY_LIST
+    | NOTIFY_SET
+    | NOTIFY_HASH
+    | NOTIFY_ZSET
+    | NOTIFY_EXPIRED
+    | NOTIFY_EVICTED
+    | NOTIFY_STREAM
+    | NOTIFY_MODULE;
+
+const FLAG_CHARS: &[(char, u16)] = &[
+    ('g', NOTIFY_GENERIC),
+    ('$', NOTIFY_STRING),
+    ('l', NOTIFY_LIST),
+    ('s', NOTIFY_SET),
+    ('h', NOTIFY_HASH),
+    ('z', NOTIFY_ZSET),
+    ('x', NOTIFY_EXPIRED),
+    ('e', NOTIFY_EVICTED),
+    ('t', NOTIFY_STREAM),
+    ('d', NOTIFY_MODULE),
+    ('K', NOTIFY_KEYSPACE),
+    ('E', NOTIFY_KEYEVENT),
+    ('m', NOTIFY_KEY_MISS),
+    ('n', NOTIFY_NEW),
+];
+
+// Parse a notify-keyspace-events string. Without K or E nothing would be published, so such
+// settings disable notifications entirely.
+pub fn parse_flags(value: &str) -> Option<u16> {
+    let mut flags = 0;
+    for c in value.chars() {
+        flags |= match c {
+            'A' => NOTIFY_ALL,
+            c => FLAG_CHARS.iter().find(|(flag, _)| *flag == c)?.1,
+        };
+    }
+    if flags & (NOTIFY_KEYSPACE | NOTIFY_KEYEVENT) == 0 {
+        flags = 0;
+    }
+    Some(flags)
+}
+
+// Canonical form reported by CONFIG GET, with 'A' standing in for all of its classes
+pub fn flags_to_string(flags: u16) -> String {
+    let mut value = String::new();
+    let mut remaining = flags;
+    if flags & NOTIFY_ALL == NOTIFY_ALL {
+        value.push('A');
+        remaining &= !NOTIFY_ALL;
+    }
+    for (c, flag) in FLAG_CHARS {
+        if remaining & flag != 0 {
+            value.push(*c);
+        }
+    }
+    value
+}
+
+impl State {
+    // Publish a keyspace event on __keyspace@0__:<key> and __keyevent@0__:<event> as configured.
+    // Every event is a modification, so this is also where tracked keys are invalidated.
+    pub fn notify_keyspace_event(&mut self, class: u16, event: &str, key: &[u8]) {
+        self.tracking.record_write();
+        self.invalidate_key(key);
+        let flags = self.config.notify_keyspace_events;
+        if flags & class == 0 {
+            return;
+        }
+        if flags & NOTIFY_KEYSPACE != 0 {
+            let channel = [b"__keyspace@0__:".as_slice(), key].concat();
+            self.pubsub.publish(&channel, event.as_bytes(), &self.config.output_buffer_limits.pubsub);
+        }
+        if flags & NOTIFY_KEYEVENT != 0 {
+            let channel = [b"__keyevent@0__:".as_slice(), event.as_bytes()].concat();
+            self.pubsub.publish(&channel, key, &self.config.output_buffer_limits.pubsub);
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    borrow::Borrow,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash},
+    sync::Arc,
+};
+
+// Bits of the hash consumed at each level of the trie
+const BITS: u32 = 5;
+const MASK: u64 = (1 << BITS) - 1;
+
+// A hash map whose clones share structure, so cloning it is O(1) however large it is. It is a
+// hash array mapped trie: each node holds up to 32 children picked by five bits of the key's
+// hash, and both nodes and entries sit behind Arcs. Changing a map copies only the nodes on the
+// path to the key and the entry itself, and only while another clone still shares them, which
+// lets a snapshot taken for BGSAVE stay consistent while writes carry on.
+#[derive(Clone)]
+pub struct PersistentMap<K, V> {
+    root: Arc<Node<K, V>>,
+    len: usize,
+    hasher: RandomState,
+}
+
+#[derive(Clone)]
+enum Child<K, V> {
+    Entry(u64, Arc<(K, V)>),
+    // Keys whose full hashes are equal
+    Collision(u64, Vec<Arc<(K, V)>>),
+    Node(Arc<Node<K, V>>),
+}
+
+// Only the children present are stored, in the order of their bits in the bitmap
+#[derive(Clone)]
+struct Node<K, V> {
+    bitmap: u32,
+    children: Vec<Child<K, V>>,
+}
+
+impl<K, V> Default for Node<K, V> {
+    fn default() -> Self {
+        Node { bitmap: 0, children: Vec::new() }
+    }
+}
+
+impl<K, V> Node<K, V> {
+    // The bit for the hash at this depth, and where its child would be stored
+    fn slot(&self, hash: u64, shift: u32) -> (u32, usize) {
+        let bit = 1 << ((hash >> shift) & MASK);
+        (bit, (self.bitmap & (bit - 1)).count_ones() as usize)
+    }
+
+    fn child(&self, hash: u64, shift: u32) -> Option<&Child<K, V>> {
+        let (bit, index) = self.slot(hash, shift);
+        (self.bitmap & bit != 0).then(|| &self.children[index])
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Node<K, V> {
+    // A node holding two entries whose hashes differ, splitting further while they share bits
+    fn pair(shift: u32, first: Child<K, V>, first_hash: u64, second: Child<K, V>, second_hash: u64) -> Node<K, V> {
+        let (first_bit, second_bit) = (1 << ((first_hash >> shift) & MASK), 1 << ((second_hash >> shift) & MASK));
+        if first_bit == second_bit {
+            let node = Node::pair(shift + BITS, first, first_hash, second, second_hash);
+            return Node { bitmap: first_bit, children: vec![Child::Node(Arc::new(node))] };
+        }
+        let children = if first_bit < second_bit { vec![first, second] } else { vec![second, first] };
+        Node { bitmap: first_bit | second_bit, children }
+    }
+
+    fn insert(&mut self, hash: u64, shift: u32, key: K, value: V) -> Option<V> {
+        let (bit, index) = self.slot(hash, shift);
+        if self.bitmap & bit == 0 {
+            self.bitmap |= bit;
+            self.children.insert(index, Child::Entry(hash, Arc::new((key, value))));
+            return None;
+        }
+        let child = &mut self.children[index];
+        match child {
+            Child::Entry(existing, entry) if *existing == hash && entry.0 == key => {
+                Some(std::mem::replace(&mut Arc::make_mut(entry).1, value))
+            }
+            Child::Entry(existing, entry) if *existing == hash => {
+                *child = Child::Collision(hash, vec![entry.clone(), Arc::new((key, value))]);
+                None
+            }
+            Child::Entry(existing, _) => {
+                let existing = *existing;
+                let old = std::mem::replace(child, Child::Node(Arc::default()));
+                let node = Node::pair(shift + BITS, old, existing, Child::Entry(hash, Arc::new((key, value))), hash);
+                *child = Child::Node(Arc::new(node));
+                None
+            }
+            Child::Collision(existing, entries) if *existing == hash => {
+                match entries.iter_mut().find(|entry| entry.0 == key) {
+                    Some(entry) => Some(std::mem::replace(&mut Arc::make_mut(entry).1, value)),
+                    None => {
+                        entries.push(Arc::new((key, value)));
+                        None
+                    }
+                }
+            }
+            Child::Collision(existing, _) => {
+                let existing = *existing;
+                let old = std::mem::replace(child, Child::Node(Arc::default()));
+                let node = Node::pair(shift + BITS, old, existing, Child::Entry(hash, Arc::new((key, value))), hash);
+                *child = Child::Node(Arc::new(node));
+                None
+            }
+            Child::Node(node) => Arc::make_mut(node).insert(hash, shift + BITS, key, value),
+        }
+    }
+
+    fn get_mut<Q: Hash + Eq + ?Sized>(&mut self, hash: u64, shift: u32, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
+        let (bit, index) = self.slot(hash, shift);
+        if self.bitmap & bit == 0 {
+            return None;
+        }
+        match &mut self.children[index] {
+            Child::Entry(existing, entry) if *existing == hash && entry.0.borrow() == key => Some(&mut Arc::make_mut(entry).1),
+            Child::Collision(existing, entries) if *existing == hash => {
+                entries.iter_mut().find(|entry| entry.0.borrow() == key).map(|entry| &mut Arc::make_mut(entry).1)
+            }
+            Child::Node(node) => Arc::make_mut(node).get_mut(hash, shift + BITS, key),
+            _ => None,
+        }
+    }
+
+    fn remove<Q: Hash + Eq + ?Sized>(&mut self, hash: u64, shift: u32, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        let (bit, index) = self.slot(hash, shift);
+        if self.bitmap & bit == 0 {
+            return None;
+        }
+        let removed = match &mut self.children[index] {
+            Child::Entry(existing, entry) if *existing == hash && entry.0.borrow() == key => {
+                let Child::Entry(_, entry) = self.children.remove(index) else { unreachable!() };
+                self.bitmap &= !bit;
+                return Some(Arc::try_unwrap(entry).map_or_else(|entry| entry.1.clone(), |(_, value)| value));
+            }
+            Child::Collision(existing, entries) if *existing == hash => {
+                let position = entries.iter().position(|entry| entry.0.borrow() == key)?;
+                let entry = entries.remove(position);
+                Arc::try_unwrap(entry).map_or_else(|entry| entry.1.clone(), |(_, value)| value)
+            }
+            Child::Node(node) => Arc::make_mut(node).remove(hash, shift + BITS, key)?,
+            _ => return None,
+        };
+        // Pull a lone entry left behind back up, so the trie stays as shallow as its keys allow
+        let child = &mut self.children[index];
+        match child {
+            Child::Collision(existing, entries) if entries.len() == 1 => *child = Child::Entry(*existing, entries.pop().unwrap()),
+            Child::Node(node) if node.children.len() == 1 && !matches!(node.children[0], Child::Node(_)) => {
+                *child = node.children[0].clone();
+            }
+            _ => {}
+        }
+        Some(removed)
+    }
+}
+
+impl<K, V> PersistentMap<K, V> {
+    pub fn new() -> Self {
+        PersistentMap { root: Arc::default(), len: 0, hasher: RandomState::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { stack: vec![self.root.children.iter()], collision: [].iter() }
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, value)| value)
+    }
+}
+
+impl<K, V> Default for PersistentMap<K, V> {
+    fn default() -> Self {
+        PersistentMap::new()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> PersistentMap<K, V> {
+    pub fn get<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        let hash = self.hasher.hash_one(key);
+        let mut node = &self.root;
+        let mut shift = 0;
+        loop {
+            match node.child(hash, shift)? {
+                Child::Entry(existing, entry) => return (*existing == hash && entry.0.borrow() == key).then_some(&entry.1),
+                Child::Collision(existing, entries) => {
+                    return entries.iter().find(|entry| *existing == hash && entry.0.borrow() == key).map(|entry| &entry.1)
+                }
+                Child::Node(child) => node = child,
+            }
+            shift += BITS;
+        }
+    }
+
+    pub fn contains_key<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.get(key).is_some()
+    }
+
+    // Checks the key is present first, so a miss doesn't copy nodes shared with a snapshot
+    pub fn get_mut<Q: Hash + Eq + ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
+        if !self.contains_key(key) {
+            return None;
+        }
+        let hash = self.hasher.hash_one(key);
+        Arc::make_mut(&mut self.root).get_mut(hash, 0, key)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = self.hasher.hash_one(&key);
+        let old = Arc::make_mut(&mut self.root).insert(hash, 0, key, value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    pub fn remove<Q: Hash + Eq + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        if !self.contains_key(key) {
+            return None;
+        }
+        let hash = self.hasher.hash_one(key);
+        let removed = Arc::make_mut(&mut self.root).remove(hash, 0, key);
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+}
+
+// Depth first walk over the trie, in hash order
+pub struct Iter<'a, K, V> {
+    stack: Vec<std::slice::Iter<'a, Child<K, V>>>,
+    collision: std::slice::Iter<'a, Arc<(K, V)>>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.collision.next() {
+                return Some((&entry.0, &entry.1));
+            }
+            let children = self.stack.last_mut()?;
+            match children.next() {
+                None => {
+                    self.stack.pop();
+                }
+                Some(Child::Entry(_, entry)) => return Some((&entry.0, &entry.1)),
+                Some(Child::Collision(_, entries)) => self.collision = entries.iter(),
+                Some(Child::Node(node)) => self.stack.push(node.children.iter()),
+            }
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use bytes::Bytes;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::{
+    replication::backlog::Backlog,
+    resp::DataType,
+    state::{State, Value},
+};
+
+// Commands sent out together, with the offset in the write stream just past them
+#[derive(Clone)]
+pub struct Chunk {
+    pub payload: Bytes,
+    pub end: u64,
+}
+
+// Writes leave the server through here, encoded as RESP commands the way the AOF and replicas
+// expect them. A master sends both the same stream. A replica writes what it applies to its
+// AOF, but passes on its master's stream to its own replicas as it came, so their offsets
+// agree with the master's.
+#[derive(Default)]
+pub struct Propagation {
+    // Consumers of the write stream, dropped once they go away
+    sinks: Vec<UnboundedSender<Chunk>>,
+    // Commands applied by the command being run, sent together once it completes
+    pending: Vec<Vec<Vec<u8>>>,
+    // Bytes sent so far
+    offset: u64,
+    // Replicas' copy of the stream, and its offset in the history of the dataset
+    replicas: Vec<UnboundedSender<Chunk>>,
+    repl_offset: u64,
+    // Created along with the first replica, after which the stream is kept going with or
+    // without any, so that one can come back to it
+    backlog: Option<Backlog>,
+}
+
+impl Propagation {
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn repl_offset(&self) -> u64 {
+        self.repl_offset
+    }
+
+    pub fn backlog(&self) -> Option<&Backlog> {
+        self.backlog.as_ref()
+    }
+
+    pub fn resize_backlog(&mut self, size: usize) {
+        if let Some(backlog) = self.backlog.as_mut() {
+            backlog.resize(size);
+        }
+    }
+
+    // Start the replication stream over from where a master's dataset was taken
+    pub fn reset_repl_stream(&mut self, offset: u64, backlog_size: usize) {
+        self.repl_offset = offset;
+        self.backlog = Some(Backlog::new(backlog_size));
+    }
+
+    // What a replica that has the stream up to an offset missed, None when that isn't a point
+    // the backlog still has
+    pub fn backlog_since(&self, offset: u64) -> Option<Vec<u8>> {
+        let missed = self.repl_offset.checked_sub(offset)?;
+        self.backlog.as_ref()?.tail(usize::try_from(missed).ok()?)
+    }
+
+    pub fn subscribe(&mut self) -> UnboundedReceiver<Chunk> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.sinks.push(tx);
+        rx
+    }
+
+    pub fn subscribe_replica(&mut self, backlog_size: usize) -> UnboundedReceiver<Chunk> {
+        self.backlog.get_or_insert_with(|| Backlog::new(backlog_size));
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.replicas.push(tx);
+        rx
+    }
+
+    // Pass on bytes of a master's stream to the replicas. The offset moves on without any,
+    // so a replica that syncs later is given the master's.
+    pub fn relay(&mut self, payload: Bytes) {
+        self.repl_offset += payload.len() as u64;
+        if let Some(backlog) = self.backlog.as_mut() {
+            backlog.feed(&payload);
+        }
+        let chunk = Chunk { payload, end: self.repl_offset };
+        self.replicas.retain(|sink| sink.send(chunk.clone()).is_ok());
+    }
+
+    // Send the pending commands, also to the replicas unless they are fed a master's stream,
+    // returning the offset just past them if the AOF got any. Several commands are wrapped in
+    // MULTI/EXEC so consumers apply them atomically too.
+    fn flush(&mut self, to_replicas: bool) -> Option<u64> {
+        let commands = std::mem::take(&mut self.pending);
+        let to_replicas = to_replicas && self.backlog.is_some();
+        if commands.is_empty() || (!to_replicas && self.sinks.is_empty()) {
+            return None;
+        }
+        let wrap = commands.len() > 1;
+        let mut buf = Vec::new();
+        if wrap {
+            DataType::bulk_array([b"MULTI".to_vec()]).serialize_into(&mut buf, false);
+        }
+        for args in commands {
+            DataType::bulk_array(args).serialize_into(&mut buf, false);
+        }
+        if wrap {
+            DataType::bulk_array([b"EXEC".to_vec()]).serialize_into(&mut buf, false);
+        }
+        let payload = Bytes::from(buf);
+        if to_replicas {
+            self.relay(payload.clone());
+        }
+        if self.sinks.is_empty() {
+            return None;
+        }
+        self.offset += payload.len() as u64;
+        let chunk = Chunk { payload, end: self.offset };
+        self.sinks.retain(|sink| sink.send(chunk.clone()).is_ok());
+        Some(self.offset)
+    }
+
+    fn has_consumers(&self) -> bool {
+        !self.sinks.is_empty() || self.backlog.is_some()
+    }
+}
+
+fn arg(s: &str) -> Vec<u8> {
+    s.as_bytes().to_vec()
+}
+
+impl State {
+    // Queue commands to go out once the command being run completes
+    pub fn propagate(&mut self, commands: Vec<Vec<Vec<u8>>>) {
+        if self.propagation.has_consumers() {
+            self.propagation.pending.extend(commands);
+        }
+    }
+
+    // Queue the effects of a write command a client ran
+    pub fn propagate_command(&mut self, args: Vec<Vec<u8>>, reply: &DataType) {
+        if self.propagation.has_consumers() {
+            let effects = self.write_effects(args, reply);
+            self.propagation.pending.extend(effects);
+        }
+    }
+
+    // Send what the command that just completed queued, as one atomic group, returning the
+    // offset in the write stream just past it if there was anything
+    pub fn flush_propagation(&mut self) -> Option<u64> {
+        let to_replicas = self.replication.master.is_none();
+        self.propagation.flush(to_replicas)
+    }
+
+    // The commands that reproduce a write's effect when replayed. Mostly that is the command
+    // as it was sent, but commands whose outcome depends on when they ran or on chance are
+    // rewritten into deterministic ones from the reply and the data they left. Writes that
+    // failed, or found nothing to act on and replied null, changed nothing.
+    pub fn write_effects(&self, mut args: Vec<Vec<u8>>, reply: &DataType) -> Vec<Vec<Vec<u8>>> {
+        if matches!(reply, DataType::SimpleError(_) | DataType::NullBulkString | DataType::NullArray) {
+            return Vec::new();
+        }
+        let name = args[0].to_ascii_lowercase();
+        match name.as_slice() {
+            // Messages aren't part of the dataset
+            b"publish" | b"spublish" => Vec::new(),
+            b"del" | b"unlink" if matches!(reply, DataType::Integer(0)) => Vec::new(),
+            // Relative expirations become the deadline they were turned into
+            b"set" if args.len() == 5 => match self.datastore.get(&args[1]).and_then(|dsv| dsv.expiry) {
+                Some(at) => vec![vec![arg("SET"), args[1].clone(), args[2].clone(), arg("PXAT"), at.to_string().into_bytes()]],
+                None => vec![args],
+            },
+            b"hexpire" | b"hpexpire" | b"hexpireat" | b"hpexpireat" => self.hexpire_effects(&args, reply),
+            b"restore" if args[2] != b"0" && !args[4..].iter().any(|arg| arg.eq_ignore_ascii_case(b"absttl")) => {
+                if let Some(at) = self.datastore.get(&args[1]).and_then(|dsv| dsv.expiry) {
+                    args[2] = at.to_string().into_bytes();
+                    args.push(arg("ABSTTL"));
+                }
+                vec![args]
+            }
+            b"spop" => {
+                let members = match reply {
+                    DataType::BulkString(member) => vec![member.clone()],
+                    DataType::Array(items) => items.iter().filter_map(|item| match item {
+                        DataType::BulkString(member) => Some(member.clone()),
+                        _ => None,
+                    }).collect(),
+                    _ => Vec::new(),
+                };
+                match members.is_empty() {
+                    true => Vec::new(),
+                    false => vec![[arg("SREM"), args[1].clone()].into_iter().chain(members).collect()],
+                }
+            }
+            b"xadd" | b"xtrim" => {
+                let id_at = self.exact_stream_trim(&mut args);
+                // Generated IDs are replaced by the one the entry got
+                if let (b"xadd", DataType::BulkString(id)) = (name.as_slice(), reply) {
+                    args[id_at] = id.clone();
+                }
+                vec![args]
+            }
+            b"ts.add" if args[2] == b"*" => {
+                if let DataType::Integer(timestamp) = reply {
+                    args[2] = timestamp.to_string().into_bytes();
+                }
+                vec![args]
+            }
+            // Blocking commands that were served become their non-blocking forms, which give
+            // the same result against the same data
+            b"blpop" | b"brpop" => match reply {
+                DataType::Array(items) => match items.first() {
+                    Some(DataType::BulkString(key)) => vec![vec![arg(if name == b"blpop" { "LPOP" } else { "RPOP" }), key.clone()]],
+                    _ => Vec::new(),
+                },
+                _ => Vec::new(),
+            },
+            b"blmove" => {
+                args.pop();
+                args[0] = arg("LMOVE");
+                vec![args]
+            }
+            b"blmpop" | b"bzmpop" => {
+                args.remove(1);
+                args[0] = arg(if name == b"blmpop" { "LMPOP" } else { "ZMPOP" });
+                vec![args]
+            }
+            b"xreadgroup" => {
+                let streams_at = args.iter().position(|arg| arg.eq_ignore_ascii_case(b"streams")).unwrap_or(args.len());
+                if let Some(block_at) = args[..streams_at].iter().position(|arg| arg.eq_ignore_ascii_case(b"block")) {
+                    args.drain(block_at..block_at + 2);
+                }
+                vec![args]
+            }
+            _ => vec![args],
+        }
+    }
+
+    // Fields given a deadline are propagated with it, and fields deleted because it had
+    // already passed as deleted, leaving out the conditions that have been checked already
+    fn hexpire_effects(&self, args: &[Vec<u8>], reply: &DataType) -> Vec<Vec<Vec<u8>>> {
+        let DataType::Array(results) = reply else {
+            return Vec::new();
+        };
+        let key = &args[1];
+        let fields = &args[args.len() - results.len()..];
+        let with_result = |result| -> Vec<Vec<u8>> {
+            fields.iter().zip(results).filter(|(_, r)| **r == DataType::Integer(result)).map(|(field, _)| field.clone()).collect()
+        };
+        let (set, deleted) = (with_result(1), with_result(2));
+        let expiry = match self.datastore.get(key).map(|dsv| &dsv.value) {
+            Some(Value::Hash(hash)) => set.first().and_then(|field| hash.expiry(field)),
+            _ => None,
+        };
+        let mut effects = Vec::new();
+        if let Some(at) = expiry {
+            let header = [arg("HPEXPIREAT"), key.clone(), at.to_string().into_bytes(), arg("FIELDS"), set.len().to_string().into_bytes()];
+            effects.push(header.into_iter().chain(set).collect());
+        }
+        if !deleted.is_empty() {
+            effects.push([arg("HDEL"), key.clone()].into_iter().chain(deleted).collect());
+        }
+        effects
+    }
+
+    // Approximate trimming depends on how the stream is laid out in memory, which a replay
+    // needn't share, so it becomes an exact trim to the length the stream was left with.
+    // Returns the index of the first argument after the options, XADD's ID.
+    fn exact_stream_trim(&self, args: &mut Vec<Vec<u8>>) -> usize {
+        let len = match self.datastore.get(&args[1]).map(|dsv| &dsv.value) {
+            Some(Value::Stream(stream)) => stream.len(),
+            _ => 0,
+        };
+        let mut i = 2;
+        while i < args.len() {
+            if args[i].eq_ignore_ascii_case(b"nomkstream") {
+                i += 1;
+            } else if args[i].eq_ignore_ascii_case(b"maxlen") || args[i].eq_ignore_ascii_case(b"minid") {
+                if args.get(i + 1).is_some_and(|arg| arg == b"~") {
+                    args.splice(i..i + 3, [arg("MAXLEN"), arg("="), len.to_string().into_bytes()]);
+                    if args.get(i + 3).is_some_and(|arg| arg.eq_ignore_ascii_case(b"limit")) {
+                        args.drain(i + 3..i + 5);
+                    }
+                    i += 3;
+                } else if args.get(i + 1).is_some_and(|arg| arg == b"=") {
+                    i += 3;
+                } else {
+                    i += 2;
+                }
+            } else {
+                break;
+            }
+        }
+        i
+    }
+}
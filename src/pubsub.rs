@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+
+use crate::{
+    client::{Client, ClientHandle},
+    command::{wrong_number_of_args, Command},
+    config::OutputBufferLimit,
+    glob::glob_match,
+    resp::DataType,
+    state::State,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SubscriptionKind {
+    Channel,
+    Pattern,
+    ShardChannel,
+}
+
+impl SubscriptionKind {
+    fn subscribe_reply(&self) -> &'static [u8] {
+        match self {
+            SubscriptionKind::Channel => b"subscribe",
+            SubscriptionKind::Pattern => b"psubscribe",
+            SubscriptionKind::ShardChannel => b"ssubscribe",
+        }
+    }
+
+    fn unsubscribe_reply(&self) -> &'static [u8] {
+        match self {
+            SubscriptionKind::Channel => b"unsubscribe",
+            SubscriptionKind::Pattern => b"punsubscribe",
+            SubscriptionKind::ShardChannel => b"sunsubscribe",
+        }
+    }
+}
+
+struct Subscriber {
+    client: ClientHandle,
+    channels: Vec<Vec<u8>>,
+    patterns: Vec<Vec<u8>>,
+    shard_channels: Vec<Vec<u8>>,
+}
+
+impl Subscriber {
+    fn subscriptions(&mut self, kind: SubscriptionKind) -> &mut Vec<Vec<u8>> {
+        match kind {
+            SubscriptionKind::Channel => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::ShardChannel => &mut self.shard_channels,
+        }
+    }
+
+    // Shard channels are counted apart from channels and patterns in subscription replies
+    fn count(&self, kind: SubscriptionKind) -> usize {
+        match kind {
+            SubscriptionKind::Channel | SubscriptionKind::Pattern => self.channels.len() + self.patterns.len(),
+            SubscriptionKind::ShardChannel => self.shard_channels.len(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.channels.is_empty() && self.patterns.is_empty() && self.shard_channels.is_empty()
+    }
+}
+
+fn subscription_frame(kind: &[u8], name: Option<&[u8]>, count: usize) -> DataType {
+    DataType::Push(vec![
+        DataType::BulkString(kind.to_vec()),
+        name.map_or(DataType::NullBulkString, |name| DataType::BulkString(name.to_vec())),
+        DataType::Integer(count as i64),
+    ])
+}
+
+// Registry of pub/sub subscriptions. Channels and patterns map to the ids of their subscribers
+// in subscription order, and each subscriber keeps the queue its messages are delivered to.
+#[derive(Default)]
+pub struct PubSubState {
+    channels: HashMap<Vec<u8>, Vec<u64>>,
+    patterns: HashMap<Vec<u8>, Vec<u64>>,
+    shard_channels: HashMap<Vec<u8>, Vec<u64>>,
+    subscribers: HashMap<u64, Subscriber>,
+}
+
+impl PubSubState {
+    fn registry(&mut self, kind: SubscriptionKind) -> &mut HashMap<Vec<u8>, Vec<u64>> {
+        match kind {
+            SubscriptionKind::Channel => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::ShardChannel => &mut self.shard_channels,
+        }
+    }
+
+    fn subscribe(&mut self, client: &Client, kind: SubscriptionKind, names: Vec<Vec<u8>>) -> Vec<DataType> {
+        let mut frames = Vec::with_capacity(names.len());
+        for name in names {
+            let subscriber = self.subscribers.entry(client.id).or_insert_with(|| Subscriber {
+                client: client.handle(),
+                channels: Vec::new(),
+                patterns: Vec::new(),
+                shard_channels: Vec::new(),
+            });
+            if !subscriber.subscriptions(kind).contains(&name) {
+                subscriber.subscriptions(kind).push(name.clone());
+                self.registry(kind).entry(name.clone()).or_default().push(client.id);
+            }
+            let count = self.subscribers[&client.id].count(kind);
+            frames.push(subscription_frame(kind.subscribe_reply(), Some(&name), count));
+        }
+        frames
+    }
+
+    // Drop the named subscriptions, or all of them of this kind when no names are given
+    fn unsubscribe(&mut self, id: u64, kind: SubscriptionKind, names: Vec<Vec<u8>>) -> Vec<DataType> {
+        let names = match (names.is_empty(), self.subscribers.get_mut(&id)) {
+            (true, Some(subscriber)) => subscriber.subscriptions(kind).clone(),
+            (true, None) => Vec::new(),
+            (false, _) => names,
+        };
+        let mut frames = Vec::with_capacity(names.len());
+        for name in names.iter() {
+            if let Some(subscriber) = self.subscribers.get_mut(&id) {
+                subscriber.subscriptions(kind).retain(|subscribed| subscribed != name);
+            }
+            let registry = self.registry(kind);
+            if let Some(ids) = registry.get_mut(name) {
+                ids.retain(|subscriber| *subscriber != id);
+                if ids.is_empty() {
+                    registry.remove(name);
+                }
+            }
+            frames.push(subscription_frame(kind.unsubscribe_reply(), Some(name), self.subscription_count(id, kind)));
+        }
+        if frames.is_empty() {
+            frames.push(subscription_frame(kind.unsubscribe_reply(), None, self.subscription_count(id, kind)));
+        }
+        if self.subscribers.get(&id).is_some_and(Subscriber::is_empty) {
+            self.subscribers.remove(&id);
+        }
+        frames
+    }
+
+    fn subscription_count(&self, id: u64, kind: SubscriptionKind) -> usize {
+        self.subscribers.get(&id).map_or(0, |subscriber| subscriber.count(kind))
+    }
+
+    // Active channels, patterns and shard channels
+    pub fn registry_sizes(&self) -> (usize, usize, usize) {
+        (self.channels.len(), self.patterns.len(), self.shard_channels.len())
+    }
+
+    // Connections with at least one subscription of any kind
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    // Whether the connection has any subscriptions, which puts a RESP2 connection in subscribe mode
+    pub fn is_subscriber(&self, id: u64) -> bool {
+        self.subscribers.contains_key(&id)
+    }
+
+    // Forget a connection that has closed
+    pub fn remove_client(&mut self, id: u64) {
+        self.unsubscribe(id, SubscriptionKind::Channel, Vec::new());
+        self.unsubscribe(id, SubscriptionKind::Pattern, Vec::new());
+        self.unsubscribe(id, SubscriptionKind::ShardChannel, Vec::new());
+    }
+
+    // Deliver a message to the channel's subscribers and every subscriber with a matching
+    // pattern, returning how many deliveries were made. Subscribers whose queues grow past the
+    // limit are disconnected.
+    pub fn publish(&self, channel: &[u8], message: &[u8], limit: &OutputBufferLimit) -> usize {
+        let mut receivers = 0;
+        for id in self.channels.get(channel).into_iter().flatten() {
+            let frame = DataType::Push(vec![
+                DataType::BulkString(b"message".to_vec()),
+                DataType::BulkString(channel.to_vec()),
+                DataType::BulkString(message.to_vec()),
+            ]);
+            let _ = self.subscribers[id].client.send_limited(frame, limit);
+            receivers += 1;
+        }
+        for (pattern, ids) in self.patterns.iter().filter(|(pattern, _)| glob_match(pattern, channel)) {
+            for id in ids {
+                let frame = DataType::Push(vec![
+                    DataType::BulkString(b"pmessage".to_vec()),
+                    DataType::BulkString(pattern.clone()),
+                    DataType::BulkString(channel.to_vec()),
+                    DataType::BulkString(message.to_vec()),
+                ]);
+                let _ = self.subscribers[id].client.send_limited(frame, limit);
+                receivers += 1;
+            }
+        }
+        receivers
+    }
+
+    // Shard channels have no pattern subscriptions, only direct subscribers are reached
+    pub fn spublish(&self, channel: &[u8], message: &[u8], limit: &OutputBufferLimit) -> usize {
+        let ids = self.shard_channels.get(channel).map_or(&[][..], Vec::as_slice);
+        for id in ids {
+            let frame = DataType::Push(vec![
+                DataType::BulkString(b"smessage".to_vec()),
+                DataType::BulkString(channel.to_vec()),
+                DataType::BulkString(message.to_vec()),
+            ]);
+            let _ = self.subscribers[id].client.send_limited(frame, limit);
+        }
+        ids.len()
+    }
+}
+
+// Active channels matching an optional pattern, sorted for stable output
+fn active_channels(registry: &HashMap<Vec<u8>, Vec<u64>>, pattern: Option<&[u8]>) -> DataType {
+    let mut channels: Vec<&Vec<u8>> = registry.keys()
+        .filter(|channel| pattern.is_none_or(|pattern| glob_match(pattern, channel)))
+        .collect();
+    channels.sort();
+    DataType::bulk_array(channels.into_iter().cloned())
+}
+
+fn subscriber_counts(registry: &HashMap<Vec<u8>, Vec<u64>>, channels: &[Vec<u8>]) -> DataType {
+    let counts = channels.iter().flat_map(|channel| {
+        let count = registry.get(channel).map_or(0, Vec::len);
+        [DataType::BulkString(channel.clone()), DataType::Integer(count as i64)]
+    });
+    DataType::Array(counts.collect())
+}
+
+impl Command {
+    // The SUBSCRIBE forms need at least one name, the UNSUBSCRIBE forms default to all
+    pub fn parse_subscription(name: &str, args: &[Vec<u8>]) -> Command {
+        let names = args[1..].to_vec();
+        match name {
+            "subscribe" | "psubscribe" | "ssubscribe" if names.is_empty() => wrong_number_of_args(name),
+            "subscribe" => Command::SUBSCRIBE(names),
+            "psubscribe" => Command::PSUBSCRIBE(names),
+            "ssubscribe" => Command::SSUBSCRIBE(names),
+            "unsubscribe" => Command::UNSUBSCRIBE(names),
+            "sunsubscribe" => Command::SUNSUBSCRIBE(names),
+            _ => Command::PUNSUBSCRIBE(names),
+        }
+    }
+
+    // PUBLISH and SPUBLISH
+    pub fn parse_publish(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() != 3 {
+            return wrong_number_of_args(name);
+        }
+        match name {
+            "spublish" => Command::SPUBLISH(args[1].clone(), args[2].clone()),
+            _ => Command::PUBLISH(args[1].clone(), args[2].clone()),
+        }
+    }
+
+    // PUBSUB CHANNELS | NUMSUB | NUMPAT | SHARDCHANNELS | SHARDNUMSUB
+    pub fn parse_pubsub(args: &[Vec<u8>]) -> Command {
+        if args.len() < 2 {
+            return wrong_number_of_args("pubsub");
+        }
+        let subcommand = String::from_utf8_lossy(&args[1]).to_lowercase();
+        match (subcommand.as_str(), &args[2..]) {
+            ("channels", [] | [_]) => Command::PUBSUBCHANNELS(args.get(2).cloned(), false),
+            ("shardchannels", [] | [_]) => Command::PUBSUBCHANNELS(args.get(2).cloned(), true),
+            ("numsub", channels) => Command::PUBSUBNUMSUB(channels.to_vec(), false),
+            ("shardnumsub", channels) => Command::PUBSUBNUMSUB(channels.to_vec(), true),
+            ("numpat", []) => Command::PUBSUBNUMPAT,
+            ("channels" | "shardchannels" | "numpat", _) => wrong_number_of_args(&format!("pubsub|{}", subcommand)),
+            _ => Command::INVALID(format!("ERR unknown subcommand '{}'. Try PUBSUB HELP.", subcommand)),
+        }
+    }
+
+    // Commands that change the calling connection's subscriptions
+    pub fn is_subscription(&self) -> bool {
+        matches!(
+            self,
+            Command::SUBSCRIBE(_)
+                | Command::UNSUBSCRIBE(_)
+                | Command::PSUBSCRIBE(_)
+                | Command::PUNSUBSCRIBE(_)
+                | Command::SSUBSCRIBE(_)
+                | Command::SUNSUBSCRIBE(_)
+        )
+    }
+
+    // Reply to a command from a RESP2 connection in subscribe mode, where only the subscription
+    // commands, PING, QUIT and RESET are allowed. None when the command should run as usual.
+    pub fn subscribe_mode_reply(&self, name: &str) -> Option<DataType> {
+        match self {
+            cmd if cmd.is_subscription() => None,
+            Command::RESET => None,
+            Command::PING => Some(DataType::bulk_array([b"pong".to_vec(), Vec::new()])),
+            Command::INVALID(msg) => Some(DataType::SimpleError(msg.clone())),
+            _ => Some(DataType::SimpleError(format!(
+                "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+                name
+            ))),
+        }
+    }
+}
+
+impl State {
+    // Run a subscription command for a connection, producing one reply frame per channel or
+    // pattern it names
+    pub fn execute_subscription(&mut self, client: &Client, cmd: Command) -> Vec<DataType> {
+        match cmd {
+            Command::SUBSCRIBE(channels) => self.pubsub.subscribe(client, SubscriptionKind::Channel, channels),
+            Command::UNSUBSCRIBE(channels) => self.pubsub.unsubscribe(client.id, SubscriptionKind::Channel, channels),
+            Command::PSUBSCRIBE(patterns) => self.pubsub.subscribe(client, SubscriptionKind::Pattern, patterns),
+            Command::PUNSUBSCRIBE(patterns) => self.pubsub.unsubscribe(client.id, SubscriptionKind::Pattern, patterns),
+            Command::SSUBSCRIBE(channels) => self.pubsub.subscribe(client, SubscriptionKind::ShardChannel, channels),
+            Command::SUNSUBSCRIBE(channels) => self.pubsub.unsubscribe(client.id, SubscriptionKind::ShardChannel, channels),
+            cmd => vec![self.execute(cmd)],
+        }
+    }
+
+    pub fn publish(&mut self, channel: &[u8], message: &[u8]) -> DataType {
+        DataType::Integer(self.pubsub.publish(channel, message, &self.config.output_buffer_limits.pubsub) as i64)
+    }
+
+    pub fn spublish(&mut self, channel: &[u8], message: &[u8]) -> DataType {
+        DataType::Integer(self.pubsub.spublish(channel, message, &self.config.output_buffer_limits.pubsub) as i64)
+    }
+
+    pub fn pubsub_channels(&mut self, pattern: Option<&[u8]>, shard: bool) -> DataType {
+        let registry = if shard { &self.pubsub.shard_channels } else { &self.pubsub.channels };
+        active_channels(registry, pattern)
+    }
+
+    pub fn pubsub_numsub(&mut self, channels: &[Vec<u8>], shard: bool) -> DataType {
+        let registry = if shard { &self.pubsub.shard_channels } else { &self.pubsub.channels };
+        subscriber_counts(registry, channels)
+    }
+
+    // Number of distinct patterns subscribed to by any client
+    pub fn pubsub_numpat(&mut self) -> DataType {
+        DataType::Integer(self.pubsub.patterns.len() as i64)
+    }
+}
@@ -0,0 +1,56 @@
+use std::{
+    cell::Cell,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+thread_local! {
+    static RNG_STATE: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish() | 1);
+}
+
+// xorshift64* generator, good enough for sampling and probabilistic counters
+pub fn random_u64() -> u64 {
+    RNG_STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545F4914F6CDD1D)
+    })
+}
+
+pub fn random_f64() -> f64 {
+    (random_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// A random 40 character hex id, like those naming replication histories and cluster nodes
+pub fn random_hex_id() -> String {
+    format!("{:016x}{:016x}{:08x}", random_u64(), random_u64(), random_u64() as u32)
+}
+
+// Uniform index in 0..n, n must be non-zero
+pub fn random_index(n: usize) -> usize {
+    (random_u64() % n as u64) as usize
+}
+
+// Pick `count` distinct items in random order, or all of them when there aren't enough
+pub fn sample_distinct<T>(mut items: Vec<T>, count: usize) -> Vec<T> {
+    let count = count.min(items.len());
+    for i in 0..count {
+        let j = i + random_index(items.len() - i);
+        items.swap(i, j);
+    }
+    items.truncate(count);
+    items
+}
+
+pub const REPLY_TOO_LARGE: &str = "ERR not enough memory for a reply of that size";
+
+// Pick `count` items allowing repeats, or None if a reply that big can't be allocated
+pub fn sample_with_repeats<T: Clone>(items: &[T], count: usize) -> Option<Vec<T>> {
+    let mut picked = Vec::new();
+    picked.try_reserve_exact(count).ok()?;
+    picked.extend((0..count).map(|_| items[random_index(items.len())].clone()));
+    Some(picked)
+}
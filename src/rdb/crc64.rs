@@ -0,0 +1,43 @@
+// CRC-64 with the Jones polynomial, reflected and with no final xor, as Redis uses to checksum
+// RDB files and DUMP payloads
+
+const POLY: u64 = 0x95ac9329ac4bc9b5;
+
+const fn make_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static TABLE: [u64; 256] = make_table();
+
+// Continue a checksum over more data, starting from zero
+pub fn crc64(crc: u64, data: &[u8]) -> u64 {
+    data.iter().fold(crc, |crc, byte| TABLE[((crc ^ *byte as u64) & 0xff) as usize] ^ (crc >> 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::crc64;
+
+    #[test]
+    fn check_value() {
+        assert_eq!(crc64(0, b"123456789"), 0xe9c6d914c4b8d9ca);
+        assert_eq!(crc64(0, b""), 0);
+    }
+
+    #[test]
+    fn continues_over_pieces() {
+        assert_eq!(crc64(crc64(0, b"1234"), b"56789"), crc64(0, b"123456789"));
+    }
+}
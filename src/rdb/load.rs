@@ -0,0 +1,395 @@
+use std::path::Path;
+
+use anyhow::{Error, Result};
+
+use super::*;
+use crate::{
+    config::EncodingLimits,
+    resp::DataType,
+    state::{DataStoreValue, State, Value},
+    types::{
+        bloom::BloomFilter,
+        cms::CountMinSketch,
+        hash::Hash,
+        json::Json,
+        list::List,
+        parse_strict_integer,
+        set::Set,
+        stream::{PendingEntry, Stream, StreamId},
+        timeseries::TimeSeries,
+        topk::TopK,
+        zset::SortedSet,
+    },
+};
+
+fn stream_id(reader: &mut Reader) -> Result<StreamId> {
+    Ok(StreamId { ms: reader.length()? as u64, seq: reader.length()? as u64 })
+}
+
+fn raw_stream_id(raw: &[u8]) -> Result<StreamId> {
+    let raw: [u8; 16] = raw.try_into().map_err(|_| Error::msg("Bad RDB file: invalid stream ID"))?;
+    Ok(StreamId { ms: u64::from_be_bytes(raw[..8].try_into().unwrap()), seq: u64::from_be_bytes(raw[8..].try_into().unwrap()) })
+}
+
+fn integer(entry: Option<&Vec<u8>>) -> Result<i64> {
+    entry.and_then(|entry| parse_strict_integer(entry)).ok_or_else(|| Error::msg("Bad RDB file: invalid stream listpack"))
+}
+
+// Split the flat entries of a compact encoding into consecutive pairs
+fn pairs(entries: Vec<Vec<u8>>) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    if !entries.len().is_multiple_of(2) {
+        return Err(Error::msg("Bad RDB file: odd number of entries"));
+    }
+    let mut entries = entries.into_iter();
+    Ok(std::iter::from_fn(|| Some((entries.next()?, entries.next()?))).collect())
+}
+
+fn list_from(items: Vec<Vec<u8>>, limits: &EncodingLimits) -> List {
+    let mut list = List::default();
+    for item in items {
+        list.push_back(item, limits);
+    }
+    list
+}
+
+fn hash_from(fields: Vec<(Vec<u8>, Vec<u8>)>, limits: &EncodingLimits) -> Hash {
+    let mut hash = Hash::default();
+    for (field, value) in fields {
+        hash.insert(field, value, limits);
+    }
+    hash
+}
+
+fn zset_from(members: Vec<(Vec<u8>, Vec<u8>)>) -> Result<SortedSet> {
+    let mut zset = SortedSet::default();
+    for (member, score) in members {
+        zset.insert(member, parse_double(&score)?);
+    }
+    Ok(zset)
+}
+
+// Add a field expiring at the given unix time in milliseconds, if any. Fields that expired
+// while the server was down are dropped.
+fn insert_expiring_field(hash: &mut Hash, field: Vec<u8>, value: Vec<u8>, expire_at: Option<u64>, limits: &EncodingLimits) {
+    match expire_at {
+        None => {
+            hash.insert(field, value, limits);
+        }
+        Some(at) => {
+            if let Some(expiry) = live_expiry(at) {
+                hash.insert(field.clone(), value, limits);
+                hash.set_expiry(&field, expiry);
+            }
+        }
+    }
+}
+
+// Hashes with field expirations carry each field's deadline relative to the earliest one
+fn read_hash(reader: &mut Reader, with_ttls: bool, limits: &EncodingLimits) -> Result<Hash> {
+    let min_expiry = if with_ttls { reader.millisecond_time()? } else { 0 };
+    let mut hash = Hash::default();
+    for _ in 0..reader.length()? {
+        let ttl = if with_ttls { reader.length()? as u64 } else { 0 };
+        let field = reader.string()?;
+        let value = reader.string()?;
+        insert_expiring_field(&mut hash, field, value, (ttl != 0).then(|| min_expiry + ttl - 1), limits);
+    }
+    Ok(hash)
+}
+
+// Small hashes with field expirations: a listpack of field, value and absolute expiry time
+// triples, zero meaning none, behind the earliest expiry
+fn read_hash_listpack_ex(reader: &mut Reader, limits: &EncodingLimits) -> Result<Hash> {
+    reader.millisecond_time()?;
+    let entries = listpack_entries(&reader.string()?)?;
+    if !entries.len().is_multiple_of(3) {
+        return Err(Error::msg("Bad RDB file: invalid hash listpack"));
+    }
+    let mut hash = Hash::default();
+    let mut entries = entries.into_iter();
+    while let (Some(field), Some(value), Some(expire_at)) = (entries.next(), entries.next(), entries.next()) {
+        let expire_at = integer(Some(&expire_at))? as u64;
+        insert_expiring_field(&mut hash, field, value, (expire_at != 0).then_some(expire_at), limits);
+    }
+    Ok(hash)
+}
+
+// Lists as a series of ziplists, or in the newer version a series of nodes that are each
+// either a listpack or a single plain element
+fn read_quicklist(reader: &mut Reader, value_type: u8, limits: &EncodingLimits) -> Result<List> {
+    let mut items = Vec::new();
+    for _ in 0..reader.length()? {
+        let container = if value_type == RDB_TYPE_LIST_QUICKLIST_2 { reader.length()? } else { QUICKLIST_NODE_CONTAINER_PACKED };
+        let node = reader.string()?;
+        match container {
+            QUICKLIST_NODE_CONTAINER_PLAIN => items.push(node),
+            QUICKLIST_NODE_CONTAINER_PACKED if value_type == RDB_TYPE_LIST_QUICKLIST_2 => items.extend(listpack_entries(&node)?),
+            QUICKLIST_NODE_CONTAINER_PACKED => items.extend(ziplist_entries(&node)?),
+            _ => return Err(Error::msg("Bad RDB file: unknown quicklist container")),
+        }
+    }
+    Ok(list_from(items, limits))
+}
+
+// The three stream types differ only in the metadata that follows the entries: the first
+// lacks the deletion and added counts, and the first two lack consumers' active times
+fn read_stream(reader: &mut Reader, value_type: u8) -> Result<Stream> {
+    let mut stream = Stream::default();
+    for _ in 0..reader.length()? {
+        let master = raw_stream_id(&reader.string()?)?;
+        let entries = listpack_entries(&reader.string()?)?;
+        let mut items = entries.iter();
+        // Master entry: counts, then the field names entries flagged SAMEFIELDS share
+        let count = integer(items.next())? + integer(items.next())?;
+        let master_fields: Vec<&Vec<u8>> = (0..integer(items.next())?).map_while(|_| items.next()).collect();
+        items.next();
+        for _ in 0..count {
+            let flags = integer(items.next())?;
+            let ms = master.ms.wrapping_add(integer(items.next())? as u64);
+            let seq = master.seq.wrapping_add(integer(items.next())? as u64);
+            let fields = if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+                master_fields.iter().map(|field| Some(((*field).clone(), items.next()?.clone()))).collect::<Option<Vec<_>>>()
+            } else {
+                (0..integer(items.next())?).map(|_| Some((items.next()?.clone(), items.next()?.clone()))).collect::<Option<Vec<_>>>()
+            };
+            let fields = fields.ok_or_else(|| Error::msg("Bad RDB file: invalid stream listpack"))?;
+            items.next();
+            let id = StreamId { ms, seq };
+            if flags & STREAM_ITEM_FLAG_DELETED != 0 {
+                continue;
+            }
+            if stream.len() > 0 && id <= stream.last_id() {
+                return Err(Error::msg("Bad RDB file: stream IDs out of order"));
+            }
+            stream.append(id, fields);
+        }
+    }
+
+    reader.length()?;
+    let last_id = stream_id(reader)?;
+    if value_type == RDB_TYPE_STREAM_LISTPACKS {
+        stream.set_last_id(last_id, None, None);
+    } else {
+        stream_id(reader)?;
+        let max_deleted_id = stream_id(reader)?;
+        let entries_added = reader.length()? as u64;
+        stream.set_last_id(last_id, Some(entries_added), Some(max_deleted_id));
+    }
+
+    for _ in 0..reader.length()? {
+        let name = reader.string()?;
+        let last_delivered = stream_id(reader)?;
+        let entries_read = match value_type {
+            RDB_TYPE_STREAM_LISTPACKS => None,
+            _ => Some(reader.length()? as u64).filter(|read| *read != u64::MAX),
+        };
+        if !stream.create_group(&name, last_delivered) {
+            return Err(Error::msg("Bad RDB file: duplicate consumer group"));
+        }
+        let group = stream.group_mut(&name).unwrap();
+        group.entries_read = entries_read;
+        for _ in 0..reader.length()? {
+            let id = raw_stream_id(reader.bytes(16)?)?;
+            let delivery_time = reader.millisecond_time()?;
+            let delivery_count = reader.length()? as u64;
+            group.pending.insert(id, PendingEntry { consumer: Vec::new(), delivery_time, delivery_count });
+        }
+        for _ in 0..reader.length()? {
+            let name = reader.string()?;
+            let seen_time = reader.millisecond_time()?;
+            let active_time = match value_type {
+                RDB_TYPE_STREAM_LISTPACKS_3 => Some(reader.millisecond_time()?).filter(|time| *time != u64::MAX),
+                _ => Some(seen_time),
+            };
+            let consumer = group.consumer(&name, seen_time);
+            consumer.active_time = active_time;
+            let mut pending = Vec::new();
+            for _ in 0..reader.length()? {
+                let id = raw_stream_id(reader.bytes(16)?)?;
+                consumer.pending.insert(id);
+                pending.push(id);
+            }
+            // Owners of the group's pending entries come from the consumers' own lists
+            for id in pending {
+                let entry = group.pending.get_mut(&id).ok_or_else(|| Error::msg("Bad RDB file: consumer owns an entry that isn't pending"))?;
+                entry.consumer = name.clone();
+            }
+        }
+    }
+    Ok(stream)
+}
+
+fn read_module(reader: &mut Reader) -> Result<Value> {
+    let name = module_name(reader.length()? as u64);
+    let mut input = ModuleReader { reader };
+    let value = match name.as_str() {
+        MODULE_BLOOM => Value::Bloom(BloomFilter::rdb_load(&mut input)?),
+        MODULE_CMS => Value::CountMinSketch(CountMinSketch::rdb_load(&mut input)?),
+        MODULE_TOPK => Value::TopK(TopK::rdb_load(&mut input)?),
+        MODULE_JSON => Value::Json(Json::rdb_load(&mut input)?),
+        MODULE_TIMESERIES => Value::TimeSeries(TimeSeries::rdb_load(&mut input)?),
+        _ => return Err(Error::msg(format!("Bad RDB file: unknown module type {}", name))),
+    };
+    if input.reader.length()? != RDB_MODULE_OPCODE_EOF {
+        return Err(Error::msg("Bad RDB file: module value not terminated"));
+    }
+    Ok(value)
+}
+
+fn read_value(reader: &mut Reader, value_type: u8, limits: &EncodingLimits) -> Result<Value> {
+    Ok(match value_type {
+        RDB_TYPE_STRING => Value::String(reader.string()?),
+        RDB_TYPE_LIST => {
+            let mut list = List::default();
+            for _ in 0..reader.length()? {
+                list.push_back(reader.string()?, limits);
+            }
+            Value::List(list)
+        }
+        RDB_TYPE_SET => {
+            let members = (0..reader.length()?).map(|_| reader.string()).collect::<Result<Vec<_>>>()?;
+            Value::Set(Set::from_members(members, limits))
+        }
+        RDB_TYPE_ZSET | RDB_TYPE_ZSET_2 => {
+            let mut zset = SortedSet::default();
+            for _ in 0..reader.length()? {
+                let member = reader.string()?;
+                let score = if value_type == RDB_TYPE_ZSET { reader.text_double()? } else { reader.double()? };
+                zset.insert(member, score);
+            }
+            Value::SortedSet(zset)
+        }
+        RDB_TYPE_HASH => Value::Hash(read_hash(reader, false, limits)?),
+        RDB_TYPE_HASH_METADATA => Value::Hash(read_hash(reader, true, limits)?),
+        RDB_TYPE_HASH_ZIPMAP => Value::Hash(hash_from(pairs(zipmap_entries(&reader.string()?)?)?, limits)),
+        RDB_TYPE_HASH_ZIPLIST => Value::Hash(hash_from(pairs(ziplist_entries(&reader.string()?)?)?, limits)),
+        RDB_TYPE_HASH_LISTPACK => Value::Hash(hash_from(pairs(listpack_entries(&reader.string()?)?)?, limits)),
+        RDB_TYPE_HASH_LISTPACK_EX => Value::Hash(read_hash_listpack_ex(reader, limits)?),
+        RDB_TYPE_LIST_ZIPLIST => Value::List(list_from(ziplist_entries(&reader.string()?)?, limits)),
+        RDB_TYPE_LIST_QUICKLIST | RDB_TYPE_LIST_QUICKLIST_2 => Value::List(read_quicklist(reader, value_type, limits)?),
+        RDB_TYPE_SET_INTSET => Value::Set(Set::from_members(intset_members(&reader.string()?)?, limits)),
+        RDB_TYPE_SET_LISTPACK => Value::Set(Set::from_members(listpack_entries(&reader.string()?)?, limits)),
+        RDB_TYPE_ZSET_ZIPLIST => Value::SortedSet(zset_from(pairs(ziplist_entries(&reader.string()?)?)?)?),
+        RDB_TYPE_ZSET_LISTPACK => Value::SortedSet(zset_from(pairs(listpack_entries(&reader.string()?)?)?)?),
+        RDB_TYPE_STREAM_LISTPACKS | RDB_TYPE_STREAM_LISTPACKS_2 | RDB_TYPE_STREAM_LISTPACKS_3 => Value::Stream(read_stream(reader, value_type)?),
+        RDB_TYPE_MODULE_2 => read_module(reader)?,
+        _ => return Err(Error::msg(format!("Bad RDB file: unsupported value type {}", value_type))),
+    })
+}
+
+// The value in a DUMP payload, rejecting payloads from newer RDB versions or that fail their
+// checksum
+pub fn restore_value(payload: &[u8], limits: &EncodingLimits) -> Result<Value> {
+    let Some(body_len) = payload.len().checked_sub(10) else {
+        return Err(Error::msg("ERR DUMP payload version or checksum are wrong"));
+    };
+    let version = u16::from_le_bytes([payload[body_len], payload[body_len + 1]]);
+    let crc = u64::from_le_bytes(payload[body_len + 2..].try_into().unwrap());
+    if version > RDB_VERSION || crc != crc64(0, &payload[..body_len + 2]) {
+        return Err(Error::msg("ERR DUMP payload version or checksum are wrong"));
+    }
+    let mut reader = Reader::new(&payload[..body_len]);
+    let value = reader.byte().and_then(|value_type| read_value(&mut reader, value_type, limits));
+    match value {
+        Ok(value) if reader.is_empty() && !value.is_empty() => Ok(value),
+        _ => Err(Error::msg("ERR Bad data format")),
+    }
+}
+
+impl State {
+    // Load the dump file at boot. A missing file just means an empty datastore.
+    pub fn load_rdb(&mut self, path: &Path) -> Result<()> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        self.verify_rdb_checksum(&data)?;
+        self.load_rdb_data(&data)?;
+        Ok(())
+    }
+
+    // Load an RDB image from the start of the data, returning how many bytes it took up. The
+    // caller verifies the checksum, as only it knows whether anything follows the image.
+    pub fn load_rdb_data(&mut self, data: &[u8]) -> Result<usize> {
+        let mut reader = Reader::new(data);
+        let header = reader.bytes(9)?;
+        let version = std::str::from_utf8(&header[5..]).ok().and_then(|version| version.parse::<u16>().ok());
+        if &header[..5] != b"REDIS" || version.is_none() {
+            return Err(Error::msg("Bad RDB file: wrong signature"));
+        }
+
+        let mut db = 0;
+        // An expiry, when present, comes just before the key it applies to
+        let mut expiry: Option<u64> = None;
+        loop {
+            match reader.byte()? {
+                RDB_OPCODE_EOF => break,
+                RDB_OPCODE_SELECTDB => db = reader.length()?,
+                RDB_OPCODE_RESIZEDB => {
+                    reader.length()?;
+                    reader.length()?;
+                }
+                RDB_OPCODE_AUX => {
+                    reader.string()?;
+                    reader.string()?;
+                }
+                RDB_OPCODE_EXPIRETIME_MS => expiry = Some(reader.millisecond_time()?),
+                RDB_OPCODE_EXPIRETIME => expiry = Some(u32::from_le_bytes(reader.array()?) as u64 * 1000),
+                // Eviction metadata isn't carried over
+                RDB_OPCODE_FREQ => {
+                    reader.byte()?;
+                }
+                RDB_OPCODE_IDLE => {
+                    reader.length()?;
+                }
+                RDB_OPCODE_FUNCTION2 => {
+                    let code = reader.string()?;
+                    if let Err(DataType::SimpleError(msg)) = self.load_library(&code) {
+                        return Err(Error::msg(format!("Bad RDB file: {}", msg)));
+                    }
+                }
+                RDB_OPCODE_MODULE_AUX => return Err(Error::msg("Bad RDB file: modules are not supported")),
+                value_type => {
+                    let key = reader.string()?;
+                    let value = read_value(&mut reader, value_type, &self.config.limits)?;
+                    let expiry = expiry.take().map(live_expiry);
+                    // Only one database is kept, and keys that expired while the server was
+                    // down are dropped, as are hashes left without fields
+                    if db != 0 || expiry == Some(None) || value.is_empty() {
+                        continue;
+                    }
+                    if matches!(&value, Value::Hash(hash) if hash.has_expiring_fields()) {
+                        self.hashes_with_field_ttl.insert(key.clone());
+                    }
+                    self.datastore.insert(key, DataStoreValue::new(value, expiry.flatten()));
+                }
+            }
+        }
+        // The checksum follows the end of file marker
+        reader.bytes(8)?;
+        Ok(reader.pos)
+    }
+
+    // The last eight bytes hold the checksum of everything before them. A zero checksum was
+    // written with checksums turned off and isn't checked, nor is anything when they're off here.
+    pub fn verify_rdb_checksum(&self, data: &[u8]) -> Result<()> {
+        let Some(body_len) = data.len().checked_sub(8) else {
+            return Err(Error::msg("Bad RDB file: truncated"));
+        };
+        let expected = u64::from_le_bytes(data[body_len..].try_into().unwrap());
+        if !self.config.rdb.checksum || expected == 0 {
+            return Ok(());
+        }
+        let actual = crc64(0, &data[..body_len]);
+        if actual == expected {
+            return Ok(());
+        }
+        let msg = format!("Wrong RDB checksum expected: ({:x}) got: ({:x})", expected, actual);
+        if self.config.rdb.warn_on_bad_checksum {
+            println!("{}, loading anyway", msg);
+            return Ok(());
+        }
+        Err(Error::msg(format!("Bad RDB file: {}", msg)))
+    }
+}
@@ -0,0 +1,644 @@
+mod crc64;
+mod load;
+mod save;
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Error, Result};
+use tokio::time::{Duration, Instant};
+
+use crate::{clock::now_ms, commands::stream::unix_time_ms};
+
+pub use crc64::crc64;
+pub use load::restore_value;
+pub use save::{dump_value, serialize, serialize_into, sync_dir, write_file};
+
+pub const RDB_VERSION: u16 = 12;
+
+// Record opcodes, which share a byte with the value type of key records
+pub const RDB_OPCODE_FUNCTION2: u8 = 245;
+const RDB_OPCODE_MODULE_AUX: u8 = 247;
+const RDB_OPCODE_IDLE: u8 = 248;
+const RDB_OPCODE_FREQ: u8 = 249;
+const RDB_OPCODE_AUX: u8 = 250;
+const RDB_OPCODE_RESIZEDB: u8 = 251;
+const RDB_OPCODE_EXPIRETIME_MS: u8 = 252;
+const RDB_OPCODE_EXPIRETIME: u8 = 253;
+const RDB_OPCODE_SELECTDB: u8 = 254;
+const RDB_OPCODE_EOF: u8 = 255;
+
+// Value types. Collections are written in the plain encodings every Redis version can load,
+// whatever their in-memory encoding, but the compact ones Redis writes are read too.
+const RDB_TYPE_STRING: u8 = 0;
+const RDB_TYPE_LIST: u8 = 1;
+const RDB_TYPE_SET: u8 = 2;
+const RDB_TYPE_ZSET: u8 = 3;
+const RDB_TYPE_HASH: u8 = 4;
+const RDB_TYPE_ZSET_2: u8 = 5;
+const RDB_TYPE_MODULE_2: u8 = 7;
+const RDB_TYPE_HASH_ZIPMAP: u8 = 9;
+const RDB_TYPE_LIST_ZIPLIST: u8 = 10;
+const RDB_TYPE_SET_INTSET: u8 = 11;
+const RDB_TYPE_ZSET_ZIPLIST: u8 = 12;
+const RDB_TYPE_HASH_ZIPLIST: u8 = 13;
+const RDB_TYPE_LIST_QUICKLIST: u8 = 14;
+const RDB_TYPE_STREAM_LISTPACKS: u8 = 15;
+const RDB_TYPE_HASH_LISTPACK: u8 = 16;
+const RDB_TYPE_ZSET_LISTPACK: u8 = 17;
+const RDB_TYPE_LIST_QUICKLIST_2: u8 = 18;
+const RDB_TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const RDB_TYPE_SET_LISTPACK: u8 = 20;
+const RDB_TYPE_STREAM_LISTPACKS_3: u8 = 21;
+const RDB_TYPE_HASH_METADATA: u8 = 24;
+const RDB_TYPE_HASH_LISTPACK_EX: u8 = 25;
+
+// Quicklist nodes hold either a single large element or a listpack of them
+const QUICKLIST_NODE_CONTAINER_PLAIN: usize = 1;
+const QUICKLIST_NODE_CONTAINER_PACKED: usize = 2;
+
+// Special string encodings, flagged by the top two bits of the length being set
+const RDB_ENC_INT8: u8 = 0;
+const RDB_ENC_INT16: u8 = 1;
+const RDB_ENC_INT32: u8 = 2;
+const RDB_ENC_LZF: u8 = 3;
+
+// Strings up to this long are never worth compressing
+const LZF_MIN_LEN: usize = 20;
+const LZF_HASH_BITS: u32 = 14;
+const LZF_MAX_LITERAL: usize = 32;
+const LZF_MAX_OFFSET: usize = 1 << 13;
+const LZF_MAX_MATCH: usize = 264;
+
+// Tags in front of each field of a module value
+const RDB_MODULE_OPCODE_EOF: usize = 0;
+const RDB_MODULE_OPCODE_UINT: usize = 2;
+const RDB_MODULE_OPCODE_DOUBLE: usize = 4;
+const RDB_MODULE_OPCODE_STRING: usize = 5;
+
+// Module type names, as registered by RedisBloom, RedisJSON and RedisTimeSeries. The layout
+// of the fields is this server's own.
+const MODULE_BLOOM: &str = "MBbloom--";
+const MODULE_CMS: &str = "CMSk-TYPE";
+const MODULE_TOPK: &str = "TopK-TYPE";
+const MODULE_JSON: &str = "ReJSON-RL";
+const MODULE_TIMESERIES: &str = "TSDB-TYPE";
+
+const MODULE_ID_CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+// Stream listpack entry flags
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2;
+
+// Outcome of saves for INFO persistence. Background saves update it from their own task, so
+// it is shared rather than behind the state lock.
+#[derive(Debug)]
+pub struct SaveStatus {
+    // Unix time in seconds of the last successful save, or of startup
+    pub last_save_time: u64,
+    // State::dirty as of the last successful save, and as of the start of the running BGSAVE
+    pub dirty_at_last_save: u64,
+    pub bgsave_dirty: u64,
+    pub bgsave_started: Option<Instant>,
+    pub last_bgsave_try: Option<Instant>,
+    pub last_bgsave_ok: bool,
+    pub last_bgsave_duration: Option<Duration>,
+}
+
+impl SaveStatus {
+    pub fn new() -> Arc<Mutex<SaveStatus>> {
+        Arc::new(Mutex::new(SaveStatus {
+            last_save_time: unix_time_ms() / 1000,
+            dirty_at_last_save: 0,
+            bgsave_dirty: 0,
+            bgsave_started: None,
+            last_bgsave_try: None,
+            last_bgsave_ok: true,
+            last_bgsave_duration: None,
+        }))
+    }
+
+    pub fn saved(&mut self, dirty: u64) {
+        self.last_save_time = unix_time_ms() / 1000;
+        self.dirty_at_last_save = dirty;
+    }
+
+    pub fn finish_bgsave(&mut self, ok: bool) {
+        if ok {
+            self.saved(self.bgsave_dirty);
+        }
+        self.last_bgsave_ok = ok;
+        self.last_bgsave_duration = self.bgsave_started.take().map(|started| started.elapsed());
+    }
+}
+
+// RDB length prefix
+pub fn encode_length(buf: &mut Vec<u8>, len: usize) {
+    match len {
+        0..=0x3f => buf.push(len as u8),
+        0x40..=0x3fff => buf.extend_from_slice(&(0x4000 | len as u16).to_be_bytes()),
+        0x4000..=0xffff_ffff => {
+            buf.push(0x80);
+            buf.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+        _ => {
+            buf.push(0x81);
+            buf.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+}
+
+// Long strings are LZF compressed when that saves space, as Redis does
+fn encode_string(buf: &mut Vec<u8>, s: &[u8]) {
+    if let Some(compressed) = Some(s).filter(|s| s.len() > LZF_MIN_LEN).and_then(lzf_compress) {
+        buf.push(0xc0 | RDB_ENC_LZF);
+        encode_length(buf, compressed.len());
+        encode_length(buf, s.len());
+        buf.extend_from_slice(&compressed);
+        return;
+    }
+    encode_length(buf, s.len());
+    buf.extend_from_slice(s);
+}
+
+// Either a plain length, or the special encoding a string was stored with
+enum Length {
+    Plain(usize),
+    Encoded(u8),
+}
+
+fn corrupt() -> Error {
+    Error::msg("Bad RDB file: unexpected end of data")
+}
+
+// Cursor over the contents of an RDB file, or of an RDB style payload such as FUNCTION DUMP's
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub fn byte(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len).ok_or_else(corrupt)?).ok_or_else(corrupt)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.bytes(N)?.try_into().unwrap_or([0; N]))
+    }
+
+    // Unix time in milliseconds
+    fn millisecond_time(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn double(&mut self) -> Result<f64> {
+        Ok(f64::from_le_bytes(self.array()?))
+    }
+
+    // Scores of the original sorted set type, stored as text behind a one byte length
+    fn text_double(&mut self) -> Result<f64> {
+        match self.byte()? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            len => parse_double(self.bytes(len as usize)?),
+        }
+    }
+
+    fn encoded_length(&mut self) -> Result<Length> {
+        let first = self.byte()?;
+        Ok(match first >> 6 {
+            0 => Length::Plain((first & 0x3f) as usize),
+            1 => Length::Plain(u16::from_be_bytes([first & 0x3f, self.byte()?]) as usize),
+            2 if first == 0x80 => Length::Plain(u32::from_be_bytes(self.array()?) as usize),
+            2 if first == 0x81 => Length::Plain(u64::from_be_bytes(self.array()?) as usize),
+            3 => Length::Encoded(first & 0x3f),
+            _ => return Err(Error::msg(format!("Bad RDB file: unknown length encoding {:#x}", first))),
+        })
+    }
+
+    pub fn length(&mut self) -> Result<usize> {
+        match self.encoded_length()? {
+            Length::Plain(len) => Ok(len),
+            Length::Encoded(_) => Err(Error::msg("Bad RDB file: encoded string where a length was expected")),
+        }
+    }
+
+    pub fn string(&mut self) -> Result<Vec<u8>> {
+        match self.encoded_length()? {
+            Length::Plain(len) => Ok(self.bytes(len)?.to_vec()),
+            Length::Encoded(RDB_ENC_INT8) => Ok((self.byte()? as i8).to_string().into_bytes()),
+            Length::Encoded(RDB_ENC_INT16) => Ok(i16::from_le_bytes(self.array()?).to_string().into_bytes()),
+            Length::Encoded(RDB_ENC_INT32) => Ok(i32::from_le_bytes(self.array()?).to_string().into_bytes()),
+            Length::Encoded(RDB_ENC_LZF) => {
+                let compressed_len = self.length()?;
+                let len = self.length()?;
+                lzf_decompress(self.bytes(compressed_len)?, len)
+            }
+            Length::Encoded(encoding) => Err(Error::msg(format!("Bad RDB file: unknown string encoding {}", encoding))),
+        }
+    }
+}
+
+// LZF blocks are a series of literal runs and back references into the output so far
+fn lzf_decompress(input: &[u8], len: usize) -> Result<Vec<u8>> {
+    let bad = || Error::msg("Bad RDB file: invalid LZF compressed string");
+    let mut output = Vec::with_capacity(len);
+    let mut i = 0;
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+        if ctrl < 32 {
+            let literal = input.get(i..i + ctrl + 1).ok_or_else(bad)?;
+            output.extend_from_slice(literal);
+            i += ctrl + 1;
+        } else {
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += *input.get(i).ok_or_else(bad)? as usize;
+                i += 1;
+            }
+            let offset = ((ctrl & 0x1f) << 8) + *input.get(i).ok_or_else(bad)? as usize + 1;
+            i += 1;
+            let start = output.len().checked_sub(offset).ok_or_else(bad)?;
+            // The reference can overlap the bytes it is producing
+            for j in 0..run + 2 {
+                output.push(output[start + j]);
+            }
+        }
+    }
+    if output.len() != len {
+        return Err(bad());
+    }
+    Ok(output)
+}
+
+fn lzf_literals(output: &mut Vec<u8>, literals: &[u8]) {
+    for run in literals.chunks(LZF_MAX_LITERAL) {
+        output.push(run.len() as u8 - 1);
+        output.extend_from_slice(run);
+    }
+}
+
+// Compress in the format lzf_decompress reads, finding earlier occurrences of each three byte
+// sequence through a hash table. Gives up unless it saves at least four bytes.
+fn lzf_compress(input: &[u8]) -> Option<Vec<u8>> {
+    let limit = input.len().checked_sub(4)?;
+    let mut output = Vec::with_capacity(input.len());
+    // Position plus one of the last occurrence of each hashed sequence, zero when there is none
+    let mut table = vec![0usize; 1 << LZF_HASH_BITS];
+    let (mut i, mut literal_start) = (0, 0);
+    while i + 2 < input.len() {
+        let sequence = u32::from_be_bytes([0, input[i], input[i + 1], input[i + 2]]);
+        let slot = (sequence.wrapping_mul(2654435761) >> (32 - LZF_HASH_BITS)) as usize;
+        let candidate = std::mem::replace(&mut table[slot], i + 1);
+        let Some(reference) = candidate.checked_sub(1).filter(|r| i - r <= LZF_MAX_OFFSET && input[*r..r + 3] == input[i..i + 3]) else {
+            i += 1;
+            continue;
+        };
+        let max_len = (input.len() - i).min(LZF_MAX_MATCH);
+        let len = (3..max_len).find(|len| input[reference + len] != input[i + len]).unwrap_or(max_len);
+        lzf_literals(&mut output, &input[literal_start..i]);
+        let (run, offset) = (len - 2, i - reference - 1);
+        if run < 7 {
+            output.push(((run << 5) | (offset >> 8)) as u8);
+        } else {
+            output.push(((7 << 5) | (offset >> 8)) as u8);
+            output.push((run - 7) as u8);
+        }
+        output.push(offset as u8);
+        if output.len() > limit {
+            return None;
+        }
+        i += len;
+        literal_start = i;
+    }
+    lzf_literals(&mut output, &input[literal_start..]);
+    Some(output).filter(|output| output.len() <= limit)
+}
+
+// Module type id: the nine character type name packed six bits per character, followed by
+// ten bits of encoding version
+fn module_id(name: &str) -> u64 {
+    let id = name.bytes().fold(0u64, |id, c| (id << 6) | MODULE_ID_CHARSET.iter().position(|&x| x == c).unwrap_or(0) as u64);
+    id << 10
+}
+
+fn module_name(id: u64) -> String {
+    (0..9).rev().map(|i| MODULE_ID_CHARSET[((id >> (10 + 6 * i)) & 0x3f) as usize] as char).collect()
+}
+
+// Fields of a module value, each tagged with its type the way RedisModule_Save* writes them
+pub struct ModuleWriter<'a> {
+    buf: &'a mut Vec<u8>,
+}
+
+impl ModuleWriter<'_> {
+    pub fn unsigned(&mut self, n: u64) {
+        encode_length(self.buf, RDB_MODULE_OPCODE_UINT);
+        encode_length(self.buf, n as usize);
+    }
+
+    pub fn double(&mut self, n: f64) {
+        encode_length(self.buf, RDB_MODULE_OPCODE_DOUBLE);
+        self.buf.extend_from_slice(&n.to_le_bytes());
+    }
+
+    pub fn string(&mut self, s: &[u8]) {
+        encode_length(self.buf, RDB_MODULE_OPCODE_STRING);
+        encode_string(self.buf, s);
+    }
+}
+
+pub struct ModuleReader<'r, 'a> {
+    reader: &'r mut Reader<'a>,
+}
+
+impl ModuleReader<'_, '_> {
+    fn expect(&mut self, opcode: usize) -> Result<()> {
+        if self.reader.length()? != opcode {
+            return Err(Error::msg("Bad RDB file: module value field of the wrong type"));
+        }
+        Ok(())
+    }
+
+    pub fn unsigned(&mut self) -> Result<u64> {
+        self.expect(RDB_MODULE_OPCODE_UINT)?;
+        Ok(self.reader.length()? as u64)
+    }
+
+    pub fn double(&mut self) -> Result<f64> {
+        self.expect(RDB_MODULE_OPCODE_DOUBLE)?;
+        self.reader.double()
+    }
+
+    pub fn string(&mut self) -> Result<Vec<u8>> {
+        self.expect(RDB_MODULE_OPCODE_STRING)?;
+        self.reader.string()
+    }
+}
+
+// Size of the back length that follows a listpack entry of the given encoded size
+fn listpack_backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
+
+// Listpack in the Redis format, which is how stream nodes are stored in RDB files
+struct ListpackWriter {
+    buf: Vec<u8>,
+    count: usize,
+}
+
+impl ListpackWriter {
+    fn new() -> Self {
+        // Total size and element count are filled in once the pack is complete
+        ListpackWriter { buf: vec![0; 6], count: 0 }
+    }
+
+    fn push_entry(&mut self, entry: &[u8]) {
+        self.buf.extend_from_slice(entry);
+        // The back length lets the pack be walked from the end, most significant bits first
+        let size = listpack_backlen_size(entry.len());
+        for i in (0..size).rev() {
+            let bits = ((entry.len() >> (7 * i)) & 0x7f) as u8;
+            self.buf.push(if i == size - 1 { bits } else { bits | 0x80 });
+        }
+        self.count += 1;
+    }
+
+    fn push_int(&mut self, n: i64) {
+        let entry = match n {
+            0..=127 => vec![n as u8],
+            -4096..=4095 => {
+                let bits = (n as u16) & 0x1fff;
+                vec![0xc0 | (bits >> 8) as u8, bits as u8]
+            }
+            -32768..=32767 => [&[0xf1], &(n as i16).to_le_bytes()[..]].concat(),
+            -8388608..=8388607 => [&[0xf2], &(n as i32).to_le_bytes()[..3]].concat(),
+            -2147483648..=2147483647 => [&[0xf3], &(n as i32).to_le_bytes()[..]].concat(),
+            _ => [&[0xf4], &n.to_le_bytes()[..]].concat(),
+        };
+        self.push_entry(&entry);
+    }
+
+    fn push_str(&mut self, s: &[u8]) {
+        let mut entry = match s.len() {
+            0..=63 => vec![0x80 | s.len() as u8],
+            64..=4095 => vec![0xe0 | (s.len() >> 8) as u8, s.len() as u8],
+            _ => [&[0xf0], &(s.len() as u32).to_le_bytes()[..]].concat(),
+        };
+        entry.extend_from_slice(s);
+        self.push_entry(&entry);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.buf.push(0xff);
+        let total = self.buf.len() as u32;
+        self.buf[..4].copy_from_slice(&total.to_le_bytes());
+        // Counts that don't fit are left for readers to work out by walking the pack
+        self.buf[4..6].copy_from_slice(&(self.count.min(u16::MAX as usize) as u16).to_le_bytes());
+        self.buf
+    }
+}
+
+// Entries of a Redis format listpack, with integers turned back into their decimal strings
+fn listpack_entries(pack: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let bad = || Error::msg("Bad RDB file: invalid listpack");
+    let mut reader = Reader::new(pack);
+    reader.bytes(6).map_err(|_| bad())?;
+    let mut entries = Vec::new();
+    loop {
+        let start = reader.pos;
+        let first = reader.byte().map_err(|_| bad())?;
+        let entry = match first {
+            0xff => break,
+            0x00..=0x7f => first.to_string().into_bytes(),
+            0x80..=0xbf => reader.bytes((first & 0x3f) as usize)?.to_vec(),
+            0xc0..=0xdf => {
+                let bits = (((first & 0x1f) as u16) << 8) | reader.byte()? as u16;
+                // Sign extend from 13 bits
+                (((bits << 3) as i16) >> 3).to_string().into_bytes()
+            }
+            0xe0..=0xef => {
+                let len = (((first & 0x0f) as usize) << 8) | reader.byte()? as usize;
+                reader.bytes(len)?.to_vec()
+            }
+            0xf0 => {
+                let len = u32::from_le_bytes(reader.array()?) as usize;
+                reader.bytes(len)?.to_vec()
+            }
+            0xf1 => i16::from_le_bytes(reader.array()?).to_string().into_bytes(),
+            0xf2 => {
+                let [a, b, c] = reader.array()?;
+                (i32::from_le_bytes([0, a, b, c]) >> 8).to_string().into_bytes()
+            }
+            0xf3 => i32::from_le_bytes(reader.array()?).to_string().into_bytes(),
+            0xf4 => i64::from_le_bytes(reader.array()?).to_string().into_bytes(),
+            _ => return Err(bad()),
+        };
+        reader.bytes(listpack_backlen_size(reader.pos - start))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+// Entries of a ziplist, the listpack's predecessor. Each entry starts with the previous
+// entry's length rather than ending with its own.
+fn ziplist_entries(ziplist: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let bad = || Error::msg("Bad RDB file: invalid ziplist");
+    let mut reader = Reader::new(ziplist);
+    reader.bytes(10).map_err(|_| bad())?;
+    let mut entries = Vec::new();
+    loop {
+        match reader.byte().map_err(|_| bad())? {
+            0xff => break,
+            0xfe => {
+                reader.bytes(4)?;
+            }
+            _ => {}
+        }
+        let first = reader.byte()?;
+        let entry = match first {
+            0x00..=0x3f => reader.bytes(first as usize)?.to_vec(),
+            0x40..=0x7f => {
+                let len = u16::from_be_bytes([first & 0x3f, reader.byte()?]) as usize;
+                reader.bytes(len)?.to_vec()
+            }
+            0x80 => {
+                let len = u32::from_be_bytes(reader.array()?) as usize;
+                reader.bytes(len)?.to_vec()
+            }
+            0xc0 => i16::from_le_bytes(reader.array()?).to_string().into_bytes(),
+            0xd0 => i32::from_le_bytes(reader.array()?).to_string().into_bytes(),
+            0xe0 => i64::from_le_bytes(reader.array()?).to_string().into_bytes(),
+            0xf0 => {
+                let [a, b, c] = reader.array()?;
+                (i32::from_le_bytes([0, a, b, c]) >> 8).to_string().into_bytes()
+            }
+            0xfe => (reader.byte()? as i8).to_string().into_bytes(),
+            // Small integers 0 to 12 live in the encoding byte itself
+            0xf1..=0xfd => (first - 0xf1).to_string().into_bytes(),
+            _ => return Err(bad()),
+        };
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+// Members of an intset: a width of 2, 4 or 8 bytes, a count, then the sorted integers
+fn intset_members(intset: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut reader = Reader::new(intset);
+    let width = u32::from_le_bytes(reader.array()?);
+    let len = u32::from_le_bytes(reader.array()?);
+    (0..len)
+        .map(|_| {
+            let member = match width {
+                2 => i16::from_le_bytes(reader.array()?) as i64,
+                4 => i32::from_le_bytes(reader.array()?) as i64,
+                8 => i64::from_le_bytes(reader.array()?),
+                _ => return Err(Error::msg("Bad RDB file: invalid intset")),
+            };
+            Ok(member.to_string().into_bytes())
+        })
+        .collect()
+}
+
+// Fields and values, alternating, of a zipmap, the oldest small hash encoding. Values may be
+// followed by free space.
+fn zipmap_entries(zipmap: &[u8]) -> Result<Vec<Vec<u8>>> {
+    fn zipmap_length(reader: &mut Reader) -> Result<Option<usize>> {
+        Ok(match reader.byte()? {
+            0xff => None,
+            0xfe => Some(u32::from_le_bytes(reader.array()?) as usize),
+            len => Some(len as usize),
+        })
+    }
+    let mut reader = Reader::new(zipmap);
+    reader.byte()?;
+    let mut entries = Vec::new();
+    while let Some(len) = zipmap_length(&mut reader)? {
+        entries.push(reader.bytes(len)?.to_vec());
+        let len = zipmap_length(&mut reader)?.ok_or_else(|| Error::msg("Bad RDB file: invalid zipmap"))?;
+        let free = reader.byte()?;
+        entries.push(reader.bytes(len)?.to_vec());
+        reader.bytes(free as usize)?;
+    }
+    Ok(entries)
+}
+
+fn parse_double(text: &[u8]) -> Result<f64> {
+    std::str::from_utf8(text).ok().and_then(|text| text.parse().ok()).ok_or_else(|| Error::msg("Bad RDB file: invalid score"))
+}
+
+// An expiry read from a file, or None once it has passed
+fn live_expiry(at: u64) -> Option<u64> {
+    (at > now_ms()).then_some(at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lzf_compress, lzf_decompress};
+
+    fn round_trip(input: &[u8]) {
+        let compressed = lzf_compress(input).expect("compressible input");
+        assert!(compressed.len() + 4 <= input.len());
+        assert_eq!(lzf_decompress(&compressed, input.len()).unwrap(), input);
+    }
+
+    #[test]
+    fn decompresses_fixed_payload() {
+        // A literal run of abc, then a back reference three bytes back producing nine more
+        let compressed = [0x02, b'a', b'b', b'c', 0xe0, 0x00, 0x02];
+        assert_eq!(lzf_decompress(&compressed, 12).unwrap(), b"abcabcabcabc");
+        // A short reference, two bytes more than its run of one, one byte back
+        let compressed = [0x00, b'x', 0x20, 0x00];
+        assert_eq!(lzf_decompress(&compressed, 4).unwrap(), b"xxxx");
+    }
+
+    #[test]
+    fn rejects_bad_payloads() {
+        // A reference before the start of the output
+        assert!(lzf_decompress(&[0x20, 0x00], 3).is_err());
+        // A literal run longer than what is left
+        assert!(lzf_decompress(&[0x05, b'a'], 6).is_err());
+        // The wrong length
+        assert!(lzf_decompress(&[0x00, b'x', 0x20, 0x00], 5).is_err());
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(b"abcabcabcabc");
+        round_trip(&[b'a'; 1000]);
+        round_trip("the quick brown fox jumps over the lazy dog. ".repeat(50).as_bytes());
+        // Literal runs longer than fit one run, between matches far apart
+        let noise: Vec<u8> = (0..5000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        let input: Vec<u8> = [&noise[..], &noise[..], &noise[..100]].concat();
+        round_trip(&input);
+    }
+
+    #[test]
+    fn gives_up_on_incompressible_input() {
+        assert_eq!(lzf_compress(b"abc"), None);
+        let noise: Vec<u8> = (0..200u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        assert_eq!(lzf_compress(&noise), None);
+    }
+}
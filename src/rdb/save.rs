@@ -0,0 +1,283 @@
+use std::{fs::File, io::Write, path::Path};
+
+use super::*;
+use crate::{
+    commands::{functions::Libraries, server::REDIS_VERSION},
+    persistent_map::PersistentMap,
+    state::{DataStoreValue, Value},
+    types::{
+        hash::Hash,
+        stream::{Stream, StreamId, STREAM_NODE_MAX_ENTRIES},
+    },
+};
+
+fn encode_aux(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.push(RDB_OPCODE_AUX);
+    encode_string(buf, name.as_bytes());
+    encode_string(buf, value.as_bytes());
+}
+
+fn encode_stream_id(buf: &mut Vec<u8>, id: StreamId) {
+    encode_length(buf, id.ms as usize);
+    encode_length(buf, id.seq as usize);
+}
+
+// IDs inside group metadata are written raw, big endian
+fn raw_stream_id(id: StreamId) -> [u8; 16] {
+    let mut raw = [0; 16];
+    raw[..8].copy_from_slice(&id.ms.to_be_bytes());
+    raw[8..].copy_from_slice(&id.seq.to_be_bytes());
+    raw
+}
+
+// Fields that expire are saved with their deadline relative to the earliest one, so hashes
+// without any keep the plain encoding
+fn encode_hash(buf: &mut Vec<u8>, hash: &Hash) -> u8 {
+    let min_expiry = hash.iter().filter_map(|(field, _)| hash.expiry(field)).min();
+    if let Some(min_expiry) = min_expiry {
+        buf.extend_from_slice(&min_expiry.to_le_bytes());
+    }
+    encode_length(buf, hash.len());
+    for (field, value) in hash.iter() {
+        if let Some(min_expiry) = min_expiry {
+            let ttl = hash.expiry(field).map_or(0, |expiry| expiry - min_expiry + 1);
+            encode_length(buf, ttl as usize);
+        }
+        encode_string(buf, field);
+        encode_string(buf, value);
+    }
+    if min_expiry.is_some() {
+        RDB_TYPE_HASH_METADATA
+    } else {
+        RDB_TYPE_HASH
+    }
+}
+
+// Entries are packed into listpacks of up to a node's worth each, every entry carrying its own
+// field names
+fn encode_stream(buf: &mut Vec<u8>, stream: &Stream) {
+    let entries = stream.range(StreamId::MIN, StreamId::MAX, false, usize::MAX);
+    let nodes: Vec<_> = entries.chunks(STREAM_NODE_MAX_ENTRIES).collect();
+    encode_length(buf, nodes.len());
+    for node in nodes {
+        let master = node[0].0;
+        encode_string(buf, &raw_stream_id(master));
+        let mut pack = ListpackWriter::new();
+        // Master entry: live and deleted counts, then no master fields
+        pack.push_int(node.len() as i64);
+        pack.push_int(0);
+        pack.push_int(0);
+        pack.push_int(0);
+        for (id, fields) in node {
+            pack.push_int(0);
+            pack.push_int(id.ms.wrapping_sub(master.ms) as i64);
+            pack.push_int(id.seq.wrapping_sub(master.seq) as i64);
+            pack.push_int(fields.len() as i64);
+            for (field, value) in fields {
+                pack.push_str(field);
+                pack.push_str(value);
+            }
+            pack.push_int(4 + 2 * fields.len() as i64);
+        }
+        encode_string(buf, &pack.finish());
+    }
+
+    encode_length(buf, stream.len());
+    encode_stream_id(buf, stream.last_id());
+    encode_stream_id(buf, stream.first_id());
+    encode_stream_id(buf, stream.max_deleted_id());
+    encode_length(buf, stream.entries_added() as usize);
+
+    let groups: Vec<_> = stream.groups().collect();
+    encode_length(buf, groups.len());
+    for (name, group) in groups {
+        encode_string(buf, name);
+        encode_stream_id(buf, group.last_delivered);
+        encode_length(buf, group.entries_read.map_or(usize::MAX, |read| read as usize));
+        encode_length(buf, group.pending.len());
+        for (id, entry) in group.pending.iter() {
+            buf.extend_from_slice(&raw_stream_id(*id));
+            buf.extend_from_slice(&entry.delivery_time.to_le_bytes());
+            encode_length(buf, entry.delivery_count as usize);
+        }
+        encode_length(buf, group.consumers.len());
+        for (name, consumer) in group.consumers.iter() {
+            encode_string(buf, name);
+            buf.extend_from_slice(&consumer.seen_time.to_le_bytes());
+            buf.extend_from_slice(&consumer.active_time.unwrap_or(u64::MAX).to_le_bytes());
+            encode_length(buf, consumer.pending.len());
+            for id in consumer.pending.iter() {
+                buf.extend_from_slice(&raw_stream_id(*id));
+            }
+        }
+    }
+}
+
+fn encode_module(buf: &mut Vec<u8>, name: &str, save: impl FnOnce(&mut ModuleWriter)) {
+    encode_length(buf, module_id(name) as usize);
+    save(&mut ModuleWriter { buf });
+    encode_length(buf, RDB_MODULE_OPCODE_EOF);
+}
+
+// Write a key's value, returning its type byte to go in front of the key
+fn encode_value(buf: &mut Vec<u8>, value: &Value) -> u8 {
+    match value {
+        Value::String(s) => {
+            encode_string(buf, s);
+            RDB_TYPE_STRING
+        }
+        Value::List(list) => {
+            encode_length(buf, list.len());
+            for item in list.iter() {
+                encode_string(buf, item);
+            }
+            RDB_TYPE_LIST
+        }
+        Value::Set(set) => {
+            let members = set.members();
+            encode_length(buf, members.len());
+            for member in members {
+                encode_string(buf, &member);
+            }
+            RDB_TYPE_SET
+        }
+        Value::SortedSet(zset) => {
+            encode_length(buf, zset.len());
+            for (member, score) in zset.iter() {
+                encode_string(buf, member);
+                buf.extend_from_slice(&score.to_le_bytes());
+            }
+            RDB_TYPE_ZSET_2
+        }
+        Value::Hash(hash) => encode_hash(buf, hash),
+        Value::Stream(stream) => {
+            encode_stream(buf, stream);
+            RDB_TYPE_STREAM_LISTPACKS_3
+        }
+        Value::Bloom(bloom) => {
+            encode_module(buf, MODULE_BLOOM, |out| bloom.rdb_save(out));
+            RDB_TYPE_MODULE_2
+        }
+        Value::CountMinSketch(cms) => {
+            encode_module(buf, MODULE_CMS, |out| cms.rdb_save(out));
+            RDB_TYPE_MODULE_2
+        }
+        Value::TopK(topk) => {
+            encode_module(buf, MODULE_TOPK, |out| topk.rdb_save(out));
+            RDB_TYPE_MODULE_2
+        }
+        Value::Json(json) => {
+            encode_module(buf, MODULE_JSON, |out| json.rdb_save(out));
+            RDB_TYPE_MODULE_2
+        }
+        Value::TimeSeries(series) => {
+            encode_module(buf, MODULE_TIMESERIES, |out| series.rdb_save(out));
+            RDB_TYPE_MODULE_2
+        }
+    }
+}
+
+// A value in the DUMP format: its type and encoding as in an RDB file, followed by the RDB
+// version and a checksum of it all
+pub fn dump_value(value: &Value) -> Vec<u8> {
+    let mut payload = vec![0];
+    payload[0] = encode_value(&mut payload, value);
+    payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let crc = crc64(0, &payload);
+    payload.extend_from_slice(&crc.to_le_bytes());
+    payload
+}
+
+// Encode the keyspace and function libraries as an RDB file. Without a checksum the trailer is
+// zero, which loaders take to mean there is nothing to verify.
+pub fn serialize(datastore: &PersistentMap<Vec<u8>, DataStoreValue>, libraries: &Libraries, checksum: bool) -> Vec<u8> {
+    let mut out = Vec::new();
+    serialize_into(datastore, libraries, checksum, |piece| {
+        out.extend_from_slice(piece);
+        true
+    });
+    out
+}
+
+// How much is encoded before it is handed out
+const PIECE_SIZE: usize = 64 << 10;
+
+// Encode the RDB file in pieces handed to `out` as they are done, so it never has to be held
+// whole. Stops early, returning false, once `out` does.
+pub fn serialize_into(
+    datastore: &PersistentMap<Vec<u8>, DataStoreValue>,
+    libraries: &Libraries,
+    checksum: bool,
+    mut out: impl FnMut(&[u8]) -> bool,
+) -> bool {
+    let mut crc = 0;
+    let mut hand_out = |buf: &mut Vec<u8>| {
+        if checksum {
+            crc = crc64(crc, buf);
+        }
+        let taken = out(buf);
+        buf.clear();
+        taken
+    };
+    let mut buf = format!("REDIS{:04}", RDB_VERSION).into_bytes();
+    encode_aux(&mut buf, "redis-ver", REDIS_VERSION);
+    encode_aux(&mut buf, "redis-bits", "64");
+    encode_aux(&mut buf, "ctime", &(unix_time_ms() / 1000).to_string());
+    encode_aux(&mut buf, "aof-base", "0");
+    for library in libraries.values() {
+        buf.push(RDB_OPCODE_FUNCTION2);
+        encode_string(&mut buf, &library.code);
+    }
+
+    let keys: Vec<_> = datastore.iter().filter(|(_, dsv)| !dsv.is_expired()).collect();
+    if !keys.is_empty() {
+        buf.push(RDB_OPCODE_SELECTDB);
+        encode_length(&mut buf, 0);
+        buf.push(RDB_OPCODE_RESIZEDB);
+        encode_length(&mut buf, keys.len());
+        encode_length(&mut buf, keys.iter().filter(|(_, dsv)| dsv.expiry.is_some()).count());
+    }
+    let mut value = Vec::new();
+    for (key, dsv) in keys {
+        if let Some(expiry) = dsv.expiry {
+            buf.push(RDB_OPCODE_EXPIRETIME_MS);
+            buf.extend_from_slice(&expiry.to_le_bytes());
+        }
+        value.clear();
+        buf.push(encode_value(&mut value, &dsv.value));
+        encode_string(&mut buf, key);
+        buf.extend_from_slice(&value);
+        if buf.len() >= PIECE_SIZE && !hand_out(&mut buf) {
+            return false;
+        }
+    }
+
+    buf.push(RDB_OPCODE_EOF);
+    if !hand_out(&mut buf) {
+        return false;
+    }
+    let crc = if checksum { crc } else { 0 };
+    out(&crc.to_le_bytes())
+}
+
+// Write through a temporary file in the same directory that is renamed over the old dump once
+// it is safely on disk, so a crash part way leaves either the old dump or the new one. The
+// directory is fsynced too, or the rename itself could be lost.
+pub fn write_file(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let temp = path.with_file_name(format!("temp-{}.rdb", std::process::id()));
+    let result = File::create(&temp).and_then(|mut file| {
+        file.write_all(data)?;
+        file.sync_all()
+    });
+    if let Err(e) = result.and_then(|_| std::fs::rename(&temp, path)) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(e);
+    }
+    sync_dir(path.parent().unwrap_or(Path::new(".")))
+}
+
+// Make renames and newly created files in a directory survive a crash
+pub fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    File::open(dir)?.sync_all()
+}
@@ -0,0 +1,48 @@
+use std::collections::VecDeque;
+
+// Smallest backlog repl-backlog-size can ask for
+pub const MIN_BACKLOG_SIZE: usize = 16 << 10;
+
+// The end of the replication stream, kept so a replica that lost its link can be sent just what
+// it missed instead of the whole dataset again. It holds the last `size` bytes.
+pub struct Backlog {
+    buf: VecDeque<u8>,
+    size: usize,
+}
+
+impl Backlog {
+    pub fn new(size: usize) -> Self {
+        Backlog { buf: VecDeque::new(), size }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // Bytes kept, which end at the stream's offset
+    pub fn histlen(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buf.extend(bytes);
+        self.trim();
+    }
+
+    // A smaller backlog drops its oldest bytes straight away, a larger one keeps them all
+    pub fn resize(&mut self, size: usize) {
+        self.size = size;
+        self.trim();
+    }
+
+    // The last `len` bytes, None when fewer than that are kept
+    pub fn tail(&self, len: usize) -> Option<Vec<u8>> {
+        let start = self.buf.len().checked_sub(len)?;
+        Some(self.buf.range(start..).copied().collect())
+    }
+
+    fn trim(&mut self) {
+        let excess = self.buf.len().saturating_sub(self.size);
+        self.buf.drain(..excess);
+    }
+}
@@ -0,0 +1,217 @@
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use tokio::{
+    sync::{watch, RwLock},
+    task::JoinHandle,
+    time::{self, Duration, Instant},
+};
+
+use crate::{
+    command::{not_an_integer, parse_integer_arg, syntax_error, Command},
+    resp::DataType,
+    state::State,
+};
+
+use super::{master::{ReplicaLink, ReplicaState}, replicate};
+
+// How often a failover checks whether a replica has caught up
+const FAILOVER_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailoverState {
+    // Writes are held until a replica has acknowledged all of the write stream
+    WaitingForSync,
+    // This server has become a replica of the one chosen, asking it to take over
+    InProgress,
+}
+
+// A FAILOVER under way on a master, run by a task of its own. Commands that may write wait for
+// it to be dropped, however it ends.
+pub struct Failover {
+    pub state: FailoverState,
+    // The replica to hand over to, the first to catch up when none was given
+    target: Option<(String, u16)>,
+    force: bool,
+    deadline: Option<Instant>,
+    held: watch::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for Failover {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Command {
+    // FAILOVER [TO host port [FORCE]] [TIMEOUT milliseconds], or FAILOVER ABORT on its own
+    pub fn parse_failover(args: &[Vec<u8>]) -> Command {
+        if args.len() == 2 && args[1].eq_ignore_ascii_case(b"abort") {
+            return Command::FAILOVERABORT;
+        }
+        let (mut target, mut force, mut timeout) = (None, false, None);
+        let mut i = 1;
+        while i < args.len() {
+            let more = args.len() - i - 1;
+            match args[i].to_ascii_lowercase().as_slice() {
+                b"to" if target.is_none() && more >= 2 => {
+                    let Some(port) = parse_integer_arg::<u16>(&args[i + 2]) else {
+                        return not_an_integer();
+                    };
+                    target = Some((String::from_utf8_lossy(&args[i + 1]).to_string(), port));
+                    i += 2;
+                }
+                b"timeout" if timeout.is_none() && more >= 1 => {
+                    match parse_integer_arg::<i64>(&args[i + 1]) {
+                        Some(ms) if ms <= 0 => return Command::INVALID("ERR FAILOVER timeout must be greater than 0".to_string()),
+                        Some(ms) => timeout = Some(Duration::from_millis(ms as u64)),
+                        None => return not_an_integer(),
+                    }
+                    i += 1;
+                }
+                b"force" if !force => force = true,
+                _ => return syntax_error(),
+            }
+            i += 1;
+        }
+        if force && (target.is_none() || timeout.is_none()) {
+            return Command::INVALID("ERR FAILOVER with force option requires both a timeout and target HOST and IP.".to_string());
+        }
+        Command::FAILOVER(target, force, timeout)
+    }
+
+    // Commands a failover holds up, as they may write. Scripts and transactions are only known
+    // to write once they run.
+    pub fn is_held_by_failover(&self) -> bool {
+        self.is_write() || matches!(self, Command::EXEC | Command::EVAL(..) | Command::EVALSHA(..) | Command::FCALL(..))
+    }
+}
+
+// Where a replica takes clients, which is how FAILOVER TO names it
+fn replica_address(replica: &ReplicaLink) -> Option<(String, u16)> {
+    Some((replica.ip?.to_string(), replica.listening_port?))
+}
+
+impl State {
+    pub fn failover_state(&self) -> Option<FailoverState> {
+        self.replication.failover.as_ref().map(|failover| failover.state)
+    }
+
+    // A replica to hand over to that has acknowledged all of the write stream
+    fn caught_up_replica(&self, target: &Option<(String, u16)>) -> Option<(String, u16)> {
+        let offset = self.propagation.repl_offset();
+        self.replication.replicas.iter()
+            .filter(|replica| replica.state == ReplicaState::Online && replica.ack_offset == offset)
+            .filter_map(replica_address)
+            .find(|address| target.as_ref().is_none_or(|target| target.0.eq_ignore_ascii_case(&address.0) && target.1 == address.1))
+    }
+
+    // Give up on a failover, letting the writes it held go ahead. Once this server has become
+    // a replica it goes back to being a master, with a history of its own.
+    pub fn abort_failover(&mut self, reason: &str) {
+        if let Some(failover) = self.replication.failover.take() {
+            println!("FAILOVER aborted: {}", reason);
+            if failover.state == FailoverState::InProgress {
+                self.stop_replicating();
+            }
+        }
+    }
+
+    // The replica took over and this server now replicates it, so the writes held can go
+    // ahead, against it
+    pub fn complete_failover(&mut self) {
+        if self.failover_state() == Some(FailoverState::InProgress) {
+            self.replication.failover = None;
+            println!("FAILOVER to the new master completed");
+        }
+    }
+
+    pub fn failover_abort(&mut self) -> DataType {
+        if self.replication.failover.is_none() {
+            return DataType::SimpleError("ERR No failover in progress.".to_string());
+        }
+        self.abort_failover("Failover manually aborted");
+        DataType::ok()
+    }
+}
+
+// Hold a command that may write until a failover under way is over, one way or the other
+pub async fn failover_finished(state: &RwLock<State>) {
+    let mut held = match &state.read().await.replication.failover {
+        Some(failover) => failover.held.subscribe(),
+        None => return,
+    };
+    while held.changed().await.is_ok() {}
+}
+
+// FAILOVER hands this master's role to one of its replicas without losing writes. Writes are
+// held until the replica has all of them, then this server becomes its replica and asks it to
+// take over as part of PSYNC. Without FORCE, a replica that doesn't catch up within the timeout
+// ends the failover.
+// Boxed, like REPLICAOF, as the master link it starts runs commands.
+pub fn failover(state: &Arc<RwLock<State>>, target: Option<(String, u16)>, force: bool, timeout: Option<Duration>) -> BoxFuture<'_, DataType> {
+    async move {
+        let mut guard = state.write().await;
+        let error = |msg: &str| DataType::SimpleError(format!("ERR {}", msg));
+        if guard.cluster.is_some() {
+            return error("FAILOVER not allowed in cluster mode.");
+        }
+        if guard.replication.master.is_some() {
+            return error("FAILOVER is not valid when server is a replica.");
+        }
+        if guard.replication.replicas.is_empty() {
+            return error("FAILOVER requires connected replicas.");
+        }
+        if guard.replication.failover.is_some() {
+            return error("FAILOVER already in progress.");
+        }
+        if let Some((host, port)) = &target {
+            let replica = guard.replication.replicas.iter()
+                .find(|replica| replica_address(replica).is_some_and(|(ip, listening_port)| ip.eq_ignore_ascii_case(host) && listening_port == *port));
+            match replica {
+                None => return error("FAILOVER target HOST and PORT is not a replica."),
+                Some(replica) if replica.state != ReplicaState::Online => return error("FAILOVER target replica is not online."),
+                Some(_) => {}
+            }
+        }
+        let task = tokio::spawn(run_failover(state.clone()));
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let held = watch::channel(()).0;
+        guard.replication.failover = Some(Failover { state: FailoverState::WaitingForSync, target, force, deadline, held, task });
+        DataType::ok()
+    }.boxed()
+}
+
+// Wait for a replica to catch up, then replicate it. Its link completes the failover once the
+// replica has taken over, which ends this task.
+async fn run_failover(state: Arc<RwLock<State>>) {
+    let mut interval = time::interval(FAILOVER_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let mut guard = state.write().await;
+        let Some(failover) = &guard.replication.failover else {
+            return;
+        };
+        let timed_out = failover.deadline.is_some_and(|deadline| Instant::now() >= deadline);
+        match failover.state {
+            FailoverState::WaitingForSync => {
+                let (target, force) = (failover.target.clone(), failover.force);
+                let (host, port) = match guard.caught_up_replica(&target) {
+                    Some(address) => address,
+                    None if timed_out && force => target.unwrap(),
+                    None if timed_out => return guard.abort_failover("Replica never caught up before timeout"),
+                    None => continue,
+                };
+                println!("FAILOVER to {}:{}, becoming its replica", host, port);
+                if let Some(failover) = guard.replication.failover.as_mut() {
+                    failover.state = FailoverState::InProgress;
+                }
+                drop(guard);
+                replicate(&state, host, port).await;
+            }
+            FailoverState::InProgress if timed_out => return guard.abort_failover("Failover target took too long"),
+            FailoverState::InProgress => {}
+        }
+    }
+}
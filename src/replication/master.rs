@@ -0,0 +1,260 @@
+use std::{net::IpAddr, sync::Arc};
+
+use bytes::Bytes;
+use tokio::{
+    sync::{mpsc::UnboundedReceiver, RwLock},
+    task::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    client::{Client, ClientHandle},
+    propagate::Chunk,
+    rdb,
+    resp::DataType,
+    state::State,
+};
+
+use super::{new_replid, LinkState};
+
+// How far a replica has got with the dataset it is being sent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplicaState {
+    // The dataset is being encoded and sent
+    Sending,
+    // The dataset is sent and the write stream follows it
+    Online,
+}
+
+// A replica of this server, fed the dataset and then the write stream over its connection by a
+// task of its own
+pub struct ReplicaLink {
+    pub id: u64,
+    pub ip: Option<IpAddr>,
+    // The port the replica takes clients on, which it gives with REPLCONF listening-port
+    pub listening_port: Option<u16>,
+    pub state: ReplicaState,
+    // How much of the write stream the replica has acknowledged applying, and when it last did
+    pub ack_offset: u64,
+    pub ack_time: Instant,
+    handle: ClientHandle,
+    task: JoinHandle<()>,
+}
+
+// Dropping a replica also closes its connection, for when it is dropped for something other
+// than having gone away
+impl Drop for ReplicaLink {
+    fn drop(&mut self) {
+        self.task.abort();
+        self.handle.close();
+    }
+}
+
+impl ReplicaLink {
+    // A replica's slaveN line in INFO replication
+    pub fn info(&self) -> String {
+        let ip = self.ip.map_or("?".to_string(), |ip| ip.to_string());
+        let state = match self.state {
+            ReplicaState::Sending => "send_bulk",
+            ReplicaState::Online => "online",
+        };
+        let (port, lag) = (self.listening_port.unwrap_or(0), self.ack_time.elapsed().as_secs());
+        format!("ip={},port={},state={},offset={},lag={}", ip, port, state, self.ack_offset, lag)
+    }
+}
+
+impl State {
+    pub fn is_replica_client(&self, id: u64) -> bool {
+        self.replication.replicas.iter().any(|replica| replica.id == id)
+    }
+
+    // REPLCONF ACK from a replica, anyone else sending it is ignored
+    pub fn replica_ack(&mut self, id: u64, offset: u64) {
+        if let Some(replica) = self.replication.replicas.iter_mut().find(|replica| replica.id == id) {
+            replica.ack_offset = replica.ack_offset.max(offset);
+            replica.ack_time = Instant::now();
+        }
+    }
+
+    // A replica is only expected to acknowledge anything once it has the dataset, so the time
+    // it is given to starts then
+    fn set_replica_state(&mut self, id: u64, replica_state: ReplicaState) {
+        if let Some(replica) = self.replication.replicas.iter_mut().find(|replica| replica.id == id) {
+            replica.state = replica_state;
+            replica.ack_time = Instant::now();
+        }
+    }
+
+    // Run every second. A master pings its replicas through the write stream every
+    // repl-ping-replica-period, so they hear from it when nothing is written, while a replica
+    // passes on its master's pings instead. Replicas that have acknowledged nothing for longer
+    // than repl-timeout are dropped.
+    pub fn replication_cron(&mut self) {
+        let timeout = Duration::from_secs(self.config.replication.timeout);
+        self.replication.replicas.retain(|replica| {
+            let timed_out = replica.state == ReplicaState::Online && replica.ack_time.elapsed() > timeout;
+            if timed_out {
+                let ip = replica.ip.map_or("?".to_string(), |ip| ip.to_string());
+                println!("Disconnecting timed out replica {}:{}", ip, replica.listening_port.unwrap_or(0));
+            }
+            !timed_out
+        });
+        let period = Duration::from_secs(self.config.replication.ping_period);
+        // A failover waits for replicas to reach an offset, which pings would move on
+        if self.replication.master.is_some()
+            || self.replication.replicas.is_empty()
+            || self.replication.failover.is_some()
+            || self.replication.last_ping.elapsed() < period
+        {
+            return;
+        }
+        self.replication.last_ping = Instant::now();
+        let mut ping = Vec::new();
+        DataType::bulk_array([b"PING".to_vec()]).serialize_into(&mut ping, false);
+        self.propagation.relay(Bytes::from(ping));
+    }
+
+    // Forget a replica whose connection has closed, stopping its task
+    pub fn remove_replica(&mut self, id: u64) {
+        self.replication.replicas.retain(|replica| replica.id != id);
+    }
+
+    // Disconnect the replicas, once the history of the dataset they have has been left behind
+    pub fn drop_replicas(&mut self) {
+        self.replication.replicas.clear();
+    }
+
+    // Pass on what the master sent to our own replicas once the commands in it have been
+    // applied, under the same lock, so a replica syncing with us gets a dataset that matches
+    // the offset it is given. A transaction goes out with its EXEC.
+    pub fn relay_master_stream(&mut self, client: &mut Client) {
+        if client.master && client.transaction.is_none() && !client.unrelayed.is_empty() {
+            let payload = Bytes::from(std::mem::take(&mut client.unrelayed));
+            self.propagation.relay(payload);
+        }
+    }
+}
+
+impl State {
+    // The part of the stream a replica asking to continue from an offset of a history missed.
+    // The offset is of the next byte it wants. The history has to be ours, or the one before
+    // it up to where ours starts.
+    fn missed_by_replica(&self, replid: &str, offset: i64) -> Option<Vec<u8>> {
+        let ours = replid == self.replication.replid;
+        let previous = replid == self.replication.replid2 && offset <= self.replication.second_replid_offset;
+        if !(ours || previous) || offset < 1 {
+            return None;
+        }
+        self.propagation.backlog_since(offset as u64 - 1)
+    }
+}
+
+// PSYNC makes the connection a replica. One that has a history of ours up to a point the
+// backlog still has is sent what it missed. Otherwise it is sent the whole dataset: the
+// snapshot and the write stream after it are taken together under the lock, and the reply
+// gives the offset the stream continues from. Returns the error a replica that can't be
+// served is given.
+// A master failing over to us asks to continue with the history we have from it, once we have
+// taken over from it, which needs it to be the same history as ours.
+pub async fn psync(client: &Client, ip: Option<IpAddr>, replid: &str, offset: i64, failover: bool, state: &Arc<RwLock<State>>) -> Option<DataType> {
+    let mut guard = state.write().await;
+    if guard.is_replica_client(client.id) {
+        return None;
+    }
+    if failover {
+        if replid != guard.replication.replid {
+            return Some(DataType::SimpleError("ERR PSYNC FAILOVER replid must match my replid.".to_string()));
+        }
+        guard.stop_replicating();
+        println!("Taking over as master, by failover request from {}", ip.map_or("?".to_string(), |ip| ip.to_string()));
+    }
+    if guard.replication.failover.is_some() {
+        return Some(DataType::SimpleError("NOMASTERLINK Can't SYNC while failing over".to_string()));
+    }
+    // A replica's dataset is only worth passing on once it has its master's
+    if guard.replication.master.as_ref().is_some_and(|master| master.state != LinkState::Connected) {
+        return Some(DataType::SimpleError("NOMASTERLINK Can't SYNC while not connected with my master".to_string()));
+    }
+    guard.flush_propagation();
+    let backlog_size = guard.config.replication.backlog_size;
+    let stream = guard.propagation.subscribe_replica(backlog_size);
+    let (id, listening_port, handle) = (client.id, client.listening_port, client.handle());
+    let (task, replica_state) = match guard.missed_by_replica(replid, offset) {
+        Some(missed) => {
+            let reply = format!("CONTINUE {}", guard.replication.replid);
+            if client.send(DataType::SimpleString(reply)).is_err() || !handle.send_raw(Bytes::from(missed)) {
+                return None;
+            }
+            (tokio::spawn(stream_to_replica(handle.clone(), stream, state.clone())), ReplicaState::Online)
+        }
+        None => {
+            let reply = format!("FULLRESYNC {} {}", guard.replication.replid, guard.propagation.repl_offset());
+            if client.send(DataType::SimpleString(reply)).is_err() {
+                return None;
+            }
+            let transfer = guard.dataset_transfer(client);
+            (tokio::spawn(feed_replica(id, handle.clone(), transfer, stream, state.clone())), ReplicaState::Sending)
+        }
+    };
+    let ack_time = Instant::now();
+    guard.replication.replicas.push(ReplicaLink { id, ip, listening_port, state: replica_state, ack_offset: 0, ack_time, handle, task });
+    None
+}
+
+// Sends a snapshot of the dataset to a replica on a blocking task, returning whether the replica
+// is still there
+type Transfer = Box<dyn FnOnce(&ClientHandle) -> bool + Send>;
+
+impl State {
+    // How a replica is sent the dataset, as a bulk string without the trailing CRLF. With
+    // repl-diskless-sync it is streamed as it is encoded, framed by a random mark it ends with
+    // since its length isn't known up front. That needs a replica that understands the mark.
+    // Otherwise it is saved to the dump file first, like a BGSAVE, unless one is already
+    // running, and sent with its length.
+    fn dataset_transfer(&mut self, client: &Client) -> Transfer {
+        let (datastore, libraries, checksum) = (self.datastore.clone(), self.libraries.clone(), self.config.rdb.checksum);
+        if self.config.replication.diskless_sync && client.capabilities.iter().any(|capa| capa == "eof") {
+            return Box::new(move |handle| {
+                let mark = new_replid();
+                handle.send_raw(Bytes::from(format!("$EOF:{}\r\n", mark)))
+                    && rdb::serialize_into(&datastore, &libraries, checksum, |piece| handle.send_raw(Bytes::copy_from_slice(piece)))
+                    && handle.send_raw(Bytes::from(mark))
+            });
+        }
+        let save = self.start_bgsave().then(|| (self.dump_path(), self.save_status.clone()));
+        Box::new(move |handle| {
+            let dataset = rdb::serialize(&datastore, &libraries, checksum);
+            if let Some((path, status)) = save {
+                let result = rdb::write_file(&path, &dataset);
+                if let Err(e) = &result {
+                    println!("Saving {} for a replica failed: {}", path.display(), e);
+                }
+                status.lock().unwrap().finish_bgsave(result.is_ok());
+            }
+            handle.send_raw(Bytes::from(format!("${}\r\n", dataset.len()))) && handle.send_raw(Bytes::from(dataset))
+        })
+    }
+}
+
+// Send the dataset, then the write stream from where the snapshot was taken
+async fn feed_replica(id: u64, handle: ClientHandle, transfer: Transfer, stream: UnboundedReceiver<Chunk>, state: Arc<RwLock<State>>) {
+    let sender = handle.clone();
+    if !matches!(task::spawn_blocking(move || transfer(&sender)).await, Ok(true)) {
+        return;
+    }
+    state.write().await.set_replica_state(id, ReplicaState::Online);
+    stream_to_replica(handle, stream, state).await
+}
+
+// Pass on the write stream for as long as the replica keeps up
+async fn stream_to_replica(handle: ClientHandle, mut stream: UnboundedReceiver<Chunk>, state: Arc<RwLock<State>>) {
+    while let Some(chunk) = stream.recv().await {
+        if !handle.send_raw(chunk.payload) {
+            return;
+        }
+        let limit = state.read().await.config.output_buffer_limits.replica;
+        if handle.enforce_limit(&limit) {
+            return;
+        }
+    }
+}
@@ -0,0 +1,429 @@
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use tokio::{
+    sync::RwLock,
+    task::JoinHandle,
+    time::Instant,
+};
+
+use crate::{
+    client::Client,
+    command::{not_an_integer, parse_integer_arg, syntax_error, wrong_number_of_args, Command},
+    random::random_hex_id,
+    resp::DataType,
+    state::State,
+};
+
+pub mod backlog;
+pub mod failover;
+pub mod master;
+mod replica;
+
+const READONLY_ERROR: &str = "READONLY You can't write against a read only replica.";
+const NOREPLICAS_ERROR: &str = "NOREPLICAS Not enough good replicas to write.";
+const MASTERDOWN_ERROR: &str = "MASTERDOWN Link with MASTER is down and replica-serve-stale-data is set to 'no'.";
+const SYNC_ERROR: &str = "SYNC with master in progress";
+
+// A random id for a history of the dataset
+pub fn new_replid() -> String {
+    random_hex_id()
+}
+
+// How far a replica has got with its master
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinkState {
+    Connecting,
+    Handshake,
+    Syncing,
+    Connected,
+}
+
+// The connection a replica keeps to its master, run by a task of its own
+pub struct MasterLink {
+    pub host: String,
+    pub port: u16,
+    pub state: LinkState,
+    // Id of the link's connection once it is applying the master's writes
+    pub client: Option<u64>,
+    // When anything last came from the master
+    pub last_io: Instant,
+    // When the link last went down, None if it has never been up
+    pub down_since: Option<Instant>,
+    // Size of the snapshot being received, when the master gave it, and how much has come
+    pub sync_total: Option<u64>,
+    pub sync_read: u64,
+    task: JoinHandle<()>,
+}
+
+pub struct Replication {
+    // The master this server replicates, None while it is a master itself
+    pub master: Option<MasterLink>,
+    // The history of the dataset this server has, a master's own or the one it replicates
+    pub replid: String,
+    // The history it had before, which its stream follows on from up to the offset where the
+    // current one starts, so replicas of the old history can continue with the new one
+    pub replid2: String,
+    pub second_replid_offset: i64,
+    // Replicas of this server, which may itself be a replica
+    pub replicas: Vec<master::ReplicaLink>,
+    // When the replicas were last pinged
+    pub last_ping: Instant,
+    pub failover: Option<failover::Failover>,
+}
+
+impl Default for Replication {
+    fn default() -> Self {
+        Replication {
+            master: None,
+            replid: new_replid(),
+            replid2: "0".repeat(40),
+            second_replid_offset: -1,
+            replicas: Vec::new(),
+            last_ping: Instant::now(),
+            failover: None,
+        }
+    }
+}
+
+impl Replication {
+    // Start a new history from the offset the stream has got to, keeping the old one as the
+    // second, as happens when a replica becomes a master
+    pub fn shift_replid(&mut self, replid: String, offset: u64) {
+        self.replid2 = std::mem::replace(&mut self.replid, replid);
+        self.second_replid_offset = offset as i64 + 1;
+    }
+
+    // A full sync starts a history with nothing before it
+    pub fn set_replid(&mut self, replid: String) {
+        self.replid = replid;
+        self.replid2 = "0".repeat(40);
+        self.second_replid_offset = -1;
+    }
+}
+
+impl Drop for MasterLink {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Command {
+    // REPLCONF option value [option value ...], how a replica tells its master about itself.
+    // GETACK is the master asking how far its replica has got.
+    pub fn parse_replconf(args: &[Vec<u8>]) -> Command {
+        let pairs = args[1..].chunks_exact(2);
+        if !pairs.remainder().is_empty() {
+            return syntax_error();
+        }
+        let (mut listening_port, mut capabilities) = (None, Vec::new());
+        for pair in pairs {
+            match String::from_utf8_lossy(&pair[0]).to_lowercase().as_str() {
+                "getack" => return Command::REPLCONFGETACK,
+                // Any FACK that follows is for AOF offsets, which replicas of this server don't report
+                "ack" => match parse_integer_arg::<u64>(&pair[1]) {
+                    Some(offset) => return Command::REPLCONFACK(offset),
+                    None => return not_an_integer(),
+                },
+                "listening-port" => match parse_integer_arg::<u16>(&pair[1]) {
+                    Some(port) => listening_port = Some(port),
+                    None => return not_an_integer(),
+                },
+                "capa" => capabilities.push(String::from_utf8_lossy(&pair[1]).to_lowercase()),
+                "ip-address" | "rdb-only" | "rdb-filter-only" => {}
+                option => return Command::INVALID(format!("ERR Unrecognized REPLCONF option: {}", option)),
+            }
+        }
+        Command::REPLCONF(listening_port, capabilities)
+    }
+
+    // PSYNC replid offset, asking to continue from an offset of a history of the dataset, or
+    // with ? and -1 for all of it. A master failing over to us adds FAILOVER.
+    pub fn parse_psync(args: &[Vec<u8>]) -> Command {
+        let failover = match args.len() {
+            3 => false,
+            4 if args[3].eq_ignore_ascii_case(b"failover") => true,
+            4 => return syntax_error(),
+            _ => return wrong_number_of_args("psync"),
+        };
+        match parse_integer_arg::<i64>(&args[2]) {
+            Some(offset) => Command::PSYNC(String::from_utf8_lossy(&args[1]).to_string(), offset, failover),
+            None => not_an_integer(),
+        }
+    }
+
+    // REPLICAOF host port, or NO ONE to stop replicating. SLAVEOF is the old name.
+    pub fn parse_replicaof(name: &str, args: &[Vec<u8>]) -> Command {
+        if args.len() != 3 {
+            return wrong_number_of_args(name);
+        }
+        if args[1].eq_ignore_ascii_case(b"no") && args[2].eq_ignore_ascii_case(b"one") {
+            return Command::REPLICAOF(None);
+        }
+        match parse_integer_arg::<u16>(&args[2]) {
+            Some(port) => Command::REPLICAOF(Some((String::from_utf8_lossy(&args[1]).to_string(), port))),
+            None => not_an_integer(),
+        }
+    }
+
+    // Commands a replica without an up to date dataset still answers, as they don't read it
+    fn is_allowed_stale(&self) -> bool {
+        self.is_connection()
+            || matches!(
+                self,
+                Command::PING
+                    | Command::QUIT
+                    | Command::HELLO(..)
+                    | Command::MULTI
+                    | Command::EXEC
+                    | Command::DISCARD
+                    | Command::INFO(_)
+                    | Command::CONFIGGET(_)
+                    | Command::CONFIGSET(_)
+                    | Command::LASTSAVE
+                    | Command::REPLCONF(..)
+                    | Command::REPLICAOF(_)
+                    | Command::FAILOVER(..)
+                    | Command::FAILOVERABORT
+                    | Command::PUBLISH(..)
+                    | Command::SPUBLISH(..)
+                    | Command::PUBSUBCHANNELS(..)
+                    | Command::PUBSUBNUMSUB(..)
+                    | Command::PUBSUBNUMPAT
+            )
+    }
+}
+
+impl State {
+    // A read-only replica's dataset is only changed by its master. Messages aren't part of the
+    // dataset, so they can still be published.
+    pub fn refuses_write(&self, cmd: &Command) -> bool {
+        self.replication.master.is_some()
+            && self.config.replication.read_only
+            && cmd.is_write()
+            && !matches!(cmd, Command::PUBLISH(..) | Command::SPUBLISH(..))
+    }
+
+    // Replicas that have the dataset and have acknowledged it within min-replicas-max-lag
+    pub fn good_replicas(&self) -> usize {
+        let max_lag = self.config.replication.min_replicas_max_lag;
+        self.replication.replicas.iter()
+            .filter(|replica| replica.state == master::ReplicaState::Online && replica.ack_time.elapsed().as_secs() <= max_lag)
+            .count()
+    }
+
+    // A master with min-replicas-to-write only takes writes while that many replicas keep up
+    // with it, which bounds what a write acknowledged to a client can be lost to
+    fn lacks_good_replicas(&self, cmd: &Command) -> bool {
+        let options = &self.config.replication;
+        self.replication.master.is_none()
+            && options.min_replicas_to_write > 0
+            && options.min_replicas_max_lag > 0
+            && cmd.is_write()
+            && !matches!(cmd, Command::PUBLISH(..) | Command::SPUBLISH(..))
+            && self.good_replicas() < options.min_replicas_to_write
+    }
+
+    // Why a write can't be made here, whether by a client or a script
+    pub fn write_rejection(&self, cmd: &Command) -> Option<&'static str> {
+        if self.refuses_write(cmd) {
+            return Some(READONLY_ERROR);
+        }
+        self.lacks_good_replicas(cmd).then_some(NOREPLICAS_ERROR)
+    }
+
+    // Why a client's command won't be run: it would write where writes can't be made, or read
+    // a dataset that may be out of date on a replica with replica-serve-stale-data off
+    pub fn replication_rejection(&self, cmd: &Command) -> Option<&'static str> {
+        if let Some(rejection) = self.write_rejection(cmd) {
+            return Some(rejection);
+        }
+        let master = self.replication.master.as_ref()?;
+        match master.state {
+            LinkState::Connected => None,
+            _ if self.config.replication.serve_stale_data || cmd.is_allowed_stale() => None,
+            LinkState::Syncing => Some(SYNC_ERROR),
+            _ => Some(MASTERDOWN_ERROR),
+        }
+    }
+
+    pub fn set_link_state(&mut self, link_state: LinkState) {
+        if let Some(master) = self.replication.master.as_mut() {
+            if master.state == LinkState::Connected && link_state != LinkState::Connected {
+                master.down_since = Some(Instant::now());
+            }
+            if link_state == LinkState::Syncing {
+                (master.sync_total, master.sync_read) = (None, 0);
+            }
+            master.state = link_state;
+            master.last_io = Instant::now();
+        }
+    }
+
+    // Only the master is answered, anyone else asking gets nothing
+    pub fn replconf_getack(&self, client: &Client) -> Vec<DataType> {
+        match client.master {
+            true => self.replconf_ack().into_iter().collect(),
+            false => Vec::new(),
+        }
+    }
+
+    // Tells the master how much of its write stream has been applied, which is what has been
+    // passed on to our own replicas
+    pub fn replconf_ack(&self) -> Option<DataType> {
+        self.replication.master.as_ref()?;
+        let offset = self.propagation.repl_offset();
+        Some(DataType::bulk_array([b"REPLCONF".to_vec(), b"ACK".to_vec(), offset.to_string().into_bytes()]))
+    }
+
+    pub fn sync_progress(&mut self, total: Option<u64>, read: u64) {
+        if let Some(master) = self.replication.master.as_mut() {
+            master.sync_total = total;
+            master.sync_read = read;
+            master.last_io = Instant::now();
+        }
+    }
+
+    pub fn heard_from_master(&mut self) {
+        if let Some(master) = self.replication.master.as_mut() {
+            master.last_io = Instant::now();
+        }
+    }
+
+    // This server's role as HELLO gives it
+    pub fn role(&self) -> &'static str {
+        if self.replication.master.is_some() { "replica" } else { "master" }
+    }
+
+    // Fields of INFO replication: a replica's link to its master and how far a sync has got,
+    // then the replicas of this server, then the history of the dataset and the backlog
+    pub fn replication_info(&self) -> Vec<(String, String)> {
+        let mut info = Vec::new();
+        let mut field = |name: &str, value: String| info.push((name.to_string(), value));
+        let offset = self.propagation.repl_offset();
+        match &self.replication.master {
+            None => field("role", "master".to_string()),
+            Some(master) => {
+                let up = master.state == LinkState::Connected;
+                let syncing = master.state == LinkState::Syncing;
+                field("role", "slave".to_string());
+                field("master_host", master.host.clone());
+                field("master_port", master.port.to_string());
+                field("master_link_status", if up { "up" } else { "down" }.to_string());
+                field("master_last_io_seconds_ago", if up { master.last_io.elapsed().as_secs() as i64 } else { -1 }.to_string());
+                field("master_sync_in_progress", (syncing as u8).to_string());
+                field("slave_repl_offset", offset.to_string());
+                // A snapshot framed by an end mark has no known size
+                if syncing {
+                    let total = master.sync_total.map_or(-1, |total| total as i64);
+                    let left = master.sync_total.map_or(-1, |total| total.saturating_sub(master.sync_read) as i64);
+                    let perc = master.sync_total.filter(|total| *total > 0).map_or(0.0, |total| master.sync_read as f64 * 100.0 / total as f64);
+                    field("master_sync_total_bytes", total.to_string());
+                    field("master_sync_read_bytes", master.sync_read.to_string());
+                    field("master_sync_left_bytes", left.to_string());
+                    field("master_sync_perc", format!("{:.2}", perc));
+                    field("master_sync_last_io_seconds_ago", master.last_io.elapsed().as_secs().to_string());
+                }
+                if !up {
+                    let down = master.down_since.map_or(-1, |since| since.elapsed().as_secs() as i64);
+                    field("master_link_down_since_seconds", down.to_string());
+                }
+                field("slave_priority", self.config.replication.priority.to_string());
+                field("slave_read_only", (self.config.replication.read_only as u8).to_string());
+                field("replica_announced", "1".to_string());
+            }
+        }
+        field("connected_slaves", self.replication.replicas.len().to_string());
+        if self.config.replication.min_replicas_to_write > 0 && self.config.replication.min_replicas_max_lag > 0 {
+            field("min_slaves_good_slaves", self.good_replicas().to_string());
+        }
+        for (i, replica) in self.replication.replicas.iter().enumerate() {
+            field(&format!("slave{}", i), replica.info());
+        }
+        let failover_state = match self.failover_state() {
+            None => "no-failover",
+            Some(failover::FailoverState::WaitingForSync) => "waiting-for-sync",
+            Some(failover::FailoverState::InProgress) => "failover-in-progress",
+        };
+        field("master_failover_state", failover_state.to_string());
+        field("master_replid", self.replication.replid.clone());
+        field("master_replid2", self.replication.replid2.clone());
+        field("master_repl_offset", offset.to_string());
+        field("second_repl_offset", self.replication.second_replid_offset.to_string());
+        let backlog = self.propagation.backlog();
+        field("repl_backlog_active", (backlog.is_some() as u8).to_string());
+        field("repl_backlog_size", backlog.map_or(self.config.replication.backlog_size, |backlog| backlog.size()).to_string());
+        // Counted from 1, as PSYNC asks for the next byte a replica wants
+        let histlen = backlog.map_or(0, |backlog| backlog.histlen() as u64);
+        let first_byte = if backlog.is_some() { offset - histlen + 1 } else { 0 };
+        field("repl_backlog_first_byte_offset", first_byte.to_string());
+        field("repl_backlog_histlen", histlen.to_string());
+        info
+    }
+}
+
+impl State {
+    // Drop the link to the master, closing its connection. Aborting the link's task drops its
+    // end of the connection, the handle other connections reach it by has to go too.
+    fn drop_master_link(&mut self) {
+        if let Some(client) = self.replication.master.take().and_then(|master| master.client) {
+            self.remove_client(client);
+        }
+    }
+
+    // Stop replicating and keep the dataset as it is, which then has a history of its own. Our
+    // replicas have to sync again to hear of it, which they can carry on with from where they
+    // are.
+    pub fn stop_replicating(&mut self) {
+        if self.replication.master.is_some() {
+            self.drop_master_link();
+            self.config.replication.replicaof = None;
+            let offset = self.propagation.repl_offset();
+            self.replication.shift_replid(new_replid(), offset);
+            self.drop_replicas();
+        }
+    }
+}
+
+// Start replicating a master, dropping the link to any previous one
+pub async fn replicate(state: &Arc<RwLock<State>>, host: String, port: u16) {
+    let mut guard = state.write().await;
+    guard.drop_master_link();
+    guard.config.replication.replicaof = Some((host.clone(), port));
+    let task = tokio::spawn(replica::run_link(state.clone(), host.clone(), port));
+    guard.replication.master = Some(MasterLink {
+        host,
+        port,
+        state: LinkState::Connecting,
+        client: None,
+        last_io: Instant::now(),
+        down_since: None,
+        sync_total: None,
+        sync_read: 0,
+        task,
+    });
+}
+
+// REPLICAOF switches to a new master, which sends its whole dataset unless its history takes
+// in ours, or with NO ONE stops replicating and keeps the dataset as it is. That dataset then
+// has a history of its own.
+// Boxed, as the master link it starts runs commands, REPLICAOF among them.
+pub fn replicaof(state: &Arc<RwLock<State>>, master: Option<(String, u16)>) -> BoxFuture<'_, DataType> {
+    async move {
+        // Cluster nodes are made replicas through the cluster instead
+        if state.read().await.cluster.is_some() {
+            return DataType::SimpleError("ERR REPLICAOF not allowed in cluster mode.".to_string());
+        }
+        let Some((host, port)) = master else {
+            state.write().await.stop_replicating();
+            return DataType::ok();
+        };
+        if let Some(master) = &state.read().await.replication.master {
+            if master.host.eq_ignore_ascii_case(&host) && master.port == port {
+                return DataType::SimpleString("OK Already connected to specified master".to_string());
+            }
+        }
+        replicate(state, host, port).await;
+        DataType::ok()
+    }.boxed()
+}
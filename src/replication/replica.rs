@@ -0,0 +1,254 @@
+use std::sync::Arc;
+
+use anyhow::{Error, Result};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    sync::RwLock,
+    time::{self, Duration},
+};
+
+use crate::{client::Client, resp::DataType, state::State};
+
+use super::{failover::FailoverState, LinkState};
+
+// How often the master is told how far the replica has got without asking
+const ACK_INTERVAL: Duration = Duration::from_secs(1);
+
+// How long to wait before connecting again after the link failed, doubling with every attempt
+// that fails before getting the master's dataset
+const RETRY_MIN: Duration = Duration::from_millis(500);
+const RETRY_MAX: Duration = Duration::from_secs(10);
+
+// The connection to the master, read through a buffer like a client's
+struct Link {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Link {
+    async fn request(&mut self, args: &[&str]) -> Result<DataType> {
+        let request = DataType::bulk_array(args.iter().map(|arg| arg.as_bytes().to_vec()));
+        self.writer.write_all(&request.serialize(false)).await?;
+        DataType::deserialize_data(&mut self.reader).await
+    }
+}
+
+// Keeps the link up until the task is aborted, connecting again whenever it fails
+pub async fn run_link(state: Arc<RwLock<State>>, host: String, port: u16) {
+    let mut retry = RETRY_MIN;
+    loop {
+        if let Err(e) = sync_with_master(&state, &host, port).await {
+            println!("Lost the link to master {}:{}: {}", host, port, e);
+        }
+        let synced = {
+            let mut state = state.write().await;
+            state.set_link_state(LinkState::Connecting);
+            match state.replication.master.as_mut() {
+                Some(master) => master.client.take().is_some(),
+                None => false,
+            }
+        };
+        if synced {
+            retry = RETRY_MIN;
+        }
+        time::sleep(retry).await;
+        retry = (retry * 2).min(RETRY_MAX);
+    }
+}
+
+// Introduce ourselves to the master and ask for its dataset, then apply the writes it sends
+// after it like a client's. The master has to hear our listening port and capabilities before
+// PSYNC, but it may not understand them, which isn't fatal.
+async fn sync_with_master(state: &Arc<RwLock<State>>, host: &str, port: u16) -> Result<()> {
+    let timeout = Duration::from_secs(state.read().await.config.replication.timeout);
+    let stream = match time::timeout(timeout, TcpStream::connect((host, port))).await {
+        Ok(stream) => stream?,
+        Err(_) => return Err(Error::msg("timeout connecting to the master")),
+    };
+    let listening_port = {
+        let mut state = state.write().await;
+        state.set_link_state(LinkState::Handshake);
+        state.config.server.port
+    };
+    let (reader, writer) = stream.into_split();
+    let mut link = Link { reader: BufReader::new(reader), writer };
+
+    match link.request(&["PING"]).await? {
+        DataType::SimpleString(_) => {}
+        reply => return Err(Error::msg(format!("unexpected reply to PING: {:?}", reply))),
+    }
+    for request in [&["REPLCONF", "listening-port", &listening_port.to_string()][..], &["REPLCONF", "capa", "eof", "capa", "psync2"]] {
+        if let DataType::SimpleError(e) = link.request(request).await? {
+            println!("Master didn't accept {}: {}", request.join(" "), e);
+        }
+    }
+    // Ask to carry on with the history of our dataset from the next byte we want, which the
+    // master can do if that is its history too and it still has what we missed. Otherwise it
+    // sends its whole dataset. A master failing over to its replica asks it to take over too,
+    // and fails over no further if it won't.
+    let (replid, next, failover) = {
+        let state = state.read().await;
+        let failover = state.failover_state() == Some(FailoverState::InProgress);
+        (state.replication.replid.clone(), (state.propagation.repl_offset() + 1).to_string(), failover)
+    };
+    let mut psync = vec!["PSYNC", &replid, &next];
+    if failover {
+        psync.push("FAILOVER");
+    }
+    let reply = match link.request(&psync).await? {
+        DataType::SimpleString(reply) => reply,
+        reply => {
+            if failover {
+                state.write().await.abort_failover("Failover target rejected psync request");
+            }
+            return Err(Error::msg(format!("unexpected reply to PSYNC: {:?}", reply)));
+        }
+    };
+    let Link { mut reader, writer } = link;
+    let mut client = Client::new(writer);
+    client.master = true;
+    let mut guard = match reply.split(' ').collect::<Vec<_>>()[..] {
+        ["CONTINUE"] => state.write().await,
+        // The master's history carries on from ours under a new id, which our own replicas
+        // have to sync again to hear of
+        ["CONTINUE", replid] => {
+            let mut state = state.write().await;
+            if replid != state.replication.replid {
+                let offset = state.propagation.repl_offset();
+                state.replication.shift_replid(replid.to_string(), offset);
+                state.drop_replicas();
+            }
+            state
+        }
+        ["FULLRESYNC", replid, offset] if replid.len() == 40 && offset.parse::<u64>().is_ok() => {
+            state.write().await.set_link_state(LinkState::Syncing);
+            let snapshot = read_snapshot(&mut reader, state).await?;
+            let mut state = state.write().await;
+            state.load_master_snapshot(&snapshot)?;
+            state.replication.set_replid(replid.to_string());
+            let backlog_size = state.config.replication.backlog_size;
+            state.propagation.reset_repl_stream(offset.parse::<u64>().unwrap(), backlog_size);
+            state
+        }
+        _ => return Err(Error::msg(format!("unexpected reply to PSYNC: {}", reply))),
+    };
+    guard.set_link_state(LinkState::Connected);
+    guard.complete_failover();
+    if let Some(master) = guard.replication.master.as_mut() {
+        master.client = Some(client.id);
+    }
+    guard.add_client(&client);
+    drop(guard);
+    // Acks also go out unasked, so the master knows the link is alive and how far behind it
+    // is. A master that has gone quiet for longer than repl-timeout is given up on, as it
+    // pings its replicas well within that.
+    let handle = client.handle();
+    let supervise = async {
+        let mut interval = time::interval(ACK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let state = state.read().await;
+            let Some(master) = &state.replication.master else {
+                return closed();
+            };
+            if master.last_io.elapsed() > Duration::from_secs(state.config.replication.timeout) {
+                return Error::msg("timeout, no data from the master");
+            }
+            if let Some(ack) = state.replconf_ack() {
+                if handle.send(ack).is_err() {
+                    return closed();
+                }
+            }
+        }
+    };
+    let result = tokio::select! {
+        result = crate::serve_client(&mut reader, &mut client, state) => result,
+        e = supervise => Err(e),
+    };
+    state.write().await.remove_client(client.id);
+    result
+}
+
+fn closed() -> Error {
+    Error::msg("connection closed by master")
+}
+
+// The master sends its dataset as a bulk string without the trailing CRLF. The length comes
+// first, or when the master streams the snapshot without knowing its length, a random 40 byte
+// mark that it then ends with. Newlines may come before either, to keep the link alive while
+// the master prepares the snapshot. How much has come is kept for INFO as it arrives.
+async fn read_snapshot(reader: &mut BufReader<OwnedReadHalf>, state: &Arc<RwLock<State>>) -> Result<Vec<u8>> {
+    let mut line = String::new();
+    while line.trim().is_empty() {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(closed());
+        }
+    }
+    let header = line.trim_end();
+    let Some(size) = header.strip_prefix('$') else {
+        return Err(Error::msg(format!("unexpected reply instead of the snapshot: {}", header)));
+    };
+    let Some(mark) = size.strip_prefix("EOF:") else {
+        let len = size.parse::<usize>().map_err(|_| Error::msg(format!("bad snapshot length: {}", size)))?;
+        let mut snapshot = vec![0; len];
+        let mut read = 0;
+        while read < len {
+            match reader.read(&mut snapshot[read..]).await? {
+                0 => return Err(closed()),
+                n => read += n,
+            }
+            state.write().await.sync_progress(Some(len as u64), read as u64);
+        }
+        return Ok(snapshot);
+    };
+    let mark = mark.as_bytes();
+    if mark.len() != 40 {
+        return Err(Error::msg(format!("bad snapshot end mark: {}", mark.len())));
+    }
+    // Commands follow the mark, so only what comes up to it is taken from the buffer
+    let mut snapshot = Vec::new();
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            return Err(closed());
+        }
+        let (searched, read) = (snapshot.len().saturating_sub(mark.len() - 1), available.len());
+        snapshot.extend_from_slice(available);
+        state.write().await.sync_progress(None, snapshot.len() as u64);
+        if let Some(at) = snapshot[searched..].windows(mark.len()).position(|window| window == mark) {
+            let end = searched + at + mark.len();
+            reader.consume(read - (snapshot.len() - end));
+            snapshot.truncate(end - mark.len());
+            return Ok(snapshot);
+        }
+        reader.consume(read);
+    }
+}
+
+impl State {
+    // Replace the dataset with the master's. The snapshot is loaded into an empty dataset
+    // first, so a bad one leaves ours as it was. A running AOF no longer matches the dataset,
+    // so it is rewritten, and our own replicas have to sync again.
+    fn load_master_snapshot(&mut self, snapshot: &[u8]) -> Result<()> {
+        let mut loaded = State::new();
+        loaded.config = self.config.clone();
+        let len = loaded.load_rdb_data(snapshot)?;
+        loaded.verify_rdb_checksum(&snapshot[..len])?;
+        self.datastore = loaded.datastore;
+        self.libraries = loaded.libraries;
+        self.hashes_with_field_ttl = loaded.hashes_with_field_ttl;
+        self.dirty += 1;
+        self.drop_replicas();
+        if self.aof_writer.is_some() {
+            if let Err(DataType::SimpleError(e)) = self.bgrewriteaof() {
+                println!("Couldn't rewrite the AOF after syncing with the master: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::{Result, Error};
+
+use futures::future::{BoxFuture, FutureExt};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, BufReader},
+    net::tcp::OwnedReadHalf,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DataType {
+    SimpleString(String),
+    SimpleError(String),
+    Integer(i64),
+    BulkString(Vec<u8>),
+    NullBulkString,
+    Array(Vec<DataType>),
+    NullArray,
+    // RESP3 types, sent in their RESP2 equivalents to connections that haven't negotiated RESP3
+    Map(Vec<(DataType, DataType)>),
+    Push(Vec<DataType>),
+}
+
+impl DataType {
+    pub fn deserialize_data<'a>(reader: &'a mut BufReader<OwnedReadHalf>) -> BoxFuture<'a, Result<DataType>> {
+        async move {
+            let mut buffer = String::with_capacity(1024);
+
+            // Read first line of data type and dispatch to handler for further processing
+            reader.read_line(&mut buffer).await?;
+            buffer = buffer.trim().to_string();
+            let data = match buffer.chars().next() {
+                Some('+') => DataType::SimpleString(buffer[1..].to_string()),
+                Some('-') => DataType::SimpleError(buffer[1..].to_string()),
+                Some(':') => DataType::Integer(buffer[1..].parse::<i64>()?),
+                Some('$') => {
+                    let len = buffer[1..].parse::<usize>()? + 2;
+                    let mut data = vec![0; len];
+                    reader.read_exact(&mut data).await?;
+                    data.truncate(len - 2);
+                    DataType::BulkString(data)
+                }
+                Some('*') => {
+                    let len = buffer[1..].parse::<usize>()?;
+                    let mut data: Vec<DataType> = Vec::with_capacity(len);
+                    for _ in 0..len {
+                        data.push(DataType::deserialize_data(reader).await?);
+                    }
+                    DataType::Array(data)
+                }
+                Some(_) => return Err(Error::msg("Command protocol error: unknown data type prefix")),
+                None => return Err(Error::msg("Client disconnected")),
+            };
+            Ok(data)
+        }.boxed()
+    }
+
+    pub fn serialize_into(&self, buf: &mut Vec<u8>, resp3: bool) {
+        match self {
+            DataType::SimpleString(s) => buf.extend_from_slice(format!("+{}\r\n", s).as_bytes()),
+            DataType::SimpleError(s) => buf.extend_from_slice(format!("-{}\r\n", s).as_bytes()),
+            DataType::Integer(i) => buf.extend_from_slice(format!(":{}\r\n", i).as_bytes()),
+            DataType::BulkString(data) => {
+                buf.extend_from_slice(format!("${}\r\n", data.len()).as_bytes());
+                buf.extend_from_slice(data);
+                buf.extend_from_slice(b"\r\n");
+            }
+            DataType::NullBulkString | DataType::NullArray if resp3 => buf.extend_from_slice(b"_\r\n"),
+            DataType::NullBulkString => buf.extend_from_slice(b"$-1\r\n"),
+            DataType::NullArray => buf.extend_from_slice(b"*-1\r\n"),
+            DataType::Array(items) | DataType::Push(items) => {
+                let prefix = if resp3 && matches!(self, DataType::Push(_)) { '>' } else { '*' };
+                buf.extend_from_slice(format!("{}{}\r\n", prefix, items.len()).as_bytes());
+                for item in items {
+                    item.serialize_into(buf, resp3);
+                }
+            }
+            // RESP2 flattens maps into alternating keys and values
+            DataType::Map(pairs) => {
+                let header = if resp3 { format!("%{}\r\n", pairs.len()) } else { format!("*{}\r\n", pairs.len() * 2) };
+                buf.extend_from_slice(header.as_bytes());
+                for (key, value) in pairs {
+                    key.serialize_into(buf, resp3);
+                    value.serialize_into(buf, resp3);
+                }
+            }
+        }
+    }
+
+    pub fn serialize(&self, resp3: bool) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.serialize_into(&mut buf, resp3);
+        buf
+    }
+
+    // Size of the RESP2 encoding, which is what queued output is accounted in whatever protocol
+    // the connection speaks
+    pub fn encoded_len(&self) -> usize {
+        let header = |len: usize| 3 + len.checked_ilog10().map_or(1, |digits| digits as usize + 1);
+        match self {
+            DataType::SimpleString(s) | DataType::SimpleError(s) => s.len() + 3,
+            DataType::Integer(i) => header(i.unsigned_abs() as usize) + usize::from(*i < 0),
+            DataType::BulkString(data) => header(data.len()) + data.len() + 2,
+            DataType::NullBulkString | DataType::NullArray => 5,
+            DataType::Array(items) | DataType::Push(items) => header(items.len()) + items.iter().map(DataType::encoded_len).sum::<usize>(),
+            DataType::Map(pairs) => {
+                header(pairs.len() * 2) + pairs.iter().map(|(key, value)| key.encoded_len() + value.encoded_len()).sum::<usize>()
+            }
+        }
+    }
+
+    pub fn ok() -> Self {
+        DataType::SimpleString("OK".to_string())
+    }
+
+    pub fn bulk_array(items: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        DataType::Array(items.into_iter().map(DataType::BulkString).collect())
+    }
+}
@@ -0,0 +1,487 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use tokio::{
+    sync::mpsc::UnboundedSender,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    aof::{AofStatus, Request},
+    blocking::BlockingState,
+    client::ClientHandle,
+    clock::now_ms,
+    cluster::Cluster,
+    commands::functions::Libraries,
+    config::Config,
+    lua::parser::FunctionBody,
+    notify::{NOTIFY_EXPIRED, NOTIFY_GENERIC, NOTIFY_HASH},
+    persistent_map::PersistentMap,
+    propagate::Propagation,
+    pubsub::PubSubState,
+    random::random_f64,
+    rdb::SaveStatus,
+    replication::Replication,
+    resp::DataType,
+    tracking::TrackingState,
+    types::{bloom::BloomFilter, cms::CountMinSketch, hash::Hash, json::Json, list::List, parse_strict_integer, set::Set, stream::Stream, timeseries::TimeSeries, topk::TopK, zset::SortedSet},
+};
+
+// Upper bound on hashes examined by each run of the active expiry cycle
+const ACTIVE_EXPIRE_HASHES_PER_CYCLE: usize = 20;
+
+// Approximated LFU counter parameters, matching the Redis defaults
+const LFU_INIT_VAL: u8 = 5;
+const LFU_LOG_FACTOR: f64 = 10.0;
+const LFU_DECAY_TIME: Duration = Duration::from_secs(60);
+
+/// Result of executing a command against the datastore. The error variant carries an error reply
+/// so type mismatches and bad arguments can be propagated with `?`.
+pub type CommandResult = Result<DataType, DataType>;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    String(Vec<u8>),
+    List(List),
+    Hash(Hash),
+    Set(Set),
+    SortedSet(SortedSet),
+    Stream(Stream),
+    Bloom(BloomFilter),
+    CountMinSketch(CountMinSketch),
+    TopK(TopK),
+    Json(Json),
+    TimeSeries(TimeSeries),
+}
+
+impl Value {
+    // Internal representation as reported by OBJECT ENCODING
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Value::String(s) if s.len() <= 20 && parse_strict_integer(s).is_some() => "int",
+            Value::String(s) if s.len() <= 44 => "embstr",
+            Value::String(_) => "raw",
+            Value::List(list) => list.encoding(),
+            Value::Hash(hash) => hash.encoding(),
+            Value::Set(set) => set.encoding(),
+            Value::SortedSet(zset) => zset.encoding(),
+            Value::Stream(_) => "stream",
+            // Module style types have no alternative encodings
+            Value::Bloom(_) | Value::CountMinSketch(_) | Value::TopK(_) | Value::Json(_) | Value::TimeSeries(_) => "raw",
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            // Streams and time series outlive their entries
+            Value::String(_) | Value::Stream(_) | Value::TimeSeries(_) => false,
+            Value::Bloom(_) | Value::CountMinSketch(_) | Value::TopK(_) | Value::Json(_) => false,
+            Value::List(list) => list.is_empty(),
+            Value::Hash(hash) => hash.is_empty(),
+            Value::Set(set) => set.is_empty(),
+            Value::SortedSet(zset) => zset.is_empty(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DataStoreValue {
+    pub value: Value,
+    // Unix time in milliseconds the key expires at
+    pub expiry: Option<u64>,
+    pub last_access: Instant,
+    pub access_frequency: u8,
+}
+
+impl DataStoreValue {
+    pub fn new(value: Value, expiry: Option<u64>) -> Self {
+        DataStoreValue {
+            value,
+            expiry,
+            last_access: Instant::now(),
+            access_frequency: LFU_INIT_VAL,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        matches!(self.expiry, Some(expiry) if expiry < now_ms())
+    }
+
+    pub fn idle_time(&self) -> Duration {
+        self.last_access.elapsed()
+    }
+
+    // Access frequency with one decrement applied for every decay period the key sat idle
+    pub fn frequency(&self) -> u8 {
+        let periods = self.idle_time().as_secs() / LFU_DECAY_TIME.as_secs();
+        self.access_frequency.saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+
+    // Record an access, bumping the logarithmic frequency counter
+    pub fn touch(&mut self) {
+        let mut counter = self.frequency();
+        if counter < u8::MAX {
+            let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+            let p = 1.0 / (base * LFU_LOG_FACTOR + 1.0);
+            if random_f64() < p {
+                counter += 1;
+            }
+        }
+        self.access_frequency = counter;
+        self.last_access = Instant::now();
+    }
+}
+
+pub fn wrong_type_error() -> DataType {
+    DataType::SimpleError("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+}
+
+pub struct State {
+    // Shares structure with the snapshots BGSAVE takes of it
+    pub datastore: PersistentMap<Vec<u8>, DataStoreValue>,
+    pub rdb_path: Option<PathBuf>,
+    pub blocking: BlockingState,
+    pub pubsub: PubSubState,
+    pub tracking: TrackingState,
+    // Open connections by client id
+    pub clients: HashMap<u64, ClientHandle>,
+    pub config: Config,
+    // Hashes that have had field expirations set, for the active expiry cycle to visit
+    pub hashes_with_field_ttl: HashSet<Vec<u8>>,
+    // Where the active expiry cycle picks up in hashes_with_field_ttl, so every hash gets its
+    // turn however many there are
+    active_expire_cursor: usize,
+    // What a replica's client is shown of a hash with expired fields
+    hash_view: Hash,
+    // Compiled scripts by the SHA1 of their source
+    pub scripts: HashMap<String, Arc<FunctionBody>>,
+    pub libraries: Libraries,
+    pub propagation: Propagation,
+    pub save_status: Arc<Mutex<SaveStatus>>,
+    pub aof_status: Arc<Mutex<AofStatus>>,
+    // Where finished rewrites are sent while the AOF is being appended to
+    pub aof_writer: Option<UnboundedSender<Request>>,
+    pub replication: Replication,
+    // This node's view of the cluster, None unless cluster-enabled
+    pub cluster: Option<Cluster>,
+    // Writes since startup, from which the changes since the last save are worked out
+    pub dirty: u64,
+}
+
+impl State {
+    pub fn new() -> Self {
+        State {
+            datastore: PersistentMap::new(),
+            rdb_path: None,
+            blocking: BlockingState::default(),
+            pubsub: PubSubState::default(),
+            tracking: TrackingState::default(),
+            clients: HashMap::new(),
+            config: Config::default(),
+            hashes_with_field_ttl: HashSet::new(),
+            active_expire_cursor: 0,
+            hash_view: Hash::default(),
+            scripts: HashMap::new(),
+            libraries: Libraries::new(),
+            propagation: Propagation::default(),
+            save_status: SaveStatus::new(),
+            aof_status: AofStatus::new(),
+            aof_writer: None,
+            replication: Replication::default(),
+            cluster: None,
+            dirty: 0,
+        }
+    }
+
+    pub fn new_with_rdbpath(rdb_path: PathBuf) -> Self {
+        State {
+            datastore: PersistentMap::new(),
+            rdb_path: Some(rdb_path),
+            blocking: BlockingState::default(),
+            pubsub: PubSubState::default(),
+            tracking: TrackingState::default(),
+            clients: HashMap::new(),
+            config: Config::default(),
+            hashes_with_field_ttl: HashSet::new(),
+            active_expire_cursor: 0,
+            hash_view: Hash::default(),
+            scripts: HashMap::new(),
+            libraries: Libraries::new(),
+            propagation: Propagation::default(),
+            save_status: SaveStatus::new(),
+            aof_status: AofStatus::new(),
+            aof_writer: None,
+            replication: Replication::default(),
+            cluster: None,
+            dirty: 0,
+        }
+    }
+
+    // Expiry noticed during a lookup isn't a write by the running command, so it mustn't stop
+    // the command's reads from being tracked
+    fn notify_lazy_expiry(&mut self, class: u16, event: &str, key: &[u8]) {
+        let wrote = self.tracking.wrote();
+        self.notify_keyspace_event(class, event, key);
+        self.tracking.set_wrote(wrote);
+    }
+
+    // A master deletes what expires on its replicas and in the AOF explicitly, so none of them
+    // depend on their own clock to agree with it
+    fn propagate_expiry(&mut self, command: Vec<Vec<u8>>) {
+        if self.replication.master.is_none() {
+            self.propagate(vec![command]);
+        }
+    }
+
+    // Look up a key without updating its access metadata, lazily removing it if it has expired.
+    // A replica leaves that to its master, which replicates the deletion, so until then the key
+    // is only missing for clients and is still there for the master's own commands.
+    pub fn peek_value(&mut self, key: &[u8]) -> Option<&mut DataStoreValue> {
+        self.tracking.record_read(key);
+        if self.datastore.get(key).is_some_and(|dsv| dsv.is_expired()) {
+            match &self.replication.master {
+                None => {
+                    self.datastore.remove(key);
+                    self.notify_lazy_expiry(NOTIFY_EXPIRED, "expired", key);
+                    self.propagate_expiry(vec![b"DEL".to_vec(), key.to_vec()]);
+                }
+                Some(master) if master.client.is_some() && master.client == self.tracking.running() => {}
+                Some(_) => return None,
+            }
+        }
+        self.datastore.get_mut(key)
+    }
+
+    // Look up a key and record the access
+    pub fn get_value(&mut self, key: &[u8]) -> Option<&mut DataStoreValue> {
+        let dsv = self.peek_value(key)?;
+        dsv.touch();
+        Some(dsv)
+    }
+
+    pub fn get_string(&mut self, key: &[u8]) -> Result<Option<&mut Vec<u8>>, DataType> {
+        match self.get_value(key) {
+            Some(DataStoreValue { value: Value::String(s), .. }) => Ok(Some(s)),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(None),
+        }
+    }
+
+    // Fetch a string for in-place modification, creating an empty one if the key doesn't exist
+    pub fn get_or_create_string(&mut self, key: &[u8]) -> Result<&mut Vec<u8>, DataType> {
+        if self.get_string(key)?.is_none() {
+            self.datastore.insert(key.to_vec(), DataStoreValue::new(Value::String(Vec::new()), None));
+        }
+        Ok(self.get_string(key)?.unwrap())
+    }
+
+    pub fn get_list(&mut self, key: &[u8]) -> Result<Option<&mut List>, DataType> {
+        match self.get_value(key) {
+            Some(DataStoreValue { value: Value::List(list), .. }) => Ok(Some(list)),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(None),
+        }
+    }
+
+    // Fetch a list for writing, creating an empty one if the key doesn't exist. Clients blocked
+    // on the key are signalled since the caller is about to add elements.
+    pub fn get_or_create_list(&mut self, key: &[u8]) -> Result<&mut List, DataType> {
+        if self.get_list(key)?.is_none() {
+            self.datastore.insert(key.to_vec(), DataStoreValue::new(Value::List(List::default()), None));
+        }
+        self.blocking.signal_key_ready(key);
+        Ok(self.get_list(key)?.unwrap())
+    }
+
+    // A read-only replica leaves expired hash fields for its master to delete, like expired
+    // keys. A writable one expires them itself, as its clients may change the hash.
+    fn hides_expired_fields(&self) -> bool {
+        self.config.replication.read_only
+            && self.replication.master.as_ref().is_some_and(|master| master.client.is_none() || master.client != self.tracking.running())
+    }
+
+    // Fields whose TTL has passed are dropped before the hash is handed out, deleting the key
+    // altogether if nothing is left. A replica's clients get a copy without them instead.
+    pub fn get_hash(&mut self, key: &[u8]) -> Result<Option<&mut Hash>, DataType> {
+        let hide = self.hides_expired_fields();
+        let (expired, emptied, view) = match self.get_value(key) {
+            Some(DataStoreValue { value: Value::Hash(hash), .. }) if hide => (Vec::new(), false, hash.without_expired()),
+            Some(DataStoreValue { value: Value::Hash(hash), .. }) => {
+                let expired = hash.remove_expired();
+                let emptied = !expired.is_empty() && hash.is_empty();
+                (expired, emptied, None)
+            }
+            Some(_) => return Err(wrong_type_error()),
+            None => return Ok(None),
+        };
+        if let Some(view) = view {
+            self.hash_view = view;
+            return Ok(Some(&mut self.hash_view).filter(|hash| !hash.is_empty()));
+        }
+        if !expired.is_empty() {
+            self.notify_lazy_expiry(NOTIFY_HASH, "hexpired", key);
+            self.propagate_expiry([b"HDEL".to_vec(), key.to_vec()].into_iter().chain(expired).collect());
+        }
+        if emptied {
+            self.datastore.remove(key);
+            self.notify_lazy_expiry(NOTIFY_GENERIC, "del", key);
+        }
+        match self.datastore.get_mut(key) {
+            Some(DataStoreValue { value: Value::Hash(hash), .. }) => Ok(Some(hash)),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn get_or_create_hash(&mut self, key: &[u8]) -> Result<&mut Hash, DataType> {
+        if self.get_hash(key)?.is_none() {
+            self.datastore.insert(key.to_vec(), DataStoreValue::new(Value::Hash(Hash::default()), None));
+        }
+        Ok(self.get_hash(key)?.unwrap())
+    }
+
+    pub fn get_set(&mut self, key: &[u8]) -> Result<Option<&mut Set>, DataType> {
+        match self.get_value(key) {
+            Some(DataStoreValue { value: Value::Set(set), .. }) => Ok(Some(set)),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_or_create_set(&mut self, key: &[u8]) -> Result<&mut Set, DataType> {
+        if self.get_set(key)?.is_none() {
+            self.datastore.insert(key.to_vec(), DataStoreValue::new(Value::Set(Set::default()), None));
+        }
+        Ok(self.get_set(key)?.unwrap())
+    }
+
+    pub fn get_sorted_set(&mut self, key: &[u8]) -> Result<Option<&mut SortedSet>, DataType> {
+        match self.get_value(key) {
+            Some(DataStoreValue { value: Value::SortedSet(zset), .. }) => Ok(Some(zset)),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_or_create_sorted_set(&mut self, key: &[u8]) -> Result<&mut SortedSet, DataType> {
+        if self.get_sorted_set(key)?.is_none() {
+            self.datastore.insert(key.to_vec(), DataStoreValue::new(Value::SortedSet(SortedSet::default()), None));
+        }
+        self.blocking.signal_key_ready(key);
+        Ok(self.get_sorted_set(key)?.unwrap())
+    }
+
+    pub fn get_stream(&mut self, key: &[u8]) -> Result<Option<&mut Stream>, DataType> {
+        match self.get_value(key) {
+            Some(DataStoreValue { value: Value::Stream(stream), .. }) => Ok(Some(stream)),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_or_create_stream(&mut self, key: &[u8]) -> Result<&mut Stream, DataType> {
+        if self.get_stream(key)?.is_none() {
+            self.datastore.insert(key.to_vec(), DataStoreValue::new(Value::Stream(Stream::default()), None));
+        }
+        self.blocking.signal_key_ready(key);
+        Ok(self.get_stream(key)?.unwrap())
+    }
+
+    pub fn get_bloom(&mut self, key: &[u8]) -> Result<Option<&mut BloomFilter>, DataType> {
+        match self.get_value(key) {
+            Some(DataStoreValue { value: Value::Bloom(bloom), .. }) => Ok(Some(bloom)),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(None),
+        }
+    }
+
+    // Filters created on first use take their parameters from the bf-* configuration
+    pub fn get_or_create_bloom(&mut self, key: &[u8]) -> Result<&mut BloomFilter, DataType> {
+        if self.get_bloom(key)?.is_none() {
+            let defaults = self.config.bloom;
+            let bloom = BloomFilter::new(defaults.error_rate, defaults.initial_size, Some(defaults.expansion_factor))
+                .map_err(|msg| DataType::SimpleError(msg.to_string()))?;
+            self.datastore.insert(key.to_vec(), DataStoreValue::new(Value::Bloom(bloom), None));
+        }
+        Ok(self.get_bloom(key)?.unwrap())
+    }
+
+    pub fn get_cms(&mut self, key: &[u8]) -> Result<Option<&mut CountMinSketch>, DataType> {
+        match self.get_value(key) {
+            Some(DataStoreValue { value: Value::CountMinSketch(cms), .. }) => Ok(Some(cms)),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_topk(&mut self, key: &[u8]) -> Result<Option<&mut TopK>, DataType> {
+        match self.get_value(key) {
+            Some(DataStoreValue { value: Value::TopK(topk), .. }) => Ok(Some(topk)),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_json(&mut self, key: &[u8]) -> Result<Option<&mut Json>, DataType> {
+        match self.get_value(key) {
+            Some(DataStoreValue { value: Value::Json(json), .. }) => Ok(Some(json)),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_time_series(&mut self, key: &[u8]) -> Result<Option<&mut TimeSeries>, DataType> {
+        match self.get_value(key) {
+            Some(DataStoreValue { value: Value::TimeSeries(series), .. }) => Ok(Some(series)),
+            Some(_) => Err(wrong_type_error()),
+            None => Ok(None),
+        }
+    }
+
+    // Aggregate types are deleted once their last element is removed
+    pub fn remove_if_empty(&mut self, key: &[u8]) {
+        if self.datastore.get(key).is_some_and(|dsv| dsv.value.is_empty()) {
+            self.datastore.remove(key);
+            self.notify_keyspace_event(NOTIFY_GENERIC, "del", key);
+        }
+    }
+
+    // Periodically reclaim memory held by expired hash fields that nobody reads. Each hash's
+    // deletions go out on their own. A replica's come from its master.
+    pub fn active_expire_cycle(&mut self) {
+        if self.replication.master.is_some() {
+            return;
+        }
+        if self.active_expire_cursor >= self.hashes_with_field_ttl.len() {
+            self.active_expire_cursor = 0;
+        }
+        let keys: Vec<Vec<u8>> =
+            self.hashes_with_field_ttl.iter().skip(self.active_expire_cursor).take(ACTIVE_EXPIRE_HASHES_PER_CYCLE).cloned().collect();
+        // Hashes that are done drop out from in front of the cursor
+        self.active_expire_cursor += keys.len();
+        for key in keys {
+            let (expired, emptied, done) = match self.datastore.get_mut(&key) {
+                Some(DataStoreValue { value: Value::Hash(hash), .. }) => {
+                    let expired = hash.remove_expired();
+                    (expired, hash.is_empty(), !hash.has_expiring_fields())
+                }
+                _ => (Vec::new(), false, true),
+            };
+            if !expired.is_empty() {
+                self.notify_keyspace_event(NOTIFY_HASH, "hexpired", &key);
+                self.propagate_expiry([b"HDEL".to_vec(), key.clone()].into_iter().chain(expired).collect());
+                self.flush_propagation();
+            }
+            if emptied {
+                self.datastore.remove(&key);
+                self.notify_keyspace_event(NOTIFY_GENERIC, "del", &key);
+            }
+            if done {
+                self.hashes_with_field_ttl.remove(&key);
+                self.active_expire_cursor -= 1;
+            }
+        }
+    }
+}
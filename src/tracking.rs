@@ -0,0 +1,277 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    client::Client,
+    command::{syntax_error, Command},
+    resp::DataType,
+    state::State,
+};
+
+// Channel RESP2 connections subscribe to when another connection redirects its invalidations
+const INVALIDATE_CHANNEL: &[u8] = b"__redis__:invalidate";
+
+// Options given to CLIENT TRACKING ON
+#[derive(Debug, Clone, Default)]
+pub struct TrackingOptions {
+    redirect: Option<u64>,
+    // Broadcasting mode invalidates every key under the prefixes, read or not
+    bcast: bool,
+    prefixes: Vec<Vec<u8>>,
+    optin: bool,
+    optout: bool,
+    noloop: bool,
+}
+
+struct TrackingClient {
+    options: TrackingOptions,
+    // CLIENT CACHING yes|no for the connection's next command
+    caching: Option<bool>,
+}
+
+impl TrackingClient {
+    // Whether keys read by the next command should be remembered
+    fn remembers_reads(&self) -> bool {
+        match &self.options {
+            TrackingOptions { bcast: true, .. } => false,
+            TrackingOptions { optin: true, .. } => self.caching == Some(true),
+            TrackingOptions { optout: true, .. } => self.caching != Some(false),
+            _ => true,
+        }
+    }
+
+    fn matches_prefix(&self, key: &[u8]) -> bool {
+        self.options.prefixes.is_empty() || self.options.prefixes.iter().any(|prefix| key.starts_with(prefix))
+    }
+}
+
+// Client side caching state: which connections track keys and which keys each has read. While
+// a command runs for a tracking connection the keys it looks up are collected, and remembered
+// once it finishes if it turned out not to modify anything.
+#[derive(Default)]
+pub struct TrackingState {
+    clients: HashMap<u64, TrackingClient>,
+    keys: HashMap<Vec<u8>, HashSet<u64>>,
+    // Connection running the current command, and whether its reads are being collected
+    running: Option<u64>,
+    remembering: bool,
+    reads: Vec<Vec<u8>>,
+    wrote: bool,
+}
+
+impl TrackingState {
+    // Record a key lookup made by the running command
+    pub fn record_read(&mut self, key: &[u8]) {
+        if self.remembering {
+            self.reads.push(key.to_vec());
+        }
+    }
+
+    pub fn record_write(&mut self) {
+        self.wrote = true;
+    }
+
+    pub fn wrote(&self) -> bool {
+        self.wrote
+    }
+
+    pub fn running(&self) -> Option<u64> {
+        self.running
+    }
+
+    // Restore the write flag, for keys deleted by lazy expiry rather than by the command
+    pub fn set_wrote(&mut self, wrote: bool) {
+        self.wrote = wrote;
+    }
+
+    fn begin_command(&mut self, id: u64) {
+        self.running = Some(id);
+        self.wrote = false;
+        self.remembering = false;
+        if let Some(client) = self.clients.get_mut(&id) {
+            self.remembering = client.remembers_reads();
+            client.caching = None;
+        }
+    }
+
+    fn end_command(&mut self) {
+        let reads = std::mem::take(&mut self.reads);
+        if let (Some(id), true, false) = (self.running.take(), self.remembering, self.wrote) {
+            for key in reads {
+                self.keys.entry(key).or_default().insert(id);
+            }
+        }
+        self.remembering = false;
+    }
+
+    // Connections to notify about a modified key, consuming the key's entry in the table
+    fn interested_clients(&mut self, key: &[u8]) -> Vec<u64> {
+        let readers = self.keys.remove(key).unwrap_or_default();
+        self.clients.iter()
+            .filter(|(id, client)| if client.options.bcast { client.matches_prefix(key) } else { readers.contains(id) })
+            .filter(|(id, client)| !(client.options.noloop && self.running == Some(**id)))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    pub fn remove_client(&mut self, id: u64) {
+        self.clients.remove(&id);
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+}
+
+impl Command {
+    // CLIENT TRACKING ON|OFF [REDIRECT id] [PREFIX prefix ...] [BCAST] [OPTIN] [OPTOUT] [NOLOOP]
+    pub fn parse_client_tracking(args: &[Vec<u8>]) -> Command {
+        let enable = match args[2].to_ascii_lowercase().as_slice() {
+            b"on" => true,
+            b"off" => false,
+            _ => return syntax_error(),
+        };
+        let mut options = TrackingOptions::default();
+        let mut i = 3;
+        while let Some(arg) = args.get(i) {
+            match arg.to_ascii_lowercase().as_slice() {
+                b"redirect" => match args.get(i + 1).and_then(|id| std::str::from_utf8(id).ok()?.parse::<u64>().ok()) {
+                    Some(id) => {
+                        options.redirect = Some(id);
+                        i += 1;
+                    }
+                    None => return Command::INVALID("ERR Invalid client ID".to_string()),
+                },
+                b"prefix" if i + 1 < args.len() => {
+                    options.prefixes.push(args[i + 1].clone());
+                    i += 1;
+                }
+                b"bcast" => options.bcast = true,
+                b"optin" => options.optin = true,
+                b"optout" => options.optout = true,
+                b"noloop" => options.noloop = true,
+                _ => return syntax_error(),
+            }
+            i += 1;
+        }
+        if !options.bcast && !options.prefixes.is_empty() {
+            return Command::INVALID("ERR PREFIX option requires BCAST mode to be enabled".to_string());
+        }
+        if options.optin && options.optout {
+            return Command::INVALID("ERR You can't use both OPTIN and OPTOUT".to_string());
+        }
+        if options.bcast && (options.optin || options.optout) {
+            return Command::INVALID("ERR OPTIN and OPTOUT are not compatible with BCAST".to_string());
+        }
+        Command::CLIENTTRACKING(enable.then(|| Box::new(options)))
+    }
+
+    pub fn parse_client_caching(args: &[Vec<u8>]) -> Command {
+        match args[2].to_ascii_lowercase().as_slice() {
+            b"yes" => Command::CLIENTCACHING(true),
+            b"no" => Command::CLIENTCACHING(false),
+            _ => syntax_error(),
+        }
+    }
+}
+
+impl State {
+    // Run a command on behalf of a connection, remembering the keys it read if the connection
+    // is tracking them
+    pub fn execute_tracked(&mut self, id: u64, cmd: Command) -> DataType {
+        self.tracking.begin_command(id);
+        let reply = self.execute(cmd);
+        self.tracking.end_command();
+        reply
+    }
+
+    // Tell every interested connection that its cached copy of a key is stale
+    pub fn invalidate_key(&mut self, key: &[u8]) {
+        if self.tracking.clients.is_empty() {
+            return;
+        }
+        for id in self.tracking.interested_clients(key) {
+            self.send_invalidation(id, key);
+        }
+    }
+
+    // Invalidations go to the connection itself under RESP3, otherwise to the redirect target,
+    // which must be a RESP3 connection or a RESP2 one in subscribe mode
+    fn send_invalidation(&self, id: u64, key: &[u8]) {
+        let Some(tracker) = self.tracking.clients.get(&id) else {
+            return;
+        };
+        let target_id = tracker.options.redirect.unwrap_or(id);
+        let Some(target) = self.clients.get(&target_id) else {
+            if let Some(client) = self.clients.get(&id).filter(|client| client.protocol() == 3) {
+                let _ = client.send(DataType::Push(vec![
+                    DataType::BulkString(b"tracking-redir-broken".to_vec()),
+                    DataType::Integer(target_id as i64),
+                ]));
+            }
+            return;
+        };
+        let keys = DataType::bulk_array([key.to_vec()]);
+        let frame = if target.protocol() == 3 {
+            DataType::Push(vec![DataType::BulkString(b"invalidate".to_vec()), keys])
+        } else if tracker.options.redirect.is_some() && self.pubsub.is_subscriber(target_id) {
+            DataType::Push(vec![DataType::BulkString(b"message".to_vec()), DataType::BulkString(INVALIDATE_CHANNEL.to_vec()), keys])
+        } else {
+            return;
+        };
+        let _ = target.send_limited(frame, &self.output_buffer_limit(target_id));
+    }
+
+    pub fn client_tracking(&mut self, client: &Client, options: Option<Box<TrackingOptions>>) -> DataType {
+        let Some(options) = options else {
+            self.tracking.remove_client(client.id);
+            return DataType::ok();
+        };
+        if let Some(redirect) = options.redirect {
+            if !self.clients.contains_key(&redirect) {
+                return DataType::SimpleError("ERR The client ID you want redirect to does not exist".to_string());
+            }
+        }
+        // Switching modes would leave keys tracked under the old rules
+        if let Some(current) = self.tracking.clients.get(&client.id) {
+            if current.options.bcast != options.bcast {
+                return DataType::SimpleError(
+                    "ERR You can't switch BCAST mode on/off before disabling tracking for this client, and then re-enabling it with a different mode."
+                        .to_string(),
+                );
+            }
+            if current.options.optin != options.optin || current.options.optout != options.optout {
+                return DataType::SimpleError(
+                    "ERR You can't switch OPTIN/OPTOUT mode before disabling tracking for this client, and then re-enabling it with a different mode."
+                        .to_string(),
+                );
+            }
+        }
+        self.tracking.clients.insert(client.id, TrackingClient { options: *options, caching: None });
+        DataType::ok()
+    }
+
+    pub fn client_caching(&mut self, client: &Client, yes: bool) -> DataType {
+        let Some(tracker) = self.tracking.clients.get_mut(&client.id).filter(|tracker| tracker.options.optin || tracker.options.optout) else {
+            return DataType::SimpleError(
+                "ERR CLIENT CACHING can be called only when the client is in tracking mode with OPTIN or OPTOUT mode enabled".to_string(),
+            );
+        };
+        match (yes, tracker.options.optin) {
+            (true, false) => DataType::SimpleError("ERR CLIENT CACHING YES is only valid when tracking is enabled in OPTIN mode.".to_string()),
+            (false, true) => DataType::SimpleError("ERR CLIENT CACHING NO is only valid when tracking is enabled in OPTOUT mode.".to_string()),
+            _ => {
+                tracker.caching = Some(yes);
+                DataType::ok()
+            }
+        }
+    }
+
+    // -1 without tracking, 0 when invalidations go to the connection itself
+    pub fn client_getredir(&self, client: &Client) -> DataType {
+        let redirect = match self.tracking.clients.get(&client.id) {
+            Some(tracker) => tracker.options.redirect.map_or(0, |id| id as i64),
+            None => -1,
+        };
+        DataType::Integer(redirect)
+    }
+}
@@ -0,0 +1,82 @@
+use crate::{
+    client::Client,
+    command::Command,
+    resp::DataType,
+    state::State,
+};
+
+// Commands queued by a connection between MULTI and EXEC
+#[derive(Default)]
+pub struct Transaction {
+    // Writes keep the arguments they were sent with, to be propagated
+    commands: Vec<(Command, Option<Vec<Vec<u8>>>)>,
+    // Set when a command fails to queue, which makes EXEC discard the whole transaction
+    aborted: bool,
+}
+
+impl Client {
+    pub fn multi(&mut self) -> DataType {
+        if self.transaction.is_some() {
+            return DataType::SimpleError("ERR MULTI calls can not be nested".to_string());
+        }
+        self.transaction = Some(Transaction::default());
+        DataType::ok()
+    }
+
+    pub fn discard(&mut self) -> DataType {
+        match self.transaction.take() {
+            Some(_) => DataType::ok(),
+            None => DataType::SimpleError("ERR DISCARD without MULTI".to_string()),
+        }
+    }
+
+    // Hold a command until EXEC, only called after MULTI. Commands that failed to parse are
+    // rejected straight away and doom the transaction.
+    pub fn queue(&mut self, cmd: Command, args: Option<Vec<Vec<u8>>>) -> DataType {
+        let transaction = self.transaction.as_mut().expect("no transaction in progress");
+        // Switching masters and becoming a replica take over the connection, they can't wait
+        // for EXEC
+        let msg = match cmd {
+            Command::INVALID(msg) => msg,
+            Command::REPLICAOF(_) | Command::FAILOVER(..) | Command::FAILOVERABORT | Command::PSYNC(..) => "ERR Command not allowed inside a transaction".to_string(),
+            cmd => {
+                transaction.commands.push((cmd, args));
+                return DataType::SimpleString("QUEUED".to_string());
+            }
+        };
+        transaction.aborted = true;
+        DataType::SimpleError(msg)
+    }
+}
+
+impl State {
+    // Run the queued commands back to back, with nothing from other connections in between.
+    // Blocking commands don't wait inside a transaction, they reply as if they timed out. A
+    // command failing at run time only puts its error in the reply array, the rest still run.
+    // The writes are queued for propagation together, so they go out as one MULTI/EXEC.
+    pub fn execute_transaction(&mut self, client: &Client, transaction: Transaction) -> DataType {
+        if transaction.aborted {
+            return DataType::SimpleError("EXECABORT Transaction discarded because of previous errors.".to_string());
+        }
+        let replies = transaction.commands.into_iter().map(|(cmd, args)| match cmd {
+            Command::HELLO(protocol, auth) => client.hello(protocol, auth, self.role()),
+            // Waiting would hold up every other client, so it reports how things stand
+            Command::WAITAOF(numlocal, ..) => self.waitaof_counts(numlocal, client.write_offset),
+            cmd if cmd.is_connection() => {
+                let mut replies = self.execute_connection(client, cmd);
+                match replies.len() {
+                    1 => replies.pop().unwrap(),
+                    _ => DataType::Array(replies),
+                }
+            }
+            cmd => {
+                let reply = self.execute_tracked(client.id, cmd);
+                if let Some(args) = args {
+                    self.propagate_command(args, &reply);
+                }
+                reply
+            }
+        });
+        DataType::Array(replies.collect())
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    f64::consts::LN_2,
+    hash::Hasher,
+};
+
+use anyhow::{Error, Result};
+
+use crate::rdb::{ModuleReader, ModuleWriter};
+
+// Each layer added when a filter scales gets a tighter error rate, so the compound false positive
+// probability stays bounded by the rate the filter was created with
+const ERROR_TIGHTENING_RATIO: f64 = 0.5;
+
+// Layers are capped at the size of the largest string, so a filter is never too big to allocate
+const MAX_LAYER_BYTES: u64 = 512 << 20;
+
+const TOO_LARGE_ERROR: &str = "ERR filter would be larger than the 512mb limit";
+
+// A fixed size bloom filter, sized for `capacity` items at the given false positive rate
+#[derive(Debug, Clone)]
+struct BloomLayer {
+    bits: Vec<u8>,
+    hashes: u32,
+    capacity: u64,
+    error_rate: f64,
+    items: u64,
+}
+
+impl BloomLayer {
+    // None if the layer would be over the size limit
+    fn new(capacity: u64, error_rate: f64) -> Option<Self> {
+        let bits_per_item = -error_rate.ln() / (LN_2 * LN_2);
+        let bits = ((capacity as f64 * bits_per_item).ceil() as u64).max(8);
+        if bits.div_ceil(8) > MAX_LAYER_BYTES {
+            return None;
+        }
+        let hashes = (bits_per_item * LN_2).ceil().max(1.0) as u32;
+        Some(BloomLayer { bits: vec![0; bits.div_ceil(8) as usize], hashes, capacity, error_rate, items: 0 })
+    }
+
+    // Bit positions for an item, using double hashing to derive every probe from two hashes
+    fn positions(&self, (h1, h2): (u64, u64)) -> impl Iterator<Item = usize> + '_ {
+        let bits = self.bits.len() as u64 * 8;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bits) as usize)
+    }
+
+    fn contains(&self, hash: (u64, u64)) -> bool {
+        self.positions(hash).all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    fn insert(&mut self, hash: (u64, u64)) {
+        let positions: Vec<usize> = self.positions(hash).collect();
+        for bit in positions {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+        self.items += 1;
+    }
+
+    fn is_full(&self) -> bool {
+        self.items >= self.capacity
+    }
+}
+
+fn item_hash(item: &[u8]) -> (u64, u64) {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(item);
+    let h1 = hasher.finish();
+    hasher.write_u8(0xff);
+    // An odd step never cycles back to the first probe early
+    (h1, hasher.finish() | 1)
+}
+
+// Scalable bloom filter in the style of RedisBloom: once the newest layer reaches its capacity a
+// larger one is stacked on top, unless the filter was created as non scaling
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    layers: Vec<BloomLayer>,
+    // Capacity growth factor for new layers, None when the filter does not scale
+    expansion: Option<u32>,
+}
+
+impl BloomFilter {
+    pub fn new(error_rate: f64, capacity: u64, expansion: Option<u32>) -> Result<Self, &'static str> {
+        let layer = BloomLayer::new(capacity, error_rate).ok_or(TOO_LARGE_ERROR)?;
+        Ok(BloomFilter { layers: vec![layer], expansion })
+    }
+
+    pub fn contains(&self, item: &[u8]) -> bool {
+        let hash = item_hash(item);
+        self.layers.iter().any(|layer| layer.contains(hash))
+    }
+
+    // Whether the item was newly added, or an error if the filter is full and can't scale, either
+    // because it is non scaling or because another layer would be over the size limit
+    pub fn insert(&mut self, item: &[u8]) -> Result<bool, &'static str> {
+        let hash = item_hash(item);
+        if self.layers.iter().any(|layer| layer.contains(hash)) {
+            return Ok(false);
+        }
+        let last = self.layers.last().unwrap();
+        if last.is_full() {
+            let Some(expansion) = self.expansion else {
+                return Err("ERR non scaling filter is full");
+            };
+            let layer = last.capacity.checked_mul(expansion as u64)
+                .and_then(|capacity| BloomLayer::new(capacity, last.error_rate * ERROR_TIGHTENING_RATIO))
+                .ok_or(TOO_LARGE_ERROR)?;
+            self.layers.push(layer);
+        }
+        self.layers.last_mut().unwrap().insert(hash);
+        Ok(true)
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.layers.iter().map(|layer| layer.capacity).sum()
+    }
+
+    // Approximate memory used, in bytes
+    pub fn size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.layers.iter().map(|layer| std::mem::size_of::<BloomLayer>() + layer.bits.len()).sum::<usize>()
+    }
+
+    pub fn filters(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn items(&self) -> u64 {
+        self.layers.iter().map(|layer| layer.items).sum()
+    }
+
+    pub fn expansion(&self) -> Option<u32> {
+        self.expansion
+    }
+
+    pub fn rdb_save(&self, out: &mut ModuleWriter) {
+        out.unsigned(self.expansion.unwrap_or(0) as u64);
+        out.unsigned(self.layers.len() as u64);
+        for layer in self.layers.iter() {
+            out.unsigned(layer.capacity);
+            out.double(layer.error_rate);
+            out.unsigned(layer.hashes as u64);
+            out.unsigned(layer.items);
+            out.string(&layer.bits);
+        }
+    }
+
+    pub fn rdb_load(input: &mut ModuleReader) -> Result<Self> {
+        let expansion = Some(input.unsigned()? as u32).filter(|expansion| *expansion > 0);
+        let mut layers = Vec::new();
+        for _ in 0..input.unsigned()? {
+            let capacity = input.unsigned()?;
+            let error_rate = input.double()?;
+            let hashes = input.unsigned()? as u32;
+            let items = input.unsigned()?;
+            let bits = input.string()?;
+            if bits.is_empty() {
+                return Err(Error::msg("Bad RDB file: empty bloom filter layer"));
+            }
+            layers.push(BloomLayer { bits, hashes, capacity, error_rate, items });
+        }
+        if layers.is_empty() {
+            return Err(Error::msg("Bad RDB file: bloom filter without layers"));
+        }
+        Ok(BloomFilter { layers, expansion })
+    }
+}
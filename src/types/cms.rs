@@ -0,0 +1,92 @@
+use anyhow::{Error, Result};
+
+use crate::{
+    rdb::{ModuleReader, ModuleWriter},
+    types::seeded_hash,
+};
+
+// Count-min sketch: `depth` rows of `width` counters, each row indexed by its own hash. Every
+// counter an item maps to is at least its true count, so the smallest one is the estimate.
+#[derive(Debug, Clone)]
+pub struct CountMinSketch {
+    width: usize,
+    depth: usize,
+    counters: Vec<u64>,
+    // Sum of all increments
+    count: u64,
+}
+
+impl CountMinSketch {
+    pub fn new(width: usize, depth: usize) -> Self {
+        CountMinSketch { width, depth, counters: vec![0; width * depth], count: 0 }
+    }
+
+    // Dimensions giving estimates within `error` of the total count with probability 1 - `probability`
+    pub fn dimensions_for(error: f64, probability: f64) -> (usize, usize) {
+        let width = (2.0 / error).ceil() as usize;
+        let depth = (probability.ln() / 0.5f64.ln()).ceil().max(1.0) as usize;
+        (width, depth)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    fn cells(&self, item: &[u8]) -> Vec<usize> {
+        (0..self.depth).map(|row| row * self.width + (seeded_hash(item, row as u64) % self.width as u64) as usize).collect()
+    }
+
+    // Add to the item's count and return the new estimate
+    pub fn increment(&mut self, item: &[u8], by: u64) -> u64 {
+        for cell in self.cells(item) {
+            self.counters[cell] = self.counters[cell].saturating_add(by);
+        }
+        self.count = self.count.saturating_add(by);
+        self.query(item)
+    }
+
+    pub fn query(&self, item: &[u8]) -> u64 {
+        self.cells(item).into_iter().map(|cell| self.counters[cell]).min().unwrap_or(0)
+    }
+
+    // Replace the counters with a weighted sum of sketches of the same dimensions
+    pub fn merge(&mut self, sources: &[(&CountMinSketch, i64)]) {
+        let mut counters = vec![0u64; self.counters.len()];
+        let mut count = 0u64;
+        for (source, weight) in sources {
+            for (total, counter) in counters.iter_mut().zip(&source.counters) {
+                *total = total.wrapping_add(counter.wrapping_mul(*weight as u64));
+            }
+            count = count.wrapping_add(source.count.wrapping_mul(*weight as u64));
+        }
+        self.counters = counters;
+        self.count = count;
+    }
+
+    pub fn rdb_save(&self, out: &mut ModuleWriter) {
+        out.unsigned(self.width as u64);
+        out.unsigned(self.depth as u64);
+        out.unsigned(self.count);
+        out.string(&self.counters.iter().flat_map(|counter| counter.to_le_bytes()).collect::<Vec<u8>>());
+    }
+
+    pub fn rdb_load(input: &mut ModuleReader) -> Result<Self> {
+        let width = input.unsigned()? as usize;
+        let depth = input.unsigned()? as usize;
+        let count = input.unsigned()?;
+        let counters = input.string()?;
+        if width == 0 || depth == 0 || counters.len() != width * depth * 8 {
+            return Err(Error::msg("Bad RDB file: count-min sketch of the wrong size"));
+        }
+        let counters = counters.chunks_exact(8).map(|counter| u64::from_le_bytes(counter.try_into().unwrap())).collect();
+        Ok(CountMinSketch { width, depth, counters, count })
+    }
+}
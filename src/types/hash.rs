@@ -0,0 +1,176 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{clock::now_ms, config::EncodingLimits, types::listpack::ListPack};
+
+// Small hashes keep their fields and values interleaved in a listpack, and are upgraded to a
+// hash table once they exceed hash-max-listpack-entries or hash-max-listpack-value
+#[derive(Debug, Clone)]
+enum Fields {
+    ListPack(ListPack),
+    HashTable(HashMap<Vec<u8>, Vec<u8>>),
+}
+
+impl Default for Fields {
+    fn default() -> Self {
+        Fields::ListPack(ListPack::default())
+    }
+}
+
+// Hash value with optional per-field expiration. Field deadlines are mirrored in an ordered
+// index so expired fields can be found without walking the whole hash.
+#[derive(Debug, Clone, Default)]
+pub struct Hash {
+    fields: Fields,
+    expires: HashMap<Vec<u8>, u64>,
+    expiry_index: BTreeSet<(u64, Vec<u8>)>,
+}
+
+impl Hash {
+    pub fn len(&self) -> usize {
+        match &self.fields {
+            Fields::ListPack(pack) => pack.len() / 2,
+            Fields::HashTable(fields) => fields.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn encoding(&self) -> &'static str {
+        match self.fields {
+            Fields::ListPack(_) => "listpack",
+            Fields::HashTable(_) => "hashtable",
+        }
+    }
+
+    // Index of a field within the listpack, values follow at the next index
+    fn listpack_position(pack: &ListPack, field: &[u8]) -> Option<usize> {
+        pack.iter().step_by(2).position(|candidate| candidate == field).map(|pair| pair * 2)
+    }
+
+    pub fn get(&self, field: &[u8]) -> Option<&[u8]> {
+        match &self.fields {
+            Fields::ListPack(pack) => Hash::listpack_position(pack, field).and_then(|pos| pack.get(pos + 1)),
+            Fields::HashTable(fields) => fields.get(field).map(|value| value.as_slice()),
+        }
+    }
+
+    pub fn contains_key(&self, field: &[u8]) -> bool {
+        self.get(field).is_some()
+    }
+
+    // Setting a field's value clears any expiration it had
+    pub fn insert(&mut self, field: Vec<u8>, value: Vec<u8>, limits: &EncodingLimits) -> Option<Vec<u8>> {
+        self.persist(&field);
+        if let Fields::ListPack(pack) = &self.fields {
+            let exists = Hash::listpack_position(pack, &field).is_some();
+            let entries = self.len() + !exists as usize;
+            if entries > limits.hash_max_listpack_entries
+                || field.len() > limits.hash_max_listpack_value
+                || value.len() > limits.hash_max_listpack_value
+            {
+                self.convert_to_hashtable();
+            }
+        }
+        match &mut self.fields {
+            Fields::ListPack(pack) => match Hash::listpack_position(pack, &field) {
+                Some(pos) => {
+                    let old = pack.get(pos + 1).map(|old| old.to_vec());
+                    pack.replace(pos + 1, &value);
+                    old
+                }
+                None => {
+                    pack.push_back(&field);
+                    pack.push_back(&value);
+                    None
+                }
+            },
+            Fields::HashTable(fields) => fields.insert(field, value),
+        }
+    }
+
+    pub fn remove(&mut self, field: &[u8]) -> Option<Vec<u8>> {
+        self.persist(field);
+        match &mut self.fields {
+            Fields::ListPack(pack) => {
+                let pos = Hash::listpack_position(pack, field)?;
+                let value = pack.remove(pos + 1);
+                pack.remove(pos);
+                value
+            }
+            Fields::HashTable(fields) => fields.remove(field),
+        }
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&[u8], &[u8])> + '_> {
+        match &self.fields {
+            Fields::ListPack(pack) => Box::new(pack.iter().step_by(2).zip(pack.iter().skip(1).step_by(2))),
+            Fields::HashTable(fields) => Box::new(fields.iter().map(|(field, value)| (field.as_slice(), value.as_slice()))),
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &[u8]> {
+        self.iter().map(|(field, _)| field)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &[u8]> {
+        self.iter().map(|(_, value)| value)
+    }
+
+    fn convert_to_hashtable(&mut self) {
+        if let Fields::ListPack(_) = self.fields {
+            let fields = self.iter().map(|(field, value)| (field.to_vec(), value.to_vec())).collect();
+            self.fields = Fields::HashTable(fields);
+        }
+    }
+
+    pub fn expiry(&self, field: &[u8]) -> Option<u64> {
+        self.expires.get(field).copied()
+    }
+
+    pub fn has_expiring_fields(&self) -> bool {
+        !self.expires.is_empty()
+    }
+
+    pub fn set_expiry(&mut self, field: &[u8], expiry: u64) {
+        self.persist(field);
+        self.expires.insert(field.to_vec(), expiry);
+        self.expiry_index.insert((expiry, field.to_vec()));
+    }
+
+    // Clear a field's expiration, returning whether it had one
+    pub fn persist(&mut self, field: &[u8]) -> bool {
+        match self.expires.remove(field) {
+            Some(expiry) => {
+                self.expiry_index.remove(&(expiry, field.to_vec()));
+                true
+            }
+            None => false,
+        }
+    }
+
+    // A copy without the fields whose deadline has passed, None when there are none
+    pub fn without_expired(&self) -> Option<Hash> {
+        let now = now_ms();
+        self.expiry_index.first().filter(|(expiry, _)| *expiry <= now)?;
+        let mut hash = self.clone();
+        hash.remove_expired();
+        Some(hash)
+    }
+
+    // Drop every field whose deadline has passed, returning the fields removed
+    pub fn remove_expired(&mut self) -> Vec<Vec<u8>> {
+        let now = now_ms();
+        let mut removed = Vec::new();
+        while let Some((expiry, _)) = self.expiry_index.first() {
+            if *expiry > now {
+                break;
+            }
+            let (_, field) = self.expiry_index.pop_first().unwrap();
+            self.remove(&field);
+            removed.push(field);
+        }
+        removed
+    }
+}
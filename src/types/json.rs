@@ -0,0 +1,413 @@
+use std::fmt::Write;
+
+use crate::rdb::{ModuleReader, ModuleWriter};
+
+// Documents nested deeper than this are rejected rather than risking the parser's stack
+const MAX_DEPTH: usize = 128;
+
+// A parsed JSON document. Objects keep their members in insertion order, the way RedisJSON
+// reports them, and numbers stay integers when written without a fraction or exponent.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+// Location of a value inside a document, one step per level
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PathStep {
+    Key(String),
+    Index(usize),
+}
+
+// Whitespace JSON.GET puts around nested values, all empty for the compact form
+#[derive(Debug, Clone, Default)]
+pub struct JsonFormat {
+    pub indent: String,
+    pub newline: String,
+    pub space: String,
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    // Errors name the position the way serde_json does, e.g. "expected value at line 1 column 5"
+    fn error(&self, msg: &str) -> String {
+        let consumed = &self.text[..self.pos.min(self.text.len())];
+        let line = consumed.matches('\n').count() + 1;
+        let column = consumed.len() - consumed.rfind('\n').map_or(0, |newline| newline + 1) + 1;
+        format!("{} at line {} column {}", msg, line, column)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect_literal(&mut self, literal: &str, value: Json) -> Result<Json, String> {
+        if self.text[self.pos..].starts_with(literal) {
+            self.pos += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error("expected ident"))
+        }
+    }
+
+    fn parse_value(&mut self, depth: usize) -> Result<Json, String> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("recursion limit exceeded"));
+        }
+        self.skip_whitespace();
+        match self.peek() {
+            None => Err(self.error("EOF while parsing a value")),
+            Some(b'n') => self.expect_literal("null", Json::Null),
+            Some(b't') => self.expect_literal("true", Json::Bool(true)),
+            Some(b'f') => self.expect_literal("false", Json::Bool(false)),
+            Some(b'"') => Ok(Json::String(self.parse_string()?)),
+            Some(b'[') => self.parse_array(depth),
+            Some(b'{') => self.parse_object(depth),
+            Some(b'-' | b'0'..=b'9') => self.parse_number(),
+            Some(_) => Err(self.error("expected value")),
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        let bytes = self.text.as_bytes();
+        let digits = |pos: &mut usize| {
+            let from = *pos;
+            while bytes.get(*pos).is_some_and(u8::is_ascii_digit) {
+                *pos += 1;
+            }
+            *pos - from
+        };
+        let mut pos = self.pos;
+        if bytes[pos] == b'-' {
+            pos += 1;
+        }
+        let integer_start = pos;
+        if digits(&mut pos) == 0 || (bytes[integer_start] == b'0' && pos - integer_start > 1) {
+            self.pos = pos;
+            return Err(self.error("invalid number"));
+        }
+        let mut is_float = false;
+        if bytes.get(pos) == Some(&b'.') {
+            pos += 1;
+            is_float = true;
+            if digits(&mut pos) == 0 {
+                self.pos = pos;
+                return Err(self.error("invalid number"));
+            }
+        }
+        if matches!(bytes.get(pos), Some(b'e' | b'E')) {
+            pos += 1;
+            is_float = true;
+            if matches!(bytes.get(pos), Some(b'+' | b'-')) {
+                pos += 1;
+            }
+            if digits(&mut pos) == 0 {
+                self.pos = pos;
+                return Err(self.error("invalid number"));
+            }
+        }
+        self.pos = pos;
+        let literal = &self.text[start..pos];
+        if !is_float {
+            if let Ok(value) = literal.parse::<i64>() {
+                return Ok(Json::Integer(value));
+            }
+        }
+        match literal.parse::<f64>() {
+            Ok(value) if value.is_finite() => Ok(Json::Float(value)),
+            _ => Err(self.error("number out of range")),
+        }
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, String> {
+        let hex = self.text.get(self.pos..self.pos + 4).filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()));
+        let Some(hex) = hex else {
+            return Err(self.error("invalid escape"));
+        };
+        self.pos += 4;
+        Ok(u32::from_str_radix(hex, 16).unwrap())
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            let rest = &self.text[self.pos..];
+            let Some(special) = rest.find(|c: char| c == '"' || c == '\\' || c < ' ') else {
+                self.pos = self.text.len();
+                return Err(self.error("EOF while parsing a string"));
+            };
+            out.push_str(&rest[..special]);
+            self.pos += special;
+            match self.text.as_bytes()[self.pos] {
+                b'"' => {
+                    self.pos += 1;
+                    return Ok(out);
+                }
+                b'\\' => {
+                    self.pos += 1;
+                    let escape = self.peek();
+                    self.pos += 1;
+                    match escape {
+                        Some(b'"') => out.push('"'),
+                        Some(b'\\') => out.push('\\'),
+                        Some(b'/') => out.push('/'),
+                        Some(b'b') => out.push('\u{8}'),
+                        Some(b'f') => out.push('\u{c}'),
+                        Some(b'n') => out.push('\n'),
+                        Some(b'r') => out.push('\r'),
+                        Some(b't') => out.push('\t'),
+                        Some(b'u') => {
+                            let mut code = self.parse_hex4()?;
+                            if (0xD800..0xDC00).contains(&code) && self.text[self.pos..].starts_with("\\u") {
+                                // High surrogate, combine with the low half that follows
+                                self.pos += 2;
+                                let low = self.parse_hex4()?;
+                                if !(0xDC00..0xE000).contains(&low) {
+                                    return Err(self.error("lone leading surrogate in hex escape"));
+                                }
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                            match char::from_u32(code) {
+                                Some(c) => out.push(c),
+                                None => return Err(self.error("lone leading surrogate in hex escape")),
+                            }
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                }
+                _ => return Err(self.error("control character (\\u0000-\\u001F) found while parsing a string")),
+            }
+        }
+    }
+
+    fn parse_array(&mut self, depth: usize) -> Result<Json, String> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.parse_value(depth + 1)?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                None => return Err(self.error("EOF while parsing a list")),
+                Some(_) => return Err(self.error("expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn parse_object(&mut self, depth: usize) -> Result<Json, String> {
+        self.pos += 1;
+        let mut members: Vec<(String, Json)> = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b'"') => (),
+                None => return Err(self.error("EOF while parsing an object")),
+                Some(_) => return Err(self.error("key must be a string")),
+            }
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            if self.peek() != Some(b':') {
+                return Err(self.error("expected `:`"));
+            }
+            self.pos += 1;
+            let value = self.parse_value(depth + 1)?;
+            // Later duplicates win, as with any JSON object
+            match members.iter_mut().find(|(name, _)| *name == key) {
+                Some(member) => member.1 = value,
+                None => members.push((key, value)),
+            }
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                None => return Err(self.error("EOF while parsing an object")),
+                Some(_) => return Err(self.error("expected `,` or `}`")),
+            }
+        }
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if c < ' ' => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+impl Json {
+    pub fn parse(text: &[u8]) -> Result<Json, String> {
+        let Ok(text) = std::str::from_utf8(text) else {
+            return Err("invalid UTF-8 in JSON text".to_string());
+        };
+        let mut parser = Parser { text, pos: 0 };
+        let value = parser.parse_value(0)?;
+        parser.skip_whitespace();
+        if parser.pos < text.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    // Type name reported by JSON.TYPE
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Bool(_) => "boolean",
+            Json::Integer(_) => "integer",
+            Json::Float(_) => "number",
+            Json::String(_) => "string",
+            Json::Array(_) => "array",
+            Json::Object(_) => "object",
+        }
+    }
+
+    pub fn serialize(&self, format: &JsonFormat) -> String {
+        let mut out = String::new();
+        self.write(&mut out, format, 0);
+        out
+    }
+
+    // Documents are saved as their compact text
+    pub fn rdb_save(&self, out: &mut ModuleWriter) {
+        out.string(self.serialize(&JsonFormat::default()).as_bytes());
+    }
+
+    pub fn rdb_load(input: &mut ModuleReader) -> anyhow::Result<Self> {
+        Json::parse(&input.string()?).map_err(|msg| anyhow::Error::msg(format!("Bad RDB file: {}", msg)))
+    }
+
+    fn write(&self, out: &mut String, format: &JsonFormat, depth: usize) {
+        let line = |out: &mut String, depth: usize| {
+            out.push_str(&format.newline);
+            for _ in 0..depth {
+                out.push_str(&format.indent);
+            }
+        };
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(value) => write!(out, "{}", value).unwrap(),
+            Json::Integer(value) => write!(out, "{}", value).unwrap(),
+            // Debug formatting gives the shortest round trip form and always marks floats
+            Json::Float(value) => write!(out, "{:?}", value).unwrap(),
+            Json::String(value) => write_string(out, value),
+            Json::Array(items) if items.is_empty() => out.push_str("[]"),
+            Json::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    line(out, depth + 1);
+                    item.write(out, format, depth + 1);
+                }
+                line(out, depth);
+                out.push(']');
+            }
+            Json::Object(members) if members.is_empty() => out.push_str("{}"),
+            Json::Object(members) => {
+                out.push('{');
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    line(out, depth + 1);
+                    write_string(out, key);
+                    out.push(':');
+                    out.push_str(&format.space);
+                    value.write(out, format, depth + 1);
+                }
+                line(out, depth);
+                out.push('}');
+            }
+        }
+    }
+
+    pub fn get_path_mut(&mut self, path: &[PathStep]) -> Option<&mut Json> {
+        let Some((step, rest)) = path.split_first() else {
+            return Some(self);
+        };
+        let child = match (self, step) {
+            (Json::Object(members), PathStep::Key(key)) => members.iter_mut().find(|(name, _)| name == key).map(|(_, value)| value),
+            (Json::Array(items), PathStep::Index(index)) => items.get_mut(*index),
+            _ => None,
+        };
+        child?.get_path_mut(rest)
+    }
+
+    // Set a member of an object, adding it at the end if it is new
+    pub fn insert(&mut self, key: &str, value: Json) -> bool {
+        let Json::Object(members) = self else {
+            return false;
+        };
+        match members.iter_mut().find(|(name, _)| name == key) {
+            Some(member) => member.1 = value,
+            None => members.push((key.to_string(), value)),
+        }
+        true
+    }
+
+    // Remove the value at a non empty path
+    pub fn remove_path(&mut self, path: &[PathStep]) -> bool {
+        let Some((last, parent)) = path.split_last() else {
+            return false;
+        };
+        match (self.get_path_mut(parent), last) {
+            (Some(Json::Object(members)), PathStep::Key(key)) => {
+                let before = members.len();
+                members.retain(|(name, _)| name != key);
+                members.len() < before
+            }
+            (Some(Json::Array(items)), PathStep::Index(index)) if *index < items.len() => {
+                items.remove(*index);
+                true
+            }
+            _ => false,
+        }
+    }
+}
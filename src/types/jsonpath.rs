@@ -0,0 +1,285 @@
+use crate::types::json::{Json, PathStep};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Selector {
+    Key(String),
+    Index(i64),
+    Slice(Option<i64>, Option<i64>),
+    Wildcard,
+    Union(Vec<Selector>),
+    // `..`, every value at or below the current ones, for the next selector to filter
+    Descendants,
+}
+
+// A parsed path argument of the JSON.* commands. JSONPath starts with `$`; anything else is the
+// legacy dotted syntax, where `.` is the root, and commands reply with a single value for it
+// instead of an array of every match.
+#[derive(Debug, Clone)]
+pub struct JsonPath {
+    text: String,
+    selectors: Vec<Selector>,
+    legacy: bool,
+}
+
+fn path_error(pos: usize) -> String {
+    format!("ERR Error occurred on position {}", pos + 1)
+}
+
+struct PathParser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl PathParser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).copied()
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek() == Some(b' ') {
+            self.pos += 1;
+        }
+    }
+
+    // Member name after a dot, which runs until the next dot or bracket
+    fn name(&mut self) -> Result<Selector, String> {
+        let start = self.pos;
+        while self.peek().is_some_and(|b| b != b'.' && b != b'[') {
+            self.pos += 1;
+        }
+        match &self.text[start..self.pos] {
+            [] => Err(path_error(start)),
+            b"*" => Ok(Selector::Wildcard),
+            name => Ok(Selector::Key(String::from_utf8_lossy(name).into_owned())),
+        }
+    }
+
+    fn integer(&mut self) -> Option<i64> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        while self.peek().is_some_and(|b| b.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.text[start..self.pos]).ok()?.parse().ok()
+    }
+
+    fn quoted(&mut self) -> Result<Selector, String> {
+        let quote = self.text[self.pos];
+        let start = self.pos;
+        self.pos += 1;
+        let mut name = Vec::new();
+        loop {
+            match self.peek() {
+                None => return Err(path_error(start)),
+                Some(b'\\') if self.text.get(self.pos + 1).is_some() => {
+                    name.push(self.text[self.pos + 1]);
+                    self.pos += 2;
+                }
+                Some(b) if b == quote => {
+                    self.pos += 1;
+                    return Ok(Selector::Key(String::from_utf8_lossy(&name).into_owned()));
+                }
+                Some(b) => {
+                    name.push(b);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    // One entry of a bracket expression: a quoted name, an index, a slice or `*`
+    fn bracket_item(&mut self) -> Result<Selector, String> {
+        self.skip_spaces();
+        match self.peek() {
+            Some(b'*') => {
+                self.pos += 1;
+                Ok(Selector::Wildcard)
+            }
+            Some(b'\'' | b'"') => self.quoted(),
+            Some(b'-' | b'0'..=b'9' | b':') => {
+                let start = if self.peek() == Some(b':') { None } else { Some(self.integer().ok_or_else(|| path_error(self.pos))?) };
+                self.skip_spaces();
+                if self.peek() != Some(b':') {
+                    return start.map(Selector::Index).ok_or_else(|| path_error(self.pos));
+                }
+                self.pos += 1;
+                self.skip_spaces();
+                let end = if self.peek().is_some_and(|b| b == b'-' || b.is_ascii_digit()) { self.integer() } else { None };
+                Ok(Selector::Slice(start, end))
+            }
+            _ => Err(path_error(self.pos)),
+        }
+    }
+
+    fn bracket(&mut self) -> Result<Selector, String> {
+        self.pos += 1;
+        let mut items = vec![self.bracket_item()?];
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                Some(b',') => {
+                    self.pos += 1;
+                    items.push(self.bracket_item()?);
+                }
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(if items.len() == 1 { items.pop().unwrap() } else { Selector::Union(items) });
+                }
+                _ => return Err(path_error(self.pos)),
+            }
+        }
+    }
+
+    fn selectors(&mut self) -> Result<Vec<Selector>, String> {
+        let mut selectors = Vec::new();
+        while let Some(b) = self.peek() {
+            match b {
+                b'.' if self.text.get(self.pos + 1) == Some(&b'.') => {
+                    self.pos += 2;
+                    selectors.push(Selector::Descendants);
+                    if self.peek() != Some(b'[') {
+                        selectors.push(self.name()?);
+                    }
+                }
+                b'.' => {
+                    self.pos += 1;
+                    selectors.push(self.name()?);
+                }
+                b'[' => selectors.push(self.bracket()?),
+                _ => return Err(path_error(self.pos)),
+            }
+        }
+        Ok(selectors)
+    }
+}
+
+// Resolve a possibly negative index against an array length
+fn normalize_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
+
+fn descendants<'a>(path: Vec<PathStep>, value: &'a Json, out: &mut Vec<(Vec<PathStep>, &'a Json)>) {
+    out.push((path.clone(), value));
+    for (step, child) in children(value) {
+        let mut child_path = path.clone();
+        child_path.push(step);
+        descendants(child_path, child, out);
+    }
+}
+
+fn children(value: &Json) -> Vec<(PathStep, &Json)> {
+    match value {
+        Json::Array(items) => items.iter().enumerate().map(|(i, item)| (PathStep::Index(i), item)).collect(),
+        Json::Object(members) => members.iter().map(|(key, value)| (PathStep::Key(key.clone()), value)).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn apply<'a>(selector: &Selector, path: &[PathStep], value: &'a Json, out: &mut Vec<(Vec<PathStep>, &'a Json)>) {
+    let mut push = |step: PathStep, child: &'a Json| {
+        let mut child_path = path.to_vec();
+        child_path.push(step);
+        out.push((child_path, child));
+    };
+    match (selector, value) {
+        (Selector::Key(key), Json::Object(members)) => {
+            if let Some((name, child)) = members.iter().find(|(name, _)| name == key) {
+                push(PathStep::Key(name.clone()), child);
+            }
+        }
+        (Selector::Index(index), Json::Array(items)) => {
+            if let Some(index) = normalize_index(*index, items.len()) {
+                push(PathStep::Index(index), &items[index]);
+            }
+        }
+        (Selector::Slice(start, end), Json::Array(items)) => {
+            let len = items.len() as i64;
+            let clamp = |bound: i64| if bound < 0 { (len + bound).max(0) } else { bound.min(len) };
+            let start = start.map_or(0, clamp);
+            let end = end.map_or(len, clamp);
+            for index in start..end {
+                push(PathStep::Index(index as usize), &items[index as usize]);
+            }
+        }
+        (Selector::Wildcard, _) => {
+            for (step, child) in children(value) {
+                push(step, child);
+            }
+        }
+        (Selector::Union(selectors), _) => {
+            for selector in selectors {
+                apply(selector, path, value, out);
+            }
+        }
+        (Selector::Descendants, _) => descendants(path.to_vec(), value, out),
+        _ => (),
+    }
+}
+
+impl JsonPath {
+    pub fn parse(text: &[u8]) -> Result<JsonPath, String> {
+        let legacy = text.first() != Some(&b'$');
+        let rest = if legacy {
+            // `.` alone is the root, and a leading member name may skip its dot
+            match text {
+                b"." => Vec::new(),
+                [b'.' | b'[', ..] => text.to_vec(),
+                _ => [b".", text].concat(),
+            }
+        } else {
+            text[1..].to_vec()
+        };
+        let mut parser = PathParser { text: &rest, pos: 0 };
+        let selectors = parser.selectors()?;
+        Ok(JsonPath { text: String::from_utf8_lossy(text).into_owned(), selectors, legacy })
+    }
+
+    pub fn root() -> JsonPath {
+        JsonPath { text: "$".to_string(), selectors: Vec::new(), legacy: false }
+    }
+
+    pub fn legacy_root() -> JsonPath {
+        JsonPath { text: ".".to_string(), selectors: Vec::new(), legacy: true }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.legacy
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.selectors.is_empty()
+    }
+
+    // Every value the path matches, with its location in the document
+    pub fn select<'a>(&self, root: &'a Json) -> Vec<(Vec<PathStep>, &'a Json)> {
+        let mut current = vec![(Vec::new(), root)];
+        for selector in &self.selectors {
+            let mut next = Vec::new();
+            for (path, value) in &current {
+                apply(selector, path, value, &mut next);
+            }
+            current = next;
+        }
+        current
+    }
+
+    // For a path ending in a plain member name, the path to its parent objects and that name,
+    // which is where JSON.SET adds members that do not exist yet
+    pub fn split_last_key(&self) -> Option<(JsonPath, &str)> {
+        let (Selector::Key(key), parent) = self.selectors.split_last()? else {
+            return None;
+        };
+        if parent.last() == Some(&Selector::Descendants) {
+            return None;
+        }
+        let parent = JsonPath { text: self.text.clone(), selectors: parent.to_vec(), legacy: self.legacy };
+        Some((parent, key))
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::VecDeque;
+
+use crate::{config::EncodingLimits, types::listpack::ListPack};
+
+// Lists start out packed into a single listpack buffer and are upgraded to a deque once they
+// outgrow the configured list-max-listpack-size
+#[derive(Debug, Clone)]
+pub enum List {
+    ListPack(ListPack),
+    QuickList(VecDeque<Vec<u8>>),
+}
+
+impl Default for List {
+    fn default() -> Self {
+        List::ListPack(ListPack::default())
+    }
+}
+
+impl List {
+    pub fn len(&self) -> usize {
+        match self {
+            List::ListPack(pack) => pack.len(),
+            List::QuickList(list) => list.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            List::ListPack(pack) => pack.is_empty(),
+            List::QuickList(list) => list.is_empty(),
+        }
+    }
+
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            List::ListPack(_) => "listpack",
+            List::QuickList(_) => "quicklist",
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        match self {
+            List::ListPack(pack) => pack.get(index),
+            List::QuickList(list) => list.get(index).map(|value| value.as_slice()),
+        }
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = &[u8]> + '_> {
+        match self {
+            List::ListPack(pack) => Box::new(pack.iter()),
+            List::QuickList(list) => Box::new(list.iter().map(|value| value.as_slice())),
+        }
+    }
+
+    pub fn range(&self, start: usize, end: usize) -> Vec<Vec<u8>> {
+        self.iter().skip(start).take(end - start).map(|value| value.to_vec()).collect()
+    }
+
+    pub fn insert(&mut self, index: usize, value: Vec<u8>, limits: &EncodingLimits) {
+        self.grow_for(&value, limits);
+        match self {
+            List::ListPack(pack) => pack.insert(index, &value),
+            List::QuickList(list) => list.insert(index, value),
+        }
+    }
+
+    pub fn push_front(&mut self, value: Vec<u8>, limits: &EncodingLimits) {
+        self.insert(0, value, limits);
+    }
+
+    pub fn push_back(&mut self, value: Vec<u8>, limits: &EncodingLimits) {
+        self.grow_for(&value, limits);
+        match self {
+            List::ListPack(pack) => pack.push_back(&value),
+            List::QuickList(list) => list.push_back(value),
+        }
+    }
+
+    pub fn set(&mut self, index: usize, value: Vec<u8>, limits: &EncodingLimits) {
+        self.grow_for(&value, limits);
+        match self {
+            List::ListPack(pack) => pack.replace(index, &value),
+            List::QuickList(list) => list[index] = value,
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Vec<u8>> {
+        match self {
+            List::ListPack(pack) => pack.remove(index),
+            List::QuickList(list) => list.remove(index),
+        }
+    }
+
+    pub fn pop_front(&mut self) -> Option<Vec<u8>> {
+        self.remove(0)
+    }
+
+    pub fn pop_back(&mut self) -> Option<Vec<u8>> {
+        match self {
+            List::ListPack(pack) => pack.remove(pack.len().checked_sub(1)?),
+            List::QuickList(list) => list.pop_back(),
+        }
+    }
+
+    // Keep only the elements in start..end
+    pub fn retain_range(&mut self, start: usize, end: usize) {
+        match self {
+            List::ListPack(pack) => pack.retain_range(start, end),
+            List::QuickList(list) => {
+                list.truncate(end);
+                list.drain(..start);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        match self {
+            List::ListPack(pack) => pack.clear(),
+            List::QuickList(list) => list.clear(),
+        }
+    }
+
+    // Switch to the quicklist encoding if adding `value` would exceed the listpack limits
+    fn grow_for(&mut self, value: &[u8], limits: &EncodingLimits) {
+        if let List::ListPack(pack) = self {
+            if !limits.list_fits_listpack(pack.len() + 1, pack.byte_len() + value.len() + 2) {
+                let list = pack.iter().map(|value| value.to_vec()).collect();
+                *self = List::QuickList(list);
+            }
+        }
+    }
+}
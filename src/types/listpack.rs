@@ -0,0 +1,137 @@
+// Compact sequence of byte strings packed into a single buffer, in the spirit of the Redis
+// listpack. Each entry is a LEB128 length followed by its bytes, so small collections cost a
+// single allocation with a byte or two of overhead per element. Positional operations are
+// linear, which is fine as long as the pack is kept small.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListPack {
+    buf: Vec<u8>,
+    len: usize,
+}
+
+fn encode_len(len: usize, out: &mut Vec<u8>) {
+    let mut len = len;
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+        if len == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+// Decode the entry at `offset`, returning its data range
+fn decode_entry(buf: &[u8], offset: usize) -> (usize, usize) {
+    let (mut len, mut shift, mut pos) = (0usize, 0, offset);
+    loop {
+        let byte = buf[pos];
+        pos += 1;
+        len |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    (pos, pos + len)
+}
+
+pub struct Iter<'a> {
+    buf: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.offset >= self.buf.len() {
+            return None;
+        }
+        let (start, end) = decode_entry(self.buf, self.offset);
+        self.offset = end;
+        Some(&self.buf[start..end])
+    }
+}
+
+impl ListPack {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Total encoded size, used to decide when to switch to a regular encoding
+    pub fn byte_len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { buf: &self.buf, offset: 0 }
+    }
+
+    // Byte offset of the entry at `index`, or of the end of the buffer for index == len
+    fn offset_of(&self, index: usize) -> usize {
+        let mut offset = 0;
+        for _ in 0..index {
+            offset = decode_entry(&self.buf, offset).1;
+        }
+        offset
+    }
+
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        if index >= self.len {
+            return None;
+        }
+        let (start, end) = decode_entry(&self.buf, self.offset_of(index));
+        Some(&self.buf[start..end])
+    }
+
+    pub fn insert(&mut self, index: usize, value: &[u8]) {
+        let offset = self.offset_of(index.min(self.len));
+        let mut entry = Vec::with_capacity(value.len() + 2);
+        encode_len(value.len(), &mut entry);
+        entry.extend_from_slice(value);
+        self.buf.splice(offset..offset, entry);
+        self.len += 1;
+    }
+
+    pub fn push_back(&mut self, value: &[u8]) {
+        encode_len(value.len(), &mut self.buf);
+        self.buf.extend_from_slice(value);
+        self.len += 1;
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Vec<u8>> {
+        if index >= self.len {
+            return None;
+        }
+        let offset = self.offset_of(index);
+        let (start, end) = decode_entry(&self.buf, offset);
+        let value = self.buf[start..end].to_vec();
+        self.buf.drain(offset..end);
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub fn replace(&mut self, index: usize, value: &[u8]) {
+        if self.remove(index).is_some() {
+            self.insert(index, value);
+        }
+    }
+
+    // Keep only the entries in start..end
+    pub fn retain_range(&mut self, start: usize, end: usize) {
+        let (from, to) = (self.offset_of(start), self.offset_of(end));
+        self.buf.truncate(to);
+        self.buf.drain(..from);
+        self.len = end - start;
+    }
+
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.len = 0;
+    }
+}
@@ -0,0 +1,39 @@
+pub mod bloom;
+pub mod cms;
+pub mod hash;
+pub mod json;
+pub mod jsonpath;
+pub mod list;
+pub mod listpack;
+pub mod rax;
+pub mod set;
+pub mod stream;
+pub mod timeseries;
+pub mod topk;
+pub mod zset;
+
+use std::{collections::hash_map::DefaultHasher, hash::Hasher};
+
+// Hash of an item under one of several independent seeds, for the per row hashes of the sketches
+pub fn seeded_hash(item: &[u8], seed: u64) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write_u64(seed);
+    hasher.write(item);
+    hasher.finish()
+}
+
+// Parse a value that is the canonical decimal representation of a 64 bit integer, the same
+// test Redis uses before choosing an integer encoding (no sign prefix, spaces or leading zeros)
+pub fn parse_strict_integer(value: &[u8]) -> Option<i64> {
+    if value.is_empty() || value.len() > 20 {
+        return None;
+    }
+    let digits = value.strip_prefix(b"-").unwrap_or(value);
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    if digits[0] == b'0' && (digits.len() > 1 || value[0] == b'-') {
+        return None;
+    }
+    std::str::from_utf8(value).ok()?.parse::<i64>().ok()
+}
@@ -0,0 +1,237 @@
+// Radix tree keyed by byte strings, after the Redis rax. Each node owns the edge label leading
+// to it, so chains of single child nodes are compressed into one, and children are kept sorted by
+// the first byte of their label so walks visit keys in lexicographic order.
+#[derive(Debug, Clone)]
+struct RaxNode<V> {
+    label: Vec<u8>,
+    value: Option<V>,
+    children: Vec<RaxNode<V>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Rax<V> {
+    root: RaxNode<V>,
+    len: usize,
+}
+
+impl<V> Default for Rax<V> {
+    fn default() -> Self {
+        Rax { root: RaxNode::new(Vec::new(), None), len: 0 }
+    }
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+impl<V> RaxNode<V> {
+    fn new(label: Vec<u8>, value: Option<V>) -> Self {
+        RaxNode { label, value, children: Vec::new() }
+    }
+
+    // Index of the child whose label starts with `byte`, or where it would be inserted
+    fn child_index(&self, byte: u8) -> Result<usize, usize> {
+        self.children.binary_search_by_key(&byte, |child| child.label[0])
+    }
+
+    fn insert(&mut self, key: &[u8], value: V) -> Option<V> {
+        let Some(&first) = key.first() else {
+            return self.value.replace(value);
+        };
+        let index = match self.child_index(first) {
+            Ok(index) => index,
+            Err(index) => {
+                self.children.insert(index, RaxNode::new(key.to_vec(), Some(value)));
+                return None;
+            }
+        };
+        let child = &mut self.children[index];
+        let common = common_prefix(&child.label, key);
+        if common < child.label.len() {
+            // Split the edge, moving the existing child below a node for the shared prefix
+            let suffix = child.label.split_off(common);
+            let mut old = std::mem::replace(child, RaxNode::new(suffix, None));
+            std::mem::swap(&mut old.label, &mut child.label);
+            child.children.push(old);
+        }
+        child.insert(&key[common..], value)
+    }
+
+    fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+        let Some(&first) = key.first() else {
+            return self.value.as_mut();
+        };
+        let index = self.child_index(first).ok()?;
+        let child = &mut self.children[index];
+        let rest = key.strip_prefix(child.label.as_slice())?;
+        child.get_mut(rest)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<V> {
+        let Some(&first) = key.first() else {
+            return self.value.take();
+        };
+        let index = self.child_index(first).ok()?;
+        let child = &mut self.children[index];
+        let rest = key.strip_prefix(child.label.as_slice())?;
+        let value = child.remove(rest)?;
+        // Drop nodes left without a purpose and re-compress a lone remaining edge
+        if child.value.is_none() {
+            match child.children.len() {
+                0 => {
+                    self.children.remove(index);
+                }
+                1 => {
+                    let mut grandchild = child.children.pop().unwrap();
+                    let mut label = std::mem::take(&mut child.label);
+                    label.append(&mut grandchild.label);
+                    grandchild.label = label;
+                    *child = grandchild;
+                }
+                _ => (),
+            }
+        }
+        Some(value)
+    }
+
+    fn node_count(&self) -> usize {
+        1 + self.children.iter().map(RaxNode::node_count).sum::<usize>()
+    }
+
+    fn first(&self, path: &mut Vec<u8>) -> Option<&V> {
+        path.extend_from_slice(&self.label);
+        if self.value.is_some() {
+            return self.value.as_ref();
+        }
+        self.children.first()?.first(path)
+    }
+
+    fn last(&self, path: &mut Vec<u8>) -> Option<&V> {
+        path.extend_from_slice(&self.label);
+        match self.children.last() {
+            Some(child) => child.last(path),
+            None => self.value.as_ref(),
+        }
+    }
+
+    // Smallest key at or after `key` within this subtree. `key` has already had the labels of
+    // the ancestors stripped, and `path` holds them.
+    fn seek_ge(&self, key: &[u8], path: &mut Vec<u8>) -> Option<&V> {
+        let Some(&first) = key.first() else {
+            let len = path.len();
+            path.truncate(len - self.label.len());
+            return self.first(path);
+        };
+        let start = match self.child_index(first) {
+            Ok(index) => {
+                let child = &self.children[index];
+                let common = common_prefix(&child.label, key);
+                let len = path.len();
+                path.extend_from_slice(&child.label);
+                let found = if common == child.label.len() {
+                    child.seek_ge(&key[common..], path)
+                } else if common == key.len() || child.label[common] > key[common] {
+                    // Every key below the child sorts after `key`
+                    path.truncate(len);
+                    child.first(path)
+                } else {
+                    None
+                };
+                if found.is_some() {
+                    return found;
+                }
+                path.truncate(len);
+                index + 1
+            }
+            Err(index) => index,
+        };
+        self.children.get(start)?.first(path)
+    }
+
+    // Largest key at or before `key` within this subtree, with the same conventions as seek_ge
+    fn seek_le(&self, key: &[u8], path: &mut Vec<u8>) -> Option<&V> {
+        let Some(&first) = key.first() else {
+            return self.value.as_ref();
+        };
+        let end = match self.child_index(first) {
+            Ok(index) => {
+                let child = &self.children[index];
+                let common = common_prefix(&child.label, key);
+                let len = path.len();
+                path.extend_from_slice(&child.label);
+                let found = if common == child.label.len() {
+                    child.seek_le(&key[common..], path)
+                } else if common < key.len() && child.label[common] < key[common] {
+                    // Every key below the child sorts before `key`
+                    path.truncate(len);
+                    child.last(path)
+                } else {
+                    None
+                };
+                if found.is_some() {
+                    return found;
+                }
+                path.truncate(len);
+                index
+            }
+            Err(index) => index,
+        };
+        match end.checked_sub(1) {
+            Some(index) => self.children[index].last(path),
+            None => self.value.as_ref(),
+        }
+    }
+}
+
+impl<V> Rax<V> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // Number of tree nodes, including the root and the inner nodes holding no value
+    pub fn node_count(&self) -> usize {
+        self.root.node_count()
+    }
+
+    pub fn insert(&mut self, key: &[u8], value: V) -> Option<V> {
+        let old = self.root.insert(key, value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+        self.root.get_mut(key)
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<V> {
+        let value = self.root.remove(key);
+        if value.is_some() {
+            self.len -= 1;
+        }
+        value
+    }
+
+    pub fn first(&self) -> Option<(Vec<u8>, &V)> {
+        let mut path = Vec::new();
+        self.root.first(&mut path).map(|value| (path, value))
+    }
+
+    pub fn last(&self) -> Option<(Vec<u8>, &V)> {
+        let mut path = Vec::new();
+        self.root.last(&mut path).map(|value| (path, value))
+    }
+
+    // Entry with the smallest key greater than or equal to `key`
+    pub fn seek_ge(&self, key: &[u8]) -> Option<(Vec<u8>, &V)> {
+        let mut path = Vec::new();
+        self.root.seek_ge(key, &mut path).map(|value| (path, value))
+    }
+
+    // Entry with the largest key less than or equal to `key`
+    pub fn seek_le(&self, key: &[u8]) -> Option<(Vec<u8>, &V)> {
+        let mut path = Vec::new();
+        self.root.seek_le(key, &mut path).map(|value| (path, value))
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::HashSet;
+
+use crate::{config::EncodingLimits, types::parse_strict_integer};
+
+// Sets made up entirely of integers stay in the compact sorted intset encoding until they grow
+// past set-max-intset-entries members
+#[derive(Debug, Clone)]
+pub enum Set {
+    IntSet(Vec<i64>),
+    HashTable(HashSet<Vec<u8>>),
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        Set::IntSet(Vec::new())
+    }
+}
+
+impl Set {
+    pub fn from_members<I: IntoIterator<Item = Vec<u8>>>(members: I, limits: &EncodingLimits) -> Self {
+        let mut set = Set::default();
+        for member in members {
+            set.insert(member, limits);
+        }
+        set
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Set::IntSet(ints) => ints.len(),
+            Set::HashTable(members) => members.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Set::IntSet(_) => "intset",
+            Set::HashTable(_) => "hashtable",
+        }
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match self {
+            Set::IntSet(ints) => parse_strict_integer(member).is_some_and(|int| ints.binary_search(&int).is_ok()),
+            Set::HashTable(members) => members.contains(member),
+        }
+    }
+
+    pub fn insert(&mut self, member: Vec<u8>, limits: &EncodingLimits) -> bool {
+        if let Set::IntSet(ints) = self {
+            if let Some(int) = parse_strict_integer(&member) {
+                match ints.binary_search(&int) {
+                    Ok(_) => return false,
+                    Err(pos) if ints.len() < limits.set_max_intset_entries => {
+                        ints.insert(pos, int);
+                        return true;
+                    }
+                    Err(_) => (),
+                }
+            }
+            self.convert_to_hashtable();
+        }
+        match self {
+            Set::HashTable(members) => members.insert(member),
+            Set::IntSet(_) => unreachable!(),
+        }
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self {
+            Set::IntSet(ints) => match parse_strict_integer(member).map(|int| ints.binary_search(&int)) {
+                Some(Ok(pos)) => {
+                    ints.remove(pos);
+                    true
+                }
+                _ => false,
+            },
+            Set::HashTable(members) => members.remove(member),
+        }
+    }
+
+    pub fn members(&self) -> Vec<Vec<u8>> {
+        match self {
+            Set::IntSet(ints) => ints.iter().map(|int| int.to_string().into_bytes()).collect(),
+            Set::HashTable(members) => members.iter().cloned().collect(),
+        }
+    }
+
+    fn convert_to_hashtable(&mut self) {
+        if let Set::IntSet(ints) = self {
+            let members = ints.iter().map(|int| int.to_string().into_bytes()).collect();
+            *self = Set::HashTable(members);
+        }
+    }
+}
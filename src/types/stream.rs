@@ -0,0 +1,632 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use crate::types::{
+    listpack::{self, ListPack},
+    rax::Rax,
+};
+
+// Entry IDs are a millisecond timestamp plus a sequence number for entries within the same
+// millisecond, ordered lexicographically
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId { ms: u64::MAX, seq: u64::MAX };
+
+    // Parse `ms-seq`, or a bare `ms` which takes the given sequence number
+    pub fn parse(arg: &[u8], default_seq: u64) -> Option<StreamId> {
+        let arg = std::str::from_utf8(arg).ok()?;
+        let (ms, seq) = match arg.split_once('-') {
+            Some((ms, seq)) => (ms.parse().ok()?, seq.parse().ok()?),
+            None => (arg.parse().ok()?, default_seq),
+        };
+        Some(StreamId { ms, seq })
+    }
+
+    // The largest ID before this one, if there is one
+    pub fn prev(self) -> Option<StreamId> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => Some(StreamId { ms: self.ms.checked_sub(1)?, seq: u64::MAX }),
+        }
+    }
+
+    // The smallest ID after this one, if there is one
+    pub fn next(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => Some(StreamId { ms: self.ms.checked_add(1)?, seq: 0 }),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+pub type StreamFields = Vec<(Vec<u8>, Vec<u8>)>;
+
+// Entries are packed into nodes of at most this many
+pub const STREAM_NODE_MAX_ENTRIES: usize = 100;
+
+#[derive(Debug, Clone, Copy)]
+pub enum TrimStrategy {
+    MaxLen(usize),
+    MinId(StreamId),
+}
+
+// Trimming options of XADD and XTRIM. A `limit` of zero means no limit.
+#[derive(Debug, Clone, Copy)]
+pub struct TrimOptions {
+    pub strategy: TrimStrategy,
+    pub approximate: bool,
+    pub limit: usize,
+}
+
+// An entry delivered to a consumer but not yet acknowledged. Times are unix milliseconds.
+#[derive(Debug, Clone)]
+pub struct PendingEntry {
+    pub consumer: Vec<u8>,
+    pub delivery_time: u64,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Consumer {
+    // Last time the consumer issued any group command, and last time it was handed entries
+    pub seen_time: u64,
+    pub active_time: Option<u64>,
+    pub pending: BTreeSet<StreamId>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConsumerGroup {
+    pub last_delivered: StreamId,
+    // Logical position of `last_delivered` counted in entries ever added, when it is known
+    pub entries_read: Option<u64>,
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<Vec<u8>, Consumer>,
+}
+
+impl ConsumerGroup {
+    fn new(last_delivered: StreamId, entries_read: Option<u64>) -> Self {
+        ConsumerGroup { last_delivered, entries_read, pending: BTreeMap::new(), consumers: BTreeMap::new() }
+    }
+
+    // Look up a consumer, creating it on first use, and record that it was seen
+    pub fn consumer(&mut self, name: &[u8], now: u64) -> &mut Consumer {
+        let consumer = self.consumers.entry(name.to_vec()).or_insert_with(|| Consumer {
+            seen_time: now,
+            active_time: None,
+            pending: BTreeSet::new(),
+        });
+        consumer.seen_time = now;
+        consumer
+    }
+
+    // Make an entry pending for the given consumer, taking it away from any previous owner
+    fn assign(&mut self, id: StreamId, consumer: &[u8], delivery_time: u64, delivery_count: u64) {
+        let entry = PendingEntry { consumer: consumer.to_vec(), delivery_time, delivery_count };
+        if let Some(previous) = self.pending.insert(id, entry) {
+            if let Some(owner) = self.consumers.get_mut(&previous.consumer) {
+                owner.pending.remove(&id);
+            }
+        }
+        if let Some(owner) = self.consumers.get_mut(consumer) {
+            owner.pending.insert(id);
+            owner.active_time = Some(delivery_time.max(owner.active_time.unwrap_or(0)));
+        }
+    }
+
+    pub fn ack(&mut self, id: StreamId) -> bool {
+        match self.pending.remove(&id) {
+            Some(entry) => {
+                if let Some(owner) = self.consumers.get_mut(&entry.consumer) {
+                    owner.pending.remove(&id);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// Options of XCLAIM. `delivery_time` overrides the new delivery time, which is otherwise now.
+#[derive(Debug, Clone, Default)]
+pub struct ClaimOptions {
+    pub min_idle: u64,
+    pub delivery_time: Option<u64>,
+    pub retry_count: Option<u64>,
+    pub force: bool,
+    pub justid: bool,
+    pub last_id: Option<StreamId>,
+}
+
+// Outcome of an XAUTOCLAIM scan: where to resume, what was claimed and which pending entries
+// were dropped because they no longer exist in the stream
+pub struct AutoClaim {
+    pub next: StreamId,
+    pub claimed: Vec<StreamId>,
+    pub deleted: Vec<StreamId>,
+}
+
+// Nodes stop taking entries once their packed encoding reaches this size, like Redis'
+// stream-node-max-bytes
+const STREAM_NODE_MAX_BYTES: usize = 4096;
+
+// Radix tree key of a node: its master ID in big endian, so byte order matches ID order
+fn node_key(id: StreamId) -> [u8; 16] {
+    let mut key = [0; 16];
+    key[..8].copy_from_slice(&id.ms.to_be_bytes());
+    key[8..].copy_from_slice(&id.seq.to_be_bytes());
+    key
+}
+
+// Integers are packed little endian with the high zero bytes dropped
+fn encode_number(n: u64) -> Vec<u8> {
+    let bytes = n.to_le_bytes();
+    let len = bytes.iter().rposition(|byte| *byte != 0).map_or(0, |pos| pos + 1);
+    bytes[..len].to_vec()
+}
+
+fn decode_number(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |n, byte| (n << 8) | *byte as u64)
+}
+
+// Run of consecutive entries packed into one listpack, in the spirit of the Redis stream macro
+// nodes. Each entry is a deleted flag, its ID as a millisecond delta from the node's master ID
+// plus the sequence number, a field count and then the fields and values. Deleting an entry only
+// sets its flag, and the node is dropped once none of its entries are live.
+#[derive(Debug, Clone)]
+struct StreamNode {
+    master: StreamId,
+    pack: ListPack,
+    entries: usize,
+    live: usize,
+}
+
+struct NodeEntry<'a> {
+    // Position of the entry's flag in the listpack
+    index: usize,
+    id: StreamId,
+    deleted: bool,
+    fields: Vec<(&'a [u8], &'a [u8])>,
+}
+
+impl NodeEntry<'_> {
+    fn to_fields(&self) -> StreamFields {
+        self.fields.iter().map(|(field, value)| (field.to_vec(), value.to_vec())).collect()
+    }
+}
+
+struct NodeIter<'a> {
+    items: listpack::Iter<'a>,
+    index: usize,
+    master: StreamId,
+}
+
+impl<'a> Iterator for NodeIter<'a> {
+    type Item = NodeEntry<'a>;
+
+    fn next(&mut self) -> Option<NodeEntry<'a>> {
+        let index = self.index;
+        let deleted = self.items.next()? == [1];
+        let ms = self.master.ms + decode_number(self.items.next()?);
+        let seq = decode_number(self.items.next()?);
+        let count = decode_number(self.items.next()?) as usize;
+        let fields = (0..count).map_while(|_| Some((self.items.next()?, self.items.next()?))).collect();
+        self.index += 4 + 2 * count;
+        Some(NodeEntry { index, id: StreamId { ms, seq }, deleted, fields })
+    }
+}
+
+impl StreamNode {
+    fn new(master: StreamId) -> Self {
+        StreamNode { master, pack: ListPack::default(), entries: 0, live: 0 }
+    }
+
+    fn is_full(&self) -> bool {
+        self.entries >= STREAM_NODE_MAX_ENTRIES || self.pack.byte_len() >= STREAM_NODE_MAX_BYTES
+    }
+
+    fn iter(&self) -> NodeIter<'_> {
+        NodeIter { items: self.pack.iter(), index: 0, master: self.master }
+    }
+
+    fn live_entries(&self) -> impl Iterator<Item = NodeEntry<'_>> {
+        self.iter().filter(|entry| !entry.deleted)
+    }
+
+    fn push(&mut self, id: StreamId, fields: &StreamFields) {
+        self.pack.push_back(&[0]);
+        self.pack.push_back(&encode_number(id.ms - self.master.ms));
+        self.pack.push_back(&encode_number(id.seq));
+        self.pack.push_back(&encode_number(fields.len() as u64));
+        for (field, value) in fields {
+            self.pack.push_back(field);
+            self.pack.push_back(value);
+        }
+        self.entries += 1;
+        self.live += 1;
+    }
+
+    // Flag the entry at the given listpack position as deleted
+    fn delete_at(&mut self, index: usize) {
+        self.pack.replace(index, &[1]);
+        self.live -= 1;
+    }
+}
+
+// Append-only log of field/value entries keyed by strictly increasing IDs. Entries live in packed
+// nodes indexed by a radix tree on their master IDs, so lookups seek to a node in O(log N) and
+// then scan at most one node's worth of entries.
+#[derive(Debug, Clone, Default)]
+pub struct Stream {
+    nodes: Rax<StreamNode>,
+    len: usize,
+    last_id: StreamId,
+    // Lifetime counters that let consumer group lag be computed without scanning entries
+    entries_added: u64,
+    max_deleted_id: StreamId,
+    groups: BTreeMap<Vec<u8>, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // Number of packed nodes and of radix tree nodes indexing them
+    pub fn node_counts(&self) -> (usize, usize) {
+        (self.nodes.len(), self.nodes.node_count())
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    pub fn entries_added(&self) -> u64 {
+        self.entries_added
+    }
+
+    pub fn max_deleted_id(&self) -> StreamId {
+        self.max_deleted_id
+    }
+
+    // Overwrite the ID bookkeeping; the caller checks it stays consistent with the entries
+    pub fn set_last_id(&mut self, last_id: StreamId, entries_added: Option<u64>, max_deleted_id: Option<StreamId>) {
+        self.last_id = last_id;
+        if let Some(entries_added) = entries_added {
+            self.entries_added = entries_added;
+        }
+        if let Some(max_deleted_id) = max_deleted_id {
+            self.max_deleted_id = max_deleted_id;
+        }
+    }
+
+    pub fn first_entry(&self) -> Option<(StreamId, StreamFields)> {
+        let (_, node) = self.nodes.first()?;
+        node.live_entries().next().map(|entry| (entry.id, entry.to_fields()))
+    }
+
+    pub fn last_entry(&self) -> Option<(StreamId, StreamFields)> {
+        let (_, node) = self.nodes.last()?;
+        node.live_entries().last().map(|entry| (entry.id, entry.to_fields()))
+    }
+
+    // ID of the oldest entry still in the stream, or 0-0 when it is empty
+    pub fn first_id(&self) -> StreamId {
+        self.nodes.first().and_then(|(_, node)| node.live_entries().next()).map_or(StreamId::MIN, |entry| entry.id)
+    }
+
+    // The node that would hold the entry with the given ID
+    fn node_for(&self, id: StreamId) -> Option<&StreamNode> {
+        self.nodes.seek_le(&node_key(id)).map(|(_, node)| node)
+    }
+
+    pub fn get(&self, id: StreamId) -> Option<StreamFields> {
+        let node = self.node_for(id)?;
+        node.live_entries().find(|entry| entry.id == id).map(|entry| entry.to_fields())
+    }
+
+    fn contains(&self, id: StreamId) -> bool {
+        self.node_for(id).is_some_and(|node| node.live_entries().any(|entry| entry.id == id))
+    }
+
+    pub fn groups(&self) -> impl Iterator<Item = (&Vec<u8>, &ConsumerGroup)> {
+        self.groups.iter()
+    }
+
+    // Whether entries at or after `from` may have been deleted, making counted positions unreliable
+    fn has_tombstones(&self, from: StreamId) -> bool {
+        if self.len == 0 || self.max_deleted_id == StreamId::MIN || self.first_id() > self.max_deleted_id {
+            return false;
+        }
+        self.max_deleted_id >= from
+    }
+
+    // Number of entries ever added up to and including `id`, when that can be told from the
+    // lifetime counters alone
+    fn estimate_entries_read(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 || (self.len == 0 && id <= self.last_id) || id == self.last_id {
+            return Some(self.entries_added);
+        }
+        if id > self.last_id {
+            return None;
+        }
+        let first = self.first_id();
+        if self.max_deleted_id == StreamId::MIN || self.max_deleted_id < first {
+            let before_first = self.entries_added - self.len as u64;
+            if id < first {
+                return Some(before_first);
+            } else if id == first {
+                return Some(before_first + 1);
+            }
+        }
+        None
+    }
+
+    // Entries added but not yet delivered to the group, or None when it can't be determined
+    pub fn group_lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        let entries_read = match group.entries_read {
+            Some(entries_read) if !self.has_tombstones(group.last_delivered) => Some(entries_read),
+            _ => self.estimate_entries_read(group.last_delivered),
+        };
+        entries_read.map(|entries_read| self.entries_added.saturating_sub(entries_read))
+    }
+
+    // Entries with IDs in the inclusive range, walking backwards from `end` when `rev` is set
+    pub fn range(&self, start: StreamId, end: StreamId, rev: bool, count: usize) -> Vec<(StreamId, StreamFields)> {
+        let mut entries = vec![];
+        if start > end || count == 0 {
+            return entries;
+        }
+        if rev {
+            let mut node = self.node_for(end);
+            while let Some(current) = node {
+                let mut node_entries: Vec<NodeEntry> = current.live_entries().filter(|entry| entry.id <= end).collect();
+                node_entries.reverse();
+                for entry in node_entries {
+                    if entry.id < start || entries.len() == count {
+                        return entries;
+                    }
+                    entries.push((entry.id, entry.to_fields()));
+                }
+                node = current.master.prev().and_then(|prev| self.node_for(prev));
+            }
+        } else {
+            let mut node = self.node_for(start).or_else(|| self.nodes.first().map(|(_, node)| node));
+            while let Some(current) = node {
+                for entry in current.live_entries().filter(|entry| entry.id >= start) {
+                    if entry.id > end || entries.len() == count {
+                        return entries;
+                    }
+                    entries.push((entry.id, entry.to_fields()));
+                }
+                node = current.master.next().and_then(|next| self.nodes.seek_ge(&node_key(next))).map(|(_, node)| node);
+            }
+        }
+        entries
+    }
+
+    // The caller guarantees the ID is greater than the current last ID
+    pub fn append(&mut self, id: StreamId, fields: StreamFields) {
+        let last = self.nodes.last().filter(|(_, node)| !node.is_full()).map(|(key, _)| key);
+        match last.and_then(|key| self.nodes.get_mut(&key)) {
+            Some(node) => node.push(id, &fields),
+            None => {
+                let mut node = StreamNode::new(id);
+                node.push(id, &fields);
+                self.nodes.insert(&node_key(id), node);
+            }
+        }
+        self.len += 1;
+        self.last_id = id;
+        self.entries_added += 1;
+    }
+
+    // Delete a live entry from the node keyed by `key`, dropping the node if it is left empty
+    fn delete_entry(&mut self, key: &[u8], index: usize) {
+        let node = self.nodes.get_mut(key).unwrap();
+        node.delete_at(index);
+        if node.live == 0 {
+            self.nodes.remove(key);
+        }
+        self.len -= 1;
+    }
+
+    pub fn remove(&mut self, id: StreamId) -> bool {
+        let (key, index) = match self.nodes.seek_le(&node_key(id)) {
+            Some((key, node)) => match node.live_entries().find(|entry| entry.id == id) {
+                Some(entry) => (key, entry.index),
+                None => return false,
+            },
+            None => return false,
+        };
+        self.delete_entry(&key, index);
+        self.max_deleted_id = self.max_deleted_id.max(id);
+        true
+    }
+
+    // Evict the oldest entries beyond the trimming threshold, returning how many were removed.
+    // Approximate trimming only ever drops whole nodes.
+    pub fn trim(&mut self, options: &TrimOptions) -> usize {
+        let mut remaining = match options.strategy {
+            TrimStrategy::MaxLen(maxlen) => self.len.saturating_sub(maxlen),
+            TrimStrategy::MinId(minid) => self.count_before(minid),
+        };
+        let mut trimmed = 0;
+        while remaining > 0 {
+            let (key, node) = match self.nodes.first() {
+                Some(first) => first,
+                None => break,
+            };
+            if node.live <= remaining {
+                if options.approximate && options.limit > 0 && trimmed + node.live > options.limit {
+                    break;
+                }
+                let live = node.live;
+                self.nodes.remove(&key);
+                self.len -= live;
+                (trimmed, remaining) = (trimmed + live, remaining - live);
+                continue;
+            }
+            if options.approximate {
+                break;
+            }
+            let indexes: Vec<usize> = node.live_entries().take(remaining).map(|entry| entry.index).collect();
+            for index in indexes {
+                self.delete_entry(&key, index);
+            }
+            trimmed += remaining;
+            break;
+        }
+        trimmed
+    }
+
+    // Number of live entries with IDs below `id`
+    fn count_before(&self, id: StreamId) -> usize {
+        let mut count = 0;
+        let mut node = self.nodes.first().map(|(_, node)| node);
+        while let Some(current) = node.filter(|node| node.master < id) {
+            count += current.live_entries().take_while(|entry| entry.id < id).count();
+            node = current.master.next().and_then(|next| self.nodes.seek_ge(&node_key(next))).map(|(_, node)| node);
+        }
+        count
+    }
+
+    pub fn group(&self, name: &[u8]) -> Option<&ConsumerGroup> {
+        self.groups.get(name)
+    }
+
+    pub fn group_mut(&mut self, name: &[u8]) -> Option<&mut ConsumerGroup> {
+        self.groups.get_mut(name)
+    }
+
+    // Returns false if a group with that name already exists
+    pub fn create_group(&mut self, name: &[u8], last_delivered: StreamId) -> bool {
+        if self.groups.contains_key(name) {
+            return false;
+        }
+        let entries_read = self.estimate_entries_read(last_delivered);
+        self.groups.insert(name.to_vec(), ConsumerGroup::new(last_delivered, entries_read));
+        true
+    }
+
+    pub fn destroy_group(&mut self, name: &[u8]) -> bool {
+        self.groups.remove(name).is_some()
+    }
+
+    // Deliver up to `count` entries the group has not seen yet to a consumer, adding them to the
+    // pending list unless `noack` is set. The group must exist.
+    pub fn read_new(&mut self, group: &[u8], consumer: &[u8], count: usize, noack: bool, now: u64) -> Vec<(StreamId, StreamFields)> {
+        let group_state = self.groups.get_mut(group).unwrap();
+        group_state.consumer(consumer, now);
+        let start = match group_state.last_delivered.next() {
+            Some(start) => start,
+            None => return vec![],
+        };
+        let entries = self.range(start, StreamId::MAX, false, count);
+        for (id, _) in &entries {
+            // Keep counting delivered entries while nothing was deleted since the previous one,
+            // otherwise re-estimate
+            let previous = &self.groups[group];
+            let entries_read = match previous.entries_read {
+                Some(entries_read) if !self.has_tombstones(previous.last_delivered) => Some(entries_read + 1),
+                _ => self.estimate_entries_read(*id),
+            };
+            let group = self.groups.get_mut(group).unwrap();
+            group.entries_read = entries_read;
+            group.last_delivered = *id;
+            if !noack {
+                group.assign(*id, consumer, now, 1);
+            }
+        }
+        entries
+    }
+
+    // Re-deliver a consumer's pending entries after the given ID. Entries deleted from the stream
+    // since their delivery come back without fields. The group must exist.
+    pub fn read_pending(&mut self, group: &[u8], consumer: &[u8], after: StreamId, count: usize, now: u64) -> Vec<(StreamId, Option<StreamFields>)> {
+        let group = self.groups.get_mut(group).unwrap();
+        let ids: Vec<StreamId> = match after.next() {
+            Some(start) => group.consumer(consumer, now).pending.range(start..).take(count).copied().collect(),
+            None => vec![],
+        };
+        for id in &ids {
+            if let Some(entry) = group.pending.get_mut(id) {
+                entry.delivery_time = now;
+                entry.delivery_count += 1;
+            }
+        }
+        ids.into_iter().map(|id| (id, self.get(id))).collect()
+    }
+
+    // Transfer ownership of an entry to `consumer` if it has been idle long enough, returning
+    // whether it was claimed. Pending entries that were deleted from the stream are dropped.
+    fn claim_one(&mut self, group: &[u8], consumer: &[u8], id: StreamId, options: &ClaimOptions, now: u64) -> bool {
+        let exists = self.contains(id);
+        let group = self.groups.get_mut(group).unwrap();
+        let delivery_count = match group.pending.get(&id) {
+            None if options.force && exists => 1,
+            None => return false,
+            Some(_) if !exists => {
+                group.ack(id);
+                return false;
+            }
+            Some(entry) if now.saturating_sub(entry.delivery_time) < options.min_idle => return false,
+            Some(entry) => entry.delivery_count,
+        };
+        let delivery_count = match options.retry_count {
+            Some(retry_count) => retry_count,
+            None if options.justid => delivery_count,
+            None => delivery_count + 1,
+        };
+        group.assign(id, consumer, options.delivery_time.unwrap_or(now), delivery_count);
+        true
+    }
+
+    // Claim the given pending entries for a consumer, returning the IDs that were claimed. The
+    // group must exist.
+    pub fn claim(&mut self, group: &[u8], consumer: &[u8], ids: &[StreamId], options: &ClaimOptions, now: u64) -> Vec<StreamId> {
+        let group_state = self.groups.get_mut(group).unwrap();
+        group_state.consumer(consumer, now);
+        if let Some(last_id) = options.last_id {
+            group_state.last_delivered = group_state.last_delivered.max(last_id);
+        }
+        ids.iter().copied().filter(|id| self.claim_one(group, consumer, *id, options, now)).collect()
+    }
+
+    // Scan the pending list from `start`, claiming up to `count` entries idle long enough. At most
+    // ten entries per requested one are examined. The group must exist.
+    pub fn autoclaim(&mut self, group: &[u8], consumer: &[u8], start: StreamId, count: usize, options: &ClaimOptions, now: u64) -> AutoClaim {
+        self.groups.get_mut(group).unwrap().consumer(consumer, now);
+        let candidates: Vec<StreamId> = self.groups[group].pending.range(start..).map(|(id, _)| *id).take(count * 10 + 1).collect();
+        let mut result = AutoClaim { next: StreamId::MIN, claimed: vec![], deleted: vec![] };
+        for (examined, id) in candidates.into_iter().enumerate() {
+            if examined == count * 10 || result.claimed.len() == count {
+                result.next = id;
+                break;
+            }
+            if !self.contains(id) {
+                self.groups.get_mut(group).unwrap().ack(id);
+                result.deleted.push(id);
+            } else if self.claim_one(group, consumer, id, options, now) {
+                result.claimed.push(id);
+            }
+        }
+        result
+    }
+}
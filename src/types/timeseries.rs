@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Error, Result};
+
+use crate::rdb::{ModuleReader, ModuleWriter};
+
+// What to do when a sample arrives for a timestamp that already has one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    Block,
+    First,
+    Last,
+    Min,
+    Max,
+    Sum,
+}
+
+impl DuplicatePolicy {
+    pub fn parse(arg: &[u8]) -> Option<Self> {
+        match arg.to_ascii_lowercase().as_slice() {
+            b"block" => Some(DuplicatePolicy::Block),
+            b"first" => Some(DuplicatePolicy::First),
+            b"last" => Some(DuplicatePolicy::Last),
+            b"min" => Some(DuplicatePolicy::Min),
+            b"max" => Some(DuplicatePolicy::Max),
+            b"sum" => Some(DuplicatePolicy::Sum),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DuplicatePolicy::Block => "block",
+            DuplicatePolicy::First => "first",
+            DuplicatePolicy::Last => "last",
+            DuplicatePolicy::Min => "min",
+            DuplicatePolicy::Max => "max",
+            DuplicatePolicy::Sum => "sum",
+        }
+    }
+}
+
+// Reduction applied to the samples of each bucket by TS.RANGE AGGREGATION
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    Avg,
+    Sum,
+    Min,
+    Max,
+    Count,
+    First,
+    Last,
+    Range,
+}
+
+impl Aggregation {
+    pub fn parse(arg: &[u8]) -> Option<Self> {
+        match arg.to_ascii_lowercase().as_slice() {
+            b"avg" => Some(Aggregation::Avg),
+            b"sum" => Some(Aggregation::Sum),
+            b"min" => Some(Aggregation::Min),
+            b"max" => Some(Aggregation::Max),
+            b"count" => Some(Aggregation::Count),
+            b"first" => Some(Aggregation::First),
+            b"last" => Some(Aggregation::Last),
+            b"range" => Some(Aggregation::Range),
+            _ => None,
+        }
+    }
+
+    fn apply(&self, values: &[f64]) -> f64 {
+        let min = || values.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = || values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        match self {
+            Aggregation::Avg => values.iter().sum::<f64>() / values.len() as f64,
+            Aggregation::Sum => values.iter().sum(),
+            Aggregation::Min => min(),
+            Aggregation::Max => max(),
+            Aggregation::Count => values.len() as f64,
+            Aggregation::First => values[0],
+            Aggregation::Last => values[values.len() - 1],
+            Aggregation::Range => max() - min(),
+        }
+    }
+}
+
+// Samples keyed by millisecond timestamp. With a retention period set, samples older than that
+// much before the newest one are dropped as new ones arrive.
+#[derive(Debug, Clone)]
+pub struct TimeSeries {
+    samples: BTreeMap<u64, f64>,
+    // Zero keeps samples forever
+    retention: u64,
+    duplicate_policy: DuplicatePolicy,
+    labels: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl TimeSeries {
+    pub fn new(retention: u64, duplicate_policy: DuplicatePolicy, labels: Vec<(Vec<u8>, Vec<u8>)>) -> Self {
+        TimeSeries { samples: BTreeMap::new(), retention, duplicate_policy, labels }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn retention(&self) -> u64 {
+        self.retention
+    }
+
+    pub fn duplicate_policy(&self) -> DuplicatePolicy {
+        self.duplicate_policy
+    }
+
+    pub fn labels(&self) -> &[(Vec<u8>, Vec<u8>)] {
+        &self.labels
+    }
+
+    pub fn first(&self) -> Option<(u64, f64)> {
+        self.samples.first_key_value().map(|(&timestamp, &value)| (timestamp, value))
+    }
+
+    pub fn last(&self) -> Option<(u64, f64)> {
+        self.samples.last_key_value().map(|(&timestamp, &value)| (timestamp, value))
+    }
+
+    // Oldest timestamp still inside the retention window
+    fn retention_floor(&self) -> u64 {
+        match self.last() {
+            Some((newest, _)) if self.retention > 0 => newest.saturating_sub(self.retention),
+            _ => 0,
+        }
+    }
+
+    // Add a sample, resolving a clash with an existing one by `policy` or the series default
+    pub fn add(&mut self, timestamp: u64, value: f64, policy: Option<DuplicatePolicy>) -> Result<u64, &'static str> {
+        if timestamp < self.retention_floor() {
+            return Err("ERR TSDB: Timestamp is older than retention");
+        }
+        match self.samples.get_mut(&timestamp) {
+            Some(existing) => {
+                *existing = match policy.unwrap_or(self.duplicate_policy) {
+                    DuplicatePolicy::Block => {
+                        return Err("ERR TSDB: Error at upsert, update is not supported when DUPLICATE_POLICY is set to BLOCK mode")
+                    }
+                    DuplicatePolicy::First => *existing,
+                    DuplicatePolicy::Last => value,
+                    DuplicatePolicy::Min => existing.min(value),
+                    DuplicatePolicy::Max => existing.max(value),
+                    DuplicatePolicy::Sum => *existing + value,
+                };
+            }
+            None => {
+                self.samples.insert(timestamp, value);
+            }
+        }
+        let floor = self.retention_floor();
+        if self.first().is_some_and(|(oldest, _)| oldest < floor) {
+            self.samples = self.samples.split_off(&floor);
+        }
+        Ok(timestamp)
+    }
+
+    // Samples between two timestamps inclusive, optionally reduced to one per bucket. Buckets
+    // are aligned to multiples of their duration and labelled with their start.
+    pub fn range(&self, from: u64, to: u64, aggregation: Option<(Aggregation, u64)>) -> Vec<(u64, f64)> {
+        if from > to {
+            return Vec::new();
+        }
+        let samples = self.samples.range(from..=to).map(|(&timestamp, &value)| (timestamp, value));
+        let Some((aggregation, bucket)) = aggregation else {
+            return samples.collect();
+        };
+        let mut buckets: Vec<(u64, Vec<f64>)> = Vec::new();
+        for (timestamp, value) in samples {
+            let start = timestamp - timestamp % bucket;
+            match buckets.last_mut() {
+                Some((current, values)) if *current == start => values.push(value),
+                _ => buckets.push((start, vec![value])),
+            }
+        }
+        buckets.into_iter().map(|(start, values)| (start, aggregation.apply(&values))).collect()
+    }
+
+    pub fn rdb_save(&self, out: &mut ModuleWriter) {
+        out.unsigned(self.retention);
+        out.string(self.duplicate_policy.name().as_bytes());
+        out.unsigned(self.labels.len() as u64);
+        for (name, value) in self.labels.iter() {
+            out.string(name);
+            out.string(value);
+        }
+        out.unsigned(self.samples.len() as u64);
+        for (timestamp, value) in self.samples.iter() {
+            out.unsigned(*timestamp);
+            out.double(*value);
+        }
+    }
+
+    pub fn rdb_load(input: &mut ModuleReader) -> Result<Self> {
+        let retention = input.unsigned()?;
+        let duplicate_policy = DuplicatePolicy::parse(&input.string()?).ok_or_else(|| Error::msg("Bad RDB file: unknown duplicate policy"))?;
+        let mut labels = Vec::new();
+        for _ in 0..input.unsigned()? {
+            labels.push((input.string()?, input.string()?));
+        }
+        let mut series = TimeSeries::new(retention, duplicate_policy, labels);
+        for _ in 0..input.unsigned()? {
+            let timestamp = input.unsigned()?;
+            series.samples.insert(timestamp, input.double()?);
+        }
+        Ok(series)
+    }
+}
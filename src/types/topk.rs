@@ -0,0 +1,171 @@
+use anyhow::{Error, Result};
+
+use crate::{
+    random::random_f64,
+    rdb::{ModuleReader, ModuleWriter},
+    types::seeded_hash,
+};
+
+// Seed for the fingerprint hash, kept apart from the row seeds 0..depth
+const FINGERPRINT_SEED: u64 = 1919;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Bucket {
+    fingerprint: u64,
+    count: u64,
+}
+
+// HeavyKeeper top-k tracker, as used by RedisBloom. Each row holds one fingerprint per bucket,
+// and colliding items decay the resident count with exponentially shrinking probability until
+// they can take the bucket over. The `k` heaviest items seen so far are kept by name.
+#[derive(Debug, Clone)]
+pub struct TopK {
+    k: usize,
+    width: usize,
+    depth: usize,
+    decay: f64,
+    buckets: Vec<Bucket>,
+    // Tracked items and their estimated counts, at most `k` of them
+    heap: Vec<(Vec<u8>, u64)>,
+}
+
+impl TopK {
+    pub fn new(k: usize, width: usize, depth: usize, decay: f64) -> Self {
+        TopK { k, width, depth, decay, buckets: vec![Bucket::default(); width * depth], heap: Vec::with_capacity(k) }
+    }
+
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn decay(&self) -> f64 {
+        self.decay
+    }
+
+    fn cell(&self, item: &[u8], row: usize) -> usize {
+        row * self.width + (seeded_hash(item, row as u64) % self.width as u64) as usize
+    }
+
+    // Smallest tracked count, zero while there is still room for more items
+    fn min_tracked(&self) -> Option<(usize, u64)> {
+        if self.heap.len() < self.k {
+            return None;
+        }
+        self.heap.iter().enumerate().map(|(index, (_, count))| (index, *count)).min_by_key(|(_, count)| *count)
+    }
+
+    // Count an item `by` times, returning the item it pushed out of the top-k list if any
+    pub fn add(&mut self, item: &[u8], by: u64) -> Option<Vec<u8>> {
+        let fingerprint = seeded_hash(item, FINGERPRINT_SEED);
+        let mut max_count = 0;
+        for row in 0..self.depth {
+            let cell = self.cell(item, row);
+            let bucket = &mut self.buckets[cell];
+            if bucket.count == 0 {
+                *bucket = Bucket { fingerprint, count: by };
+            } else if bucket.fingerprint == fingerprint {
+                bucket.count += by;
+            } else {
+                for remaining in (1..=by).rev() {
+                    if random_f64() < self.decay.powf(bucket.count as f64) {
+                        bucket.count -= 1;
+                        if bucket.count == 0 {
+                            *bucket = Bucket { fingerprint, count: remaining };
+                            break;
+                        }
+                    }
+                }
+                if bucket.fingerprint != fingerprint {
+                    continue;
+                }
+            }
+            max_count = max_count.max(bucket.count);
+        }
+
+        if let Some(tracked) = self.heap.iter_mut().find(|(name, _)| name == item) {
+            tracked.1 = tracked.1.max(max_count);
+            return None;
+        }
+        match self.min_tracked() {
+            None if max_count > 0 => {
+                self.heap.push((item.to_vec(), max_count));
+                None
+            }
+            Some((index, min)) if max_count > min => {
+                let (expelled, _) = std::mem::replace(&mut self.heap[index], (item.to_vec(), max_count));
+                Some(expelled)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.heap.iter().any(|(name, _)| name == item)
+    }
+
+    // Estimated count, the largest of the buckets still holding the item's fingerprint
+    pub fn count(&self, item: &[u8]) -> u64 {
+        let fingerprint = seeded_hash(item, FINGERPRINT_SEED);
+        (0..self.depth)
+            .map(|row| self.buckets[self.cell(item, row)])
+            .filter(|bucket| bucket.fingerprint == fingerprint)
+            .map(|bucket| bucket.count)
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Tracked items, heaviest first
+    pub fn list(&self) -> Vec<(Vec<u8>, u64)> {
+        let mut items = self.heap.clone();
+        items.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        items
+    }
+
+    pub fn rdb_save(&self, out: &mut ModuleWriter) {
+        out.unsigned(self.k as u64);
+        out.unsigned(self.width as u64);
+        out.unsigned(self.depth as u64);
+        out.double(self.decay);
+        let buckets: Vec<u8> = self.buckets.iter().flat_map(|bucket| [bucket.fingerprint.to_le_bytes(), bucket.count.to_le_bytes()]).flatten().collect();
+        out.string(&buckets);
+        out.unsigned(self.heap.len() as u64);
+        for (item, count) in self.heap.iter() {
+            out.string(item);
+            out.unsigned(*count);
+        }
+    }
+
+    pub fn rdb_load(input: &mut ModuleReader) -> Result<Self> {
+        let k = input.unsigned()? as usize;
+        let width = input.unsigned()? as usize;
+        let depth = input.unsigned()? as usize;
+        let decay = input.double()?;
+        let buckets = input.string()?;
+        if width == 0 || buckets.len() != width * depth * 16 {
+            return Err(Error::msg("Bad RDB file: top-k of the wrong size"));
+        }
+        let buckets = buckets
+            .chunks_exact(16)
+            .map(|bucket| Bucket {
+                fingerprint: u64::from_le_bytes(bucket[..8].try_into().unwrap()),
+                count: u64::from_le_bytes(bucket[8..].try_into().unwrap()),
+            })
+            .collect();
+        let mut heap = Vec::with_capacity(k);
+        for _ in 0..input.unsigned()? {
+            heap.push((input.string()?, input.unsigned()?));
+        }
+        if heap.len() > k {
+            return Err(Error::msg("Bad RDB file: top-k tracking too many items"));
+        }
+        Ok(TopK { k, width, depth, decay, buckets, heap })
+    }
+}
@@ -0,0 +1,411 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use crate::random::random_u64;
+
+// Enough levels for 2^64 elements with p = 1/4
+const SKIPLIST_MAX_LEVEL: usize = 32;
+
+// Index of the header node, which holds no element
+const HEAD: usize = 0;
+
+#[derive(Debug, Clone)]
+struct Level {
+    forward: Option<usize>,
+    // Number of level 0 links crossed by following `forward`
+    span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    member: Vec<u8>,
+    score: f64,
+    backward: Option<usize>,
+    levels: Vec<Level>,
+}
+
+// Skiplist ordered by (score, member), laid out like the Redis zskiplist. Nodes live in an arena
+// and refer to each other by index, and the per level spans let ranks be computed on the way
+// down instead of walking the bottom level.
+#[derive(Debug, Clone)]
+pub struct SkipList {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    tail: Option<usize>,
+    level: usize,
+    len: usize,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        let head = Node {
+            member: Vec::new(),
+            score: 0.0,
+            backward: None,
+            levels: vec![Level { forward: None, span: 0 }; SKIPLIST_MAX_LEVEL],
+        };
+        SkipList { nodes: vec![head], free: vec![], tail: None, level: 1, len: 0 }
+    }
+}
+
+impl Node {
+    // Position of this node relative to the (score, member) element
+    fn compare(&self, score: f64, member: &[u8]) -> Ordering {
+        self.score.partial_cmp(&score).unwrap_or(Ordering::Equal).then_with(|| self.member.as_slice().cmp(member))
+    }
+}
+
+fn random_level() -> usize {
+    let mut level = 1;
+    while level < SKIPLIST_MAX_LEVEL && random_u64() & 3 == 0 {
+        level += 1;
+    }
+    level
+}
+
+impl SkipList {
+    fn compare(&self, node: usize, score: f64, member: &[u8]) -> Ordering {
+        self.nodes[node].compare(score, member)
+    }
+
+    fn forward(&self, node: usize, level: usize) -> Option<usize> {
+        self.nodes[node].levels[level].forward
+    }
+
+    fn alloc(&mut self, node: Node) -> usize {
+        match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    // The member must not already be present
+    pub fn insert(&mut self, score: f64, member: Vec<u8>) {
+        let mut update = [HEAD; SKIPLIST_MAX_LEVEL];
+        let mut rank = [0; SKIPLIST_MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i + 1 == self.level { 0 } else { rank[i + 1] };
+            while let Some(next) = self.forward(x, i) {
+                if self.compare(next, score, &member) != Ordering::Less {
+                    break;
+                }
+                rank[i] += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let level = random_level();
+        if level > self.level {
+            for i in self.level..level {
+                self.nodes[HEAD].levels[i].span = self.len;
+            }
+            self.level = level;
+        }
+
+        let node = self.alloc(Node { member, score, backward: None, levels: vec![Level { forward: None, span: 0 }; level] });
+        for i in 0..level {
+            let prev = update[i];
+            let prev_span = self.nodes[prev].levels[i].span;
+            self.nodes[node].levels[i] = Level {
+                forward: self.nodes[prev].levels[i].forward,
+                span: prev_span - (rank[0] - rank[i]),
+            };
+            self.nodes[prev].levels[i] = Level { forward: Some(node), span: rank[0] - rank[i] + 1 };
+        }
+        for (i, &prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[prev].levels[i].span += 1;
+        }
+
+        self.nodes[node].backward = (update[0] != HEAD).then_some(update[0]);
+        match self.forward(node, 0) {
+            Some(next) => self.nodes[next].backward = Some(node),
+            None => self.tail = Some(node),
+        }
+        self.len += 1;
+    }
+
+    pub fn remove(&mut self, score: f64, member: &[u8]) -> bool {
+        let mut update = [HEAD; SKIPLIST_MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if self.compare(next, score, member) != Ordering::Less {
+                    break;
+                }
+                x = next;
+            }
+            update[i] = x;
+        }
+        let target = match self.forward(x, 0) {
+            Some(next) if self.compare(next, score, member) == Ordering::Equal => next,
+            _ => return false,
+        };
+
+        for (i, &prev) in update.iter().enumerate().take(self.level) {
+            if self.forward(prev, i) == Some(target) {
+                self.nodes[prev].levels[i] = Level {
+                    forward: self.nodes[target].levels[i].forward,
+                    span: self.nodes[prev].levels[i].span + self.nodes[target].levels[i].span - 1,
+                };
+            } else {
+                self.nodes[prev].levels[i].span -= 1;
+            }
+        }
+        let backward = self.nodes[target].backward;
+        match self.forward(target, 0) {
+            Some(next) => self.nodes[next].backward = backward,
+            None => self.tail = backward,
+        }
+        while self.level > 1 && self.forward(HEAD, self.level - 1).is_none() {
+            self.level -= 1;
+        }
+
+        self.nodes[target] = Node { member: Vec::new(), score: 0.0, backward: None, levels: Vec::new() };
+        self.free.push(target);
+        self.len -= 1;
+        true
+    }
+
+    // Node holding the element at the given 0-based rank
+    fn node_by_rank(&self, rank: usize) -> Option<usize> {
+        let target = rank + 1;
+        let mut traversed = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if traversed + self.nodes[x].levels[i].span > target {
+                    break;
+                }
+                traversed += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if traversed == target {
+                return Some(x);
+            }
+        }
+        None
+    }
+
+    // Descend the list advancing while `advance` holds for the next node, returning the last node
+    // reached (HEAD if none) and its 1-based rank. `advance` must hold for a prefix of the list.
+    fn seek(&self, advance: impl Fn(&Node) -> bool) -> (usize, usize) {
+        let (mut x, mut rank) = (HEAD, 0);
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if !advance(&self.nodes[next]) {
+                    break;
+                }
+                rank += self.nodes[x].levels[i].span;
+                x = next;
+            }
+        }
+        (x, rank)
+    }
+
+    // 0-based rank of an element
+    pub fn rank(&self, score: f64, member: &[u8]) -> Option<usize> {
+        let (node, rank) = self.seek(|node| node.compare(score, member) != Ordering::Greater);
+        (rank > 0 && self.compare(node, score, member) == Ordering::Equal).then(|| rank - 1)
+    }
+
+    // Walk from the given node towards the tail, or towards the head when `rev` is set
+    fn walk(&self, start: Option<usize>, rev: bool) -> impl Iterator<Item = &Node> + '_ {
+        std::iter::successors(start, move |&node| {
+            if rev { self.nodes[node].backward } else { self.forward(node, 0) }
+        })
+        .map(|node| &self.nodes[node])
+    }
+}
+
+// Score interval, each end of which may be exclusive
+#[derive(Debug, Clone)]
+pub struct ScoreRange {
+    pub min: f64,
+    pub min_exclusive: bool,
+    pub max: f64,
+    pub max_exclusive: bool,
+}
+
+impl ScoreRange {
+    pub fn above_min(&self, score: f64) -> bool {
+        if self.min_exclusive { score > self.min } else { score >= self.min }
+    }
+
+    pub fn below_max(&self, score: f64) -> bool {
+        if self.max_exclusive { score < self.max } else { score <= self.max }
+    }
+}
+
+// One end of a lexicographic interval, `-` and `+` standing for the smallest and largest strings
+#[derive(Debug, Clone)]
+pub enum LexBound {
+    Min,
+    Max,
+    Inclusive(Vec<u8>),
+    Exclusive(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+pub struct LexRange {
+    pub min: LexBound,
+    pub max: LexBound,
+}
+
+impl LexRange {
+    pub fn above_min(&self, member: &[u8]) -> bool {
+        match &self.min {
+            LexBound::Min => true,
+            LexBound::Max => false,
+            LexBound::Inclusive(min) => member >= min.as_slice(),
+            LexBound::Exclusive(min) => member > min.as_slice(),
+        }
+    }
+
+    pub fn below_max(&self, member: &[u8]) -> bool {
+        match &self.max {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(max) => member <= max.as_slice(),
+            LexBound::Exclusive(max) => member < max.as_slice(),
+        }
+    }
+}
+
+// Sorted set keeping a member to score map for O(1) score lookups next to the skiplist that
+// provides ordering
+#[derive(Debug, Clone, Default)]
+pub struct SortedSet {
+    scores: HashMap<Vec<u8>, f64>,
+    list: SkipList,
+}
+
+impl SortedSet {
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn encoding(&self) -> &'static str {
+        "skiplist"
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    // Members with their scores in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], f64)> + '_ {
+        self.list.walk(self.list.forward(HEAD, 0), false).map(|node| (node.member.as_slice(), node.score))
+    }
+
+    // Add a member or update its score, returning whether it was newly added
+    pub fn insert(&mut self, member: Vec<u8>, score: f64) -> bool {
+        match self.scores.get_mut(&member) {
+            Some(current) => {
+                if *current != score {
+                    self.list.remove(*current, &member);
+                    self.list.insert(score, member);
+                    *current = score;
+                }
+                false
+            }
+            None => {
+                self.list.insert(score, member.clone());
+                self.scores.insert(member, score);
+                true
+            }
+        }
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => self.list.remove(score, member),
+            None => false,
+        }
+    }
+
+    // Remove and return the element with the lowest score, or the highest unless `min` is set
+    pub fn pop(&mut self, min: bool) -> Option<(Vec<u8>, f64)> {
+        let node = if min { self.list.forward(HEAD, 0) } else { self.list.tail }?;
+        let member = self.list.nodes[node].member.clone();
+        let score = self.list.nodes[node].score;
+        self.remove(&member);
+        Some((member, score))
+    }
+
+    // Rank and score of a member, ranks counting from the highest score when `rev` is set
+    pub fn rank(&self, member: &[u8], rev: bool) -> Option<(usize, f64)> {
+        let score = self.score(member)?;
+        let rank = self.list.rank(score, member)?;
+        Some((if rev { self.len() - 1 - rank } else { rank }, score))
+    }
+
+    // Elements in the half-open rank range, ranks counting from the highest score when `rev` is set
+    pub fn range_by_rank(&self, start: usize, end: usize, rev: bool) -> Vec<(Vec<u8>, f64)> {
+        let first = if rev { self.len() - 1 - start } else { start };
+        self.list.walk(self.list.node_by_rank(first), rev)
+            .take(end - start)
+            .map(|node| (node.member.clone(), node.score))
+            .collect()
+    }
+
+    // Elements between two bounds, the predicates telling whether an element is past the lower
+    // bound and before the upper one. `offset` and `limit` apply in iteration order.
+    fn range_between(
+        &self,
+        above_min: impl Fn(f64, &[u8]) -> bool,
+        below_max: impl Fn(f64, &[u8]) -> bool,
+        rev: bool,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Vec<(Vec<u8>, f64)> {
+        let elements: Box<dyn Iterator<Item = &Node>> = if rev {
+            let (last, rank) = self.list.seek(|node| below_max(node.score, &node.member));
+            let start = (rank > 0).then_some(last);
+            Box::new(self.list.walk(start, true).take_while(|node| above_min(node.score, &node.member)))
+        } else {
+            let (before, _) = self.list.seek(|node| !above_min(node.score, &node.member));
+            let start = self.list.forward(before, 0);
+            Box::new(self.list.walk(start, false).take_while(|node| below_max(node.score, &node.member)))
+        };
+        elements
+            .skip(offset)
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|node| (node.member.clone(), node.score))
+            .collect()
+    }
+
+    // Number of elements between two bounds, found from the ranks of both ends
+    fn count_between(&self, above_min: impl Fn(f64, &[u8]) -> bool, below_max: impl Fn(f64, &[u8]) -> bool) -> usize {
+        let (_, below) = self.list.seek(|node| !above_min(node.score, &node.member));
+        let (_, within) = self.list.seek(|node| below_max(node.score, &node.member));
+        within.saturating_sub(below)
+    }
+
+    pub fn count_by_score(&self, range: &ScoreRange) -> usize {
+        self.count_between(|score, _| range.above_min(score), |score, _| range.below_max(score))
+    }
+
+    pub fn count_by_lex(&self, range: &LexRange) -> usize {
+        self.count_between(|_, member| range.above_min(member), |_, member| range.below_max(member))
+    }
+
+    pub fn range_by_score(&self, range: &ScoreRange, rev: bool, offset: usize, limit: Option<usize>) -> Vec<(Vec<u8>, f64)> {
+        self.range_between(|score, _| range.above_min(score), |score, _| range.below_max(score), rev, offset, limit)
+    }
+
+    // Only meaningful when all elements share the same score, as with Redis
+    pub fn range_by_lex(&self, range: &LexRange, rev: bool, offset: usize, limit: Option<usize>) -> Vec<(Vec<u8>, f64)> {
+        self.range_between(|_, member| range.above_min(member), |_, member| range.below_max(member), rev, offset, limit)
+    }
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use common::{Reply, Server};
+
+#[test]
+fn bits_and_counts() {
+    let server = Server::start("bitmap-bits", 17581, &[]);
+    let mut client = server.client();
+
+    assert_eq!(client.call(&["SETBIT", "b", "7", "1"]), Reply::Integer(0));
+    assert_eq!(client.call(&["SETBIT", "b", "7", "1"]), Reply::Integer(1));
+    assert_eq!(client.call(&["GET", "b"]), Reply::bulk("\u{1}"));
+    assert_eq!(client.call(&["GETBIT", "b", "7"]), Reply::Integer(1));
+    assert_eq!(client.call(&["GETBIT", "b", "100"]), Reply::Integer(0));
+    assert_eq!(client.call(&["SETBIT", "b", "x", "1"]), Reply::error("ERR bit offset is not an integer or out of range"));
+    assert_eq!(client.call(&["SETBIT", "b", "1", "2"]), Reply::error("ERR bit is not an integer or out of range"));
+
+    // "foobar" has 26 bits set, 4 in its second byte and 6 in its second to fifth bits
+    client.call(&["SET", "s", "foobar"]);
+    assert_eq!(client.call(&["BITCOUNT", "s"]), Reply::Integer(26));
+    assert_eq!(client.call(&["BITCOUNT", "s", "1", "1"]), Reply::Integer(6));
+    assert_eq!(client.call(&["BITCOUNT", "s", "-1", "-1"]), Reply::Integer(4));
+    assert_eq!(client.call(&["BITCOUNT", "s", "5", "30", "BIT"]), Reply::Integer(17));
+    assert_eq!(client.call(&["BITCOUNT", "missing"]), Reply::Integer(0));
+}
+
+#[test]
+fn bitop_and_bitpos() {
+    let server = Server::start("bitmap-bitop", 17582, &[]);
+    let mut client = server.client();
+    client.call(&["SET", "a", "abc"]);
+    client.call(&["SET", "b", "a"]);
+
+    // Shorter inputs are padded with zeros
+    assert_eq!(client.call(&["BITOP", "AND", "dst", "a", "b"]), Reply::Integer(3));
+    assert_eq!(client.call(&["GET", "dst"]), Reply::bulk("a\0\0"));
+    assert_eq!(client.call(&["BITOP", "OR", "dst", "a", "b"]), Reply::Integer(3));
+    assert_eq!(client.call(&["GET", "dst"]), Reply::bulk("abc"));
+    assert_eq!(client.call(&["BITOP", "XOR", "dst", "a", "a"]), Reply::Integer(3));
+    assert_eq!(client.call(&["GET", "dst"]), Reply::bulk("\0\0\0"));
+    assert_eq!(client.call(&["BITOP", "NOT", "dst", "b"]), Reply::Integer(1));
+    assert_eq!(client.call(&["GET", "dst"]), Reply::Bulk(Some(vec![0x9e])));
+    assert_eq!(client.call(&["BITOP", "NOT", "dst", "a", "b"]), Reply::error("ERR BITOP NOT must be called with a single source key."));
+
+    client.call(&["SETBIT", "p", "10", "1"]);
+    assert_eq!(client.call(&["BITPOS", "p", "1"]), Reply::Integer(10));
+    assert_eq!(client.call(&["BITPOS", "p", "0"]), Reply::Integer(0));
+    assert_eq!(client.call(&["BITPOS", "p", "1", "2"]), Reply::Integer(-1));
+    assert_eq!(client.call(&["BITPOS", "p", "1", "0", "9", "BIT"]), Reply::Integer(-1));
+    // Looking for a clear bit past the end of an all-ones string finds the first bit after it
+    client.call(&["SET", "ones", "\u{7f}"]);
+    client.call(&["SETBIT", "ones", "0", "1"]);
+    assert_eq!(client.call(&["BITPOS", "ones", "0"]), Reply::Integer(8));
+    assert_eq!(client.call(&["BITPOS", "missing", "1"]), Reply::Integer(-1));
+    assert_eq!(client.call(&["BITPOS", "p", "2"]), Reply::error("ERR The bit argument must be 1 or 0."));
+}
+
+#[test]
+fn bitfield() {
+    let server = Server::start("bitmap-bitfield", 17583, &[]);
+    let mut client = server.client();
+
+    assert_eq!(
+        client.call(&["BITFIELD", "f", "SET", "i8", "0", "-100", "GET", "i8", "0", "GET", "u4", "0"]),
+        Reply::array(vec![Reply::Integer(0), Reply::Integer(-100), Reply::Integer(9)])
+    );
+    // Overflow wraps by default, saturates or fails when asked to
+    assert_eq!(
+        client.call(&["BITFIELD", "f", "INCRBY", "u2", "100", "1", "OVERFLOW", "SAT", "INCRBY", "u2", "102", "5", "OVERFLOW", "FAIL", "INCRBY", "u2", "104", "5"]),
+        Reply::array(vec![Reply::Integer(1), Reply::Integer(3), Reply::Bulk(None)])
+    );
+    assert_eq!(client.call(&["BITFIELD", "f", "INCRBY", "u2", "100", "3"]), Reply::array(vec![Reply::Integer(0)]));
+    // # offsets count in units of the type's width
+    assert_eq!(client.call(&["BITFIELD", "g", "SET", "u8", "#1", "200", "GET", "u8", "8"]), Reply::array(vec![Reply::Integer(0), Reply::Integer(200)]));
+
+    assert_eq!(client.call(&["BITFIELD_RO", "g", "GET", "u8", "#1"]), Reply::array(vec![Reply::Integer(200)]));
+    assert_eq!(client.call(&["BITFIELD_RO", "g", "SET", "u8", "0", "1"]), Reply::error("ERR BITFIELD_RO only supports the GET subcommand"));
+    assert_eq!(client.call(&["BITFIELD", "g", "OVERFLOW", "NOPE"]), Reply::error("ERR Invalid OVERFLOW type specified"));
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use common::{Reply, Server};
+
+// Filters too large to allocate are refused up front, and a full filter that can't grow any
+// larger fails the add rather than the server
+#[test]
+fn bloom_filter_size_limits() {
+    let server = Server::start("bloom-limits", 17471, &[]);
+    let mut client = server.client();
+    let too_large = Reply::Error("ERR filter would be larger than the 512mb limit".to_string());
+    assert_eq!(client.call(&["BF.RESERVE", "bf", "0.0000001", "100000000000"]), too_large);
+    assert_eq!(client.call(&["BF.RESERVE", "bf", "0.01", "9223372036854775807"]), too_large);
+    assert_eq!(client.call(&["DEL", "bf"]), Reply::Integer(0));
+    assert_eq!(client.call(&["BF.RESERVE", "bf", "0.01", "1000"]), Reply::Simple("OK".to_string()));
+    assert_eq!(client.call(&["BF.ADD", "bf", "item"]), Reply::Integer(1));
+    assert_eq!(client.call(&["BF.EXISTS", "bf", "item"]), Reply::Integer(1));
+
+    // The second layer would be 4294967295 times the size of the first
+    assert_eq!(client.call(&["BF.RESERVE", "grows", "0.5", "1", "EXPANSION", "4294967295"]), Reply::Simple("OK".to_string()));
+    assert_eq!(client.call(&["BF.ADD", "grows", "first"]), Reply::Integer(1));
+    assert_eq!(client.call(&["BF.ADD", "grows", "second"]), too_large);
+    assert_eq!(client.call(&["BF.EXISTS", "grows", "first"]), Reply::Integer(1));
+
+    // Filters made on first use are held to the limit too
+    assert_eq!(client.call(&["CONFIG", "SET", "bf-initial-size", "100000000000"]), Reply::Simple("OK".to_string()));
+    assert_eq!(client.call(&["BF.ADD", "implicit", "item"]), too_large);
+    assert_eq!(client.call(&["DEL", "implicit"]), Reply::Integer(0));
+    assert_eq!(client.call(&["PING"]), Reply::Simple("PONG".to_string()));
+}
+
+#[test]
+fn bloom_filter_commands() {
+    let server = Server::start("bloom-commands", 17472, &[]);
+    let mut client = server.client();
+
+    assert_eq!(client.call(&["BF.ADD", "bf", "a"]), Reply::Integer(1));
+    assert_eq!(client.call(&["BF.ADD", "bf", "a"]), Reply::Integer(0));
+    assert_eq!(
+        client.call(&["BF.MADD", "bf", "a", "b", "c"]),
+        Reply::array(vec![Reply::Integer(0), Reply::Integer(1), Reply::Integer(1)])
+    );
+    assert_eq!(
+        client.call(&["BF.MEXISTS", "bf", "a", "c", "nope"]),
+        Reply::array(vec![Reply::Integer(1), Reply::Integer(1), Reply::Integer(0)])
+    );
+    assert_eq!(client.call(&["BF.EXISTS", "missing", "a"]), Reply::Integer(0));
+    assert_eq!(client.call(&["BF.RESERVE", "bf", "0.01", "10"]), Reply::error("ERR item exists"));
+
+    // A filter grows by its expansion once full
+    client.call(&["BF.RESERVE", "small", "0.01", "2", "EXPANSION", "3"]);
+    client.call(&["BF.MADD", "small", "a", "b", "c"]);
+    let info = client.call(&["BF.INFO", "small"]);
+    assert_eq!(info.field("Capacity"), Reply::Integer(8));
+    assert_eq!(info.field("Number of filters"), Reply::Integer(2));
+    assert_eq!(info.field("Number of items inserted"), Reply::Integer(3));
+    assert_eq!(info.field("Expansion rate"), Reply::Integer(3));
+    assert_eq!(client.call(&["BF.INFO", "small", "ITEMS"]), Reply::array(vec![Reply::Integer(3)]));
+    assert_eq!(client.call(&["BF.INFO", "missing"]), Reply::error("ERR not found"));
+
+    // Unless it's non scaling, when adds past its capacity fail
+    client.call(&["BF.RESERVE", "fixed", "0.01", "2", "NONSCALING"]);
+    assert_eq!(
+        client.call(&["BF.MADD", "fixed", "a", "b", "c"]),
+        Reply::array(vec![Reply::Integer(1), Reply::Integer(1), Reply::error("ERR non scaling filter is full")])
+    );
+    assert_eq!(client.call(&["BF.INFO", "fixed", "EXPANSION"]), Reply::array(vec![Reply::Bulk(None)]));
+
+    assert_eq!(client.call(&["BF.RESERVE", "x", "1.5", "10"]), Reply::error("ERR (0 < error rate range < 1)"));
+    assert_eq!(client.call(&["BF.RESERVE", "x", "0.01", "0"]), Reply::error("ERR (capacity should be larger than 0)"));
+    assert_eq!(
+        client.call(&["BF.RESERVE", "x", "0.01", "10", "NONSCALING", "EXPANSION", "2"]),
+        Reply::error("ERR Nonscaling filters cannot expand")
+    );
+    client.call(&["SET", "s", "v"]);
+    assert_eq!(client.call(&["BF.ADD", "s", "a"]), Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"));
+}
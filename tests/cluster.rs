@@ -0,0 +1,47 @@
+mod common;
+
+use common::{Reply, Server};
+
+const NODES: &str = "\
+aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa 127.0.0.1:17411@27411 myself,master - 0 0 1 connected 0-5000
+bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb 127.0.0.1:17412@27412 master - 0 0 2 connected 5001-16383
+vars currentEpoch 2 lastVoteEpoch 0
+";
+
+fn error(msg: &str) -> Reply {
+    Reply::Error(msg.to_string())
+}
+
+// Keys in slots another node serves are redirected to it, both for clients and for scripts,
+// and nothing is served while some slot isn't
+#[test]
+fn keys_are_served_by_their_slot_owner() {
+    let dir = std::env::temp_dir().join(format!("redis-test-cluster-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("nodes.conf"), NODES).unwrap();
+    let server = Server::start_in(dir, 17411, &["--cluster-enabled", "yes"]);
+    let mut client = server.client();
+
+    // b and {user1000} hash to slots 3300 and 3443, here, and foo to 12182, elsewhere
+    assert_eq!(client.call(&["SET", "b", "1"]), Reply::Simple("OK".to_string()));
+    assert_eq!(client.call(&["GET", "foo"]), error("MOVED 12182 127.0.0.1:17412"));
+    assert_eq!(client.call(&["SINTER", "b", "{user1000}.a"]), error("CROSSSLOT Keys in request don't hash to the same slot"));
+    assert_eq!(client.call(&["SINTER", "{user1000}.a", "{user1000}.b"]), Reply::Array(Some(Vec::new())));
+
+    assert_eq!(client.call(&["EVAL", "return redis.call('GET', KEYS[1])", "1", "foo"]), error("MOVED 12182 127.0.0.1:17412"));
+    assert_eq!(client.call(&["EVAL", "return redis.call('GET', KEYS[1])", "1", "b"]), Reply::bulk("1"));
+    let remote = client.call(&["EVAL", "return redis.call('GET', 'foo')", "0"]).text();
+    assert!(remote.contains("Script attempted to access a non local key in a cluster node script"), "{}", remote);
+    let cross = client.call(&["EVAL", "return redis.call('GET', '{user1000}.a')", "1", "b"]).text();
+    assert!(cross.contains("Script attempted to access keys that do not hash to the same slot"), "{}", cross);
+    let undeclared = client.call(&["EVAL", "redis.call('GET', 'b') return redis.call('GET', '{user1000}.a')", "0"]).text();
+    assert!(undeclared.contains("Script attempted to access keys that do not hash to the same slot"), "{}", undeclared);
+
+    assert_eq!(client.call(&["CLUSTER", "DELSLOTS", "16383"]), Reply::Simple("OK".to_string()));
+    assert_eq!(client.call(&["GET", "b"]), error("CLUSTERDOWN The cluster is down"));
+    let down = client.call(&["EVAL", "return redis.call('GET', 'b')", "0"]).text();
+    assert!(down.contains("Script attempted to execute a command while the cluster is down"), "{}", down);
+    assert_eq!(client.call(&["CLUSTER", "ADDSLOTS", "16383"]), Reply::Simple("OK".to_string()));
+    assert_eq!(client.call(&["GET", "b"]), Reply::bulk("1"));
+}
@@ -0,0 +1,244 @@
+// Servers run as their own processes, each in a directory of its own, and are talked to over
+// RESP like any client would
+#![allow(dead_code)]
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+    // RESP3 only
+    Null,
+    Map(Vec<(Reply, Reply)>),
+    Push(Vec<Reply>),
+}
+
+impl Reply {
+    pub fn bulk(s: &str) -> Reply {
+        Reply::Bulk(Some(s.as_bytes().to_vec()))
+    }
+
+    pub fn ok() -> Reply {
+        Reply::Simple("OK".to_string())
+    }
+
+    pub fn error(s: &str) -> Reply {
+        Reply::Error(s.to_string())
+    }
+
+    pub fn bulks(items: &[&str]) -> Reply {
+        Reply::Array(Some(items.iter().map(|item| Reply::bulk(item)).collect()))
+    }
+
+    pub fn array(items: Vec<Reply>) -> Reply {
+        Reply::Array(Some(items))
+    }
+
+    // The items of an array, as text
+    pub fn texts(&self) -> Vec<String> {
+        match self {
+            Reply::Array(Some(items)) | Reply::Push(items) => items.iter().map(Reply::text).collect(),
+            other => panic!("expected an array, got {:?}", other),
+        }
+    }
+
+    // A field of a reply made of names and values, which RESP2 flattens into an array
+    pub fn field(&self, name: &str) -> Reply {
+        let value = match self {
+            Reply::Array(Some(items)) => items.chunks(2).find(|pair| pair[0].text() == name).map(|pair| &pair[1]),
+            Reply::Map(pairs) => pairs.iter().find(|(key, _)| key.text() == name).map(|(_, value)| value),
+            _ => None,
+        };
+        value.unwrap_or_else(|| panic!("no field {} in {:?}", name, self)).clone()
+    }
+
+    pub fn text(&self) -> String {
+        match self {
+            Reply::Simple(s) | Reply::Error(s) => s.clone(),
+            Reply::Bulk(Some(s)) => String::from_utf8_lossy(s).to_string(),
+            other => format!("{:?}", other),
+        }
+    }
+}
+
+pub struct Client {
+    reader: BufReader<TcpStream>,
+}
+
+impl Client {
+    pub fn connect(port: u16) -> std::io::Result<Client> {
+        let stream = TcpStream::connect(("127.0.0.1", port))?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        Ok(Client { reader: BufReader::new(stream) })
+    }
+
+    pub fn call(&mut self, args: &[&str]) -> Reply {
+        self.send(args);
+        self.read()
+    }
+
+    // Send a command without waiting for its reply
+    pub fn send(&mut self, args: &[&str]) {
+        let mut request = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            request.extend(format!("${}\r\n{}\r\n", arg.len(), arg).into_bytes());
+        }
+        self.reader.get_mut().write_all(&request).unwrap();
+    }
+
+    // The next frame, or None if nothing comes within the timeout
+    pub fn read_within(&mut self, timeout: Duration) -> Option<Reply> {
+        if self.reader.buffer().is_empty() {
+            self.reader.get_ref().set_read_timeout(Some(timeout)).unwrap();
+            let ready = self.reader.fill_buf().map(|buf| !buf.is_empty());
+            self.reader.get_ref().set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            match ready {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(err) if matches!(err.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => return None,
+                Err(err) => panic!("{}", err),
+            }
+        }
+        Some(self.read())
+    }
+
+    pub fn read(&mut self) -> Reply {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        let (kind, rest) = line.trim_end().split_at(1);
+        match kind {
+            "+" => Reply::Simple(rest.to_string()),
+            "-" => Reply::Error(rest.to_string()),
+            ":" => Reply::Integer(rest.parse().unwrap()),
+            "$" => match rest.parse::<i64>().unwrap() {
+                n if n < 0 => Reply::Bulk(None),
+                n => {
+                    let mut data = vec![0; n as usize + 2];
+                    self.reader.read_exact(&mut data).unwrap();
+                    data.truncate(n as usize);
+                    Reply::Bulk(Some(data))
+                }
+            },
+            "*" => match rest.parse::<i64>().unwrap() {
+                n if n < 0 => Reply::Array(None),
+                n => Reply::Array(Some((0..n).map(|_| self.read()).collect())),
+            },
+            "_" => Reply::Null,
+            "%" => Reply::Map((0..rest.parse::<usize>().unwrap()).map(|_| (self.read(), self.read())).collect()),
+            ">" => Reply::Push((0..rest.parse::<usize>().unwrap()).map(|_| self.read()).collect()),
+            _ => panic!("unexpected reply {:?}", line),
+        }
+    }
+}
+
+pub struct Server {
+    pub port: u16,
+    pub dir: PathBuf,
+    child: Child,
+}
+
+impl Server {
+    // A server in a fresh directory
+    pub fn start(name: &str, port: u16, args: &[&str]) -> Server {
+        let dir = std::env::temp_dir().join(format!("redis-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Server::start_in(dir, port, args)
+    }
+
+    // A server picking up whatever an earlier one left in the directory
+    pub fn start_in(dir: PathBuf, port: u16, args: &[&str]) -> Server {
+        let child = Command::new(env!("CARGO_BIN_EXE_redis-starter-rust"))
+            .args(["--port", &port.to_string(), "--dir", dir.to_str().unwrap()])
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let server = Server { port, dir, child };
+        wait_until(|| Client::connect(port).is_ok());
+        server
+    }
+
+    // Whether a server refuses to start from what is in the directory, exiting rather than
+    // listening
+    pub fn refuses_to_start(dir: &Path, port: u16, args: &[&str]) -> bool {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_redis-starter-rust"))
+            .args(["--port", &port.to_string(), "--dir", dir.to_str().unwrap()])
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if child.try_wait().unwrap().is_some() {
+                return true;
+            }
+            if Client::connect(port).is_ok() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+        false
+    }
+
+    pub fn client(&self) -> Client {
+        Client::connect(self.port).unwrap()
+    }
+
+    // Freeze the process, so nothing it does in the background gets in the way, and thaw it
+    pub fn pause(&self) {
+        self.signal("STOP");
+    }
+
+    pub fn resume(&self) {
+        self.signal("CONT");
+    }
+
+    fn signal(&self, signal: &str) {
+        let status = Command::new("kill").args([&format!("-{}", signal), &self.child.id().to_string()]).status().unwrap();
+        assert!(status.success());
+    }
+
+    pub fn stop(mut self) -> PathBuf {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        std::mem::take(&mut self.dir)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Poll for something that happens in the background, failing after a few seconds
+pub fn wait_until(mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out waiting");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+// A field of an INFO section
+pub fn info_field(client: &mut Client, section: &str, field: &str) -> Option<String> {
+    let info = client.call(&["INFO", section]).text();
+    info.lines().find_map(|line| line.strip_prefix(&format!("{}:", field)).map(str::to_string))
+}
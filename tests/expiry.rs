@@ -0,0 +1,103 @@
+mod common;
+
+use std::{thread, time::Duration};
+
+use common::{info_field, wait_until, Reply, Server};
+
+// A key that expires on a master is deleted from the AOF with DEL, which has to replay
+#[test]
+fn aof_with_expired_key_replays() {
+    let server = Server::start("aof-expiry", 17401, &["--appendonly", "yes", "--appendfsync", "always"]);
+    let mut client = server.client();
+    assert_eq!(client.call(&["SET", "short", "lived", "PX", "50"]), Reply::Simple("OK".to_string()));
+    assert_eq!(client.call(&["SET", "kept", "value"]), Reply::Simple("OK".to_string()));
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(client.call(&["GET", "short"]), Reply::Bulk(None));
+    assert_eq!(client.call(&["DEL", "kept", "missing"]), Reply::Integer(1));
+    assert_eq!(client.call(&["SET", "kept", "again"]), Reply::Simple("OK".to_string()));
+    let aof_dir = server.dir.join("appendonlydir");
+    wait_until(|| {
+        let written: Vec<u8> = std::fs::read_dir(&aof_dir).unwrap().flat_map(|entry| std::fs::read(entry.unwrap().path()).unwrap()).collect();
+        written.windows(7).any(|w| w == b"again\r\n")
+    });
+    let dir = server.stop();
+
+    let server = Server::start_in(dir, 17401, &["--appendonly", "yes"]);
+    let mut client = server.client();
+    assert_eq!(client.call(&["GET", "short"]), Reply::Bulk(None));
+    assert_eq!(client.call(&["GET", "kept"]), Reply::bulk("again"));
+    assert_eq!(info_field(&mut client, "keyspace", "db0").as_deref(), Some("keys=1,expires=0,avg_ttl=0"));
+}
+
+// A replica only hides a key that has expired, and deletes it once its master does
+#[test]
+fn replica_applies_master_expiry() {
+    let master = Server::start("expiry-master", 17402, &[]);
+    let replica = Server::start("expiry-replica", 17403, &["--replicaof", "127.0.0.1 17402"]);
+    let (mut to_master, mut to_replica) = (master.client(), replica.client());
+    wait_until(|| info_field(&mut to_replica, "replication", "master_link_status").as_deref() == Some("up"));
+
+    to_master.call(&["SET", "short", "lived", "PX", "100"]);
+    wait_until(|| info_field(&mut to_replica, "keyspace", "db0").is_some_and(|db| db.starts_with("keys=1,")));
+    thread::sleep(Duration::from_millis(200));
+
+    assert_eq!(to_replica.call(&["GET", "short"]), Reply::Bulk(None));
+    // Still there until the master says otherwise
+    assert_eq!(info_field(&mut to_replica, "keyspace", "db0").as_deref(), Some("keys=1,expires=1,avg_ttl=0"));
+
+    assert_eq!(to_master.call(&["GET", "short"]), Reply::Bulk(None));
+    wait_until(|| info_field(&mut to_replica, "keyspace", "db0").is_none());
+}
+
+// Expired hash fields are likewise only hidden by a replica until its master deletes them
+#[test]
+fn replica_applies_master_field_expiry() {
+    let master = Server::start("field-expiry-master", 17404, &[]);
+    let replica = Server::start("field-expiry-replica", 17405, &["--replicaof", "127.0.0.1 17404"]);
+    let (mut to_master, mut to_replica) = (master.client(), replica.client());
+    wait_until(|| info_field(&mut to_replica, "replication", "master_link_status").as_deref() == Some("up"));
+
+    to_master.call(&["HSET", "hash", "gone", "value", "kept", "value"]);
+    to_master.call(&["HSET", "emptied", "gone", "value"]);
+    to_master.call(&["HPEXPIRE", "hash", "100", "FIELDS", "1", "gone"]);
+    to_master.call(&["HPEXPIRE", "emptied", "100", "FIELDS", "1", "gone"]);
+    wait_until(|| info_field(&mut to_replica, "keyspace", "db0").is_some_and(|db| db.starts_with("keys=2,")));
+    // The master would expire the fields on its own before long
+    master.pause();
+    thread::sleep(Duration::from_millis(300));
+
+    let stored = |client: &mut common::Client| match client.call(&["DUMP", "hash"]) {
+        Reply::Bulk(Some(dump)) => dump.windows(4).any(|w| w == b"gone"),
+        _ => false,
+    };
+    assert_eq!(to_replica.call(&["HGETALL", "hash"]), Reply::Array(Some(vec![Reply::bulk("kept"), Reply::bulk("value")])));
+    assert_eq!(to_replica.call(&["HTTL", "hash", "FIELDS", "1", "gone"]), Reply::Array(Some(vec![Reply::Integer(-2)])));
+    assert_eq!(to_replica.call(&["HLEN", "emptied"]), Reply::Integer(0));
+    assert!(stored(&mut to_replica));
+    assert_eq!(info_field(&mut to_replica, "keyspace", "db0").as_deref(), Some("keys=2,expires=0,avg_ttl=0"));
+    master.resume();
+
+    assert_eq!(to_master.call(&["HLEN", "hash"]), Reply::Integer(1));
+    assert_eq!(to_master.call(&["HLEN", "emptied"]), Reply::Integer(0));
+    wait_until(|| !stored(&mut to_replica));
+    wait_until(|| info_field(&mut to_replica, "keyspace", "db0").as_deref() == Some("keys=1,expires=0,avg_ttl=0"));
+}
+
+// Every hash with expiring fields gets reclaimed, not just the first few the cycle comes to
+#[test]
+fn active_expiry_reaches_every_hash() {
+    let server = Server::start("active-expiry", 17406, &[]);
+    let mut client = server.client();
+    let keys: Vec<String> = (0..100).map(|i| format!("hash:{}", i)).collect();
+    for key in &keys {
+        client.call(&["HSET", key, "short", "value", "long", "value"]);
+        client.call(&["HPEXPIRE", key, "100", "FIELDS", "1", "short"]);
+        client.call(&["HEXPIRE", key, "3600", "FIELDS", "1", "long"]);
+    }
+    // DUMP shows what is stored without expiring anything itself
+    let mut has_short = |key: &str| match client.call(&["DUMP", key]) {
+        Reply::Bulk(Some(dump)) => dump.windows(5).any(|w| w == b"short"),
+        _ => false,
+    };
+    wait_until(|| !keys.iter().any(|key| has_short(key)));
+}
@@ -0,0 +1,31 @@
+mod common;
+
+use common::{Reply, Server};
+
+#[test]
+fn positions_distances_and_hashes() {
+    let server = Server::start("geo", 17591, &[]);
+    let mut client = server.client();
+
+    assert_eq!(client.call(&["GEOADD", "sicily", "13.361389", "38.115556", "Palermo", "15.087269", "37.502669", "Catania"]), Reply::Integer(2));
+    assert_eq!(client.call(&["GEOADD", "sicily", "NX", "13", "38", "Palermo"]), Reply::Integer(0));
+    assert_eq!(client.call(&["GEOADD", "sicily", "XX", "CH", "13.361389", "38.115556", "Palermo"]), Reply::Integer(0));
+    // Members are stored in a sorted set, scored by their geohash
+    assert_eq!(client.call(&["ZCARD", "sicily"]), Reply::Integer(2));
+
+    assert_eq!(client.call(&["GEODIST", "sicily", "Palermo", "Catania"]), Reply::bulk("166274.1516"));
+    assert_eq!(client.call(&["GEODIST", "sicily", "Palermo", "Catania", "km"]), Reply::bulk("166.2742"));
+    assert_eq!(client.call(&["GEODIST", "sicily", "Palermo", "Nowhere"]), Reply::Bulk(None));
+    assert_eq!(client.call(&["GEOHASH", "sicily", "Palermo", "Catania"]), Reply::bulks(&["sqc8b49rny0", "sqdtr74hyu0"]));
+
+    // Positions come back from the geohash, close to but not exactly what was added
+    let Reply::Array(Some(positions)) = client.call(&["GEOPOS", "sicily", "Palermo", "Nowhere"]) else {
+        panic!("GEOPOS should reply with an array");
+    };
+    let position: Vec<f64> = positions[0].texts().iter().map(|coordinate| coordinate.parse().unwrap()).collect();
+    assert!((position[0] - 13.361389).abs() < 1e-5 && (position[1] - 38.115556).abs() < 1e-5);
+    assert_eq!(positions[1], Reply::Array(None));
+
+    assert_eq!(client.call(&["GEOADD", "sicily", "200", "38", "x"]), Reply::error("ERR invalid longitude,latitude pair 200.000000,38.000000"));
+    assert_eq!(client.call(&["GEOADD", "sicily", "NX", "XX", "1", "1", "x"]), Reply::error("ERR XX and NX options at the same time are not compatible"));
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use std::{collections::HashSet, thread, time::Duration};
+
+use common::{wait_until, Reply, Server};
+
+#[test]
+fn fields() {
+    let server = Server::start("hash-fields", 17551, &[]);
+    let mut client = server.client();
+
+    assert_eq!(client.call(&["HSET", "h", "a", "1", "b", "2"]), Reply::Integer(2));
+    assert_eq!(client.call(&["HSET", "h", "a", "10", "c", "3"]), Reply::Integer(1));
+    assert_eq!(client.call(&["HGET", "h", "a"]), Reply::bulk("10"));
+    assert_eq!(client.call(&["HGET", "h", "z"]), Reply::Bulk(None));
+    assert_eq!(
+        client.call(&["HMGET", "h", "a", "z", "c"]),
+        Reply::array(vec![Reply::bulk("10"), Reply::Bulk(None), Reply::bulk("3")])
+    );
+    assert_eq!(client.call(&["HEXISTS", "h", "b"]), Reply::Integer(1));
+    assert_eq!(client.call(&["HLEN", "h"]), Reply::Integer(3));
+    assert_eq!(client.call(&["HKEYS", "h"]).texts(), ["a", "b", "c"]);
+    assert_eq!(client.call(&["HVALS", "h"]).texts(), ["10", "2", "3"]);
+    assert_eq!(client.call(&["HGETALL", "h"]).texts(), ["a", "10", "b", "2", "c", "3"]);
+
+    // Deleting the last field removes the key
+    assert_eq!(client.call(&["HDEL", "h", "a", "b", "z"]), Reply::Integer(2));
+    assert_eq!(client.call(&["HDEL", "h", "c"]), Reply::Integer(1));
+    assert_eq!(client.call(&["HLEN", "h"]), Reply::Integer(0));
+    assert_eq!(client.call(&["HGETALL", "h"]), Reply::bulks(&[]));
+    assert_eq!(client.call(&["HSET", "h", "a"]), Reply::error("ERR wrong number of arguments for 'hset' command"));
+}
+
+#[test]
+fn scan_and_encoding() {
+    let server = Server::start("hash-scan", 17552, &["--hash-max-listpack-entries", "4"]);
+    let mut client = server.client();
+    client.call(&["HSET", "h", "a", "1", "b", "2", "c", "3"]);
+    assert_eq!(client.call(&["OBJECT", "ENCODING", "h"]), Reply::bulk("listpack"));
+
+    // A scan of a small hash is done in one call
+    let Reply::Array(Some(reply)) = client.call(&["HSCAN", "h", "0", "MATCH", "[ab]"]) else {
+        panic!("HSCAN should reply with an array");
+    };
+    assert_eq!(reply[0], Reply::bulk("0"));
+    assert_eq!(reply[1].texts(), ["a", "1", "b", "2"]);
+
+    // Growing past the limit converts it, and a scan then takes several calls
+    let fields: Vec<String> = (0..100).map(|i| format!("f{}", i)).collect();
+    for field in &fields {
+        client.call(&["HSET", "big", field, "v"]);
+    }
+    assert_eq!(client.call(&["OBJECT", "ENCODING", "big"]), Reply::bulk("hashtable"));
+    let mut cursor = "0".to_string();
+    let mut seen = HashSet::new();
+    loop {
+        let Reply::Array(Some(reply)) = client.call(&["HSCAN", "big", &cursor, "COUNT", "10", "NOVALUES"]) else {
+            panic!("HSCAN should reply with an array");
+        };
+        seen.extend(reply[1].texts());
+        cursor = reply[0].text();
+        if cursor == "0" {
+            break;
+        }
+    }
+    assert_eq!(seen, fields.into_iter().collect());
+}
+
+#[test]
+fn field_ttls() {
+    let server = Server::start("hash-field-ttl", 17553, &[]);
+    let mut client = server.client();
+    client.call(&["HSET", "h", "a", "1", "b", "2"]);
+
+    assert_eq!(
+        client.call(&["HEXPIRE", "h", "100", "FIELDS", "2", "a", "z"]),
+        Reply::array(vec![Reply::Integer(1), Reply::Integer(-2)])
+    );
+    assert_eq!(client.call(&["HTTL", "h", "FIELDS", "2", "a", "b"]), Reply::array(vec![Reply::Integer(100), Reply::Integer(-1)]));
+    let Reply::Array(Some(ttls)) = client.call(&["HPTTL", "h", "FIELDS", "1", "a"]) else {
+        panic!("HPTTL should reply with an array");
+    };
+    assert!(matches!(ttls[0], Reply::Integer(ms) if ms > 99_000 && ms <= 100_000));
+
+    // Conditions compare with the current TTL, where none counts as infinite
+    assert_eq!(client.call(&["HEXPIRE", "h", "50", "GT", "FIELDS", "1", "a"]), Reply::array(vec![Reply::Integer(0)]));
+    assert_eq!(client.call(&["HEXPIRE", "h", "50", "NX", "FIELDS", "1", "b"]), Reply::array(vec![Reply::Integer(1)]));
+    assert_eq!(client.call(&["HEXPIRE", "h", "500", "XX", "FIELDS", "1", "b"]), Reply::array(vec![Reply::Integer(1)]));
+
+    assert_eq!(client.call(&["HPERSIST", "h", "FIELDS", "2", "a", "z"]), Reply::array(vec![Reply::Integer(1), Reply::Integer(-2)]));
+    assert_eq!(client.call(&["HTTL", "h", "FIELDS", "1", "a"]), Reply::array(vec![Reply::Integer(-1)]));
+    assert_eq!(client.call(&["HPERSIST", "h", "FIELDS", "1", "a"]), Reply::array(vec![Reply::Integer(-1)]));
+
+    // A time already passed deletes the field, and expired fields disappear
+    assert_eq!(client.call(&["HPEXPIREAT", "h", "1", "FIELDS", "1", "a"]), Reply::array(vec![Reply::Integer(2)]));
+    assert_eq!(client.call(&["HGET", "h", "a"]), Reply::Bulk(None));
+    client.call(&["HPEXPIRE", "h", "50", "FIELDS", "1", "b"]);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(client.call(&["HGET", "h", "b"]), Reply::Bulk(None));
+    wait_until(|| client.call(&["HLEN", "h"]) == Reply::Integer(0));
+
+    assert_eq!(client.call(&["HEXPIRE", "h", "-1", "FIELDS", "1", "a"]), Reply::error("ERR invalid expire time in 'hexpire' command"));
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use common::{Reply, Server};
+
+const DOCUMENT: &str = r#"{"name":"shop","items":[{"name":"pen","price":1.5},{"name":"ink","price":4}],"open":true}"#;
+
+#[test]
+fn set_and_get_paths() {
+    let server = Server::start("json-paths", 17611, &[]);
+    let mut client = server.client();
+
+    assert_eq!(client.call(&["JSON.SET", "j", "$.x", "1"]), Reply::error("ERR new objects must be created at the root"));
+    assert_eq!(client.call(&["JSON.SET", "j", "$", DOCUMENT]), Reply::ok());
+    assert_eq!(client.call(&["JSON.GET", "j"]), Reply::bulk(DOCUMENT));
+
+    // JSONPath replies with every match, legacy paths with the first
+    assert_eq!(client.call(&["JSON.GET", "j", "$..name"]), Reply::bulk(r#"["shop","pen","ink"]"#));
+    assert_eq!(client.call(&["JSON.GET", "j", "$.items[*].price"]), Reply::bulk("[1.5,4]"));
+    assert_eq!(client.call(&["JSON.GET", "j", "$.items[-1].name"]), Reply::bulk(r#"["ink"]"#));
+    assert_eq!(client.call(&["JSON.GET", "j", "$.nope"]), Reply::bulk("[]"));
+    assert_eq!(client.call(&["JSON.GET", "j", ".items[0].name"]), Reply::bulk(r#""pen""#));
+    assert_eq!(client.call(&["JSON.GET", "j", ".nope"]), Reply::error("ERR Path '.nope' does not exist"));
+    assert_eq!(
+        client.call(&["JSON.GET", "j", ".name", ".open"]),
+        Reply::bulk(r#"{".name":"shop",".open":true}"#)
+    );
+    assert_eq!(
+        client.call(&["JSON.GET", "j", "INDENT", "  ", "NEWLINE", "\n", "SPACE", " ", "$.open"]),
+        Reply::bulk("[\n  true\n]")
+    );
+    assert_eq!(client.call(&["JSON.GET", "missing"]), Reply::Bulk(None));
+
+    // Setting updates every match, or adds a member to the parent objects
+    assert_eq!(client.call(&["JSON.SET", "j", "$.items[*].price", "0"]), Reply::ok());
+    assert_eq!(client.call(&["JSON.GET", "j", "$.items[*].price"]), Reply::bulk("[0,0]"));
+    assert_eq!(client.call(&["JSON.SET", "j", "$.items[*].stock", "7"]), Reply::ok());
+    assert_eq!(client.call(&["JSON.GET", "j", "$..stock"]), Reply::bulk("[7,7]"));
+    assert_eq!(client.call(&["JSON.SET", "j", "$.open", "false", "NX"]), Reply::Bulk(None));
+    assert_eq!(client.call(&["JSON.SET", "j", "$.closed", "true", "XX"]), Reply::Bulk(None));
+    assert_eq!(client.call(&["JSON.SET", "j", "$.open", "false", "XX"]), Reply::ok());
+    assert_eq!(client.call(&["JSON.GET", "j", ".open"]), Reply::bulk("false"));
+    assert!(client.call(&["JSON.SET", "j", "$", "{bad"]).text().starts_with("ERR "));
+}
+
+#[test]
+fn delete_and_types() {
+    let server = Server::start("json-del", 17612, &[]);
+    let mut client = server.client();
+    client.call(&["JSON.SET", "j", "$", DOCUMENT]);
+
+    assert_eq!(client.call(&["JSON.TYPE", "j"]), Reply::bulk("object"));
+    assert_eq!(client.call(&["JSON.TYPE", "j", "$..price"]), Reply::bulks(&["number", "integer"]));
+    assert_eq!(client.call(&["JSON.TYPE", "j", "$.open"]), Reply::bulks(&["boolean"]));
+    assert_eq!(client.call(&["JSON.TYPE", "j", ".items"]), Reply::bulk("array"));
+    assert_eq!(client.call(&["JSON.TYPE", "missing"]), Reply::Bulk(None));
+
+    assert_eq!(client.call(&["JSON.DEL", "j", "$.items[*].price"]), Reply::Integer(2));
+    assert_eq!(client.call(&["JSON.GET", "j", "$.items"]), Reply::bulk(r#"[[{"name":"pen"},{"name":"ink"}]]"#));
+    // A recursive path deletes at every depth
+    assert_eq!(client.call(&["JSON.DEL", "j", "$..name"]), Reply::Integer(3));
+    assert_eq!(client.call(&["JSON.FORGET", "j", "$.nope"]), Reply::Integer(0));
+    assert_eq!(client.call(&["JSON.DEL", "j"]), Reply::Integer(1));
+    assert_eq!(client.call(&["JSON.GET", "j"]), Reply::Bulk(None));
+    assert_eq!(client.call(&["JSON.DEL", "j"]), Reply::Integer(0));
+
+    client.call(&["SET", "s", "v"]);
+    assert_eq!(client.call(&["JSON.GET", "s"]), Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"));
+}
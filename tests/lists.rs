@@ -0,0 +1,116 @@
+mod common;
+
+use std::{thread, time::Duration};
+
+use common::{Reply, Server};
+
+fn range(client: &mut common::Client, key: &str) -> Reply {
+    client.call(&["LRANGE", key, "0", "-1"])
+}
+
+#[test]
+fn push_pop_and_range() {
+    let server = Server::start("list-core", 17541, &[]);
+    let mut client = server.client();
+
+    assert_eq!(client.call(&["RPUSH", "l", "a", "b"]), Reply::Integer(2));
+    assert_eq!(client.call(&["LPUSH", "l", "y", "z"]), Reply::Integer(4));
+    assert_eq!(range(&mut client, "l"), Reply::bulks(&["z", "y", "a", "b"]));
+    assert_eq!(client.call(&["LRANGE", "l", "1", "-2"]), Reply::bulks(&["y", "a"]));
+    assert_eq!(client.call(&["LRANGE", "l", "5", "10"]), Reply::bulks(&[]));
+    assert_eq!(client.call(&["LLEN", "l"]), Reply::Integer(4));
+
+    assert_eq!(client.call(&["LPOP", "l"]), Reply::bulk("z"));
+    assert_eq!(client.call(&["RPOP", "l", "2"]), Reply::bulks(&["b", "a"]));
+    // Popping the last element removes the key
+    assert_eq!(client.call(&["LPOP", "l", "5"]), Reply::bulks(&["y"]));
+    assert_eq!(client.call(&["LLEN", "l"]), Reply::Integer(0));
+    assert_eq!(client.call(&["LPOP", "l"]), Reply::Bulk(None));
+    assert_eq!(client.call(&["DEL", "l"]), Reply::Integer(0));
+
+    client.call(&["SET", "s", "v"]);
+    assert_eq!(client.call(&["LPUSH", "s", "a"]), Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"));
+}
+
+#[test]
+fn editing() {
+    let server = Server::start("list-editing", 17542, &[]);
+    let mut client = server.client();
+    client.call(&["RPUSH", "l", "a", "b", "c", "b", "d"]);
+
+    assert_eq!(client.call(&["LINDEX", "l", "-1"]), Reply::bulk("d"));
+    assert_eq!(client.call(&["LINDEX", "l", "9"]), Reply::Bulk(None));
+    assert_eq!(client.call(&["LSET", "l", "0", "A"]), Reply::ok());
+    assert_eq!(client.call(&["LSET", "l", "9", "x"]), Reply::error("ERR index out of range"));
+    assert_eq!(client.call(&["LINSERT", "l", "BEFORE", "c", "x"]), Reply::Integer(6));
+    assert_eq!(client.call(&["LINSERT", "l", "AFTER", "nope", "x"]), Reply::Integer(-1));
+    assert_eq!(range(&mut client, "l"), Reply::bulks(&["A", "b", "x", "c", "b", "d"]));
+
+    // LREM counts from the tail when negative
+    assert_eq!(client.call(&["LREM", "l", "-1", "b"]), Reply::Integer(1));
+    assert_eq!(range(&mut client, "l"), Reply::bulks(&["A", "b", "x", "c", "d"]));
+    assert_eq!(client.call(&["LTRIM", "l", "1", "-2"]), Reply::ok());
+    assert_eq!(range(&mut client, "l"), Reply::bulks(&["b", "x", "c"]));
+    assert_eq!(client.call(&["LTRIM", "l", "5", "1"]), Reply::ok());
+    assert_eq!(client.call(&["LLEN", "l"]), Reply::Integer(0));
+}
+
+#[test]
+fn moves_and_positions() {
+    let server = Server::start("list-moves", 17543, &[]);
+    let mut client = server.client();
+    client.call(&["RPUSH", "src", "a", "b", "c"]);
+
+    assert_eq!(client.call(&["LMOVE", "src", "dst", "LEFT", "RIGHT"]), Reply::bulk("a"));
+    assert_eq!(client.call(&["RPOPLPUSH", "src", "dst"]), Reply::bulk("c"));
+    assert_eq!(range(&mut client, "dst"), Reply::bulks(&["c", "a"]));
+    // A list can rotate onto itself
+    assert_eq!(client.call(&["LMOVE", "dst", "dst", "LEFT", "RIGHT"]), Reply::bulk("c"));
+    assert_eq!(range(&mut client, "dst"), Reply::bulks(&["a", "c"]));
+    assert_eq!(client.call(&["LMOVE", "missing", "dst", "LEFT", "RIGHT"]), Reply::Bulk(None));
+
+    client.call(&["RPUSH", "p", "a", "b", "c", "b", "b"]);
+    assert_eq!(client.call(&["LPOS", "p", "b"]), Reply::Integer(1));
+    assert_eq!(client.call(&["LPOS", "p", "b", "RANK", "-1"]), Reply::Integer(4));
+    assert_eq!(
+        client.call(&["LPOS", "p", "b", "COUNT", "0"]),
+        Reply::array(vec![Reply::Integer(1), Reply::Integer(3), Reply::Integer(4)])
+    );
+    assert_eq!(client.call(&["LPOS", "p", "b", "COUNT", "2", "MAXLEN", "2"]), Reply::array(vec![Reply::Integer(1)]));
+    assert_eq!(client.call(&["LPOS", "p", "z"]), Reply::Bulk(None));
+
+    // LMPOP takes from the first non-empty list
+    assert_eq!(
+        client.call(&["LMPOP", "2", "empty", "p", "RIGHT", "COUNT", "2"]),
+        Reply::array(vec![Reply::bulk("p"), Reply::bulks(&["b", "b"])])
+    );
+    assert_eq!(client.call(&["LMPOP", "1", "empty", "LEFT"]), Reply::Array(None));
+}
+
+#[test]
+fn blocking_pops() {
+    let server = Server::start("list-blocking", 17544, &[]);
+    let mut pusher = server.client();
+    let mut first = server.client();
+    let mut second = server.client();
+
+    // Waiters are served in the order they blocked
+    first.send(&["BLPOP", "a", "b", "0"]);
+    thread::sleep(Duration::from_millis(100));
+    second.send(&["BRPOP", "b", "0"]);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(pusher.call(&["RPUSH", "b", "x", "y"]), Reply::Integer(2));
+    assert_eq!(first.read(), Reply::bulks(&["b", "x"]));
+    assert_eq!(second.read(), Reply::bulks(&["b", "y"]));
+
+    // A timeout gives a null array
+    assert_eq!(first.call(&["BLPOP", "a", "0.1"]), Reply::Array(None));
+
+    first.send(&["BLMOVE", "src", "dst", "RIGHT", "LEFT", "0"]);
+    second.send(&["BLMPOP", "0", "1", "dst", "LEFT"]);
+    thread::sleep(Duration::from_millis(100));
+    pusher.call(&["RPUSH", "src", "v"]);
+    assert_eq!(first.read(), Reply::bulk("v"));
+    assert_eq!(second.read(), Reply::array(vec![Reply::bulk("dst"), Reply::bulks(&["v"])]));
+    assert_eq!(pusher.call(&["LLEN", "dst"]), Reply::Integer(0));
+}
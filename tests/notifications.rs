@@ -0,0 +1,113 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{Client, Reply, Server};
+
+// A subscriber to every keyspace and keyevent channel
+fn subscriber(server: &Server) -> Client {
+    let mut client = server.client();
+    assert_eq!(client.call(&["PSUBSCRIBE", "__key*__:*"]).texts(), ["psubscribe", "__key*__:*", "Integer(1)"]);
+    client
+}
+
+// The channels and payloads published since the last call, as "channel payload"
+fn events(subscriber: &mut Client) -> Vec<String> {
+    let mut events = vec![];
+    while let Some(reply) = subscriber.read_within(Duration::from_millis(200)) {
+        let message = reply.texts();
+        assert_eq!(message[0], "pmessage");
+        events.push(format!("{} {}", message[2], message[3]));
+    }
+    events
+}
+
+fn set_flags(client: &mut Client, flags: &str) {
+    assert_eq!(client.call(&["CONFIG", "SET", "notify-keyspace-events", flags]), Reply::ok());
+}
+
+#[test]
+fn notification_flag_filtering() {
+    let server = Server::start("notify-flags", 17501, &[]);
+    let mut client = server.client();
+    let mut subscriber = subscriber(&server);
+
+    // Off by default
+    client.call(&["SET", "k", "v"]);
+    assert_eq!(events(&mut subscriber), Vec::<String>::new());
+
+    // K and E pick the channels, the rest the classes of event
+    set_flags(&mut client, "KEA");
+    client.call(&["SET", "k", "v"]);
+    assert_eq!(events(&mut subscriber), ["__keyspace@0__:k set", "__keyevent@0__:set k"]);
+
+    set_flags(&mut client, "K$");
+    client.call(&["SET", "k", "v"]);
+    client.call(&["LPUSH", "l", "a"]);
+    assert_eq!(events(&mut subscriber), ["__keyspace@0__:k set"]);
+
+    set_flags(&mut client, "El");
+    client.call(&["SET", "k", "v"]);
+    client.call(&["LPUSH", "l", "a"]);
+    assert_eq!(events(&mut subscriber), ["__keyevent@0__:lpush l"]);
+
+    set_flags(&mut client, "Eg");
+    client.call(&["SADD", "s", "a"]);
+    client.call(&["DEL", "s"]);
+    assert_eq!(events(&mut subscriber), ["__keyevent@0__:del s"]);
+
+    set_flags(&mut client, "Ehz");
+    client.call(&["HSET", "h", "f", "v"]);
+    client.call(&["ZADD", "z", "1", "a"]);
+    client.call(&["XADD", "x", "*", "f", "v"]);
+    assert_eq!(events(&mut subscriber), ["__keyevent@0__:hset h", "__keyevent@0__:zadd z"]);
+
+    // Classes without K or E, and an empty string, turn notifications off
+    set_flags(&mut client, "A");
+    client.call(&["SET", "k", "v"]);
+    assert_eq!(events(&mut subscriber), Vec::<String>::new());
+    set_flags(&mut client, "KEA");
+    set_flags(&mut client, "");
+    client.call(&["SET", "k", "v"]);
+    assert_eq!(events(&mut subscriber), Vec::<String>::new());
+}
+
+#[test]
+fn expired_events() {
+    let server = Server::start("notify-expired", 17502, &[]);
+    let mut client = server.client();
+    let mut subscriber = subscriber(&server);
+
+    // Only the expiry is published, not the SET or its TTL
+    set_flags(&mut client, "Ex");
+    client.call(&["SET", "k", "v", "PX", "50"]);
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(client.call(&["GET", "k"]), Reply::Bulk(None));
+    assert_eq!(events(&mut subscriber), ["__keyevent@0__:expired k"]);
+}
+
+#[test]
+fn notification_flags_config() {
+    let server = Server::start("notify-config", 17503, &["--notify-keyspace-events", "KEA"]);
+    let mut client = server.client();
+    let get = |client: &mut Client| client.call(&["CONFIG", "GET", "notify-keyspace-events"]);
+
+    // The canonical form folds the classes 'A' covers back into it
+    assert_eq!(get(&mut client), Reply::bulks(&["notify-keyspace-events", "AKE"]));
+    set_flags(&mut client, "Kg$lshzxetd");
+    assert_eq!(get(&mut client), Reply::bulks(&["notify-keyspace-events", "AK"]));
+    set_flags(&mut client, "Elm");
+    assert_eq!(get(&mut client), Reply::bulks(&["notify-keyspace-events", "lEm"]));
+    set_flags(&mut client, "g");
+    assert_eq!(get(&mut client), Reply::bulks(&["notify-keyspace-events", ""]));
+
+    // Unknown characters are refused and leave the setting alone
+    set_flags(&mut client, "KEA");
+    assert_eq!(
+        client.call(&["CONFIG", "SET", "notify-keyspace-events", "KEq"]),
+        Reply::error(
+            "ERR CONFIG SET failed (possibly related to argument 'notify-keyspace-events') - Invalid event class character. Use 'Ag$lshzxeKEtmnd'."
+        )
+    );
+    assert_eq!(get(&mut client), Reply::bulks(&["notify-keyspace-events", "AKE"]));
+}
@@ -0,0 +1,228 @@
+mod common;
+
+use std::{fs, path::Path, thread, time::Duration};
+
+use common::{info_field, wait_until, Client, Reply, Server};
+
+// A key of every type, in both the compact and the regular encodings where there are two
+fn fill(client: &mut Client) {
+    client.call(&["SET", "string", "value"]);
+    client.call(&["SET", "number", "12345"]);
+    client.call(&["SET", "expiring", "value", "PX", "1000000"]);
+    client.call(&["SET", "short", "value", "PX", "3000"]);
+    client.call(&["SET", "long", &"x".repeat(1000)]);
+    client.call(&["RPUSH", "list", "a", "b", "c"]);
+    for i in 0..200 {
+        client.call(&["RPUSH", "biglist", &i.to_string()]);
+        client.call(&["HSET", "bighash", &format!("f{}", i), &i.to_string()]);
+        client.call(&["SADD", "bigset", &format!("m{}", i)]);
+        client.call(&["ZADD", "bigzset", &i.to_string(), &format!("m{}", i)]);
+    }
+    client.call(&["HSET", "hash", "a", "1", "b", "2"]);
+    client.call(&["HEXPIRE", "hash", "1000", "FIELDS", "1", "a"]);
+    client.call(&["SADD", "intset", "1", "2", "3"]);
+    client.call(&["ZADD", "zset", "1.5", "a", "2", "b"]);
+    client.call(&["XADD", "stream", "1-0", "f", "v"]);
+    client.call(&["XADD", "stream", "2-0", "f", "w"]);
+    client.call(&["XGROUP", "CREATE", "stream", "group", "0"]);
+    client.call(&["XREADGROUP", "GROUP", "group", "alice", "COUNT", "1", "STREAMS", "stream", ">"]);
+    client.call(&["BF.ADD", "bloom", "item"]);
+    client.call(&["CMS.INITBYDIM", "cms", "10", "2"]);
+    client.call(&["CMS.INCRBY", "cms", "a", "3"]);
+    client.call(&["TOPK.RESERVE", "topk", "2"]);
+    client.call(&["TOPK.ADD", "topk", "a"]);
+    client.call(&["JSON.SET", "json", "$", r#"{"a":[1,2]}"#]);
+    client.call(&["TS.ADD", "ts", "100", "1.5", "LABELS", "l", "v"]);
+}
+
+fn check(client: &mut Client) {
+    assert_eq!(client.call(&["GET", "string"]), Reply::bulk("value"));
+    assert_eq!(client.call(&["GET", "number"]), Reply::bulk("12345"));
+    assert_eq!(client.call(&["GET", "expiring"]), Reply::bulk("value"));
+    wait_until(|| client.call(&["GET", "short"]) == Reply::Bulk(None));
+    assert_eq!(client.call(&["GET", "long"]), Reply::bulk(&"x".repeat(1000)));
+    assert_eq!(client.call(&["LRANGE", "list", "0", "-1"]), Reply::bulks(&["a", "b", "c"]));
+    assert_eq!(client.call(&["LINDEX", "biglist", "199"]), Reply::bulk("199"));
+    assert_eq!(client.call(&["HGET", "bighash", "f150"]), Reply::bulk("150"));
+    assert_eq!(client.call(&["SCARD", "bigset"]), Reply::Integer(200));
+    assert_eq!(client.call(&["ZSCORE", "bigzset", "m150"]), Reply::bulk("150"));
+    assert_eq!(client.call(&["HGETALL", "hash"]).texts(), ["a", "1", "b", "2"]);
+    let Reply::Array(Some(ttls)) = client.call(&["HTTL", "hash", "FIELDS", "2", "a", "b"]) else {
+        panic!("HTTL should reply with an array");
+    };
+    assert!(matches!(ttls[..], [Reply::Integer(ttl), Reply::Integer(-1)] if ttl > 990));
+    assert_eq!(client.call(&["OBJECT", "ENCODING", "intset"]), Reply::bulk("intset"));
+    assert_eq!(client.call(&["SMEMBERS", "intset"]), Reply::bulks(&["1", "2", "3"]));
+    assert_eq!(client.call(&["ZRANGE", "zset", "0", "-1", "WITHSCORES"]), Reply::bulks(&["a", "1.5", "b", "2"]));
+    assert_eq!(client.call(&["XLEN", "stream"]), Reply::Integer(2));
+    let Reply::Array(Some(pending)) = client.call(&["XPENDING", "stream", "group", "-", "+", "10"]) else {
+        panic!("XPENDING should reply with an array");
+    };
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].texts()[..2], ["1-0", "alice"]);
+    assert_eq!(client.call(&["BF.EXISTS", "bloom", "item"]), Reply::Integer(1));
+    assert_eq!(client.call(&["CMS.QUERY", "cms", "a"]), Reply::array(vec![Reply::Integer(3)]));
+    assert_eq!(client.call(&["TOPK.LIST", "topk"]), Reply::bulks(&["a"]));
+    assert_eq!(client.call(&["JSON.GET", "json"]), Reply::bulk(r#"{"a":[1,2]}"#));
+    assert_eq!(client.call(&["TS.GET", "ts"]), Reply::array(vec![Reply::Integer(100), Reply::bulk("1.5")]));
+}
+
+fn aof_files(dir: &Path) -> Vec<String> {
+    let mut files: Vec<String> = fs::read_dir(dir.join("appendonlydir")).unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn rdb_round_trip() {
+    let server = Server::start("rdb-round-trip", 17631, &[]);
+    let mut client = server.client();
+    fill(&mut client);
+    assert_eq!(client.call(&["SAVE"]), Reply::ok());
+    assert_eq!(info_field(&mut client, "persistence", "rdb_changes_since_last_save").as_deref(), Some("0"));
+    let dir = server.stop();
+
+    // Long repetitive strings are compressed
+    let data = fs::read(dir.join("dump.rdb")).unwrap();
+    assert!(!data.windows(1000).any(|window| window == "x".repeat(1000).as_bytes()));
+    let server = Server::start_in(dir, 17631, &[]);
+    check(&mut server.client());
+}
+
+#[test]
+fn background_and_automatic_saves() {
+    let server = Server::start("rdb-bgsave", 17632, &[]);
+    let mut client = server.client();
+    let Reply::Integer(before) = client.call(&["LASTSAVE"]) else {
+        panic!("LASTSAVE should reply with an integer");
+    };
+    client.call(&["SET", "k", "v"]);
+    assert_eq!(info_field(&mut client, "persistence", "rdb_changes_since_last_save").as_deref(), Some("1"));
+    thread::sleep(Duration::from_millis(1100));
+
+    assert_eq!(client.call(&["BGSAVE"]), Reply::Simple("Background saving started".to_string()));
+    wait_until(|| info_field(&mut client, "persistence", "rdb_bgsave_in_progress").as_deref() == Some("0"));
+    assert!(matches!(client.call(&["LASTSAVE"]), Reply::Integer(after) if after > before));
+    assert_eq!(info_field(&mut client, "persistence", "rdb_last_bgsave_status").as_deref(), Some("ok"));
+    assert_eq!(info_field(&mut client, "persistence", "rdb_changes_since_last_save").as_deref(), Some("0"));
+
+    // A save rule saves once enough changes have been made in its time
+    assert_eq!(client.call(&["CONFIG", "SET", "save", "1 2"]), Reply::ok());
+    client.call(&["SET", "k", "w"]);
+    thread::sleep(Duration::from_millis(1500));
+    assert_eq!(info_field(&mut client, "persistence", "rdb_changes_since_last_save").as_deref(), Some("1"));
+    client.call(&["SET", "k", "x"]);
+    wait_until(|| info_field(&mut client, "persistence", "rdb_changes_since_last_save").as_deref() == Some("0"));
+    let dir = server.stop();
+    let server = Server::start_in(dir, 17632, &[]);
+    assert_eq!(server.client().call(&["GET", "k"]), Reply::bulk("x"));
+}
+
+#[test]
+fn rdb_checksums() {
+    let server = Server::start("rdb-checksum", 17633, &[]);
+    let mut client = server.client();
+    client.call(&["SET", "k", "checksummed"]);
+    client.call(&["SAVE"]);
+    let dir = server.stop();
+
+    // Flip a byte of the value, leaving the file readable but its checksum wrong
+    let path = dir.join("dump.rdb");
+    let mut data = fs::read(&path).unwrap();
+    let at = data.windows(11).position(|window| window == b"checksummed").unwrap();
+    data[at] = b'C';
+    fs::write(&path, &data).unwrap();
+    assert!(Server::refuses_to_start(&dir, 17633, &[]));
+    let server = Server::start_in(dir, 17633, &["--rdb-checksum-mismatch", "warn"]);
+    let mut client = server.client();
+    assert_eq!(client.call(&["GET", "k"]), Reply::bulk("Checksummed"));
+
+    // Files saved without checksums load anyway
+    client.call(&["CONFIG", "SET", "rdbchecksum", "no"]);
+    client.call(&["SAVE"]);
+    let dir = server.stop();
+    assert!(fs::read(dir.join("dump.rdb")).unwrap().ends_with(&[0; 8]));
+    let server = Server::start_in(dir, 17633, &[]);
+    assert_eq!(server.client().call(&["GET", "k"]), Reply::bulk("Checksummed"));
+}
+
+#[test]
+fn aof_round_trip_and_rewrite() {
+    let server = Server::start("aof-round-trip", 17634, &["--appendonly", "yes"]);
+    let mut client = server.client();
+    fill(&mut client);
+    for i in 0..100 {
+        client.call(&["SET", "counter", &i.to_string()]);
+    }
+    assert_eq!(aof_files(&server.dir), ["appendonly.aof.1.base.rdb", "appendonly.aof.1.incr.aof", "appendonly.aof.manifest"]);
+
+    // A rewrite starts a new base and incr file and drops the old ones
+    assert_eq!(client.call(&["BGREWRITEAOF"]), Reply::Simple("Background append only file rewriting started".to_string()));
+    wait_until(|| info_field(&mut client, "persistence", "aof_rewrite_in_progress").as_deref() == Some("0"));
+    assert_eq!(info_field(&mut client, "persistence", "aof_last_bgrewrite_status").as_deref(), Some("ok"));
+    wait_until(|| aof_files(&server.dir) == ["appendonly.aof.2.base.rdb", "appendonly.aof.2.incr.aof", "appendonly.aof.manifest"]);
+    client.call(&["SET", "after", "rewrite"]);
+    assert_eq!(client.call(&["WAITAOF", "1", "0", "0"]), Reply::array(vec![Reply::Integer(1), Reply::Integer(0)]));
+
+    let dir = server.stop();
+    let server = Server::start_in(dir, 17634, &["--appendonly", "yes"]);
+    let mut client = server.client();
+    check(&mut client);
+    assert_eq!(client.call(&["GET", "counter"]), Reply::bulk("99"));
+    assert_eq!(client.call(&["GET", "after"]), Reply::bulk("rewrite"));
+}
+
+#[test]
+fn aof_without_rdb_preamble() {
+    let server = Server::start("aof-no-preamble", 17635, &["--appendonly", "yes", "--aof-use-rdb-preamble", "no"]);
+    let mut client = server.client();
+    fill(&mut client);
+    client.call(&["BGREWRITEAOF"]);
+    wait_until(|| info_field(&mut client, "persistence", "aof_rewrite_in_progress").as_deref() == Some("0"));
+    wait_until(|| aof_files(&server.dir).contains(&"appendonly.aof.2.base.aof".to_string()));
+    client.call(&["WAITAOF", "1", "0", "0"]);
+    let dir = server.stop();
+    let server = Server::start_in(dir, 17635, &["--appendonly", "yes"]);
+    check(&mut server.client());
+}
+
+#[test]
+fn truncated_aof() {
+    let server = Server::start("aof-truncated", 17636, &["--appendonly", "yes", "--appendfsync", "always"]);
+    let mut client = server.client();
+    client.call(&["SET", "first", "1"]);
+    client.call(&["SET", "second", "2"]);
+    // Appending happens in the background, so make sure it is done before the server goes
+    client.call(&["WAITAOF", "1", "0", "0"]);
+    let dir = server.stop();
+
+    // Cut the last command short, as a crash in the middle of a write would
+    let path = dir.join("appendonlydir").join("appendonly.aof.1.incr.aof");
+    let data = fs::read(&path).unwrap();
+    fs::write(&path, &data[..data.len() - 3]).unwrap();
+    assert!(Server::refuses_to_start(&dir, 17636, &["--appendonly", "yes", "--aof-load-truncated", "no"]));
+    let server = Server::start_in(dir, 17636, &["--appendonly", "yes"]);
+    let mut client = server.client();
+    assert_eq!(client.call(&["GET", "first"]), Reply::bulk("1"));
+    assert_eq!(client.call(&["GET", "second"]), Reply::Bulk(None));
+    // And the file is cut back to the last whole command, so new writes follow on from it
+    client.call(&["SET", "third", "3"]);
+    client.call(&["WAITAOF", "1", "0", "0"]);
+    let dir = server.stop();
+    let server = Server::start_in(dir, 17636, &["--appendonly", "yes", "--aof-load-truncated", "no"]);
+    assert_eq!(server.client().call(&["GET", "third"]), Reply::bulk("3"));
+}
+
+#[test]
+fn waitaof_needs_appendonly() {
+    let server = Server::start("waitaof", 17637, &[]);
+    let mut client = server.client();
+    assert_eq!(
+        client.call(&["WAITAOF", "1", "0", "0"]),
+        Reply::error("ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled.")
+    );
+    assert_eq!(client.call(&["WAITAOF", "0", "0", "0"]), Reply::array(vec![Reply::Integer(0), Reply::Integer(0)]));
+    assert_eq!(client.call(&["WAITAOF", "0", "0", "-1"]), Reply::error("ERR timeout is negative"));
+}
@@ -0,0 +1,191 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{info_field, wait_until, Client, Reply, Server};
+
+fn subscription(kind: &str, name: &str, count: i64) -> Reply {
+    Reply::array(vec![Reply::bulk(kind), Reply::bulk(name), Reply::Integer(count)])
+}
+
+fn push(items: &[&str]) -> Reply {
+    Reply::Push(items.iter().map(|item| Reply::bulk(item)).collect())
+}
+
+fn nothing_more(client: &mut Client) {
+    assert_eq!(client.read_within(Duration::from_millis(200)), None);
+}
+
+#[test]
+fn channels_and_patterns() {
+    let server = Server::start("pubsub-channels", 17511, &[]);
+    let mut publisher = server.client();
+    let mut subscriber = server.client();
+
+    // One reply per name, counting channels and patterns together
+    subscriber.send(&["SUBSCRIBE", "news", "sport"]);
+    assert_eq!(subscriber.read(), subscription("subscribe", "news", 1));
+    assert_eq!(subscriber.read(), subscription("subscribe", "sport", 2));
+    assert_eq!(subscriber.call(&["PSUBSCRIBE", "n*"]), subscription("psubscribe", "n*", 3));
+
+    // A channel that is also matched by a pattern is delivered both ways
+    assert_eq!(publisher.call(&["PUBLISH", "news", "hello"]), Reply::Integer(2));
+    assert_eq!(subscriber.read(), Reply::bulks(&["message", "news", "hello"]));
+    assert_eq!(subscriber.read(), Reply::bulks(&["pmessage", "n*", "news", "hello"]));
+    assert_eq!(publisher.call(&["PUBLISH", "nothing", "x"]), Reply::Integer(1));
+    assert_eq!(subscriber.read(), Reply::bulks(&["pmessage", "n*", "nothing", "x"]));
+    assert_eq!(publisher.call(&["PUBLISH", "weather", "x"]), Reply::Integer(0));
+    nothing_more(&mut subscriber);
+
+    // UNSUBSCRIBE without names drops every channel but leaves the patterns
+    subscriber.send(&["UNSUBSCRIBE"]);
+    assert_eq!(subscriber.read(), subscription("unsubscribe", "news", 2));
+    assert_eq!(subscriber.read(), subscription("unsubscribe", "sport", 1));
+    assert_eq!(publisher.call(&["PUBLISH", "sport", "x"]), Reply::Integer(0));
+    assert_eq!(subscriber.call(&["PUNSUBSCRIBE"]), subscription("punsubscribe", "n*", 0));
+    assert_eq!(
+        subscriber.call(&["PUNSUBSCRIBE"]),
+        Reply::array(vec![Reply::bulk("punsubscribe"), Reply::Bulk(None), Reply::Integer(0)])
+    );
+    assert_eq!(publisher.call(&["PUBLISH", "news", "x"]), Reply::Integer(0));
+}
+
+#[test]
+fn pubsub_introspection() {
+    let server = Server::start("pubsub-introspection", 17512, &[]);
+    let mut client = server.client();
+    let mut first = server.client();
+    let mut second = server.client();
+    first.send(&["SUBSCRIBE", "a1", "b1"]);
+    first.read();
+    first.read();
+    second.call(&["SUBSCRIBE", "a1"]);
+    second.call(&["PSUBSCRIBE", "a*"]);
+    second.call(&["SSUBSCRIBE", "s1"]);
+
+    assert_eq!(client.call(&["PUBSUB", "CHANNELS"]), Reply::bulks(&["a1", "b1"]));
+    assert_eq!(client.call(&["PUBSUB", "CHANNELS", "a*"]), Reply::bulks(&["a1"]));
+    assert_eq!(
+        client.call(&["PUBSUB", "NUMSUB", "a1", "b1", "c1"]),
+        Reply::array(vec![
+            Reply::bulk("a1"),
+            Reply::Integer(2),
+            Reply::bulk("b1"),
+            Reply::Integer(1),
+            Reply::bulk("c1"),
+            Reply::Integer(0),
+        ])
+    );
+    assert_eq!(client.call(&["PUBSUB", "NUMPAT"]), Reply::Integer(1));
+    assert_eq!(client.call(&["PUBSUB", "SHARDCHANNELS"]), Reply::bulks(&["s1"]));
+    assert_eq!(client.call(&["PUBSUB", "SHARDNUMSUB", "s1"]), Reply::array(vec![Reply::bulk("s1"), Reply::Integer(1)]));
+    assert_eq!(client.call(&["PUBSUB", "NUMPAT", "x"]), Reply::error("ERR wrong number of arguments for 'pubsub|numpat' command"));
+    assert_eq!(client.call(&["PUBSUB", "NOPE"]), Reply::error("ERR unknown subcommand 'nope'. Try PUBSUB HELP."));
+
+    // Closed connections are forgotten
+    drop(first);
+    wait_until(|| client.call(&["PUBSUB", "CHANNELS"]) == Reply::bulks(&["a1"]));
+}
+
+#[test]
+fn sharded_channels() {
+    let server = Server::start("pubsub-sharded", 17513, &[]);
+    let mut publisher = server.client();
+    let mut subscriber = server.client();
+
+    // Shard channels are counted apart from the others and patterns don't see their messages
+    assert_eq!(subscriber.call(&["SUBSCRIBE", "c"]), subscription("subscribe", "c", 1));
+    assert_eq!(subscriber.call(&["PSUBSCRIBE", "*"]), subscription("psubscribe", "*", 2));
+    assert_eq!(subscriber.call(&["SSUBSCRIBE", "c"]), subscription("ssubscribe", "c", 1));
+    assert_eq!(publisher.call(&["SPUBLISH", "c", "hi"]), Reply::Integer(1));
+    assert_eq!(subscriber.read(), Reply::bulks(&["smessage", "c", "hi"]));
+    nothing_more(&mut subscriber);
+
+    // And PUBLISH doesn't reach shard subscribers
+    assert_eq!(publisher.call(&["PUBLISH", "c", "hi"]), Reply::Integer(2));
+    assert_eq!(subscriber.read(), Reply::bulks(&["message", "c", "hi"]));
+    assert_eq!(subscriber.read(), Reply::bulks(&["pmessage", "*", "c", "hi"]));
+    nothing_more(&mut subscriber);
+
+    assert_eq!(subscriber.call(&["SUNSUBSCRIBE", "c"]), subscription("sunsubscribe", "c", 0));
+    assert_eq!(publisher.call(&["SPUBLISH", "c", "hi"]), Reply::Integer(0));
+}
+
+#[test]
+fn subscribe_mode_restrictions() {
+    let server = Server::start("pubsub-restrictions", 17514, &[]);
+    let mut client = server.client();
+    client.call(&["SUBSCRIBE", "c"]);
+
+    // A RESP2 subscriber may only manage subscriptions, PING, QUIT and RESET
+    assert_eq!(
+        client.call(&["GET", "k"]),
+        Reply::error("ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context")
+    );
+    assert_eq!(client.call(&["PING"]), Reply::bulks(&["pong", ""]));
+
+    // RESET drops the subscriptions without unsubscribe replies
+    assert_eq!(client.call(&["RESET"]), Reply::Simple("RESET".to_string()));
+    assert_eq!(client.call(&["GET", "k"]), Reply::Bulk(None));
+    assert_eq!(client.call(&["PUBSUB", "NUMSUB", "c"]), Reply::array(vec![Reply::bulk("c"), Reply::Integer(0)]));
+
+    // QUIT replies and then closes the connection
+    client.call(&["SUBSCRIBE", "c"]);
+    assert_eq!(client.call(&["QUIT"]), Reply::ok());
+    let mut other = server.client();
+    wait_until(|| other.call(&["PUBSUB", "NUMSUB", "c"]) == Reply::array(vec![Reply::bulk("c"), Reply::Integer(0)]));
+}
+
+#[test]
+fn resp3_push_frames() {
+    let server = Server::start("pubsub-resp3", 17515, &[]);
+    let mut publisher = server.client();
+    let mut subscriber = server.client();
+    let Reply::Map(fields) = subscriber.call(&["HELLO", "3"]) else {
+        panic!("HELLO 3 should reply with a map");
+    };
+    assert!(fields.contains(&(Reply::bulk("proto"), Reply::Integer(3))));
+
+    // Subscription replies and messages arrive as push frames
+    assert_eq!(
+        subscriber.call(&["SUBSCRIBE", "c"]),
+        Reply::Push(vec![Reply::bulk("subscribe"), Reply::bulk("c"), Reply::Integer(1)])
+    );
+    publisher.call(&["PUBLISH", "c", "hi"]);
+    assert_eq!(subscriber.read(), push(&["message", "c", "hi"]));
+
+    // And a RESP3 subscriber can go on running other commands, with nulls in RESP3 form
+    assert_eq!(subscriber.call(&["GET", "k"]), Reply::Null);
+    assert_eq!(subscriber.call(&["PING"]), Reply::Simple("PONG".to_string()));
+    publisher.call(&["PUBLISH", "c", "again"]);
+    assert_eq!(subscriber.read(), push(&["message", "c", "again"]));
+
+    // RESET goes back to RESP2
+    assert_eq!(subscriber.call(&["RESET"]), Reply::Simple("RESET".to_string()));
+    assert_eq!(subscriber.call(&["GET", "k"]), Reply::Bulk(None));
+    assert_eq!(subscriber.call(&["HELLO", "4"]), Reply::error("NOPROTO unsupported protocol version"));
+}
+
+// A subscriber that stops reading is dropped once its queued messages pass the pubsub limit,
+// while others on the channel carry on
+#[test]
+fn slow_subscribers_are_dropped() {
+    let server = Server::start("pubsub-limit", 17516, &["--client-output-buffer-limit", "pubsub 1mb 0 0"]);
+    let mut client = server.client();
+    assert_eq!(
+        client.call(&["CONFIG", "GET", "client-output-buffer-limit"]),
+        Reply::bulks(&["client-output-buffer-limit", "normal 0 0 0 slave 268435456 67108864 60 pubsub 1048576 0 0"])
+    );
+    let mut slow = server.client();
+    slow.call(&["SUBSCRIBE", "c"]);
+    let mut fast = server.client();
+    fast.call(&["SUBSCRIBE", "c"]);
+
+    let message = "m".repeat(100_000);
+    for _ in 0..200 {
+        client.call(&["PUBLISH", "c", &message]);
+        assert_eq!(fast.read(), Reply::bulks(&["message", "c", &message]));
+    }
+    wait_until(|| client.call(&["PUBSUB", "NUMSUB", "c"]) == Reply::array(vec![Reply::bulk("c"), Reply::Integer(1)]));
+    assert_eq!(info_field(&mut client, "stats", "client_output_buffer_limit_disconnections").as_deref(), Some("1"));
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use std::collections::HashSet;
+
+use common::{Client, Reply, Server};
+
+fn items(reply: Reply) -> Vec<String> {
+    match reply {
+        Reply::Array(Some(items)) => items.iter().map(Reply::text).collect(),
+        other => panic!("expected an array, got {:?}", other),
+    }
+}
+
+// Counts asking for more repeats than a reply could hold are refused rather than allocated
+fn check_count_limits(client: &mut Client, command: &[&str]) {
+    let call = |client: &mut Client, count: &str| client.call(&[command, &[count]].concat());
+    assert_eq!(call(client, "-4611686018427387904"), Reply::Error("ERR value is out of range".to_string()));
+    assert_eq!(call(client, "-9223372036854775808"), Reply::Error("ERR value is out of range".to_string()));
+    assert_eq!(call(client, "-100000000000000"), Reply::Error("ERR not enough memory for a reply of that size".to_string()));
+    assert_eq!(call(client, "abc"), Reply::Error("ERR value is not an integer or out of range".to_string()));
+    assert_eq!(client.call(&["PING"]), Reply::Simple("PONG".to_string()));
+}
+
+#[test]
+fn srandmember_counts() {
+    let server = Server::start("srandmember", 17451, &[]);
+    let mut client = server.client();
+    client.call(&["SADD", "s", "a", "b", "c"]);
+    let members: HashSet<String> = ["a", "b", "c"].map(String::from).into();
+
+    // Positive counts give distinct members, at most all of them
+    let picked = items(client.call(&["SRANDMEMBER", "s", "2"]));
+    assert_eq!(picked.len(), 2);
+    assert_eq!(picked.iter().collect::<HashSet<_>>().len(), 2);
+    assert_eq!(items(client.call(&["SRANDMEMBER", "s", "10"])).into_iter().collect::<HashSet<_>>(), members);
+    // Negative ones give exactly that many, with repeats
+    let picked = items(client.call(&["SRANDMEMBER", "s", "-20"]));
+    assert_eq!(picked.len(), 20);
+    assert!(picked.iter().all(|member| members.contains(member)));
+    assert_eq!(client.call(&["SRANDMEMBER", "s", "0"]), Reply::Array(Some(vec![])));
+    assert_eq!(client.call(&["SRANDMEMBER", "missing", "-5"]), Reply::Array(Some(vec![])));
+    assert_eq!(client.call(&["SRANDMEMBER", "missing"]), Reply::Bulk(None));
+
+    check_count_limits(&mut client, &["SRANDMEMBER", "s"]);
+}
+
+#[test]
+fn hrandfield_counts() {
+    let server = Server::start("hrandfield", 17452, &[]);
+    let mut client = server.client();
+    client.call(&["HSET", "h", "a", "1", "b", "2", "c", "3"]);
+    let fields: HashSet<String> = ["a", "b", "c"].map(String::from).into();
+
+    let picked = items(client.call(&["HRANDFIELD", "h", "2"]));
+    assert_eq!(picked.iter().collect::<HashSet<_>>().len(), 2);
+    assert_eq!(items(client.call(&["HRANDFIELD", "h", "10"])).into_iter().collect::<HashSet<_>>(), fields);
+    let picked = items(client.call(&["HRANDFIELD", "h", "-20"]));
+    assert_eq!(picked.len(), 20);
+    assert!(picked.iter().all(|field| fields.contains(field)));
+    // Values follow their fields
+    let picked = items(client.call(&["HRANDFIELD", "h", "-6", "WITHVALUES"]));
+    assert_eq!(picked.len(), 12);
+    for pair in picked.chunks(2) {
+        assert_eq!(pair[1], (pair[0].as_bytes()[0] - b'a' + 1).to_string());
+    }
+    assert_eq!(client.call(&["HRANDFIELD", "missing", "-5"]), Reply::Array(Some(vec![])));
+    assert_eq!(client.call(&["HRANDFIELD", "missing"]), Reply::Bulk(None));
+
+    check_count_limits(&mut client, &["HRANDFIELD", "h"]);
+}
+
+#[test]
+fn zrandmember_counts() {
+    let server = Server::start("zrandmember", 17453, &[]);
+    let mut client = server.client();
+    client.call(&["ZADD", "z", "1", "a", "2", "b", "3", "c"]);
+    let members: HashSet<String> = ["a", "b", "c"].map(String::from).into();
+
+    let picked = items(client.call(&["ZRANDMEMBER", "z", "2"]));
+    assert_eq!(picked.iter().collect::<HashSet<_>>().len(), 2);
+    assert_eq!(items(client.call(&["ZRANDMEMBER", "z", "10"])).into_iter().collect::<HashSet<_>>(), members);
+    let picked = items(client.call(&["ZRANDMEMBER", "z", "-20"]));
+    assert_eq!(picked.len(), 20);
+    assert!(picked.iter().all(|member| members.contains(member)));
+    // Scores follow their members
+    let picked = items(client.call(&["ZRANDMEMBER", "z", "-6", "WITHSCORES"]));
+    assert_eq!(picked.len(), 12);
+    for pair in picked.chunks(2) {
+        assert_eq!(pair[1], (pair[0].as_bytes()[0] - b'a' + 1).to_string());
+    }
+    assert_eq!(client.call(&["ZRANDMEMBER", "missing", "-5"]), Reply::Array(Some(vec![])));
+    assert_eq!(client.call(&["ZRANDMEMBER", "missing"]), Reply::Bulk(None));
+
+    check_count_limits(&mut client, &["ZRANDMEMBER", "z"]);
+}
@@ -0,0 +1,224 @@
+mod common;
+
+use common::{info_field, wait_until, Reply, Server};
+
+// IDs XADD generates reach replicas and the AOF as they were generated, rather than as *
+#[test]
+fn generated_stream_ids_propagate() {
+    let master = Server::start("xadd-master", 17441, &["--appendonly", "yes", "--appendfsync", "always"]);
+    let replica = Server::start("xadd-replica", 17442, &["--replicaof", "127.0.0.1 17441"]);
+    let (mut to_master, mut to_replica) = (master.client(), replica.client());
+    wait_until(|| info_field(&mut to_replica, "replication", "master_link_status").as_deref() == Some("up"));
+
+    to_master.call(&["XADD", "s", "*", "f", "1"]);
+    to_master.call(&["XADD", "s", "*", "f", "2"]);
+    to_master.call(&["XADD", "s", "MAXLEN", "~", "10", "*", "f", "3"]);
+    let entries = to_master.call(&["XRANGE", "s", "-", "+"]);
+    wait_until(|| to_replica.call(&["XLEN", "s"]) == Reply::Integer(3));
+    assert_eq!(to_replica.call(&["XRANGE", "s", "-", "+"]), entries);
+    drop(replica);
+
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    let dir = master.stop();
+    let master = Server::start_in(dir, 17441, &["--appendonly", "yes"]);
+    assert_eq!(master.client().call(&["XRANGE", "s", "-", "+"]), entries);
+}
+
+// A replica of a replica gets the master's stream passed on, at the master's offsets
+#[test]
+fn replicas_chain() {
+    let master = Server::start("chain-master", 17443, &[]);
+    let middle = Server::start("chain-middle", 17444, &["--replicaof", "127.0.0.1 17443"]);
+    let end = Server::start("chain-end", 17445, &["--replicaof", "127.0.0.1 17444"]);
+    let (mut to_master, mut to_middle, mut to_end) = (master.client(), middle.client(), end.client());
+    wait_until(|| info_field(&mut to_middle, "replication", "master_link_status").as_deref() == Some("up"));
+    wait_until(|| info_field(&mut to_end, "replication", "master_link_status").as_deref() == Some("up"));
+    assert_eq!(info_field(&mut to_middle, "replication", "connected_slaves").as_deref(), Some("1"));
+
+    assert_eq!(to_master.call(&["SET", "key", "value"]), Reply::Simple("OK".to_string()));
+    to_master.call(&["RPUSH", "list", "a", "b", "c"]);
+    wait_until(|| to_end.call(&["LLEN", "list"]) == Reply::Integer(3));
+    assert_eq!(to_end.call(&["GET", "key"]), Reply::bulk("value"));
+
+    let offset = info_field(&mut to_master, "replication", "master_repl_offset");
+    let replid = info_field(&mut to_master, "replication", "master_replid");
+    wait_until(|| info_field(&mut to_end, "replication", "master_repl_offset") == offset);
+    assert_eq!(info_field(&mut to_middle, "replication", "master_repl_offset"), offset);
+    assert_eq!(info_field(&mut to_middle, "replication", "master_replid"), replid);
+    assert_eq!(info_field(&mut to_end, "replication", "master_replid"), replid);
+
+    // The end of the chain is read-only like any replica
+    assert_eq!(to_end.call(&["SET", "key", "other"]).text(), "READONLY You can't write against a read only replica.");
+}
+
+// HELLO gives the role the server has now, which changes with REPLICAOF
+#[test]
+fn hello_reports_role() {
+    let master = Server::start("hello-master", 17446, &[]);
+    let replica = Server::start("hello-replica", 17447, &["--replicaof", "127.0.0.1 17446"]);
+    let role = |client: &mut common::Client| match client.call(&["HELLO"]) {
+        Reply::Array(Some(fields)) => fields.chunks(2).find(|pair| pair[0].text() == "role").map(|pair| pair[1].text()),
+        other => panic!("unexpected HELLO reply {:?}", other),
+    };
+    let (mut to_master, mut to_replica) = (master.client(), replica.client());
+    assert_eq!(role(&mut to_master).as_deref(), Some("master"));
+    assert_eq!(role(&mut to_replica).as_deref(), Some("replica"));
+
+    assert_eq!(to_replica.call(&["REPLICAOF", "NO", "ONE"]), Reply::Simple("OK".to_string()));
+    assert_eq!(role(&mut to_replica).as_deref(), Some("master"));
+}
+
+#[test]
+fn replicas_are_read_only() {
+    let master = Server::start("read-only-master", 17481, &[]);
+    let replica = Server::start("read-only-replica", 17482, &["--replicaof", "127.0.0.1 17481"]);
+    let (mut to_master, mut to_replica) = (master.client(), replica.client());
+    wait_until(|| info_field(&mut to_replica, "replication", "master_link_status").as_deref() == Some("up"));
+    to_master.call(&["SET", "k", "v"]);
+    wait_until(|| to_replica.call(&["GET", "k"]) == Reply::bulk("v"));
+
+    // Writes are refused, reads and PUBLISH aren't
+    let readonly = Reply::Error("READONLY You can't write against a read only replica.".to_string());
+    assert_eq!(to_replica.call(&["SET", "k", "w"]), readonly);
+    assert_eq!(to_replica.call(&["DEL", "k"]), readonly);
+    assert_eq!(to_replica.call(&["PUBLISH", "c", "m"]), Reply::Integer(0));
+    assert_eq!(info_field(&mut to_replica, "replication", "slave_read_only").as_deref(), Some("1"));
+
+    // A refused write aborts the transaction it was queued in
+    to_replica.call(&["MULTI"]);
+    assert_eq!(to_replica.call(&["SET", "k", "w"]), readonly);
+    assert_eq!(to_replica.call(&["EXEC"]).text(), "EXECABORT Transaction discarded because of previous errors.");
+
+    // Unless replica-read-only is turned off, when local writes are allowed
+    to_replica.call(&["CONFIG", "SET", "replica-read-only", "no"]);
+    assert_eq!(to_replica.call(&["SET", "local", "x"]), Reply::Simple("OK".to_string()));
+    assert_eq!(to_master.call(&["GET", "local"]), Reply::Bulk(None));
+    assert_eq!(info_field(&mut to_replica, "replication", "slave_read_only").as_deref(), Some("0"));
+}
+
+#[test]
+fn replicaof_no_one_promotes() {
+    let master = Server::start("promote-master", 17483, &[]);
+    let replica = Server::start("promote-replica", 17484, &["--replicaof", "127.0.0.1 17483"]);
+    let (mut to_master, mut to_replica) = (master.client(), replica.client());
+    wait_until(|| info_field(&mut to_replica, "replication", "master_link_status").as_deref() == Some("up"));
+    assert_eq!(info_field(&mut to_replica, "replication", "role").as_deref(), Some("slave"));
+    assert_eq!(info_field(&mut to_replica, "replication", "master_host").as_deref(), Some("127.0.0.1"));
+    assert_eq!(info_field(&mut to_replica, "replication", "master_port").as_deref(), Some("17483"));
+    assert_eq!(info_field(&mut to_master, "replication", "connected_slaves").as_deref(), Some("1"));
+    to_master.call(&["SET", "k", "v"]);
+    let offset = info_field(&mut to_master, "replication", "master_repl_offset");
+    wait_until(|| info_field(&mut to_replica, "replication", "master_repl_offset") == offset);
+    let replid = info_field(&mut to_master, "replication", "master_replid");
+
+    // The dataset is kept, writes are taken, and the old history is remembered as the second
+    assert_eq!(to_replica.call(&["REPLICAOF", "NO", "ONE"]), Reply::Simple("OK".to_string()));
+    assert_eq!(info_field(&mut to_replica, "replication", "role").as_deref(), Some("master"));
+    assert_eq!(info_field(&mut to_replica, "replication", "master_host"), None);
+    assert_eq!(info_field(&mut to_replica, "replication", "master_replid2"), replid);
+    assert_ne!(info_field(&mut to_replica, "replication", "master_replid"), replid);
+    let next = offset.unwrap().parse::<u64>().unwrap() + 1;
+    assert_eq!(info_field(&mut to_replica, "replication", "second_repl_offset"), Some(next.to_string()));
+    assert_eq!(to_replica.call(&["GET", "k"]), Reply::bulk("v"));
+    assert_eq!(to_replica.call(&["SET", "k", "w"]), Reply::Simple("OK".to_string()));
+
+    // And the old master forgets it
+    wait_until(|| info_field(&mut to_master, "replication", "connected_slaves").as_deref() == Some("0"));
+    assert_eq!(to_master.call(&["GET", "k"]), Reply::bulk("v"));
+}
+
+#[test]
+fn stale_data_while_master_is_down() {
+    // Nothing listens on the master's port, so the link never comes up
+    let replica = Server::start("stale-replica", 17485, &["--replicaof", "127.0.0.1 17486", "--replica-serve-stale-data", "no"]);
+    let mut client = replica.client();
+    assert_eq!(info_field(&mut client, "replication", "master_link_status").as_deref(), Some("down"));
+
+    let masterdown = Reply::Error("MASTERDOWN Link with MASTER is down and replica-serve-stale-data is set to 'no'.".to_string());
+    assert_eq!(client.call(&["GET", "k"]), masterdown);
+    assert_eq!(client.call(&["PING"]), Reply::Simple("PONG".to_string()));
+    assert_eq!(client.call(&["CONFIG", "SET", "replica-serve-stale-data", "yes"]), Reply::Simple("OK".to_string()));
+    assert_eq!(client.call(&["GET", "k"]), Reply::Bulk(None));
+}
+
+#[test]
+fn min_replicas_to_write() {
+    let master = Server::start("min-replicas-master", 17487, &["--min-replicas-to-write", "1", "--min-replicas-max-lag", "10"]);
+    let mut client = master.client();
+    let noreplicas = Reply::Error("NOREPLICAS Not enough good replicas to write.".to_string());
+    assert_eq!(client.call(&["SET", "k", "v"]), noreplicas);
+    assert_eq!(client.call(&["GET", "k"]), Reply::Bulk(None));
+    assert_eq!(info_field(&mut client, "replication", "min_slaves_good_slaves").as_deref(), Some("0"));
+
+    // Writes are taken once a replica has synced and acknowledged
+    let replica = Server::start("min-replicas-replica", 17488, &["--replicaof", "127.0.0.1 17487"]);
+    wait_until(|| info_field(&mut client, "replication", "min_slaves_good_slaves").as_deref() == Some("1"));
+    assert_eq!(client.call(&["SET", "k", "v"]), Reply::Simple("OK".to_string()));
+
+    // And refused again once it has gone
+    drop(replica);
+    wait_until(|| client.call(&["SET", "k", "w"]) == noreplicas);
+}
+
+// A replica that loses its link picks up where it left off from the backlog, keeping what it
+// has, which a full resync would have thrown away
+#[test]
+fn partial_resync_from_backlog() {
+    let master = Server::start("backlog-master", 17489, &["--repl-timeout", "2"]);
+    let replica = Server::start("backlog-replica", 17490, &["--replicaof", "127.0.0.1 17489", "--replica-read-only", "no"]);
+    let (mut to_master, mut to_replica) = (master.client(), replica.client());
+    wait_until(|| info_field(&mut to_replica, "replication", "master_link_status").as_deref() == Some("up"));
+    to_master.call(&["SET", "a", "1"]);
+    wait_until(|| to_replica.call(&["GET", "a"]) == Reply::bulk("1"));
+    to_replica.call(&["SET", "local", "x"]);
+    let slave = info_field(&mut to_master, "replication", "slave0").unwrap();
+    assert!(slave.starts_with("ip=127.0.0.1,port=17490,state=online,"), "{}", slave);
+    assert_eq!(info_field(&mut to_master, "replication", "repl_backlog_active").as_deref(), Some("1"));
+    assert_eq!(info_field(&mut to_master, "replication", "repl_backlog_first_byte_offset").as_deref(), Some("1"));
+
+    // A replica that stops acknowledging is dropped once repl-timeout passes
+    replica.pause();
+    wait_until(|| info_field(&mut to_master, "replication", "connected_slaves").as_deref() == Some("0"));
+    to_master.call(&["SET", "b", "2"]);
+    replica.resume();
+    wait_until(|| to_replica.call(&["GET", "b"]) == Reply::bulk("2"));
+    assert_eq!(to_replica.call(&["GET", "local"]), Reply::bulk("x"));
+    let offset = info_field(&mut to_master, "replication", "master_repl_offset");
+    wait_until(|| info_field(&mut to_replica, "replication", "slave_repl_offset") == offset);
+}
+
+// Once the backlog no longer goes back far enough the replica has to start over
+#[test]
+fn full_resync_past_the_backlog() {
+    let master = Server::start("overflow-master", 17491, &["--repl-timeout", "2", "--repl-backlog-size", "16kb"]);
+    let replica = Server::start("overflow-replica", 17492, &["--replicaof", "127.0.0.1 17491", "--replica-read-only", "no"]);
+    let (mut to_master, mut to_replica) = (master.client(), replica.client());
+    wait_until(|| info_field(&mut to_replica, "replication", "master_link_status").as_deref() == Some("up"));
+    to_replica.call(&["SET", "local", "x"]);
+
+    replica.pause();
+    wait_until(|| info_field(&mut to_master, "replication", "connected_slaves").as_deref() == Some("0"));
+    for i in 0..40 {
+        to_master.call(&["SET", &format!("k{}", i), &"v".repeat(1000)]);
+    }
+    assert_eq!(info_field(&mut to_master, "replication", "repl_backlog_histlen").as_deref(), Some("16384"));
+    replica.resume();
+    wait_until(|| to_replica.call(&["GET", "k39"]) == Reply::bulk(&"v".repeat(1000)));
+    assert_eq!(to_replica.call(&["GET", "k0"]), Reply::bulk(&"v".repeat(1000)));
+    assert_eq!(to_replica.call(&["GET", "local"]), Reply::Bulk(None));
+}
+
+// A full sync saves the dump first, unless the snapshot is streamed straight to the replica
+#[test]
+fn diskless_sync() {
+    for (diskless, port) in [("no", 17493), ("yes", 17495)] {
+        let master = Server::start("diskless-master", port, &["--repl-diskless-sync", diskless]);
+        let mut to_master = master.client();
+        to_master.call(&["SET", "k", "v"]);
+        let replica = Server::start("diskless-replica", port + 1, &["--replicaof", &format!("127.0.0.1 {}", port)]);
+        let mut to_replica = replica.client();
+        wait_until(|| to_replica.call(&["GET", "k"]) == Reply::bulk("v"));
+        assert_eq!(info_field(&mut to_replica, "replication", "master_sync_in_progress").as_deref(), Some("0"));
+        assert_eq!(master.dir.join("dump.rdb").exists(), diskless == "no");
+    }
+}
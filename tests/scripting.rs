@@ -0,0 +1,130 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{Reply, Server};
+
+// A script that never finishes is stopped once it passes busy-reply-threshold, and the server
+// goes back to answering
+#[test]
+fn runaway_script_is_stopped() {
+    let server = Server::start("runaway-script", 17421, &["--busy-reply-threshold", "200"]);
+    let mut client = server.client();
+    let start = Instant::now();
+    let Reply::Error(error) = client.call(&["EVAL", "while true do end", "0"]) else {
+        panic!("the script wasn't stopped");
+    };
+    assert!(error.starts_with("ERR user_script:1: Script exceeded the time limit of 200 milliseconds script: "), "{}", error);
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert_eq!(client.call(&["PING"]), Reply::Simple("PONG".to_string()));
+
+    // pcall can't catch it
+    assert_eq!(client.call(&["CONFIG", "SET", "lua-time-limit", "100"]), Reply::Simple("OK".to_string()));
+    let Reply::Error(error) = client.call(&["EVAL", "while true do pcall(function() while true do end end) end", "0"]) else {
+        panic!("the script wasn't stopped");
+    };
+    assert!(error.contains("Script exceeded the time limit of 100 milliseconds"), "{}", error);
+    assert_eq!(client.call(&["SET", "key", "value"]), Reply::Simple("OK".to_string()));
+}
+
+#[test]
+fn script_cache() {
+    let server = Server::start("script-cache", 17422, &[]);
+    let mut client = server.client();
+    let script = "return {KEYS[1], ARGV[1], redis.call('SET', KEYS[1], ARGV[1])}";
+
+    let sha = client.call(&["SCRIPT", "LOAD", script]).text();
+    assert_eq!(sha.len(), 40);
+    assert_eq!(
+        client.call(&["EVALSHA", &sha, "1", "k", "v"]),
+        Reply::array(vec![Reply::bulk("k"), Reply::bulk("v"), Reply::Simple("OK".to_string())])
+    );
+    assert_eq!(client.call(&["GET", "k"]), Reply::bulk("v"));
+    // EVAL caches what it runs under the same digest
+    let other = "return 1";
+    assert_eq!(client.call(&["EVAL", other, "0"]), Reply::Integer(1));
+    let other_sha = client.call(&["SCRIPT", "LOAD", other]).text();
+    assert_eq!(
+        client.call(&["SCRIPT", "EXISTS", &sha, &other_sha, "0000000000000000000000000000000000000000"]),
+        Reply::array(vec![Reply::Integer(1), Reply::Integer(1), Reply::Integer(0)])
+    );
+
+    assert_eq!(client.call(&["SCRIPT", "FLUSH"]), Reply::Simple("OK".to_string()));
+    assert_eq!(client.call(&["EVALSHA", &sha, "0"]), Reply::Error("NOSCRIPT No matching script. Please use EVAL.".to_string()));
+    assert_eq!(client.call(&["EVAL", other, "-1"]), Reply::Error("ERR Number of keys can't be negative".to_string()));
+    assert_eq!(client.call(&["EVAL", other, "2", "k"]), Reply::Error("ERR Number of keys can't be greater than number of args".to_string()));
+    assert!(client.call(&["EVAL", "return (", "0"]).text().starts_with("ERR Error compiling script"));
+}
+
+#[test]
+fn read_only_scripts() {
+    let server = Server::start("script-read-only", 17423, &[]);
+    let mut client = server.client();
+    client.call(&["SET", "k", "v"]);
+
+    assert_eq!(client.call(&["EVAL_RO", "return redis.call('GET', KEYS[1])", "1", "k"]), Reply::bulk("v"));
+    let refused = client.call(&["EVAL_RO", "return redis.call('SET', KEYS[1], 'w')", "1", "k"]).text();
+    assert!(refused.contains("Write commands are not allowed from read-only scripts"), "{}", refused);
+    assert_eq!(client.call(&["GET", "k"]), Reply::bulk("v"));
+    let blocked = client.call(&["EVAL", "return redis.call('MULTI')", "0"]).text();
+    assert!(blocked.contains("This Redis command is not allowed from script"), "{}", blocked);
+}
+
+#[test]
+fn functions() {
+    let server = Server::start("functions", 17424, &[]);
+    let mut client = server.client();
+    let library = "#!lua name=mylib\n\
+        redis.register_function('setter', function(keys, args) return redis.call('SET', keys[1], args[1]) end)\n\
+        redis.register_function{function_name='getter', callback=function(keys) return redis.call('GET', keys[1]) end, flags={'no-writes'}}";
+
+    assert_eq!(client.call(&["FUNCTION", "LOAD", library]), Reply::bulk("mylib"));
+    assert_eq!(client.call(&["FUNCTION", "LOAD", library]), Reply::Error("ERR Library 'mylib' already exists".to_string()));
+    assert_eq!(client.call(&["FUNCTION", "LOAD", "REPLACE", library]), Reply::bulk("mylib"));
+    assert_eq!(client.call(&["FCALL", "setter", "1", "k", "v"]), Reply::Simple("OK".to_string()));
+    assert_eq!(client.call(&["FCALL_RO", "getter", "1", "k"]), Reply::bulk("v"));
+    assert_eq!(
+        client.call(&["FCALL_RO", "setter", "1", "k", "v"]),
+        Reply::Error("ERR Can not execute a script with write flag using *_ro command.".to_string())
+    );
+    assert_eq!(client.call(&["FCALL", "nope", "0"]), Reply::Error("ERR Function not found".to_string()));
+
+    let Reply::Array(Some(libraries)) = client.call(&["FUNCTION", "LIST", "WITHCODE"]) else {
+        panic!("FUNCTION LIST should reply with an array");
+    };
+    assert_eq!(libraries.len(), 1);
+    assert_eq!(libraries[0].field("library_name"), Reply::bulk("mylib"));
+    assert_eq!(libraries[0].field("library_code"), Reply::bulk(library));
+    let Reply::Array(Some(functions)) = libraries[0].field("functions") else {
+        panic!("functions should be an array");
+    };
+    let names: Vec<String> = functions.iter().map(|function| function.field("name").text()).collect();
+    assert_eq!(names, ["setter", "getter"]);
+
+    assert_eq!(client.call(&["FUNCTION", "LOAD", "return 1"]), Reply::Error("ERR Missing library metadata".to_string()));
+    assert_eq!(client.call(&["FUNCTION", "LOAD", "#!lua name=empty\nlocal x = 1"]), Reply::Error("ERR No functions registered".to_string()));
+    assert_eq!(client.call(&["FUNCTION", "DELETE", "mylib"]), Reply::Simple("OK".to_string()));
+    assert_eq!(client.call(&["FUNCTION", "DELETE", "mylib"]), Reply::Error("ERR Library not found".to_string()));
+    assert_eq!(client.call(&["FCALL", "getter", "1", "k"]), Reply::Error("ERR Function not found".to_string()));
+}
+
+// Replicas get the writes a script made rather than the script itself
+#[test]
+fn script_effects_replicate() {
+    let master = Server::start("script-master", 17425, &[]);
+    let replica = Server::start("script-replica", 17426, &["--replicaof", "127.0.0.1 17425"]);
+    let (mut to_master, mut to_replica) = (master.client(), replica.client());
+    common::wait_until(|| common::info_field(&mut to_replica, "replication", "master_link_status").as_deref() == Some("up"));
+
+    to_master.call(&["SADD", "set", "a", "b", "c", "d"]);
+    let script = "redis.call('SET', KEYS[2], redis.call('SPOP', KEYS[1])) redis.call('RPUSH', KEYS[3], 'a', 'b') return 1";
+    assert_eq!(to_master.call(&["EVAL", script, "3", "set", "popped", "list"]), Reply::Integer(1));
+    let popped = to_master.call(&["GET", "popped"]);
+    common::wait_until(|| to_replica.call(&["LLEN", "list"]) == Reply::Integer(2));
+    assert_eq!(to_replica.call(&["GET", "popped"]), popped);
+    assert_eq!(to_replica.call(&["SISMEMBER", "set", &popped.text()]), Reply::Integer(0));
+    // And nothing of a read-only one
+    let offset = common::info_field(&mut to_master, "replication", "master_repl_offset");
+    to_master.call(&["EVAL", "return redis.call('GET', KEYS[1])", "1", "popped"]);
+    assert_eq!(common::info_field(&mut to_master, "replication", "master_repl_offset"), offset);
+}
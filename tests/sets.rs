@@ -0,0 +1,109 @@
+mod common;
+
+use std::collections::HashSet;
+
+use common::{Client, Reply, Server};
+
+fn members(client: &mut Client, key: &str) -> HashSet<String> {
+    client.call(&["SMEMBERS", key]).texts().into_iter().collect()
+}
+
+fn set_of(items: &[&str]) -> HashSet<String> {
+    items.iter().map(|item| item.to_string()).collect()
+}
+
+#[test]
+fn members_and_encoding() {
+    let server = Server::start("set-members", 17561, &["--set-max-intset-entries", "3"]);
+    let mut client = server.client();
+
+    assert_eq!(client.call(&["SADD", "s", "1", "2", "2"]), Reply::Integer(2));
+    assert_eq!(client.call(&["OBJECT", "ENCODING", "s"]), Reply::bulk("intset"));
+    // Integer sets list their members in order
+    client.call(&["SADD", "s", "-5"]);
+    assert_eq!(client.call(&["SMEMBERS", "s"]).texts(), ["-5", "1", "2"]);
+    // A fourth member, or one that isn't an integer, converts it for good
+    client.call(&["SADD", "s", "3"]);
+    assert_eq!(client.call(&["OBJECT", "ENCODING", "s"]), Reply::bulk("hashtable"));
+    client.call(&["SREM", "s", "3"]);
+    assert_eq!(client.call(&["OBJECT", "ENCODING", "s"]), Reply::bulk("hashtable"));
+    client.call(&["SADD", "t", "a"]);
+    assert_eq!(client.call(&["OBJECT", "ENCODING", "t"]), Reply::bulk("hashtable"));
+
+    assert_eq!(client.call(&["SISMEMBER", "s", "1"]), Reply::Integer(1));
+    assert_eq!(client.call(&["SISMEMBER", "s", "9"]), Reply::Integer(0));
+    assert_eq!(
+        client.call(&["SMISMEMBER", "s", "1", "9", "2"]),
+        Reply::array(vec![Reply::Integer(1), Reply::Integer(0), Reply::Integer(1)])
+    );
+    assert_eq!(client.call(&["SCARD", "s"]), Reply::Integer(3));
+    assert_eq!(client.call(&["SREM", "s", "1", "2", "-5", "9"]), Reply::Integer(3));
+    assert_eq!(client.call(&["SCARD", "s"]), Reply::Integer(0));
+    assert_eq!(client.call(&["DEL", "s"]), Reply::Integer(0));
+}
+
+#[test]
+fn algebra() {
+    let server = Server::start("set-algebra", 17562, &[]);
+    let mut client = server.client();
+    client.call(&["SADD", "a", "1", "2", "3", "x"]);
+    client.call(&["SADD", "b", "2", "3", "4"]);
+    client.call(&["SADD", "c", "3", "x"]);
+
+    assert_eq!(client.call(&["SINTER", "a", "b"]).texts().into_iter().collect::<HashSet<_>>(), set_of(&["2", "3"]));
+    assert_eq!(client.call(&["SINTER", "a", "missing"]), Reply::bulks(&[]));
+    assert_eq!(
+        client.call(&["SUNION", "b", "c"]).texts().into_iter().collect::<HashSet<_>>(),
+        set_of(&["2", "3", "4", "x"])
+    );
+    assert_eq!(client.call(&["SDIFF", "a", "b", "c"]).texts(), ["1"]);
+
+    assert_eq!(client.call(&["SINTERSTORE", "d", "a", "b", "c"]), Reply::Integer(1));
+    assert_eq!(members(&mut client, "d"), set_of(&["3"]));
+    assert_eq!(client.call(&["SUNIONSTORE", "d", "b", "c"]), Reply::Integer(4));
+    // An empty result removes the destination
+    assert_eq!(client.call(&["SDIFFSTORE", "d", "c", "a"]), Reply::Integer(0));
+    assert_eq!(client.call(&["SCARD", "d"]), Reply::Integer(0));
+
+    assert_eq!(client.call(&["SINTERCARD", "2", "a", "b"]), Reply::Integer(2));
+    assert_eq!(client.call(&["SINTERCARD", "2", "a", "b", "LIMIT", "1"]), Reply::Integer(1));
+    assert_eq!(client.call(&["SINTERCARD", "0", "a"]), Reply::error("ERR numkeys should be greater than 0"));
+}
+
+#[test]
+fn pop_move_and_scan() {
+    let server = Server::start("set-pop", 17563, &[]);
+    let mut client = server.client();
+    client.call(&["SADD", "s", "a", "b", "c"]);
+
+    let popped = client.call(&["SPOP", "s"]).text();
+    assert!(["a", "b", "c"].contains(&popped.as_str()));
+    assert_eq!(client.call(&["SCARD", "s"]), Reply::Integer(2));
+    assert_eq!(client.call(&["SPOP", "s", "5"]).texts().len(), 2);
+    assert_eq!(client.call(&["SCARD", "s"]), Reply::Integer(0));
+    assert_eq!(client.call(&["SPOP", "s"]), Reply::Bulk(None));
+
+    client.call(&["SADD", "src", "a", "b"]);
+    assert_eq!(client.call(&["SMOVE", "src", "dst", "a"]), Reply::Integer(1));
+    assert_eq!(client.call(&["SMOVE", "src", "dst", "z"]), Reply::Integer(0));
+    assert_eq!(members(&mut client, "dst"), set_of(&["a"]));
+    assert_eq!(members(&mut client, "src"), set_of(&["b"]));
+
+    let items: Vec<String> = (0..50).map(|i| format!("m{}", i)).collect();
+    for item in &items {
+        client.call(&["SADD", "big", item]);
+    }
+    let mut cursor = "0".to_string();
+    let mut seen = HashSet::new();
+    loop {
+        let Reply::Array(Some(reply)) = client.call(&["SSCAN", "big", &cursor, "MATCH", "m1*", "COUNT", "5"]) else {
+            panic!("SSCAN should reply with an array");
+        };
+        seen.extend(reply[1].texts());
+        cursor = reply[0].text();
+        if cursor == "0" {
+            break;
+        }
+    }
+    assert_eq!(seen, items.into_iter().filter(|item| item.starts_with("m1")).collect());
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use common::{Reply, Server};
+
+fn integers(values: &[i64]) -> Reply {
+    Reply::array(values.iter().map(|value| Reply::Integer(*value)).collect())
+}
+
+#[test]
+fn count_min_sketch() {
+    let server = Server::start("cms", 17601, &[]);
+    let mut client = server.client();
+
+    assert_eq!(client.call(&["CMS.INITBYDIM", "c", "100", "5"]), Reply::ok());
+    assert_eq!(client.call(&["CMS.INITBYDIM", "c", "100", "5"]), Reply::error("CMS: key already exists"));
+    assert_eq!(client.call(&["CMS.INCRBY", "c", "a", "3", "b", "1", "a", "2"]), integers(&[3, 1, 5]));
+    assert_eq!(client.call(&["CMS.QUERY", "c", "a", "b", "z"]), integers(&[5, 1, 0]));
+    let info = client.call(&["CMS.INFO", "c"]);
+    assert_eq!(info.field("width"), Reply::Integer(100));
+    assert_eq!(info.field("depth"), Reply::Integer(5));
+    assert_eq!(info.field("count"), Reply::Integer(6));
+
+    // Merging sums the sources' counts, times their weights
+    client.call(&["CMS.INITBYDIM", "d", "100", "5"]);
+    client.call(&["CMS.INCRBY", "d", "a", "1"]);
+    client.call(&["CMS.INITBYDIM", "dst", "100", "5"]);
+    assert_eq!(client.call(&["CMS.MERGE", "dst", "2", "c", "d", "WEIGHTS", "1", "10"]), Reply::ok());
+    assert_eq!(client.call(&["CMS.QUERY", "dst", "a", "b"]), integers(&[15, 1]));
+    client.call(&["CMS.INITBYDIM", "narrow", "10", "5"]);
+    assert_eq!(client.call(&["CMS.MERGE", "dst", "1", "narrow"]), Reply::error("CMS: width/depth is not equal"));
+
+    assert_eq!(client.call(&["CMS.INITBYPROB", "p", "0.001", "0.01"]), Reply::ok());
+    assert_eq!(client.call(&["CMS.INITBYPROB", "q", "2", "0.01"]), Reply::error("CMS: invalid overestimation value"));
+    assert_eq!(client.call(&["CMS.INITBYDIM", "q", "0", "5"]), Reply::error("CMS: invalid width"));
+    assert_eq!(client.call(&["CMS.INCRBY", "c", "a", "-1"]), Reply::error("CMS: Number cannot be negative"));
+    assert_eq!(client.call(&["CMS.QUERY", "missing", "a"]), Reply::error("CMS: key does not exist"));
+}
+
+#[test]
+fn top_k() {
+    let server = Server::start("topk", 17602, &[]);
+    let mut client = server.client();
+
+    assert_eq!(client.call(&["TOPK.RESERVE", "t", "2", "50", "4", "0.9"]), Reply::ok());
+    assert_eq!(client.call(&["TOPK.RESERVE", "t", "2"]), Reply::error("TopK: key already exists"));
+    assert_eq!(client.call(&["TOPK.INCRBY", "t", "a", "10", "b", "5"]), Reply::array(vec![Reply::Bulk(None), Reply::Bulk(None)]));
+    // A heavier item pushes the lightest one out
+    assert_eq!(client.call(&["TOPK.INCRBY", "t", "c", "20"]), Reply::array(vec![Reply::bulk("b")]));
+    assert_eq!(client.call(&["TOPK.ADD", "t", "a"]), Reply::array(vec![Reply::Bulk(None)]));
+
+    assert_eq!(client.call(&["TOPK.QUERY", "t", "a", "b", "c"]), integers(&[1, 0, 1]));
+    assert_eq!(client.call(&["TOPK.COUNT", "t", "a", "c"]), integers(&[11, 20]));
+    assert_eq!(client.call(&["TOPK.LIST", "t"]), Reply::bulks(&["c", "a"]));
+    assert_eq!(
+        client.call(&["TOPK.LIST", "t", "WITHCOUNT"]),
+        Reply::array(vec![Reply::bulk("c"), Reply::Integer(20), Reply::bulk("a"), Reply::Integer(11)])
+    );
+    let info = client.call(&["TOPK.INFO", "t"]);
+    assert_eq!(info.field("k"), Reply::Integer(2));
+    assert_eq!(info.field("width"), Reply::Integer(50));
+    assert_eq!(info.field("depth"), Reply::Integer(4));
+    assert_eq!(info.field("decay"), Reply::bulk("0.9"));
+
+    assert_eq!(client.call(&["TOPK.RESERVE", "x", "0"]), Reply::error("TopK: invalid k"));
+    assert_eq!(client.call(&["TOPK.RESERVE", "x", "2", "8", "7", "1.5"]), Reply::error("TopK: invalid decay value. must be '<= 1' & '> 0'"));
+    assert_eq!(client.call(&["TOPK.LIST", "missing"]), Reply::error("TopK: key does not exist"));
+}
@@ -0,0 +1,173 @@
+mod common;
+
+use common::{Reply, Server};
+
+// The last ID can't go back past the entries a stream has held, deleted or not
+#[test]
+fn xsetid_keeps_ids_increasing() {
+    let server = Server::start("xsetid", 17431, &[]);
+    let mut client = server.client();
+    let ok = Reply::Simple("OK".to_string());
+    client.call(&["XADD", "s", "1-0", "f", "v"]);
+    client.call(&["XADD", "s", "5-0", "f", "v"]);
+    assert_eq!(client.call(&["XSETID", "s", "4-0"]), Reply::Error("ERR The ID specified in XSETID is smaller than the target stream top item".to_string()));
+
+    // With the top entry deleted, the deleted entry's ID is the bound
+    assert_eq!(client.call(&["XDEL", "s", "5-0"]), Reply::Integer(1));
+    assert_eq!(client.call(&["XSETID", "s", "3-0"]), Reply::Error("ERR The ID specified in XSETID is smaller than current max_deleted_entry_id".to_string()));
+    assert_eq!(client.call(&["XSETID", "s", "5-0"]), ok);
+    assert_eq!(client.call(&["XSETID", "s", "3-0", "MAXDELETEDID", "4-0"]), Reply::Error("ERR The ID specified in XSETID is smaller than the provided max_deleted_entry_id".to_string()));
+    assert_eq!(client.call(&["XSETID", "s", "3-0", "MAXDELETEDID", "2-0"]), ok);
+    assert_eq!(client.call(&["XSETID", "s", "2-5"]), ok);
+    assert_eq!(client.call(&["XSETID", "s", "1-5"]), Reply::Error("ERR The ID specified in XSETID is smaller than current max_deleted_entry_id".to_string()));
+
+    // Emptied streams are bound the same way
+    assert_eq!(client.call(&["XDEL", "s", "1-0"]), Reply::Integer(1));
+    assert_eq!(client.call(&["XSETID", "s", "0-5"]), Reply::Error("ERR The ID specified in XSETID is smaller than current max_deleted_entry_id".to_string()));
+    assert_eq!(client.call(&["XSETID", "s", "9-0"]), ok);
+}
+
+fn entry(id: &str, fields: &[&str]) -> Reply {
+    Reply::array(vec![Reply::bulk(id), Reply::bulks(fields)])
+}
+
+#[test]
+fn xadd_ids_and_ranges() {
+    let server = Server::start("stream-ranges", 17432, &[]);
+    let mut client = server.client();
+
+    assert_eq!(client.call(&["XADD", "s", "1-1", "a", "1"]), Reply::bulk("1-1"));
+    assert_eq!(client.call(&["XADD", "s", "1-*", "b", "2"]), Reply::bulk("1-2"));
+    assert_eq!(client.call(&["XADD", "s", "3-0", "c", "3"]), Reply::bulk("3-0"));
+    assert_eq!(
+        client.call(&["XADD", "s", "2-0", "x", "y"]),
+        Reply::error("ERR The ID specified in XADD is equal or smaller than the target stream top item")
+    );
+    assert_eq!(client.call(&["XADD", "t", "0-0", "x", "y"]), Reply::error("ERR The ID specified in XADD must be greater than 0-0"));
+    assert_eq!(client.call(&["XADD", "t", "NOMKSTREAM", "*", "x", "y"]), Reply::Bulk(None));
+    let generated = client.call(&["XADD", "t", "*", "x", "y"]).text();
+    assert!(generated.split_once('-').unwrap().0.parse::<u64>().unwrap() > 1_600_000_000_000);
+
+    assert_eq!(
+        client.call(&["XRANGE", "s", "-", "+"]),
+        Reply::array(vec![entry("1-1", &["a", "1"]), entry("1-2", &["b", "2"]), entry("3-0", &["c", "3"])])
+    );
+    // A bare millisecond time covers every sequence number, ( excludes the bound
+    assert_eq!(client.call(&["XRANGE", "s", "1", "1"]), Reply::array(vec![entry("1-1", &["a", "1"]), entry("1-2", &["b", "2"])]));
+    assert_eq!(client.call(&["XRANGE", "s", "(1-1", "+", "COUNT", "1"]), Reply::array(vec![entry("1-2", &["b", "2"])]));
+    assert_eq!(client.call(&["XREVRANGE", "s", "+", "-", "COUNT", "1"]), Reply::array(vec![entry("3-0", &["c", "3"])]));
+
+    assert_eq!(client.call(&["XLEN", "s"]), Reply::Integer(3));
+    assert_eq!(client.call(&["XDEL", "s", "1-2", "9-9"]), Reply::Integer(1));
+    assert_eq!(client.call(&["XLEN", "s"]), Reply::Integer(2));
+}
+
+#[test]
+fn xtrim() {
+    let server = Server::start("stream-trim", 17433, &[]);
+    let mut client = server.client();
+    for i in 1..=10 {
+        client.call(&["XADD", "s", &format!("{}-0", i), "f", "v"]);
+    }
+
+    assert_eq!(client.call(&["XTRIM", "s", "MAXLEN", "8"]), Reply::Integer(2));
+    assert_eq!(client.call(&["XTRIM", "s", "MINID", "5"]), Reply::Integer(2));
+    assert_eq!(client.call(&["XRANGE", "s", "-", "+", "COUNT", "1"]), Reply::array(vec![entry("5-0", &["f", "v"])]));
+    // Approximate trimming never removes more than asked
+    let Reply::Integer(trimmed) = client.call(&["XTRIM", "s", "MAXLEN", "~", "3"]) else {
+        panic!("XTRIM should reply with an integer");
+    };
+    assert!(trimmed <= 3);
+    client.call(&["XADD", "s", "MAXLEN", "2", "*", "f", "v"]);
+    assert_eq!(client.call(&["XLEN", "s"]), Reply::Integer(2));
+
+    // Trimming everything leaves an empty stream rather than no key
+    assert_eq!(client.call(&["XTRIM", "s", "MAXLEN", "0"]), Reply::Integer(2));
+    assert_eq!(client.call(&["XLEN", "s"]), Reply::Integer(0));
+    assert_eq!(client.call(&["DEL", "s"]), Reply::Integer(1));
+}
+
+#[test]
+fn xread() {
+    let server = Server::start("stream-xread", 17434, &[]);
+    let mut client = server.client();
+    let mut reader = server.client();
+    client.call(&["XADD", "a", "1-0", "f", "1"]);
+    client.call(&["XADD", "a", "2-0", "f", "2"]);
+
+    assert_eq!(
+        reader.call(&["XREAD", "COUNT", "1", "STREAMS", "a", "b", "1-0", "0"]),
+        Reply::array(vec![Reply::array(vec![Reply::bulk("a"), Reply::array(vec![entry("2-0", &["f", "2"])])])])
+    );
+    assert_eq!(reader.call(&["XREAD", "STREAMS", "a", "2-0"]), Reply::Array(None));
+    assert_eq!(reader.call(&["XREAD", "BLOCK", "100", "STREAMS", "a", "$"]), Reply::Array(None));
+
+    // $ waits for entries added after the call, not those already there
+    reader.send(&["XREAD", "BLOCK", "0", "STREAMS", "a", "$"]);
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    client.call(&["XADD", "a", "3-0", "f", "3"]);
+    assert_eq!(
+        reader.read(),
+        Reply::array(vec![Reply::array(vec![Reply::bulk("a"), Reply::array(vec![entry("3-0", &["f", "3"])])])])
+    );
+}
+
+#[test]
+fn consumer_groups() {
+    let server = Server::start("stream-groups", 17435, &[]);
+    let mut client = server.client();
+    client.call(&["XADD", "s", "1-0", "f", "1"]);
+    client.call(&["XADD", "s", "2-0", "f", "2"]);
+    client.call(&["XADD", "s", "3-0", "f", "3"]);
+    assert_eq!(client.call(&["XGROUP", "CREATE", "s", "g", "0"]), Reply::ok());
+
+    let read = client.call(&["XREADGROUP", "GROUP", "g", "alice", "COUNT", "2", "STREAMS", "s", ">"]);
+    assert_eq!(read, Reply::array(vec![Reply::array(vec![Reply::bulk("s"), Reply::array(vec![entry("1-0", &["f", "1"]), entry("2-0", &["f", "2"])])])]));
+    client.call(&["XREADGROUP", "GROUP", "g", "bob", "STREAMS", "s", ">"]);
+
+    // The summary form counts what each consumer has pending
+    assert_eq!(
+        client.call(&["XPENDING", "s", "g"]),
+        Reply::array(vec![
+            Reply::Integer(3),
+            Reply::bulk("1-0"),
+            Reply::bulk("3-0"),
+            Reply::array(vec![Reply::bulks(&["alice", "2"]), Reply::bulks(&["bob", "1"])]),
+        ])
+    );
+    assert_eq!(client.call(&["XACK", "s", "g", "1-0", "9-0"]), Reply::Integer(1));
+    let Reply::Array(Some(pending)) = client.call(&["XPENDING", "s", "g", "-", "+", "10", "alice"]) else {
+        panic!("XPENDING should reply with an array");
+    };
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].texts()[..2], ["2-0", "alice"]);
+
+    // Claiming moves an entry to another consumer
+    assert_eq!(client.call(&["XCLAIM", "s", "g", "bob", "0", "2-0"]), Reply::array(vec![entry("2-0", &["f", "2"])]));
+    assert_eq!(
+        client.call(&["XAUTOCLAIM", "s", "g", "carol", "0", "0", "COUNT", "10"]),
+        Reply::array(vec![Reply::bulk("0-0"), Reply::array(vec![entry("2-0", &["f", "2"]), entry("3-0", &["f", "3"])]), Reply::bulks(&[])])
+    );
+
+    let info = client.call(&["XINFO", "STREAM", "s"]);
+    assert_eq!(info.field("length"), Reply::Integer(3));
+    assert_eq!(info.field("last-generated-id"), Reply::bulk("3-0"));
+    assert_eq!(info.field("entries-added"), Reply::Integer(3));
+    assert_eq!(info.field("groups"), Reply::Integer(1));
+    let Reply::Array(Some(groups)) = client.call(&["XINFO", "GROUPS", "s"]) else {
+        panic!("XINFO GROUPS should reply with an array");
+    };
+    assert_eq!(groups[0].field("name"), Reply::bulk("g"));
+    assert_eq!(groups[0].field("pending"), Reply::Integer(2));
+    assert_eq!(groups[0].field("last-delivered-id"), Reply::bulk("3-0"));
+    let Reply::Array(Some(consumers)) = client.call(&["XINFO", "CONSUMERS", "s", "g"]) else {
+        panic!("XINFO CONSUMERS should reply with an array");
+    };
+    let names: Vec<String> = consumers.iter().map(|consumer| consumer.field("name").text()).collect();
+    assert_eq!(names, ["alice", "bob", "carol"]);
+
+    assert_eq!(
+        client.call(&["XGROUP", "CREATE", "s", "g", "$"]),
+        Reply::error("BUSYGROUP Consumer Group name already exists")
+    );
+}
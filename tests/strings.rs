@@ -0,0 +1,94 @@
+mod common;
+
+use std::{thread, time::Duration};
+
+use common::{Reply, Server};
+
+// Bad expiry values are refused, and the connection carries on
+#[test]
+fn set_expiry_arguments() {
+    let server = Server::start("set-expiry", 17461, &[]);
+    let mut client = server.client();
+    let invalid = Reply::Error("ERR invalid expire time in 'set' command".to_string());
+    assert_eq!(client.call(&["SET", "k", "v", "PX", "abc"]), Reply::Error("ERR value is not an integer or out of range".to_string()));
+    assert_eq!(client.call(&["SET", "k", "v", "PX", "99999999999999999999"]), Reply::Error("ERR value is not an integer or out of range".to_string()));
+    assert_eq!(client.call(&["SET", "k", "v", "PX", "0"]), invalid);
+    assert_eq!(client.call(&["SET", "k", "v", "PX", "-5"]), invalid);
+    assert_eq!(client.call(&["SET", "k", "v", "PXAT", "0"]), invalid);
+    assert_eq!(client.call(&["GET", "k"]), Reply::Bulk(None));
+
+    assert_eq!(client.call(&["SET", "k", "v", "PX", "100"]), Reply::Simple("OK".to_string()));
+    assert_eq!(client.call(&["GET", "k"]), Reply::bulk("v"));
+    thread::sleep(Duration::from_millis(150));
+    assert_eq!(client.call(&["GET", "k"]), Reply::Bulk(None));
+}
+
+#[test]
+fn lcs() {
+    let server = Server::start("lcs", 17462, &[]);
+    let mut client = server.client();
+    client.call(&["SET", "a", "ohmytext"]);
+    client.call(&["SET", "b", "mynewtext"]);
+
+    assert_eq!(client.call(&["LCS", "a", "b"]), Reply::bulk("mytext"));
+    assert_eq!(client.call(&["LCS", "a", "b", "LEN"]), Reply::Integer(6));
+    assert_eq!(client.call(&["LCS", "a", "missing"]), Reply::bulk(""));
+
+    // Matches are listed from the end of the strings, as ranges in each
+    let range = |start: i64, end: i64| Reply::array(vec![Reply::Integer(start), Reply::Integer(end)]);
+    assert_eq!(
+        client.call(&["LCS", "a", "b", "IDX"]),
+        Reply::array(vec![
+            Reply::bulk("matches"),
+            Reply::array(vec![Reply::array(vec![range(4, 7), range(5, 8)]), Reply::array(vec![range(2, 3), range(0, 1)])]),
+            Reply::bulk("len"),
+            Reply::Integer(6),
+        ])
+    );
+    assert_eq!(
+        client.call(&["LCS", "a", "b", "IDX", "MINMATCHLEN", "4", "WITHMATCHLEN"]),
+        Reply::array(vec![
+            Reply::bulk("matches"),
+            Reply::array(vec![Reply::array(vec![range(4, 7), range(5, 8), Reply::Integer(4)])]),
+            Reply::bulk("len"),
+            Reply::Integer(6),
+        ])
+    );
+    assert_eq!(
+        client.call(&["LCS", "a", "b", "LEN", "IDX"]),
+        Reply::error("ERR If you want both the length and indexes, please just use IDX.")
+    );
+    client.call(&["LPUSH", "l", "x"]);
+    assert_eq!(client.call(&["LCS", "a", "l"]), Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"));
+}
+
+#[test]
+fn touch_and_access_metadata() {
+    let server = Server::start("touch", 17463, &[]);
+    let mut client = server.client();
+    client.call(&["SET", "a", "1"]);
+    client.call(&["SET", "b", "2"]);
+    assert_eq!(client.call(&["TOUCH", "a", "b", "missing"]), Reply::Integer(2));
+    assert_eq!(client.call(&["OBJECT", "IDLETIME", "missing"]), Reply::Bulk(None));
+
+    // OBJECT itself doesn't count as an access, TOUCH and reads do
+    thread::sleep(Duration::from_millis(1100));
+    assert_eq!(client.call(&["OBJECT", "IDLETIME", "a"]), Reply::Integer(1));
+    assert_eq!(client.call(&["OBJECT", "IDLETIME", "a"]), Reply::Integer(1));
+    client.call(&["TOUCH", "a"]);
+    client.call(&["GET", "b"]);
+    assert_eq!(client.call(&["OBJECT", "IDLETIME", "a"]), Reply::Integer(0));
+    assert_eq!(client.call(&["OBJECT", "IDLETIME", "b"]), Reply::Integer(0));
+
+    // The access frequency only grows with use
+    let Reply::Integer(before) = client.call(&["OBJECT", "FREQ", "a"]) else {
+        panic!("OBJECT FREQ should reply with an integer");
+    };
+    for _ in 0..100 {
+        client.call(&["TOUCH", "a"]);
+    }
+    let Reply::Integer(after) = client.call(&["OBJECT", "FREQ", "a"]) else {
+        panic!("OBJECT FREQ should reply with an integer");
+    };
+    assert!(after > before, "{} should have grown past {}", after, before);
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use common::{Reply, Server};
+
+fn sample(timestamp: i64, value: &str) -> Reply {
+    Reply::array(vec![Reply::Integer(timestamp), Reply::bulk(value)])
+}
+
+#[test]
+fn add_and_range() {
+    let server = Server::start("ts-range", 17621, &[]);
+    let mut client = server.client();
+
+    assert_eq!(client.call(&["TS.CREATE", "t", "LABELS", "room", "kitchen"]), Reply::ok());
+    assert_eq!(client.call(&["TS.CREATE", "t"]), Reply::error("ERR TSDB: key already exists"));
+    for (timestamp, value) in [("1000", "1"), ("1500", "3"), ("2000", "5"), ("2500", "7.5"), ("3000", "2")] {
+        assert_eq!(client.call(&["TS.ADD", "t", timestamp, value]), Reply::Integer(timestamp.parse().unwrap()));
+    }
+    assert_eq!(client.call(&["TS.GET", "t"]), sample(3000, "2"));
+
+    assert_eq!(client.call(&["TS.RANGE", "t", "1500", "2500"]), Reply::array(vec![sample(1500, "3"), sample(2000, "5"), sample(2500, "7.5")]));
+    assert_eq!(client.call(&["TS.REVRANGE", "t", "-", "+", "COUNT", "2"]), Reply::array(vec![sample(3000, "2"), sample(2500, "7.5")]));
+    // Buckets start at multiples of their duration
+    assert_eq!(
+        client.call(&["TS.RANGE", "t", "-", "+", "AGGREGATION", "avg", "1000"]),
+        Reply::array(vec![sample(1000, "2"), sample(2000, "6.25"), sample(3000, "2")])
+    );
+    assert_eq!(
+        client.call(&["TS.RANGE", "t", "-", "+", "AGGREGATION", "count", "2000"]),
+        Reply::array(vec![sample(0, "2"), sample(2000, "3")])
+    );
+    assert_eq!(
+        client.call(&["TS.RANGE", "t", "-", "+", "AGGREGATION", "range", "2000"]),
+        Reply::array(vec![sample(0, "2"), sample(2000, "5.5")])
+    );
+
+    let info = client.call(&["TS.INFO", "t"]);
+    assert_eq!(info.field("totalSamples"), Reply::Integer(5));
+    assert_eq!(info.field("firstTimestamp"), Reply::Integer(1000));
+    assert_eq!(info.field("lastTimestamp"), Reply::Integer(3000));
+    assert_eq!(info.field("duplicatePolicy"), Reply::bulk("block"));
+    assert_eq!(info.field("labels"), Reply::array(vec![Reply::bulks(&["room", "kitchen"])]));
+
+    assert_eq!(client.call(&["TS.RANGE", "t", "-", "+", "AGGREGATION", "median", "10"]), Reply::error("ERR TSDB: Unknown aggregation type"));
+    assert_eq!(client.call(&["TS.RANGE", "t", "-", "+", "AGGREGATION", "avg", "0"]), Reply::error("ERR TSDB: bucketDuration must be greater than zero"));
+    assert_eq!(client.call(&["TS.GET", "missing"]), Reply::error("ERR TSDB: the key does not exist"));
+}
+
+#[test]
+fn duplicates_and_retention() {
+    let server = Server::start("ts-duplicates", 17622, &[]);
+    let mut client = server.client();
+
+    // TS.ADD creates the series with its options when it doesn't exist
+    assert_eq!(client.call(&["TS.ADD", "t", "100", "1", "RETENTION", "50"]), Reply::Integer(100));
+    assert_eq!(
+        client.call(&["TS.ADD", "t", "100", "2"]),
+        Reply::error("ERR TSDB: Error at upsert, update is not supported when DUPLICATE_POLICY is set to BLOCK mode")
+    );
+    assert_eq!(client.call(&["TS.ADD", "t", "100", "2", "ON_DUPLICATE", "SUM"]), Reply::Integer(100));
+    assert_eq!(client.call(&["TS.GET", "t"]), sample(100, "3"));
+    assert_eq!(client.call(&["TS.ADD", "t", "100", "0.5", "ON_DUPLICATE", "MIN"]), Reply::Integer(100));
+    assert_eq!(client.call(&["TS.GET", "t"]), sample(100, "0.5"));
+
+    // Samples older than the retention window behind the newest are refused
+    client.call(&["TS.ADD", "t", "200", "1"]);
+    assert_eq!(client.call(&["TS.ADD", "t", "140", "1"]), Reply::error("ERR TSDB: Timestamp is older than retention"));
+    assert_eq!(client.call(&["TS.ADD", "t", "150", "1"]), Reply::Integer(150));
+    assert_eq!(client.call(&["TS.INFO", "t"]).field("retentionTime"), Reply::Integer(50));
+
+    client.call(&["TS.CREATE", "last", "DUPLICATE_POLICY", "LAST"]);
+    client.call(&["TS.ADD", "last", "1", "1"]);
+    client.call(&["TS.ADD", "last", "1", "9"]);
+    assert_eq!(client.call(&["TS.GET", "last"]), sample(1, "9"));
+    assert_eq!(client.call(&["TS.GET", "empty"]), Reply::error("ERR TSDB: the key does not exist"));
+    client.call(&["TS.CREATE", "empty"]);
+    assert_eq!(client.call(&["TS.GET", "empty"]), Reply::bulks(&[]));
+
+    assert_eq!(client.call(&["TS.ADD", "t", "-1", "1"]), Reply::error("ERR TSDB: invalid timestamp, must be a nonnegative integer"));
+    assert_eq!(client.call(&["TS.ADD", "t", "1", "x"]), Reply::error("ERR TSDB: invalid value"));
+    assert_eq!(client.call(&["TS.CREATE", "u", "DUPLICATE_POLICY", "NOPE"]), Reply::error("ERR TSDB: Unknown DUPLICATE_POLICY"));
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{Client, Reply, Server};
+
+fn invalidate(key: &str) -> Reply {
+    Reply::Push(vec![Reply::bulk("invalidate"), Reply::bulks(&[key])])
+}
+
+fn resp3_client(server: &Server) -> Client {
+    let mut client = server.client();
+    client.call(&["HELLO", "3"]);
+    client
+}
+
+fn nothing_more(client: &mut Client) {
+    assert_eq!(client.read_within(Duration::from_millis(200)), None);
+}
+
+#[test]
+fn tracked_reads_are_invalidated() {
+    let server = Server::start("tracking-default", 17521, &[]);
+    let mut writer = server.client();
+    let mut reader = resp3_client(&server);
+    assert_eq!(reader.call(&["CLIENT", "GETREDIR"]), Reply::Integer(-1));
+    assert_eq!(reader.call(&["CLIENT", "TRACKING", "ON"]), Reply::ok());
+    assert_eq!(reader.call(&["CLIENT", "GETREDIR"]), Reply::Integer(0));
+
+    // Only keys the connection read are invalidated, and only once until read again
+    writer.call(&["SET", "a", "1"]);
+    nothing_more(&mut reader);
+    reader.call(&["GET", "a"]);
+    writer.call(&["SET", "a", "2"]);
+    assert_eq!(reader.read(), invalidate("a"));
+    writer.call(&["SET", "a", "3"]);
+    nothing_more(&mut reader);
+
+    // The connection's own writes invalidate too, unless it asked for NOLOOP
+    reader.call(&["GET", "a"]);
+    reader.send(&["SET", "a", "4"]);
+    assert_eq!(reader.read(), invalidate("a"));
+    assert_eq!(reader.read(), Reply::ok());
+    assert_eq!(reader.call(&["CLIENT", "TRACKING", "ON", "NOLOOP"]), Reply::ok());
+    reader.call(&["GET", "a"]);
+    assert_eq!(reader.call(&["SET", "a", "5"]), Reply::ok());
+    nothing_more(&mut reader);
+
+    // Turning tracking off stops the invalidations
+    reader.call(&["GET", "a"]);
+    assert_eq!(reader.call(&["CLIENT", "TRACKING", "OFF"]), Reply::ok());
+    writer.call(&["SET", "a", "6"]);
+    nothing_more(&mut reader);
+}
+
+#[test]
+fn broadcast_and_opt_in_modes() {
+    let server = Server::start("tracking-modes", 17522, &[]);
+    let mut writer = server.client();
+    let mut reader = resp3_client(&server);
+
+    // BCAST covers every key under its prefixes whether read or not
+    assert_eq!(reader.call(&["CLIENT", "TRACKING", "ON", "BCAST", "PREFIX", "user:"]), Reply::ok());
+    writer.call(&["SET", "user:1", "x"]);
+    writer.call(&["SET", "other", "x"]);
+    assert_eq!(reader.read(), invalidate("user:1"));
+    nothing_more(&mut reader);
+    assert_eq!(
+        reader.call(&["CLIENT", "TRACKING", "ON"]),
+        Reply::error(
+            "ERR You can't switch BCAST mode on/off before disabling tracking for this client, and then re-enabling it with a different mode."
+        )
+    );
+    reader.call(&["CLIENT", "TRACKING", "OFF"]);
+
+    // With OPTIN only reads right after CLIENT CACHING YES are remembered
+    assert_eq!(reader.call(&["CLIENT", "TRACKING", "ON", "OPTIN"]), Reply::ok());
+    reader.call(&["GET", "a"]);
+    assert_eq!(reader.call(&["CLIENT", "CACHING", "YES"]), Reply::ok());
+    reader.call(&["GET", "b"]);
+    writer.call(&["SET", "a", "x"]);
+    writer.call(&["SET", "b", "x"]);
+    assert_eq!(reader.read(), invalidate("b"));
+    nothing_more(&mut reader);
+    assert_eq!(
+        reader.call(&["CLIENT", "CACHING", "NO"]),
+        Reply::error("ERR CLIENT CACHING NO is only valid when tracking is enabled in OPTOUT mode.")
+    );
+    reader.call(&["CLIENT", "TRACKING", "OFF"]);
+
+    // And with OPTOUT every read but the one after CLIENT CACHING NO
+    assert_eq!(reader.call(&["CLIENT", "TRACKING", "ON", "OPTOUT"]), Reply::ok());
+    assert_eq!(reader.call(&["CLIENT", "CACHING", "NO"]), Reply::ok());
+    reader.call(&["GET", "a"]);
+    reader.call(&["GET", "b"]);
+    writer.call(&["SET", "a", "y"]);
+    writer.call(&["SET", "b", "y"]);
+    assert_eq!(reader.read(), invalidate("b"));
+    nothing_more(&mut reader);
+}
+
+#[test]
+fn tracking_option_errors() {
+    let server = Server::start("tracking-errors", 17523, &[]);
+    let mut client = server.client();
+    let call = |client: &mut Client, args: &[&str]| client.call(&[&["CLIENT", "TRACKING", "ON"], args].concat());
+
+    assert_eq!(call(&mut client, &["PREFIX", "a"]), Reply::error("ERR PREFIX option requires BCAST mode to be enabled"));
+    assert_eq!(call(&mut client, &["OPTIN", "OPTOUT"]), Reply::error("ERR You can't use both OPTIN and OPTOUT"));
+    assert_eq!(call(&mut client, &["BCAST", "OPTIN"]), Reply::error("ERR OPTIN and OPTOUT are not compatible with BCAST"));
+    assert_eq!(call(&mut client, &["REDIRECT", "9999"]), Reply::error("ERR The client ID you want redirect to does not exist"));
+    assert_eq!(call(&mut client, &["REDIRECT", "x"]), Reply::error("ERR Invalid client ID"));
+    assert_eq!(
+        client.call(&["CLIENT", "CACHING", "YES"]),
+        Reply::error("ERR CLIENT CACHING can be called only when the client is in tracking mode with OPTIN or OPTOUT mode enabled")
+    );
+}
+
+#[test]
+fn redirected_invalidations() {
+    let server = Server::start("tracking-redirect", 17524, &[]);
+    let mut writer = server.client();
+    let mut reader = server.client();
+    let mut listener = server.client();
+    let Reply::Integer(listener_id) = listener.call(&["CLIENT", "ID"]) else {
+        panic!("CLIENT ID should reply with an integer");
+    };
+    listener.call(&["SUBSCRIBE", "__redis__:invalidate"]);
+
+    // A RESP2 connection's invalidations go to the one it redirects to, as pub/sub messages
+    let listener_id = listener_id.to_string();
+    assert_eq!(reader.call(&["CLIENT", "TRACKING", "ON", "REDIRECT", &listener_id]), Reply::ok());
+    assert_eq!(reader.call(&["CLIENT", "GETREDIR"]), Reply::Integer(listener_id.parse().unwrap()));
+    reader.call(&["GET", "k"]);
+    writer.call(&["SET", "k", "v"]);
+    assert_eq!(
+        listener.read(),
+        Reply::array(vec![Reply::bulk("message"), Reply::bulk("__redis__:invalidate"), Reply::bulks(&["k"])])
+    );
+    nothing_more(&mut reader);
+}
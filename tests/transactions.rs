@@ -0,0 +1,91 @@
+mod common;
+
+use common::{wait_until, Reply, Server};
+
+fn queued() -> Reply {
+    Reply::Simple("QUEUED".to_string())
+}
+
+#[test]
+fn exec_runs_queued_commands() {
+    let server = Server::start("transaction-exec", 17531, &[]);
+    let mut client = server.client();
+    let mut other = server.client();
+
+    // Nothing runs until EXEC, which replies with every command's reply in order
+    assert_eq!(client.call(&["MULTI"]), Reply::ok());
+    assert_eq!(client.call(&["SET", "k", "v"]), queued());
+    assert_eq!(client.call(&["GET", "k"]), queued());
+    assert_eq!(other.call(&["GET", "k"]), Reply::Bulk(None));
+    assert_eq!(client.call(&["EXEC"]), Reply::array(vec![Reply::ok(), Reply::bulk("v")]));
+
+    // A command failing as it runs leaves the others to run
+    client.call(&["MULTI"]);
+    client.call(&["LPUSH", "k", "a"]);
+    client.call(&["SET", "k", "w"]);
+    assert_eq!(
+        client.call(&["EXEC"]),
+        Reply::array(vec![
+            Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+            Reply::ok(),
+        ])
+    );
+    assert_eq!(client.call(&["GET", "k"]), Reply::bulk("w"));
+
+    // Blocking commands reply as if they timed out instead of waiting
+    client.call(&["MULTI"]);
+    client.call(&["BLPOP", "list", "0"]);
+    assert_eq!(client.call(&["EXEC"]), Reply::array(vec![Reply::Array(None)]));
+}
+
+#[test]
+fn discard_and_misuse() {
+    let server = Server::start("transaction-discard", 17532, &[]);
+    let mut client = server.client();
+
+    assert_eq!(client.call(&["EXEC"]), Reply::error("ERR EXEC without MULTI"));
+    assert_eq!(client.call(&["DISCARD"]), Reply::error("ERR DISCARD without MULTI"));
+
+    client.call(&["MULTI"]);
+    client.call(&["SET", "k", "v"]);
+    assert_eq!(client.call(&["MULTI"]), Reply::error("ERR MULTI calls can not be nested"));
+    assert_eq!(client.call(&["DISCARD"]), Reply::ok());
+    assert_eq!(client.call(&["GET", "k"]), Reply::Bulk(None));
+
+    // RESET abandons the transaction too
+    client.call(&["MULTI"]);
+    client.call(&["SET", "k", "v"]);
+    client.call(&["RESET"]);
+    assert_eq!(client.call(&["EXEC"]), Reply::error("ERR EXEC without MULTI"));
+    assert_eq!(client.call(&["GET", "k"]), Reply::Bulk(None));
+}
+
+#[test]
+fn commands_failing_to_queue_abort_exec() {
+    let server = Server::start("transaction-abort", 17533, &[]);
+    let mut client = server.client();
+
+    client.call(&["MULTI"]);
+    client.call(&["SET", "k", "v"]);
+    assert_eq!(client.call(&["NOSUCHCOMMAND"]), Reply::error("ERR unknown command 'nosuchcommand'"));
+    assert_eq!(client.call(&["REPLICAOF", "NO", "ONE"]), Reply::error("ERR Command not allowed inside a transaction"));
+    assert_eq!(client.call(&["EXEC"]), Reply::error("EXECABORT Transaction discarded because of previous errors."));
+    assert_eq!(client.call(&["GET", "k"]), Reply::Bulk(None));
+}
+
+#[test]
+fn transactions_replicate() {
+    let master = Server::start("transaction-master", 17534, &[]);
+    let replica = Server::start("transaction-replica", 17535, &["--replicaof", "127.0.0.1 17534"]);
+    let mut client = master.client();
+    let mut replica_client = replica.client();
+    wait_until(|| common::info_field(&mut replica_client, "replication", "master_link_status").as_deref() == Some("up"));
+
+    client.call(&["MULTI"]);
+    client.call(&["SET", "a", "1"]);
+    client.call(&["GET", "a"]);
+    client.call(&["RPUSH", "l", "x", "y"]);
+    client.call(&["EXEC"]);
+    wait_until(|| replica_client.call(&["GET", "a"]) == Reply::bulk("1"));
+    assert_eq!(replica_client.call(&["LRANGE", "l", "0", "-1"]), Reply::bulks(&["x", "y"]));
+}
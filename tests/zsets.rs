@@ -0,0 +1,153 @@
+mod common;
+
+use std::{collections::HashSet, thread, time::Duration};
+
+use common::{Reply, Server};
+
+fn pairs(items: &[(&str, &str)]) -> Reply {
+    Reply::array(items.iter().map(|(member, score)| Reply::bulks(&[member, score])).collect())
+}
+
+#[test]
+fn add_options() {
+    let server = Server::start("zset-add", 17571, &[]);
+    let mut client = server.client();
+
+    assert_eq!(client.call(&["ZADD", "z", "1", "a", "2", "b"]), Reply::Integer(2));
+    assert_eq!(client.call(&["ZADD", "z", "NX", "5", "a", "3", "c"]), Reply::Integer(1));
+    assert_eq!(client.call(&["ZSCORE", "z", "a"]), Reply::bulk("1"));
+    assert_eq!(client.call(&["ZADD", "z", "XX", "5", "a", "4", "d"]), Reply::Integer(0));
+    assert_eq!(client.call(&["ZSCORE", "z", "d"]), Reply::Bulk(None));
+    // CH counts updated members too, GT and LT only move scores one way
+    assert_eq!(client.call(&["ZADD", "z", "GT", "CH", "1", "a", "9", "b"]), Reply::Integer(1));
+    assert_eq!(client.call(&["ZSCORE", "z", "a"]), Reply::bulk("5"));
+    assert_eq!(client.call(&["ZADD", "z", "LT", "CH", "1", "a"]), Reply::Integer(1));
+    assert_eq!(client.call(&["ZADD", "z", "INCR", "1.5", "a"]), Reply::bulk("2.5"));
+    assert_eq!(client.call(&["ZADD", "z", "NX", "INCR", "1", "a"]), Reply::Bulk(None));
+    assert_eq!(client.call(&["ZCARD", "z"]), Reply::Integer(3));
+
+    assert_eq!(
+        client.call(&["ZADD", "z", "NX", "XX", "1", "a"]),
+        Reply::error("ERR XX and NX options at the same time are not compatible")
+    );
+    assert_eq!(
+        client.call(&["ZADD", "z", "NX", "GT", "1", "a"]),
+        Reply::error("ERR GT, LT, and/or NX options at the same time are not compatible")
+    );
+    assert_eq!(
+        client.call(&["ZADD", "z", "INCR", "1", "a", "2", "b"]),
+        Reply::error("ERR INCR option supports a single increment-element pair")
+    );
+    assert_eq!(client.call(&["ZADD", "z", "x", "a"]), Reply::error("ERR value is not a valid float"));
+}
+
+#[test]
+fn ranges() {
+    let server = Server::start("zset-ranges", 17572, &[]);
+    let mut client = server.client();
+    client.call(&["ZADD", "z", "1", "a", "2", "b", "3", "c", "4", "d"]);
+
+    assert_eq!(client.call(&["ZRANGE", "z", "0", "-1"]), Reply::bulks(&["a", "b", "c", "d"]));
+    assert_eq!(client.call(&["ZRANGE", "z", "0", "1", "REV", "WITHSCORES"]), Reply::bulks(&["d", "4", "c", "3"]));
+    assert_eq!(client.call(&["ZRANGEBYSCORE", "z", "(1", "3"]), Reply::bulks(&["b", "c"]));
+    assert_eq!(client.call(&["ZRANGEBYSCORE", "z", "-inf", "+inf", "LIMIT", "1", "2"]), Reply::bulks(&["b", "c"]));
+    assert_eq!(client.call(&["ZREVRANGEBYSCORE", "z", "3", "2"]), Reply::bulks(&["c", "b"]));
+    assert_eq!(client.call(&["ZRANGE", "z", "[b", "(d", "BYLEX"]), Reply::bulks(&["b", "c"]));
+    assert_eq!(client.call(&["ZREVRANGEBYLEX", "z", "+", "[c"]), Reply::bulks(&["d", "c"]));
+    assert_eq!(client.call(&["ZRANGEBYLEX", "z", "b", "c"]), Reply::error("ERR min or max not valid string range item"));
+
+    assert_eq!(client.call(&["ZRANGESTORE", "dst", "z", "2", "4", "BYSCORE"]), Reply::Integer(3));
+    assert_eq!(client.call(&["ZRANGE", "dst", "0", "-1", "WITHSCORES"]), Reply::bulks(&["b", "2", "c", "3", "d", "4"]));
+
+    assert_eq!(client.call(&["ZCOUNT", "z", "2", "(4"]), Reply::Integer(2));
+    assert_eq!(client.call(&["ZLEXCOUNT", "z", "-", "+"]), Reply::Integer(4));
+    assert_eq!(
+        client.call(&["ZMSCORE", "z", "a", "x"]),
+        Reply::array(vec![Reply::bulk("1"), Reply::Bulk(None)])
+    );
+}
+
+#[test]
+fn ranks_and_removal() {
+    let server = Server::start("zset-ranks", 17573, &[]);
+    let mut client = server.client();
+    client.call(&["ZADD", "z", "1", "a", "2", "b", "3", "c", "4", "d", "5", "e"]);
+
+    assert_eq!(client.call(&["ZRANK", "z", "c"]), Reply::Integer(2));
+    assert_eq!(client.call(&["ZREVRANK", "z", "c", "WITHSCORE"]), Reply::array(vec![Reply::Integer(2), Reply::bulk("3")]));
+    assert_eq!(client.call(&["ZRANK", "z", "x"]), Reply::Bulk(None));
+    assert_eq!(client.call(&["ZINCRBY", "z", "10", "a"]), Reply::bulk("11"));
+    assert_eq!(client.call(&["ZRANK", "z", "a"]), Reply::Integer(4));
+
+    assert_eq!(client.call(&["ZREM", "z", "a", "x"]), Reply::Integer(1));
+    assert_eq!(client.call(&["ZREMRANGEBYRANK", "z", "0", "0"]), Reply::Integer(1));
+    assert_eq!(client.call(&["ZREMRANGEBYSCORE", "z", "(3", "+inf"]), Reply::Integer(2));
+    assert_eq!(client.call(&["ZRANGE", "z", "0", "-1"]), Reply::bulks(&["c"]));
+    assert_eq!(client.call(&["ZREMRANGEBYLEX", "z", "-", "+"]), Reply::Integer(1));
+    assert_eq!(client.call(&["ZCARD", "z"]), Reply::Integer(0));
+}
+
+#[test]
+fn combining_sets() {
+    let server = Server::start("zset-algebra", 17574, &[]);
+    let mut client = server.client();
+    client.call(&["ZADD", "a", "1", "x", "2", "y"]);
+    client.call(&["ZADD", "b", "10", "y", "20", "z"]);
+    client.call(&["SADD", "s", "x"]);
+
+    assert_eq!(client.call(&["ZUNIONSTORE", "u", "2", "a", "b"]), Reply::Integer(3));
+    assert_eq!(client.call(&["ZRANGE", "u", "0", "-1", "WITHSCORES"]), Reply::bulks(&["x", "1", "y", "12", "z", "20"]));
+    assert_eq!(client.call(&["ZUNIONSTORE", "u", "2", "a", "b", "WEIGHTS", "2", "1", "AGGREGATE", "MAX"]), Reply::Integer(3));
+    assert_eq!(client.call(&["ZSCORE", "u", "y"]), Reply::bulk("10"));
+    assert_eq!(client.call(&["ZINTERSTORE", "i", "2", "a", "b", "AGGREGATE", "MIN"]), Reply::Integer(1));
+    assert_eq!(client.call(&["ZRANGE", "i", "0", "-1", "WITHSCORES"]), Reply::bulks(&["y", "2"]));
+    // Plain sets count as sorted sets with scores of 1
+    assert_eq!(client.call(&["ZINTERSTORE", "i", "2", "a", "s"]), Reply::Integer(1));
+    assert_eq!(client.call(&["ZSCORE", "i", "x"]), Reply::bulk("2"));
+
+    assert_eq!(client.call(&["ZDIFF", "2", "a", "b", "WITHSCORES"]), Reply::bulks(&["x", "1"]));
+    assert_eq!(client.call(&["ZDIFFSTORE", "d", "2", "b", "a"]), Reply::Integer(1));
+    assert_eq!(client.call(&["ZRANGE", "d", "0", "-1"]), Reply::bulks(&["z"]));
+    assert_eq!(
+        client.call(&["ZUNIONSTORE", "u", "2", "a", "b", "WEIGHTS", "1"]),
+        Reply::error("ERR syntax error")
+    );
+}
+
+#[test]
+fn scan_and_pops() {
+    let server = Server::start("zset-pops", 17575, &[]);
+    let mut client = server.client();
+    let members: Vec<String> = (0..60).map(|i| format!("m{}", i)).collect();
+    for (i, member) in members.iter().enumerate() {
+        client.call(&["ZADD", "big", &i.to_string(), member]);
+    }
+    let mut cursor = "0".to_string();
+    let mut seen = HashSet::new();
+    loop {
+        let Reply::Array(Some(reply)) = client.call(&["ZSCAN", "big", &cursor, "COUNT", "7"]) else {
+            panic!("ZSCAN should reply with an array");
+        };
+        seen.extend(reply[1].texts().chunks(2).map(|pair| pair[0].clone()));
+        cursor = reply[0].text();
+        if cursor == "0" {
+            break;
+        }
+    }
+    assert_eq!(seen, members.into_iter().collect());
+
+    client.call(&["ZADD", "z", "1", "a", "2", "b", "3", "c"]);
+    assert_eq!(
+        client.call(&["ZMPOP", "2", "empty", "z", "MAX", "COUNT", "2"]),
+        Reply::array(vec![Reply::bulk("z"), pairs(&[("c", "3"), ("b", "2")])])
+    );
+    assert_eq!(client.call(&["ZMPOP", "1", "empty", "MIN"]), Reply::Array(None));
+
+    // BZMPOP waits for a member to arrive
+    let mut waiter = server.client();
+    waiter.send(&["BZMPOP", "0", "1", "w", "MIN"]);
+    thread::sleep(Duration::from_millis(100));
+    client.call(&["ZADD", "w", "7", "x"]);
+    assert_eq!(waiter.read(), Reply::array(vec![Reply::bulk("w"), pairs(&[("x", "7")])]));
+    assert_eq!(waiter.call(&["BZMPOP", "0.1", "1", "w", "MIN"]), Reply::Array(None));
+}